@@ -0,0 +1,117 @@
+// The regtest demo of `cargo run`, against the in-memory MockBackend instead of a bitcoind:
+// fund a pool through the staging output, then let every user leave in address order.
+//
+//     cargo run --example offline_demo
+use std::str::FromStr;
+
+use anyhow::Result;
+use bitcoin::{Address, Amount};
+use bitcoincore_rpc::RpcApi;
+use tracing::info;
+
+use op_ctv_payment_pool::{
+    config::NetworkConfig,
+    covenant::CtvBackend,
+    ids::UserIndex,
+    lifecycle::Event,
+    manifest::PoolManifest,
+    mempool::BroadcastQueue,
+    miner::fund_regtest_wallet,
+    mock::MockBackend,
+    pools::{build_pools, process_pool_spend},
+    profile::NetworkProfile,
+    rpc_helper::{send_funding_transaction, simulate_psbt_signing, FundingDestination},
+    template::Bip119Ctv,
+    AMOUNT_PER_USER, POOL_USERS,
+};
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    tracing_subscriber::fmt()
+        .with_target(false)
+        .with_writer(std::io::stderr)
+        .init();
+
+    let config = NetworkConfig::new(NetworkProfile::RegtestLocal);
+    let backend = CtvBackend::from(Bip119Ctv {
+        tx_version: config.tx_version,
+    });
+
+    // like the Dockerfile's node: no min relay fee, and every tx mined as soon as it is sent
+    let mock = MockBackend::new(config.network);
+    mock.auto_mine(Some(1));
+    mock.set_relay_fee(Amount::ZERO);
+    let rpc = mock.rpc();
+
+    let mining_address = rpc
+        .run(|c| c.get_new_address(None, None))
+        .await?
+        .require_network(config.network)?;
+    fund_regtest_wallet(&rpc, &mining_address, AMOUNT_PER_USER * POOL_USERS as u64).await?;
+
+    let anchor_addr =
+        Address::from_str(&config.fee_anchor_addr)?.require_network(config.network)?;
+    let mut addresses = Vec::with_capacity(POOL_USERS);
+    for _ in 0..POOL_USERS {
+        addresses.push(
+            rpc.run(|c| c.get_new_address(None, None))
+                .await?
+                .require_network(config.network)?,
+        );
+    }
+    let pools = build_pools(&addresses, &anchor_addr, &config, &backend)?;
+    let pool_addr = pools.root()?.address(&config);
+    info!("Initial pool address: {}", pool_addr);
+    let mut manifest = PoolManifest::new(&config, &backend, &anchor_addr, &addresses, &pool_addr);
+
+    let staging = rpc
+        .run(|c| c.get_raw_change_address(None))
+        .await?
+        .require_network(config.network)?;
+    let (staging_txid, staging_spk) =
+        send_funding_transaction(&rpc, &config, &FundingDestination::Staging(staging), None)
+            .await?;
+    let funding_txid =
+        simulate_psbt_signing(&rpc, &config, staging_txid, &staging_spk, &pool_addr).await?;
+    manifest.funding_txid = Some(funding_txid);
+    manifest.advance(Event::Fund)?;
+
+    let mut queue = BroadcastQueue::new(&config);
+    let mut current_txid = funding_txid;
+    let mut exits = Vec::new();
+    for i in UserIndex::all().take(POOL_USERS - 1) {
+        current_txid = process_pool_spend(
+            &pools,
+            &config,
+            &rpc,
+            &rpc,
+            &mut queue,
+            &backend,
+            i,
+            &addresses,
+            current_txid,
+            &anchor_addr,
+        )
+        .await?;
+        manifest.advance(Event::Withdraw(i))?;
+        info!(
+            "user {} left in {} ({})",
+            i, current_txid, manifest.lifecycle
+        );
+        exits.push((i, current_txid));
+    }
+    // the final exit pays the last two users
+    exits.push((UserIndex::new(POOL_USERS - 1)?, current_txid));
+
+    println!("funding tx: {}", funding_txid);
+    for (i, txid) in &exits {
+        println!("user {} withdrew in {}", i, txid);
+    }
+    println!(
+        "{} blocks mined, {} in the mempool, pool {}",
+        mock.height(),
+        mock.mempool().len(),
+        manifest.lifecycle
+    );
+    Ok(())
+}
@@ -0,0 +1,292 @@
+use std::path::Path;
+
+use anyhow::{anyhow, bail, Result};
+use bitcoin::{
+    address::NetworkUnchecked, consensus::encode::serialize_hex, hex::DisplayHex, Address, Amount,
+    ScriptBuf, Sequence, Transaction, TxIn, TxOut, Txid,
+};
+use bitcoincore_rpc::RpcApi;
+use serde::Serialize;
+use tracing::{info, trace, warn};
+
+use crate::{
+    broadcast::{broadcast, rpc_reject, BroadcastKind},
+    config::{NetworkConfig, DUST_AMOUNT},
+    fee_bump::INCREMENTAL_RELAY_FEE,
+    fetch::RpcFetcher,
+    lifecycle::{Event, Lifecycle},
+    manifest::PoolManifest,
+    redact,
+    rpc_helper::{estimate_fee_rate, fee_for_vsize, AsyncRpc, WALLET_INPUT_WITNESS_VSIZE},
+    webhooks::PoolEvent,
+};
+
+// The tx that took the funding tx's coins back, in place of the pool
+#[derive(Debug, Clone, Serialize)]
+pub struct Refund {
+    pub txid: Txid,
+    pub address: String,
+    pub amount: Amount,
+    pub fee: Amount,
+}
+
+// What `abort` did
+#[derive(Debug, Clone, Serialize)]
+pub struct AbortedPool {
+    pub root_address: String,
+    // what the pool was before it was cancelled
+    pub was: Lifecycle,
+    pub funding_txid: Option<Txid>,
+    pub refund: Option<Refund>,
+}
+
+// bitcoind's RPC_INVALID_ADDRESS_OR_KEY: gettransaction and getrawtransaction for a tx they don't have
+const RPC_INVALID_ADDRESS_OR_KEY: i32 = -5;
+// outputs of a funding tx the node can't find looked up in the utxo set: the pool's and the change
+// of a funding this wallet sent, and a few more for one from another wallet
+const FUNDING_OUTPUTS_CHECKED: u32 = 8;
+
+fn not_found(error: &anyhow::Error) -> bool {
+    rpc_reject(error).is_some_and(|rpc_error| rpc_error.code == RPC_INVALID_ADDRESS_OR_KEY)
+}
+
+fn already_confirmed(funding_txid: Txid, confirmations: u32) -> anyhow::Error {
+    anyhow!(
+        "the funding tx {} has {} confirmations, the pool can only be unwound or recovered now",
+        funding_txid,
+        confirmations
+    )
+}
+
+// Where the funding tx is: confirmations, None when neither the wallet nor the node knows it
+// (never broadcast, or dropped from the mempool). A node without -txindex only finds mempool and
+// wallet txs, so before a tx counts as gone its outputs have to be missing from the utxo set too.
+// Any other rpc failure is passed on, it says nothing about the tx.
+async fn funding_status(rpc: &AsyncRpc, txid: Txid) -> Result<Option<(Transaction, u32)>> {
+    match rpc.run(move |c| c.get_transaction(&txid, None)).await {
+        Ok(wallet_tx) => {
+            let confirmations = u32::try_from(wallet_tx.info.confirmations.max(0))?;
+            return Ok(Some((wallet_tx.transaction()?, confirmations)));
+        }
+        Err(e) if !not_found(&e) => return Err(e),
+        Err(_) => {}
+    }
+    match rpc
+        .run(move |c| c.get_raw_transaction_info(&txid, None))
+        .await
+    {
+        Ok(info) => return Ok(Some((info.transaction()?, info.confirmations.unwrap_or(0)))),
+        Err(e) if !not_found(&e) => return Err(e),
+        Err(_) => {}
+    }
+    for vout in 0..FUNDING_OUTPUTS_CHECKED {
+        if let Some(output) = rpc
+            .run(move |c| c.get_tx_out(&txid, vout, Some(true)))
+            .await?
+        {
+            return Err(already_confirmed(txid, output.confirmations));
+        }
+    }
+    Ok(None)
+}
+
+// A replacement of the unconfirmed `funding` spending all of its inputs to `refund_to`, paying
+// the fee it paid plus the incremental relay fee for its own size (BIP-125 rules 3 and 4), and
+// at least the node's estimate
+pub fn refund_tx(
+    funding: &Transaction,
+    prevouts: &[TxOut],
+    refund_to: ScriptBuf,
+    fee_rate: u64,
+) -> Result<(Transaction, Amount)> {
+    let total_in: Amount = prevouts.iter().map(|prevout| prevout.value).sum();
+    let total_out: Amount = funding.output.iter().map(|output| output.value).sum();
+    let replaced_fee = total_in
+        .checked_sub(total_out)
+        .ok_or_else(|| anyhow!("the funding tx pays out more than its inputs"))?;
+
+    let mut tx = Transaction {
+        version: funding.version,
+        lock_time: funding.lock_time,
+        input: funding
+            .input
+            .iter()
+            .map(|input| TxIn {
+                previous_output: input.previous_output,
+                sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+                ..Default::default()
+            })
+            .collect(),
+        output: vec![TxOut {
+            value: Amount::ZERO,
+            script_pubkey: refund_to,
+        }],
+    };
+    let vsize = tx.vsize() as u64 + WALLET_INPUT_WITNESS_VSIZE * tx.input.len() as u64;
+    let fee = (replaced_fee + fee_for_vsize(INCREMENTAL_RELAY_FEE, vsize)?)
+        .max(fee_for_vsize(fee_rate, vsize)?);
+    let refund = total_in
+        .checked_sub(fee)
+        .filter(|refund| *refund >= DUST_AMOUNT)
+        .ok_or_else(|| {
+            anyhow!(
+                "the funding inputs ({}) can't pay a {} replacement fee",
+                total_in,
+                fee
+            )
+        })?;
+    tx.output[0].value = refund;
+    Ok((tx, fee))
+}
+
+// Give up the pool in the manifest before its funding confirms. A funding tx still in the mempool
+// is replaced by one paying its coins back to `refund_to` (the payer's first coin by default),
+// the registered users are told through the webhook and the pool is recorded as cancelled so
+// nothing funds or unwinds it any more. A confirmed funding can only be unwound or recovered.
+pub async fn abort_pool(
+    manifest_path: &Path,
+    refund_to: Option<Address<NetworkUnchecked>>,
+) -> Result<AbortedPool> {
+    let mut manifest = PoolManifest::load(manifest_path)?;
+    let was = manifest.lifecycle;
+    manifest.lifecycle.apply(Event::Abort)?;
+    let config = NetworkConfig::new(manifest.profile);
+    let refund_to = refund_to
+        .map(|address| address.require_network(config.network))
+        .transpose()?;
+
+    let refund = match manifest.funding_txid {
+        Some(funding_txid) => {
+            let rpc = AsyncRpc::connect(&config).await?;
+            refund_funding(&rpc, &config, funding_txid, refund_to.as_ref()).await?
+        }
+        None => None,
+    };
+
+    manifest.advance(Event::Abort)?;
+    manifest.write(manifest_path)?;
+
+    let event = PoolEvent::PoolCancelled {
+        funding_txid: manifest.funding_txid,
+        refund_txid: refund.as_ref().map(|refund| refund.txid),
+        users: manifest.pool_root()?.user_indices().collect(),
+    };
+    info!("pool event: {:?}", event);
+    if let Some(webhook) = &config.webhook {
+        webhook
+            .notify(
+                manifest.pool_id.as_ref(),
+                &manifest.root_address,
+                &event,
+                &[],
+            )
+            .await;
+    }
+
+    Ok(AbortedPool {
+        root_address: manifest.root_address,
+        was,
+        funding_txid: manifest.funding_txid,
+        refund,
+    })
+}
+
+// Replace the funding tx if it is still unconfirmed, None when there is nothing left to replace
+pub async fn refund_funding(
+    rpc: &AsyncRpc,
+    config: &NetworkConfig,
+    funding_txid: Txid,
+    refund_to: Option<&Address>,
+) -> Result<Option<Refund>> {
+    let Some((funding, confirmations)) = funding_status(rpc, funding_txid).await? else {
+        warn!(
+            "funding tx {} is in neither the mempool nor the chain, nothing to replace",
+            funding_txid
+        );
+        return Ok(None);
+    };
+    if confirmations > 0 {
+        return Err(already_confirmed(funding_txid, confirmations));
+    }
+
+    let spent: Vec<Txid> = funding
+        .input
+        .iter()
+        .map(|input| input.previous_output.txid)
+        .collect();
+    let parents = RpcFetcher::new(vec![rpc.clone()], config.rpc_fetch.batch)?
+        .transactions(&spent)
+        .await?;
+    let prevouts = funding
+        .input
+        .iter()
+        .map(|input| {
+            parents
+                .get(&input.previous_output.txid)
+                .and_then(|parent| parent.output.get(input.previous_output.vout as usize))
+                .cloned()
+                .ok_or_else(|| {
+                    anyhow!(
+                        "can't find the coin {} the funding tx spends",
+                        input.previous_output
+                    )
+                })
+        })
+        .collect::<Result<Vec<TxOut>>>()?;
+    let refund_spk = match refund_to {
+        Some(address) => address.script_pubkey(),
+        None => prevouts[0].script_pubkey.clone(),
+    };
+    let address = Address::from_script(&refund_spk, config.network)?;
+
+    let fee_rate = estimate_fee_rate(rpc, config).await;
+    let (tx, fee) = refund_tx(&funding, &prevouts, refund_spk, fee_rate)?;
+    let amount = tx.output[0].value;
+    let unsigned = serialize_hex(&tx);
+    let signed = rpc
+        .run(move |c| c.sign_raw_transaction_with_wallet(unsigned, None, None))
+        .await?;
+    if !signed.complete {
+        bail!(
+            "the wallet can't sign every input of the funding tx {}, the payer has to replace it \
+             (e.g. `bumpfee` or sending the coins back to itself) and run abort again",
+            funding_txid
+        );
+    }
+    trace!("refund tx: {}", signed.hex.to_lower_hex_string());
+
+    let txid = broadcast(rpc, &signed.hex, BroadcastKind::Funding).await?;
+    info!(
+        "funding tx {} replaced by {}, {} back to {} \n",
+        funding_txid,
+        txid,
+        amount,
+        redact::short(&address)
+    );
+    Ok(Some(Refund {
+        txid,
+        address: address.to_string(),
+        amount,
+        fee,
+    }))
+}
+
+pub fn print_aborted_pool(aborted: &AbortedPool) {
+    println!(
+        "pool {} is cancelled (it was {})",
+        aborted.root_address, aborted.was
+    );
+    match (&aborted.refund, aborted.funding_txid) {
+        (Some(refund), Some(funding_txid)) => {
+            println!("funding tx {} replaced by {}", funding_txid, refund.txid);
+            println!(
+                "{} back to {}, {} fee",
+                refund.amount, refund.address, refund.fee
+            );
+        }
+        (None, Some(funding_txid)) => {
+            println!("funding tx {} is gone, nothing to refund", funding_txid)
+        }
+        _ => println!("it was never funded"),
+    }
+}
@@ -0,0 +1,244 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::atomic::{AtomicU32, Ordering},
+};
+
+use anyhow::{anyhow, bail, Result};
+use bitcoin::{address::NetworkUnchecked, Address, Network, ScriptBuf, WitnessVersion};
+use serde_json::value::RawValue;
+
+// Witness versions no soft fork has defined yet that the pool pays anyway, one bit per version. Set
+// from the config's allowed_witness_versions (POOL_ALLOWED_WITNESS_VERSIONS), none until then.
+static ALLOWED_WITNESS_VERSIONS: AtomicU32 = AtomicU32::new(0);
+
+pub fn set_allowed_witness_versions(versions: &[WitnessVersion]) {
+    let bits = versions
+        .iter()
+        .fold(0, |bits, version| bits | 1 << version.to_num());
+    ALLOWED_WITNESS_VERSIONS.store(bits, Ordering::Relaxed);
+}
+
+pub fn witness_version_allowed(version: WitnessVersion) -> bool {
+    ALLOWED_WITNESS_VERSIONS.load(Ordering::Relaxed) & 1 << version.to_num() != 0
+}
+
+// "2,3" -> v2 and v3. Only versions that can hold programs nobody defined yet: v1 (a program
+// other than taproot's 32 bytes) up to v16
+pub fn parse_witness_versions(list: &str) -> Result<Vec<WitnessVersion>> {
+    list.split(',')
+        .map(str::trim)
+        .filter(|version| !version.is_empty())
+        .map(|version| {
+            let number: u8 = version
+                .trim_start_matches(['v', 'V'])
+                .parse()
+                .map_err(|_| anyhow!("{} is not a witness version", version))?;
+            if number == 0 {
+                bail!("witness v0 has no undefined programs, allow v1 to v16");
+            }
+            Ok(WitnessVersion::try_from(number)?)
+        })
+        .collect()
+}
+
+// What is wrong with one address a file or request gave us
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AddressProblem {
+    // the entry isn't an address, or isn't the json it should be
+    Invalid(String),
+    // a valid address, for another network
+    WrongNetwork { address: String, expected: Network },
+    // an address the pool can't safely pay, e.g. an undefined witness version anyone can spend
+    NonStandard { address: String, reason: String },
+    // the same script as an earlier entry, both users would be paid to one address
+    Duplicate { address: String, first: usize },
+}
+
+impl fmt::Display for AddressProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Invalid(e) => write!(f, "{}", e),
+            Self::WrongNetwork { address, expected } => {
+                write!(f, "{} is not a {} address", address, expected)
+            }
+            Self::NonStandard { address, reason } => write!(f, "{} {}", address, reason),
+            Self::Duplicate { address, first } => {
+                write!(f, "{} is already used by entry {}", address, first)
+            }
+        }
+    }
+}
+
+// One bad entry: its position in the list and, for a file, the line it starts on
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AddressError {
+    pub entry: usize,
+    pub line: Option<usize>,
+    pub problem: AddressProblem,
+}
+
+impl fmt::Display for AddressError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "entry {} (line {}): {}", self.entry, line, self.problem),
+            None => write!(f, "entry {}: {}", self.entry, self.problem),
+        }
+    }
+}
+
+// Every bad entry of a list, downcast an anyhow error to this to report them one by one
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AddressErrors(pub Vec<AddressError>);
+
+impl fmt::Display for AddressErrors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} bad address entries", self.0.len())?;
+        for error in &self.0 {
+            write!(f, "\n  {}", error)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for AddressErrors {}
+
+// `address` if it is for `network` and pays a script the pool can use as a leaf output
+pub fn check_address(
+    address: &Address<NetworkUnchecked>,
+    network: Network,
+) -> Result<Address, AddressProblem> {
+    let unchecked = address.clone().assume_checked().to_string();
+    let address =
+        address
+            .clone()
+            .require_network(network)
+            .map_err(|_| AddressProblem::WrongNetwork {
+                address: unchecked.clone(),
+                expected: network,
+            })?;
+    let script = address.script_pubkey();
+    // witness versions past taproot (and v1 programs that aren't 32 bytes) relay, but anyone can
+    // spend them until a soft fork defines them. Newer wallets' addresses can be allowed by version
+    if address.address_type().is_none() {
+        let allowed = script
+            .witness_version()
+            .is_some_and(witness_version_allowed);
+        if !allowed {
+            return Err(AddressProblem::NonStandard {
+                address: unchecked,
+                reason: format!(
+                    "pays undefined witness version {} ({} byte program), anyone could spend it. \
+                     Allow it with POOL_ALLOWED_WITNESS_VERSIONS if a soft fork defined it",
+                    script.witness_version().map_or(0, |v| v.to_num()),
+                    script.len().saturating_sub(2)
+                ),
+            });
+        }
+    }
+    if !(script.is_p2pkh() || script.is_p2sh() || script.is_witness_program()) {
+        return Err(AddressProblem::NonStandard {
+            address: unchecked,
+            reason: format!("script {} is not a standard type", script.to_asm_string()),
+        });
+    }
+    Ok(address)
+}
+
+// Checks the addresses of a list one entry at a time and keeps every problem, so the whole list can
+// be reported at once instead of stopping at the first bad entry
+#[derive(Debug)]
+pub struct AddressChecker {
+    network: Network,
+    seen: HashMap<ScriptBuf, usize>,
+    errors: Vec<AddressError>,
+}
+
+impl AddressChecker {
+    pub fn new(network: Network) -> Self {
+        Self {
+            network,
+            seen: HashMap::new(),
+            errors: Vec::new(),
+        }
+    }
+
+    // the address of `entry` if it is fine, otherwise its problem is kept and None returned
+    pub fn check(
+        &mut self,
+        entry: usize,
+        line: Option<usize>,
+        address: &Address<NetworkUnchecked>,
+    ) -> Option<Address> {
+        match check_address(address, self.network) {
+            Ok(address) => self.checked(entry, line, address),
+            Err(problem) => {
+                self.reject(entry, line, problem);
+                None
+            }
+        }
+    }
+
+    // an address we made ourselves (e.g. derived from an xpub), only duplicates are looked for
+    pub fn checked(
+        &mut self,
+        entry: usize,
+        line: Option<usize>,
+        address: Address,
+    ) -> Option<Address> {
+        if let Some(&first) = self.seen.get(&address.script_pubkey()) {
+            self.reject(
+                entry,
+                line,
+                AddressProblem::Duplicate {
+                    address: address.to_string(),
+                    first,
+                },
+            );
+            return None;
+        }
+        self.seen.insert(address.script_pubkey(), entry);
+        Some(address)
+    }
+
+    pub fn reject(&mut self, entry: usize, line: Option<usize>, problem: AddressProblem) {
+        self.errors.push(AddressError {
+            entry,
+            line,
+            problem,
+        });
+    }
+
+    pub fn errors(&self) -> &[AddressError] {
+        &self.errors
+    }
+
+    // Ok if no entry had a problem, otherwise an AddressErrors with all of them
+    pub fn finish(self) -> Result<()> {
+        if self.errors.is_empty() {
+            Ok(())
+        } else {
+            Err(AddressErrors(self.errors).into())
+        }
+    }
+}
+
+// the 1-based line `raw` starts on, `raw` has to be borrowed from `text`
+pub fn line_of(text: &str, raw: &RawValue) -> usize {
+    let offset = (raw.get().as_ptr() as usize).saturating_sub(text.as_ptr() as usize);
+    text.as_bytes()[..offset.min(text.len())]
+        .iter()
+        .filter(|&&byte| byte == b'\n')
+        .count()
+        + 1
+}
+
+// The entries of the json list in `text`, each with the line it starts on, left unparsed so a bad
+// entry can be reported on its own
+pub fn json_entries(text: &str) -> Result<Vec<(usize, &RawValue)>> {
+    let entries: Vec<&RawValue> = serde_json::from_str(text)?;
+    Ok(entries
+        .into_iter()
+        .map(|raw| (line_of(text, raw), raw))
+        .collect())
+}
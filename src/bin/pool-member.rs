@@ -0,0 +1,299 @@
+// The participant side of a pool: keeps only the user's own branch, follows it over Esplora and
+// takes the user out of the pool without the coordinator
+
+use std::{env, fs, path::PathBuf, str::FromStr, time::Duration};
+
+use anyhow::{anyhow, Result};
+use bitcoin::{
+    consensus::encode::serialize_hex,
+    key::{Keypair, Secp256k1},
+    secp256k1::SecretKey,
+    Txid,
+};
+use clap::{Parser, Subcommand};
+use tracing::info;
+use tracing_subscriber::EnvFilter;
+
+use op_ctv_payment_pool::{
+    esplora::Esplora,
+    key_close::{
+        KeyCloseNonce, KeyCloseSession, DEFAULT_KEY_CLOSE_NONCE, DEFAULT_KEY_CLOSE_SESSION,
+    },
+    member::{broadcast_chain, bump_exit, MemberState, MemberStatus, DEFAULT_MEMBER_STATE},
+    proxy::{parse_proxy, set_proxy},
+};
+
+#[derive(Parser, Debug)]
+#[command(
+    version,
+    about = "Follow your branch of a CTV payment pool and leave it on your own"
+)]
+struct MemberCli {
+    /// Your branch of the pool and where you were last seen in it
+    #[arg(long, default_value = DEFAULT_MEMBER_STATE)]
+    state: PathBuf,
+
+    /// Esplora API to follow the pool with, e.g. https://mempool.space/signet/api. Defaults to POOL_ESPLORA_URL
+    #[arg(long)]
+    esplora_url: Option<String>,
+
+    /// SOCKS5 proxy to reach esplora through, e.g. socks5h://127.0.0.1:9050 for tor. Defaults to POOL_PROXY
+    #[arg(long)]
+    proxy: Option<String>,
+
+    #[command(subcommand)]
+    command: MemberCommand,
+}
+
+#[derive(Subcommand, Debug)]
+enum MemberCommand {
+    /// Check the export the coordinator gave you (`export --user`) and keep it as your state
+    Import {
+        /// Export json file
+        export: PathBuf,
+    },
+    /// Where you are in the pool
+    Status {
+        /// Print json instead of a sentence
+        #[arg(long)]
+        json: bool,
+    },
+    /// Follow your branch until you are out of the pool
+    Watch {
+        /// Seconds between looks
+        #[arg(long, default_value_t = 60)]
+        interval_secs: u64,
+    },
+    /// Broadcast the txs taking you out of the node you are in
+    Exit {
+        /// Print the txs as hex instead of broadcasting them
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Pay for an exit tx yourself through the anchor paying the key you registered (anchor_key)
+    Bump {
+        /// The exit tx paying your anchor
+        txid: Txid,
+        /// File with the hex secret key of your anchor key
+        #[arg(long)]
+        key_file: PathBuf,
+        /// Fee rate the exit and its child pay together, sat/vB
+        #[arg(long)]
+        fee_rate: u64,
+        /// Print the child as hex instead of broadcasting it
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Sign the coordinator's key path close of the pool root (`key-close start`) with the musig
+    /// key you registered: first a nonce, then once every member's nonce is in the signature
+    KeyClose {
+        #[command(subcommand)]
+        step: KeyCloseStep,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum KeyCloseStep {
+    /// Add your nonce to the session, its secret half is kept in --nonce-file until you sign
+    Nonce {
+        /// The session file the coordinator shared
+        #[arg(long, default_value = DEFAULT_KEY_CLOSE_SESSION)]
+        session: PathBuf,
+        /// File with the hex secret key of your musig key
+        #[arg(long)]
+        key_file: PathBuf,
+        #[arg(long, default_value = DEFAULT_KEY_CLOSE_NONCE)]
+        nonce_file: PathBuf,
+        /// Refuse a close paying more than this fee rate, sat/vB. Its fee is split evenly between the members
+        #[arg(long, default_value_t = 20)]
+        max_fee_rate: u64,
+    },
+    /// Add your partial signature to the session, once it has every member's nonce
+    Sign {
+        #[arg(long, default_value = DEFAULT_KEY_CLOSE_SESSION)]
+        session: PathBuf,
+        /// File with the hex secret key of your musig key
+        #[arg(long)]
+        key_file: PathBuf,
+        #[arg(long, default_value = DEFAULT_KEY_CLOSE_NONCE)]
+        nonce_file: PathBuf,
+        #[arg(long, default_value_t = 20)]
+        max_fee_rate: u64,
+    },
+}
+
+impl MemberCli {
+    fn esplora(&self) -> Result<Esplora> {
+        let url = self
+            .esplora_url
+            .clone()
+            .or_else(|| env::var("POOL_ESPLORA_URL").ok())
+            .ok_or_else(|| anyhow!("no esplora url, pass --esplora-url or set POOL_ESPLORA_URL"))?;
+        let proxy = self
+            .proxy
+            .clone()
+            .or_else(|| env::var("POOL_PROXY").ok())
+            .filter(|proxy| !proxy.is_empty());
+        set_proxy(
+            proxy
+                .map(|proxy| parse_proxy(&proxy))
+                .transpose()?
+                .as_deref(),
+        );
+        Esplora::new(&url)
+    }
+}
+
+fn describe(status: &MemberStatus) -> String {
+    match status {
+        MemberStatus::NotFunded => "the pool isn't funded yet".to_string(),
+        MemberStatus::InPool { position } => format!(
+            "in the pool of users {} at {} ({}{})",
+            position.node,
+            position.outpoint,
+            position.amount,
+            if position.confirmed {
+                ""
+            } else {
+                ", unconfirmed"
+            }
+        ),
+        MemberStatus::Left => "out of the pool, none of your nodes holds anything".to_string(),
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")),
+        )
+        .with_target(false)
+        .with_writer(std::io::stderr)
+        .init();
+
+    let cli = MemberCli::parse();
+    match &cli.command {
+        MemberCommand::Import { export } => {
+            let state = MemberState::import_file(export)?;
+            state.write(&cli.state)?;
+            info!("state written to {} \n", cli.state.display());
+            Ok(())
+        }
+        MemberCommand::Status { json } => {
+            let mut state = MemberState::load(&cli.state)?;
+            let status = state.locate(&cli.esplora()?).await?;
+            state.write(&cli.state)?;
+            if *json {
+                println!("{}", serde_json::to_string_pretty(&status)?);
+            } else {
+                println!("user {}: {}", state.user, describe(&status));
+            }
+            Ok(())
+        }
+        MemberCommand::Watch { interval_secs } => {
+            let esplora = cli.esplora()?;
+            let mut state = MemberState::load(&cli.state)?;
+            let mut ticker = tokio::time::interval(Duration::from_secs(*interval_secs));
+            let mut last = None;
+            loop {
+                ticker.tick().await;
+                let status = state.locate(&esplora).await?;
+                state.write(&cli.state)?;
+                if last.as_ref() != Some(&status) {
+                    info!("user {}: {}", state.user, describe(&status));
+                }
+                if status == MemberStatus::Left {
+                    return Ok(());
+                }
+                last = Some(status);
+            }
+        }
+        MemberCommand::Exit { dry_run } => {
+            let esplora = cli.esplora()?;
+            let mut state = MemberState::load(&cli.state)?;
+            let status = state.locate(&esplora).await?;
+            state.write(&cli.state)?;
+            let MemberStatus::InPool { position } = status else {
+                return Err(anyhow!("nothing to exit, {}", describe(&status)));
+            };
+            let chain = state.exit_chain(&position)?;
+            if *dry_run {
+                for tx in &chain {
+                    println!("{}", serialize_hex(tx));
+                }
+                return Ok(());
+            }
+            let txids = broadcast_chain(&esplora, &chain).await?;
+            info!(
+                "{} txs broadcast, the last one pays user {}: {}",
+                txids.len(),
+                state.user,
+                txids[txids.len() - 1]
+            );
+            Ok(())
+        }
+        MemberCommand::KeyClose { step } => {
+            let state = MemberState::load(&cli.state)?;
+            match step {
+                KeyCloseStep::Nonce {
+                    session: path,
+                    key_file,
+                    nonce_file,
+                    max_fee_rate,
+                } => {
+                    let mut session = KeyCloseSession::load(path)?;
+                    // nothing is given out for a close that doesn't pay the user their share
+                    session.check_payout(&state.export, state.user, *max_fee_rate)?;
+                    let secret = SecretKey::from_str(fs::read_to_string(key_file)?.trim())?;
+                    let nonce = session.add_nonce(state.user, &secret)?;
+                    fs::write(nonce_file, serde_json::to_string_pretty(&nonce)?)?;
+                    session.write(path)?;
+                    info!(
+                        "nonce of user {} added to {} \n",
+                        state.user,
+                        path.display()
+                    );
+                }
+                KeyCloseStep::Sign {
+                    session: path,
+                    key_file,
+                    nonce_file,
+                    max_fee_rate,
+                } => {
+                    let mut session = KeyCloseSession::load(path)?;
+                    session.check_payout(&state.export, state.user, *max_fee_rate)?;
+                    let secret = SecretKey::from_str(fs::read_to_string(key_file)?.trim())?;
+                    let nonce: KeyCloseNonce =
+                        serde_json::from_str(&fs::read_to_string(nonce_file)?)?;
+                    session.sign(state.user, &secret, nonce)?;
+                    session.write(path)?;
+                    // a nonce signs once, a second signature with it would give the key away
+                    fs::remove_file(nonce_file)?;
+                    info!("user {} signed {} \n", state.user, path.display());
+                }
+            }
+            Ok(())
+        }
+        MemberCommand::Bump {
+            txid,
+            key_file,
+            fee_rate,
+            dry_run,
+        } => {
+            let esplora = cli.esplora()?;
+            let state = MemberState::load(&cli.state)?;
+            let secret = SecretKey::from_str(fs::read_to_string(key_file)?.trim())?;
+            let keypair = Keypair::from_secret_key(&Secp256k1::new(), &secret);
+            let child =
+                bump_exit(&esplora, *txid, &keypair, *fee_rate, state.export.network).await?;
+            if *dry_run {
+                println!("{}", serialize_hex(&child));
+                return Ok(());
+            }
+            let child_txid = esplora.broadcast(&child).await?;
+            info!("{} bumped by {} \n", txid, child_txid);
+            Ok(())
+        }
+    }
+}
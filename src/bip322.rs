@@ -0,0 +1,123 @@
+use anyhow::{anyhow, bail, Result};
+use bitcoin::{
+    absolute,
+    base64::{engine::general_purpose::STANDARD, Engine},
+    consensus, ecdsa,
+    hashes::{sha256, Hash, HashEngine},
+    key::Secp256k1,
+    opcodes::all::{OP_PUSHBYTES_0, OP_RETURN},
+    script::Builder,
+    secp256k1::Message,
+    sighash::{Prevouts, SighashCache},
+    taproot, transaction, Address, Amount, CompressedPublicKey, OutPoint, ScriptBuf, Sequence,
+    Transaction, TxIn, TxOut, Txid, Witness, XOnlyPublicKey,
+};
+
+// BIP-322 "simple" signatures: the base64 witness of a virtual tx spending an output locked to the address.
+// Only single key segwit addresses (p2tr key path, p2wpkh) are supported, that covers what wallets sign today.
+// https://github.com/bitcoin/bips/blob/master/bip-0322.mediawiki
+
+// the tagged hash of `message` the to_spend tx commits to
+pub fn message_hash(message: &[u8]) -> [u8; 32] {
+    let tag = sha256::Hash::hash(b"BIP0322-signed-message");
+    let mut engine = sha256::Hash::engine();
+    engine.input(tag.as_ref());
+    engine.input(tag.as_ref());
+    engine.input(message);
+    sha256::Hash::from_engine(engine).to_byte_array()
+}
+
+// the virtual tx paying `script_pubkey` whose output a signature spends
+pub fn to_spend(script_pubkey: &ScriptBuf, message: &[u8]) -> Transaction {
+    Transaction {
+        version: transaction::Version(0),
+        lock_time: absolute::LockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: OutPoint {
+                txid: Txid::all_zeros(),
+                vout: 0xFFFFFFFF,
+            },
+            script_sig: Builder::new()
+                .push_opcode(OP_PUSHBYTES_0)
+                .push_slice(message_hash(message))
+                .into_script(),
+            sequence: Sequence::ZERO,
+            witness: Witness::new(),
+        }],
+        output: vec![TxOut {
+            value: Amount::ZERO,
+            script_pubkey: script_pubkey.clone(),
+        }],
+    }
+}
+
+// the virtual tx spending it with the signature as `witness`, what a wallet signs
+pub fn to_sign(to_spend: &Transaction, witness: Witness) -> Transaction {
+    Transaction {
+        version: transaction::Version(0),
+        lock_time: absolute::LockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: OutPoint {
+                txid: to_spend.compute_txid(),
+                vout: 0,
+            },
+            script_sig: ScriptBuf::new(),
+            sequence: Sequence::ZERO,
+            witness,
+        }],
+        output: vec![TxOut {
+            value: Amount::ZERO,
+            script_pubkey: Builder::new().push_opcode(OP_RETURN).into_script(),
+        }],
+    }
+}
+
+pub fn verify_simple(address: &Address, message: &str, signature: &str) -> Result<()> {
+    let witness: Witness = consensus::deserialize(&STANDARD.decode(signature)?)?;
+    let script_pubkey = address.script_pubkey();
+    let to_spend = to_spend(&script_pubkey, message.as_bytes());
+    let to_sign = to_sign(&to_spend, witness);
+    let witness = &to_sign.input[0].witness;
+    let secp = Secp256k1::verification_only();
+
+    if script_pubkey.is_p2tr() {
+        if witness.len() != 1 {
+            bail!("p2tr signature must be a single key path signature");
+        }
+        let signature = taproot::Signature::from_slice(&witness[0])?;
+        let output_key = XOnlyPublicKey::from_slice(&script_pubkey.as_bytes()[2..])?;
+        let sighash = SighashCache::new(&to_sign).taproot_key_spend_signature_hash(
+            0,
+            &Prevouts::All(&[&to_spend.output[0]]),
+            signature.sighash_type,
+        )?;
+        let msg = Message::from_digest(sighash.to_byte_array());
+        secp.verify_schnorr(&signature.signature, &msg, &output_key)
+            .map_err(|_| anyhow!("invalid signature for {}", address))?;
+    } else if script_pubkey.is_p2wpkh() {
+        if witness.len() != 2 {
+            bail!("p2wpkh signature must be a signature and a public key");
+        }
+        let signature = ecdsa::Signature::from_slice(&witness[0])?;
+        let pubkey = CompressedPublicKey::from_slice(&witness[1])?;
+        if ScriptBuf::new_p2wpkh(&pubkey.wpubkey_hash()) != script_pubkey {
+            bail!("public key in the signature does not belong to {}", address);
+        }
+        let sighash = SighashCache::new(&to_sign).p2wpkh_signature_hash(
+            0,
+            &script_pubkey,
+            Amount::ZERO,
+            signature.sighash_type,
+        )?;
+        let msg = Message::from_digest(sighash.to_byte_array());
+        secp.verify_ecdsa(&msg, &signature.signature, &pubkey.0)
+            .map_err(|_| anyhow!("invalid signature for {}", address))?;
+    } else {
+        bail!(
+            "{} is not a p2tr or p2wpkh address, can't check its BIP-322 signature",
+            address
+        );
+    }
+
+    Ok(())
+}
@@ -0,0 +1,276 @@
+use std::{fmt, fs, io::ErrorKind, path::PathBuf, sync::Mutex, time::Duration};
+
+use anyhow::Result;
+use bitcoin::{consensus::encode::deserialize_hex, Transaction, Txid};
+use bitcoincore_rpc::{jsonrpc, RawTx, RpcApi};
+use tracing::{info, trace, warn};
+
+use crate::{metrics::METRICS, rpc_helper::AsyncRpc};
+
+pub const DEFAULT_OUTBOX_DIR: &str = "outbox";
+pub const OUTBOX_POLL: Duration = Duration::from_secs(5);
+
+// Where txs are written instead of sent with sendrawtransaction (--no-broadcast), for operators who
+// broadcast through their own infrastructure. Each one is waited for until the node sees it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Outbox {
+    pub dir: PathBuf,
+    // how often the node is asked whether a tx in the outbox went out
+    pub poll: Duration,
+}
+
+static OUTBOX: Mutex<Option<Outbox>> = Mutex::new(None);
+
+// every broadcast from now on goes to `outbox` instead of the node, None sends them again
+pub fn set_outbox(outbox: Option<Outbox>) {
+    *OUTBOX
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner()) = outbox;
+}
+
+pub fn outbox() -> Option<Outbox> {
+    OUTBOX
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .clone()
+}
+
+// What is being broadcast, the same reject means something else for each
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BroadcastKind {
+    Funding,
+    PoolSpend,
+    AnchorChild,
+    RecoverySweep,
+}
+
+// Why the node refused a tx, from the reject string of sendrawtransaction
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RejectReason {
+    // "bad-txns-inputs-missingorspent", "missing-inputs"
+    MissingInputs,
+    // "txn-mempool-conflict"
+    MempoolConflict,
+    // "txn-already-in-mempool", "txn-already-known", already in the chain
+    AlreadyKnown,
+    // "min relay fee not met", "mempool min fee not met"
+    MinRelayFee,
+    // "insufficient fee" for a replacement
+    ReplacementFee,
+    // "non-BIP68-final", "non-final"
+    NonFinal,
+    // "TRUC-violation", "v3-rule-violation"
+    Truc(String),
+    // "(non-)mandatory-script-verify-flag", with the script error bitcoind gave
+    ScriptVerify(String),
+    // anything else, as bitcoind said it
+    Other(String),
+}
+
+impl RejectReason {
+    pub fn parse(message: &str) -> Self {
+        let lower = message.to_ascii_lowercase();
+        // the detail in brackets after the reject code, e.g. "(Witness program hash mismatch)"
+        let detail = || {
+            message
+                .split_once('(')
+                .and_then(|(_, rest)| rest.rsplit_once(')'))
+                .map_or_else(|| message.to_string(), |(detail, _)| detail.to_string())
+        };
+        if lower.contains("missingorspent") || lower.contains("missing-inputs") {
+            Self::MissingInputs
+        } else if lower.contains("txn-mempool-conflict") {
+            Self::MempoolConflict
+        } else if lower.contains("already-in-mempool")
+            || lower.contains("already-known")
+            || lower.contains("already in block chain")
+            || lower.contains("outputs already in utxo set")
+        {
+            Self::AlreadyKnown
+        } else if lower.contains("min relay fee not met")
+            || lower.contains("mempool min fee not met")
+        {
+            Self::MinRelayFee
+        } else if lower.contains("insufficient fee") {
+            Self::ReplacementFee
+        } else if lower.contains("non-bip68-final") || lower.contains("non-final") {
+            Self::NonFinal
+        } else if lower.contains("truc-violation") || lower.contains("v3-rule-violation") {
+            Self::Truc(message.to_string())
+        } else if lower.contains("script-verify-flag") {
+            Self::ScriptVerify(detail())
+        } else {
+            Self::Other(message.to_string())
+        }
+    }
+
+    // what this usually means for `kind`, and what to do about it
+    pub fn hint(&self, kind: BroadcastKind) -> Option<&'static str> {
+        use BroadcastKind::*;
+        let hint = match (self, kind) {
+            (Self::MissingInputs, PoolSpend) => "parent pool spend not yet broadcast, or the funding tx isn't in the mempool yet; broadcast the previous withdrawal first",
+            (Self::MissingInputs, AnchorChild) => "the pool spend paying this anchor isn't in the mempool, it was refused or already confirmed with another child",
+            (Self::MissingInputs, RecoverySweep) => "the pool node was spent since the sweep was built, a withdrawal probably went out",
+            (Self::MissingInputs, Funding) => "a wallet coin of this tx was spent elsewhere, build it again",
+            (Self::MempoolConflict, PoolSpend) => "another spend of this pool node is already in the mempool, someone else withdrew or swept first (see `watch`)",
+            (Self::MempoolConflict, AnchorChild) => "the anchor already has a child, a pool spend can only have one unconfirmed child",
+            (Self::MempoolConflict, RecoverySweep) => "a withdrawal of this pool node is already in the mempool",
+            (Self::MempoolConflict, Funding) => "a wallet coin of this tx is spent by another unconfirmed tx",
+            (Self::AlreadyKnown, _) => "the node already has this tx, nothing to do",
+            (Self::MinRelayFee, PoolSpend) => "the pool spend's fee is fixed by its template, it has to go out with its anchor child as a package or when the mempool clears",
+            (Self::MinRelayFee, _) => "pay a higher fee rate or wait for the mempool to clear",
+            (Self::ReplacementFee, _) => "a conflicting tx pays more, a replacement has to outbid it",
+            (Self::NonFinal, PoolSpend) => "the leaf's relative timelock hasn't matured, wait for the parent pool spend to get more confirmations",
+            (Self::NonFinal, RecoverySweep) => "the recovery timelock hasn't expired yet, wait for more blocks",
+            (Self::NonFinal, _) => "a timelock of this tx hasn't expired yet",
+            (Self::Truc(_), _) => "v3 txs allow one unconfirmed child, wait for the previous pool spend to confirm",
+            (Self::ScriptVerify(_), PoolSpend) => "the spend doesn't satisfy its leaf: the node may not enforce the covenant (run `doctor`) or the template doesn't match the manifest (see `explain`)",
+            (Self::ScriptVerify(_), RecoverySweep) => "the recovery leaf refused the sweep, check the recovery key and the timelock",
+            (Self::ScriptVerify(_), _) => "the wallet's signature was refused",
+            (Self::Other(_), _) => return None,
+        };
+        Some(hint)
+    }
+}
+
+impl fmt::Display for RejectReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MissingInputs => write!(f, "missing inputs"),
+            Self::MempoolConflict => write!(f, "conflicts with a tx in the mempool"),
+            Self::AlreadyKnown => write!(f, "already known"),
+            Self::MinRelayFee => write!(f, "min relay fee not met"),
+            Self::ReplacementFee => write!(f, "insufficient fee to replace a tx in the mempool"),
+            Self::NonFinal => write!(f, "timelock not final"),
+            Self::Truc(message) => write!(f, "v3 policy violation ({})", message),
+            Self::ScriptVerify(detail) => write!(f, "script verification failed ({})", detail),
+            Self::Other(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl fmt::Display for BroadcastKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Funding => write!(f, "funding tx"),
+            Self::PoolSpend => write!(f, "pool spend"),
+            Self::AnchorChild => write!(f, "anchor child"),
+            Self::RecoverySweep => write!(f, "recovery sweep"),
+        }
+    }
+}
+
+// A tx the node refused, downcast an anyhow error to this to act on the reason
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BroadcastError {
+    pub kind: BroadcastKind,
+    pub reason: RejectReason,
+    // the rpc error code
+    pub code: i32,
+}
+
+impl fmt::Display for BroadcastError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "the node refused the {}: {}", self.kind, self.reason)?;
+        if let Some(hint) = self.reason.hint(self.kind) {
+            write!(f, " ({})", hint)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for BroadcastError {}
+
+// the rpc error bitcoind answered with, if that's what `error` is
+pub fn rpc_reject(error: &anyhow::Error) -> Option<&jsonrpc::error::RpcError> {
+    match error.downcast_ref::<bitcoincore_rpc::Error>()? {
+        bitcoincore_rpc::Error::JsonRpc(jsonrpc::Error::Rpc(rpc_error)) => Some(rpc_error),
+        _ => None,
+    }
+}
+
+// turn a refused sendrawtransaction into a BroadcastError, anything else (connection, auth) is passed on
+pub fn classify(error: anyhow::Error, kind: BroadcastKind) -> anyhow::Error {
+    match rpc_reject(&error) {
+        Some(rpc_error) => BroadcastError {
+            kind,
+            reason: RejectReason::parse(&rpc_error.message),
+            code: rpc_error.code,
+        }
+        .into(),
+        None => error,
+    }
+}
+
+// Whether the node has `tx`: in its mempool or, with -txindex, in a block. Without -txindex a
+// confirmed tx is found by an output of it still unspent.
+pub async fn node_has(rpc: &AsyncRpc, tx: &Transaction) -> bool {
+    let txid = tx.compute_txid();
+    if rpc
+        .run(move |c| c.get_raw_transaction(&txid, None))
+        .await
+        .is_ok()
+    {
+        return true;
+    }
+    for vout in 0..tx.output.len() as u32 {
+        if let Ok(Some(_)) = rpc
+            .run(move |c| c.get_tx_out(&txid, vout, Some(true)))
+            .await
+        {
+            return true;
+        }
+    }
+    false
+}
+
+// Write `hex` to the outbox and wait for the node to see it, whoever sends it. The file is removed
+// once it has.
+async fn hand_off(
+    rpc: &AsyncRpc,
+    outbox: &Outbox,
+    hex: String,
+    kind: BroadcastKind,
+) -> Result<Txid> {
+    let tx: Transaction = deserialize_hex(&hex)?;
+    let txid = tx.compute_txid();
+    fs::create_dir_all(&outbox.dir)?;
+    let file = outbox.dir.join(format!(
+        "{}-{}.hex",
+        kind.to_string().replace(' ', "_"),
+        txid
+    ));
+    fs::write(&file, format!("{}\n", hex))?;
+    info!(
+        "{} {} written to {}, waiting for it to be broadcast \n",
+        kind,
+        txid,
+        file.display()
+    );
+    while !node_has(rpc, &tx).await {
+        trace!("{} {} not seen by the node yet", kind, txid);
+        tokio::time::sleep(outbox.poll).await;
+    }
+    info!("{} {} seen by the node \n", kind, txid);
+    // whoever sent it may have moved it already
+    if let Err(e) = fs::remove_file(&file) {
+        if e.kind() != ErrorKind::NotFound {
+            warn!("can't remove {} from the outbox: {}", file.display(), e);
+        }
+    }
+    Ok(txid)
+}
+
+// sendrawtransaction, counted in the metrics, with the reject decoded if the node refuses it. With
+// an outbox set the tx is handed off there instead, see set_outbox.
+pub async fn broadcast(rpc: &AsyncRpc, tx: impl RawTx, kind: BroadcastKind) -> Result<Txid> {
+    let hex = tx.raw_hex();
+    if let Some(outbox) = outbox() {
+        return METRICS.record_broadcast(hand_off(rpc, &outbox, hex, kind).await);
+    }
+    METRICS.record_broadcast(
+        rpc.run(move |c| c.send_raw_transaction(hex))
+            .await
+            .map_err(|e| classify(e, kind)),
+    )
+}
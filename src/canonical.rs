@@ -0,0 +1,73 @@
+use anyhow::{bail, Result};
+use bitcoin::hashes::{sha256, Hash};
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::manifest::PoolManifest;
+
+// largest integer a double holds exactly, what a json parser in any language reads back unchanged
+const MAX_SAFE_INTEGER: u64 = (1 << 53) - 1;
+
+// Canonical json (RFC 8785, JCS) of anything that serializes to json: no whitespace, object keys
+// sorted by their UTF-16 code units, strings escaped the minimal way. Numbers are only ever integers
+// printed in decimal, a float or an integer a double can't hold exactly is refused instead of being
+// formatted some way another implementation might not.
+pub fn canonical_json<T: Serialize>(value: &T) -> Result<String> {
+    let mut out = String::new();
+    write_value(&serde_json::to_value(value)?, &mut out)?;
+    Ok(out)
+}
+
+fn write_value(value: &Value, out: &mut String) -> Result<()> {
+    match value {
+        Value::Null | Value::Bool(_) | Value::String(_) => {
+            // serde_json escapes exactly what JCS does: ", \, the short forms and \u00xx for the rest
+            out.push_str(&serde_json::to_string(value)?)
+        }
+        Value::Number(number) => {
+            let safe = match (number.as_u64(), number.as_i64()) {
+                (Some(n), _) => n <= MAX_SAFE_INTEGER,
+                (None, Some(n)) => n.unsigned_abs() <= MAX_SAFE_INTEGER,
+                (None, None) => false,
+            };
+            if !safe {
+                bail!(
+                    "{} has no canonical json form, only integers up to 2^53 - 1 do",
+                    number
+                );
+            }
+            out.push_str(&number.to_string());
+        }
+        Value::Array(items) => {
+            out.push('[');
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                write_value(item, out)?;
+            }
+            out.push(']');
+        }
+        Value::Object(map) => {
+            let mut entries: Vec<_> = map.iter().collect();
+            entries.sort_by(|(a, _), (b, _)| a.encode_utf16().cmp(b.encode_utf16()));
+            out.push('{');
+            for (i, (key, item)) in entries.into_iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                out.push_str(&serde_json::to_string(key)?);
+                out.push(':');
+                write_value(item, out)?;
+            }
+            out.push('}');
+        }
+    }
+    Ok(())
+}
+
+// sha256 of the manifest's canonical json, the same whichever format it is stored in. Fields an
+// older manifest doesn't have are hashed with the defaults they load with.
+pub fn manifest_hash(manifest: &PoolManifest) -> Result<sha256::Hash> {
+    Ok(sha256::Hash::hash(canonical_json(manifest)?.as_bytes()))
+}
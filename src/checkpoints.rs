@@ -0,0 +1,164 @@
+use std::collections::HashMap;
+
+use anyhow::Result;
+use bitcoin::{BlockHash, OutPoint, ScriptBuf, Transaction, Txid};
+use bitcoincore_rpc::RpcApi;
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::{fetch::RpcFetcher, ids::NodePath, manifest::PoolManifest, rpc_helper::AsyncRpc};
+
+// A confirmed pool tx and the block it is in. A restart picks up from the last one still in the
+// chain instead of looking up every pool tx again, which adds up for a pool unwinding over weeks.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Checkpoint {
+    pub txid: Txid,
+    pub height: u64,
+    pub block_hash: BlockHash,
+    // the pool node the tx spent, None for the funding tx
+    pub spent: Option<OutPoint>,
+    // the pool nodes it created
+    pub nodes: Vec<(OutPoint, NodePath)>,
+}
+
+impl Checkpoint {
+    // `tx` spending `spent`, confirmed at `height`, its outputs paying a node in `nodes` (node
+    // scriptPubKey -> users in it) are the nodes it created
+    pub fn of_tx(
+        tx: &Transaction,
+        spent: Option<OutPoint>,
+        height: u64,
+        block_hash: BlockHash,
+        nodes: &HashMap<ScriptBuf, NodePath>,
+    ) -> Self {
+        let txid = tx.compute_txid();
+        Self {
+            txid,
+            height,
+            block_hash,
+            spent,
+            nodes: tx
+                .output
+                .iter()
+                .enumerate()
+                .filter_map(|(vout, output)| {
+                    nodes
+                        .get(&output.script_pubkey)
+                        .map(|users| (OutPoint::new(txid, vout as u32), users.clone()))
+                })
+                .collect(),
+        }
+    }
+}
+
+// the pool nodes every checkpointed tx together left unspent
+pub fn unspent_nodes(checkpoints: &[Checkpoint]) -> HashMap<OutPoint, NodePath> {
+    let mut unspent = HashMap::new();
+    for checkpoint in checkpoints {
+        if let Some(spent) = &checkpoint.spent {
+            unspent.remove(spent);
+        }
+        unspent.extend(checkpoint.nodes.iter().cloned());
+    }
+    unspent
+}
+
+// Add `checkpoint` unless its tx already has one, keeping them in height order. Returns whether it
+// was added.
+pub fn record_checkpoint(checkpoints: &mut Vec<Checkpoint>, checkpoint: Checkpoint) -> bool {
+    if checkpoints
+        .iter()
+        .any(|known| known.txid == checkpoint.txid)
+    {
+        return false;
+    }
+    let at = checkpoints.partition_point(|known| known.height <= checkpoint.height);
+    checkpoints.insert(at, checkpoint);
+    true
+}
+
+// The checkpoint of `txid` once it is confirmed, None while it isn't. The node it spent is whichever
+// one `checkpoints` left unspent.
+pub async fn confirmed_checkpoint(
+    rpc: &AsyncRpc,
+    txid: Txid,
+    nodes: &HashMap<ScriptBuf, NodePath>,
+    checkpoints: &[Checkpoint],
+) -> Result<Option<Checkpoint>> {
+    let info = rpc
+        .run(move |c| c.get_raw_transaction_info(&txid, None))
+        .await?;
+    let Some(block_hash) = info.blockhash else {
+        return Ok(None);
+    };
+    let header = rpc
+        .run(move |c| c.get_block_header_info(&block_hash))
+        .await?;
+    let tx = info.transaction()?;
+    let unspent = unspent_nodes(checkpoints);
+    let spent = tx
+        .input
+        .iter()
+        .map(|input| input.previous_output)
+        .find(|outpoint| unspent.contains_key(outpoint));
+    Ok(Some(Checkpoint::of_tx(
+        &tx,
+        spent,
+        header.height as u64,
+        block_hash,
+        nodes,
+    )))
+}
+
+// Checkpoint `txid` in `manifest` if it is confirmed. Checkpoints only make restarts faster, so a
+// failed lookup is logged and skipped. Returns whether the manifest changed.
+pub async fn checkpoint_tx(
+    rpc: &AsyncRpc,
+    manifest: &mut PoolManifest,
+    txid: Txid,
+    nodes: &HashMap<ScriptBuf, NodePath>,
+) -> bool {
+    match confirmed_checkpoint(rpc, txid, nodes, &manifest.checkpoints).await {
+        Ok(Some(checkpoint)) => record_checkpoint(&mut manifest.checkpoints, checkpoint),
+        Ok(None) => false,
+        Err(e) => {
+            warn!("no checkpoint for {}: {}", txid, e);
+            false
+        }
+    }
+}
+
+// Drop the checkpoints whose block a reorg took out of the chain, the txs after the last one left
+// are found again by the rescan. Returns how many were dropped.
+pub async fn prune_reorged(
+    rpc: &AsyncRpc,
+    fetcher: &RpcFetcher,
+    checkpoints: &mut Vec<Checkpoint>,
+) -> Result<usize> {
+    let tip = rpc.run(|c| c.get_block_count()).await?;
+    // what the chain has at every checkpoint's height, in one go
+    let heights: Vec<u64> = checkpoints
+        .iter()
+        .map(|checkpoint| checkpoint.height)
+        .filter(|height| *height <= tip)
+        .collect();
+    let in_chain: HashMap<u64, BlockHash> = heights
+        .iter()
+        .copied()
+        .zip(fetcher.block_hashes(&heights).await?)
+        .collect();
+    let mut dropped = 0;
+    // the blocks before one still in the chain are too
+    while let Some(last) = checkpoints.last() {
+        if in_chain.get(&last.height) == Some(&last.block_hash) {
+            break;
+        }
+        warn!(
+            "block {} of pool tx {} is no longer in the chain, dropping its checkpoint",
+            last.block_hash, last.txid
+        );
+        checkpoints.pop();
+        dropped += 1;
+    }
+    Ok(dropped)
+}
@@ -0,0 +1,720 @@
+use std::{io::Write, net::SocketAddr, path::PathBuf};
+
+use bitcoin::{address::NetworkUnchecked, Address, OutPoint, Txid, XOnlyPublicKey};
+use clap::{CommandFactory, Parser, Subcommand};
+use clap_complete::Shell;
+
+use crate::{
+    broadcast::DEFAULT_OUTBOX_DIR,
+    close::DEFAULT_ARCHIVE_DIR,
+    cold::DEFAULT_PSBT_DIR,
+    config::DEFAULT_FEE_RATE,
+    ids::{PoolId, UserIndex},
+    key_close::{DEFAULT_KEY_CLOSE_SESSION, DEFAULT_KEY_CLOSE_TIMEOUT_SECS},
+    packages::DEFAULT_PACKAGE_DIR,
+    profile::NetworkProfile,
+    removal::Rebalance,
+    research::{Shape, UsersRange},
+    vault::{DEFAULT_UNVAULT_DELAY, DEFAULT_VAULT_FILE},
+};
+
+#[derive(Parser, Debug)]
+#[command(version, about = "CTV payment pool proof of concept")]
+pub struct Cli {
+    /// Network profile to run against. Defaults to the one selected with cargo features
+    #[arg(long, global = true, value_enum, default_value_t = NetworkProfile::compiled_default())]
+    pub network: NetworkProfile,
+
+    /// Where withdrawal receipts are written
+    #[arg(long, default_value = "receipts")]
+    pub receipts_dir: PathBuf,
+
+    /// Pool manifest, written when the pool is created and read by the offline commands
+    #[arg(long, default_value = "pool_manifest.json")]
+    pub manifest: PathBuf,
+
+    /// BIP-329 wallet labels for every pool tx and address, written after the demo unwinds the pool
+    #[arg(long, default_value = "pool_labels.jsonl")]
+    pub labels: PathBuf,
+
+    /// Withdraw addresses registered by the users, json list of {address, signature} where the
+    /// signature is a BIP-322 signature of the pool id. Without it the wallet plays every user
+    #[arg(long, requires = "pool_id")]
+    pub registrations: Option<PathBuf>,
+
+    /// Pool id the users sign when registering
+    #[arg(long)]
+    pub pool_id: Option<PoolId>,
+
+    /// Payroll registry, every pay period's pool computed in advance
+    #[arg(long, default_value = "payroll.json")]
+    pub payroll: PathBuf,
+
+    /// Change of the demo's funding tx goes here instead of to a fresh wallet change address
+    #[arg(long)]
+    pub change_address: Option<Address<NetworkUnchecked>>,
+
+    /// Have the demo's funding tx pay the entry pool directly, instead of a staging output that the
+    /// pool psbt then spends
+    #[arg(long)]
+    pub fund_directly: bool,
+
+    /// Serve prometheus metrics at /metrics on this address while the command runs, e.g. 127.0.0.1:9184
+    #[arg(long)]
+    pub metrics_addr: Option<SocketAddr>,
+
+    /// Never send a tx to the node: write each one to --outbox instead and wait until it shows up
+    /// in the mempool or the chain, for broadcasting through other infrastructure
+    #[arg(long)]
+    pub no_broadcast: bool,
+
+    /// Where txs are written with --no-broadcast, one <kind>-<txid>.hex file each
+    #[arg(long, default_value = DEFAULT_OUTBOX_DIR, requires = "no_broadcast")]
+    pub outbox: PathBuf,
+
+    /// Print a failure as json on stderr, {"code": "E_...", "error": "..."}, for scripts to branch on
+    /// the code instead of the message
+    #[arg(long, global = true)]
+    pub json_errors: bool,
+
+    /// Without a command the full pool demo is run (create, fund and unwind)
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Create the withdrawal receipt for an exit that has confirmed since the demo ran
+    Receipt {
+        /// Index of the user in the pool
+        #[arg(long)]
+        user: UserIndex,
+        /// Txid of the transaction paying the user
+        #[arg(long)]
+        txid: Txid,
+        /// The user's withdraw address
+        #[arg(long)]
+        address: Address<NetworkUnchecked>,
+    },
+    /// Check a withdrawal receipt offline, no node needed
+    VerifyReceipt {
+        /// Receipt json file
+        file: PathBuf,
+    },
+    /// Watch the pool, post its lifecycle events to WEBHOOK_URL and sweep nodes to the recovery
+    /// address once their timeout matures
+    Watch {
+        /// Seconds between utxo set scans
+        #[arg(long, default_value_t = 60)]
+        interval_secs: u64,
+    },
+    /// Ledger of every sat in and out of the pool, from the funding inputs to the last payout
+    Report {
+        /// One csv line per entry instead of the totals
+        #[arg(long)]
+        csv: bool,
+        /// Write the csv to this file instead of stdout
+        #[arg(long, requires = "csv")]
+        output: Option<PathBuf>,
+    },
+    /// Narrate what a pool transaction does: the leaf it spends, the template hash it has to match,
+    /// who gets each output and the fee and anchor. Only a txid needs the node
+    Explain {
+        /// Txids of confirmed or mempool txs, or the raw hex of templates or signed txs. The txids
+        /// are looked up in one batch
+        #[arg(required = true)]
+        txs: Vec<String>,
+        /// Print json instead of the narrative, an array when more than one tx is given
+        #[arg(long)]
+        json: bool,
+    },
+    /// Rebuild pool txs from the manifest and compare them with what is on chain
+    Debug {
+        #[command(subcommand)]
+        command: DebugCommand,
+    },
+    /// Rebuild the pool from the manifest alone (no env vars) and check it against the funding
+    /// output: the root, every leaf's control block and that each leaf pays its user
+    Audit {
+        /// Raw hex of the funding tx instead of asking the node for it
+        #[arg(long)]
+        funding_tx: Option<PathBuf>,
+        /// Also finalize every templated spend of every node and execute its witness against the
+        /// output it spends with the built-in tapscript interpreter
+        #[arg(long)]
+        execute_scripts: bool,
+        /// Print json instead of a summary
+        #[arg(long)]
+        json: bool,
+    },
+    /// Rebuild the pool from the manifest and export its nodes, leaf scripts and control blocks
+    Export {
+        /// Only export what this user needs to leave the pool
+        #[arg(long)]
+        user: Option<UserIndex>,
+        /// Only the path from the root to the user's leaf in the planned unwind
+        #[arg(long, requires = "user")]
+        branch_only: bool,
+        /// Write to this file instead of stdout
+        #[arg(long)]
+        output: Option<PathBuf>,
+    },
+    /// Check the leaf proofs of an export against their node addresses, no manifest or node needed
+    VerifyExport {
+        /// Export json file
+        file: PathBuf,
+    },
+    /// Write every spend of the planned unwind (and the close-all spend) as a PSBT with its tap leaf
+    /// script, control block and template hash, for an offline machine to finalize
+    ExportPsbts {
+        /// Directory the PSBTs are written to
+        #[arg(long, default_value = DEFAULT_PSBT_DIR)]
+        output_dir: PathBuf,
+        /// Raw hex of the funding tx, instead of asking the node for the manifest's funding txid
+        #[arg(long)]
+        funding_tx: Option<PathBuf>,
+        /// Write version 0 PSBTs (BIP-174) for signers that don't read version 2 (BIP-370) yet
+        #[arg(long)]
+        psbt_v0: bool,
+        /// Print json instead of the list of files
+        #[arg(long)]
+        json: bool,
+    },
+    /// Write every step of the planned unwind (and the close-all spend) as the json array of raw txs
+    /// `bitcoin-cli submitpackage` takes: the template and an anchor child signed by the fee payer
+    /// wallet. Nothing is broadcast
+    ExportPackages {
+        /// Directory the packages are written to
+        #[arg(long, default_value = DEFAULT_PACKAGE_DIR)]
+        output_dir: PathBuf,
+        /// Raw hex of the funding tx, instead of asking the node for the manifest's funding txid
+        #[arg(long)]
+        funding_tx: Option<PathBuf>,
+        /// Fee rate of the anchor children in sat/vB, the node's estimate by default
+        #[arg(long)]
+        fee_rate: Option<u64>,
+        /// Print json instead of the list of files
+        #[arg(long)]
+        json: bool,
+    },
+    /// Finalize a pool spend PSBT: check the tx matches the template its OP_CTV leaf commits to and
+    /// attach the leaf script and control block. Written back in the PSBT version it was read in.
+    /// No manifest or node needed
+    FinalizePsbt {
+        /// PSBT file, base64 or binary
+        file: PathBuf,
+        /// Write the finalized PSBT here instead of over the input file
+        #[arg(long)]
+        output: Option<PathBuf>,
+    },
+    /// Broadcast the tx of a finalized PSBT, no manifest needed
+    BroadcastPsbt {
+        /// PSBT file, base64 or binary
+        file: PathBuf,
+    },
+    /// Prove that an address is paid by a leaf of the pool: the leaf's template, its merkle path and
+    /// the pool output key, for an auditor to check without the rest of the pool
+    ProveMembership {
+        /// A withdraw address of the pool, or one of a user's split addresses
+        address: String,
+        /// Write to this file instead of stdout
+        #[arg(long)]
+        output: Option<PathBuf>,
+    },
+    /// Check a membership proof, no manifest or node needed
+    VerifyMembership {
+        /// Membership proof json file
+        file: PathBuf,
+    },
+    /// Prove the funded pool's one output backs every member's claim: the funding tx, the manifest
+    /// and what each user is owed, for anyone to check without a node
+    ProveReserves {
+        /// Raw hex of the funding tx, instead of asking the node for the manifest's funding txid
+        #[arg(long)]
+        funding_tx: Option<PathBuf>,
+        /// Write to this file instead of stdout
+        #[arg(long)]
+        output: Option<PathBuf>,
+    },
+    /// Check a proof of reserves, no manifest or node needed
+    VerifyReserves {
+        /// Proof of reserves json file
+        file: PathBuf,
+        /// Also check the funding output is still unspent with this Esplora API
+        #[arg(long)]
+        esplora_url: Option<String>,
+        /// Print json instead of a summary
+        #[arg(long)]
+        json: bool,
+    },
+    /// Bundle the manifest, every user's exit kit, the node descriptors and the labels into one
+    /// archive signed with POOL_COORDINATOR_KEY, and upload it to POOL_PUBLISH_TO if set
+    Publish {
+        /// Where the archive is written
+        #[arg(long, default_value = "pool_release.tar.zst")]
+        output: PathBuf,
+        /// Upload here instead of POOL_PUBLISH_TO: a directory, or an http(s) url to PUT to
+        #[arg(long)]
+        to: Option<String>,
+        /// Print json instead of a summary
+        #[arg(long)]
+        json: bool,
+    },
+    /// Check a published archive's signature and every file in it, no manifest or node needed
+    VerifyRelease {
+        /// Archive written by `publish`
+        file: PathBuf,
+        /// The coordinator's key the release has to be signed with
+        #[arg(long)]
+        coordinator: Option<XOnlyPublicKey>,
+    },
+    /// Fund the pool in the manifest with a PSBT from another wallet, signing what is left with ours
+    Fund {
+        /// PSBT paying exactly the pool amount to the pool address, base64 or binary
+        #[arg(long)]
+        psbt: PathBuf,
+        /// Verification code of another participant (see `p2p verify`), funding is refused
+        /// unless every one matches this manifest
+        #[arg(long = "code")]
+        codes: Vec<String>,
+    },
+    /// Pay every user of the funded pool at once through the root's close-all leaf (pools created
+    /// with POOL_CLOSE_ALL_LEAF=true), instead of unwinding it level by level
+    CloseAll,
+    /// Once every user has left (or a recovery sweep took the rest), check every sat of the pool
+    /// is accounted for on chain and move the manifest to the archive, so nothing runs against it again
+    Close {
+        /// The pool's manifest goes in a directory named after its root address in here
+        #[arg(long, default_value = DEFAULT_ARCHIVE_DIR)]
+        archive_dir: PathBuf,
+        /// Also write the final ledger csv to the archive
+        #[arg(long)]
+        report: bool,
+        /// Print json instead of the summary
+        #[arg(long)]
+        json: bool,
+    },
+    /// Give up a pool before its funding confirms: an unconfirmed funding tx is replaced by one
+    /// paying its coins back, the users are told through the webhook and the pool is cancelled
+    Abort {
+        /// Where the replacement pays the funding coins, the address of the funding tx's first
+        /// input by default
+        #[arg(long)]
+        refund_to: Option<Address<NetworkUnchecked>>,
+        /// Print json instead of the summary
+        #[arg(long)]
+        json: bool,
+    },
+    /// Take a participant who dropped out before funding out of the pool: the tree is rebuilt, the
+    /// pool is funded at the node of the users left and the manifest's revision is bumped
+    RemoveParticipant {
+        /// Position of the user in the tree
+        #[arg(long)]
+        user: UserIndex,
+        /// What happens to their share
+        #[arg(long, value_enum, default_value_t = Rebalance::ReduceFunding)]
+        rebalance: Rebalance,
+    },
+    /// Close the funded pool in one tx through the root's MuSig2 key path (pools whose users all
+    /// registered a musig key), falling back to the root's leaves if a member doesn't sign in time
+    KeyClose {
+        #[command(subcommand)]
+        command: KeyCloseCommand,
+    },
+    /// Pools on Elements, where the leaves check the exits with introspection opcodes instead of
+    /// OP_CTV. Offline: the txs are written for `elements-cli sendrawtransaction`
+    #[cfg(feature = "elements")]
+    Elements {
+        #[command(subcommand)]
+        command: ElementsCommand,
+    },
+    /// Wallet coins held for pool fundings: what the node has locked and what POOL_RESERVATIONS_FILE
+    /// says each pool holds
+    Reservations {
+        /// Unlock and forget the coins held for this pool address, after a funding that died
+        #[arg(long)]
+        release: Option<String>,
+        /// Print json instead of the summary
+        #[arg(long)]
+        json: bool,
+    },
+    /// Fee and cost breakdown of the pool as configured (with the splits of --manifest if it
+    /// exists), no node needed
+    Costs {
+        /// Fee rate for the anchor children and the naive payout comparison, sat/vB
+        #[arg(long, default_value_t = DEFAULT_FEE_RATE / 1000)]
+        fee_rate: u64,
+        /// Print json instead of a table
+        #[arg(long)]
+        json: bool,
+    },
+    /// Check the node, network, wallet, OP_CTV, zmq and the manifest, with a fix for everything that is off
+    Doctor {
+        /// Print json instead of a list
+        #[arg(long)]
+        json: bool,
+    },
+    /// Witness weight of every spend path with the balanced and the weighted tree layout, no node needed
+    Footprint {
+        /// Print json instead of a table
+        #[arg(long)]
+        json: bool,
+    },
+    /// Fee rate and size every template of the manifest's pool commits to, and whether relay policy
+    /// could strand any of them
+    TemplateFees {
+        /// Print json instead of a summary
+        #[arg(long)]
+        json: bool,
+    },
+    /// What the manifest's pool gives away on chain (address reuse, uniform amounts, the anchor
+    /// output, revealed leaves) and the settings that would blur it
+    Privacy {
+        /// Print json instead of a summary
+        #[arg(long)]
+        json: bool,
+    },
+    /// Compute the pool of every pay period in a payroll template and write the payroll registry
+    PayrollSchedule {
+        /// Recipients, amounts and pay periods, json
+        #[arg(long)]
+        template: PathBuf,
+    },
+    /// Convert the manifest between json and the compressed binary state format
+    State {
+        #[command(subcommand)]
+        command: StateCommand,
+    },
+    /// Canonical form of the manifest, for hashing and signing
+    Manifest {
+        #[command(subcommand)]
+        command: ManifestCommand,
+    },
+    /// Labels on the pool and its users (names, emails, internal ids), kept in the manifest
+    Meta {
+        #[command(subcommand)]
+        command: MetaCommand,
+    },
+    /// Open registration for a pool to anyone: registrations are taken over http until the pool is
+    /// full or the window closes, then the pool is built and its manifest published. Every step is
+    /// streamed to clients of GET /events
+    Serve {
+        /// Address to take registrations on
+        #[arg(long, default_value = "127.0.0.1:8340")]
+        listen: SocketAddr,
+        /// Seconds before registration opens, so the pool can be announced ahead of it. /pool
+        /// answers meanwhile, /register refuses
+        #[arg(long, default_value_t = 0)]
+        opens_in_secs: u64,
+        /// How long registration stays open once it has opened
+        #[arg(long, default_value_t = 3600)]
+        window_secs: u64,
+        /// Once the pool is built, watch it on chain like `watch` and stream its funding and
+        /// withdrawals too. Needs the node
+        #[arg(long)]
+        follow: bool,
+        /// Seconds between utxo set scans with --follow
+        #[arg(long, default_value_t = 60, requires = "follow")]
+        interval_secs: u64,
+    },
+    /// The scripted demo, e.g. `demo run --network inquisition` as an end-to-end test on a real network
+    Demo {
+        #[command(subcommand)]
+        command: DemoCommand,
+    },
+    /// Pools without a coordinator: every participant checks the manifest and compares codes
+    P2p {
+        #[command(subcommand)]
+        command: P2pCommand,
+    },
+    /// Build pools over a range of parameters without broadcasting anything, for research
+    Research {
+        #[command(subcommand)]
+        command: ResearchCommand,
+    },
+    /// Replay scenarios against the manifest's pool without a node or broadcasting anything
+    Simulate {
+        #[command(subcommand)]
+        command: SimulateCommand,
+    },
+    /// A single owner CTV vault built from the pool's template code: the vault output can only go
+    /// to the unvault tx, which sends to the cold address at any time or to the hot key after a delay
+    Vault {
+        #[command(subcommand)]
+        command: VaultCommand,
+    },
+    /// Fund and unwind the pool of the current pay period from the payroll registry
+    Payroll {
+        /// Pay this period instead of the one the chain tip is in
+        #[arg(long)]
+        epoch: Option<u32>,
+    },
+    /// Print the version. With --formats also the template and state versions this build reads and
+    /// writes and the reference pool it derives, to check before starting a pool together
+    Version {
+        #[arg(long)]
+        formats: bool,
+        /// `version --formats --json` of another build, fails unless both derive the same pools
+        #[arg(long, requires = "formats")]
+        compare: Option<PathBuf>,
+        /// Print json instead of a list
+        #[arg(long, requires = "formats")]
+        json: bool,
+    },
+    /// Print the completion script for a shell, e.g. `completions bash > /etc/bash_completion.d/op_ctv_payment_pool`
+    Completions {
+        #[arg(value_enum)]
+        shell: Shell,
+    },
+}
+
+// the completion script of the whole cli for `shell`
+pub fn write_completions(shell: Shell, out: &mut dyn Write) {
+    let mut command = Cli::command();
+    let name = command.get_name().to_string();
+    clap_complete::generate(shell, &mut command, name, out);
+}
+
+#[derive(Subcommand, Debug)]
+pub enum StateCommand {
+    /// Rewrite the manifest (json or an older binary version) in the current binary version
+    Upgrade {
+        /// Defaults to the manifest path with the .ctvpool extension
+        #[arg(long)]
+        output: Option<PathBuf>,
+    },
+    /// Print the manifest as json, whatever format it is stored in
+    ExportJson {
+        /// Write to this file instead of stdout
+        #[arg(long)]
+        output: Option<PathBuf>,
+        /// Canonical json (RFC 8785: sorted keys, no whitespace), the bytes `manifest hash` hashes
+        #[arg(long)]
+        canonical: bool,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ManifestCommand {
+    /// Sha256 of the manifest's canonical json (RFC 8785), the same for any implementation and
+    /// whichever format the manifest is stored in
+    Hash {
+        /// Print json instead of the hash
+        #[arg(long)]
+        json: bool,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum KeyCloseCommand {
+    /// Write the close for the members to sign: `pool-member key-close nonce`, then once every
+    /// nonce is in `pool-member key-close sign`
+    Start {
+        /// Fee rate of the close, sat/vB
+        #[arg(long, default_value_t = 2)]
+        fee_rate: u64,
+        /// How long the members have to sign, seconds
+        #[arg(long, default_value_t = DEFAULT_KEY_CLOSE_TIMEOUT_SECS)]
+        timeout_secs: u64,
+        #[arg(long, default_value = DEFAULT_KEY_CLOSE_SESSION)]
+        session: PathBuf,
+    },
+    /// Aggregate the members' signatures and broadcast the close. Past the deadline with members
+    /// missing, spend the root through its leaves instead: the close-all leaf or the first exit
+    Finish {
+        #[arg(long, default_value = DEFAULT_KEY_CLOSE_SESSION)]
+        session: PathBuf,
+        /// Print the signed close instead of broadcasting it
+        #[arg(long)]
+        dry_run: bool,
+    },
+}
+
+#[cfg(feature = "elements")]
+#[derive(Subcommand, Debug)]
+pub enum ElementsCommand {
+    /// Build the pool of the elementsregtest addresses in a file (one ert1 address per line, in
+    /// tree order), write its manifest and print the address to fund with the policy asset
+    Create { addresses: PathBuf },
+    /// Write every level of the unwind as a raw Elements tx, each spending the output the level
+    /// before it leaves
+    Unwind {
+        /// The output of the funding tx paying the pool, txid:vout
+        #[arg(long)]
+        funding: OutPoint,
+        #[arg(long, default_value = "elements_unwind")]
+        output_dir: PathBuf,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum MetaCommand {
+    /// Set a label on the pool, or on a user with --user
+    Set {
+        key: String,
+        value: String,
+        /// Position of the user in the tree
+        #[arg(long)]
+        user: Option<UserIndex>,
+    },
+    /// Remove a label from the pool, or from a user with --user
+    Unset {
+        key: String,
+        #[arg(long)]
+        user: Option<UserIndex>,
+    },
+    /// Print every label
+    Show {
+        /// Print json instead of a list
+        #[arg(long)]
+        json: bool,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum P2pCommand {
+    /// Rebuild the pool from the shared manifest and print its verification code, to read out to
+    /// the other participants. With their codes, fails unless every one matches
+    Verify {
+        /// Verification code of another participant, once per participant
+        #[arg(long = "code")]
+        codes: Vec<String>,
+        /// Print json instead of a list
+        #[arg(long)]
+        json: bool,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum DebugCommand {
+    /// Rebuild the tx at a level of the planned unwind and compare it byte for byte with the one
+    /// observed, listing every field that differs (amounts, sequence, scripts, witness)
+    Diff {
+        /// Level of the planned unwind, 0 is the tx spending the funding output
+        #[arg(long)]
+        level: usize,
+        /// Txid or raw hex of the observed tx, e.g. a broadcast the node rejected. Defaults to
+        /// the checkpointed tx spending that level's node
+        tx: Option<String>,
+        /// Raw hex of the funding tx instead of asking the node for it
+        #[arg(long)]
+        funding_tx: Option<PathBuf>,
+        /// Print json instead of a list
+        #[arg(long)]
+        json: bool,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum SimulateCommand {
+    /// Replay a feerate time series against the planned unwind with its anchors and bumping, and
+    /// report when each level would confirm and what the anchor children would cost
+    Fees {
+        /// Fee scenario json: {"name", "points": [{"block", "sat_per_vb"}], "blocks"}
+        #[arg(long)]
+        scenario: PathBuf,
+        /// Bump a spend stuck for this many blocks, instead of POOL_BUMP_AFTER_BLOCKS
+        #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+        bump_after_blocks: Option<u64>,
+        /// Most one anchor child pays, instead of POOL_BUMP_MAX_FEE_SATS
+        #[arg(long)]
+        bump_max_fee_sats: Option<u64>,
+        /// Print json instead of a summary
+        #[arg(long)]
+        json: bool,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum VaultCommand {
+    /// Compute the vault for an amount and write it to --output, printing the address to fund
+    Create {
+        /// Sats the vault output holds, the unvault and to-cold txs each pay the fixed fee from it
+        #[arg(long)]
+        amount_sats: u64,
+        /// X-only key that can take the coins once an unvault has waited --delay blocks
+        #[arg(long)]
+        hot_key: XOnlyPublicKey,
+        /// Where the to-cold tx sends the coins, no key needed
+        #[arg(long)]
+        cold_address: Address<NetworkUnchecked>,
+        /// Blocks an unvault waits before the hot key can spend it
+        #[arg(long, default_value_t = DEFAULT_UNVAULT_DELAY)]
+        delay: u16,
+        #[arg(long, default_value = DEFAULT_VAULT_FILE)]
+        output: PathBuf,
+        /// Print json instead of a list
+        #[arg(long)]
+        json: bool,
+    },
+    /// Print the raw unvault tx spending the funded vault output
+    Unvault {
+        #[arg(long, default_value = DEFAULT_VAULT_FILE)]
+        vault: PathBuf,
+        /// The vault output, txid:vout
+        #[arg(long)]
+        utxo: OutPoint,
+    },
+    /// Print the raw to-cold tx sweeping an unvault output to the cold address
+    ToCold {
+        #[arg(long, default_value = DEFAULT_VAULT_FILE)]
+        vault: PathBuf,
+        /// The unvault output, txid:vout
+        #[arg(long)]
+        utxo: OutPoint,
+    },
+    /// Print the raw tx spending an unvault output with the hot key from VAULT_HOT_KEY, valid once
+    /// the unvault has --delay confirmations
+    Withdraw {
+        #[arg(long, default_value = DEFAULT_VAULT_FILE)]
+        vault: PathBuf,
+        /// The unvault output, txid:vout
+        #[arg(long)]
+        utxo: OutPoint,
+        #[arg(long)]
+        to: Address<NetworkUnchecked>,
+        /// Fee of the withdrawal in sats
+        #[arg(long, default_value_t = 1000)]
+        fee_sats: u64,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum DemoCommand {
+    /// Create a pool with the wallet playing every user, fund it from the wallet and unwind every
+    /// user, waiting for each tx to confirm. A run that stopped half way picks up where the
+    /// manifest says it was
+    Run {
+        /// Start a new pool even if the manifest has an unfinished one
+        #[arg(long)]
+        fresh: bool,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ResearchCommand {
+    /// Csv of tree size, vbytes of a full unwind and a user's worst case per pool size and shape.
+    /// Uses the network's backend, layout, anchors and recovery path, any POOL_USERS
+    Sweep {
+        /// Pool sizes, e.g. 3..512
+        #[arg(long, default_value = "3..32")]
+        users: UsersRange,
+        /// Only every nth pool size
+        #[arg(long, default_value_t = 1)]
+        step: usize,
+        /// Tree shapes, comma separated
+        #[arg(
+            long,
+            value_enum,
+            value_delimiter = ',',
+            default_value = "linear,binary"
+        )]
+        shape: Vec<Shape>,
+        /// Fee rate for the worst case user fee, sat/vB
+        #[arg(long, default_value_t = DEFAULT_FEE_RATE / 1000)]
+        fee_rate: u64,
+        /// Write the csv to this file instead of stdout
+        #[arg(long)]
+        output: Option<PathBuf>,
+    },
+}
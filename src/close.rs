@@ -0,0 +1,198 @@
+use std::{
+    collections::BTreeSet,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, bail, Result};
+use bitcoin::Amount;
+use serde::Serialize;
+use tracing::{info, warn};
+
+use crate::{
+    ids::{NodePath, UserIndex},
+    lifecycle::{Event, Lifecycle},
+    manifest::PoolManifest,
+    report::{ledger_csv, pool_ledger, LedgerEntry},
+};
+
+pub const DEFAULT_ARCHIVE_DIR: &str = "archive";
+// the final report, next to the manifest in the pool's archive directory
+pub const ARCHIVE_REPORT: &str = "pool_ledger.csv";
+
+// Where the pool's deposit ended up, from its ledger
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Settlement {
+    pub deposited: Amount,
+    pub paid_out: Amount,
+    pub swept: Amount,
+    pub anchors: Amount,
+    pub pool_fees: Amount,
+    pub users_paid: BTreeSet<UserIndex>,
+    // how many users the pool was funded for, fewer than POOL_USERS after a removal
+    pub users: usize,
+    // Recovered when a sweep took what was left, Closed otherwise
+    pub lifecycle: Lifecycle,
+}
+
+// What `close` did
+#[derive(Debug, Clone, Serialize)]
+pub struct ClosedPool {
+    pub root_address: String,
+    #[serde(flatten)]
+    pub settlement: Settlement,
+    pub archive: PathBuf,
+    pub report: Option<PathBuf>,
+}
+
+// Check the pool is over: no node left unspent or spent by something other than the pool's own
+// txs, every user paid unless a recovery sweep took the rest, and what went in equal to what
+// came out plus the fees. `members` is the node the pool was funded at
+pub fn settle(entries: &[LedgerEntry], members: &NodePath) -> Result<Settlement> {
+    let total = |kind: &str| -> Amount {
+        entries
+            .iter()
+            .filter(|entry| entry.kind == kind)
+            .map(|entry| entry.amount)
+            .sum()
+    };
+    let in_pool: Vec<&LedgerEntry> = entries
+        .iter()
+        .filter(|entry| entry.kind == "in_pool")
+        .collect();
+    if let Some(node) = in_pool.first() {
+        bail!(
+            "{} still in the pool at {}:{}, the pool isn't over yet",
+            total("in_pool"),
+            node.txid,
+            node.vout.unwrap_or_default()
+        );
+    }
+    if let Some(unexpected) = entries
+        .iter()
+        .find(|entry| entry.kind == "unexpected_spend")
+    {
+        bail!(
+            "a pool node was spent by {}, which is none of the pool's txs",
+            unexpected.txid
+        );
+    }
+
+    let settlement = Settlement {
+        deposited: total("pool_deposit"),
+        paid_out: total("payout"),
+        swept: total("recovery_sweep"),
+        anchors: total("anchor"),
+        pool_fees: total("pool_fee"),
+        users_paid: entries
+            .iter()
+            .filter(|entry| entry.kind == "payout")
+            .filter_map(|entry| entry.user)
+            .collect(),
+        users: members.len(),
+        lifecycle: if entries.iter().any(|entry| entry.kind == "recovery_sweep") {
+            Lifecycle::Recovered
+        } else {
+            Lifecycle::Closed
+        },
+    };
+    let out = settlement.paid_out + settlement.swept + settlement.anchors + settlement.pool_fees;
+    if out != settlement.deposited {
+        bail!(
+            "{} deposited but {} paid out, swept, to anchors and in fees",
+            settlement.deposited,
+            out
+        );
+    }
+    if settlement.lifecycle == Lifecycle::Closed {
+        if let Some(unpaid) = members
+            .user_indices()
+            .find(|user| !settlement.users_paid.contains(user))
+        {
+            bail!("user {} was never paid and nothing was swept", unpaid);
+        }
+    }
+    Ok(settlement)
+}
+
+// Check a finished pool against the chain, record it as closed and archived and move its manifest
+// to `archive_dir`/<root address>/, with the final ledger when `report` is set. Nothing reads the
+// manifest at its old path afterwards, so no command can run against the pool by accident.
+pub async fn close_pool(
+    manifest_path: &Path,
+    archive_dir: &Path,
+    report: bool,
+) -> Result<ClosedPool> {
+    let mut manifest = PoolManifest::load(manifest_path)?;
+    if manifest.lifecycle == Lifecycle::Archived {
+        bail!("the pool is archived already");
+    }
+    let ledger = pool_ledger(&manifest).await?;
+    let settlement = settle(&ledger, &manifest.pool_root()?)?;
+
+    // the users can leave without the coordinator, the chain knows better than the manifest
+    if manifest.lifecycle != settlement.lifecycle {
+        warn!(
+            "the manifest says the pool is {}, the chain says {}",
+            manifest.lifecycle, settlement.lifecycle
+        );
+        manifest.lifecycle = settlement.lifecycle;
+    }
+    manifest.advance(Event::Archive)?;
+
+    let archive = archive_dir.join(&manifest.root_address);
+    if archive.exists() {
+        bail!("{} exists already", archive.display());
+    }
+    fs::create_dir_all(&archive)?;
+    let file_name = manifest_path
+        .file_name()
+        .ok_or_else(|| anyhow!("{} is not a file", manifest_path.display()))?;
+    manifest.write(&archive.join(file_name))?;
+    let report = if report {
+        let path = archive.join(ARCHIVE_REPORT);
+        fs::write(&path, ledger_csv(&ledger, &manifest.metadata))?;
+        info!("final pool ledger written to {} \n", path.display());
+        Some(path)
+    } else {
+        None
+    };
+    fs::remove_file(manifest_path)?;
+    info!(
+        "pool {} archived to {} \n",
+        manifest.root_address,
+        archive.display()
+    );
+
+    Ok(ClosedPool {
+        root_address: manifest.root_address,
+        settlement,
+        archive,
+        report,
+    })
+}
+
+pub fn print_closed_pool(closed: &ClosedPool) {
+    let settlement = &closed.settlement;
+    println!("pool {} is {}", closed.root_address, settlement.lifecycle);
+    let rows = [
+        ("deposited into the pool", settlement.deposited),
+        ("paid out to users", settlement.paid_out),
+        ("swept to recovery", settlement.swept),
+        ("anchors", settlement.anchors),
+        ("pool tx fees", settlement.pool_fees),
+    ];
+    for (label, amount) in rows {
+        println!("{:>28}: {:>12} sats", label, amount.to_sat());
+    }
+    println!(
+        "{:>28}: {} of {}",
+        "users paid",
+        settlement.users_paid.len(),
+        settlement.users
+    );
+    println!("archived to {}", closed.archive.display());
+    if let Some(report) = &closed.report {
+        println!("final ledger in {}", report.display());
+    }
+}
@@ -0,0 +1,235 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, bail, Result};
+use bitcoin::{
+    consensus::encode::deserialize_hex,
+    hex::DisplayHex,
+    key::Secp256k1,
+    psbt::{self, raw::ProprietaryKey},
+    Psbt, ScriptBuf, Transaction, Txid, Witness, XOnlyPublicKey,
+};
+use serde::Serialize;
+use tracing::info;
+
+use crate::{
+    broadcast::{broadcast, BroadcastKind},
+    covenant::TemplateSpend,
+    ctv_scripts::{ctv_hash, ctv_script},
+    error_codes::{coded, ErrorCode},
+    fund::read_psbt_version,
+    ids::{NodePath, UserIndex},
+    manifest::LoadedPool,
+    pools::{close_all_template, pool_spend_template},
+    psbt_v2::{encode_psbt, PsbtVersion},
+    rpc_helper::AsyncRpc,
+};
+
+pub const DEFAULT_PSBT_DIR: &str = "psbts";
+// proprietary input field (BIP-174) carrying the template hash the leaf locks the spend to
+pub const PSBT_PREFIX: &[u8] = b"ctvpool";
+pub const PSBT_TEMPLATE_HASH: u8 = 0x00;
+
+fn template_hash_key() -> ProprietaryKey {
+    ProprietaryKey {
+        prefix: PSBT_PREFIX.to_vec(),
+        subtype: PSBT_TEMPLATE_HASH,
+        key: Vec::new(),
+    }
+}
+
+// One pool spend written out for a cold setup
+#[derive(Debug, Clone, Serialize)]
+pub struct ColdSpend {
+    pub users: NodePath,
+    // None for the close-all spend
+    pub spender: Option<UserIndex>,
+    pub txid: Txid,
+    pub file: PathBuf,
+    pub version: PsbtVersion,
+}
+
+// The unsigned template as a PSBT: the node output it spends, its internal key and merkle root,
+// the leaf with its control block (PSBT_IN_TAP_LEAF_SCRIPT) and the template hash. That is
+// everything a finalizer needs, no manifest and no node.
+pub fn template_psbt(spend: &TemplateSpend, leaf_script: ScriptBuf) -> Result<Psbt> {
+    let control_block = spend
+        .spend_info
+        .control_block(&(leaf_script.clone(), spend.leaf_version))
+        .ok_or_else(|| anyhow!("the leaf of this template isn't in the node's tap tree"))?;
+    let mut psbt = Psbt::from_unsigned_tx(spend.tx.clone())?;
+    let input = &mut psbt.inputs[0];
+    input.witness_utxo = Some(spend.prevout.clone());
+    input.tap_internal_key = Some(spend.spend_info.internal_key());
+    input.tap_merkle_root = spend.spend_info.merkle_root();
+    input
+        .tap_scripts
+        .insert(control_block, (leaf_script, spend.leaf_version));
+    input
+        .proprietary
+        .insert(template_hash_key(), spend.template_hash.to_vec());
+    Ok(psbt)
+}
+
+// A PSBT for every spend of the planned unwind (user 0 leaves first, each spend building on the
+// txid of the one before) and the close-all spend if the pool has one, written to `dir` as base64
+// in `version`
+pub fn export_psbts(
+    pool: &LoadedPool,
+    funding_tx: &Transaction,
+    dir: &Path,
+    version: PsbtVersion,
+) -> Result<Vec<ColdSpend>> {
+    if pool.backend.requires_presigning() {
+        bail!(
+            "presigned leaves need the coordinator's signatures, only ctv pools can be spent cold"
+        );
+    }
+    fs::create_dir_all(dir)?;
+    let mut spends = Vec::new();
+    let mut write = |spend: &TemplateSpend, name: String, spender: Option<UserIndex>, users| {
+        let psbt = template_psbt(spend, pool.backend.leaf_script(spend.template_hash))?;
+        let file = dir.join(name);
+        fs::write(&file, encode_psbt(&psbt, version)?)?;
+        info!("{} written \n", file.display());
+        spends.push(ColdSpend {
+            users,
+            spender,
+            txid: spend.tx.compute_txid(),
+            file,
+            version,
+        });
+        Ok::<_, anyhow::Error>(())
+    };
+
+    let mut previous_tx = funding_tx.clone();
+    for user in pool.config.spenders()? {
+        let spend = pool_spend_template(
+            &pool.tree,
+            &pool.config,
+            pool.backend.as_ref(),
+            user,
+            &pool.addresses,
+            &previous_tx,
+            &pool.anchor_addr,
+        )?;
+        write(
+            &spend,
+            format!("unwind_user_{}.psbt", user),
+            Some(user),
+            pool.config.unwind_node(user)?,
+        )?;
+        previous_tx = spend.tx;
+    }
+    if pool.tree.root()?.close_all.is_some() && pool.config.pool_root()?.is_root() {
+        let spend = close_all_template(
+            &pool.tree,
+            &pool.config,
+            pool.backend.as_ref(),
+            &pool.addresses,
+            funding_tx,
+            &pool.anchor_addr,
+        )?;
+        write(&spend, "close_all.psbt".to_string(), None, NodePath::root())?;
+    }
+    Ok(spends)
+}
+
+// The finalizer (BIP-174) for a pool spend, what an offline machine runs. Each input has to be a
+// bare OP_CTV leaf whose control block commits to the output it spends, and the unsigned tx has
+// to hash to the template in the leaf: the covenant would refuse anything else. No signature is
+// needed, the witness is the leaf script and its control block.
+pub fn finalize_pool_psbt(psbt: &mut Psbt) -> Result<()> {
+    let secp = Secp256k1::verification_only();
+    for (i, input) in psbt.inputs.iter_mut().enumerate() {
+        if input.final_script_witness.is_some() {
+            continue;
+        }
+        let (control_block, (leaf_script, _)) = match input.tap_scripts.len() {
+            1 => input.tap_scripts.iter().next().expect("one tap leaf"),
+            0 => bail!("input {} has no tap leaf script", i),
+            _ => bail!("input {} has more than one tap leaf script", i),
+        };
+        let template_hash: [u8; 32] = input
+            .proprietary
+            .get(&template_hash_key())
+            .and_then(|hash| hash.as_slice().try_into().ok())
+            .ok_or_else(|| anyhow!("input {} has no template hash", i))?;
+        if *leaf_script != ctv_script(template_hash) {
+            return Err(coded(
+                ErrorCode::TemplateMismatch,
+                format!(
+                    "the leaf of input {} isn't `<template hash> OP_CTV` for {}",
+                    i,
+                    template_hash.to_lower_hex_string()
+                ),
+            ));
+        }
+        let spent = input
+            .witness_utxo
+            .as_ref()
+            .ok_or_else(|| anyhow!("input {} has no witness utxo", i))?;
+        if !spent.script_pubkey.is_p2tr() {
+            bail!("input {} doesn't spend a taproot output", i);
+        }
+        let output_key = XOnlyPublicKey::from_slice(&spent.script_pubkey.as_bytes()[2..])?;
+        if !control_block.verify_taproot_commitment(&secp, output_key, leaf_script) {
+            bail!(
+                "the control block of input {} doesn't commit to the output it spends",
+                i
+            );
+        }
+        let tx_hash = ctv_hash(&psbt.unsigned_tx, i as u32);
+        if tx_hash != template_hash {
+            bail!(
+                "the tx of input {} hashes to {}, the leaf only allows {}",
+                i,
+                tx_hash.to_lower_hex_string(),
+                template_hash.to_lower_hex_string()
+            );
+        }
+
+        let mut witness = Witness::new();
+        witness.push(leaf_script.as_bytes());
+        witness.push(control_block.serialize());
+        *input = psbt::Input {
+            witness_utxo: input.witness_utxo.take(),
+            final_script_witness: Some(witness),
+            proprietary: std::mem::take(&mut input.proprietary),
+            unknown: std::mem::take(&mut input.unknown),
+            ..Default::default()
+        };
+    }
+    Ok(())
+}
+
+// written back in the version it was read in
+pub fn finalize_psbt_file(path: &Path, output: Option<&Path>) -> Result<Txid> {
+    let (mut psbt, version) = read_psbt_version(path)?;
+    finalize_pool_psbt(&mut psbt)?;
+    let txid = psbt.unsigned_tx.compute_txid();
+    let output = output.unwrap_or(path);
+    fs::write(output, encode_psbt(&psbt, version)?)?;
+    info!("{} finalized to {} \n", txid, output.display());
+    Ok(txid)
+}
+
+// The online role: a finalized PSBT's tx goes to the node as it is, nothing is added to it
+pub async fn broadcast_psbt(rpc: &AsyncRpc, psbt: Psbt) -> Result<Txid> {
+    if let Some(i) = psbt
+        .inputs
+        .iter()
+        .position(|input| input.final_script_witness.is_none() && input.final_script_sig.is_none())
+    {
+        bail!("input {} isn't finalized, run finalize-psbt first", i);
+    }
+    let tx = psbt.extract_tx()?;
+    broadcast(rpc, &tx, BroadcastKind::PoolSpend).await
+}
+
+// raw hex of a funding tx, for an export that can't ask the node for it
+pub fn read_raw_tx(path: &Path) -> Result<Transaction> {
+    Ok(deserialize_hex(fs::read_to_string(path)?.trim())?)
+}
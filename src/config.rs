@@ -1,8 +1,34 @@
-use bitcoin::{Amount, Network};
-use bitcoincore_rpc::{Auth, Client, Error, RpcApi};
-use std::{env, path::PathBuf};
+use anyhow::bail;
+use bitcoin::{
+    taproot::LeafVersion, Address, Amount, Network, ScriptBuf, Sequence, TxOut, WitnessProgram,
+    WitnessVersion,
+};
+use bitcoincore_rpc::{jsonrpc::serde_json, Auth, Client, Error, RpcApi};
+use std::{env, fmt, path::PathBuf, str::FromStr, sync::Arc, time::Duration};
 use tracing::{error, info};
 
+use crate::{
+    addresses::{parse_witness_versions, set_allowed_witness_versions},
+    ctv_scripts::{parse_leaf_version, TemplateVersion, TreeLayout},
+    faucet::Faucet,
+    fee_bump::FeeBumpPolicy,
+    fee_policy::{parse_fee_policy, FeePolicy, TemplateInfo},
+    fetch::RpcFetchLimits,
+    ids::{NodePath, UserIndex},
+    mempool::MempoolLimits,
+    musig::MusigKeys,
+    payouts::{draw_payout_jitter, user_share, PayoutJitter, PayoutSplits},
+    profile::NetworkProfile,
+    progress::ProgressMode,
+    proxy::{check_endpoint, parse_proxy, set_proxy},
+    recovery::RecoveryPath,
+    redact::set_log_sensitive,
+    removal::{pool_members, Removal},
+    rpc_helper::AsyncRpc,
+    user_anchor::{AnchorKeys, KEYED_ANCHOR_DUST},
+    webhooks::Webhook,
+};
+
 // https://bitcoinops.org/en/bitcoin-core-28-wallet-integration-guide/
 // mainnet: bc1pfeessrawgf
 // regtest: bcrt1pfeesnyr2tx
@@ -13,6 +39,9 @@ pub const FEE_AMOUNT: Amount = Amount::from_sat(5000);
 pub const DUST_AMOUNT: Amount = Amount::from_sat(546);
 pub const DEFAULT_FEE_RATE: u64 = 5000;
 
+// warn when the fee payer wallet gets below this, it pays every anchor child
+pub const FEE_WALLET_LOW_BALANCE: Amount = Amount::from_sat(100_000);
+
 //send a bit more so we can cover the fees for the pool funding transaction
 pub const INIT_WALLET_AMOUNT_FEE: Amount = Amount::from_sat(2000);
 
@@ -22,74 +51,668 @@ pub const POOL_USERS: usize = 10;
 //has to be more than FEE_AMOUNT + DUST_AMOUNT
 pub const AMOUNT_PER_USER: Amount = Amount::from_sat(11000);
 
-#[cfg(feature = "signet")]
-pub const TX_VERSION: i32 = 2;
+// bip325 challenge of the default signet, bitcoin inquisition runs on this one too
+pub const DEFAULT_SIGNET_CHALLENGE: &str = "512103ad5e0edad18cb1f0fc0d28a3d4f1f3e445640337489abb10404f2d1e086be430210359ef5021964fe22d6f8e05b2463c9540ce96883fe3b278760f048f5189f2e6c452ae";
+
+// the kinds of tx the pool waits on before building the next step on them
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfirmationStage {
+    // the tx paying into the entry pool
+    Funding,
+    // a withdrawal that leaves a smaller pool behind
+    PoolSpend,
+    // the last withdrawal, paying out the final two users
+    Payout,
+}
+
+impl ConfirmationStage {
+    // the stage of the withdrawal made by `spender` when the pool's members leave in order, the
+    // last one's exit pays the last member too
+    pub fn of_spend(spender: UserIndex, config: &NetworkConfig) -> anyhow::Result<Self> {
+        Ok(if config.spenders()?.last() == Some(&spender) {
+            Self::Payout
+        } else {
+            Self::PoolSpend
+        })
+    }
+}
+
+// blocks deep each kind of tx has to be before the pool moves on, e.g. a deeper funding tx than
+// the withdrawals that follow it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConfirmationTargets {
+    pub funding: u32,
+    pub pool_spend: u32,
+    pub payout: u32,
+}
 
-#[cfg(feature = "regtest")]
-pub const TX_VERSION: i32 = 3;
+impl ConfirmationTargets {
+    pub const fn uniform(blocks: u32) -> Self {
+        Self {
+            funding: blocks,
+            pool_spend: blocks,
+            payout: blocks,
+        }
+    }
 
-#[cfg(feature = "testnet4")]
-pub const TX_VERSION: i32 = 2;
+    // never less than one, the next tx can't build on an unconfirmed one
+    pub fn get(&self, stage: ConfirmationStage) -> u32 {
+        let blocks = match stage {
+            ConfirmationStage::Funding => self.funding,
+            ConfirmationStage::PoolSpend => self.pool_spend,
+            ConfirmationStage::Payout => self.payout,
+        };
+        blocks.max(1)
+    }
+}
 
 #[allow(dead_code)]
 #[derive(Debug, Clone)]
 pub struct NetworkConfig {
+    pub profile: NetworkProfile,
     pub network: Network,
-    pub port: &'static str,
-    pub fee_anchor_addr: &'static str,
+    pub rpc_url: String,
+    pub fee_anchor_addr: String,
     pub wallet_name: String,
+    // separate wallet that only pays for the anchor (CPFP) children, FEE_WALLET env var
+    pub fee_wallet_name: Option<String>,
+    pub tx_version: i32,
+    // value of the p2a output added to every pool tx, None means the fee is taken from the withdrawal instead
+    pub anchor_amount: Option<Amount>,
+    // blocks estimatesmartfee aims for
+    pub conf_target: u16,
+    pub signet_challenge: Option<String>,
+    // refuse to run the ctv backend if the node does not enforce OP_CTV
+    pub require_ctv: bool,
+    // mine blocks in the background at this interval (regtest only)
+    pub block_interval: Option<Duration>,
+    // timeout leaf sweeping stale pool nodes, RECOVERY_ADDRESS env var
+    pub recovery: Option<RecoveryPath>,
+    // relative timelock (blocks) on every unwind spend, UNWIND_DELAY_BLOCKS env var
+    pub unwind_delay: Option<u16>,
+    // cooldown of each level from the root down, overriding unwind_delay where set (0 for none),
+    // UNWIND_LEVEL_DELAYS env var
+    pub level_delays: Vec<u16>,
+    // how building the tree reports progress, POOL_PROGRESS env var
+    pub progress: ProgressMode,
+    // arrangement of the leaves in every node's tap tree, POOL_TREE_LAYOUT env var
+    pub tree_layout: TreeLayout,
+    // version of every pool leaf, POOL_LEAF_VERSION env var. Only TapScript (0xc0) is enforced today,
+    // other versions are for trying out future tapscript versions on inquisition
+    pub leaf_version: LeafVersion,
+    // tree shape and fee model, always the latest for new pools, the manifest's for existing ones
+    pub template_version: TemplateVersion,
+    // pool lifecycle events are posted here by watch, WEBHOOK_URL env var
+    pub webhook: Option<Webhook>,
+    // signet faucet topping up the wallet before the demo funds the pool, POOL_FAUCET_URL env var
+    pub faucet: Option<Faucet>,
+    // esplora api used to track confirmations where no miner runs, POOL_ESPLORA_URL env var
+    pub esplora_url: Option<String>,
+    // SOCKS5 proxy for esplora, the faucet, webhooks and release uploads, e.g. socks5h://127.0.0.1:9050
+    // for tor, POOL_PROXY env var. See proxy.rs
+    pub proxy: Option<String>,
+    // blocks deep each kind of pool tx has to be before the next one builds on it,
+    // POOL_CONFIRMATIONS and POOL_{FUNDING,SPEND,PAYOUT}_CONFIRMATIONS env vars
+    pub confirmations: ConfirmationTargets,
+    // nLockTime of the funding tx at the chain tip (anti fee sniping), POOL_ANTI_FEE_SNIPING env var.
+    // The pool's own txs are templates and always have locktime 0
+    pub anti_fee_sniping: bool,
+    // extra leaf on the root paying every user in one tx, POOL_CLOSE_ALL_LEAF env var
+    pub close_all_leaf: bool,
+    // users paid to several of their addresses, from their registrations, see payouts.rs
+    pub splits: PayoutSplits,
+    // users who bump their own exit through an anchor paying their key, from their registrations,
+    // see user_anchor.rs
+    pub anchor_keys: AnchorKeys,
+    // the users' keys for a key path close of the pool root, from their registrations. See musig.rs
+    pub musig_keys: MusigKeys,
+    // witness versions no soft fork has defined that withdraw and split addresses may still use,
+    // POOL_ALLOWED_WITNESS_VERSIONS env var. See addresses.rs
+    pub allowed_witness_versions: Vec<WitnessVersion>,
+    // longest unconfirmed chain the node accepts, POOL_MEMPOOL_ANCESTORS / POOL_MEMPOOL_DESCENDANTS
+    // env vars (bitcoind's -limitancestorcount / -limitdescendantcount), see mempool.rs
+    pub mempool_limits: MempoolLimits,
+    // lowest fee rate (sat/kvB) a new pool may commit a template to, POOL_MIN_TEMPLATE_FEE_RATE env var
+    // in sat/vB, see template_fees.rs
+    pub min_template_fee_rate: Option<u64>,
+    // what each user's share is moved by so the payouts differ, drawn for a new pool when
+    // POOL_PAYOUT_JITTER_SATS is set, the manifest's for an existing one. See payouts.rs
+    pub payout_jitter: PayoutJitter,
+    // participants taken out before funding, the manifest's for an existing pool. See removal.rs
+    pub removed: Vec<Removal>,
+    // anchor children for pool spends stuck in the mempool, POOL_BUMP_AFTER_BLOCKS env var, see fee_bump.rs
+    pub fee_bump: Option<FeeBumpPolicy>,
+    // raw tx hex, keys, addresses and wallet coins in full in the info logs instead of shortened,
+    // POOL_LOG_SENSITIVE env var. They are always in full at trace level, see redact.rs
+    pub log_sensitive: bool,
+    // lookups sent to the node at once by report and watch, POOL_RPC_CONNECTIONS / POOL_RPC_BATCH
+    // env vars, see fetch.rs
+    pub rpc_fetch: RpcFetchLimits,
+    // which pool's funding holds which wallet coin, kept on disk across node restarts,
+    // POOL_RESERVATIONS_FILE env var. The node's lockunspent is used either way, see reservations.rs
+    pub coin_reservations: Option<PathBuf>,
+    // fee each template commits to, FixedFee(FEE_AMOUNT) unless POOL_FEE_POLICY says otherwise, the
+    // manifest's for an existing pool. See fee_policy.rs
+    pub fee_policy: Arc<dyn FeePolicy>,
+    // bytes the tree may take in memory while it is built, past that the levels below the one being
+    // built go to a temp file, POOL_TREE_MEMORY_MB env var. See spill.rs
+    pub tree_memory_budget: Option<u64>,
+    // env vars set to something unusable, the preset's value is kept for each. validate() reports them
+    pub env_problems: Vec<ConfigProblem>,
+}
+
+// One invariant a config breaks, see NetworkConfig::validate
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConfigProblem {
+    // an env var that doesn't parse or contradicts another one
+    InvalidEnv {
+        var: String,
+        reason: String,
+    },
+    TooFewUsers {
+        users: usize,
+    },
+    AmountBelowFee {
+        amount: Amount,
+        fee: Amount,
+        dust: Amount,
+    },
+    AnchorBelowDust {
+        anchor: Amount,
+        dust: Amount,
+    },
+    // zero fee templates and nothing to pay for them with
+    NoFee {
+        policy: String,
+    },
+    FeeBelowAnchor {
+        policy: String,
+        fee: Amount,
+        anchor: Amount,
+    },
+    // what the user is paid for leaving the root
+    ExitBelowDust {
+        user: UserIndex,
+        share: Amount,
+        fee: Amount,
+        dust: Amount,
+    },
+}
+
+impl fmt::Display for ConfigProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidEnv { var, reason } => write!(f, "{} {}", var, reason),
+            Self::TooFewUsers { users } => {
+                write!(f, "the pool has {} users, it needs at least 3 (POOL_USERS)", users)
+            }
+            Self::AmountBelowFee { amount, fee, dust } => write!(
+                f,
+                "AMOUNT_PER_USER {} has to be more than FEE_AMOUNT {} + DUST_AMOUNT {}",
+                amount, fee, dust
+            ),
+            Self::AnchorBelowDust { anchor, dust } => write!(
+                f,
+                "the {} anchor is dust, it has to be at least {} (POOL_ANCHOR_AMOUNT_SATS)",
+                anchor, dust
+            ),
+            Self::NoFee { policy } => write!(
+                f,
+                "fee policy {} commits no fee and there is no anchor to pay for the templates with (POOL_FEE_POLICY, POOL_ANCHOR_AMOUNT_SATS)",
+                policy
+            ),
+            Self::FeeBelowAnchor {
+                policy,
+                fee,
+                anchor,
+            } => write!(
+                f,
+                "fee policy {} commits {}, less than the {} anchor it has to fund",
+                policy, fee, anchor
+            ),
+            Self::ExitBelowDust {
+                user,
+                share,
+                fee,
+                dust,
+            } => write!(
+                f,
+                "user {} has {} in the pool, less the {} fee of leaving the root that is below {} dust",
+                user, share, fee, dust
+            ),
+        }
+    }
+}
+
+impl ConfigProblem {
+    pub fn invalid_value(var: &str, value: &str) -> Self {
+        Self::InvalidEnv {
+            var: var.to_string(),
+            reason: format!("has an invalid value: {}", value),
+        }
+    }
+}
+
+// Every problem of a config, downcast an anyhow error to this to go through them one by one
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigErrors(pub Vec<ConfigProblem>);
+
+impl fmt::Display for ConfigErrors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} config problems", self.0.len())?;
+        for problem in &self.0 {
+            write!(f, "\n  {}", problem)?;
+        }
+        Ok(())
+    }
 }
 
+impl std::error::Error for ConfigErrors {}
+
 impl NetworkConfig {
-    #[allow(clippy::needless_return)]
-    pub fn new() -> Self {
-        #[cfg(feature = "regtest")]
+    // the profile's preset with the POOL_* overrides and optional pool features from the env
+    pub fn new(profile: NetworkProfile) -> Self {
+        let mut config = profile.preset();
+        let mut problems = Vec::new();
+
+        if let Some(wallet_name) = Self::env_override("POOL_WALLET")
+            .or_else(|| profile.wallet_env_var().and_then(Self::env_override))
         {
-            return Self {
-                network: Network::Regtest,
-                port: "18443",
-                fee_anchor_addr: "bcrt1pfeesnyr2tx",
-                wallet_name: "simple_ctv".to_string(),
-            };
+            config.wallet_name = wallet_name;
+        }
+        if let Some(rpc_url) = Self::env_override("POOL_RPC_URL") {
+            config.rpc_url = rpc_url;
+        }
+        if let Some(fee_anchor_addr) = Self::env_override("POOL_FEE_ANCHOR_ADDR") {
+            config.fee_anchor_addr = fee_anchor_addr;
+        }
+        if let Some(tx_version) = Self::parse_env("POOL_TX_VERSION", &mut problems) {
+            config.tx_version = tx_version;
+        }
+        // 0 turns the anchors off, the fee is taken from the withdrawal instead
+        if let Some(sats) = Self::parse_env("POOL_ANCHOR_AMOUNT_SATS", &mut problems) {
+            config.anchor_amount = (sats > 0).then(|| Amount::from_sat(sats));
+        }
+        if let Some(conf_target) = Self::parse_env("POOL_CONF_TARGET", &mut problems) {
+            config.conf_target = conf_target;
+        }
+        if let Some(challenge) = Self::env_override("POOL_SIGNET_CHALLENGE") {
+            config.signet_challenge = Some(challenge);
+        }
+        if let Some(require_ctv) = Self::parse_env("POOL_REQUIRE_CTV", &mut problems) {
+            config.require_ctv = require_ctv;
+        }
+        // 0 stops the background miner
+        if let Some(secs) = Self::parse_env("POOL_BLOCK_INTERVAL_SECS", &mut problems) {
+            config.block_interval = (secs > 0).then(|| Duration::from_secs(secs));
+        }
+
+        config.fee_wallet_name = Self::env_override("FEE_WALLET");
+        config.recovery = RecoveryPath::from_env(config.network).unwrap_or_else(|problem| {
+            problems.push(problem);
+            None
+        });
+        config.unwind_delay = Self::parse_env("UNWIND_DELAY_BLOCKS", &mut problems);
+        // comma separated blocks, the root's spend first
+        if let Some(delays) = Self::env_override("UNWIND_LEVEL_DELAYS") {
+            match delays
+                .split(',')
+                .map(|blocks| blocks.trim().parse())
+                .collect()
+            {
+                Ok(level_delays) => config.level_delays = level_delays,
+                Err(_) => {
+                    problems.push(ConfigProblem::invalid_value("UNWIND_LEVEL_DELAYS", &delays))
+                }
+            }
+        }
+        config.webhook = Webhook::from_env().unwrap_or_else(|problem| {
+            problems.push(problem);
+            None
+        });
+        config.faucet = Faucet::from_env(config.network).unwrap_or_else(|problem| {
+            problems.push(problem);
+            None
+        });
+        config.fee_bump = FeeBumpPolicy::from_env().unwrap_or_else(|problem| {
+            problems.push(problem);
+            None
+        });
+        if let Some(log_sensitive) = Self::parse_env("POOL_LOG_SENSITIVE", &mut problems) {
+            config.log_sensitive = log_sensitive;
+        }
+        set_log_sensitive(config.log_sensitive);
+        // comma separated, e.g. 2,3
+        if let Some(versions) = Self::env_override("POOL_ALLOWED_WITNESS_VERSIONS") {
+            match parse_witness_versions(&versions) {
+                Ok(allowed) => config.allowed_witness_versions = allowed,
+                Err(e) => problems.push(ConfigProblem::InvalidEnv {
+                    var: "POOL_ALLOWED_WITNESS_VERSIONS".to_string(),
+                    reason: format!("has an invalid value: {}", e),
+                }),
+            }
+        }
+        set_allowed_witness_versions(&config.allowed_witness_versions);
+        if let Some(progress) = Self::parse_env("POOL_PROGRESS", &mut problems) {
+            config.progress = progress;
+        }
+        if let Some(tree_layout) = Self::parse_env("POOL_TREE_LAYOUT", &mut problems) {
+            config.tree_layout = tree_layout;
+        }
+        if let Some(esplora_url) = Self::env_override("POOL_ESPLORA_URL") {
+            config.esplora_url = Some(esplora_url);
+        }
+        if let Some(proxy) = Self::env_override("POOL_PROXY") {
+            match parse_proxy(&proxy) {
+                Ok(proxy) => config.proxy = Some(proxy),
+                Err(e) => problems.push(ConfigProblem::InvalidEnv {
+                    var: "POOL_PROXY".to_string(),
+                    reason: format!("has an invalid value: {}", e),
+                }),
+            }
+        }
+        set_proxy(config.proxy.as_deref());
+        // an onion endpoint without a proxy able to reach it fails now, not on the first request
+        let endpoints = [
+            ("POOL_ESPLORA_URL", config.esplora_url.as_deref()),
+            (
+                "POOL_FAUCET_URL",
+                config.faucet.as_ref().map(|faucet| faucet.url.as_str()),
+            ),
+            (
+                "WEBHOOK_URL",
+                config.webhook.as_ref().map(|webhook| webhook.url.as_str()),
+            ),
+        ];
+        for (var, url) in endpoints {
+            if let Some(Err(e)) = url.map(|url| check_endpoint(url, config.proxy.as_deref())) {
+                problems.push(ConfigProblem::InvalidEnv {
+                    var: var.to_string(),
+                    reason: e.to_string(),
+                });
+            }
+        }
+        // one for all, then per kind of tx
+        if let Some(confirmations) = Self::parse_env("POOL_CONFIRMATIONS", &mut problems) {
+            config.confirmations = ConfirmationTargets::uniform(confirmations);
+        }
+        if let Some(funding) = Self::parse_env("POOL_FUNDING_CONFIRMATIONS", &mut problems) {
+            config.confirmations.funding = funding;
+        }
+        if let Some(pool_spend) = Self::parse_env("POOL_SPEND_CONFIRMATIONS", &mut problems) {
+            config.confirmations.pool_spend = pool_spend;
+        }
+        if let Some(payout) = Self::parse_env("POOL_PAYOUT_CONFIRMATIONS", &mut problems) {
+            config.confirmations.payout = payout;
+        }
+        if let Some(anti_fee_sniping) = Self::parse_env("POOL_ANTI_FEE_SNIPING", &mut problems) {
+            config.anti_fee_sniping = anti_fee_sniping;
+        }
+        if let Some(close_all_leaf) = Self::parse_env("POOL_CLOSE_ALL_LEAF", &mut problems) {
+            config.close_all_leaf = close_all_leaf;
         }
-        #[cfg(feature = "testnet4")]
+        if let Some(ancestors) = Self::parse_env("POOL_MEMPOOL_ANCESTORS", &mut problems) {
+            config.mempool_limits.ancestors = ancestors;
+        }
+        if let Some(descendants) = Self::parse_env("POOL_MEMPOOL_DESCENDANTS", &mut problems) {
+            config.mempool_limits.descendants = descendants;
+        }
+        if let Some(connections) = Self::parse_env::<usize>("POOL_RPC_CONNECTIONS", &mut problems) {
+            config.rpc_fetch.connections = connections.max(1);
+        }
+        if let Some(batch) = Self::parse_env::<usize>("POOL_RPC_BATCH", &mut problems) {
+            config.rpc_fetch.batch = batch.max(1);
+        }
+        config.coin_reservations = Self::env_override("POOL_RESERVATIONS_FILE").map(PathBuf::from);
+        // 0 holds the whole tree in memory however big it is
+        if let Some(mb) = Self::parse_env::<u64>("POOL_TREE_MEMORY_MB", &mut problems) {
+            config.tree_memory_budget = (mb > 0).then(|| mb.saturating_mul(1024 * 1024));
+        }
+        if let Some(spec) = Self::env_override("POOL_FEE_POLICY") {
+            match parse_fee_policy(&spec) {
+                Ok(policy) => config.fee_policy = policy,
+                Err(e) => problems.push(ConfigProblem::InvalidEnv {
+                    var: "POOL_FEE_POLICY".to_string(),
+                    reason: format!("has an invalid value: {}", e),
+                }),
+            }
+        }
+        config.min_template_fee_rate =
+            Self::parse_env::<u64>("POOL_MIN_TEMPLATE_FEE_RATE", &mut problems)
+                .map(|sat_vb| sat_vb * 1000);
+        // a jitter leaving someone's exit dust is one of validate's ExitBelowDust
+        if let Some(max) = Self::parse_env::<u64>("POOL_PAYOUT_JITTER_SATS", &mut problems)
+            .filter(|sats| *sats > 0)
         {
-            let wallet_name = env::var("TESTNET4_WALLET").expect("TESTNET4_WALLET env var not set");
-            info!("wallet name: {} \n", wallet_name);
-            return Self {
-                network: Network::Testnet4,
-                port: "48332",
-                fee_anchor_addr: "tb1pfees9rn5nz",
-                wallet_name,
+            config.payout_jitter =
+                draw_payout_jitter(Amount::from_sat(max), POOL_USERS, &mut rand::thread_rng());
+        }
+        if let Some(version) = Self::env_override("POOL_LEAF_VERSION") {
+            match parse_leaf_version(&version) {
+                Ok(leaf_version) => config.leaf_version = leaf_version,
+                Err(e) => problems.push(ConfigProblem::InvalidEnv {
+                    var: "POOL_LEAF_VERSION".to_string(),
+                    reason: format!("has an invalid value: {} ({})", version, e),
+                }),
+            }
+        }
+
+        config.env_problems = problems;
+        config
+    }
+
+    fn env_override(var_name: &str) -> Option<String> {
+        env::var(var_name).ok().filter(|value| !value.is_empty())
+    }
+
+    // None when the var is unset, or set to something that doesn't parse: that is one of `problems`
+    fn parse_env<T: FromStr>(var_name: &str, problems: &mut Vec<ConfigProblem>) -> Option<T> {
+        let value = Self::env_override(var_name)?;
+        match value.parse() {
+            Ok(parsed) => Some(parsed),
+            Err(_) => {
+                problems.push(ConfigProblem::invalid_value(var_name, &value));
+                None
+            }
+        }
+    }
+
+    // Every invariant of the pool's amounts, fees and anchors this config breaks, all at once.
+    // The fees are the fee policy's for the root's templates, the biggest of the tree.
+    pub fn validate(&self) -> Result<(), ConfigErrors> {
+        let mut problems = self.env_problems.clone();
+        if POOL_USERS < 3 {
+            problems.push(ConfigProblem::TooFewUsers { users: POOL_USERS });
+        }
+        if AMOUNT_PER_USER <= FEE_AMOUNT + DUST_AMOUNT {
+            problems.push(ConfigProblem::AmountBelowFee {
+                amount: AMOUNT_PER_USER,
+                fee: FEE_AMOUNT,
+                dust: DUST_AMOUNT,
+            });
+        }
+
+        let anchor_script = Address::from_str(&self.fee_anchor_addr)
+            .ok()
+            .map(|address| address.assume_checked().script_pubkey());
+        if let (Some(anchor), Some(script)) = (self.anchor_amount, &anchor_script) {
+            let mut dust = script.minimal_non_dust();
+            if !self.anchor_keys.is_empty() {
+                dust = dust.max(KEYED_ANCHOR_DUST);
+            }
+            if anchor < dust {
+                problems.push(ConfigProblem::AnchorBelowDust { anchor, dust });
+            }
+        }
+
+        // a withdrawal from the root: the rest of the pool, the user and the anchor
+        let p2tr = ScriptBuf::new_witness_program(
+            &WitnessProgram::new(WitnessVersion::V1, &[0; 32]).expect("32 byte v1 program"),
+        );
+        let mut outputs = vec![
+            TxOut {
+                value: AMOUNT_PER_USER,
+                script_pubkey: p2tr.clone(),
             };
+            2
+        ];
+        if let Some(anchor) = self.anchor_amount {
+            outputs.push(TxOut {
+                value: anchor,
+                script_pubkey: anchor_script.clone().unwrap_or(p2tr),
+            });
+        }
+        let template = TemplateInfo::new(&NodePath::root(), false, &outputs, self);
+        let fee = self.fee_policy.fee_for(&template);
+        match self.anchor_amount {
+            None if fee == Amount::ZERO => problems.push(ConfigProblem::NoFee {
+                policy: self.fee_policy.spec(),
+            }),
+            Some(anchor) if fee < anchor => problems.push(ConfigProblem::FeeBelowAnchor {
+                policy: self.fee_policy.spec(),
+                fee,
+                anchor,
+            }),
+            _ => {}
+        }
+        for user in UserIndex::all() {
+            let share = user_share(user, self);
+            if share.checked_sub(fee).is_none_or(|exit| exit < DUST_AMOUNT) {
+                problems.push(ConfigProblem::ExitBelowDust {
+                    user,
+                    share,
+                    fee,
+                    dust: DUST_AMOUNT,
+                });
+            }
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(ConfigErrors(problems))
+        }
+    }
+
+    pub fn is_regtest(&self) -> bool {
+        self.network == Network::Regtest
+    }
+
+    // make sure the node is on the chain this profile expects, e.g. not some custom signet
+    pub async fn check_chain(&self, rpc: &AsyncRpc) -> anyhow::Result<()> {
+        let Some(expected_challenge) = &self.signet_challenge else {
+            return Ok(());
+        };
+
+        let info: serde_json::Value = rpc.run(|c| c.call("getblockchaininfo", &[])).await?;
+        let challenge = info["signet_challenge"].as_str().unwrap_or_default();
+        if challenge != expected_challenge {
+            bail!(
+                "node is not on the expected signet, challenge {} (expected {})",
+                challenge,
+                expected_challenge
+            );
+        }
+
+        Ok(())
+    }
+
+    // inquisition reports BIP-119 in getdeploymentinfo as "checktemplateverify"
+    pub async fn ctv_active(&self, rpc: &AsyncRpc) -> anyhow::Result<bool> {
+        let info: serde_json::Value = rpc.run(|c| c.call("getdeploymentinfo", &[])).await?;
+        Ok(info["deployments"]["checktemplateverify"]["active"]
+            .as_bool()
+            .unwrap_or(false))
+    }
+
+    // the node the pool is funded at, the root unless participants were removed before funding
+    pub fn pool_root(&self) -> anyhow::Result<NodePath> {
+        pool_members(&self.removed)
+    }
+
+    // the node the pool is in right before `spender` leaves, users leave in address order
+    pub fn unwind_node(&self, spender: UserIndex) -> anyhow::Result<NodePath> {
+        let root = self.pool_root()?;
+        if !root.contains(spender) {
+            bail!(
+                "user {} was removed from the pool before it was funded",
+                spender
+            );
         }
-        #[cfg(feature = "signet")]
+        NodePath::new(
+            root.users()
+                .iter()
+                .copied()
+                .filter(|&user| user >= spender.index())
+                .collect(),
+        )
+    }
+
+    // everyone who leaves on their own, in order, the last member is paid by the final exit
+    pub fn spenders(&self) -> anyhow::Result<Vec<UserIndex>> {
+        let mut spenders: Vec<UserIndex> = self.pool_root()?.user_indices().collect();
+        spenders.pop();
+        Ok(spenders)
+    }
+
+    // members of the node the pool is funded at, fewer than POOL_USERS after removals
+    fn pool_size(&self) -> usize {
+        self.pool_root().map_or(POOL_USERS, |root| root.len())
+    }
+
+    // blocks the node of `users` has to be deep before anyone spends it: its level's cooldown,
+    // unwind_delay for the levels without one. Levels count from the node the pool is funded at
+    pub fn unwind_delay_for(&self, users: &NodePath) -> Option<u16> {
+        match self
+            .level_delays
+            .get(self.pool_size().saturating_sub(users.len()))
         {
-            let wallet_name = env::var("SIGNET_WALLET").expect("SIGNET_WALLET env var not set");
-            info!("wallet name: {} \n", wallet_name);
-            return Self {
-                network: Network::Signet,
-                port: "38332",
-                fee_anchor_addr: "tb1pfees9rn5nz",
-                wallet_name,
-            };
+            Some(0) => None,
+            Some(&blocks) => Some(blocks),
+            None => self.unwind_delay,
+        }
+    }
+
+    // the longest wait of any level
+    pub fn max_unwind_delay(&self) -> Option<u16> {
+        let members = self.pool_size();
+        (2..=members)
+            .filter_map(|users| self.level_delays.get(members - users).copied())
+            .chain(self.unwind_delay)
+            .filter(|blocks| *blocks > 0)
+            .max()
+    }
+
+    // nSequence of every spend of the node of `users`, the templates commit to it
+    pub fn unwind_sequence(&self, users: &NodePath) -> Sequence {
+        match self.unwind_delay_for(users) {
+            Some(blocks) => Sequence::from_height(blocks),
+            None => Sequence::ENABLE_RBF_NO_LOCKTIME,
         }
-        //wen mainnet
     }
 
     pub fn get_env_var(var_name: &str, default_value: &str) -> String {
         std::env::var(var_name).unwrap_or_else(|_| default_value.to_string())
     }
 
-    pub fn bitcoin_rpc(&self) -> Result<Client, Error> {
+    pub fn wallet_rpc(&self, wallet_name: &str) -> Result<Client, Error> {
         let bitcoin_rpc_user = Self::get_env_var("BITCOIN_RPC_USER", "NA");
         let bitcoin_rpc_pass = Self::get_env_var("BITCOIN_RPC_PASS", "NA");
         let bitcoin_rpc_cookie_path = Self::get_env_var("BITCOIN_RPC_COOKIE_PATH", "NA");
 
-        let bitcoin_rpc_url =
-            format!("http://localhost:{}/wallet/{}", self.port, self.wallet_name,);
+        if wallet_name.is_empty() {
+            error!(
+                "No wallet name set, export POOL_WALLET (or TESTNET4_WALLET, SIGNET_WALLET, INQUISITION_WALLET)"
+            );
+            return Err(Error::ReturnedError(format!(
+                "no wallet name set for {}",
+                self.network
+            )));
+        }
+
+        let bitcoin_rpc_url = format!("{}/wallet/{}", self.rpc_url, wallet_name);
 
-        info!("wallet name in use: {} \n", self.wallet_name);
+        info!("wallet name in use: {} \n", wallet_name);
 
         //Check if user/pass or cookie is found in enviroment variables.
         //If both are found, UserPass will be tried first.
@@ -143,14 +766,15 @@ impl NetworkConfig {
             }
         };
 
-        #[cfg(feature = "regtest")]
-        let regtest_wallet = bitcoin_rpc.create_wallet(&self.wallet_name, None, None, None, None);
-        #[cfg(feature = "regtest")]
-        if regtest_wallet.is_ok() {
+        if self.is_regtest()
+            && bitcoin_rpc
+                .create_wallet(wallet_name, None, None, None, None)
+                .is_ok()
+        {
             info!("regtest wallet created \n")
         }
 
-        let _ = bitcoin_rpc.load_wallet(&self.wallet_name);
+        let _ = bitcoin_rpc.load_wallet(wallet_name);
 
         Ok(bitcoin_rpc)
     }
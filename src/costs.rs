@@ -0,0 +1,249 @@
+use bitcoin::{Amount, ScriptBuf, TxOut};
+use serde::Serialize;
+
+use crate::{
+    config::NetworkConfig, ctv_scripts::close_all_fee_share, fee_policy::TemplateInfo,
+    ids::NodePath, payouts::split_total, pools::CPFP_CHILD_VSIZE, AMOUNT_PER_USER, POOL_USERS,
+};
+
+// weight units of a single key path p2tr input / p2tr output / tx overhead (incl. segwit marker)
+const P2TR_KEY_SPEND_WU: u64 = 41 * 4 + 66;
+const P2TR_OUTPUT_WU: u64 = 43 * 4;
+const TX_OVERHEAD_WU: u64 = 10 * 4 + 2;
+
+// every node with `users` users in it, and how many templates (leaves) they commit to
+#[derive(Debug, Serialize)]
+pub struct LevelCost {
+    pub users: usize,
+    pub nodes: u64,
+    pub templates: u64,
+    pub fee_per_spend: Amount,
+}
+
+// Closing the pool through the root's close-all leaf: one tx paying every user, its fee committed
+// once and split between them
+#[derive(Debug, Serialize)]
+pub struct CloseAllCost {
+    // whether the pool as configured has the leaf (POOL_CLOSE_ALL_LEAF)
+    pub enabled: bool,
+    pub committed_fee: Amount,
+    pub fee_per_user: Amount,
+    pub anchor_budget: Amount,
+    // what it saves over the full unwind, committed fees and anchor children together
+    pub savings: Amount,
+}
+
+// Users paid to several of their addresses. Every extra output makes each of their exits bigger,
+// its fee comes out of their own exit, nobody else pays for it.
+#[derive(Debug, Serialize)]
+pub struct SplitCost {
+    pub users: usize,
+    pub extra_outputs: usize,
+    // what the extra outputs cost their users at DEFAULT_FEE_RATE, over all of them
+    pub fee: Amount,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PoolCosts {
+    pub users: usize,
+    pub total_locked: Amount,
+    pub levels: Vec<LevelCost>,
+    // txs to unwind the whole pool, every one commits the fee policy's fee (and the anchor value)
+    pub unwind_spends: usize,
+    pub committed_fees: Amount,
+    pub anchor_amount: Option<Amount>,
+    // what the fee payer spends on the anchor children for a full unwind at `fee_rate`
+    pub anchor_budget: Amount,
+    pub fee_rate_sat_vb: u64,
+    // the first of the last two users is paid in full, everyone else pays their own exit
+    pub best_case_exit: Amount,
+    pub worst_case_exit: Amount,
+    pub naive_payout_vsize: u64,
+    pub naive_payout_fee: Amount,
+    pub overhead_percent: f64,
+    pub close_all: CloseAllCost,
+    pub splits: SplitCost,
+}
+
+pub fn binomial(n: u64, k: u64) -> u64 {
+    (0..k).fold(1, |acc, i| acc * (n - i) / (i + 1))
+}
+
+// The fee policy's fee for a template spending a node of `users` users with `payouts` p2tr outputs
+// (and the anchor). Splits and other scripts change the size, this is the plain pool.
+fn policy_fee(users: usize, payouts: usize, close_all: bool, config: &NetworkConfig) -> Amount {
+    let spent = NodePath::new((0..users).collect()).unwrap_or_else(|_| NodePath::root());
+    let p2tr = TxOut {
+        value: Amount::ZERO,
+        script_pubkey: ScriptBuf::from_bytes(vec![0; 34]),
+    };
+    let mut outputs = vec![p2tr; payouts];
+    if let Some(anchor_amount) = config.anchor_amount {
+        outputs.push(TxOut {
+            value: anchor_amount,
+            script_pubkey: ScriptBuf::from_bytes(vec![0; 4]),
+        });
+    }
+    config
+        .fee_policy
+        .fee_for(&TemplateInfo::new(&spent, close_all, &outputs, config))
+}
+
+// Cost of the pool as it is configured (POOL_USERS, AMOUNT_PER_USER, the fee policy, the network's anchors)
+pub fn pool_costs(config: &NetworkConfig, fee_rate_sat_vb: u64) -> PoolCosts {
+    let n = POOL_USERS as u64;
+    let levels: Vec<LevelCost> = (2..=POOL_USERS)
+        .rev()
+        .map(|users| {
+            let nodes = binomial(n, users as u64);
+            // the exit pool has a single leaf paying both users
+            let leaves = if users == 2 { 1 } else { users as u64 };
+            LevelCost {
+                users,
+                nodes,
+                templates: nodes * leaves,
+                fee_per_spend: policy_fee(users, 2, false, config),
+            }
+        })
+        .collect();
+
+    let unwind_spends = POOL_USERS - 1;
+    // the unwind spends one node of every level
+    let committed_fees: Amount = levels.iter().map(|level| level.fee_per_spend).sum();
+    let highest_fee = levels
+        .iter()
+        .map(|level| level.fee_per_spend)
+        .max()
+        .unwrap_or_default();
+    let child_fee = Amount::from_sat(fee_rate_sat_vb * CPFP_CHILD_VSIZE);
+    let anchor_budget = match config.anchor_amount {
+        Some(_) => child_fee * unwind_spends as u64,
+        None => Amount::ZERO,
+    };
+    let worst_case_exit = match config.anchor_amount {
+        Some(_) => highest_fee + child_fee,
+        None => highest_fee,
+    };
+
+    // one key path input paying every user directly
+    let naive_payout_vsize = (TX_OVERHEAD_WU + P2TR_KEY_SPEND_WU + P2TR_OUTPUT_WU * n).div_ceil(4);
+    let naive_payout_fee = Amount::from_sat(naive_payout_vsize * fee_rate_sat_vb);
+    let pool_cost = committed_fees + anchor_budget;
+    let overhead_percent = if naive_payout_fee == Amount::ZERO {
+        0.0
+    } else {
+        (pool_cost.to_sat() as f64 - naive_payout_fee.to_sat() as f64)
+            / naive_payout_fee.to_sat() as f64
+            * 100.0
+    };
+
+    let fee_per_user =
+        close_all_fee_share(policy_fee(POOL_USERS, POOL_USERS, true, config), POOL_USERS);
+    let close_all_committed = fee_per_user * n;
+    let close_all_anchor_budget = match config.anchor_amount {
+        Some(_) => child_fee,
+        None => Amount::ZERO,
+    };
+    let close_all = CloseAllCost {
+        enabled: config.close_all_leaf,
+        committed_fee: close_all_committed,
+        fee_per_user,
+        anchor_budget: close_all_anchor_budget,
+        savings: pool_cost
+            .checked_sub(close_all_committed + close_all_anchor_budget)
+            .unwrap_or_default(),
+    };
+
+    let splits = SplitCost {
+        users: config.splits.len(),
+        extra_outputs: config.splits.values().map(Vec::len).sum(),
+        fee: config
+            .splits
+            .values()
+            .map(|splits| {
+                split_total(splits) - splits.iter().map(|split| split.amount).sum::<Amount>()
+            })
+            .sum(),
+    };
+
+    PoolCosts {
+        users: POOL_USERS,
+        total_locked: AMOUNT_PER_USER * n,
+        levels,
+        unwind_spends,
+        committed_fees,
+        anchor_amount: config.anchor_amount,
+        anchor_budget,
+        fee_rate_sat_vb,
+        best_case_exit: Amount::ZERO,
+        worst_case_exit,
+        naive_payout_vsize,
+        naive_payout_fee,
+        overhead_percent,
+        close_all,
+        splits,
+    }
+}
+
+pub fn print_costs(costs: &PoolCosts) {
+    println!(
+        "pool of {} users, {} locked",
+        costs.users, costs.total_locked
+    );
+    println!();
+    println!(
+        "{:>6} {:>8} {:>10} {:>14}",
+        "users", "nodes", "templates", "fee/spend"
+    );
+    for level in &costs.levels {
+        println!(
+            "{:>6} {:>8} {:>10} {:>14}",
+            level.users,
+            level.nodes,
+            level.templates,
+            level.fee_per_spend.to_sat()
+        );
+    }
+    println!();
+    println!(
+        "full unwind: {} txs, {} committed in fees",
+        costs.unwind_spends, costs.committed_fees
+    );
+    match costs.anchor_amount {
+        Some(anchor) => println!(
+            "anchors: {} per spend (part of the committed fees), fee payer budget {} at {} sat/vB",
+            anchor, costs.anchor_budget, costs.fee_rate_sat_vb
+        ),
+        None => println!("anchors: none, the fee comes out of the withdrawal"),
+    }
+    println!(
+        "exit cost per user: best {}, worst {}",
+        costs.best_case_exit, costs.worst_case_exit
+    );
+    println!(
+        "naive payout: {} vB, {} at {} sat/vB, the pool costs {:.1}% more",
+        costs.naive_payout_vsize,
+        costs.naive_payout_fee,
+        costs.fee_rate_sat_vb,
+        costs.overhead_percent
+    );
+    let close_all = &costs.close_all;
+    println!(
+        "close all: 1 tx, {} committed in fees ({} per user), fee payer budget {}, saves {} over the full unwind{}",
+        close_all.committed_fee,
+        close_all.fee_per_user,
+        close_all.anchor_budget,
+        close_all.savings,
+        if close_all.enabled {
+            ""
+        } else {
+            " (not in this pool, create it with POOL_CLOSE_ALL_LEAF=true)"
+        }
+    );
+    if costs.splits.users > 0 {
+        println!(
+            "splits: {} users paid to {} extra addresses, {} in fees out of their own exits",
+            costs.splits.users, costs.splits.extra_outputs, costs.splits.fee
+        );
+    }
+}
@@ -0,0 +1,293 @@
+use std::collections::HashMap;
+
+use anyhow::{anyhow, bail, Result};
+use bitcoin::{
+    hashes::Hash,
+    key::{Keypair, Secp256k1},
+    opcodes::all::{OP_CHECKSIG, OP_DROP},
+    script::Builder,
+    secp256k1::Message,
+    sighash::{Prevouts, SighashCache},
+    taproot::{self, LeafVersion, TapLeafHash, TaprootSpendInfo},
+    ScriptBuf, Sequence, TapSighashType, Transaction, TxOut, Txid, XOnlyPublicKey,
+};
+use tracing::info;
+
+use crate::{
+    config::NetworkConfig,
+    ctv_scripts::spend_leaf,
+    footprint::SCHNORR_SIG_SIZE,
+    template::{Bip119Ctv, TemplateCommitment},
+};
+
+// the Elements introspection backend's name, known without the `elements` feature so its manifests
+// are refused rather than rebuilt as ctv
+pub const INTROSPECTION_BACKEND_NAME: &str = "elements-introspection";
+
+// A pool spend before the covenant specific witness is attached.
+// The tx has no witness yet, so its txid is already final (segwit) and the next spend can build on it.
+#[derive(Debug, Clone)]
+pub struct TemplateSpend {
+    pub tx: Transaction,
+    pub prevout: TxOut,
+    pub spend_info: TaprootSpendInfo,
+    pub template_hash: [u8; 32],
+    pub leaf_version: LeafVersion,
+}
+
+// How a pool node is restricted to its pre-agreed spends.
+// The tree shape and the template hashes are the same for every backend, only the leaf script and witness change.
+pub trait CovenantBackend: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    // hash identifying the spend with these outputs and input sequence, leaves are built from it
+    fn template_hash(&self, outputs: &[TxOut], sequence: Sequence) -> [u8; 32];
+
+    // the tapleaf script that locks a node to the template with this hash
+    fn leaf_script(&self, template_hash: [u8; 32]) -> ScriptBuf;
+
+    // Whether the spend with this template can use its leaf, with `sequence` on the input. Checked for
+    // every leaf when a node is built, so a leaf the pool can't spend fails before anything is funded.
+    fn check_leaf(&self, _template_hash: [u8; 32], _sequence: Sequence) -> Result<()> {
+        Ok(())
+    }
+
+    // true if every spend has to go through `presign` before the backend is sealed
+    fn requires_presigning(&self) -> bool {
+        false
+    }
+
+    // sizes of the witness items `finalize` puts before the leaf script and control block, for
+    // weight and fee estimates of the template's spend
+    fn witness_items(&self, _template_hash: [u8; 32]) -> Vec<usize> {
+        Vec::new()
+    }
+
+    fn presign(&mut self, _spend: &TemplateSpend) -> Result<()> {
+        Ok(())
+    }
+
+    // called once all spends are presigned, nothing can be signed after this
+    fn seal(&mut self) {}
+
+    // attach the witness so the template can be broadcast
+    fn finalize(&self, spend: TemplateSpend) -> Result<Transaction>;
+
+    // key baked into the leaf scripts, needed to rebuild the tree somewhere else
+    fn leaf_key(&self) -> Option<XOnlyPublicKey> {
+        None
+    }
+}
+
+// Consensus enforced covenant, the leaf script itself checks the spending tx against the template.
+#[derive(Debug, Clone, Copy)]
+pub struct OpcodeBackend<C: TemplateCommitment>(pub C);
+
+// Real OP_CTV enforcement, only works where BIP-119 is active (inquisition signet/regtest)
+pub type CtvBackend = OpcodeBackend<Bip119Ctv>;
+
+impl<C: TemplateCommitment> From<C> for OpcodeBackend<C> {
+    fn from(commitment: C) -> Self {
+        Self(commitment)
+    }
+}
+
+impl<C: TemplateCommitment> CovenantBackend for OpcodeBackend<C> {
+    fn name(&self) -> &'static str {
+        self.0.name()
+    }
+
+    fn template_hash(&self, outputs: &[TxOut], sequence: Sequence) -> [u8; 32] {
+        self.0.template_hash(outputs, sequence)
+    }
+
+    fn leaf_script(&self, template_hash: [u8; 32]) -> ScriptBuf {
+        self.0.leaf_script(template_hash)
+    }
+
+    fn finalize(&self, spend: TemplateSpend) -> Result<Transaction> {
+        let leaf_script = self.leaf_script(spend.template_hash);
+        Ok(spend_leaf(
+            spend.tx,
+            spend.spend_info,
+            leaf_script,
+            spend.leaf_version,
+        ))
+    }
+}
+
+// Covenant emulation with a key that is thrown away after signing.
+// Every leaf is `<template_hash> OP_DROP <ephemeral_key> OP_CHECKSIG`, so the tree looks the same as the ctv one,
+// but the restriction only holds if the key was really deleted after presigning.
+//
+// Unlike CTV the signatures commit to the outpoint being spent, so they are only valid for the exact
+// unwind order that was presigned (for the PoC that is the order of the addresses).
+// With SIGHASH_ALL|SIGHASH_ANYONECANPAY inputs can still be added to pay fees on networks without anchors.
+pub struct EphemeralSignerBackend {
+    commitment: Bip119Ctv,
+    sighash_type: TapSighashType,
+    secp: Secp256k1<bitcoin::secp256k1::All>,
+    pubkey: XOnlyPublicKey,
+    keypair: Option<Keypair>,
+    signatures: HashMap<Txid, taproot::Signature>,
+}
+
+impl EphemeralSignerBackend {
+    // only sighash types that commit to every output, anything else would let the outputs be changed
+    pub fn new(commitment: Bip119Ctv, sighash_type: TapSighashType) -> Result<Self> {
+        if !matches!(
+            sighash_type,
+            TapSighashType::Default | TapSighashType::All | TapSighashType::AllPlusAnyoneCanPay
+        ) {
+            bail!(
+                "{} does not commit to all outputs, it can't enforce the templates",
+                sighash_type
+            );
+        }
+
+        let secp = Secp256k1::new();
+        let keypair = Keypair::new(&secp, &mut rand::thread_rng());
+        let (pubkey, _parity) = XOnlyPublicKey::from_keypair(&keypair);
+
+        Ok(Self {
+            commitment,
+            sighash_type,
+            secp,
+            pubkey,
+            keypair: Some(keypair),
+            signatures: HashMap::new(),
+        })
+    }
+
+    // Same leaves as the backend that held `pubkey`, but it can't sign anything.
+    // Enough to rebuild and check the tree from a manifest.
+    pub fn watch_only(commitment: Bip119Ctv, pubkey: XOnlyPublicKey) -> Self {
+        Self {
+            commitment,
+            sighash_type: TapSighashType::Default,
+            secp: Secp256k1::new(),
+            pubkey,
+            keypair: None,
+            signatures: HashMap::new(),
+        }
+    }
+}
+
+impl CovenantBackend for EphemeralSignerBackend {
+    fn name(&self) -> &'static str {
+        "presigned"
+    }
+
+    // keep the ctv hash so the presigned tree commits to exactly the same templates
+    fn template_hash(&self, outputs: &[TxOut], sequence: Sequence) -> [u8; 32] {
+        self.commitment.template_hash(outputs, sequence)
+    }
+
+    fn leaf_script(&self, template_hash: [u8; 32]) -> ScriptBuf {
+        Builder::new()
+            .push_slice(template_hash)
+            .push_opcode(OP_DROP)
+            .push_x_only_key(&self.pubkey)
+            .push_opcode(OP_CHECKSIG)
+            .into_script()
+    }
+
+    fn requires_presigning(&self) -> bool {
+        true
+    }
+
+    // the signature, with a sighash byte unless it is SIGHASH_DEFAULT
+    fn witness_items(&self, _template_hash: [u8; 32]) -> Vec<usize> {
+        match self.sighash_type {
+            TapSighashType::Default => vec![SCHNORR_SIG_SIZE],
+            _ => vec![SCHNORR_SIG_SIZE + 1],
+        }
+    }
+
+    fn presign(&mut self, spend: &TemplateSpend) -> Result<()> {
+        let keypair = self
+            .keypair
+            .as_ref()
+            .ok_or_else(|| anyhow!("ephemeral key already deleted, cannot presign"))?;
+
+        let leaf_hash =
+            TapLeafHash::from_script(&self.leaf_script(spend.template_hash), spend.leaf_version);
+        let sighash = SighashCache::new(&spend.tx).taproot_script_spend_signature_hash(
+            0,
+            &Prevouts::All(&[&spend.prevout]),
+            leaf_hash,
+            self.sighash_type,
+        )?;
+
+        let msg = Message::from_digest(sighash.to_byte_array());
+        let signature = taproot::Signature {
+            signature: self.secp.sign_schnorr(&msg, keypair),
+            sighash_type: self.sighash_type,
+        };
+
+        self.signatures.insert(spend.tx.compute_txid(), signature);
+        Ok(())
+    }
+
+    fn seal(&mut self) {
+        if let Some(mut keypair) = self.keypair.take() {
+            keypair.non_secure_erase();
+        }
+        info!(
+            "ephemeral key deleted, {} presigned spends \n",
+            self.signatures.len()
+        );
+    }
+
+    fn finalize(&self, mut spend: TemplateSpend) -> Result<Transaction> {
+        if self.keypair.is_some() {
+            bail!("ephemeral key has not been deleted yet, refusing to spend");
+        }
+
+        let txid = spend.tx.compute_txid();
+        let signature = self
+            .signatures
+            .get(&txid)
+            .ok_or_else(|| anyhow!("no presigned signature for template {}", txid))?;
+
+        let script_ver = (self.leaf_script(spend.template_hash), spend.leaf_version);
+        let ctrl_block = spend
+            .spend_info
+            .control_block(&script_ver)
+            .ok_or_else(|| anyhow!("leaf for template {} not found in pool tree", txid))?;
+
+        let input = &mut spend.tx.input[0];
+        input.witness.push(signature.to_vec());
+        input.witness.push(script_ver.0.into_bytes());
+        input.witness.push(ctrl_block.serialize());
+
+        Ok(spend.tx)
+    }
+
+    fn leaf_key(&self) -> Option<XOnlyPublicKey> {
+        Some(self.pubkey)
+    }
+}
+
+// COVENANT_BACKEND=ctv (default) or COVENANT_BACKEND=presigned for networks without OP_CTV,
+// PRESIGN_SIGHASH picks the presigned backend's sighash type (SIGHASH_DEFAULT)
+pub fn backend_from_env(config: &NetworkConfig) -> Result<Box<dyn CovenantBackend>> {
+    let ctv = Bip119Ctv {
+        tx_version: config.tx_version,
+    };
+    let backend: Box<dyn CovenantBackend> =
+        match NetworkConfig::get_env_var("COVENANT_BACKEND", "ctv").as_str() {
+            "ctv" => Box::new(CtvBackend::from(ctv)),
+            "presigned" => {
+                let sighash_type =
+                    NetworkConfig::get_env_var("PRESIGN_SIGHASH", "SIGHASH_DEFAULT").parse()?;
+                Box::new(EphemeralSignerBackend::new(ctv, sighash_type)?)
+            }
+            other => bail!(
+                "unknown COVENANT_BACKEND {}, expected ctv or presigned",
+                other
+            ),
+        };
+    info!("covenant backend: {} \n", backend.name());
+    Ok(backend)
+}
@@ -1,24 +1,41 @@
 use bitcoin::{
+    absolute,
     consensus::Encodable,
     hashes::{sha256, Hash},
-    key::{Keypair, Secp256k1},
+    key::Secp256k1,
     opcodes::all::OP_NOP4,
     script::Builder,
-    taproot::{LeafVersion, TaprootBuilder, TaprootSpendInfo},
-    Address, Amount, Opcode, ScriptBuf, Sequence, Transaction, TxOut, XOnlyPublicKey,
+    taproot::{
+        LeafVersion, NodeInfo, TaprootBuilder, TaprootBuilderError, TaprootSpendInfo,
+        TAPROOT_CONTROL_MAX_NODE_COUNT,
+    },
+    transaction, Address, Amount, Opcode, ScriptBuf, Sequence, Transaction, TxIn, TxOut,
+    XOnlyPublicKey,
 };
 
-use anyhow::Result;
+use anyhow::{anyhow, bail, Result};
+use serde::{Deserialize, Serialize};
+use std::{cmp::Reverse, collections::BinaryHeap, fmt, str::FromStr};
+use tracing::warn;
 
 use crate::{
-    config::{FEE_AMOUNT, TX_VERSION},
-    AMOUNT_PER_USER,
+    config::{NetworkConfig, DUST_AMOUNT},
+    covenant::CovenantBackend,
+    fee_policy::{template_fee, TemplateInfo},
+    ids::{NodePath, UserIndex},
+    payouts::{payout_outputs, split_total, user_share},
+    user_anchor::exit_anchor_script,
 };
 
 // OP_SECURETHEBAG is the original name (well there was another name before this but thats deep lore) for OP_CHECKTEMPLATEVERIFY.
 // OP_NOP4 is the spare opcode that will be used for op_ctv cos of softfork reasons
 // https://github.com/bitcoin/bips/blob/master/bip-0119.mediawiki
-const OP_SECURETHEBAG: Opcode = OP_NOP4;
+pub const OP_SECURETHEBAG: Opcode = OP_NOP4;
+
+// BIP-341 "H" point, nobody knows its discrete log so the key path can't be spent
+// https://github.com/bitcoin/bips/blob/master/bip-0341.mediawiki#constructing-and-spending-taproot-outputs
+pub const NUMS_INTERNAL_KEY: &str =
+    "50929b74c1a04954b78b4b6035e97a5e078a5a0f28ec96d547bfee9ace803ac0";
 
 pub fn ctv_script(ctv_hash: [u8; 32]) -> ScriptBuf {
     Builder::new()
@@ -27,58 +44,319 @@ pub fn ctv_script(ctv_hash: [u8; 32]) -> ScriptBuf {
         .into_script()
 }
 
-pub fn calc_ctv_hash(outputs: &[TxOut], timeout: Option<u32>) -> [u8; 32] {
+// BIP-119 DefaultCheckTemplateVerifyHash of `tx` spent at `input_index`, what OP_CTV in that input
+// checks its 32 byte argument against. Commits to the version, the locktime, the scriptSigs (only if
+// any input has one), the number of inputs and their nSequences, the outputs and the input index, but
+// not to the outpoints or witnesses, so a template can be fixed before the coin it spends exists.
+// `input_index` isn't checked against the inputs, it is hashed as given like the spec's reference code.
+pub fn ctv_hash(tx: &Transaction, input_index: u32) -> [u8; 32] {
     let mut buffer = Vec::new();
-    buffer.extend(TX_VERSION.to_le_bytes()); // version
-    buffer.extend(0_i32.to_le_bytes()); // locktime
-    buffer.extend(1_u32.to_le_bytes()); // inputs len
-
-    let seq = if let Some(timeout_value) = timeout {
-        sha256::Hash::hash(&Sequence(timeout_value).0.to_le_bytes())
-    } else {
-        sha256::Hash::hash(&Sequence::ENABLE_RBF_NO_LOCKTIME.0.to_le_bytes())
-    };
-    buffer.extend(seq.to_byte_array()); // sequences
+    buffer.extend(tx.version.0.to_le_bytes()); // version
+    buffer.extend(tx.lock_time.to_consensus_u32().to_le_bytes()); // locktime
+
+    if tx.input.iter().any(|input| !input.script_sig.is_empty()) {
+        let mut script_sig_bytes: Vec<u8> = Vec::new();
+        for input in &tx.input {
+            input
+                .script_sig
+                .consensus_encode(&mut script_sig_bytes)
+                .unwrap();
+        }
+        buffer.extend(sha256::Hash::hash(&script_sig_bytes).to_byte_array()); // scriptSigs
+    }
 
-    let outputs_len = outputs.len() as u32;
-    buffer.extend(outputs_len.to_le_bytes()); // outputs len
+    buffer.extend((tx.input.len() as u32).to_le_bytes()); // inputs len
+    let mut sequence_bytes: Vec<u8> = Vec::new();
+    for input in &tx.input {
+        sequence_bytes.extend(input.sequence.to_consensus_u32().to_le_bytes());
+    }
+    buffer.extend(sha256::Hash::hash(&sequence_bytes).to_byte_array()); // sequences
 
+    buffer.extend((tx.output.len() as u32).to_le_bytes()); // outputs len
     let mut output_bytes: Vec<u8> = Vec::new();
-    for o in outputs {
+    for o in &tx.output {
         o.consensus_encode(&mut output_bytes).unwrap();
     }
     buffer.extend(sha256::Hash::hash(&output_bytes).to_byte_array()); // outputs hash
 
-    buffer.extend(0_u32.to_le_bytes()); // inputs index
+    buffer.extend(input_index.to_le_bytes()); // inputs index
 
     let hash = sha256::Hash::hash(&buffer);
     hash.to_byte_array()
 }
 
-pub fn create_pool_address(ctv_hashes: Vec<[u8; 32]>) -> Result<TaprootSpendInfo> {
-    let secp = Secp256k1::new();
+// Template hash of the pool's spends: input index 0, no locktime, one sequence per input.
+// CTV commits to every input's nSequence, so relative timelocks are part of the template.
+// The inputs are segwit so there are no scriptSigs to commit to.
+pub fn calc_ctv_hash(tx_version: i32, outputs: &[TxOut], sequences: &[Sequence]) -> [u8; 32] {
+    let tx = Transaction {
+        version: transaction::Version(tx_version),
+        lock_time: absolute::LockTime::ZERO,
+        input: sequences
+            .iter()
+            .map(|&sequence| TxIn {
+                sequence,
+                ..Default::default()
+            })
+            .collect(),
+        output: outputs.to_vec(),
+    };
+    ctv_hash(&tx, 0)
+}
 
-    let key_pair = Keypair::new(&secp, &mut rand::thread_rng());
-    //TO DO: replace this with a MuSig key for happy spend :)
-    // Random unspendable XOnlyPublicKey provided for internal key. Will replace this with combination of all pool users pubkeys (MuSig)
-    //in a real implentation we would most likely use nostr to communicate the funding PSBT, so you could also use their npubs to create the MuSig key
-    let (unspendable_pubkey, _parity) = XOnlyPublicKey::from_keypair(&key_pair);
+// How the leaves of a node are arranged in its tap tree. Part of every pool address, so it is recorded in the manifest.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TreeLayout {
+    // every leaf at (almost) the same depth, in template order. The layout of pools from before it could be picked
+    #[default]
+    Balanced,
+    // huffman tree over how likely each path is: the next user of the planned unwind gets the shortest
+    // control block and the recovery leaf, only used if nobody unwinds, the longest. Identical leaves are merged.
+    Weighted,
+}
 
-    let num_scripts = ctv_hashes.len();
-    let depths = calculate_depths(num_scripts);
+impl FromStr for TreeLayout {
+    type Err = anyhow::Error;
 
-    let mut builder = TaprootBuilder::new();
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "balanced" => Ok(Self::Balanced),
+            "weighted" => Ok(Self::Weighted),
+            other => bail!(
+                "unknown tree layout {}, expected balanced or weighted",
+                other
+            ),
+        }
+    }
+}
+
+// The tree shape and fee model of the release that built a pool. Recorded in the manifest, a pool is
+// always rebuilt (and so unwound) with the construction it was created with, new pools get LATEST.
+// Changing either means a new version here, with the old code path kept behind the dispatch.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "u16", into = "u16")]
+pub enum TemplateVersion {
+    // a node for every set of remaining users, each leaf pays the spender their share (AMOUNT_PER_USER
+    // and any payout jitter) - the fee policy's fee, the rest of the node to the next one and the anchor.
+    // Every pool from before the versions
+    #[default]
+    V1,
+}
+
+impl TemplateVersion {
+    pub const LATEST: Self = Self::V1;
+    // every version this build can rebuild, oldest first
+    pub const ALL: &'static [Self] = &[Self::V1];
+}
+
+impl TryFrom<u16> for TemplateVersion {
+    type Error = anyhow::Error;
+
+    fn try_from(version: u16) -> Result<Self> {
+        match version {
+            1 => Ok(Self::V1),
+            other => bail!(
+                "template version {} is unknown to this build (latest {})",
+                other,
+                u16::from(Self::LATEST)
+            ),
+        }
+    }
+}
+
+impl From<TemplateVersion> for u16 {
+    fn from(version: TemplateVersion) -> u16 {
+        match version {
+            TemplateVersion::V1 => 1,
+        }
+    }
+}
 
-    for (depth, hash) in depths.iter().zip(ctv_hashes.iter()) {
-        let script = ctv_script(*hash);
-        builder = builder.add_leaf((*depth).try_into()?, script)?;
+impl fmt::Display for TemplateVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "v{}", u16::from(*self))
     }
+}
+
+// huffman weights of the weighted layout
+const NEXT_SPENDER_WEIGHT: u128 = 3;
+const TEMPLATE_WEIGHT: u128 = 2;
+const EXTRA_LEAF_WEIGHT: u128 = 1;
+
+// 0xc0 or an even version that is not the annex tag, as hex (0xc2) or decimal
+pub fn parse_leaf_version(s: &str) -> Result<LeafVersion> {
+    let version = match s.strip_prefix("0x") {
+        Some(hex) => u8::from_str_radix(hex, 16)?,
+        None => s.parse()?,
+    };
+    Ok(LeafVersion::from_consensus(version)?)
+}
+
+// `extra_leaf` goes next to the template leaves, e.g. the recovery path.
+// The first hash is the template of the user who leaves next in the planned unwind.
+// Every leaf gets `leaf_version`, anything but TapScript (0xc0) is only for experiments, see POOL_LEAF_VERSION.
+pub fn create_pool_address(
+    ctv_hashes: Vec<[u8; 32]>,
+    backend: &dyn CovenantBackend,
+    extra_leaf: Option<ScriptBuf>,
+    layout: TreeLayout,
+    leaf_version: LeafVersion,
+) -> Result<TaprootSpendInfo> {
+    // Unspendable internal key, only the leaves can spend the output.
+    // it has to be the same every time, so anyone with the manifest can rebuild the exact same tree
+    let unspendable_pubkey = XOnlyPublicKey::from_str(NUMS_INTERNAL_KEY)?;
+    create_keyed_pool_address(
+        ctv_hashes,
+        backend,
+        extra_leaf,
+        layout,
+        leaf_version,
+        unspendable_pubkey,
+    )
+}
 
-    let taproot_spend_info = builder.finalize(&secp, unspendable_pubkey).unwrap();
+// create_pool_address with `internal_key` for the key path, e.g. the users' MuSig2 aggregate (see musig.rs)
+pub fn create_keyed_pool_address(
+    ctv_hashes: Vec<[u8; 32]>,
+    backend: &dyn CovenantBackend,
+    extra_leaf: Option<ScriptBuf>,
+    layout: TreeLayout,
+    leaf_version: LeafVersion,
+    internal_key: XOnlyPublicKey,
+) -> Result<TaprootSpendInfo> {
+    let scripts: Vec<ScriptBuf> = ctv_hashes
+        .iter()
+        .map(|hash| backend.leaf_script(*hash))
+        .chain(extra_leaf.clone())
+        .collect();
+    // the spend code rebuilds the same scripts, so they have to be minimal where they are made
+    for script in &scripts {
+        if script
+            .instructions_minimal()
+            .any(|instruction| instruction.is_err())
+        {
+            bail!(
+                "leaf script {} is not minimally encoded",
+                script.to_asm_string()
+            );
+        }
+    }
+
+    if scripts.is_empty() {
+        bail!("pool node without leaves");
+    }
+    let taproot_spend_info = match layout {
+        TreeLayout::Balanced => balanced_tree(scripts, leaf_version, internal_key)?,
+        TreeLayout::Weighted => weighted_tree(
+            leaf_weights(scripts, extra_leaf.as_ref()),
+            leaf_version,
+            internal_key,
+        )?,
+    };
 
     Ok(taproot_spend_info)
 }
 
+// every leaf at (almost) the same depth, in order
+fn balanced_tree(
+    scripts: Vec<ScriptBuf>,
+    leaf_version: LeafVersion,
+    internal_key: XOnlyPublicKey,
+) -> Result<TaprootSpendInfo> {
+    let depths = calculate_depths(scripts.len());
+    let deepest = depths.iter().copied().max().unwrap_or(0);
+    if deepest > TAPROOT_CONTROL_MAX_NODE_COUNT {
+        bail!(
+            "pool node has {} leaves, a tap tree holds at most 2^{} (consensus depth limit)",
+            scripts.len(),
+            TAPROOT_CONTROL_MAX_NODE_COUNT
+        );
+    }
+    let mut builder = TaprootBuilder::new();
+    for (depth, script) in depths.iter().zip(scripts) {
+        builder = builder.add_leaf_with_ver((*depth).try_into()?, script, leaf_version)?;
+    }
+    builder
+        .finalize(&Secp256k1::verification_only(), internal_key)
+        .map_err(|_| anyhow!("balanced tap tree of {} leaves is incomplete", depths.len()))
+}
+
+// The weight of every leaf of a weighted node: the next spender's template is the likeliest spend,
+// the extra leaf the least likely
+fn leaf_weights(scripts: Vec<ScriptBuf>, extra_leaf: Option<&ScriptBuf>) -> Vec<(u128, ScriptBuf)> {
+    let mut weighted: Vec<(u128, ScriptBuf)> = Vec::with_capacity(scripts.len());
+    for (i, script) in scripts.into_iter().enumerate() {
+        let weight = if Some(&script) == extra_leaf {
+            EXTRA_LEAF_WEIGHT
+        } else if i == 0 {
+            NEXT_SPENDER_WEIGHT
+        } else {
+            TEMPLATE_WEIGHT
+        };
+        // the same script twice (e.g. two users with the same address) only needs one leaf
+        match weighted.iter_mut().find(|(_, leaf)| *leaf == script) {
+            Some((existing, _)) => *existing += weight,
+            None => weighted.push((weight, script)),
+        }
+    }
+    weighted
+}
+
+// Likelier leaves closer to the root, the weighted layout. A tree too lopsided for consensus
+// (deeper than TAPROOT_CONTROL_MAX_NODE_COUNT) falls back to the balanced layout of the same
+// leaves, nested balanced subtrees at most log2 of the leaf count deep.
+pub fn weighted_tree(
+    weighted: Vec<(u128, ScriptBuf)>,
+    leaf_version: LeafVersion,
+    internal_key: XOnlyPublicKey,
+) -> Result<TaprootSpendInfo> {
+    if weighted.is_empty() {
+        bail!("pool node without leaves");
+    }
+    let leaves = weighted.len();
+    match huffman_tree(weighted.clone(), leaf_version) {
+        Ok(root) => Ok(TaprootSpendInfo::from_node_info(
+            &Secp256k1::verification_only(),
+            internal_key,
+            root,
+        )),
+        Err(TaprootBuilderError::InvalidMerkleTreeDepth(depth)) => {
+            warn!(
+                "weighted tap tree of {} leaves would be {} deep (consensus limit {}), this node falls back to the balanced layout",
+                leaves, depth, TAPROOT_CONTROL_MAX_NODE_COUNT
+            );
+            let scripts = weighted.into_iter().map(|(_, script)| script).collect();
+            balanced_tree(scripts, leaf_version, internal_key)
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+// TaprootBuilder::with_huffman_tree, but for any leaf version and weights past u32, which
+// Fibonacci-like weights need to reach the depth limit at all. Fails with InvalidMerkleTreeDepth
+// if a leaf would end up deeper than consensus allows.
+pub fn huffman_tree(
+    weighted: Vec<(u128, ScriptBuf)>,
+    leaf_version: LeafVersion,
+) -> Result<NodeInfo, TaprootBuilderError> {
+    let mut nodes: BinaryHeap<(Reverse<u128>, NodeInfo)> = weighted
+        .into_iter()
+        .map(|(weight, script)| {
+            (
+                Reverse(weight),
+                NodeInfo::new_leaf_with_ver(script, leaf_version),
+            )
+        })
+        .collect();
+    while nodes.len() > 1 {
+        let (Reverse(w1), a) = nodes.pop().expect("two nodes left");
+        let (Reverse(w2), b) = nodes.pop().expect("two nodes left");
+        nodes.push((Reverse(w1.saturating_add(w2)), NodeInfo::combine(a, b)?));
+    }
+    Ok(nodes.pop().expect("a node has at least one leaf").1)
+}
+
 fn calculate_depths(num_scripts: usize) -> Vec<usize> {
     if num_scripts == 0 {
         return vec![];
@@ -99,48 +377,187 @@ fn calculate_depths(num_scripts: usize) -> Vec<usize> {
     depths
 }
 
+// outputs of a withdrawal from the node of `spent`: the rest of the pool, the user leaving (and their splits) and the fee anchor (if the network uses one),
+// keyed to the leaving user when they registered an anchor key.
+// `withdraw_share` is what the leaving user has in the pool (see payouts::user_share), they get it minus the fee policy's fee.
+pub fn create_withdraw_outputs(
+    spent: &NodePath,
+    pool_addr: &Address,
+    withdraw_addr: &Address,
+    anchor_addr: &Address,
+    pool_exit_amount: Amount,
+    withdraw_share: Amount,
+    config: &NetworkConfig,
+) -> Result<Vec<TxOut>> {
+    match config.template_version {
+        TemplateVersion::V1 => withdraw_outputs_v1(
+            spent,
+            pool_addr,
+            withdraw_addr,
+            anchor_addr,
+            pool_exit_amount,
+            withdraw_share,
+            config,
+        ),
+    }
+}
+
+fn withdraw_outputs_v1(
+    spent: &NodePath,
+    pool_addr: &Address,
+    withdraw_addr: &Address,
+    anchor_addr: &Address,
+    pool_exit_amount: Amount,
+    withdraw_share: Amount,
+    config: &NetworkConfig,
+) -> Result<Vec<TxOut>> {
+    // the leaving user's own anchor if they registered a key, so they can bump their exit
+    let anchor_script = exit_anchor_script(withdraw_addr, anchor_addr, config)?;
+    let outputs = |withdraw_amount| {
+        // a user with splits gets several outputs, in the exit node `pool_addr` is a user too
+        let mut outputs = payout_outputs(pool_addr, pool_exit_amount, config);
+        outputs.extend(payout_outputs(withdraw_addr, withdraw_amount, config));
+
+        if let Some(anchor_amount) = config.anchor_amount {
+            outputs.push(TxOut {
+                value: anchor_amount,
+                script_pubkey: anchor_script.clone(),
+            });
+        }
+        outputs
+    };
+
+    let template = TemplateInfo::new(spent, false, &outputs(withdraw_share), config);
+    let splits = config
+        .splits
+        .get(&withdraw_addr.to_string())
+        .map(Vec::as_slice)
+        .unwrap_or_default();
+    let fee = template_fee(
+        &template,
+        withdraw_share,
+        split_total(splits) + DUST_AMOUNT,
+        config,
+    )?;
+    let withdraw_amount = withdraw_share.checked_sub(fee).ok_or_else(|| {
+        anyhow!(
+            "fee policy {} takes {} from the exit of {} out of the pool of {}, more than their {} share",
+            config.fee_policy.spec(),
+            fee,
+            withdraw_addr,
+            spent,
+            withdraw_share
+        )
+    })?;
+    Ok(outputs(withdraw_amount))
+}
+
+// What each user pays towards the close-all tx: its fee once for the whole pool, split evenly
+// (rounded up) instead of once per withdrawal.
+pub fn close_all_fee_share(fee: Amount, users: usize) -> Amount {
+    Amount::from_sat(fee.to_sat().div_ceil(users as u64))
+}
+
+// the close-all leaf's tx: every user (and their splits) paid at once, then the anchor
+pub fn create_close_all_outputs(
+    addresses: &[Address],
+    anchor_addr: &Address,
+    config: &NetworkConfig,
+) -> Result<Vec<TxOut>> {
+    match config.template_version {
+        TemplateVersion::V1 => close_all_outputs_v1(addresses, anchor_addr, config),
+    }
+}
+
+fn close_all_outputs_v1(
+    addresses: &[Address],
+    anchor_addr: &Address,
+    config: &NetworkConfig,
+) -> Result<Vec<TxOut>> {
+    let outputs = |share: Amount| {
+        let mut outputs: Vec<TxOut> = UserIndex::all()
+            .zip(addresses)
+            .flat_map(|(user, address)| {
+                payout_outputs(address, user_share(user, config) - share, config)
+            })
+            .collect();
+
+        if let Some(anchor_amount) = config.anchor_amount {
+            outputs.push(TxOut {
+                value: anchor_amount,
+                script_pubkey: anchor_addr.script_pubkey(),
+            });
+        }
+        outputs
+    };
+
+    let template = TemplateInfo::new(&NodePath::root(), true, &outputs(Amount::ZERO), config);
+    let total = UserIndex::all()
+        .take(addresses.len())
+        .map(|user| user_share(user, config))
+        .sum();
+    let fee = template_fee(&template, total, Amount::ZERO, config)?;
+    let share = close_all_fee_share(fee, addresses.len());
+    for (user, address) in UserIndex::all().zip(addresses) {
+        let splits = config
+            .splits
+            .get(&address.to_string())
+            .map(Vec::as_slice)
+            .unwrap_or_default();
+        let keep = split_total(splits) + DUST_AMOUNT;
+        if user_share(user, config)
+            .checked_sub(share)
+            .is_none_or(|rest| rest < keep)
+        {
+            bail!(
+                "fee policy {} takes {} from user {} to close the pool, it has to leave them {}",
+                config.fee_policy.spec(),
+                share,
+                user,
+                keep
+            );
+        }
+    }
+    Ok(outputs(share))
+}
+
+// the template of a spend of the node of `spent`, committing to its level's nSequence
+#[allow(clippy::too_many_arguments)]
 pub fn create_withdraw_ctv_hash(
+    spent: &NodePath,
     pool_addr: &Address,
     withdraw_addr: &Address,
     anchor_addr: &Address,
     pool_exit_amount: Amount,
-) -> [u8; 32] {
-    let ctv_tx_out = [
-        TxOut {
-            value: pool_exit_amount,
-            script_pubkey: pool_addr.script_pubkey(),
-        },
-        TxOut {
-            value: AMOUNT_PER_USER - FEE_AMOUNT,
-            script_pubkey: withdraw_addr.script_pubkey(),
-        },
-        #[cfg(feature = "testnet4")]
-        TxOut {
-            value: Amount::ZERO,
-            script_pubkey: anchor_addr.script_pubkey(),
-        },
-        #[cfg(feature = "regtest")]
-        TxOut {
-            value: FEE_AMOUNT,
-            script_pubkey: anchor_addr.script_pubkey(),
-        },
-    ];
-
-    calc_ctv_hash(&ctv_tx_out, None)
-}
-
-pub fn spend_ctv(
+    withdraw_share: Amount,
+    config: &NetworkConfig,
+    backend: &dyn CovenantBackend,
+) -> Result<[u8; 32]> {
+    let ctv_tx_out = create_withdraw_outputs(
+        spent,
+        pool_addr,
+        withdraw_addr,
+        anchor_addr,
+        pool_exit_amount,
+        withdraw_share,
+        config,
+    )?;
+
+    Ok(backend.template_hash(&ctv_tx_out, config.unwind_sequence(spent)))
+}
+
+// script path spend through `leaf_script`, the covenant opcode itself needs no extra witness data
+pub fn spend_leaf(
     mut unsigned_tx: Transaction,
     taproot_spend_info: TaprootSpendInfo,
-    ctv_hash: [u8; 32],
+    leaf_script: ScriptBuf,
+    leaf_version: LeafVersion,
 ) -> Transaction {
-    let ctv_script = ctv_script(ctv_hash);
-
     //TO DO - add a signature here for the spends, for now it works ok as an example,
     //or maybe we dont even need them, it just means anyone with the descriptor can spend these...
 
     for input in unsigned_tx.input.iter_mut() {
-        let script_ver = (ctv_script.clone(), LeafVersion::TapScript);
+        let script_ver = (leaf_script.clone(), leaf_version);
         let ctrl_block = taproot_spend_info.control_block(&script_ver).unwrap();
 
         input.witness.push(script_ver.0.into_bytes());
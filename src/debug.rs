@@ -0,0 +1,245 @@
+use anyhow::{anyhow, bail, Result};
+use bitcoin::{
+    consensus::encode::serialize, hex::DisplayHex, Address, Network, ScriptBuf, Transaction, Txid,
+};
+use serde::Serialize;
+
+use crate::{
+    covenant::TemplateSpend,
+    ids::{NodePath, UserIndex},
+    manifest::LoadedPool,
+    pools::pool_spend_template,
+};
+
+// One field where the tx seen on chain isn't the template the manifest rebuilds
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Difference {
+    // e.g. "output 1 amount", "input 0 sequence", "input 0 leaf script"
+    pub field: String,
+    pub expected: String,
+    pub observed: String,
+}
+
+// The tx at one level of the planned unwind, rebuilt and compared with the one observed
+#[derive(Debug, Clone, Serialize)]
+pub struct LevelDiff {
+    pub level: usize,
+    pub node: NodePath,
+    pub spender: UserIndex,
+    pub expected_txid: Txid,
+    pub observed_txid: Txid,
+    // the template hash the leaf commits to, and the one of the observed tx's outputs and sequence
+    pub template_hash: String,
+    pub observed_template_hash: Option<String>,
+    // byte for byte the same tx, witness included
+    pub identical: bool,
+    pub differences: Vec<Difference>,
+}
+
+// The template at `level` of the planned unwind, 0 spending the funding output: the levels before
+// it are finalized from `funding_tx` down so its input is the outpoint the real unwind spends
+pub fn rebuild_level(
+    pool: &LoadedPool,
+    funding_tx: &Transaction,
+    level: usize,
+) -> Result<TemplateSpend> {
+    let spenders = pool.config.spenders()?;
+    if level >= spenders.len() {
+        bail!(
+            "the pool has levels 0 to {}, there is no level {}",
+            spenders.len() - 1,
+            level
+        );
+    }
+    let mut previous_tx = funding_tx.clone();
+    for (at, user) in spenders.into_iter().enumerate() {
+        let spend = pool_spend_template(
+            &pool.tree,
+            &pool.config,
+            pool.backend.as_ref(),
+            user,
+            &pool.addresses,
+            &previous_tx,
+            &pool.anchor_addr,
+        )?;
+        if at == level {
+            return Ok(spend);
+        }
+        previous_tx = pool.backend.finalize(spend)?;
+    }
+    unreachable!("level {} is below the pool size", level)
+}
+
+// an output's address, or the script's hex when it has none
+fn script_text(script: &ScriptBuf, network: Network) -> String {
+    Address::from_script(script, network)
+        .map(|address| address.to_string())
+        .unwrap_or_else(|_| script.to_hex_string())
+}
+
+fn push<T: PartialEq + ToString>(
+    differences: &mut Vec<Difference>,
+    field: impl Into<String>,
+    expected: T,
+    observed: T,
+) {
+    if expected != observed {
+        differences.push(Difference {
+            field: field.into(),
+            expected: expected.to_string(),
+            observed: observed.to_string(),
+        });
+    }
+}
+
+// name of witness item `i` of `count`, a script path spend ends with the leaf script and control block
+fn witness_item(i: usize, count: usize) -> String {
+    match count - i {
+        1 => "control block".to_string(),
+        2 => "leaf script".to_string(),
+        _ => format!("witness item {}", i),
+    }
+}
+
+// Rebuild the tx at `level` and compare it with `observed`, field by field
+pub fn diff_level(
+    pool: &LoadedPool,
+    funding_tx: &Transaction,
+    level: usize,
+    observed: &Transaction,
+) -> Result<LevelDiff> {
+    let spender = *pool
+        .config
+        .spenders()?
+        .get(level)
+        .ok_or_else(|| anyhow!("the pool has no level {}", level))?;
+    let node = pool.config.unwind_node(spender)?;
+    let spend = rebuild_level(pool, funding_tx, level)?;
+    let template_hash = spend.template_hash;
+    let expected = pool.backend.finalize(spend)?;
+    let network = pool.config.network;
+
+    let mut differences = Vec::new();
+    push(
+        &mut differences,
+        "version",
+        expected.version.0,
+        observed.version.0,
+    );
+    push(
+        &mut differences,
+        "locktime",
+        expected.lock_time.to_consensus_u32(),
+        observed.lock_time.to_consensus_u32(),
+    );
+    push(
+        &mut differences,
+        "input count",
+        expected.input.len(),
+        observed.input.len(),
+    );
+    for (i, (expected, observed)) in expected.input.iter().zip(&observed.input).enumerate() {
+        push(
+            &mut differences,
+            format!("input {} outpoint", i),
+            expected.previous_output,
+            observed.previous_output,
+        );
+        push(
+            &mut differences,
+            format!("input {} sequence", i),
+            expected.sequence.to_consensus_u32(),
+            observed.sequence.to_consensus_u32(),
+        );
+        push(
+            &mut differences,
+            format!("input {} witness items", i),
+            expected.witness.len(),
+            observed.witness.len(),
+        );
+        // matched from the end, where the leaf script and control block are
+        let count = expected.witness.len();
+        let expected_items = expected.witness.to_vec();
+        let observed_items = observed.witness.to_vec();
+        for (back, (expected_item, observed_item)) in expected_items
+            .iter()
+            .rev()
+            .zip(observed_items.iter().rev())
+            .enumerate()
+        {
+            push(
+                &mut differences,
+                format!("input {} {}", i, witness_item(count - 1 - back, count)),
+                expected_item.to_lower_hex_string(),
+                observed_item.to_lower_hex_string(),
+            );
+        }
+    }
+    push(
+        &mut differences,
+        "output count",
+        expected.output.len(),
+        observed.output.len(),
+    );
+    for (i, (expected, observed)) in expected.output.iter().zip(&observed.output).enumerate() {
+        push(
+            &mut differences,
+            format!("output {} amount", i),
+            expected.value,
+            observed.value,
+        );
+        push(
+            &mut differences,
+            format!("output {} script", i),
+            script_text(&expected.script_pubkey, network),
+            script_text(&observed.script_pubkey, network),
+        );
+    }
+
+    // what the leaf would have to commit to for the observed tx to pass it
+    let observed_template_hash = observed.input.first().map(|input| {
+        pool.backend
+            .template_hash(&observed.output, input.sequence)
+            .to_lower_hex_string()
+    });
+    Ok(LevelDiff {
+        level,
+        node,
+        spender,
+        expected_txid: expected.compute_txid(),
+        observed_txid: observed.compute_txid(),
+        template_hash: template_hash.to_lower_hex_string(),
+        observed_template_hash,
+        identical: serialize(&expected) == serialize(observed),
+        differences,
+    })
+}
+
+pub fn print_level_diff(diff: &LevelDiff) {
+    println!(
+        "level {}: user {} leaving the pool of users {}",
+        diff.level, diff.spender, diff.node
+    );
+    println!("  rebuilt  {}", diff.expected_txid);
+    println!("  observed {}", diff.observed_txid);
+    if diff.identical {
+        println!("identical, byte for byte");
+        return;
+    }
+    if diff.differences.is_empty() {
+        println!("the txs differ in their bytes but in none of the fields compared");
+    }
+    for difference in &diff.differences {
+        println!("{}:", difference.field);
+        println!("  expected {}", difference.expected);
+        println!("  observed {}", difference.observed);
+    }
+    if let Some(observed) = &diff.observed_template_hash {
+        if *observed != diff.template_hash {
+            println!(
+                "the leaf commits to template {}, the observed outputs and sequence hash to {}: the covenant check fails",
+                diff.template_hash, observed
+            );
+        }
+    }
+}
@@ -0,0 +1,109 @@
+use anyhow::{bail, Result};
+use bitcoin::{Transaction, Txid};
+use bitcoincore_rpc::RpcApi;
+use clap::ValueEnum;
+use serde::Serialize;
+use tracing::info;
+
+use crate::{
+    config::{ConfirmationStage, NetworkConfig},
+    esplora::ConfirmationTracker,
+    lifecycle::Lifecycle,
+    manifest::{LoadedPool, PoolManifest},
+    miner::{wait_for_confirmations, wait_for_maturity},
+    pools::pool_spend_template,
+    profile::NetworkProfile,
+    rpc_helper::AsyncRpc,
+};
+
+// Where `demo run` picks an unfinished pool up from
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DemoResume {
+    // None while the pool still has to be funded
+    pub funding_txid: Option<Txid>,
+    // users that have left, the next one to leave is this index
+    pub withdrawn: usize,
+}
+
+// The demo pool in `manifest` still to be finished on `network`, None when there is nothing to pick
+// up and a new pool is started. The wallet played every user, so it can carry on with any step.
+pub fn demo_resume(manifest: &PoolManifest, network: NetworkProfile) -> Result<Option<DemoResume>> {
+    let withdrawn = match manifest.lifecycle {
+        Lifecycle::Registered => 0,
+        Lifecycle::Funded => 0,
+        Lifecycle::Unwinding(withdrawn) => withdrawn,
+        _ => return Ok(None),
+    };
+    if manifest.profile != network {
+        let name = manifest.profile.to_possible_value().map_or_else(
+            || format!("{:?}", manifest.profile),
+            |value| value.get_name().to_string(),
+        );
+        bail!(
+            "the manifest has an unfinished {} pool, resume it with --network {} or start over with --fresh",
+            name,
+            name
+        );
+    }
+    // the presigned key only lived in the run that built the pool
+    if manifest.covenant_key.is_some() {
+        bail!("the presigned backend's key is gone with the run that built this pool, it can't be resumed");
+    }
+    Ok(Some(DemoResume {
+        funding_txid: manifest.funding_txid,
+        withdrawn,
+    }))
+}
+
+// txid of every spend of the unwind the demo walks (user 0 leaves first) on top of `funding_tx`.
+// The templates fix every tx, so a restart knows what was sent before it stopped.
+pub fn planned_unwind(pool: &LoadedPool, funding_tx: &Transaction) -> Result<Vec<Txid>> {
+    let mut previous_tx = funding_tx.clone();
+    let mut txids = Vec::new();
+    for user in pool.config.spenders()? {
+        let spend = pool_spend_template(
+            &pool.tree,
+            &pool.config,
+            pool.backend.as_ref(),
+            user,
+            &pool.addresses,
+            &previous_tx,
+            &pool.anchor_addr,
+        )?;
+        previous_tx = pool.backend.finalize(spend)?;
+        txids.push(previous_tx.compute_txid());
+    }
+    Ok(txids)
+}
+
+// whether the node has `txid`, in the mempool or a block
+pub async fn node_knows(rpc: &AsyncRpc, txid: Txid) -> bool {
+    rpc.run(move |c| c.get_raw_transaction_info(&txid, None))
+        .await
+        .is_ok()
+}
+
+// Wait until `txid` is as deep as `stage` asks: the regtest miner buries it, esplora watches it for
+// reorgs, and without either the node is polled until the real network confirms it
+pub async fn wait_for_depth(
+    rpc: &AsyncRpc,
+    config: &NetworkConfig,
+    tracker: &mut Option<ConfirmationTracker>,
+    txid: Txid,
+    stage: ConfirmationStage,
+) -> Result<()> {
+    let blocks = config.confirmations.get(stage);
+    if config.block_interval.is_some() {
+        return wait_for_confirmations(rpc, config, txid, blocks).await;
+    }
+    match tracker {
+        Some(tracker) => tracker.wait(txid, stage).await,
+        None => {
+            info!(
+                "no miner and no POOL_ESPLORA_URL, polling the node for {}",
+                txid
+            );
+            wait_for_maturity(rpc, config, txid, u16::try_from(blocks)?).await
+        }
+    }
+}
@@ -0,0 +1,475 @@
+use std::{path::Path, time::Duration};
+
+use anyhow::{bail, Result};
+use bitcoin::Amount;
+use bitcoincore_rpc::RpcApi;
+use clap::ValueEnum;
+use serde::Serialize;
+use tokio::net::TcpStream;
+
+use crate::{
+    config::{NetworkConfig, DEFAULT_FEE_RATE, FEE_WALLET_LOW_BALANCE},
+    covenant::backend_from_env,
+    fund::{required_funding, FUNDING_TX_VSIZE},
+    manifest::PoolManifest,
+    profile::NetworkProfile,
+    rpc_helper::{fee_for_vsize, AsyncRpc},
+};
+
+const ZMQ_CONNECT_TIMEOUT: Duration = Duration::from_secs(3);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Ok,
+    Warn,
+    Fail,
+    // an earlier check failed or it doesn't apply to this setup
+    Skipped,
+}
+
+// One thing `doctor` looked at, with what to do about it when it isn't ok
+#[derive(Debug, Clone, Serialize)]
+pub struct Check {
+    pub name: &'static str,
+    pub status: Status,
+    pub detail: String,
+    pub fix: Option<String>,
+}
+
+impl Check {
+    fn ok(name: &'static str, detail: impl Into<String>) -> Self {
+        Self {
+            name,
+            status: Status::Ok,
+            detail: detail.into(),
+            fix: None,
+        }
+    }
+
+    fn skipped(name: &'static str, detail: impl Into<String>) -> Self {
+        Self {
+            name,
+            status: Status::Skipped,
+            detail: detail.into(),
+            fix: None,
+        }
+    }
+
+    fn warn(name: &'static str, detail: impl Into<String>, fix: impl Into<String>) -> Self {
+        Self {
+            name,
+            status: Status::Warn,
+            detail: detail.into(),
+            fix: Some(fix.into()),
+        }
+    }
+
+    fn fail(name: &'static str, detail: impl Into<String>, fix: impl Into<String>) -> Self {
+        Self {
+            name,
+            status: Status::Fail,
+            detail: detail.into(),
+            fix: Some(fix.into()),
+        }
+    }
+}
+
+// Go through everything a run needs, in the order a run needs it. Never fails itself, every problem
+// (including a bad env var or any other ConfigProblem) ends up as a failed check.
+pub async fn diagnose(profile: NetworkProfile, manifest_path: &Path) -> Vec<Check> {
+    let mut checks = Vec::new();
+
+    let config = NetworkConfig::new(profile);
+    if let Err(errors) = config.validate() {
+        checks.push(Check::fail(
+            "config",
+            errors.to_string(),
+            "fix or unset the env vars named above, see the network profiles table in the README",
+        ));
+        return checks;
+    }
+    let backend = match backend_from_env(&config) {
+        Ok(backend) => backend,
+        Err(e) => {
+            checks.push(Check::fail(
+                "config",
+                e.to_string(),
+                "COVENANT_BACKEND is ctv or presigned, PRESIGN_SIGHASH a sighash type like SIGHASH_DEFAULT",
+            ));
+            return checks;
+        }
+    };
+    checks.push(Check::ok(
+        "config",
+        format!(
+            "{} profile on {}, {} backend, wallet {:?}",
+            profile_name(profile),
+            config.network,
+            backend.name(),
+            config.wallet_name
+        ),
+    ));
+
+    let Some(rpc) = check_node(&config, &mut checks).await else {
+        for name in ["network", "wallet", "funds", "ctv", "zmq"] {
+            checks.push(Check::skipped(name, "the node is not reachable"));
+        }
+        checks.push(check_state(&config, manifest_path));
+        return checks;
+    };
+    checks.push(check_network(&config, &rpc).await);
+    checks.push(check_wallet(&config, &rpc).await);
+    checks.push(check_funds(&config, &rpc).await);
+    if let Some(check) = check_fee_wallet(&config).await {
+        checks.push(check);
+    }
+    checks.push(if backend.requires_presigning() {
+        Check::skipped("ctv", "the presigned backend doesn't need OP_CTV")
+    } else {
+        check_ctv(&config, &rpc).await
+    });
+    checks.push(check_zmq(&rpc).await);
+    checks.push(check_state(&config, manifest_path));
+
+    checks
+}
+
+// as --network takes it
+fn profile_name(profile: NetworkProfile) -> String {
+    profile
+        .to_possible_value()
+        .map(|value| value.get_name().to_string())
+        .unwrap_or_else(|| format!("{:?}", profile))
+}
+
+async fn check_node(config: &NetworkConfig, checks: &mut Vec<Check>) -> Option<AsyncRpc> {
+    let rpc = match AsyncRpc::connect(config).await {
+        Ok(rpc) => rpc,
+        Err(e) => {
+            let error = e.to_string();
+            let fix = if config.wallet_name.is_empty() {
+                "export POOL_WALLET (or TESTNET4_WALLET, SIGNET_WALLET, INQUISITION_WALLET)"
+                    .to_string()
+            } else if error.contains("cookie") || error.contains("401") || error.contains("auth") {
+                "export BITCOIN_RPC_USER and BITCOIN_RPC_PASS, or BITCOIN_RPC_COOKIE_PATH pointing at the node's .cookie".to_string()
+            } else {
+                format!(
+                    "start bitcoind for {} with -server, or point POOL_RPC_URL at it (now {})",
+                    config.network, config.rpc_url
+                )
+            };
+            checks.push(Check::fail("node", error, fix));
+            return None;
+        }
+    };
+    match rpc.run(|c| c.get_network_info()).await {
+        Ok(info) => {
+            checks.push(Check::ok(
+                "node",
+                format!("{} at {}", info.subversion, config.rpc_url),
+            ));
+            Some(rpc)
+        }
+        Err(e) => {
+            checks.push(Check::fail(
+                "node",
+                e.to_string(),
+                format!(
+                    "check the node at {} is up and answering RPC",
+                    config.rpc_url
+                ),
+            ));
+            None
+        }
+    }
+}
+
+async fn check_network(config: &NetworkConfig, rpc: &AsyncRpc) -> Check {
+    let info: serde_json::Value = match rpc.run(|c| c.call("getblockchaininfo", &[])).await {
+        Ok(info) => info,
+        Err(e) => {
+            return Check::fail(
+                "network",
+                e.to_string(),
+                "check the node is synced and answering RPC",
+            )
+        }
+    };
+    let chain = info["chain"].as_str().unwrap_or_default();
+    if chain != config.network.to_core_arg() {
+        return Check::fail(
+            "network",
+            format!("the node is on {} but the profile expects {}", chain, config.network.to_core_arg()),
+            "pick the matching --network profile, or point POOL_RPC_URL at a node on the right chain",
+        );
+    }
+    if let Err(e) = config.check_chain(rpc).await {
+        return Check::fail(
+            "network",
+            e.to_string(),
+            "connect to a node on the profile's signet, or set POOL_SIGNET_CHALLENGE to this one's",
+        );
+    }
+    let blocks = info["blocks"].as_u64().unwrap_or_default();
+    let headers = info["headers"].as_u64().unwrap_or_default();
+    if info["initialblockdownload"].as_bool().unwrap_or(false) && !config.is_regtest() {
+        return Check::warn(
+            "network",
+            format!("{} still syncing, {} of {} blocks", chain, blocks, headers),
+            "wait for the node to finish syncing, fee estimates and confirmations are off until then",
+        );
+    }
+    Check::ok("network", format!("{} at height {}", chain, blocks))
+}
+
+async fn check_wallet(config: &NetworkConfig, rpc: &AsyncRpc) -> Check {
+    match rpc.run(|c| c.list_wallets()).await {
+        Ok(wallets) if wallets.contains(&config.wallet_name) => {
+            Check::ok("wallet", format!("{} is loaded", config.wallet_name))
+        }
+        Ok(wallets) => Check::fail(
+            "wallet",
+            format!("{} is not loaded (loaded: {:?})", config.wallet_name, wallets),
+            format!(
+                "bitcoin-cli loadwallet {0}, or bitcoin-cli createwallet {0} if it doesn't exist yet",
+                config.wallet_name
+            ),
+        ),
+        Err(e) => Check::fail(
+            "wallet",
+            e.to_string(),
+            "start the node with its wallet enabled (no -disablewallet)",
+        ),
+    }
+}
+
+async fn check_funds(config: &NetworkConfig, rpc: &AsyncRpc) -> Check {
+    if config.is_regtest() {
+        return Check::skipped("funds", "regtest mines its own coins");
+    }
+    let balances = match rpc.run(|c| c.get_balances()).await {
+        Ok(balances) => balances,
+        Err(e) => return Check::fail("funds", e.to_string(), "load the wallet first"),
+    };
+    let fee = fee_for_vsize(DEFAULT_FEE_RATE, FUNDING_TX_VSIZE).unwrap_or(Amount::ZERO);
+    let needed = required_funding() + fee;
+    let confirmed = balances.mine.trusted;
+    if confirmed >= needed {
+        return Check::ok(
+            "funds",
+            format!(
+                "{} confirmed, the pool needs {} plus fees",
+                confirmed,
+                required_funding()
+            ),
+        );
+    }
+    let pending = balances.mine.untrusted_pending + balances.mine.immature;
+    let fix = if confirmed + pending >= needed {
+        "wait for the pending coins to confirm".to_string()
+    } else {
+        format!(
+            "send at least {} to the {} wallet (bitcoin-cli -rpcwallet={} getnewaddress), or lower AMOUNT_PER_USER / POOL_USERS",
+            needed - confirmed - pending,
+            config.wallet_name,
+            config.wallet_name
+        )
+    };
+    Check::fail(
+        "funds",
+        format!(
+            "{} confirmed ({} pending) but about {} is needed",
+            confirmed, pending, needed
+        ),
+        fix,
+    )
+}
+
+// only when a separate FEE_WALLET pays the anchor children
+async fn check_fee_wallet(config: &NetworkConfig) -> Option<Check> {
+    let name = config.fee_wallet_name.as_ref()?;
+    if config.anchor_amount.is_none() {
+        return Some(Check::warn(
+            "fee wallet",
+            format!(
+                "FEE_WALLET {} is set but {} has no fee anchors",
+                name, config.network
+            ),
+            "unset FEE_WALLET, or set POOL_ANCHOR_AMOUNT_SATS to use anchors",
+        ));
+    }
+    let fee_payer = match AsyncRpc::connect_wallet(config, name).await {
+        Ok(fee_payer) => fee_payer,
+        Err(e) => {
+            return Some(Check::fail(
+                "fee wallet",
+                e.to_string(),
+                format!("bitcoin-cli loadwallet {}", name),
+            ))
+        }
+    };
+    Some(
+        match fee_payer.run(|c| c.get_balance(Some(1), None)).await {
+            Ok(balance) if config.is_regtest() || balance >= FEE_WALLET_LOW_BALANCE => {
+                Check::ok("fee wallet", format!("{} has {} confirmed", name, balance))
+            }
+            Ok(balance) => Check::warn(
+                "fee wallet",
+                format!(
+                    "{} has {} confirmed, below {}",
+                    name, balance, FEE_WALLET_LOW_BALANCE
+                ),
+                format!("top up {}, every anchor child is paid from it", name),
+            ),
+            Err(e) => Check::fail(
+                "fee wallet",
+                e.to_string(),
+                format!("bitcoin-cli loadwallet {}", name),
+            ),
+        },
+    )
+}
+
+async fn check_ctv(config: &NetworkConfig, rpc: &AsyncRpc) -> Check {
+    match config.ctv_active(rpc).await {
+        Ok(true) => Check::ok("ctv", "OP_CHECKTEMPLATEVERIFY is active"),
+        Ok(false) if config.require_ctv => Check::fail(
+            "ctv",
+            "OP_CTV is not active on this node and the profile requires it",
+            "connect to a bitcoin inquisition node, or use COVENANT_BACKEND=presigned",
+        ),
+        Ok(false) => Check::warn(
+            "ctv",
+            "OP_CTV is not active on this node, the templates are not enforced (OP_NOP4 passes anything)",
+            "fine for trying things out, use an inquisition node or POOL_REQUIRE_CTV=true for a real pool",
+        ),
+        Err(e) => Check::fail(
+            "ctv",
+            e.to_string(),
+            "the node has to support getdeploymentinfo (Bitcoin Core 23 or later)",
+        ),
+    }
+}
+
+// nothing here needs zmq, but a node set up for it should have it working
+async fn check_zmq(rpc: &AsyncRpc) -> Check {
+    let notifications: serde_json::Value =
+        match rpc.run(|c| c.call("getzmqnotifications", &[])).await {
+            Ok(notifications) => notifications,
+            Err(_) => return Check::skipped("zmq", "the node was built without zmq"),
+        };
+    let endpoints: Vec<String> = notifications
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|notification| notification["address"].as_str().map(str::to_string))
+        .collect();
+    if endpoints.is_empty() {
+        return Check::skipped("zmq", "no zmq notifications configured");
+    }
+
+    let mut dead = Vec::new();
+    for endpoint in &endpoints {
+        let Some(host) = endpoint.strip_prefix("tcp://") else {
+            continue;
+        };
+        // the node binds the wildcard, it answers on localhost
+        let host = host.replace("0.0.0.0", "127.0.0.1");
+        let live = tokio::time::timeout(ZMQ_CONNECT_TIMEOUT, TcpStream::connect(&host))
+            .await
+            .is_ok_and(|connected| connected.is_ok());
+        if !live {
+            dead.push(endpoint.clone());
+        }
+    }
+    if dead.is_empty() {
+        Check::ok("zmq", format!("{} endpoints live", endpoints.len()))
+    } else {
+        Check::fail(
+            "zmq",
+            format!("not accepting connections: {}", dead.join(", ")),
+            "check the -zmqpub* addresses the node was started with and that no firewall is in the way",
+        )
+    }
+}
+
+fn check_state(config: &NetworkConfig, manifest_path: &Path) -> Check {
+    if !manifest_path.exists() {
+        return Check::skipped(
+            "state",
+            format!(
+                "no manifest at {} yet, a run writes one",
+                manifest_path.display()
+            ),
+        );
+    }
+    let manifest = match PoolManifest::load(manifest_path) {
+        Ok(manifest) => manifest,
+        Err(e) => {
+            return Check::fail(
+                "state",
+                format!("{} can't be read: {}", manifest_path.display(), e),
+                "a file from a newer build needs that build; otherwise restore the manifest from a backup or a pool member",
+            )
+        }
+    };
+    if manifest.network != config.network {
+        return Check::warn(
+            "state",
+            format!(
+                "{} is a {} pool, the profile is {}",
+                manifest_path.display(),
+                manifest.network,
+                config.network
+            ),
+            format!(
+                "use --network {} for this manifest, or another --manifest",
+                profile_name(manifest.profile)
+            ),
+        );
+    }
+    match manifest.load_pool() {
+        Ok(_) => Check::ok(
+            "state",
+            format!(
+                "{} rebuilds to its root {}, the pool is {}",
+                manifest_path.display(),
+                manifest.root_address,
+                manifest.lifecycle
+            ),
+        ),
+        Err(e) => Check::fail(
+            "state",
+            format!("{} is inconsistent: {}", manifest_path.display(), e),
+            "the manifest doesn't describe the tree it claims, rebuild it with the POOL_USERS and AMOUNT_PER_USER it was made with or get a good copy",
+        ),
+    }
+}
+
+pub fn print_diagnosis(checks: &[Check]) {
+    for check in checks {
+        let status = match check.status {
+            Status::Ok => "ok",
+            Status::Warn => "warn",
+            Status::Fail => "FAIL",
+            Status::Skipped => "skip",
+        };
+        println!("[{:>4}] {:<10} {}", status, check.name, check.detail);
+        if let Some(fix) = &check.fix {
+            println!("{:18}fix: {}", "", fix);
+        }
+    }
+}
+
+// an error once everything is printed, so the exit code says whether the setup is healthy
+pub fn diagnosis_result(checks: &[Check]) -> Result<()> {
+    let failed = checks
+        .iter()
+        .filter(|check| check.status == Status::Fail)
+        .count();
+    if failed > 0 {
+        bail!("{} of {} checks failed", failed, checks.len());
+    }
+    Ok(())
+}
@@ -0,0 +1,697 @@
+// Elements (Liquid) support, behind the `elements` feature. Elements has no OP_CTV but its tapscript
+// can look at the spending tx (OP_INSPECTOUTPUT* and friends), which is enough for the pool's
+// covenant today. The pool is built the usual way, only three things change: every output carries an
+// explicit asset and the fee is an output of its own, the taproot hashes are tagged
+// `TapLeaf/elements`... with leaf version 0xc4, and addresses use Elements' params.
+// Only unblinded (explicit) outputs, no confidential transactions.
+
+use std::{collections::HashMap, fmt, fs, path::Path, str::FromStr, sync::Mutex};
+
+use anyhow::{anyhow, bail, Result};
+use bitcoin::{
+    bech32::{self, Fe32, Hrp},
+    hashes::{sha256, sha256d, Hash, HashEngine},
+    hex::{DisplayHex, FromHex},
+    key::{Secp256k1, TweakedPublicKey},
+    opcodes::{
+        all::{OP_EQUAL, OP_EQUALVERIFY, OP_RETURN},
+        Opcode,
+    },
+    script::{Builder, Instruction},
+    secp256k1::{Parity, Scalar},
+    taproot::{LeafVersion, TapLeafHash, TapNodeHash, TaprootSpendInfo},
+    Address, Amount, Network, OutPoint, Script, ScriptBuf, Sequence, Transaction, TxOut, Txid,
+    Witness, XOnlyPublicKey,
+};
+use tracing::info;
+
+use crate::{
+    config::NetworkConfig,
+    covenant::{CovenantBackend, TemplateSpend, INTROSPECTION_BACKEND_NAME},
+    ids::NodePath,
+    manifest::{LoadedPool, PoolManifest},
+    pools::{build_pools, node_exit},
+    profile::NetworkProfile,
+};
+
+// tapscript's leaf version on Elements
+pub const ELEMENTS_LEAF_VERSION: u8 = 0xc4;
+
+const OP_PUSHCURRENTINPUTINDEX: u8 = 0xcd;
+const OP_INSPECTINPUTSEQUENCE: u8 = 0xcb;
+const OP_INSPECTOUTPUTASSET: u8 = 0xce;
+const OP_INSPECTOUTPUTVALUE: u8 = 0xcf;
+const OP_INSPECTOUTPUTSCRIPTPUBKEY: u8 = 0xd1;
+const OP_INSPECTNUMINPUTS: u8 = 0xd4;
+const OP_INSPECTNUMOUTPUTS: u8 = 0xd5;
+
+// prefix of an explicit (unblinded) asset or value
+const EXPLICIT: u8 = 0x01;
+
+// What an Elements chain looks like to the pool: the hrp of its unconfidential segwit addresses and
+// the asset fees are paid in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ElementsParams {
+    pub name: &'static str,
+    pub hrp: &'static str,
+    pub policy_asset: &'static str,
+}
+
+// elementsd -chain=elementsregtest with the default `bitcoin` asset
+pub const ELEMENTS_REGTEST: ElementsParams = ElementsParams {
+    name: "elementsregtest",
+    hrp: "ert",
+    policy_asset: "b2e15d0d7a0c94e4e2ce0fe6e8691b9e451377f6e46e8045a86f7c4b5d4f0f23",
+};
+
+// An asset id, shown byte reversed like a txid
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct AssetId([u8; 32]);
+
+impl FromStr for AssetId {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut bytes = <[u8; 32]>::from_hex(s)?;
+        bytes.reverse();
+        Ok(Self(bytes))
+    }
+}
+
+impl fmt::Display for AssetId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut bytes = self.0;
+        bytes.reverse();
+        write!(f, "{}", bytes.to_lower_hex_string())
+    }
+}
+
+impl AssetId {
+    // the chain's policy asset, POOL_ELEMENTS_ASSET for a chain started with another one
+    pub fn from_env(params: &ElementsParams) -> Result<Self> {
+        NetworkConfig::get_env_var("POOL_ELEMENTS_ASSET", params.policy_asset).parse()
+    }
+}
+
+// The unconfidential address paying `script`, a segwit script only
+pub fn elements_address(script: &Script, params: &ElementsParams) -> Result<String> {
+    let version = script
+        .witness_version()
+        .ok_or_else(|| anyhow!("{} has no address, it isn't segwit", script.to_asm_string()))?;
+    let hrp = Hrp::parse(params.hrp)?;
+    let version = Fe32::try_from(version.to_num())?;
+    Ok(bech32::segwit::encode(
+        hrp,
+        version,
+        &script.as_bytes()[2..],
+    )?)
+}
+
+// The script an unconfidential segwit address of the chain pays
+pub fn elements_script(address: &str, params: &ElementsParams) -> Result<ScriptBuf> {
+    let (hrp, version, program) = bech32::segwit::decode(address)?;
+    if hrp != Hrp::parse(params.hrp)? {
+        bail!(
+            "{} isn't an {} address ({}1...)",
+            address,
+            params.name,
+            params.hrp
+        );
+    }
+    let version = bitcoin::WitnessVersion::try_from(version.to_u8())?;
+    Ok(ScriptBuf::new_witness_program(
+        &bitcoin::WitnessProgram::new(version, &program)?,
+    ))
+}
+
+// sha256(sha256(tag) || sha256(tag) || data), BIP-340's tagged hash under Elements' tags
+fn tagged_hash(tag: &str, data: &[&[u8]]) -> [u8; 32] {
+    let tag = sha256::Hash::hash(tag.as_bytes());
+    let mut engine = sha256::Hash::engine();
+    engine.input(tag.as_ref());
+    engine.input(tag.as_ref());
+    for part in data {
+        engine.input(part);
+    }
+    sha256::Hash::from_engine(engine).to_byte_array()
+}
+
+fn compact_size(n: usize) -> Vec<u8> {
+    match n {
+        0..=0xfc => vec![n as u8],
+        0xfd..=0xffff => [&[0xfd][..], &(n as u16).to_le_bytes()].concat(),
+        0x10000..=0xffff_ffff => [&[0xfe][..], &(n as u32).to_le_bytes()].concat(),
+        _ => [&[0xff][..], &(n as u64).to_le_bytes()].concat(),
+    }
+}
+
+fn leaf_hash(script: &Script, version: LeafVersion) -> [u8; 32] {
+    tagged_hash(
+        "TapLeaf/elements",
+        &[
+            &[version.to_consensus()],
+            &compact_size(script.len()),
+            script.as_bytes(),
+        ],
+    )
+}
+
+fn branch_hash(a: [u8; 32], b: [u8; 32]) -> [u8; 32] {
+    let (first, second) = if a < b { (a, b) } else { (b, a) };
+    tagged_hash("TapBranch/elements", &[&first, &second])
+}
+
+// A pool node's tap tree with Elements' tags. The tree is the one rust-bitcoin built (same leaves at
+// the same places), its hashes walked back from every leaf's merkle branch and redone with the tags
+#[derive(Debug, Clone)]
+pub struct ElementsTaproot {
+    pub internal_key: XOnlyPublicKey,
+    pub output_key: XOnlyPublicKey,
+    parity: Parity,
+    // every leaf's sibling hashes from the leaf up
+    paths: HashMap<(ScriptBuf, LeafVersion), Vec<[u8; 32]>>,
+}
+
+impl ElementsTaproot {
+    pub fn from_spend_info(spend_info: &TaprootSpendInfo) -> Result<Self> {
+        let mut leaves = HashMap::new();
+        let mut children = HashMap::new();
+        for ((script, version), branches) in spend_info.script_map() {
+            for branch in branches {
+                let mut node = TapNodeHash::from(TapLeafHash::from_script(script, *version));
+                leaves.insert(node, leaf_hash(script, *version));
+                for sibling in branch.iter() {
+                    let parent = TapNodeHash::from_node_hashes(node, *sibling);
+                    children.insert(parent, (node, *sibling));
+                    node = parent;
+                }
+            }
+        }
+        // every node's hash under the elements tags, children first
+        fn rehash(
+            node: TapNodeHash,
+            leaves: &HashMap<TapNodeHash, [u8; 32]>,
+            children: &HashMap<TapNodeHash, (TapNodeHash, TapNodeHash)>,
+            hashes: &mut HashMap<TapNodeHash, [u8; 32]>,
+        ) -> Result<[u8; 32]> {
+            if let Some(hash) = hashes.get(&node) {
+                return Ok(*hash);
+            }
+            let hash = match (leaves.get(&node), children.get(&node)) {
+                (Some(leaf), _) => *leaf,
+                (None, Some((a, b))) => branch_hash(
+                    rehash(*a, leaves, children, hashes)?,
+                    rehash(*b, leaves, children, hashes)?,
+                ),
+                (None, None) => bail!("tap tree node {} is neither a leaf nor a branch", node),
+            };
+            hashes.insert(node, hash);
+            Ok(hash)
+        }
+        let mut hashes = HashMap::new();
+        let merkle_root = spend_info
+            .merkle_root()
+            .map(|root| rehash(root, &leaves, &children, &mut hashes))
+            .transpose()?;
+
+        let mut paths = HashMap::new();
+        for ((script, version), branches) in spend_info.script_map() {
+            if let Some(branch) = branches.iter().next() {
+                let path = branch
+                    .iter()
+                    .map(|sibling| rehash(*sibling, &leaves, &children, &mut hashes))
+                    .collect::<Result<_>>()?;
+                paths.insert((script.clone(), *version), path);
+            }
+        }
+
+        let internal_key = spend_info.internal_key();
+        let mut data = internal_key.serialize().to_vec();
+        if let Some(root) = merkle_root {
+            data.extend(root);
+        }
+        let tweak = tagged_hash("TapTweak/elements", &[&data]);
+        let (output_key, parity) = internal_key.add_tweak(
+            &Secp256k1::verification_only(),
+            &Scalar::from_be_bytes(tweak)?,
+        )?;
+        Ok(Self {
+            internal_key,
+            output_key,
+            parity,
+            paths,
+        })
+    }
+
+    pub fn script_pubkey(&self) -> ScriptBuf {
+        ScriptBuf::new_p2tr_tweaked(TweakedPublicKey::dangerous_assume_tweaked(self.output_key))
+    }
+
+    // leaf version and parity, the internal key, then the path up from the leaf
+    pub fn control_block(&self, script: &Script, version: LeafVersion) -> Option<Vec<u8>> {
+        let path = self.paths.get(&(script.to_owned(), version))?;
+        let parity = match self.parity {
+            Parity::Even => 0,
+            Parity::Odd => 1,
+        };
+        let mut control_block = vec![version.to_consensus() | parity];
+        control_block.extend(self.internal_key.serialize());
+        for hash in path {
+            control_block.extend(hash);
+        }
+        Some(control_block)
+    }
+}
+
+// The address of a pool node on Elements, as the pool's own (bitcoin network) address type: only
+// its script matters to the templates
+pub fn elements_node_address(spend_info: &TaprootSpendInfo, network: Network) -> Result<Address> {
+    let taproot = ElementsTaproot::from_spend_info(spend_info)?;
+    Ok(Address::p2tr_tweaked(
+        TweakedPublicKey::dangerous_assume_tweaked(taproot.output_key),
+        network,
+    ))
+}
+
+// An explicit output: asset, value and script, no nonce. The fee output has an empty script
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ElementsTxOut {
+    pub asset: AssetId,
+    pub value: Amount,
+    pub script_pubkey: ScriptBuf,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ElementsTxIn {
+    pub previous_output: OutPoint,
+    pub sequence: Sequence,
+    pub witness: Witness,
+}
+
+// An Elements tx with explicit outputs and no issuance or peg-in, enough for the pool's spends
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ElementsTransaction {
+    pub version: i32,
+    pub lock_time: u32,
+    pub input: Vec<ElementsTxIn>,
+    pub output: Vec<ElementsTxOut>,
+}
+
+impl ElementsTransaction {
+    // Elements' consensus encoding: the witness flag is a byte of its own, outputs carry the
+    // asset and a big endian value, and the witness has (empty) proofs for every input and output
+    pub fn serialize(&self, with_witness: bool) -> Vec<u8> {
+        let with_witness = with_witness && self.input.iter().any(|input| !input.witness.is_empty());
+        let mut bytes = self.version.to_le_bytes().to_vec();
+        bytes.push(with_witness as u8);
+        bytes.extend(compact_size(self.input.len()));
+        for input in &self.input {
+            bytes.extend(input.previous_output.txid.to_byte_array());
+            bytes.extend(input.previous_output.vout.to_le_bytes());
+            // no script sig
+            bytes.push(0);
+            bytes.extend(input.sequence.to_consensus_u32().to_le_bytes());
+        }
+        bytes.extend(compact_size(self.output.len()));
+        for output in &self.output {
+            bytes.push(EXPLICIT);
+            bytes.extend(output.asset.0);
+            bytes.push(EXPLICIT);
+            bytes.extend(output.value.to_sat().to_be_bytes());
+            // no nonce
+            bytes.push(0);
+            bytes.extend(compact_size(output.script_pubkey.len()));
+            bytes.extend(output.script_pubkey.as_bytes());
+        }
+        bytes.extend(self.lock_time.to_le_bytes());
+        if with_witness {
+            for input in &self.input {
+                // issuance and inflation key range proofs
+                bytes.extend([0, 0]);
+                bytes.extend(compact_size(input.witness.len()));
+                for item in input.witness.iter() {
+                    bytes.extend(compact_size(item.len()));
+                    bytes.extend(item);
+                }
+                // peg-in witness
+                bytes.push(0);
+            }
+            // surjection and range proofs
+            for _ in &self.output {
+                bytes.extend([0, 0]);
+            }
+        }
+        bytes
+    }
+
+    pub fn txid(&self) -> Txid {
+        Txid::from_raw_hash(sha256d::Hash::hash(&self.serialize(false)))
+    }
+
+    // for elements-cli sendrawtransaction
+    pub fn raw_hex(&self) -> String {
+        self.serialize(true).to_lower_hex_string()
+    }
+}
+
+// a script number as the stack holds it: minimal little endian, the sign in the top bit
+fn script_num(n: i64) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    let mut abs = n.unsigned_abs();
+    while abs > 0 {
+        bytes.push((abs & 0xff) as u8);
+        abs >>= 8;
+    }
+    let sign = if n < 0 { 0x80 } else { 0 };
+    match bytes.last_mut() {
+        Some(top) if *top & 0x80 != 0 => bytes.push(sign),
+        Some(top) => *top |= sign,
+        None => {}
+    }
+    bytes
+}
+
+// (witness version, program) as OP_INSPECTOUTPUTSCRIPTPUBKEY pushes them, -1 and the script's
+// sha256 for anything not segwit
+fn script_commitment(script: &Script) -> (i64, Vec<u8>) {
+    match script.witness_version() {
+        Some(version) => (version.to_num() as i64, script.as_bytes()[2..].to_vec()),
+        None => (
+            -1,
+            sha256::Hash::hash(script.as_bytes())
+                .to_byte_array()
+                .to_vec(),
+        ),
+    }
+}
+
+// The leaf locking a node to one spend: a single input with `sequence`, then every output's script,
+// asset and value, then the fee output (empty script, its value follows from the others)
+pub fn introspection_script(asset: AssetId, outputs: &[TxOut], sequence: Sequence) -> ScriptBuf {
+    let mut builder = Builder::new()
+        .push_opcode(Opcode::from(OP_INSPECTNUMINPUTS))
+        .push_int(1)
+        .push_opcode(OP_EQUALVERIFY)
+        .push_opcode(Opcode::from(OP_PUSHCURRENTINPUTINDEX))
+        .push_opcode(Opcode::from(OP_INSPECTINPUTSEQUENCE))
+        .push_slice(sequence.to_consensus_u32().to_le_bytes())
+        .push_opcode(OP_EQUALVERIFY)
+        .push_opcode(Opcode::from(OP_INSPECTNUMOUTPUTS))
+        .push_int(outputs.len() as i64 + 1)
+        .push_opcode(OP_EQUALVERIFY);
+    let fee = TxOut {
+        value: Amount::ZERO,
+        script_pubkey: ScriptBuf::new(),
+    };
+    for (index, output) in outputs.iter().chain([&fee]).enumerate() {
+        let (version, program) = script_commitment(&output.script_pubkey);
+        builder = builder
+            .push_int(index as i64)
+            .push_opcode(Opcode::from(OP_INSPECTOUTPUTSCRIPTPUBKEY))
+            .push_int(version)
+            .push_opcode(OP_EQUALVERIFY)
+            .push_slice(
+                <&bitcoin::script::PushBytes>::try_from(program.as_slice())
+                    .expect("at most 40 bytes"),
+            )
+            .push_opcode(OP_EQUALVERIFY)
+            .push_int(index as i64)
+            .push_opcode(Opcode::from(OP_INSPECTOUTPUTASSET))
+            .push_int(EXPLICIT as i64)
+            .push_opcode(OP_EQUALVERIFY)
+            .push_slice(asset.0)
+            .push_opcode(OP_EQUALVERIFY);
+        if index < outputs.len() {
+            builder = builder
+                .push_int(index as i64)
+                .push_opcode(Opcode::from(OP_INSPECTOUTPUTVALUE))
+                .push_int(EXPLICIT as i64)
+                .push_opcode(OP_EQUALVERIFY)
+                .push_slice(output.value.to_sat().to_le_bytes())
+                .push_opcode(OP_EQUALVERIFY);
+        }
+    }
+    // the last check leaves its result
+    let mut script = builder.into_bytes();
+    script.pop();
+    script.push(OP_EQUAL.to_u8());
+    ScriptBuf::from_bytes(script)
+}
+
+// Run a leaf made by introspection_script against `tx`'s input `index`: only the opcodes it uses
+pub fn check_introspection(script: &Script, tx: &ElementsTransaction, index: usize) -> Result<()> {
+    let mut stack: Vec<Vec<u8>> = Vec::new();
+    let pop = |stack: &mut Vec<Vec<u8>>| stack.pop().ok_or_else(|| anyhow!("empty stack"));
+    let output = |stack: &mut Vec<Vec<u8>>| -> Result<&ElementsTxOut> {
+        let at = pop(stack)?;
+        let at = match at.as_slice() {
+            [] => 0,
+            [n] if *n < 0x80 => *n as usize,
+            _ => bail!("output index {} out of range", at.to_lower_hex_string()),
+        };
+        tx.output
+            .get(at)
+            .ok_or_else(|| anyhow!("the tx has no output {}", at))
+    };
+    for instruction in script.instructions() {
+        match instruction? {
+            Instruction::PushBytes(bytes) => stack.push(bytes.as_bytes().to_vec()),
+            Instruction::Op(op) => match op.to_u8() {
+                0x4f | 0x51..=0x60 => stack.push(script_num(op.to_u8() as i64 - 0x50)),
+                code if code == OP_EQUALVERIFY.to_u8() || code == OP_EQUAL.to_u8() => {
+                    let (a, b) = (pop(&mut stack)?, pop(&mut stack)?);
+                    if code == OP_EQUAL.to_u8() {
+                        stack.push(script_num((a == b) as i64));
+                    } else if a != b {
+                        bail!(
+                            "{} is not {} ({})",
+                            b.to_lower_hex_string(),
+                            a.to_lower_hex_string(),
+                            script.to_asm_string()
+                        );
+                    }
+                }
+                OP_INSPECTNUMINPUTS => stack.push(script_num(tx.input.len() as i64)),
+                OP_INSPECTNUMOUTPUTS => stack.push(script_num(tx.output.len() as i64)),
+                OP_PUSHCURRENTINPUTINDEX => stack.push(script_num(index as i64)),
+                OP_INSPECTINPUTSEQUENCE => {
+                    pop(&mut stack)?;
+                    stack.push(
+                        tx.input[index]
+                            .sequence
+                            .to_consensus_u32()
+                            .to_le_bytes()
+                            .to_vec(),
+                    );
+                }
+                OP_INSPECTOUTPUTSCRIPTPUBKEY => {
+                    let (version, program) = script_commitment(&output(&mut stack)?.script_pubkey);
+                    stack.push(program);
+                    stack.push(script_num(version));
+                }
+                OP_INSPECTOUTPUTASSET => {
+                    let asset = output(&mut stack)?.asset;
+                    stack.push(asset.0.to_vec());
+                    stack.push(vec![EXPLICIT]);
+                }
+                OP_INSPECTOUTPUTVALUE => {
+                    let value = output(&mut stack)?.value;
+                    stack.push(value.to_sat().to_le_bytes().to_vec());
+                    stack.push(vec![EXPLICIT]);
+                }
+                _ => bail!("{} isn't an opcode the pool's leaves use", op),
+            },
+        }
+    }
+    if stack != [script_num(1)] {
+        bail!("the leaf doesn't accept the tx");
+    }
+    Ok(())
+}
+
+// The covenant on Elements: every leaf checks the spending tx's outputs with the introspection
+// opcodes, there is nothing to sign. A template is identified by the hash of its leaf script, the
+// leaves are kept as they are made so the tree can be built from the hashes
+pub struct IntrospectionBackend {
+    pub asset: AssetId,
+    leaves: Mutex<HashMap<[u8; 32], ScriptBuf>>,
+}
+
+impl IntrospectionBackend {
+    pub fn new(asset: AssetId) -> Self {
+        Self {
+            asset,
+            leaves: Mutex::default(),
+        }
+    }
+}
+
+impl CovenantBackend for IntrospectionBackend {
+    fn name(&self) -> &'static str {
+        INTROSPECTION_BACKEND_NAME
+    }
+
+    fn template_hash(&self, outputs: &[TxOut], sequence: Sequence) -> [u8; 32] {
+        let script = introspection_script(self.asset, outputs, sequence);
+        let hash = sha256::Hash::hash(script.as_bytes()).to_byte_array();
+        self.leaves
+            .lock()
+            .expect("leaf cache poisoned")
+            .insert(hash, script);
+        hash
+    }
+
+    // a hash this backend never made has no leaf, it gets one nobody can spend
+    fn leaf_script(&self, template_hash: [u8; 32]) -> ScriptBuf {
+        self.leaves
+            .lock()
+            .expect("leaf cache poisoned")
+            .get(&template_hash)
+            .cloned()
+            .unwrap_or_else(|| {
+                Builder::new()
+                    .push_opcode(OP_RETURN)
+                    .push_slice(template_hash)
+                    .into_script()
+            })
+    }
+
+    fn finalize(&self, _spend: TemplateSpend) -> Result<Transaction> {
+        bail!("the spends of an Elements pool are Elements txs, `elements unwind` writes them")
+    }
+}
+
+// `spend` as an Elements tx spending `previous_output`: its outputs in `asset`, the rest of the node
+// as the fee output, and the leaf with its Elements control block as the witness
+pub fn elements_spend(
+    spend: &TemplateSpend,
+    backend: &IntrospectionBackend,
+    previous_output: OutPoint,
+) -> Result<ElementsTransaction> {
+    let paid: Amount = spend.tx.output.iter().map(|output| output.value).sum();
+    let fee = spend
+        .prevout
+        .value
+        .checked_sub(paid)
+        .ok_or_else(|| anyhow!("the template pays more than the node holds"))?;
+    let mut output: Vec<ElementsTxOut> = spend
+        .tx
+        .output
+        .iter()
+        .map(|output| ElementsTxOut {
+            asset: backend.asset,
+            value: output.value,
+            script_pubkey: output.script_pubkey.clone(),
+        })
+        .collect();
+    output.push(ElementsTxOut {
+        asset: backend.asset,
+        value: fee,
+        script_pubkey: ScriptBuf::new(),
+    });
+    let leaf_script = backend.leaf_script(spend.template_hash);
+    let control_block = ElementsTaproot::from_spend_info(&spend.spend_info)?
+        .control_block(&leaf_script, spend.leaf_version)
+        .ok_or_else(|| {
+            anyhow!(
+                "the node has no leaf for template {}",
+                spend.template_hash.to_lower_hex_string()
+            )
+        })?;
+    let tx = ElementsTransaction {
+        version: spend.tx.version.0,
+        lock_time: spend.tx.lock_time.to_consensus_u32(),
+        input: vec![ElementsTxIn {
+            previous_output,
+            sequence: spend.tx.input[0].sequence,
+            witness: Witness::from_slice(&[leaf_script.as_bytes(), &control_block]),
+        }],
+        output,
+    };
+    // what the chain will run, before anything goes out
+    check_introspection(&leaf_script, &tx, 0)?;
+    Ok(tx)
+}
+
+// The planned unwind of a pool funded at `funding` as Elements txs, each spending the node the one
+// before it left. Nothing has to be on chain yet: the leaves don't commit to the outpoints
+pub fn elements_unwind(
+    pool: &LoadedPool,
+    backend: &IntrospectionBackend,
+    funding: OutPoint,
+) -> Result<Vec<ElementsTransaction>> {
+    let mut previous_output = funding;
+    let mut txs = Vec::new();
+    for spender in pool.config.spenders()? {
+        let users = pool.config.unwind_node(spender)?;
+        let node = pool.tree.node(&users)?;
+        let prevout = TxOut {
+            value: node.amount,
+            script_pubkey: node.address(&pool.config).script_pubkey(),
+        };
+        let exit = node_exit(
+            &pool.tree,
+            &pool.config,
+            backend,
+            &pool.addresses,
+            &pool.anchor_addr,
+            &users,
+            spender,
+        )?;
+        // the exit pool pays both its users, nothing is left to spend
+        let next = if users.is_exit() {
+            None
+        } else {
+            let next = pool.tree.node(&users.without(spender)?)?;
+            Some(next.address(&pool.config).script_pubkey())
+        };
+        let spend = exit.template_spend(previous_output, prevout, &pool.config);
+        let tx = elements_spend(&spend, backend, previous_output)?;
+        if let Some(next) = next {
+            let vout = tx
+                .output
+                .iter()
+                .position(|output| output.script_pubkey == next)
+                .ok_or_else(|| anyhow!("user {}'s exit doesn't pay the next node", spender))?;
+            previous_output = OutPoint::new(tx.txid(), vout as u32);
+        }
+        info!("user {} leaves in {} \n", spender, tx.txid());
+        txs.push(tx);
+    }
+    Ok(txs)
+}
+
+// The manifest of the pool paying the chain's `addresses`, in tree order. The manifest keeps the
+// bitcoin regtest form of every address, they are the same scripts
+pub fn elements_pool(addresses: &[String]) -> Result<PoolManifest> {
+    let config = NetworkConfig::new(NetworkProfile::ElementsRegtest);
+    let params = ELEMENTS_REGTEST;
+    let addresses = addresses
+        .iter()
+        .map(|address| {
+            let script = elements_script(address, &params)?;
+            Ok(Address::from_script(&script, config.network)?)
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let backend = IntrospectionBackend::new(AssetId::from_env(&params)?);
+    let anchor_addr =
+        Address::from_str(&config.fee_anchor_addr)?.require_network(config.network)?;
+    let tree = build_pools(&addresses, &anchor_addr, &config, &backend)?;
+    let root = tree.node(&NodePath::root())?.address(&config);
+    Ok(PoolManifest::new(
+        &config,
+        &backend,
+        &anchor_addr,
+        &addresses,
+        &root,
+    ))
+}
+
+// every tx of the unwind as hex, one file per level in order
+pub fn write_elements_unwind(txs: &[ElementsTransaction], dir: &Path) -> Result<()> {
+    fs::create_dir_all(dir)?;
+    for (level, tx) in txs.iter().enumerate() {
+        fs::write(dir.join(format!("level_{}.hex", level)), tx.raw_hex())?;
+    }
+    Ok(())
+}
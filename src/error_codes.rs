@@ -0,0 +1,173 @@
+use std::fmt;
+
+use bitcoincore_rpc::jsonrpc;
+use serde::{Serialize, Serializer};
+
+use crate::{addresses::AddressErrors, broadcast::BroadcastError, config::ConfigErrors};
+
+// What went wrong as a code scripts and frontends can branch on, instead of matching the message.
+// The codes are stable: a new kind of failure gets a new code, an existing one is never renamed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ErrorCode {
+    // the config breaks an invariant of the amounts, fees or anchors, see ConfigErrors
+    ConfigInvalid,
+    // an address the pool would pay is wrong for it, see AddressErrors
+    AddressInvalid,
+    // the command needs a funded pool
+    PoolNotFunded,
+    // the pool's lifecycle doesn't allow this now, e.g. funding it twice or a withdrawal out of order
+    InvalidState,
+    // a rebuilt tree, template or committed script differs from what the manifest or tx says
+    TemplateMismatch,
+    // the node, esplora or another http endpoint could not be reached
+    BackendUnreachable,
+    // the node refused a tx, see BroadcastError
+    BroadcastRejected,
+    // `serve` is not taking registrations, not yet or not any more
+    RegistrationClosed,
+    // a registration that doesn't check out: its signature, address or xpub, or one already taken
+    RegistrationInvalid,
+    RateLimited,
+    // nothing there (yet), e.g. the manifest of a pool that isn't built
+    NotFound,
+    // json that doesn't parse or isn't what it should be
+    InvalidInput,
+    Io,
+    // anything without a code of its own
+    Internal,
+}
+
+impl ErrorCode {
+    pub const ALL: [ErrorCode; 14] = [
+        Self::ConfigInvalid,
+        Self::AddressInvalid,
+        Self::PoolNotFunded,
+        Self::InvalidState,
+        Self::TemplateMismatch,
+        Self::BackendUnreachable,
+        Self::BroadcastRejected,
+        Self::RegistrationClosed,
+        Self::RegistrationInvalid,
+        Self::RateLimited,
+        Self::NotFound,
+        Self::InvalidInput,
+        Self::Io,
+        Self::Internal,
+    ];
+
+    // the code as printed and serialized, the one place its string is spelled out
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::ConfigInvalid => "E_CONFIG_INVALID",
+            Self::AddressInvalid => "E_ADDRESS_INVALID",
+            Self::PoolNotFunded => "E_POOL_NOT_FUNDED",
+            Self::InvalidState => "E_INVALID_STATE",
+            Self::TemplateMismatch => "E_TEMPLATE_MISMATCH",
+            Self::BackendUnreachable => "E_BACKEND_UNREACHABLE",
+            Self::BroadcastRejected => "E_BROADCAST_REJECTED",
+            Self::RegistrationClosed => "E_REGISTRATION_CLOSED",
+            Self::RegistrationInvalid => "E_REGISTRATION_INVALID",
+            Self::RateLimited => "E_RATE_LIMITED",
+            Self::NotFound => "E_NOT_FOUND",
+            Self::InvalidInput => "E_INVALID_INPUT",
+            Self::Io => "E_IO",
+            Self::Internal => "E_INTERNAL",
+        }
+    }
+}
+
+impl Serialize for ErrorCode {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+// An error that says its code, for failures no typed error stands for
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CodedError {
+    pub code: ErrorCode,
+    pub message: String,
+}
+
+impl fmt::Display for CodedError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for CodedError {}
+
+// `return Err(coded(ErrorCode::PoolNotFunded, ...))` in place of bail! for a failure with a code
+pub fn coded(code: ErrorCode, message: impl fmt::Display) -> anyhow::Error {
+    CodedError {
+        code,
+        message: message.to_string(),
+    }
+    .into()
+}
+
+// The code of the first error in the chain that has one, outermost first, E_INTERNAL if none does
+pub fn error_code(error: &anyhow::Error) -> ErrorCode {
+    error
+        .chain()
+        .find_map(|cause| {
+            if let Some(coded) = cause.downcast_ref::<CodedError>() {
+                return Some(coded.code);
+            }
+            if cause.is::<ConfigErrors>() {
+                return Some(ErrorCode::ConfigInvalid);
+            }
+            if cause.is::<AddressErrors>() {
+                return Some(ErrorCode::AddressInvalid);
+            }
+            if cause.is::<BroadcastError>() {
+                return Some(ErrorCode::BroadcastRejected);
+            }
+            if let Some(rpc) = cause.downcast_ref::<bitcoincore_rpc::Error>() {
+                return match rpc {
+                    bitcoincore_rpc::Error::JsonRpc(jsonrpc::Error::Transport(_))
+                    | bitcoincore_rpc::Error::Io(_) => Some(ErrorCode::BackendUnreachable),
+                    _ => None,
+                };
+            }
+            if let Some(http) = cause.downcast_ref::<reqwest::Error>() {
+                return (http.is_connect() || http.is_timeout())
+                    .then_some(ErrorCode::BackendUnreachable);
+            }
+            if cause.is::<serde_json::Error>() {
+                return Some(ErrorCode::InvalidInput);
+            }
+            if let Some(io) = cause.downcast_ref::<std::io::Error>() {
+                return Some(match io.kind() {
+                    std::io::ErrorKind::ConnectionRefused
+                    | std::io::ErrorKind::ConnectionReset
+                    | std::io::ErrorKind::TimedOut => ErrorCode::BackendUnreachable,
+                    _ => ErrorCode::Io,
+                });
+            }
+            None
+        })
+        .unwrap_or(ErrorCode::Internal)
+}
+
+// what --json-errors prints and `serve` answers with
+#[derive(Debug, Clone, Serialize)]
+pub struct ErrorReport {
+    pub code: ErrorCode,
+    pub error: String,
+}
+
+impl ErrorReport {
+    pub fn new(error: &anyhow::Error) -> Self {
+        Self {
+            code: error_code(error),
+            error: format!("{:#}", error),
+        }
+    }
+}
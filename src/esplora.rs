@@ -0,0 +1,222 @@
+use std::time::Duration;
+
+use anyhow::{bail, Result};
+use bitcoin::{
+    consensus::encode::{deserialize_hex, serialize_hex},
+    Address, Amount, BlockHash, Transaction, Txid,
+};
+use reqwest::StatusCode;
+use serde::Deserialize;
+use tracing::{info, warn};
+
+use crate::{
+    config::{ConfirmationStage, ConfirmationTargets, NetworkConfig},
+    proxy::http_client,
+};
+
+const POLL_INTERVAL: Duration = Duration::from_secs(30);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(20);
+
+// GET /tx/:txid/status
+#[derive(Debug, Clone, Deserialize)]
+pub struct TxStatus {
+    pub confirmed: bool,
+    pub block_height: Option<u32>,
+    pub block_hash: Option<BlockHash>,
+}
+
+// GET /address/:address/utxo, mempool outputs included
+#[derive(Debug, Clone, Deserialize)]
+pub struct AddressUtxo {
+    pub txid: Txid,
+    pub vout: u32,
+    #[serde(with = "bitcoin::amount::serde::as_sat")]
+    pub value: Amount,
+    pub status: TxStatus,
+}
+
+// Minimal Esplora REST client (blockstream.info, mempool.space or a self hosted electrs),
+// e.g. https://mempool.space/signet/api
+#[derive(Debug, Clone)]
+pub struct Esplora {
+    url: String,
+    client: reqwest::Client,
+}
+
+impl Esplora {
+    // through POOL_PROXY when it is set, see proxy.rs
+    pub fn new(url: &str) -> Result<Self> {
+        Ok(Self {
+            url: url.trim_end_matches('/').to_string(),
+            client: http_client(url)?,
+        })
+    }
+
+    async fn get(&self, path: &str) -> Result<Option<String>> {
+        let response = self
+            .client
+            .get(format!("{}{}", self.url, path))
+            .timeout(REQUEST_TIMEOUT)
+            .send()
+            .await?;
+        match response.status() {
+            // esplora answers 404 for txs it hasn't seen (yet)
+            StatusCode::NOT_FOUND => Ok(None),
+            status if status.is_success() => Ok(Some(response.text().await?)),
+            status => bail!("esplora {} answered {}", path, status),
+        }
+    }
+
+    // None until the tx has reached the esplora instance
+    pub async fn tx_status(&self, txid: Txid) -> Result<Option<TxStatus>> {
+        match self.get(&format!("/tx/{}/status", txid)).await? {
+            Some(body) => Ok(Some(serde_json::from_str(&body)?)),
+            None => Ok(None),
+        }
+    }
+
+    // GET /tx/:txid/hex, None until the tx has reached the esplora instance
+    pub async fn tx(&self, txid: Txid) -> Result<Option<Transaction>> {
+        match self.get(&format!("/tx/{}/hex", txid)).await? {
+            Some(body) => Ok(Some(deserialize_hex(body.trim())?)),
+            None => Ok(None),
+        }
+    }
+
+    pub async fn tip_height(&self) -> Result<u32> {
+        match self.get("/blocks/tip/height").await? {
+            Some(body) => Ok(body.trim().parse()?),
+            None => bail!("esplora has no chain tip"),
+        }
+    }
+
+    pub async fn address_utxos(&self, address: &Address) -> Result<Vec<AddressUtxo>> {
+        match self.get(&format!("/address/{}/utxo", address)).await? {
+            Some(body) => Ok(serde_json::from_str(&body)?),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    // POST /tx, the error is the node's reject reason as esplora passes it on
+    pub async fn broadcast(&self, tx: &Transaction) -> Result<Txid> {
+        let response = self
+            .client
+            .post(format!("{}/tx", self.url))
+            .timeout(REQUEST_TIMEOUT)
+            .body(serialize_hex(tx))
+            .send()
+            .await?;
+        let status = response.status();
+        let body = response.text().await?;
+        if !status.is_success() {
+            bail!("esplora rejected {}: {}", tx.compute_txid(), body.trim());
+        }
+        Ok(body.trim().parse()?)
+    }
+
+    pub async fn block_hash(&self, height: u32) -> Result<Option<BlockHash>> {
+        match self.get(&format!("/block-height/{}", height)).await? {
+            Some(body) => Ok(Some(body.trim().parse()?)),
+            None => Ok(None),
+        }
+    }
+}
+
+// a tx that reached its target, and the block it was in at that point
+#[derive(Debug, Clone)]
+struct ConfirmedTx {
+    txid: Txid,
+    stage: ConfirmationStage,
+    height: u32,
+    block_hash: BlockHash,
+}
+
+// Waits for every pool tx to be as deep as its stage asks before the pool moves on, and checks the txs
+// it already moved on from are still in the same blocks, so a shallow reorg is caught instead of
+// building on a parent that is back in the mempool (or gone).
+pub struct ConfirmationTracker {
+    esplora: Esplora,
+    targets: ConfirmationTargets,
+    confirmed: Vec<ConfirmedTx>,
+}
+
+impl ConfirmationTracker {
+    // only for networks without a background miner, regtest confirms everything itself
+    pub fn from_config(config: &NetworkConfig) -> Result<Option<Self>> {
+        if config.block_interval.is_some() {
+            return Ok(None);
+        }
+        let Some(url) = config.esplora_url.as_ref() else {
+            return Ok(None);
+        };
+        info!(
+            "tracking confirmations with {}, needing {} for the funding, {} per pool spend and {} for the payout \n",
+            url,
+            config.confirmations.get(ConfirmationStage::Funding),
+            config.confirmations.get(ConfirmationStage::PoolSpend),
+            config.confirmations.get(ConfirmationStage::Payout)
+        );
+        Ok(Some(Self {
+            esplora: Esplora::new(url)?,
+            targets: config.confirmations,
+            confirmed: Vec::new(),
+        }))
+    }
+
+    pub async fn wait(&mut self, txid: Txid, stage: ConfirmationStage) -> Result<()> {
+        let mut confirmed = self.wait_for_depth(txid, stage).await?;
+        while let Some(i) = self.reorged().await? {
+            let ancestor = &self.confirmed[i];
+            warn!(
+                "reorg: {} is no longer in block {} at height {}, waiting for it again",
+                ancestor.txid, ancestor.block_hash, ancestor.height
+            );
+            self.confirmed[i] = self.wait_for_depth(ancestor.txid, ancestor.stage).await?;
+            confirmed = self.wait_for_depth(txid, stage).await?;
+        }
+        info!(
+            "{} is {} blocks deep (block {}) \n",
+            txid,
+            self.targets.get(stage),
+            confirmed.height
+        );
+        self.confirmed.push(confirmed);
+        Ok(())
+    }
+
+    async fn wait_for_depth(&self, txid: Txid, stage: ConfirmationStage) -> Result<ConfirmedTx> {
+        let target = self.targets.get(stage);
+        loop {
+            if let Some(TxStatus {
+                confirmed: true,
+                block_height: Some(height),
+                block_hash: Some(block_hash),
+            }) = self.esplora.tx_status(txid).await?
+            {
+                let depth = self.esplora.tip_height().await?.saturating_sub(height) + 1;
+                if depth >= target {
+                    return Ok(ConfirmedTx {
+                        txid,
+                        stage,
+                        height,
+                        block_hash,
+                    });
+                }
+                info!("{} has {} of {} confirmations", txid, depth, target);
+            } else {
+                info!("{} not confirmed yet", txid);
+            }
+            tokio::time::sleep(POLL_INTERVAL).await;
+        }
+    }
+
+    // the first tx we moved on from whose block is no longer in the best chain
+    async fn reorged(&self) -> Result<Option<usize>> {
+        for (i, tx) in self.confirmed.iter().enumerate() {
+            if self.esplora.block_hash(tx.height).await? != Some(tx.block_hash) {
+                return Ok(Some(i));
+            }
+        }
+        Ok(None)
+    }
+}
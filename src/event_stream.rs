@@ -0,0 +1,128 @@
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+};
+
+use tokio::sync::broadcast;
+use tracing::warn;
+
+use crate::{
+    ids::PoolId,
+    next_step::NextStep,
+    webhooks::{PoolEvent, WebhookPayload},
+};
+
+// events kept for clients that connect late or reconnect with Last-Event-ID
+const HISTORY: usize = 256;
+// events a slow client may fall behind by before it misses some
+const CHANNEL_CAPACITY: usize = 64;
+
+// One event as it goes out on the stream, numbered from 1 in the order it was published
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StreamedEvent {
+    pub id: u64,
+    // the payload's "event" field, e.g. user_registered
+    pub name: String,
+    // the same json a webhook gets
+    pub data: String,
+}
+
+impl StreamedEvent {
+    // as a server-sent event
+    pub fn to_sse(&self) -> String {
+        format!(
+            "id: {}\nevent: {}\ndata: {}\n\n",
+            self.id, self.name, self.data
+        )
+    }
+}
+
+struct History {
+    next_id: u64,
+    events: VecDeque<StreamedEvent>,
+}
+
+// Pool events for clients keeping a connection open, serve's GET /events. Every subscriber gets
+// the events since the one it last saw and then each one as it is published.
+#[derive(Clone)]
+pub struct EventStream {
+    sender: broadcast::Sender<StreamedEvent>,
+    history: Arc<Mutex<History>>,
+}
+
+impl Default for EventStream {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl EventStream {
+    pub fn new() -> Self {
+        Self {
+            sender: broadcast::channel(CHANNEL_CAPACITY).0,
+            history: Arc::new(Mutex::new(History {
+                next_id: 1,
+                events: VecDeque::new(),
+            })),
+        }
+    }
+
+    pub fn publish(
+        &self,
+        pool_id: Option<&PoolId>,
+        root_address: &str,
+        event: &PoolEvent,
+        next_steps: &[NextStep],
+    ) {
+        let payload = WebhookPayload {
+            pool_id,
+            root_address,
+            event,
+            next_steps,
+        };
+        let value = match serde_json::to_value(&payload) {
+            Ok(value) => value,
+            Err(e) => {
+                warn!("event stream payload for {:?}: {}", event, e);
+                return;
+            }
+        };
+        let name = value["event"].as_str().unwrap_or("pool_event").to_string();
+        let Ok(mut history) = self.history.lock() else {
+            warn!("event stream history poisoned, {} not streamed", name);
+            return;
+        };
+        let streamed = StreamedEvent {
+            id: history.next_id,
+            name,
+            data: value.to_string(),
+        };
+        history.next_id += 1;
+        if history.events.len() == HISTORY {
+            history.events.pop_front();
+        }
+        history.events.push_back(streamed.clone());
+        // nobody listening is fine, the history has it
+        let _ = self.sender.send(streamed);
+    }
+
+    // The kept events after `last_event_id` (all of them without one) and the receiver of every
+    // event published from now on, taken together so none is missed or sent twice
+    pub fn subscribe(
+        &self,
+        last_event_id: Option<u64>,
+    ) -> (Vec<StreamedEvent>, broadcast::Receiver<StreamedEvent>) {
+        let history = match self.history.lock() {
+            Ok(history) => history,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        let after = last_event_id.unwrap_or(0);
+        let missed = history
+            .events
+            .iter()
+            .filter(|event| event.id > after)
+            .cloned()
+            .collect();
+        (missed, self.sender.subscribe())
+    }
+}
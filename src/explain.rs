@@ -0,0 +1,530 @@
+use std::{
+    collections::HashMap,
+    fmt::{self, Write},
+};
+
+use anyhow::Result;
+use bitcoin::{hex::DisplayHex, Address, Amount, ScriptBuf, Transaction, Txid};
+use serde::Serialize;
+
+use crate::{
+    config::{AMOUNT_PER_USER, POOL_USERS},
+    ctv_scripts::ctv_hash,
+    ids::{NodePath, UserIndex},
+    manifest::LoadedPool,
+    payouts::user_scripts,
+    pools::{close_all_exit, node_exit},
+};
+
+// which of the pool's spend paths a tx takes
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum PoolSpend {
+    // pays the pool's root address
+    Funding,
+    // `spender` leaves `node`, in the exit pool the one leaf pays both users
+    Exit {
+        node: NodePath,
+        spender: UserIndex,
+    },
+    // the root's close-all leaf pays every user at once
+    CloseAll,
+    // every node of the same size has the same sweep, only the control block in the witness tells which one
+    Recovery {
+        users: usize,
+        node: Option<NodePath>,
+        timeout: u16,
+    },
+    // none of the pool's leaves
+    Unknown,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "to", rename_all = "snake_case")]
+pub enum Recipient {
+    User { user: UserIndex },
+    // one of the other addresses a user split their exit to
+    Split { user: UserIndex },
+    Pool { node: NodePath },
+    Anchor,
+    Recovery,
+    Other,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ExplainedOutput {
+    pub vout: usize,
+    pub amount: Amount,
+    pub address: Option<String>,
+    #[serde(flatten)]
+    pub recipient: Recipient,
+}
+
+// the tapleaf a pool spend goes through and the template it locks the node to
+#[derive(Debug, Clone, Serialize)]
+pub struct SpentLeaf {
+    pub script: String,
+    pub template_hash: String,
+    // merkle path length from the leaf to the node's taptree root
+    pub depth: usize,
+    // the ctv hash of the tx as given, the leaf only accepts it if this equals `template_hash`
+    pub tx_template_hash: String,
+    // None while the tx is an unwitnessed template
+    pub witness_matches: Option<bool>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Explanation {
+    pub txid: Txid,
+    // how deep the node has it, 0 in the mempool. None for a raw tx, nothing was looked up
+    pub confirmations: Option<u32>,
+    pub version: i32,
+    pub sequence: Option<u32>,
+    pub spend: PoolSpend,
+    pub leaf: Option<SpentLeaf>,
+    // only known for spends of a pool node
+    pub input_amount: Option<Amount>,
+    pub outputs: Vec<ExplainedOutput>,
+    pub fee: Option<Amount>,
+    pub vsize: usize,
+}
+
+// Work out, offline, what `tx` does in the pool of the manifest: which leaf it spends, what it commits to
+// and where every output goes.
+pub fn explain_tx(pool: &LoadedPool, tx: &Transaction) -> Result<Explanation> {
+    let config = &pool.config;
+    let nodes: HashMap<ScriptBuf, NodePath> = pool
+        .tree
+        .iter_nodes()
+        .map(|(users, node)| {
+            (
+                ScriptBuf::new_p2tr_tweaked(node.spend_info.output_key()),
+                users.clone(),
+            )
+        })
+        .collect();
+
+    let outputs: Vec<ExplainedOutput> = tx
+        .output
+        .iter()
+        .enumerate()
+        .map(|(vout, output)| {
+            let spk = &output.script_pubkey;
+            let recipient = if let Some(node) = nodes.get(spk) {
+                Recipient::Pool { node: node.clone() }
+            } else if *spk == pool.anchor_addr.script_pubkey() {
+                Recipient::Anchor
+            } else if let Some(user) = pool
+                .addresses
+                .iter()
+                .position(|address| address.script_pubkey() == *spk)
+            {
+                Recipient::User {
+                    user: UserIndex::new(user)?,
+                }
+            } else if let Some(user) = pool
+                .addresses
+                .iter()
+                .position(|address| user_scripts(address, config)[1..].contains(spk))
+            {
+                Recipient::Split {
+                    user: UserIndex::new(user)?,
+                }
+            } else if config
+                .recovery
+                .as_ref()
+                .is_some_and(|recovery| recovery.address.script_pubkey() == *spk)
+            {
+                Recipient::Recovery
+            } else {
+                Recipient::Other
+            };
+            Ok(ExplainedOutput {
+                vout,
+                amount: output.value,
+                address: Address::from_script(spk, config.network)
+                    .ok()
+                    .map(|address| address.to_string()),
+                recipient,
+            })
+        })
+        .collect::<Result<_>>()?;
+
+    let (spend, leaf) = spent_leaf(pool, tx)?;
+    let input_amount = match &spend {
+        PoolSpend::Exit { node, .. } => pool.tree.node(node).ok().map(|node| node.amount),
+        PoolSpend::CloseAll => Some(pool.tree.root()?.amount),
+        PoolSpend::Recovery { users, node, .. } => node
+            .as_ref()
+            .and_then(|node| pool.tree.node(node).ok())
+            .map(|node| node.amount)
+            .or(Some(AMOUNT_PER_USER * *users as u64)),
+        _ => None,
+    };
+    let spend = match spend {
+        PoolSpend::Unknown
+            if outputs.iter().any(
+                |output| matches!(&output.recipient, Recipient::Pool { node } if node.is_root()),
+            ) =>
+        {
+            PoolSpend::Funding
+        }
+        spend => spend,
+    };
+    let output_amount: Amount = tx.output.iter().map(|output| output.value).sum();
+
+    Ok(Explanation {
+        txid: tx.compute_txid(),
+        confirmations: None,
+        version: tx.version.0,
+        sequence: tx.input.first().map(|input| input.sequence.0),
+        spend,
+        leaf,
+        input_amount,
+        fee: input_amount.and_then(|amount| amount.checked_sub(output_amount)),
+        outputs,
+        vsize: tx.vsize(),
+    })
+}
+
+// Templates are identified by their outputs, every (node, spender) pair pays a different next pool
+fn spent_leaf(pool: &LoadedPool, tx: &Transaction) -> Result<(PoolSpend, Option<SpentLeaf>)> {
+    let config = &pool.config;
+    let Some(input) = tx.input.first() else {
+        return Ok((PoolSpend::Unknown, None));
+    };
+    let tx_template_hash = ctv_hash(tx, 0);
+    let witness_script = input.witness.taproot_leaf_script().map(|leaf| leaf.script);
+    let witness_control_block = input.witness.taproot_control_block();
+
+    let leaf = |script: ScriptBuf, template_hash: [u8; 32], depth: usize| SpentLeaf {
+        script: script.to_asm_string(),
+        template_hash: template_hash.to_lower_hex_string(),
+        depth,
+        tx_template_hash: tx_template_hash.to_lower_hex_string(),
+        witness_matches: witness_script.map(|witnessed| *witnessed == *script),
+    };
+
+    for node in pool.tree.node_paths() {
+        // the exit pool has one template paying both users
+        let spenders: Vec<_> = if node.is_exit() {
+            node.user_indices().take(1).collect()
+        } else {
+            node.user_indices().collect()
+        };
+        for spender in spenders {
+            let exit = node_exit(
+                &pool.tree,
+                config,
+                pool.backend.as_ref(),
+                &pool.addresses,
+                &pool.anchor_addr,
+                &node,
+                spender,
+            )?;
+            if exit.outputs != tx.output {
+                continue;
+            }
+            let script = pool.backend.leaf_script(exit.template_hash);
+            let depth = exit
+                .spend_info
+                .control_block(&(script.clone(), config.leaf_version))
+                .map(|control_block| control_block.merkle_branch.len())
+                .unwrap_or_default();
+            return Ok((
+                PoolSpend::Exit { node, spender },
+                Some(leaf(script, exit.template_hash, depth)),
+            ));
+        }
+    }
+
+    if let Ok(exit) = close_all_exit(
+        &pool.tree,
+        config,
+        pool.backend.as_ref(),
+        &pool.addresses,
+        &pool.anchor_addr,
+    ) {
+        if exit.outputs == tx.output {
+            let script = pool.backend.leaf_script(exit.template_hash);
+            let depth = exit
+                .spend_info
+                .control_block(&(script.clone(), config.leaf_version))
+                .map(|control_block| control_block.merkle_branch.len())
+                .unwrap_or_default();
+            return Ok((
+                PoolSpend::CloseAll,
+                Some(leaf(script, exit.template_hash, depth)),
+            ));
+        }
+    }
+
+    let Some(recovery) = &config.recovery else {
+        return Ok((PoolSpend::Unknown, None));
+    };
+    // nodes of one size hold the same amount, unless the payouts are jittered
+    let mut amounts: Vec<Amount> = pool
+        .tree
+        .iter_nodes()
+        .map(|(_, node)| node.amount)
+        .collect();
+    amounts.sort();
+    amounts.dedup();
+    for amount in amounts {
+        let outputs = recovery.outputs(amount, &pool.anchor_addr, config);
+        if outputs != tx.output {
+            continue;
+        }
+        let template_hash = recovery.template_hash(&outputs, config);
+        let script = recovery.leaf_script(template_hash);
+        let candidates: Vec<NodePath> = pool
+            .tree
+            .iter_nodes()
+            .filter(|(_, node)| node.amount == amount)
+            .map(|(users, _)| users.clone())
+            .collect();
+        let mut users = candidates.first().map(NodePath::len).unwrap_or_default();
+        let mut node = None;
+        let mut depth = 0;
+        for candidate in candidates {
+            let Some(control_block) = pool
+                .tree
+                .spend_info(&candidate)?
+                .control_block(&(script.clone(), config.leaf_version))
+            else {
+                continue;
+            };
+            depth = control_block.merkle_branch.len();
+            if witness_control_block == Some(control_block.serialize().as_slice()) {
+                users = candidate.len();
+                node = Some(candidate);
+                break;
+            }
+        }
+        return Ok((
+            PoolSpend::Recovery {
+                users,
+                node,
+                timeout: recovery.timeout,
+            },
+            Some(leaf(script, template_hash, depth)),
+        ));
+    }
+    Ok((PoolSpend::Unknown, None))
+}
+
+pub fn print_explanation(pool: &LoadedPool, explanation: &Explanation) {
+    print!("{}", render_explanation(pool, explanation));
+}
+
+// The narrative `explain` prints, one paragraph per part of the tx
+pub fn render_explanation(pool: &LoadedPool, explanation: &Explanation) -> String {
+    let mut out = String::new();
+    write_explanation(&mut out, pool, explanation).expect("writing to a String");
+    out
+}
+
+fn write_explanation(
+    out: &mut impl Write,
+    pool: &LoadedPool,
+    explanation: &Explanation,
+) -> fmt::Result {
+    let config = &pool.config;
+    writeln!(out, "Transaction {}", explanation.txid)?;
+    match explanation.confirmations {
+        Some(0) => writeln!(out, "In the mempool, not confirmed yet.")?,
+        Some(confirmations) => writeln!(out, "Confirmed, {} blocks deep.", confirmations)?,
+        None => {}
+    }
+    writeln!(out)?;
+
+    match &explanation.spend {
+        PoolSpend::Funding => writeln!(
+            out,
+            "This is the funding transaction. It pays the root of the pool, the node every user starts in. \
+             From here on nothing needs the coordinator, every move out of the pool is one of the templates \
+             committed to in the tree."
+        )?,
+        PoolSpend::Exit { node, .. } if node.is_exit() => writeln!(
+            out,
+            "This is the last pool ({} users). It has a single leaf that pays both remaining users \
+             and closes the pool.",
+            node
+        )?,
+        PoolSpend::Exit { node, spender } => writeln!(
+            out,
+            "This spends the pool node of users {} through the leaf that lets user {} leave. \
+             User {} gets paid and the {} other users move on to the next, smaller pool.",
+            node,
+            spender,
+            spender,
+            node.len() - 1
+        )?,
+        PoolSpend::CloseAll => writeln!(
+            out,
+            "This spends the root through its close-all leaf: every one of the {} users is paid at once \
+             and the pool is closed without walking down the tree.",
+            POOL_USERS
+        )?,
+        PoolSpend::Recovery {
+            users,
+            node,
+            timeout,
+        } => {
+            match node {
+                Some(node) => write!(out, "This sweeps the pool node of users {}", node)?,
+                None => write!(out, "This sweeps a pool node of {} users", users)?,
+            }
+            writeln!(
+                out,
+                " through its recovery leaf. Nobody unwound the node for {} blocks, so the whole of it \
+                 goes to the recovery address.",
+                timeout
+            )?;
+        }
+        PoolSpend::Unknown => writeln!(
+            out,
+            "This is not one of the pool's templates: its outputs match no leaf of any node in the tree, \
+             so no pool node could be spent by it."
+        )?,
+    }
+
+    if let Some(leaf) = &explanation.leaf {
+        writeln!(out)?;
+        writeln!(
+            out,
+            "The leaf script is `{}`, {} levels below the node's taproot output key (the key path is \
+             unspendable, it uses the NUMS point).",
+            leaf.script, leaf.depth
+        )?;
+        writeln!(
+            out,
+            "It only lets the node be spent by a transaction whose BIP-119 template hash is {}. \
+             That hash commits to the version, locktime, input count, every input's nSequence, the output \
+             count and every output, but not to the input being spent.",
+            leaf.template_hash
+        )?;
+        if leaf.tx_template_hash == leaf.template_hash {
+            writeln!(
+                out,
+                "This transaction hashes to exactly that, so the leaf accepts it."
+            )?;
+        } else {
+            writeln!(
+                out,
+                "This transaction hashes to {} instead, the leaf would reject it (a changed nSequence, \
+                 version or an extra input breaks the template).",
+                leaf.tx_template_hash
+            )?;
+        }
+        if !pool.backend.requires_presigning() {
+            writeln!(
+                out,
+                "OP_NOP4 is OP_CHECKTEMPLATEVERIFY, the opcode BIP-119 redefines."
+            )?;
+        } else {
+            writeln!(
+                out,
+                "The pool uses the {} backend: instead of OP_CTV the leaf checks a signature from a key \
+                 that was deleted after presigning every planned spend.",
+                pool.backend.name()
+            )?;
+        }
+        match leaf.witness_matches {
+            None => writeln!(
+                out,
+                "It has no witness yet, the leaf script and control block are attached when it is broadcast."
+            )?,
+            Some(true) => writeln!(out, "Its witness reveals this leaf and its control block.")?,
+            Some(false) => writeln!(out, "Its witness reveals a different script, it spends some other leaf.")?,
+        }
+        if let Some(sequence) = explanation.sequence {
+            let delay = match &explanation.spend {
+                PoolSpend::Exit { node, .. } => config.unwind_delay_for(node),
+                PoolSpend::CloseAll => config.unwind_delay_for(&NodePath::root()),
+                _ => None,
+            };
+            match (&explanation.spend, delay) {
+                (PoolSpend::Recovery { timeout, .. }, _) => writeln!(
+                    out,
+                    "The input's nSequence ({:#x}) is a {} block relative timelock, the leaf checks it \
+                     with OP_CHECKSEQUENCEVERIFY.",
+                    sequence, timeout
+                )?,
+                (_, Some(delay)) => writeln!(
+                    out,
+                    "The input's nSequence ({:#x}) makes it wait until the node is {} blocks deep.",
+                    sequence, delay
+                )?,
+                _ => writeln!(
+                    out,
+                    "The input's nSequence ({:#x}) is part of the template too.",
+                    sequence
+                )?,
+            }
+        }
+    }
+
+    writeln!(out)?;
+    writeln!(out, "Outputs:")?;
+    for output in &explanation.outputs {
+        let address = output.address.as_deref().unwrap_or("(no address)");
+        let to = match &output.recipient {
+            Recipient::User { user } => format!("user {}'s withdraw address", user),
+            Recipient::Split { user } => format!("an address user {} split their exit to", user),
+            Recipient::Pool { node } if node.is_root() => "the root of the pool".to_string(),
+            Recipient::Pool { node } => format!("the next pool, users {}", node),
+            Recipient::Anchor => {
+                "the pay-to-anchor output, anyone can spend it in a child to bump the fee (CPFP)"
+                    .to_string()
+            }
+            Recipient::Recovery => "the recovery address".to_string(),
+            Recipient::Other => "an address outside the pool".to_string(),
+        };
+        writeln!(
+            out,
+            "  {}: {} to {} ({})",
+            output.vout, output.amount, to, address
+        )?;
+    }
+
+    writeln!(out)?;
+    match (explanation.input_amount, explanation.fee) {
+        (Some(input), Some(fee)) => {
+            writeln!(
+                out,
+                "It spends {} and leaves {} as fee, {:.1} sat/vB at {} vB. The fee was fixed when the pool \
+                 was built and can't be changed without breaking the template.",
+                input,
+                fee,
+                fee.to_sat() as f64 / explanation.vsize as f64,
+                explanation.vsize
+            )?;
+            if explanation
+                .outputs
+                .iter()
+                .any(|output| matches!(output.recipient, Recipient::Anchor))
+            {
+                writeln!(
+                    out,
+                    "If that is too low for the mempool the anchor is how the fee gets raised."
+                )?;
+                if explanation.version == 3 {
+                    writeln!(
+                        out,
+                        "It is a v3 (TRUC) transaction, so it may pay less than the minimum relay fee \
+                         and has to be submitted together with its child (submitpackage)."
+                    )?;
+                }
+            }
+        }
+        _ => {
+            writeln!(
+            out,
+            "The input amount isn't part of the pool, the fee needs the previous outputs ({} vB).",
+            explanation.vsize
+        )?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,218 @@
+use std::{fs, path::Path};
+
+use anyhow::{anyhow, bail, Result};
+use bitcoin::{hex::DisplayHex, Address, Network, ScriptBuf, Sequence, TxOut, Txid};
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use crate::{
+    ids::{NodePath, UserIndex},
+    manifest::{LoadedPool, PoolManifest},
+    pools::{node_exit, NodeExit},
+    verify::{verify_leaf_proof, LeafProof},
+};
+
+// One way out of a pool node. The control block carries the internal key and the sibling hashes,
+// so the leaf can be checked against the node address without knowing the rest of the node.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ExportedLeaf {
+    // None for the exit pool leaf, it pays both users
+    pub spender: Option<UserIndex>,
+    pub template_hash: String,
+    pub leaf_script: ScriptBuf,
+    pub control_block: String,
+    // the same path spelled out, check it with `verify::verify_leaf_proof`
+    pub proof: LeafProof,
+    // the spending input's nSequence, committed to by the template
+    pub sequence: Sequence,
+    pub outputs: Vec<TxOut>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ExportedNode {
+    pub users: NodePath,
+    pub address: String,
+    pub leaves: Vec<ExportedLeaf>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PoolExport {
+    pub network: Network,
+    pub root_address: String,
+    pub funding_txid: Option<Txid>,
+    // version of every pool tx, part of the templates. None in exports from before it was recorded
+    #[serde(default)]
+    pub tx_version: Option<i32>,
+    // the user this export was made for, None for the whole pool
+    pub user: Option<UserIndex>,
+    pub nodes: Vec<ExportedNode>,
+}
+
+fn export_leaf(
+    pool: &LoadedPool,
+    exit: NodeExit,
+    spender: Option<UserIndex>,
+) -> Result<ExportedLeaf> {
+    let leaf_script = pool.backend.leaf_script(exit.template_hash);
+    let control_block = exit
+        .spend_info
+        .control_block(&(leaf_script.clone(), pool.config.leaf_version))
+        .ok_or_else(|| anyhow!("leaf not found in pool {}", exit.users))?;
+
+    let proof = LeafProof::new(&exit.spend_info, &leaf_script, pool.config.leaf_version)?;
+
+    Ok(ExportedLeaf {
+        spender,
+        template_hash: exit.template_hash.to_lower_hex_string(),
+        leaf_script,
+        control_block: control_block.serialize().to_lower_hex_string(),
+        proof,
+        sequence: exit.sequence,
+        outputs: exit.outputs,
+    })
+}
+
+fn export_node(
+    pool: &LoadedPool,
+    users: &NodePath,
+    spenders: &[UserIndex],
+) -> Result<ExportedNode> {
+    let spend_info = pool.tree.spend_info(users)?;
+    let leaves = if users.is_exit() {
+        let exit = node_exit(
+            &pool.tree,
+            &pool.config,
+            pool.backend.as_ref(),
+            &pool.addresses,
+            &pool.anchor_addr,
+            users,
+            UserIndex::new(users.users()[0])?,
+        )?;
+        vec![export_leaf(pool, exit, None)?]
+    } else {
+        spenders
+            .iter()
+            .map(|&spender| {
+                let exit = node_exit(
+                    &pool.tree,
+                    &pool.config,
+                    pool.backend.as_ref(),
+                    &pool.addresses,
+                    &pool.anchor_addr,
+                    users,
+                    spender,
+                )?;
+                export_leaf(pool, exit, Some(spender))
+            })
+            .collect::<Result<Vec<_>>>()?
+    };
+
+    Ok(ExportedNode {
+        users: users.clone(),
+        address: Address::p2tr_tweaked(spend_info.output_key(), pool.config.network).to_string(),
+        leaves,
+    })
+}
+
+// `user` None: the whole tree with every leaf.
+// `user` Some, not `branch_only`: every node the user is in with just their own leaf, so they can leave whatever the others do.
+// `branch_only`: only the path from the root to the user's leaf following the planned unwind (users leave in address order).
+pub fn export_pool(
+    manifest: &PoolManifest,
+    user: Option<UserIndex>,
+    branch_only: bool,
+) -> Result<PoolExport> {
+    export_loaded_pool(&manifest.load_pool()?, manifest, user, branch_only)
+}
+
+// the same from a pool that is already rebuilt, for making many exports of one pool
+pub fn export_loaded_pool(
+    pool: &LoadedPool,
+    manifest: &PoolManifest,
+    user: Option<UserIndex>,
+    branch_only: bool,
+) -> Result<PoolExport> {
+    let nodes = match (user, branch_only) {
+        (None, true) => bail!("--branch-only needs --user"),
+        (None, false) => pool
+            .tree
+            .node_paths()
+            .iter()
+            .map(|users| export_node(pool, users, &users.user_indices().collect::<Vec<_>>()))
+            .collect::<Result<Vec<_>>>()?,
+        (Some(user), false) => pool
+            .tree
+            .node_paths()
+            .iter()
+            .filter(|users| users.contains(user))
+            .map(|users| export_node(pool, users, &[user]))
+            .collect::<Result<Vec<_>>>()?,
+        // the last two users share the final exit
+        (Some(user), true) => pool
+            .config
+            .spenders()?
+            .into_iter()
+            .take_while(|spender| *spender <= user)
+            .map(|spender| export_node(pool, &pool.config.unwind_node(spender)?, &[spender]))
+            .collect::<Result<Vec<_>>>()?,
+    };
+
+    Ok(PoolExport {
+        network: manifest.network,
+        root_address: manifest.root_address.clone(),
+        funding_txid: manifest.funding_txid,
+        tx_version: Some(pool.config.tx_version),
+        user,
+        nodes,
+    })
+}
+
+pub fn write_export(export: &PoolExport, output: Option<&Path>) -> Result<()> {
+    let json = serde_json::to_string_pretty(export)?;
+    match output {
+        Some(path) => {
+            fs::write(path, json)?;
+            info!(
+                "exported {} pool nodes to {} \n",
+                export.nodes.len(),
+                path.display()
+            );
+        }
+        None => println!("{}", json),
+    }
+    Ok(())
+}
+
+// Check every leaf proof in an export against its node address, no manifest or node needed
+pub fn verify_export_file(path: &Path) -> Result<()> {
+    let export: PoolExport = serde_json::from_str(&fs::read_to_string(path)?)?;
+    let mut failed = 0;
+    for node in &export.nodes {
+        let address = node
+            .address
+            .parse::<Address<_>>()?
+            .require_network(export.network)?;
+        for leaf in &node.leaves {
+            let name = match leaf.spender {
+                Some(spender) => format!("user {} leaving {}", spender, node.users),
+                None => format!("exit of {}", node.users),
+            };
+            match verify_leaf_proof(&leaf.leaf_script, &leaf.proof, &address.script_pubkey()) {
+                Ok(()) => info!(
+                    "{}: committed to by {} ({} sibling hashes)",
+                    name,
+                    address,
+                    leaf.proof.siblings.len()
+                ),
+                Err(e) => {
+                    failed += 1;
+                    warn!("{}: {}", name, e);
+                }
+            }
+        }
+    }
+    if failed > 0 {
+        bail!("{} leaf proofs in {} do not verify", failed, path.display());
+    }
+    Ok(())
+}
@@ -0,0 +1,155 @@
+use std::{
+    fmt,
+    str::FromStr,
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Result};
+use bitcoin::{Amount, Network, Txid};
+use bitcoincore_rpc::RpcApi;
+use tracing::info;
+
+use crate::{
+    config::{ConfigProblem, NetworkConfig},
+    proxy::http_client,
+    rpc_helper::AsyncRpc,
+};
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+// a faucet that hasn't paid by then most likely refused without saying so
+const ARRIVAL_TIMEOUT: Duration = Duration::from_secs(600);
+
+// Signet faucet the demo asks for coins when the wallet can't fund the pool, POOL_FAUCET_URL env var.
+// Requests are the form POST of Bitcoin Core's contrib/signet/getcoins.py, which
+// https://signetfaucet.com/claim and its clones answer.
+#[derive(Clone)]
+pub struct Faucet {
+    pub url: String,
+    // POOL_FAUCET_PASSWORD, for faucets that hand out coins to known users only
+    password: Option<String>,
+}
+
+// the password stays out of logs and debug output
+impl fmt::Debug for Faucet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Faucet").field("url", &self.url).finish()
+    }
+}
+
+// the txid of the payment in a faucet's reply, e.g. "Payment of 0.001 BTC sent with txid <txid>"
+pub fn faucet_txid(reply: &str) -> Option<Txid> {
+    reply
+        .split(|c: char| !c.is_ascii_hexdigit())
+        .filter(|word| word.len() == 64)
+        .find_map(|word| Txid::from_str(word).ok())
+}
+
+impl Faucet {
+    pub fn new(url: &str, password: Option<String>) -> Self {
+        Self {
+            url: url.to_string(),
+            password,
+        }
+    }
+
+    // POOL_FAUCET_URL enables it, faucets only hand out signet coins
+    pub fn from_env(network: Network) -> Result<Option<Self>, ConfigProblem> {
+        let url = NetworkConfig::get_env_var("POOL_FAUCET_URL", "");
+        if url.is_empty() {
+            return Ok(None);
+        }
+        if network != Network::Signet {
+            return Err(ConfigProblem::InvalidEnv {
+                var: "POOL_FAUCET_URL".to_string(),
+                reason: format!(
+                    "is set but faucets only fund signet wallets, not {}",
+                    network
+                ),
+            });
+        }
+        let password = NetworkConfig::get_env_var("POOL_FAUCET_PASSWORD", "");
+        Ok(Some(Self::new(
+            &url,
+            (!password.is_empty()).then_some(password),
+        )))
+    }
+
+    // ask for coins to `address`, the txid if the faucet said which
+    pub async fn request(&self, address: &str) -> Result<Option<Txid>> {
+        let mut form = vec![("address", address)];
+        if let Some(password) = &self.password {
+            form.push(("password", password.as_str()));
+        }
+        let response = http_client(&self.url)?
+            .post(&self.url)
+            .form(&form)
+            .timeout(REQUEST_TIMEOUT)
+            .send()
+            .await?;
+        let status = response.status();
+        let reply = response.text().await?;
+        if !status.is_success() {
+            bail!("faucet {} answered {}: {}", self.url, status, reply.trim());
+        }
+        info!("faucet: {} \n", reply.trim());
+        Ok(faucet_txid(&reply))
+    }
+
+    // Top the wallet up to `needed` (unconfirmed coins count, the funding tx can spend them) and
+    // wait until the coins reach it. Does nothing when the wallet has enough already.
+    pub async fn fund_wallet(
+        &self,
+        rpc: &AsyncRpc,
+        network: Network,
+        needed: Amount,
+    ) -> Result<()> {
+        let before = wallet_balance(rpc).await?;
+        if before >= needed {
+            return Ok(());
+        }
+        let address = rpc
+            .run(|c| c.get_new_address(Some("faucet"), None))
+            .await?
+            .require_network(network)?;
+        info!(
+            "wallet has {} of the {} the pool needs, asking {} for coins to {} \n",
+            before, needed, self.url, address
+        );
+        match self.request(&address.to_string()).await? {
+            Some(txid) => info!("waiting for faucet payment {} \n", txid),
+            None => info!("waiting for the faucet payment \n"),
+        }
+
+        let started = Instant::now();
+        loop {
+            let balance = wallet_balance(rpc).await?;
+            if balance >= needed {
+                info!("faucet coins arrived, wallet has {} \n", balance);
+                return Ok(());
+            }
+            if balance > before {
+                bail!(
+                    "the faucet sent {} but the pool needs {} more, send it to {} by hand",
+                    balance - before,
+                    needed - balance,
+                    address
+                );
+            }
+            if started.elapsed() > ARRIVAL_TIMEOUT {
+                bail!(
+                    "nothing from the faucet after {:?}, it may be rate limiting, send {} to {} by hand",
+                    ARRIVAL_TIMEOUT,
+                    needed - balance,
+                    address
+                );
+            }
+            tokio::time::sleep(POLL_INTERVAL).await;
+        }
+    }
+}
+
+// mempool coins included
+async fn wallet_balance(rpc: &AsyncRpc) -> Result<Amount> {
+    rpc.run(|c| c.get_balance(Some(0), None)).await
+}
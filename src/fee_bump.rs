@@ -0,0 +1,367 @@
+use std::{
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{Context, Result};
+use bitcoin::{Amount, Transaction, TxOut, Txid};
+use bitcoincore_rpc::RpcApi;
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use crate::{
+    config::{ConfigProblem, NetworkConfig},
+    mempool::in_mempool,
+    metrics::METRICS,
+    pools::{cpfp_change, send_anchor_child, FeeCoin, CPFP_CHILD_VSIZE},
+    rpc_helper::{check_fee_payer_balance, estimate_fee_rate, fee_for_vsize, AsyncRpc},
+};
+
+// most a single anchor child pays when POOL_BUMP_MAX_FEE_SATS isn't set
+pub const DEFAULT_BUMP_MAX_FEE: Amount = Amount::from_sat(50_000);
+pub const DEFAULT_BUMP_JOURNAL: &str = "pool_journal.jsonl";
+// bitcoind's -incrementalrelayfee (sat/kvB), a replacement pays at least this on top for its own size
+pub const INCREMENTAL_RELAY_FEE: u64 = 1000;
+// how often a stuck spend is looked at when no miner sets the pace
+const DEFAULT_POLL: Duration = Duration::from_secs(30);
+
+// Pool spends that sit in the mempool for `after_blocks` get an anchor child, and every
+// `after_blocks` after that a replacement of it paying twice the fee rate, until one of them pays
+// `max_fee`. POOL_BUMP_AFTER_BLOCKS enables it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FeeBumpPolicy {
+    pub after_blocks: u64,
+    // POOL_BUMP_MAX_FEE_SATS, the fee payer's budget for one spend. A replacement supersedes the
+    // child before it, so this is what the most expensive child pays
+    pub max_fee: Amount,
+    // POOL_BUMP_JOURNAL, every decision is appended to it
+    pub journal: PathBuf,
+}
+
+impl FeeBumpPolicy {
+    pub fn from_env() -> Result<Option<Self>, ConfigProblem> {
+        let after_blocks = NetworkConfig::get_env_var("POOL_BUMP_AFTER_BLOCKS", "0");
+        let after_blocks: u64 = after_blocks
+            .parse()
+            .map_err(|_| ConfigProblem::invalid_value("POOL_BUMP_AFTER_BLOCKS", &after_blocks))?;
+        if after_blocks == 0 {
+            return Ok(None);
+        }
+        let max_fee = NetworkConfig::get_env_var(
+            "POOL_BUMP_MAX_FEE_SATS",
+            &DEFAULT_BUMP_MAX_FEE.to_sat().to_string(),
+        );
+        let max_fee = max_fee
+            .parse()
+            .map(Amount::from_sat)
+            .map_err(|_| ConfigProblem::invalid_value("POOL_BUMP_MAX_FEE_SATS", &max_fee))?;
+        let journal = NetworkConfig::get_env_var("POOL_BUMP_JOURNAL", DEFAULT_BUMP_JOURNAL);
+
+        Ok(Some(Self {
+            after_blocks,
+            max_fee,
+            journal: PathBuf::from(journal),
+        }))
+    }
+}
+
+// the anchor child a stuck spend has in the mempool
+#[derive(Debug, Clone)]
+pub struct BumpedChild {
+    pub txid: Txid,
+    pub fee: Amount,
+    pub fee_coin: FeeCoin,
+}
+
+// a pool spend broadcast and not confirmed yet
+#[derive(Debug, Clone)]
+pub struct PendingSpend {
+    pub txid: Txid,
+    // who it is for, e.g. "user 3", for the logs and the journal
+    pub label: String,
+    pub vsize: u64,
+    // what the spend pays itself, committed in its template
+    pub fee: Amount,
+    pub broadcast_height: u64,
+    // the height of the broadcast or the last bump, the next one is `after_blocks` later
+    pub since: u64,
+    pub child: Option<BumpedChild>,
+    // the last child paid `max_fee`, nothing more is done about it
+    pub budget_spent: bool,
+}
+
+impl PendingSpend {
+    pub fn new(tx: &Transaction, prevouts: &[TxOut], height: u64, label: &str) -> Self {
+        let input: Amount = prevouts.iter().map(|prevout| prevout.value).sum();
+        let output: Amount = tx.output.iter().map(|output| output.value).sum();
+        Self {
+            txid: tx.compute_txid(),
+            label: label.to_string(),
+            vsize: tx.vsize() as u64,
+            fee: input.checked_sub(output).unwrap_or(Amount::ZERO),
+            broadcast_height: height,
+            since: height,
+            child: None,
+            budget_spent: false,
+        }
+    }
+
+    // sat/kvB of the spend and its child together, what a miner gets for including both
+    pub fn package_fee_rate(&self, child_fee: Amount) -> u64 {
+        (self.fee + child_fee).to_sat() * 1000 / (self.vsize + CPFP_CHILD_VSIZE)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BumpAction {
+    // the first anchor child of the spend
+    Child,
+    // a child replacing the one before it (RBF)
+    Replace,
+    // the budget doesn't allow another replacement
+    BudgetSpent,
+}
+
+// What to do about `spend` at `tip` with the node estimating `estimate` sat/kvB, and the fee of the
+// child. None while it hasn't been stuck for `after_blocks` since the last bump.
+pub fn next_bump(
+    policy: &FeeBumpPolicy,
+    spend: &PendingSpend,
+    tip: u64,
+    estimate: u64,
+) -> Option<(BumpAction, Amount)> {
+    if spend.budget_spent || tip < spend.since + policy.after_blocks {
+        return None;
+    }
+    // at least double what the package pays now, and the estimate
+    let current = spend
+        .package_fee_rate(spend.child.as_ref().map_or(Amount::ZERO, |child| child.fee))
+        .max(INCREMENTAL_RELAY_FEE);
+    let target = estimate.max(current * 2);
+    let package_fee = fee_for_vsize(target, spend.vsize + CPFP_CHILD_VSIZE).ok()?;
+    // the child relays on its own fee rate too, a replacement pays for its own size on top
+    let min_fee = match &spend.child {
+        Some(child) => child.fee + fee_for_vsize(INCREMENTAL_RELAY_FEE, CPFP_CHILD_VSIZE).ok()?,
+        None => fee_for_vsize(INCREMENTAL_RELAY_FEE, CPFP_CHILD_VSIZE).ok()?,
+    };
+    let fee = package_fee
+        .checked_sub(spend.fee)
+        .unwrap_or(Amount::ZERO)
+        .max(min_fee)
+        .min(policy.max_fee);
+    if fee < min_fee {
+        return Some((
+            BumpAction::BudgetSpent,
+            spend.child.as_ref().map_or(Amount::ZERO, |child| child.fee),
+        ));
+    }
+    let action = match spend.child {
+        Some(_) => BumpAction::Replace,
+        None => BumpAction::Child,
+    };
+    Some((action, fee))
+}
+
+// one decision about a stuck spend, a line of the journal
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BumpRecord {
+    // unix time
+    pub time: u64,
+    pub height: u64,
+    pub spend: Txid,
+    pub label: String,
+    pub action: BumpAction,
+    pub blocks_unconfirmed: u64,
+    pub child: Option<Txid>,
+    pub replaced: Option<Txid>,
+    // what the child pays, and the sat/kvB of the spend and the child together
+    pub fee: Amount,
+    pub package_fee_rate: u64,
+}
+
+pub fn append_journal(path: &Path, record: &BumpRecord) -> Result<()> {
+    let mut file = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .with_context(|| format!("can't open the journal {}", path.display()))?;
+    writeln!(file, "{}", serde_json::to_string(record)?)?;
+    Ok(())
+}
+
+pub fn read_journal(path: &Path) -> Result<Vec<BumpRecord>> {
+    fs::read_to_string(path)
+        .with_context(|| format!("can't read the journal {}", path.display()))?
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| Ok(serde_json::from_str(line)?))
+        .collect()
+}
+
+// Keeps the pool spends in the mempool moving: a spend stuck for `after_blocks` gets an anchor child
+// or a replacement of the one it has, see next_bump. Used by the unwind and by watch.
+#[derive(Debug)]
+pub struct FeeBumper {
+    policy: FeeBumpPolicy,
+    spends: Vec<PendingSpend>,
+}
+
+impl FeeBumper {
+    pub fn new(policy: FeeBumpPolicy) -> Self {
+        Self {
+            policy,
+            spends: Vec::new(),
+        }
+    }
+
+    pub fn track(&mut self, spend: PendingSpend) {
+        self.spends.push(spend);
+    }
+
+    // `spend` gets its first child on the next tick instead of `after_blocks` later
+    pub fn track_due(&mut self, mut spend: PendingSpend) {
+        spend.since = spend.since.saturating_sub(self.policy.after_blocks);
+        self.spends.push(spend);
+    }
+
+    pub fn pending(&self) -> &[PendingSpend] {
+        &self.spends
+    }
+
+    // Forget the spends that left the mempool and bump the ones that are due.
+    // Returns the txids that left, mined (or their chain was replaced).
+    pub async fn tick(
+        &mut self,
+        rpc: &AsyncRpc,
+        fee_payer: &AsyncRpc,
+        config: &NetworkConfig,
+    ) -> Result<Vec<Txid>> {
+        let mut left = Vec::new();
+        let mut pending = Vec::new();
+        for spend in std::mem::take(&mut self.spends) {
+            if in_mempool(rpc, spend.txid).await? {
+                pending.push(spend);
+            } else {
+                left.push(spend.txid);
+            }
+        }
+        self.spends = pending;
+
+        let tip = rpc.run(|c| c.get_block_count()).await?;
+        if self
+            .spends
+            .iter()
+            .all(|spend| next_bump(&self.policy, spend, tip, 0).is_none())
+        {
+            return Ok(left);
+        }
+        let estimate = estimate_fee_rate(rpc, config).await;
+        for index in 0..self.spends.len() {
+            if let Some((action, fee)) = next_bump(&self.policy, &self.spends[index], tip, estimate)
+            {
+                self.bump(fee_payer, config, index, action, fee, tip).await;
+            }
+        }
+        Ok(left)
+    }
+
+    // Wait for `txid` to leave the mempool, bumping it (and anything else tracked) on the way
+    pub async fn wait(
+        &mut self,
+        rpc: &AsyncRpc,
+        fee_payer: &AsyncRpc,
+        config: &NetworkConfig,
+        txid: Txid,
+    ) -> Result<()> {
+        let poll = config.block_interval.map_or(DEFAULT_POLL, |interval| {
+            interval.min(Duration::from_secs(1))
+        });
+        while !self.tick(rpc, fee_payer, config).await?.contains(&txid) {
+            tokio::time::sleep(poll).await;
+        }
+        Ok(())
+    }
+
+    // Pay the spend at `index` an anchor child of `fee` now, replacing the one it has. A failed
+    // child is logged and tried again `after_blocks` later, the spend itself is out already.
+    async fn bump(
+        &mut self,
+        fee_payer: &AsyncRpc,
+        config: &NetworkConfig,
+        index: usize,
+        action: BumpAction,
+        fee: Amount,
+        tip: u64,
+    ) {
+        let spend = &mut self.spends[index];
+        spend.since = tip;
+        let blocks_unconfirmed = tip - spend.broadcast_height;
+
+        let (child, replaced) = match action {
+            BumpAction::BudgetSpent => {
+                spend.budget_spent = true;
+                warn!(
+                    "{} ({}) is still unconfirmed after {} blocks, its child pays {} already and POOL_BUMP_MAX_FEE_SATS is {}",
+                    spend.label, spend.txid, blocks_unconfirmed, fee, self.policy.max_fee
+                );
+                (None, None)
+            }
+            BumpAction::Child | BumpAction::Replace => {
+                // the coin of the child it replaces, if it covers the new fee, otherwise a fresh one
+                let fee_coin = spend
+                    .child
+                    .as_ref()
+                    .map(|child| child.fee_coin.clone())
+                    .filter(|coin| cpfp_change(coin.txout.value, fee).is_ok());
+                if fee_coin.is_none() {
+                    if let Err(e) = check_fee_payer_balance(fee_payer, fee).await {
+                        warn!("can't bump {} ({}): {}", spend.label, spend.txid, e);
+                        return;
+                    }
+                }
+                match send_anchor_child(fee_payer, config, spend.txid, fee, fee_coin).await {
+                    Ok((txid, fee_coin)) => {
+                        let previous = spend.child.replace(BumpedChild {
+                            txid,
+                            fee,
+                            fee_coin,
+                        });
+                        let previous_fee = previous.as_ref().map_or(Amount::ZERO, |c| c.fee);
+                        METRICS.add_anchor_sats((fee - previous_fee).to_sat());
+                        info!(
+                            "{} ({}) unconfirmed for {} blocks, anchor child {} pays {} \n",
+                            spend.label, spend.txid, blocks_unconfirmed, txid, fee
+                        );
+                        (Some(txid), previous.map(|child| child.txid))
+                    }
+                    Err(e) => {
+                        warn!("bumping {} ({}) failed: {}", spend.label, spend.txid, e);
+                        return;
+                    }
+                }
+            }
+        };
+
+        let record = BumpRecord {
+            time: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |time| time.as_secs()),
+            height: tip,
+            spend: spend.txid,
+            label: spend.label.clone(),
+            action,
+            blocks_unconfirmed,
+            child,
+            replaced,
+            fee,
+            package_fee_rate: spend.package_fee_rate(fee),
+        };
+        // the bump is out either way, a journal that can't be written doesn't stop it
+        if let Err(e) = append_journal(&self.policy.journal, &record) {
+            warn!("{}", e);
+        }
+    }
+}
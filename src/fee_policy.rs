@@ -0,0 +1,175 @@
+use std::{fmt, sync::Arc};
+
+use anyhow::{anyhow, bail, Result};
+use bitcoin::{absolute, transaction, Amount, Transaction, TxIn, TxOut, Witness};
+
+use crate::{config::NetworkConfig, ids::NodePath, rpc_helper::fee_for_vsize};
+
+// <hash> OP_DROP <key> OP_CHECKSIG of the presigned backend, bigger than <hash> OP_CTV
+const TEMPLATE_SCRIPT_SIZE: usize = 68;
+const SIGNATURE_SIZE: usize = 64;
+
+// What a fee policy gets to go on for one template, before the template (and so its hash) exists
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TemplateInfo {
+    // the node the template spends
+    pub spent: NodePath,
+    // the root's close-all leaf rather than one user leaving
+    pub close_all: bool,
+    pub outputs: usize,
+    // an upper bound: a presigned signature and a control block as deep as the node has leaves
+    pub vsize: u64,
+    // value of its anchor output, a child can pay for it through it
+    pub anchor: Option<Amount>,
+}
+
+impl TemplateInfo {
+    // `outputs` are the template's with any amounts, only their scripts change the size
+    pub fn new(
+        spent: &NodePath,
+        close_all: bool,
+        outputs: &[TxOut],
+        config: &NetworkConfig,
+    ) -> Self {
+        let mut witness = Witness::new();
+        witness.push([0; SIGNATURE_SIZE]);
+        witness.push([0; TEMPLATE_SCRIPT_SIZE]);
+        // the recovery and close-all leaves next to one leaf per user
+        witness.push(vec![0; 33 + 32 * (spent.len() + 1)]);
+        let tx = Transaction {
+            version: transaction::Version(config.tx_version),
+            lock_time: absolute::LockTime::ZERO,
+            input: vec![TxIn {
+                witness,
+                ..Default::default()
+            }],
+            output: outputs.to_vec(),
+        };
+        Self {
+            spent: spent.clone(),
+            close_all,
+            outputs: outputs.len(),
+            vsize: tx.vsize() as u64,
+            anchor: config.anchor_amount,
+        }
+    }
+}
+
+// What a template commits towards getting mined: its own fee and the anchor's value, taken from the
+// leaving user's exit (split between every user for the close-all tx). The template commits to it,
+// so a policy can never change it for an existing pool. Integrators can set their own on
+// NetworkConfig::fee_policy.
+pub trait FeePolicy: fmt::Debug + Send + Sync {
+    fn fee_for(&self, template: &TemplateInfo) -> Amount;
+
+    // how the manifest records the policy, parse_fee_policy has to give it back. A pool built with a
+    // policy that isn't built in can only be rebuilt with the same policy already on the config.
+    fn spec(&self) -> String;
+}
+
+// the same on every template, anchor included, what every pool before fee policies used (FEE_AMOUNT)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FixedFee(pub Amount);
+
+impl FeePolicy for FixedFee {
+    fn fee_for(&self, _template: &TemplateInfo) -> Amount {
+        self.0
+    }
+
+    fn spec(&self) -> String {
+        format!("fixed:{}", self.0.to_sat())
+    }
+}
+
+// sat/vB on the template's estimated size on top of the anchor, bigger nodes and users with splits
+// pay more
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FeeRateFee {
+    pub sat_per_vb: u64,
+}
+
+impl FeePolicy for FeeRateFee {
+    fn fee_for(&self, template: &TemplateInfo) -> Amount {
+        fee_for_vsize(self.sat_per_vb * 1000, template.vsize)
+            .ok()
+            .and_then(|fee| fee.checked_add(template.anchor.unwrap_or(Amount::ZERO)))
+            .unwrap_or(Amount::MAX_MONEY)
+    }
+
+    fn spec(&self) -> String {
+        format!("feerate:{}", self.sat_per_vb)
+    }
+}
+
+// Zero fee templates that only fund their anchor, the anchor child pays for everything when the tx
+// is actually sent. Needs anchors and a node relaying zero fee v3 parents in a package (Bitcoin Core 28+)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AnchorOnly;
+
+impl FeePolicy for AnchorOnly {
+    fn fee_for(&self, template: &TemplateInfo) -> Amount {
+        template.anchor.unwrap_or(Amount::ZERO)
+    }
+
+    fn spec(&self) -> String {
+        "anchor-only".to_string()
+    }
+}
+
+// fixed:<sats>, feerate:<sat/vB> or anchor-only, POOL_FEE_POLICY env var
+pub fn parse_fee_policy(spec: &str) -> Result<Arc<dyn FeePolicy>> {
+    let (kind, value) = spec.split_once(':').unwrap_or((spec, ""));
+    let number = || -> Result<u64> {
+        value
+            .parse()
+            .map_err(|_| anyhow!("fee policy {} needs a number, e.g. {}:5", spec, kind))
+    };
+    Ok(match kind {
+        "fixed" => Arc::new(FixedFee(Amount::from_sat(number()?))),
+        "feerate" => Arc::new(FeeRateFee {
+            sat_per_vb: number()?,
+        }),
+        "anchor-only" if value.is_empty() => Arc::new(AnchorOnly),
+        _ => bail!(
+            "unknown fee policy {}, expected fixed:<sats>, feerate:<sat/vB> or anchor-only",
+            spec
+        ),
+    })
+}
+
+// What one template commits, refusing one that can't fund its anchor, could never be mined or leaves
+// `available` (what it comes out of) less than `keep`
+pub fn template_fee(
+    template: &TemplateInfo,
+    available: Amount,
+    keep: Amount,
+    config: &NetworkConfig,
+) -> Result<Amount> {
+    let fee = config.fee_policy.fee_for(template);
+    match template.anchor {
+        Some(anchor) if fee < anchor => bail!(
+            "fee policy {} commits {} to a template of the pool of {}, less than its {} anchor",
+            config.fee_policy.spec(),
+            fee,
+            template.spent,
+            anchor
+        ),
+        None if fee == Amount::ZERO => bail!(
+            "fee policy {} leaves a template of the pool of {} without a fee and there is no anchor to pay for it",
+            config.fee_policy.spec(),
+            template.spent
+        ),
+        _ => {}
+    }
+    if available.checked_sub(fee).is_none_or(|rest| rest < keep) {
+        bail!(
+            "fee policy {} takes {} from a template of the pool of {}, it has to leave {} of {}",
+            config.fee_policy.spec(),
+            fee,
+            template.spent,
+            keep,
+            available
+        );
+    }
+    Ok(fee)
+}
@@ -0,0 +1,280 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    ops::RangeInclusive,
+};
+
+use anyhow::{anyhow, bail, Result};
+use bitcoin::{consensus::encode::deserialize_hex, Block, BlockHash, OutPoint, Transaction, Txid};
+use bitcoincore_rpc::{
+    json::{GetRawTransactionResult, GetTransactionResult, GetTxOutResult},
+    Client,
+};
+use serde::de::DeserializeOwned;
+use serde_json::{json, value::RawValue, Value};
+use tokio::task::JoinSet;
+
+use crate::{config::NetworkConfig, rpc_helper::AsyncRpc};
+
+// How many lookups go to the node at once. A bitcoincore_rpc client has one request in flight at a
+// time, so the lookups are sent as json-rpc batches of `batch` requests, spread over `connections`
+// clients. POOL_RPC_CONNECTIONS / POOL_RPC_BATCH env vars.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RpcFetchLimits {
+    pub connections: usize,
+    pub batch: usize,
+}
+
+impl RpcFetchLimits {
+    pub const DEFAULT: Self = Self {
+        connections: 4,
+        batch: 25,
+    };
+}
+
+// Lookups of many blocks, txs or outputs, e.g. the blocks `report` and `watch` go through one by one
+#[derive(Clone)]
+pub struct RpcFetcher {
+    connections: Vec<AsyncRpc>,
+    batch: usize,
+}
+
+impl RpcFetcher {
+    // `rpc` and the rest of the config's connections to the same wallet
+    pub async fn connect(rpc: &AsyncRpc, config: &NetworkConfig) -> Result<Self> {
+        let mut connections = vec![rpc.clone()];
+        for _ in 1..config.rpc_fetch.connections {
+            connections.push(AsyncRpc::connect(config).await?);
+        }
+        Ok(Self {
+            connections,
+            batch: config.rpc_fetch.batch.max(1),
+        })
+    }
+
+    // clients built elsewhere, e.g. over the mock's transport
+    pub fn new(connections: Vec<AsyncRpc>, batch: usize) -> Result<Self> {
+        if connections.is_empty() {
+            bail!("no rpc connection to fetch over");
+        }
+        Ok(Self {
+            connections,
+            batch: batch.max(1),
+        })
+    }
+
+    // how many blocks are worth fetching ahead of a scan that may stop early
+    pub fn window(&self) -> u64 {
+        (self.connections.len() * self.batch) as u64
+    }
+
+    // `method` with each of `params`, the results in the same order. One request failing (e.g. an
+    // unknown txid) doesn't fail the others.
+    pub async fn fetch<T>(&self, method: &'static str, params: Vec<Value>) -> Result<Vec<Result<T>>>
+    where
+        T: DeserializeOwned + Send + 'static,
+    {
+        let batches: Vec<Vec<Value>> = params.chunks(self.batch).map(<[_]>::to_vec).collect();
+        let count = batches.len();
+        // connection i sends batches i, i + connections, ... one after the other
+        let mut queues: Vec<Vec<(usize, Vec<Value>)>> = vec![Vec::new(); self.connections.len()];
+        for (i, batch) in batches.into_iter().enumerate() {
+            queues[i % self.connections.len()].push((i, batch));
+        }
+        let mut tasks = JoinSet::new();
+        for (rpc, queue) in self.connections.iter().zip(queues) {
+            let rpc = rpc.clone();
+            tasks.spawn(async move {
+                let mut done = Vec::new();
+                for (i, batch) in queue {
+                    let results = rpc
+                        .run(move |c| Ok(send_batch::<T>(c, method, &batch)))
+                        .await?;
+                    done.push((i, results));
+                }
+                Ok::<_, anyhow::Error>(done)
+            });
+        }
+        let mut results: Vec<Option<Vec<Result<T>>>> = (0..count).map(|_| None).collect();
+        while let Some(done) = tasks.join_next().await {
+            for (i, batch) in done?? {
+                results[i] = Some(batch);
+            }
+        }
+        Ok(results.into_iter().flatten().flatten().collect())
+    }
+
+    // the hashes of the blocks at `heights` in the active chain, in order
+    pub async fn block_hashes(&self, heights: &[u64]) -> Result<Vec<BlockHash>> {
+        self.fetch::<BlockHash>(
+            "getblockhash",
+            heights.iter().map(|height| json!([height])).collect(),
+        )
+        .await?
+        .into_iter()
+        .collect()
+    }
+
+    // the blocks at `heights` with their hashes, in order
+    pub async fn blocks(
+        &self,
+        heights: RangeInclusive<u64>,
+    ) -> Result<Vec<(u64, BlockHash, Block)>> {
+        let heights: Vec<u64> = heights.collect();
+        let hashes = self.block_hashes(&heights).await?;
+        let blocks = self
+            .fetch::<String>(
+                "getblock",
+                hashes.iter().map(|hash| json!([hash, 0])).collect(),
+            )
+            .await?;
+        heights
+            .into_iter()
+            .zip(hashes)
+            .zip(blocks)
+            .map(|((height, hash), block)| Ok((height, hash, deserialize_hex(&block?)?)))
+            .collect()
+    }
+
+    // `heights` block by block, fetched a window ahead
+    pub fn scan(&self, heights: RangeInclusive<u64>) -> BlockScan {
+        BlockScan {
+            fetcher: self.clone(),
+            next: *heights.start(),
+            to: *heights.end(),
+            ahead: VecDeque::new(),
+        }
+    }
+
+    // The txs the wallet has or, for the rest, the node (mempool or -txindex). The ones neither
+    // knows are left out.
+    pub async fn transactions(&self, txids: &[Txid]) -> Result<HashMap<Txid, Transaction>> {
+        let mut unique = txids.to_vec();
+        unique.sort();
+        unique.dedup();
+        let mut found = HashMap::new();
+        let wallet_txs = self
+            .fetch::<GetTransactionResult>(
+                "gettransaction",
+                unique.iter().map(|txid| json!([txid])).collect(),
+            )
+            .await?;
+        for (txid, wallet_tx) in unique.iter().zip(wallet_txs) {
+            if let Ok(wallet_tx) = wallet_tx {
+                found.insert(*txid, wallet_tx.transaction()?);
+            }
+        }
+
+        unique.retain(|txid| !found.contains_key(txid));
+        let txs = self
+            .fetch::<String>(
+                "getrawtransaction",
+                unique.iter().map(|txid| json!([txid, false])).collect(),
+            )
+            .await?;
+        for (txid, tx) in unique.into_iter().zip(txs) {
+            if let Ok(hex) = tx {
+                found.insert(txid, deserialize_hex(&hex)?);
+            }
+        }
+        Ok(found)
+    }
+
+    // Verbose getrawtransaction for each of `txids`: the tx with its block and confirmations. The
+    // ones the node doesn't know (confirmed without -txindex and not the wallet's) are left out.
+    pub async fn transaction_infos(
+        &self,
+        txids: &[Txid],
+    ) -> Result<HashMap<Txid, GetRawTransactionResult>> {
+        let infos = self
+            .fetch::<GetRawTransactionResult>(
+                "getrawtransaction",
+                txids.iter().map(|txid| json!([txid, true])).collect(),
+            )
+            .await?;
+        Ok(txids
+            .iter()
+            .zip(infos)
+            .filter_map(|(txid, info)| Some((*txid, info.ok()?)))
+            .collect())
+    }
+
+    // gettxout for each of `outpoints`, None for the spent or unknown ones
+    pub async fn tx_outs(
+        &self,
+        outpoints: &[OutPoint],
+        include_mempool: bool,
+    ) -> Result<Vec<Option<GetTxOutResult>>> {
+        self.fetch::<Option<GetTxOutResult>>(
+            "gettxout",
+            outpoints
+                .iter()
+                .map(|outpoint| json!([outpoint.txid, outpoint.vout, include_mempool]))
+                .collect(),
+        )
+        .await?
+        .into_iter()
+        .collect()
+    }
+}
+
+pub struct BlockScan {
+    fetcher: RpcFetcher,
+    next: u64,
+    to: u64,
+    ahead: VecDeque<(u64, BlockHash, Block)>,
+}
+
+impl BlockScan {
+    // the next block with its height and hash, None past the end
+    pub async fn next_block(&mut self) -> Result<Option<(u64, BlockHash, Block)>> {
+        if self.ahead.is_empty() && self.next <= self.to {
+            let last = self.to.min(self.next + self.fetcher.window() - 1);
+            self.ahead = self.fetcher.blocks(self.next..=last).await?.into();
+            self.next = last + 1;
+        }
+        Ok(self.ahead.pop_front())
+    }
+}
+
+// One json-rpc call with a request for each of `params` (one rpc in the latency metric). The batch
+// as a whole failing fails every request in it.
+fn send_batch<T: DeserializeOwned>(
+    client: &Client,
+    method: &str,
+    params: &[Value],
+) -> Vec<Result<T>> {
+    let raw: Vec<Box<RawValue>> = match params
+        .iter()
+        .map(serde_json::value::to_raw_value)
+        .collect::<Result<_, _>>()
+    {
+        Ok(raw) => raw,
+        Err(e) => {
+            return params
+                .iter()
+                .map(|_| Err(anyhow!("{}: {}", method, e)))
+                .collect()
+        }
+    };
+    let jsonrpc = client.get_jsonrpc_client();
+    let requests: Vec<_> = raw
+        .iter()
+        .map(|params| jsonrpc.build_request(method, Some(params)))
+        .collect();
+    let responses = jsonrpc.send_batch(&requests);
+    match responses {
+        Ok(responses) => responses
+            .into_iter()
+            .map(|response| {
+                response
+                    .ok_or_else(|| anyhow!("no response to {}", method))?
+                    .result::<T>()
+                    .map_err(|e| anyhow!("{}: {}", method, e))
+            })
+            .collect(),
+        Err(e) => params
+            .iter()
+            .map(|_| Err(anyhow!("{} batch failed: {}", method, e)))
+            .collect(),
+    }
+}
@@ -0,0 +1,184 @@
+use std::str::FromStr;
+
+use anyhow::Result;
+use bitcoin::{
+    taproot::{LeafVersion, TaprootSpendInfo},
+    Address, ScriptBuf, VarInt,
+};
+use serde::Serialize;
+
+use crate::{
+    config::NetworkConfig,
+    covenant::CovenantBackend,
+    ctv_scripts::{create_pool_address, TreeLayout},
+    pools::node_amount,
+    recovery::recovery_leaf,
+    POOL_USERS,
+};
+
+// schnorr signature with SIGHASH_DEFAULT, the presigned backend's witness carries one
+pub const SCHNORR_SIG_SIZE: usize = 64;
+
+// One way out of a node of `users` users, spent with each layout
+#[derive(Debug, Serialize)]
+pub struct PathFootprint {
+    pub users: usize,
+    // "next" (the planned unwind), "other" (worst case of the other users), "exit" or "recovery"
+    pub path: &'static str,
+    pub balanced_control_block: usize,
+    pub weighted_control_block: usize,
+    pub balanced_witness_wu: u64,
+    pub weighted_witness_wu: u64,
+    pub saved_wu: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Footprint {
+    pub layout: TreeLayout,
+    pub paths: Vec<PathFootprint>,
+    // every spend of the planned unwind, root to exit pool
+    pub unwind_saved_wu: i64,
+}
+
+// witness weight units of a script path spend, segwit data counts 1 wu per byte
+fn witness_weight(items: &[usize]) -> u64 {
+    let mut size = VarInt(items.len() as u64).size();
+    for item in items {
+        size += VarInt(*item as u64).size() + item;
+    }
+    size as u64
+}
+
+fn control_block_size(
+    spend_info: &TaprootSpendInfo,
+    script: &ScriptBuf,
+    leaf_version: LeafVersion,
+) -> usize {
+    spend_info
+        .control_block(&(script.clone(), leaf_version))
+        .map(|control_block| control_block.size())
+        .unwrap_or_default()
+}
+
+// Witness weight of every spend path per node size, balanced vs weighted layout.
+// The template hashes don't change the shape of a node, so one node per size with stand-in hashes is enough.
+pub fn pool_footprint(config: &NetworkConfig, backend: &dyn CovenantBackend) -> Result<Footprint> {
+    let anchor_addr =
+        Address::from_str(&config.fee_anchor_addr)?.require_network(config.network)?;
+    let mut paths = Vec::new();
+    let mut unwind_saved_wu = 0;
+
+    for users in (2..=POOL_USERS).rev() {
+        // the exit pool has one template paying both users
+        let templates = if users == 2 { 1 } else { users };
+        let hashes: Vec<[u8; 32]> = (0..templates).map(|i| [i as u8 + 1; 32]).collect();
+        let extra_leaf = recovery_leaf(config, node_amount(users)?, &anchor_addr);
+        let balanced = create_pool_address(
+            hashes.clone(),
+            backend,
+            extra_leaf.clone(),
+            TreeLayout::Balanced,
+            config.leaf_version,
+        )?;
+        let weighted = create_pool_address(
+            hashes.clone(),
+            backend,
+            extra_leaf.clone(),
+            TreeLayout::Weighted,
+            config.leaf_version,
+        )?;
+
+        // with the witness items the backend adds before the script, the recovery leaf takes none
+        let mut leaves: Vec<(&'static str, ScriptBuf, Vec<usize>)> = hashes
+            .iter()
+            .map(|hash| (backend.leaf_script(*hash), backend.witness_items(*hash)))
+            .enumerate()
+            .map(|(i, (script, items))| match (users, i) {
+                (2, _) => ("exit", script, items),
+                (_, 0) => ("next", script, items),
+                _ => ("other", script, items),
+            })
+            .collect();
+        leaves.extend(extra_leaf.map(|script| ("recovery", script, Vec::new())));
+
+        let mut others: Vec<PathFootprint> = Vec::new();
+        for (path, script, items) in leaves {
+            let balanced_control_block =
+                control_block_size(&balanced, &script, config.leaf_version);
+            let weighted_control_block =
+                control_block_size(&weighted, &script, config.leaf_version);
+            let witness = |control_block: usize| {
+                let mut items = items.clone();
+                items.extend([script.len(), control_block]);
+                witness_weight(&items)
+            };
+            let footprint = PathFootprint {
+                users,
+                path,
+                balanced_control_block,
+                weighted_control_block,
+                balanced_witness_wu: witness(balanced_control_block),
+                weighted_witness_wu: witness(weighted_control_block),
+                saved_wu: witness(balanced_control_block) as i64
+                    - witness(weighted_control_block) as i64,
+            };
+
+            match path {
+                "other" => others.push(footprint),
+                "next" | "exit" => {
+                    unwind_saved_wu += footprint.saved_wu;
+                    paths.push(footprint);
+                }
+                _ => paths.push(footprint),
+            }
+        }
+        // the longest path any other user could take, in each layout
+        if let (Some(balanced), Some(weighted)) = (
+            others.iter().max_by_key(|other| other.balanced_witness_wu),
+            others.iter().max_by_key(|other| other.weighted_witness_wu),
+        ) {
+            paths.push(PathFootprint {
+                users,
+                path: "other",
+                balanced_control_block: balanced.balanced_control_block,
+                weighted_control_block: weighted.weighted_control_block,
+                balanced_witness_wu: balanced.balanced_witness_wu,
+                weighted_witness_wu: weighted.weighted_witness_wu,
+                saved_wu: balanced.balanced_witness_wu as i64 - weighted.weighted_witness_wu as i64,
+            });
+        }
+    }
+
+    Ok(Footprint {
+        layout: config.tree_layout,
+        paths,
+        unwind_saved_wu,
+    })
+}
+
+pub fn print_footprint(footprint: &Footprint) {
+    println!("tree layout: {:?} (POOL_TREE_LAYOUT)", footprint.layout);
+    println!();
+    println!(
+        "{:>6} {:>9} {:>22} {:>22} {:>8}",
+        "users", "path", "balanced cb / wu", "weighted cb / wu", "saved"
+    );
+    for path in &footprint.paths {
+        println!(
+            "{:>6} {:>9} {:>14} / {:>5} {:>14} / {:>5} {:>8}",
+            path.users,
+            path.path,
+            path.balanced_control_block,
+            path.balanced_witness_wu,
+            path.weighted_control_block,
+            path.weighted_witness_wu,
+            path.saved_wu
+        );
+    }
+    println!();
+    println!(
+        "planned unwind: {} wu ({:.1} vB) saved by the weighted layout",
+        footprint.unwind_saved_wu,
+        footprint.unwind_saved_wu as f64 / 4.0
+    );
+}
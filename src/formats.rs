@@ -0,0 +1,210 @@
+use std::str::FromStr;
+
+use anyhow::{bail, Result};
+use bitcoin::{
+    key::{Keypair, Secp256k1},
+    secp256k1::SecretKey,
+    Address, Network,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::{NetworkConfig, POOL_USERS},
+    covenant::CtvBackend,
+    ctv_scripts::{TemplateVersion, TreeLayout},
+    pools::build_pools,
+    state::STATE_VERSION,
+    template::Bip119Ctv,
+};
+
+// What one binary state version added to the manifest, the changelog of state.rs's layouts
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StateChange {
+    pub version: u16,
+    pub change: String,
+}
+
+// version -> what it added, every version up to STATE_VERSION has an entry
+pub const STATE_CHANGELOG: &[(u16, &str)] = &[(1, "first binary format")];
+
+// The root address the pool of the reference users comes to with one construction. The root
+// commits to every node and template below it, so two builds with the same address derive the
+// same trees.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Derivation {
+    pub template_version: u16,
+    pub layout: TreeLayout,
+    pub root_address: String,
+}
+
+// The committed formats of this build, what `version --formats --json` prints for another
+// coordinator or member to compare with
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Formats {
+    pub crate_version: String,
+    pub network: Network,
+    pub pool_users: usize,
+    // the version new pools are built with, and every one this build can rebuild
+    pub template_version: u16,
+    pub template_versions: Vec<u16>,
+    // the binary state version written, every older one (and json) is read
+    pub state_version: u16,
+    pub state_changelog: Vec<StateChange>,
+    pub derivations: Vec<Derivation>,
+}
+
+// one reference user, the same keys in every build
+fn reference_address(seed: u8, network: Network) -> Address {
+    let secp = Secp256k1::new();
+    let key = SecretKey::from_slice(&[seed; 32]).expect("seed is a valid key");
+    let (xonly, _) = Keypair::from_secret_key(&secp, &key).x_only_public_key();
+    Address::p2tr(&secp, xonly, None, network)
+}
+
+// The root of the reference pool under `config` with every template version and layout, with the
+// ctv backend (the presigned one has a fresh key every pool)
+fn derivations(config: &NetworkConfig) -> Result<Vec<Derivation>> {
+    let addresses: Vec<Address> = (0..POOL_USERS)
+        .map(|i| reference_address(i as u8 + 1, config.network))
+        .collect();
+    let anchor_addr =
+        Address::from_str(&config.fee_anchor_addr)?.require_network(config.network)?;
+    let backend = CtvBackend::from(Bip119Ctv {
+        tx_version: config.tx_version,
+    });
+    let mut derivations = Vec::new();
+    for template_version in TemplateVersion::ALL {
+        for layout in [TreeLayout::Balanced, TreeLayout::Weighted] {
+            let mut config = config.clone();
+            config.template_version = *template_version;
+            config.tree_layout = layout;
+            let tree = build_pools(&addresses, &anchor_addr, &config, &backend)?;
+            derivations.push(Derivation {
+                template_version: (*template_version).into(),
+                layout,
+                root_address: tree.root()?.address(&config).to_string(),
+            });
+        }
+    }
+    Ok(derivations)
+}
+
+pub fn formats(config: &NetworkConfig) -> Result<Formats> {
+    Ok(Formats {
+        crate_version: env!("CARGO_PKG_VERSION").to_string(),
+        network: config.network,
+        pool_users: POOL_USERS,
+        template_version: TemplateVersion::LATEST.into(),
+        template_versions: TemplateVersion::ALL
+            .iter()
+            .map(|version| (*version).into())
+            .collect(),
+        state_version: STATE_VERSION,
+        state_changelog: STATE_CHANGELOG
+            .iter()
+            .map(|(version, change)| StateChange {
+                version: *version,
+                change: change.to_string(),
+            })
+            .collect(),
+        derivations: derivations(config)?,
+    })
+}
+
+// Everything that keeps two builds from working on the same pool, empty when they derive the same
+// trees and read each other's manifests
+pub fn incompatibilities(ours: &Formats, theirs: &Formats) -> Vec<String> {
+    let mut problems = Vec::new();
+    if ours.network != theirs.network {
+        problems.push(format!(
+            "formats of {} compared with {}, run both on the same network",
+            ours.network, theirs.network
+        ));
+        return problems;
+    }
+    if ours.pool_users != theirs.pool_users {
+        problems.push(format!(
+            "this build makes pools of {} users, theirs of {}",
+            ours.pool_users, theirs.pool_users
+        ));
+    }
+    if !ours.template_versions.contains(&theirs.template_version) {
+        problems.push(format!(
+            "their new pools use template v{}, which this build can't rebuild",
+            theirs.template_version
+        ));
+    }
+    if !theirs.template_versions.contains(&ours.template_version) {
+        problems.push(format!(
+            "new pools of this build use template v{}, which theirs can't rebuild",
+            ours.template_version
+        ));
+    }
+    if theirs.state_version > ours.state_version {
+        problems.push(format!(
+            "their binary manifests (state version {}) are newer than this build reads ({}), share them as json",
+            theirs.state_version, ours.state_version
+        ));
+    }
+    for derivation in &ours.derivations {
+        let Some(other) = theirs.derivations.iter().find(|other| {
+            other.template_version == derivation.template_version
+                && other.layout == derivation.layout
+        }) else {
+            continue;
+        };
+        if other.root_address != derivation.root_address {
+            problems.push(format!(
+                "template v{} with the {:?} layout derives root {} here, {} in theirs",
+                derivation.template_version,
+                derivation.layout,
+                derivation.root_address,
+                other.root_address
+            ));
+        }
+    }
+    problems
+}
+
+pub fn require_compatible(ours: &Formats, theirs: &Formats) -> Result<()> {
+    let problems = incompatibilities(ours, theirs);
+    if !problems.is_empty() {
+        bail!(
+            "the builds won't derive the same pools, {} problems:\n  {}",
+            problems.len(),
+            problems.join("\n  ")
+        );
+    }
+    Ok(())
+}
+
+pub fn print_formats(formats: &Formats) {
+    println!("op_ctv_payment_pool {}", formats.crate_version);
+    println!(
+        "template v{} for new pools, rebuilds {}",
+        formats.template_version,
+        formats
+            .template_versions
+            .iter()
+            .map(|version| format!("v{}", version))
+            .collect::<Vec<_>>()
+            .join(", ")
+    );
+    println!(
+        "binary state version {}, reads every older one and json",
+        formats.state_version
+    );
+    for change in &formats.state_changelog {
+        println!("  {:>3}  {}", change.version, change.change);
+    }
+    println!(
+        "reference pool of {} users on {}:",
+        formats.pool_users, formats.network
+    );
+    for derivation in &formats.derivations {
+        println!(
+            "  v{} {:?}: {}",
+            derivation.template_version, derivation.layout, derivation.root_address
+        );
+    }
+}
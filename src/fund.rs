@@ -0,0 +1,155 @@
+use std::{fs, path::Path, str::FromStr};
+
+use anyhow::{anyhow, bail, Result};
+use bitcoin::{
+    base64::{prelude::BASE64_STANDARD, Engine},
+    Address, Amount, OutPoint, Psbt, Transaction, TxOut, Txid,
+};
+use bitcoincore_rpc::RpcApi;
+use tracing::info;
+
+use crate::{
+    broadcast::{broadcast, BroadcastKind},
+    config::{NetworkConfig, DEFAULT_FEE_RATE},
+    lifecycle::Event,
+    manifest::PoolManifest,
+    p2p::{compare_codes, require_agreement, verification_code},
+    psbt_v2::{decode_psbt, PsbtVersion},
+    reservations::check_unreserved,
+    rpc_helper::{fee_for_vsize, AsyncRpc},
+    standardness::check_standard,
+    AMOUNT_PER_USER, POOL_USERS,
+};
+
+// what the funding output of a pool of every user has to hold, the templates spend exactly this.
+// One short of removed participants holds less, see PoolManifest::funding_target
+pub fn required_funding() -> Amount {
+    AMOUNT_PER_USER * POOL_USERS as u64
+}
+
+// what the funding tx costs on top of the pool amount, roughly, at the fallback fee rate
+pub const FUNDING_TX_VSIZE: u64 = 250;
+
+// the pool amount and a funding tx fee at the fallback fee rate, what the wallet has to hold
+pub fn funding_budget() -> Result<Amount> {
+    Ok(required_funding() + fee_for_vsize(DEFAULT_FEE_RATE, FUNDING_TX_VSIZE)?)
+}
+
+// base64 (what most wallets export) or raw binary, version 0 or 2
+pub fn read_psbt(path: &Path) -> Result<Psbt> {
+    Ok(read_psbt_version(path)?.0)
+}
+
+// the PSBT and the version it is in, to write it back the same way
+pub fn read_psbt_version(path: &Path) -> Result<(Psbt, PsbtVersion)> {
+    let bytes = fs::read(path)?;
+    match std::str::from_utf8(&bytes) {
+        Ok(text) => decode_psbt(&BASE64_STANDARD.decode(text.trim())?),
+        Err(_) => decode_psbt(&bytes),
+    }
+}
+
+// exactly one output pays the pool root, with exactly the pool amount
+fn check_funding_outputs(outputs: &[TxOut], manifest: &PoolManifest) -> Result<()> {
+    let root = Address::from_str(&manifest.root_address)?.require_network(manifest.network)?;
+    let to_pool: Vec<&TxOut> = outputs
+        .iter()
+        .filter(|output| output.script_pubkey == root.script_pubkey())
+        .collect();
+
+    let target = manifest.funding_target()?;
+    match to_pool.as_slice() {
+        [] => bail!("the PSBT does not pay the pool address {}", root),
+        [output] if output.value != target => bail!(
+            "the PSBT pays {} to the pool but it needs exactly {}",
+            output.value,
+            target
+        ),
+        [_] => Ok(()),
+        _ => bail!("the PSBT pays the pool address {} more than once", root),
+    }
+}
+
+fn is_finalized(psbt: &Psbt) -> bool {
+    psbt.inputs
+        .iter()
+        .all(|input| input.final_script_witness.is_some() || input.final_script_sig.is_some())
+}
+
+// Fund the pool in the manifest with a PSBT made by some other wallet. Inputs that are not signed yet
+// are handed to the wallet, then the tx is finalized, linted and broadcast, and the manifest gets the txid.
+pub async fn fund_from_psbt(
+    rpc: &AsyncRpc,
+    mut manifest: PoolManifest,
+    manifest_path: &Path,
+    psbt_path: &Path,
+    codes: &[String],
+) -> Result<Txid> {
+    // a pool is only funded once
+    manifest.lifecycle.apply(Event::Fund)?;
+    // rebuilds the tree, so we never fund a root the manifest can't spend from
+    manifest.load_pool()?;
+    // and without a coordinator, everyone else has to have built the same one
+    if !codes.is_empty() {
+        require_agreement(&compare_codes(&verification_code(&manifest)?, codes))?;
+        info!("all {} verification codes match \n", codes.len());
+    }
+
+    let mut psbt = read_psbt(psbt_path)?;
+    check_funding_outputs(&psbt.unsigned_tx.output, &manifest)?;
+    // the coins of a funding still being built for another pool
+    let spent: Vec<OutPoint> = psbt
+        .unsigned_tx
+        .input
+        .iter()
+        .map(|input| input.previous_output)
+        .collect();
+    check_unreserved(
+        &NetworkConfig::new(manifest.profile),
+        &manifest.root_address,
+        &spent,
+    )?;
+    info!(
+        "PSBT pays {} to the pool at {} \n",
+        manifest.funding_target()?,
+        manifest.root_address
+    );
+
+    if !is_finalized(&psbt) {
+        info!("PSBT is not fully signed, asking the wallet to sign it");
+        let processed = rpc
+            .run(move |c| c.wallet_process_psbt(&psbt.to_string(), Some(true), None, None))
+            .await?;
+        let finalized = rpc
+            .run(move |c| c.finalize_psbt(&processed.psbt, Some(false)))
+            .await?;
+        if !finalized.complete {
+            bail!(
+                "the wallet could not sign every input, sign the PSBT with the other wallets first"
+            );
+        }
+        psbt = Psbt::from_str(
+            &finalized
+                .psbt
+                .ok_or_else(|| anyhow!("finalizepsbt returned no PSBT"))?,
+        )?;
+    }
+
+    let prevouts = psbt
+        .iter_funding_utxos()
+        .map(|utxo| utxo.cloned())
+        .collect::<Result<Vec<TxOut>, _>>()
+        .map_err(|e| anyhow!("PSBT input is missing its utxo: {}", e))?;
+    let funding_tx: Transaction = psbt.extract_tx()?;
+    check_funding_outputs(&funding_tx.output, &manifest)?;
+    check_standard(rpc, &funding_tx, &prevouts).await?;
+
+    let txid = broadcast(rpc, &funding_tx, BroadcastKind::Funding).await?;
+    info!("pool funded: {} \n", txid);
+
+    manifest.funding_txid = Some(txid);
+    manifest.advance(Event::Fund)?;
+    manifest.write(manifest_path)?;
+
+    Ok(txid)
+}
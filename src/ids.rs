@@ -0,0 +1,192 @@
+use std::{fmt, str::FromStr};
+
+use anyhow::{anyhow, bail, Error, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::POOL_USERS;
+
+// Position of a user in the pool, i.e. in the withdraw address list. Always below POOL_USERS.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(try_from = "usize", into = "usize")]
+pub struct UserIndex(usize);
+
+impl UserIndex {
+    pub fn new(index: usize) -> Result<Self> {
+        if index >= POOL_USERS {
+            bail!("user {} not in a pool of {} users", index, POOL_USERS);
+        }
+        Ok(Self(index))
+    }
+
+    pub fn index(self) -> usize {
+        self.0
+    }
+
+    // every user, in pool order
+    pub fn all() -> impl Iterator<Item = Self> {
+        (0..POOL_USERS).map(Self)
+    }
+}
+
+impl TryFrom<usize> for UserIndex {
+    type Error = Error;
+
+    fn try_from(index: usize) -> Result<Self> {
+        Self::new(index)
+    }
+}
+
+impl From<UserIndex> for usize {
+    fn from(user: UserIndex) -> usize {
+        user.0
+    }
+}
+
+impl FromStr for UserIndex {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Self::new(s.parse()?)
+    }
+}
+
+impl fmt::Display for UserIndex {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+// A node of the pool tree, named by the users still in it (sorted, at least 2).
+// The root holds every user, a node's children are the same set minus the user leaving.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(try_from = "Vec<usize>", into = "Vec<usize>")]
+pub struct NodePath(Vec<usize>);
+
+impl NodePath {
+    pub fn new(users: Vec<usize>) -> Result<Self> {
+        if users.len() < 2 {
+            bail!("a pool node has at least 2 users, got {:?}", users);
+        }
+        if !users.windows(2).all(|pair| pair[0] < pair[1]) {
+            bail!("pool node users must be sorted and unique, got {:?}", users);
+        }
+        if let Some(user) = users.iter().find(|&&user| user >= POOL_USERS) {
+            bail!("user {} not in a pool of {} users", user, POOL_USERS);
+        }
+        Ok(Self(users))
+    }
+
+    pub fn root() -> Self {
+        Self((0..POOL_USERS).collect())
+    }
+
+    // the node the pool is in right before `spender` leaves, users leave in address order
+    pub fn unwind(spender: UserIndex) -> Result<Self> {
+        Self::new((spender.index()..POOL_USERS).collect())
+    }
+
+    pub fn users(&self) -> &[usize] {
+        &self.0
+    }
+
+    pub fn user_indices(&self) -> impl Iterator<Item = UserIndex> + '_ {
+        self.0.iter().map(|&user| UserIndex(user))
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    // never true, a node has at least 2 users
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn contains(&self, user: UserIndex) -> bool {
+        self.0.contains(&user.index())
+    }
+
+    // index into the pool levels, the exit pool (2 users) is level 0
+    pub fn level(&self) -> usize {
+        self.0.len() - 2
+    }
+
+    pub fn is_root(&self) -> bool {
+        self.0.len() == POOL_USERS
+    }
+
+    pub fn is_exit(&self) -> bool {
+        self.0.len() == 2
+    }
+
+    // the node left once `user` has withdrawn
+    pub fn without(&self, user: UserIndex) -> Result<Self> {
+        if !self.contains(user) {
+            bail!("user {} is not in the pool of users {}", user, self);
+        }
+        Self::new(self.0.iter().copied().filter(|&u| u != user.0).collect())
+            .map_err(|_| anyhow!("the exit pool {} pays both users at once", self))
+    }
+}
+
+impl TryFrom<Vec<usize>> for NodePath {
+    type Error = Error;
+
+    fn try_from(users: Vec<usize>) -> Result<Self> {
+        Self::new(users)
+    }
+}
+
+impl From<NodePath> for Vec<usize> {
+    fn from(node: NodePath) -> Vec<usize> {
+        node.0
+    }
+}
+
+impl fmt::Display for NodePath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self.0)
+    }
+}
+
+// The name a pool goes by, what the users sign when they register their addresses
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct PoolId(String);
+
+impl PoolId {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl FromStr for PoolId {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        if s.trim().is_empty() {
+            bail!("pool id can't be empty");
+        }
+        Ok(Self(s.to_string()))
+    }
+}
+
+impl TryFrom<String> for PoolId {
+    type Error = Error;
+
+    fn try_from(id: String) -> Result<Self> {
+        id.parse()
+    }
+}
+
+impl From<PoolId> for String {
+    fn from(id: PoolId) -> String {
+        id.0
+    }
+}
+
+impl fmt::Display for PoolId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
@@ -0,0 +1,562 @@
+use anyhow::{anyhow, bail, Result};
+use bitcoin::{
+    consensus::Encodable,
+    hashes::{hash160, ripemd160, sha1, sha256, sha256d, Hash, HashEngine},
+    hex::DisplayHex,
+    key::{Secp256k1, XOnlyPublicKey},
+    opcodes::{all::*, Class, ClassifyContext, Opcode},
+    script::Instruction,
+    secp256k1::{schnorr, Message, Verification},
+    sighash::{Prevouts, SighashCache, TapSighashType},
+    taproot::{ControlBlock, LeafVersion, TapLeafHash, TAPROOT_ANNEX_PREFIX},
+    Script, Transaction, TxOut,
+};
+
+// Tapscript interpreter of our own, to execute a pool spend's witness against the output it spends
+// before anything is broadcast. Nothing in it comes from the code that builds the templates, OP_CTV
+// included: it hashes the spending tx with its own BIP-119 implementation (template_hash), so a bug
+// in ctv_scripts::ctv_hash shows up as a leaf that doesn't execute. It only knows BIP-341/342,
+// BIP-119 OP_CTV, BIP-65/112 and the opcodes the pool's leaves and compiled miniscript policies use;
+// anything else is refused rather than guessed. Standardness rules are applied where
+// they are cheap (minimal numbers), signature op budgets are not.
+
+const MAX_ELEMENT_SIZE: usize = 520;
+const MAX_STACK_SIZE: usize = 1000;
+
+// bottom first
+type Stack = Vec<Vec<u8>>;
+
+// BIP-119's DefaultCheckTemplateVerifyHash of `tx` spent at `input_index`, written from the BIP
+// and not shared with ctv_scripts, see above
+pub fn template_hash(tx: &Transaction, input_index: u32) -> [u8; 32] {
+    let mut engine = sha256::Hash::engine();
+    engine.input(&tx.version.0.to_le_bytes());
+    engine.input(&tx.lock_time.to_consensus_u32().to_le_bytes());
+    // the scriptSigs only when one isn't empty, so segwit spends don't commit to them
+    if tx.input.iter().any(|input| !input.script_sig.is_empty()) {
+        let mut script_sigs = sha256::Hash::engine();
+        for input in &tx.input {
+            input
+                .script_sig
+                .consensus_encode(&mut script_sigs)
+                .expect("hash engines don't fail");
+        }
+        engine.input(sha256::Hash::from_engine(script_sigs).as_byte_array());
+    }
+    engine.input(&(tx.input.len() as u32).to_le_bytes());
+    let mut sequences = sha256::Hash::engine();
+    for input in &tx.input {
+        sequences.input(&input.sequence.0.to_le_bytes());
+    }
+    engine.input(sha256::Hash::from_engine(sequences).as_byte_array());
+    engine.input(&(tx.output.len() as u32).to_le_bytes());
+    let mut outputs = sha256::Hash::engine();
+    for output in &tx.output {
+        output
+            .consensus_encode(&mut outputs)
+            .expect("hash engines don't fail");
+    }
+    engine.input(sha256::Hash::from_engine(outputs).as_byte_array());
+    engine.input(&input_index.to_le_bytes());
+    sha256::Hash::from_engine(engine).to_byte_array()
+}
+
+// Execute input `input_index` of `tx`, `prevouts` being the outputs every input spends in order
+pub fn verify_input(tx: &Transaction, input_index: usize, prevouts: &[TxOut]) -> Result<()> {
+    let secp = Secp256k1::verification_only();
+    let input = tx
+        .input
+        .get(input_index)
+        .ok_or_else(|| anyhow!("the tx has no input {}", input_index))?;
+    if prevouts.len() != tx.input.len() {
+        bail!("{} prevouts for {} inputs", prevouts.len(), tx.input.len());
+    }
+    let prevout = &prevouts[input_index];
+    if !prevout.script_pubkey.is_p2tr() {
+        bail!("input {} doesn't spend a taproot output", input_index);
+    }
+    let output_key = XOnlyPublicKey::from_slice(&prevout.script_pubkey.as_bytes()[2..34])?;
+
+    let mut witness: Stack = input.witness.to_vec();
+    if witness.len() >= 2
+        && witness
+            .last()
+            .is_some_and(|item| item.first() == Some(&TAPROOT_ANNEX_PREFIX))
+    {
+        bail!("input {} has an annex, no pool spend uses one", input_index);
+    }
+    match witness.len() {
+        0 => bail!("input {} has an empty witness", input_index),
+        1 => {
+            // key path
+            let (signature, sighash_type) = parse_signature(&witness[0])?;
+            let sighash = SighashCache::new(tx).taproot_key_spend_signature_hash(
+                input_index,
+                &Prevouts::All(prevouts),
+                sighash_type,
+            )?;
+            secp.verify_schnorr(
+                &signature,
+                &Message::from_digest(sighash.to_byte_array()),
+                &output_key,
+            )
+            .map_err(|_| anyhow!("the key path signature doesn't verify"))
+        }
+        _ => {
+            let control_block = ControlBlock::decode(&witness.pop().expect("two items"))
+                .map_err(|e| anyhow!("the control block doesn't decode: {}", e))?;
+            let script = bitcoin::ScriptBuf::from_bytes(witness.pop().expect("one item left"));
+            if !control_block.verify_taproot_commitment(&secp, output_key, &script) {
+                bail!("the control block doesn't commit the leaf script to the output key");
+            }
+            if control_block.leaf_version != LeafVersion::TapScript {
+                bail!(
+                    "leaf version {} isn't tapscript, there is nothing to execute",
+                    control_block.leaf_version
+                );
+            }
+            if let Some(item) = witness.iter().find(|item| item.len() > MAX_ELEMENT_SIZE) {
+                bail!("a {} byte witness item is over the limit", item.len());
+            }
+            let checker = Checker {
+                secp: &secp,
+                tx,
+                input_index,
+                prevouts,
+                leaf_hash: TapLeafHash::from_script(&script, LeafVersion::TapScript),
+            };
+            execute(&script, witness, &checker)
+        }
+    }
+}
+
+struct Checker<'a, C: Verification> {
+    secp: &'a Secp256k1<C>,
+    tx: &'a Transaction,
+    input_index: usize,
+    prevouts: &'a [TxOut],
+    leaf_hash: TapLeafHash,
+}
+
+impl<C: Verification> Checker<'_, C> {
+    // BIP-342: an empty signature is a failed check, a bad one fails the script, keys other than 32
+    // bytes are upgradable and pass
+    fn check_sig(&self, signature: &[u8], key: &[u8]) -> Result<bool> {
+        if key.is_empty() {
+            bail!("OP_CHECKSIG with an empty key");
+        }
+        if signature.is_empty() {
+            return Ok(false);
+        }
+        if key.len() != 32 {
+            return Ok(true);
+        }
+        let key = XOnlyPublicKey::from_slice(key)?;
+        let (signature, sighash_type) = parse_signature(signature)?;
+        let sighash = SighashCache::new(self.tx).taproot_script_spend_signature_hash(
+            self.input_index,
+            &Prevouts::All(self.prevouts),
+            self.leaf_hash,
+            sighash_type,
+        )?;
+        self.secp
+            .verify_schnorr(
+                &signature,
+                &Message::from_digest(sighash.to_byte_array()),
+                &key,
+            )
+            .map_err(|_| anyhow!("a signature doesn't verify against key {}", key))?;
+        Ok(true)
+    }
+
+    // BIP-119, only 32 byte arguments are templates, other sizes are left for upgrades
+    fn check_template(&self, argument: &[u8]) -> Result<()> {
+        if argument.len() != 32 {
+            return Ok(());
+        }
+        let hash = template_hash(self.tx, self.input_index as u32);
+        if argument != hash {
+            bail!(
+                "OP_CTV: the leaf commits to template {}, the tx hashes to {}",
+                argument.to_lower_hex_string(),
+                hash.to_lower_hex_string()
+            );
+        }
+        Ok(())
+    }
+
+    // BIP-112
+    fn check_sequence(&self, blocks: i64) -> Result<()> {
+        const DISABLE: i64 = 1 << 31;
+        const TYPE: i64 = 1 << 22;
+        const MASK: i64 = TYPE | 0xffff;
+        if blocks < 0 {
+            bail!("OP_CSV with a negative argument");
+        }
+        if blocks & DISABLE != 0 {
+            return Ok(());
+        }
+        if self.tx.version.0 < 2 {
+            bail!("OP_CSV needs tx version 2 or more");
+        }
+        let sequence = i64::from(self.tx.input[self.input_index].sequence.0);
+        if sequence & DISABLE != 0 || sequence & TYPE != blocks & TYPE {
+            bail!(
+                "OP_CSV {}: the input's sequence {:#x} is no relative lock of the same kind",
+                blocks,
+                sequence
+            );
+        }
+        if blocks & MASK > sequence & MASK {
+            bail!(
+                "OP_CSV {}: the input's sequence only waits {}",
+                blocks & MASK,
+                sequence & MASK
+            );
+        }
+        Ok(())
+    }
+
+    // BIP-65
+    fn check_locktime(&self, locktime: i64) -> Result<()> {
+        const THRESHOLD: i64 = 500_000_000;
+        if locktime < 0 {
+            bail!("OP_CLTV with a negative argument");
+        }
+        let tx_locktime = i64::from(self.tx.lock_time.to_consensus_u32());
+        if (locktime < THRESHOLD) != (tx_locktime < THRESHOLD) || locktime > tx_locktime {
+            bail!("OP_CLTV {}: the tx's locktime is {}", locktime, tx_locktime);
+        }
+        if self.tx.input[self.input_index].sequence.is_final() {
+            bail!("OP_CLTV with a final input sequence");
+        }
+        Ok(())
+    }
+}
+
+// a 64 byte signature is SIGHASH_DEFAULT, 65 bytes carry their type, which can't be 0
+fn parse_signature(bytes: &[u8]) -> Result<(schnorr::Signature, TapSighashType)> {
+    match bytes.len() {
+        64 => Ok((
+            schnorr::Signature::from_slice(bytes)?,
+            TapSighashType::Default,
+        )),
+        65 if bytes[64] != 0 => Ok((
+            schnorr::Signature::from_slice(&bytes[..64])?,
+            TapSighashType::from_consensus_u8(bytes[64])?,
+        )),
+        len => bail!("a {} byte signature", len),
+    }
+}
+
+fn cast_to_bool(bytes: &[u8]) -> bool {
+    match bytes.split_last() {
+        // negative zero is false too
+        Some((last, rest)) => rest.iter().any(|b| *b != 0) || (*last != 0 && *last != 0x80),
+        None => false,
+    }
+}
+
+// a minimally encoded script number of at most `max_len` bytes
+fn read_num(bytes: &[u8], max_len: usize) -> Result<i64> {
+    if bytes.len() > max_len {
+        bail!("a {} byte number, at most {} allowed", bytes.len(), max_len);
+    }
+    let Some((last, rest)) = bytes.split_last() else {
+        return Ok(0);
+    };
+    if *last & 0x7f == 0 && rest.last().is_none_or(|b| *b & 0x80 == 0) {
+        bail!(
+            "number {} isn't minimally encoded",
+            bytes.to_lower_hex_string()
+        );
+    }
+    let mut value: i64 = 0;
+    for (i, byte) in bytes.iter().enumerate() {
+        value |= i64::from(*byte) << (8 * i);
+    }
+    if last & 0x80 != 0 {
+        value &= !(0x80 << (8 * rest.len()));
+        value = -value;
+    }
+    Ok(value)
+}
+
+fn num_bytes(value: i64) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    let negative = value < 0;
+    let mut abs = value.unsigned_abs();
+    while abs > 0 {
+        bytes.push((abs & 0xff) as u8);
+        abs >>= 8;
+    }
+    match bytes.last_mut() {
+        Some(last) if *last & 0x80 != 0 => bytes.push(if negative { 0x80 } else { 0 }),
+        Some(last) if negative => *last |= 0x80,
+        _ => {}
+    }
+    bytes
+}
+
+fn pop(stack: &mut Stack, op: Opcode) -> Result<Vec<u8>> {
+    stack
+        .pop()
+        .ok_or_else(|| anyhow!("{} on an empty stack", op))
+}
+
+fn pop_num(stack: &mut Stack, op: Opcode) -> Result<i64> {
+    read_num(&pop(stack, op)?, 4)
+}
+
+fn top(stack: &Stack, depth: usize, op: Opcode) -> Result<Vec<u8>> {
+    stack
+        .len()
+        .checked_sub(depth + 1)
+        .map(|i| stack[i].clone())
+        .ok_or_else(|| anyhow!("{} needs {} stack items", op, depth + 1))
+}
+
+fn push_bool(stack: &mut Stack, value: bool) {
+    stack.push(if value { vec![1] } else { Vec::new() });
+}
+
+fn execute<C: Verification>(script: &Script, mut stack: Stack, checker: &Checker<C>) -> Result<()> {
+    // BIP-342: any OP_SUCCESS anywhere makes the leaf spendable by anyone, decoding has to succeed
+    for instruction in script.instructions() {
+        if let Instruction::Op(op) = instruction? {
+            if op.classify(ClassifyContext::TapScript) == Class::SuccessOp {
+                bail!("{} makes the leaf spendable by anyone", op);
+            }
+        }
+    }
+
+    let mut alt: Stack = Vec::new();
+    // the branches of the open OP_IFs, executing while all are true
+    let mut branches: Vec<bool> = Vec::new();
+    for instruction in script.instructions() {
+        let executing = branches.iter().all(|taken| *taken);
+        let op = match instruction? {
+            Instruction::PushBytes(bytes) => {
+                if bytes.len() > MAX_ELEMENT_SIZE {
+                    bail!("a {} byte push is over the limit", bytes.len());
+                }
+                if executing {
+                    stack.push(bytes.as_bytes().to_vec());
+                }
+                continue;
+            }
+            Instruction::Op(op) => op,
+        };
+        if !executing && !matches!(op, OP_IF | OP_NOTIF | OP_ELSE | OP_ENDIF) {
+            if op.classify(ClassifyContext::TapScript) == Class::IllegalOp {
+                bail!("{} is illegal even unexecuted", op);
+            }
+            continue;
+        }
+        match op {
+            OP_NOP4 => {
+                let argument = top(&stack, 0, op)?;
+                checker.check_template(&argument)?;
+            }
+            OP_CSV => checker.check_sequence(read_num(&top(&stack, 0, op)?, 5)?)?,
+            OP_CLTV => checker.check_locktime(read_num(&top(&stack, 0, op)?, 5)?)?,
+            OP_IF | OP_NOTIF => {
+                let taken = if executing {
+                    let condition = pop(&mut stack, op)?;
+                    // MINIMALIF is consensus in tapscript
+                    let value = match condition.as_slice() {
+                        [] => false,
+                        [1] => true,
+                        _ => bail!(
+                            "{} argument {} isn't empty or 1",
+                            op,
+                            condition.to_lower_hex_string()
+                        ),
+                    };
+                    value == (op == OP_IF)
+                } else {
+                    false
+                };
+                branches.push(taken);
+            }
+            OP_ELSE => {
+                let taken = branches
+                    .last_mut()
+                    .ok_or_else(|| anyhow!("OP_ELSE without OP_IF"))?;
+                *taken = !*taken;
+            }
+            OP_ENDIF => {
+                branches
+                    .pop()
+                    .ok_or_else(|| anyhow!("OP_ENDIF without OP_IF"))?;
+            }
+            OP_VERIFY => {
+                if !cast_to_bool(&pop(&mut stack, op)?) {
+                    bail!("OP_VERIFY failed");
+                }
+            }
+            OP_TOALTSTACK => {
+                let item = pop(&mut stack, op)?;
+                alt.push(item);
+            }
+            OP_FROMALTSTACK => {
+                let item = alt
+                    .pop()
+                    .ok_or_else(|| anyhow!("OP_FROMALTSTACK on an empty alt stack"))?;
+                stack.push(item);
+            }
+            OP_DROP => {
+                pop(&mut stack, op)?;
+            }
+            OP_2DROP => {
+                pop(&mut stack, op)?;
+                pop(&mut stack, op)?;
+            }
+            OP_DUP => stack.push(top(&stack, 0, op)?),
+            OP_2DUP => {
+                let (a, b) = (top(&stack, 1, op)?, top(&stack, 0, op)?);
+                stack.extend([a, b]);
+            }
+            OP_IFDUP => {
+                let item = top(&stack, 0, op)?;
+                if cast_to_bool(&item) {
+                    stack.push(item);
+                }
+            }
+            OP_DEPTH => stack.push(num_bytes(stack.len() as i64)),
+            OP_NIP => {
+                let item = pop(&mut stack, op)?;
+                pop(&mut stack, op)?;
+                stack.push(item);
+            }
+            OP_OVER => stack.push(top(&stack, 1, op)?),
+            OP_SWAP => {
+                top(&stack, 1, op)?;
+                let len = stack.len();
+                stack.swap(len - 1, len - 2);
+            }
+            OP_TUCK => {
+                let item = top(&stack, 0, op)?;
+                top(&stack, 1, op)?;
+                stack.insert(stack.len() - 2, item);
+            }
+            OP_ROT => {
+                top(&stack, 2, op)?;
+                let item = stack.remove(stack.len() - 3);
+                stack.push(item);
+            }
+            OP_SIZE => stack.push(num_bytes(top(&stack, 0, op)?.len() as i64)),
+            OP_EQUAL | OP_EQUALVERIFY => {
+                let (b, a) = (pop(&mut stack, op)?, pop(&mut stack, op)?);
+                if op == OP_EQUALVERIFY {
+                    if a != b {
+                        bail!("OP_EQUALVERIFY failed");
+                    }
+                } else {
+                    push_bool(&mut stack, a == b);
+                }
+            }
+            OP_1ADD | OP_1SUB | OP_NEGATE | OP_ABS | OP_NOT | OP_0NOTEQUAL => {
+                let a = pop_num(&mut stack, op)?;
+                let value = match op {
+                    OP_1ADD => a + 1,
+                    OP_1SUB => a - 1,
+                    OP_NEGATE => -a,
+                    OP_ABS => a.abs(),
+                    OP_NOT => (a == 0) as i64,
+                    _ => (a != 0) as i64,
+                };
+                stack.push(num_bytes(value));
+            }
+            OP_ADD
+            | OP_SUB
+            | OP_BOOLAND
+            | OP_BOOLOR
+            | OP_NUMEQUAL
+            | OP_NUMEQUALVERIFY
+            | OP_NUMNOTEQUAL
+            | OP_LESSTHAN
+            | OP_GREATERTHAN
+            | OP_LESSTHANOREQUAL
+            | OP_GREATERTHANOREQUAL
+            | OP_MIN
+            | OP_MAX => {
+                let (b, a) = (pop_num(&mut stack, op)?, pop_num(&mut stack, op)?);
+                let value = match op {
+                    OP_ADD => a + b,
+                    OP_SUB => a - b,
+                    OP_BOOLAND => (a != 0 && b != 0) as i64,
+                    OP_BOOLOR => (a != 0 || b != 0) as i64,
+                    OP_NUMEQUAL | OP_NUMEQUALVERIFY => (a == b) as i64,
+                    OP_NUMNOTEQUAL => (a != b) as i64,
+                    OP_LESSTHAN => (a < b) as i64,
+                    OP_GREATERTHAN => (a > b) as i64,
+                    OP_LESSTHANOREQUAL => (a <= b) as i64,
+                    OP_GREATERTHANOREQUAL => (a >= b) as i64,
+                    OP_MIN => a.min(b),
+                    _ => a.max(b),
+                };
+                if op == OP_NUMEQUALVERIFY {
+                    if value == 0 {
+                        bail!("OP_NUMEQUALVERIFY failed");
+                    }
+                } else {
+                    stack.push(num_bytes(value));
+                }
+            }
+            OP_WITHIN => {
+                let max = pop_num(&mut stack, op)?;
+                let min = pop_num(&mut stack, op)?;
+                let x = pop_num(&mut stack, op)?;
+                push_bool(&mut stack, min <= x && x < max);
+            }
+            OP_SHA256 | OP_HASH160 | OP_HASH256 | OP_RIPEMD160 | OP_SHA1 => {
+                let item = pop(&mut stack, op)?;
+                stack.push(match op {
+                    OP_SHA256 => sha256::Hash::hash(&item).to_byte_array().to_vec(),
+                    OP_HASH160 => hash160::Hash::hash(&item).to_byte_array().to_vec(),
+                    OP_HASH256 => sha256d::Hash::hash(&item).to_byte_array().to_vec(),
+                    OP_RIPEMD160 => ripemd160::Hash::hash(&item).to_byte_array().to_vec(),
+                    _ => sha1::Hash::hash(&item).to_byte_array().to_vec(),
+                });
+            }
+            OP_CHECKSIG | OP_CHECKSIGVERIFY => {
+                let key = pop(&mut stack, op)?;
+                let signature = pop(&mut stack, op)?;
+                let valid = checker.check_sig(&signature, &key)?;
+                if op == OP_CHECKSIGVERIFY {
+                    if !valid {
+                        bail!("OP_CHECKSIGVERIFY with an empty signature");
+                    }
+                } else {
+                    push_bool(&mut stack, valid);
+                }
+            }
+            OP_CHECKSIGADD => {
+                let key = pop(&mut stack, op)?;
+                let n = pop_num(&mut stack, op)?;
+                let signature = pop(&mut stack, op)?;
+                let valid = checker.check_sig(&signature, &key)?;
+                stack.push(num_bytes(n + valid as i64));
+            }
+            op => match op.classify(ClassifyContext::TapScript) {
+                Class::PushNum(n) => stack.push(num_bytes(n.into())),
+                Class::NoOp => {}
+                Class::ReturnOp | Class::IllegalOp => bail!("{} fails the script", op),
+                _ => bail!("{} is not an opcode this interpreter executes", op),
+            },
+        }
+        if stack.len() + alt.len() > MAX_STACK_SIZE {
+            bail!("the stack grew over {} items", MAX_STACK_SIZE);
+        }
+    }
+    if !branches.is_empty() {
+        bail!("OP_IF without OP_ENDIF");
+    }
+    // clean stack: exactly one true item
+    match stack.as_slice() {
+        [item] if cast_to_bool(item) => Ok(()),
+        [_] => bail!("the script left false on the stack"),
+        items => bail!("the script left {} items on the stack, not 1", items.len()),
+    }
+}
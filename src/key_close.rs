@@ -0,0 +1,481 @@
+use std::{
+    fs,
+    path::Path,
+    str::FromStr,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{anyhow, bail, Result};
+use bitcoin::{
+    absolute,
+    hashes::Hash,
+    secp256k1::{PublicKey, SecretKey},
+    sighash::{Prevouts, SighashCache, TapSighashType},
+    taproot::{self, TapNodeHash},
+    transaction, Address, Amount, Network, OutPoint, Sequence, Transaction, TxIn, TxOut, Witness,
+};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use crate::{
+    config::DUST_AMOUNT,
+    ctv_scripts::close_all_fee_share,
+    error_codes::{coded, ErrorCode},
+    export::PoolExport,
+    ids::{NodePath, UserIndex},
+    lifecycle::Lifecycle,
+    manifest::{LoadedPool, PoolManifest},
+    musig::{
+        member_keys, nonce_gen, AggNonce, KeyAggContext, PartialSig, PubNonce, SecNonce,
+        SigningSession,
+    },
+    payouts::{payout_outputs, split_total, user_share},
+    rpc_helper::fee_for_vsize,
+};
+
+pub const DEFAULT_KEY_CLOSE_SESSION: &str = "key_close.json";
+// where a member keeps the secret half of their nonce between `nonce` and `sign`
+pub const DEFAULT_KEY_CLOSE_NONCE: &str = "key_close_nonce.json";
+// how long the members have to sign before the coordinator falls back to the leaves
+pub const DEFAULT_KEY_CLOSE_TIMEOUT_SECS: u64 = 24 * 60 * 60;
+
+// A key path close of the pool root, passed between the coordinator and the members as a file:
+// the coordinator starts it, every member adds a nonce, then once all nonces are in a partial
+// signature, and the coordinator aggregates them into the one signature the spend carries.
+// Nothing about it is committed in the tree, so it pays whatever fee it is started with.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KeyCloseSession {
+    pub network: Network,
+    pub root_address: String,
+    // the users the pool is funded for, in signing order, and each one's musig key
+    pub members: NodePath,
+    pub keys: Vec<PublicKey>,
+    // each member's withdraw address, for them to find their payout by
+    pub addresses: Vec<String>,
+    // the root's tap tree, the aggregate key is tweaked with it like any taproot internal key
+    pub merkle_root: Option<TapNodeHash>,
+    pub tx: Transaction,
+    pub prevout: TxOut,
+    // unix time after which the coordinator stops waiting and falls back to the leaves
+    pub deadline: u64,
+    // by position in `members`
+    pub nonces: Vec<Option<PubNonce>>,
+    pub partial_sigs: Vec<Option<PartialSig>>,
+}
+
+// the secret nonce a member made for a session, tied to the message it may sign
+#[derive(Debug, Serialize, Deserialize)]
+pub struct KeyCloseNonce {
+    pub sighash: String,
+    pub nonce: SecNonce,
+}
+
+// What the coordinator does when the members didn't all sign by the deadline: the close-all leaf
+// if the pool has one, otherwise the first exit of the planned unwind
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyCloseFallback {
+    CloseAll,
+    Unwind(UserIndex),
+}
+
+pub fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or_default()
+}
+
+// every member paid their share less an even part of `fee`, and their splits
+fn key_close_outputs(pool: &LoadedPool, members: &NodePath, fee: Amount) -> Result<Vec<TxOut>> {
+    let share = close_all_fee_share(fee, members.len());
+    let mut outputs = Vec::new();
+    for user in members.user_indices() {
+        let address = &pool.addresses[user.index()];
+        let splits = pool
+            .config
+            .splits
+            .get(&address.to_string())
+            .map(Vec::as_slice)
+            .unwrap_or_default();
+        // like an exit, the address itself is left at least dust after its splits
+        let amount = user_share(user, &pool.config)
+            .checked_sub(share)
+            .filter(|amount| *amount >= split_total(splits) + DUST_AMOUNT)
+            .ok_or_else(|| anyhow!("user {} can't pay {} of the close's fee", user, share))?;
+        outputs.extend(payout_outputs(address, amount, &pool.config));
+    }
+    Ok(outputs)
+}
+
+impl KeyCloseSession {
+    // The close of the pool root output in `funding_tx` at `fee_rate` sat/vB, open until `deadline`
+    pub fn start(
+        manifest: &PoolManifest,
+        pool: &LoadedPool,
+        funding_tx: &Transaction,
+        fee_rate: u64,
+        deadline: u64,
+    ) -> Result<Self> {
+        // once a user left by their leaf the root is spent
+        if manifest.lifecycle != Lifecycle::Funded {
+            return Err(coded(
+                ErrorCode::InvalidState,
+                format!(
+                    "the pool is {}, only a funded pool nobody has left yet closes through the root's key path",
+                    manifest.lifecycle
+                ),
+            ));
+        }
+        let members = pool.config.pool_root()?;
+        let keys = member_keys(&members, &pool.addresses, &pool.config.musig_keys)
+            .ok_or_else(|| anyhow!("the users registered no musig keys, the root can only be spent through its leaves"))?;
+        let spend_info = pool.tree.spend_info(&members)?;
+        let root = Address::p2tr_tweaked(spend_info.output_key(), pool.config.network);
+        let (vout, prevout) = funding_tx
+            .output
+            .iter()
+            .enumerate()
+            .find(|(_, output)| output.script_pubkey == root.script_pubkey())
+            .ok_or_else(|| {
+                anyhow!(
+                    "{} doesn't fund the pool root {}",
+                    funding_tx.compute_txid(),
+                    root
+                )
+            })?;
+
+        let mut session = Self {
+            network: pool.config.network,
+            root_address: root.to_string(),
+            members: members.clone(),
+            keys,
+            addresses: members
+                .users()
+                .iter()
+                .map(|&user| pool.addresses[user].to_string())
+                .collect(),
+            merkle_root: spend_info.merkle_root(),
+            tx: Transaction {
+                version: transaction::Version::TWO,
+                lock_time: absolute::LockTime::ZERO,
+                input: vec![TxIn {
+                    previous_output: OutPoint::new(funding_tx.compute_txid(), vout as u32),
+                    sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+                    // a key path spend is one signature
+                    witness: Witness::from_slice(&[[0u8; 64]]),
+                    ..Default::default()
+                }],
+                output: key_close_outputs(pool, &members, Amount::ZERO)?,
+            },
+            prevout: prevout.clone(),
+            deadline,
+            nonces: vec![None; members.len()],
+            partial_sigs: vec![None; members.len()],
+        };
+        if session.context()?.x_only_key() != spend_info.output_key().to_x_only_public_key() {
+            bail!("the members' aggregate key doesn't tweak to the pool root's output key");
+        }
+        let fee = fee_for_vsize(fee_rate.saturating_mul(1000), session.tx.vsize() as u64)?;
+        session.tx.output = key_close_outputs(pool, &members, fee)?;
+        session.tx.input[0].witness = Witness::new();
+        info!(
+            "key path close of {} started: {} members, {} fee, signed by {} \n",
+            session.root_address,
+            members.len(),
+            fee,
+            deadline
+        );
+        Ok(session)
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
+    }
+
+    pub fn write(&self, path: &Path) -> Result<()> {
+        fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    // the aggregate of the members' keys, tweaked to the root's output key
+    fn context(&self) -> Result<KeyAggContext> {
+        let mut context = KeyAggContext::new(&self.keys)?;
+        context.tap_tweak(self.merkle_root)?;
+        Ok(context)
+    }
+
+    // what the signature commits to: BIP-341's key path sighash of the close
+    pub fn sighash(&self) -> Result<[u8; 32]> {
+        Ok(SighashCache::new(&self.tx)
+            .taproot_key_spend_signature_hash(
+                0,
+                &Prevouts::All(&[&self.prevout]),
+                TapSighashType::Default,
+            )?
+            .to_byte_array())
+    }
+
+    fn position(&self, user: UserIndex) -> Result<usize> {
+        self.members
+            .users()
+            .iter()
+            .position(|&member| member == user.index())
+            .ok_or_else(|| anyhow!("user {} is not in the pool of users {}", user, self.members))
+    }
+
+    pub fn expired(&self, now: u64) -> bool {
+        now > self.deadline
+    }
+
+    // members whose nonce or, once every nonce is in, partial signature the session still waits for
+    pub fn missing(&self) -> Vec<UserIndex> {
+        let signing = self.nonces.iter().all(Option::is_some);
+        self.members
+            .user_indices()
+            .zip(self.nonces.iter().zip(&self.partial_sigs))
+            .filter(|(_, (nonce, sig))| {
+                if signing {
+                    sig.is_none()
+                } else {
+                    nonce.is_none()
+                }
+            })
+            .map(|(user, _)| user)
+            .collect()
+    }
+
+    // `user`'s nonce for the close, the secret half comes back for them to keep until they sign
+    pub fn add_nonce(&mut self, user: UserIndex, secret: &SecretKey) -> Result<KeyCloseNonce> {
+        let position = self.position(user)?;
+        if self.partial_sigs.iter().any(Option::is_some) {
+            bail!("the members are signing already, a new nonce would void their signatures");
+        }
+        let (nonce, public) = nonce_gen(
+            secret,
+            &self.context()?.x_only_key(),
+            &self.sighash()?,
+            random_bytes(),
+        )?;
+        if nonce.key() != self.keys[position] {
+            bail!(
+                "user {} registered musig key {}, not {}",
+                user,
+                self.keys[position],
+                nonce.key()
+            );
+        }
+        self.nonces[position] = Some(public);
+        Ok(KeyCloseNonce {
+            sighash: hex_sighash(&self.sighash()?),
+            nonce,
+        })
+    }
+
+    fn signing_session(&self) -> Result<SigningSession> {
+        let nonces = self
+            .nonces
+            .iter()
+            .copied()
+            .collect::<Option<Vec<_>>>()
+            .ok_or_else(|| anyhow!("users {:?} have no nonce in yet", self.missing()))?;
+        SigningSession::new(self.context()?, &AggNonce::new(&nonces)?, self.sighash()?)
+    }
+
+    // `user`'s partial signature with the nonce they made for this session
+    pub fn sign(
+        &mut self,
+        user: UserIndex,
+        secret: &SecretKey,
+        nonce: KeyCloseNonce,
+    ) -> Result<()> {
+        let position = self.position(user)?;
+        if nonce.sighash != hex_sighash(&self.sighash()?) {
+            bail!("the nonce was made for another close, make a new one");
+        }
+        if self.nonces[position] != Some(nonce.nonce.public()) {
+            bail!("the session has another nonce of user {}", user);
+        }
+        self.partial_sigs[position] = Some(self.signing_session()?.sign(nonce.nonce, secret)?);
+        Ok(())
+    }
+
+    // The signed close: every partial signature checked, then aggregated into the key path witness
+    pub fn finish(&self) -> Result<Transaction> {
+        let session = self.signing_session()?;
+        let mut sigs = Vec::new();
+        for ((user, key), (nonce, sig)) in self
+            .members
+            .user_indices()
+            .zip(&self.keys)
+            .zip(self.nonces.iter().zip(&self.partial_sigs))
+        {
+            let sig =
+                sig.ok_or_else(|| anyhow!("users {:?} haven't signed yet", self.missing()))?;
+            let nonce = nonce.expect("every nonce is in");
+            session
+                .verify(&sig, &nonce, key)
+                .map_err(|e| anyhow!("user {}: {}", user, e))?;
+            sigs.push(sig);
+        }
+        let signature = taproot::Signature {
+            signature: session.aggregate(&sigs)?,
+            sighash_type: TapSighashType::Default,
+        };
+        let mut tx = self.tx.clone();
+        tx.input[0].witness = Witness::p2tr_key_spend(&signature);
+        Ok(tx)
+    }
+
+    // the fee the close pays, the sighash commits to the prevout's value so the members can trust it
+    pub fn fee(&self) -> Result<Amount> {
+        let paid: Amount = self.tx.output.iter().map(|output| output.value).sum();
+        self.prevout
+            .value
+            .checked_sub(paid)
+            .ok_or_else(|| anyhow!("the close pays out more than the root holds"))
+    }
+
+    // sat/vB of the signed close
+    pub fn fee_rate(&self) -> Result<u64> {
+        let mut signed = self.tx.clone();
+        signed.input[0].witness = Witness::from_slice(&[[0u8; 64]]);
+        Ok(self.fee()?.to_sat() / signed.vsize() as u64)
+    }
+
+    // A member's check before signing: the close spends their pool's root and pays the withdraw
+    // address the session gives them what their exit leaf does, less an even part of a fee of at
+    // most `max_fee_rate` sat/vB. The address has to be paid by a leaf of their export, by no other
+    // member's entry and not be a pool node
+    pub fn check_payout(
+        &self,
+        export: &PoolExport,
+        user: UserIndex,
+        max_fee_rate: u64,
+    ) -> Result<()> {
+        if self.fee_rate()? > max_fee_rate {
+            bail!(
+                "the close pays {} sat/vB, more than {}",
+                self.fee_rate()?,
+                max_fee_rate
+            );
+        }
+        if self.root_address != export.root_address {
+            bail!(
+                "the close spends {}, your pool's root is {}",
+                self.root_address,
+                export.root_address
+            );
+        }
+        let root = Address::from_str(&self.root_address)?.require_network(export.network)?;
+        if self.prevout.script_pubkey != root.script_pubkey() {
+            bail!("the close's prevout isn't the pool root");
+        }
+        let position = self.position(user)?;
+        let address = &self.addresses[position];
+        if self
+            .addresses
+            .iter()
+            .filter(|other| *other == address)
+            .count()
+            > 1
+        {
+            bail!("the session gives {} to more than one member", address);
+        }
+        let script = Address::from_str(address)?
+            .require_network(export.network)?
+            .script_pubkey();
+        let mut leaf_payouts = Vec::new();
+        for node in &export.nodes {
+            if Address::from_str(&node.address)?
+                .assume_checked()
+                .script_pubkey()
+                == script
+            {
+                bail!(
+                    "the session gives you pool node {} as your address",
+                    node.users
+                );
+            }
+            for leaf in &node.leaves {
+                leaf_payouts.extend(
+                    leaf.outputs
+                        .iter()
+                        .filter(|output| output.script_pubkey == script)
+                        .map(|output| output.value),
+                );
+            }
+        }
+        let owed = leaf_payouts
+            .into_iter()
+            .max()
+            .ok_or_else(|| anyhow!("no leaf of your export pays {}, it isn't yours", address))?;
+        let paid: Amount = self
+            .tx
+            .output
+            .iter()
+            .filter(|output| output.script_pubkey == script)
+            .map(|output| output.value)
+            .sum();
+        let share = close_all_fee_share(self.fee()?, self.members.len());
+        if paid + share < owed {
+            bail!(
+                "the close pays {} {} and {} of its fee, your exit leaf pays {}",
+                address,
+                paid,
+                share,
+                owed
+            );
+        }
+        Ok(())
+    }
+
+    // the leaves the coordinator falls back to
+    pub fn fallback(pool: &LoadedPool) -> Result<KeyCloseFallback> {
+        if pool.config.pool_root()?.is_root() && pool.tree.root()?.close_all.is_some() {
+            return Ok(KeyCloseFallback::CloseAll);
+        }
+        let first = pool
+            .config
+            .spenders()?
+            .first()
+            .copied()
+            .ok_or_else(|| anyhow!("the pool has nobody to unwind"))?;
+        Ok(KeyCloseFallback::Unwind(first))
+    }
+}
+
+fn hex_sighash(sighash: &[u8; 32]) -> String {
+    bitcoin::hex::DisplayHex::to_lower_hex_string(&sighash[..])
+}
+
+fn random_bytes() -> [u8; 32] {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    bytes
+}
+
+// the pool root spent through its key path, what the watcher tells a key close by: one signature
+// in the witness and every member's withdraw address paid
+pub fn is_key_close(
+    pool: &LoadedPool,
+    node: &NodePath,
+    outpoint: OutPoint,
+    tx: &Transaction,
+) -> bool {
+    if pool.config.musig_keys.is_empty() || pool.config.pool_root().ok().as_ref() != Some(node) {
+        return false;
+    }
+    let key_path = tx
+        .input
+        .iter()
+        .find(|input| input.previous_output == outpoint)
+        .is_some_and(|input| input.witness.len() == 1);
+    key_path
+        && node.user_indices().all(|user| {
+            let script = pool.addresses[user.index()].script_pubkey();
+            tx.output
+                .iter()
+                .any(|output| output.script_pubkey == script)
+        })
+}
@@ -0,0 +1,165 @@
+use std::{fs, path::Path};
+
+use anyhow::{anyhow, Result};
+use bitcoin::{Address, OutPoint, Txid};
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use crate::{config::NetworkConfig, ids::UserIndex, tree::PoolTree};
+
+// BIP-329 wallet label, one json object per line
+// https://github.com/bitcoin/bips/blob/master/bip-0329.mediawiki
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Bip329Label {
+    #[serde(rename = "type")]
+    pub kind: String,
+    #[serde(rename = "ref")]
+    pub reference: String,
+    pub label: String,
+}
+
+impl Bip329Label {
+    fn tx(txid: Txid, label: String) -> Self {
+        Self {
+            kind: "tx".to_string(),
+            reference: txid.to_string(),
+            label,
+        }
+    }
+
+    fn addr(address: &Address, label: String) -> Self {
+        Self {
+            kind: "addr".to_string(),
+            reference: address.to_string(),
+            label,
+        }
+    }
+
+    fn output(txid: Txid, vout: u32, label: String) -> Self {
+        Self {
+            kind: "output".to_string(),
+            reference: OutPoint { txid, vout }.to_string(),
+            label,
+        }
+    }
+}
+
+// Labels for every pool node address and every withdraw address, known before the pool is funded
+pub fn address_labels(
+    pools: &PoolTree,
+    config: &NetworkConfig,
+    addresses: &[Address],
+) -> Vec<Bip329Label> {
+    let mut labels = Vec::new();
+    for (users, node) in pools.iter_nodes() {
+        let address = node.address(config);
+        labels.push(Bip329Label::addr(
+            &address,
+            format!("ctv pool node, users {}", users),
+        ));
+    }
+
+    for (i, address) in addresses.iter().enumerate() {
+        labels.push(Bip329Label::addr(
+            address,
+            format!("ctv pool user {} withdraw address", i),
+        ));
+    }
+    labels
+}
+
+// Labels for the funding tx, every pool node address, every exit with its payouts and anchors.
+// `exits` is (user, txid) in unwind order, the last two users share the final exit.
+pub fn pool_labels(
+    pools: &PoolTree,
+    config: &NetworkConfig,
+    addresses: &[Address],
+    funding_txid: Txid,
+    exits: &[(UserIndex, Txid)],
+) -> Result<Vec<Bip329Label>> {
+    let members = config.pool_root()?;
+    let mut labels = vec![
+        Bip329Label::tx(funding_txid, "ctv pool funding".to_string()),
+        Bip329Label::output(
+            funding_txid,
+            0,
+            format!("ctv pool ({} users)", members.len()),
+        ),
+    ];
+    labels.extend(address_labels(pools, config, addresses));
+
+    for (spender, txid) in exits {
+        // how many users left before them
+        let left = members
+            .users()
+            .iter()
+            .position(|&user| user == spender.index())
+            .ok_or_else(|| anyhow!("user {} is not in the pool of users {}", spender, members))?;
+        let spender = spender.index();
+        // the last user is paid by the same tx as the one before
+        if left == members.len() - 1 {
+            labels.push(Bip329Label::output(
+                *txid,
+                1,
+                format!("ctv pool user {} payout", spender),
+            ));
+            continue;
+        }
+
+        if left == members.len() - 2 {
+            labels.push(Bip329Label::tx(
+                *txid,
+                format!(
+                    "ctv pool final exit, users {} and {}",
+                    spender,
+                    members.users()[left + 1]
+                ),
+            ));
+            labels.push(Bip329Label::output(
+                *txid,
+                0,
+                format!("ctv pool user {} payout", spender),
+            ));
+        } else {
+            labels.push(Bip329Label::tx(
+                *txid,
+                format!("ctv pool exit, user {}", spender),
+            ));
+            labels.push(Bip329Label::output(
+                *txid,
+                0,
+                format!("ctv pool ({} users)", members.len() - left - 1),
+            ));
+            labels.push(Bip329Label::output(
+                *txid,
+                1,
+                format!("ctv pool user {} payout", spender),
+            ));
+        }
+
+        if config.anchor_amount.is_some() {
+            labels.push(Bip329Label::output(
+                *txid,
+                2,
+                format!("ctv pool fee anchor, exit of user {}", spender),
+            ));
+        }
+    }
+
+    Ok(labels)
+}
+
+pub fn write_labels(labels: &[Bip329Label], path: &Path) -> Result<()> {
+    let mut jsonl = String::new();
+    for label in labels {
+        jsonl.push_str(&serde_json::to_string(label)?);
+        jsonl.push('\n');
+    }
+    fs::write(path, jsonl)?;
+    info!(
+        "{} BIP-329 wallet labels written to {} \n",
+        labels.len(),
+        path.display()
+    );
+    Ok(())
+}
@@ -0,0 +1,84 @@
+// the pool logic, the binary in main.rs is just the cli around it
+pub mod abort;
+pub mod addresses;
+pub mod bip322;
+pub mod broadcast;
+pub mod canonical;
+pub mod checkpoints;
+pub mod cli;
+pub mod close;
+pub mod cold;
+pub mod config;
+pub mod costs;
+pub mod covenant;
+pub mod ctv_scripts;
+pub mod debug;
+pub mod demo;
+pub mod doctor;
+#[cfg(feature = "elements")]
+pub mod elements;
+pub mod error_codes;
+pub mod esplora;
+pub mod event_stream;
+pub mod explain;
+pub mod export;
+pub mod faucet;
+pub mod fee_bump;
+pub mod fee_policy;
+pub mod fetch;
+pub mod footprint;
+pub mod formats;
+pub mod fund;
+pub mod ids;
+pub mod interpreter;
+pub mod key_close;
+pub mod labels;
+pub mod lifecycle;
+pub mod manifest;
+pub mod member;
+pub mod membership;
+pub mod mempool;
+pub mod metadata;
+pub mod metrics;
+pub mod miner;
+pub mod mock;
+pub mod musig;
+pub mod next_step;
+pub mod p2p;
+pub mod packages;
+pub mod payouts;
+pub mod payroll;
+pub mod policy;
+pub mod pools;
+pub mod privacy;
+pub mod profile;
+pub mod progress;
+pub mod proxy;
+pub mod psbt_v2;
+pub mod publish;
+pub mod receipts;
+pub mod recovery;
+pub mod redact;
+pub mod registration;
+pub mod removal;
+pub mod report;
+pub mod research;
+pub mod reservations;
+pub mod reserves;
+pub mod rpc_helper;
+pub mod serve;
+pub mod simulate;
+pub mod spill;
+pub mod standardness;
+pub mod state;
+pub mod template;
+pub mod template_fees;
+pub mod tree;
+pub mod user_anchor;
+pub mod vault;
+pub mod verify;
+pub mod wallet_coins;
+pub mod watch;
+pub mod webhooks;
+
+pub use config::{AMOUNT_PER_USER, POOL_USERS};
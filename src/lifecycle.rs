@@ -0,0 +1,172 @@
+use std::fmt;
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error_codes::{coded, ErrorCode},
+    ids::{NodePath, UserIndex},
+    POOL_USERS,
+};
+
+// Where a pool is in its life, kept in the manifest so every command can check it is allowed to run.
+// Users leave in tree order, so the number of withdrawals is also the level of the live pool node.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Lifecycle {
+    // taking registrations, not every user is known yet
+    Draft,
+    // the tree is built and its root address can be funded
+    #[default]
+    Registered,
+    // the funding tx is out, nobody has left yet
+    Funded,
+    // this many users have withdrawn
+    Unwinding(usize),
+    // the final withdrawal paid out the last two users
+    Closed,
+    // a recovery sweep took what was left
+    Recovered,
+    // checked against the chain and moved to the archive by `close`, nothing more can happen to it
+    Archived,
+    // given up by `abort` before the funding confirmed, the funding tx (if any) was replaced
+    Cancelled,
+}
+
+// what happens to a pool, each one only allowed from some states
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    Register,
+    Fund,
+    Withdraw(UserIndex),
+    // the close-all leaf paid every user at once, only from the root
+    CloseAll,
+    // the members signed the root's key path together, see key_close.rs
+    KeyClose,
+    Recover,
+    // every sat of a finished pool is accounted for, see close.rs
+    Archive,
+    // the funding never confirmed and never will, see abort.rs
+    Abort,
+}
+
+impl Lifecycle {
+    // the state after `event`, or why it can't happen now
+    pub fn apply(self, event: Event) -> Result<Self> {
+        self.apply_in(event, &NodePath::root())
+    }
+
+    // apply for a pool funded at `pool`, which is short of the participants removed before funding
+    pub fn apply_in(self, event: Event, pool: &NodePath) -> Result<Self> {
+        let next = match (self, event) {
+            (Self::Draft, Event::Register) => Self::Registered,
+            (Self::Registered, Event::Fund) => Self::Funded,
+            (Self::Funded, Event::Withdraw(spender)) => Self::withdrawn(0, spender, pool)?,
+            (Self::Unwinding(left), Event::Withdraw(spender)) => {
+                Self::withdrawn(left, spender, pool)?
+            }
+            (Self::Funded, Event::CloseAll) if !pool.is_root() => {
+                return Err(coded(
+                    ErrorCode::InvalidState,
+                    "the close-all leaf is on the root, a pool short of removed participants is funded below it",
+                ))
+            }
+            (Self::Funded, Event::CloseAll) => Self::Closed,
+            (Self::Funded, Event::KeyClose) => Self::Closed,
+            (Self::Funded | Self::Unwinding(_), Event::Recover) => Self::Recovered,
+            (Self::Closed | Self::Recovered, Event::Archive) => Self::Archived,
+            (Self::Draft | Self::Registered | Self::Funded, Event::Abort) => Self::Cancelled,
+            (state, event) => {
+                return Err(coded(
+                    ErrorCode::InvalidState,
+                    format!("can't {} a pool that is {}", event, state),
+                ))
+            }
+        };
+        Ok(next)
+    }
+
+    // `spender` has to be the next user in the unwind, the same withdrawal can't go out twice
+    fn withdrawn(left: usize, spender: UserIndex, pool: &NodePath) -> Result<Self> {
+        let next = pool.users().get(left).copied().unwrap_or(POOL_USERS);
+        if spender.index() != next {
+            return Err(coded(
+                ErrorCode::InvalidState,
+                format!(
+                    "user {} can't withdraw now, {} users have left and user {} is next",
+                    spender, left, next
+                ),
+            ));
+        }
+        if left + 2 == pool.len() {
+            Ok(Self::Closed)
+        } else {
+            Ok(Self::Unwinding(left + 1))
+        }
+    }
+
+    // the funding tx is out, whether or not the pool has been (partly) paid out since
+    pub fn is_funded(self) -> bool {
+        !matches!(self, Self::Draft | Self::Registered | Self::Cancelled)
+    }
+
+    // for commands that need a funded pool
+    pub fn require_funded(self) -> Result<()> {
+        if !self.is_funded() {
+            return Err(coded(
+                ErrorCode::PoolNotFunded,
+                format!("the pool is {}, fund it first", self),
+            ));
+        }
+        Ok(())
+    }
+
+    // nothing is left in the pool
+    pub fn is_finished(self) -> bool {
+        matches!(
+            self,
+            Self::Closed | Self::Recovered | Self::Archived | Self::Cancelled
+        )
+    }
+
+    // for records from before the lifecycle was kept, which only say whether the pool was funded
+    // and how many withdrawals went out
+    pub fn from_record(funded: bool, withdrawals: usize) -> Self {
+        match (funded, withdrawals) {
+            (false, _) => Self::Registered,
+            (true, 0) => Self::Funded,
+            (true, withdrawals) if withdrawals + 1 >= POOL_USERS => Self::Closed,
+            (true, withdrawals) => Self::Unwinding(withdrawals),
+        }
+    }
+}
+
+impl fmt::Display for Lifecycle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Draft => write!(f, "a draft"),
+            Self::Registered => write!(f, "registered"),
+            Self::Funded => write!(f, "funded"),
+            Self::Unwinding(left) => write!(f, "unwinding ({} of {} users left)", left, POOL_USERS),
+            Self::Closed => write!(f, "closed"),
+            Self::Recovered => write!(f, "recovered"),
+            Self::Archived => write!(f, "archived"),
+            Self::Cancelled => write!(f, "cancelled"),
+        }
+    }
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Register => write!(f, "register"),
+            Self::Fund => write!(f, "fund"),
+            Self::Withdraw(user) => write!(f, "withdraw user {} from", user),
+            Self::CloseAll => write!(f, "close out every user of"),
+            Self::KeyClose => write!(f, "close through the key path"),
+            Self::Recover => write!(f, "recover"),
+            Self::Archive => write!(f, "archive"),
+            Self::Abort => write!(f, "abort"),
+        }
+    }
+}
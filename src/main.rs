@@ -1,147 +1,1546 @@
-use anyhow::Result;
-use bitcoin::Address;
+use anyhow::{anyhow, bail, Result};
+use bitcoin::{
+    consensus::encode::{deserialize_hex, serialize_hex},
+    Address, Amount, OutPoint, Txid,
+};
 use bitcoincore_rpc::RpcApi;
-use config::{NetworkConfig, AMOUNT_PER_USER, DUST_AMOUNT, FEE_AMOUNT, POOL_USERS};
-use ctv_scripts::create_pool_address;
-use pools::{
-    create_all_pools, create_entry_pool_withdraw_hashes, create_exit_pool, process_pool_spend,
+use clap::Parser;
+use op_ctv_payment_pool::{
+    abort::{abort_pool, print_aborted_pool},
+    broadcast::{set_outbox, BroadcastKind, Outbox, OUTBOX_POLL},
+    canonical::{canonical_json, manifest_hash},
+    checkpoints::checkpoint_tx,
+    cli::{
+        write_completions, Cli, Command, DebugCommand, DemoCommand, KeyCloseCommand,
+        ManifestCommand, MetaCommand, P2pCommand, ResearchCommand, SimulateCommand, StateCommand,
+        VaultCommand,
+    },
+    close::{close_pool, print_closed_pool},
+    cold::{broadcast_psbt, export_psbts, finalize_psbt_file, read_raw_tx},
+    config::{
+        ConfirmationStage, NetworkConfig, AMOUNT_PER_USER, FEE_WALLET_LOW_BALANCE, POOL_USERS,
+    },
+    costs::{pool_costs, print_costs},
+    covenant::{backend_from_env, CovenantBackend},
+    debug::{diff_level, print_level_diff, rebuild_level},
+    demo::{demo_resume, node_knows, planned_unwind, wait_for_depth},
+    doctor::{diagnose, diagnosis_result, print_diagnosis},
+    error_codes::ErrorReport,
+    esplora::{ConfirmationTracker, Esplora},
+    explain::{explain_tx, print_explanation},
+    export::{export_pool, verify_export_file, write_export},
+    fetch::RpcFetcher,
+    footprint::{pool_footprint, print_footprint},
+    formats::{formats, print_formats, require_compatible, Formats},
+    fund::{fund_from_psbt, funding_budget, read_psbt, required_funding},
+    ids::NodePath,
+    key_close::{unix_now, KeyCloseFallback, KeyCloseSession},
+    labels::{pool_labels, write_labels},
+    lifecycle::Event,
+    manifest::PoolManifest,
+    membership::{prove_membership, verify_membership_file, write_membership_proof},
+    mempool::BroadcastQueue,
+    metadata::print_metadata,
+    metrics::{spawn_metrics_server, METRICS},
+    miner::{fund_regtest_wallet, spawn_miner},
+    p2p::{cross_verify, print_cross_check, require_agreement},
+    packages::export_packages,
+    payroll::{run_payroll_epoch, schedule_payroll, PayrollRegistry, PayrollTemplate},
+    pools::{build_pools, presign_unwind, process_close_all, process_pool_spend},
+    privacy::{print_privacy, privacy_report},
+    psbt_v2::PsbtVersion,
+    publish::{print_publication, publish, verify_release_file},
+    receipts::{create_receipt, verify_receipt_file, write_receipt},
+    redact,
+    registration::{load_registrations, shuffle_registrations, RegisteredUsers},
+    removal::remove_participant,
+    report::{pool_ledger, print_ledger_summary, write_ledger_csv},
+    research::{sweep, write_sweep_csv},
+    reservations::{print_reserved_coins, release_pool, reserved_coins},
+    reserves::{
+        print_verified_reserves, prove_reserves, verify_reserves, write_reserves_proof,
+        ReservesProof,
+    },
+    rpc_helper::{
+        check_fee_payer_balance, connect_fee_payer, estimate_fee_rate, send_funding_transaction,
+        simulate_psbt_signing, AsyncRpc, FundingDestination,
+    },
+    serve::serve_registration,
+    simulate::{bump_policy, print_fee_simulation, simulate_fees, FeeScenario},
+    state::{upgrade_state, STATE_EXTENSION},
+    template_fees::{print_template_fees, template_fee_report},
+    tree::PoolTree,
+    vault::{hot_key_from_env, print_vault, Vault, VaultSpec},
+    verify::{execute_pool_scripts, print_verification, verify_pool},
+    watch::watch,
 };
-use rpc_helper::{send_funding_transaction, simulate_psbt_signing};
-use std::{collections::HashMap, str::FromStr};
-use tracing::info;
-
-mod config;
-mod ctv_scripts;
-mod pools;
-mod rpc_helper;
+#[cfg(feature = "elements")]
+use op_ctv_payment_pool::{
+    cli::ElementsCommand,
+    elements::{
+        elements_address, elements_pool, elements_unwind, write_elements_unwind, AssetId,
+        IntrospectionBackend, ELEMENTS_REGTEST,
+    },
+};
+use std::{collections::HashMap, fs, path::Path, str::FromStr, time::Duration};
+use tracing::{info, trace, warn};
+use tracing_subscriber::EnvFilter;
 
-fn main() -> Result<()> {
-    tracing_subscriber::fmt().with_target(false).init();
+#[tokio::main]
+async fn main() -> Result<()> {
+    // info unless RUST_LOG says otherwise, RUST_LOG=op_ctv_payment_pool=trace shows what info redacts
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")),
+        )
+        .with_target(false)
+        .with_writer(std::io::stderr)
+        .init();
 
-    if POOL_USERS < 3 {
-        panic!("Pool must have at least 3 users");
+    let cli = Cli::parse();
+    let json_errors = cli.json_errors;
+    if let Err(e) = run(cli).await {
+        let report = ErrorReport::new(&e);
+        if json_errors {
+            eprintln!("{}", serde_json::to_string(&report)?);
+        } else {
+            eprintln!("Error [{}]: {:?}", report.code, e);
+        }
+        std::process::exit(1);
     }
+    Ok(())
+}
 
-    if AMOUNT_PER_USER <= FEE_AMOUNT + DUST_AMOUNT {
-        panic!("Amount per user must be more than the FEE_AMOUNT + DUST_AMOUNT const");
+async fn run(cli: Cli) -> Result<()> {
+    // every broken invariant of the amounts, fees and anchors at once, before any command runs.
+    // doctor reports them as its first check
+    if !matches!(cli.command, Some(Command::Doctor { .. })) {
+        NetworkConfig::new(cli.network).validate()?;
+    }
+    if let Some(addr) = cli.metrics_addr {
+        spawn_metrics_server(addr).await?;
     }
+    if cli.no_broadcast {
+        set_outbox(Some(Outbox {
+            dir: cli.outbox.clone(),
+            poll: OUTBOX_POLL,
+        }));
+    }
+    match &cli.command {
+        None => run_pool(&cli, None).await,
+        Some(Command::Receipt {
+            user,
+            txid,
+            address,
+        }) => {
+            let config = NetworkConfig::new(cli.network);
+            let rpc = AsyncRpc::connect(&config).await?;
+            let address = address.clone().require_network(config.network)?;
+            match create_receipt(&rpc, config.network, *user, *txid, &address).await? {
+                Some(receipt) => {
+                    write_receipt(&receipt, &cli.receipts_dir)?;
+                    Ok(())
+                }
+                None => bail!("{} is not confirmed yet", txid),
+            }
+        }
+        Some(Command::VerifyReceipt { file }) => verify_receipt_file(file),
+        Some(Command::Watch { interval_secs }) => {
+            let manifest = PoolManifest::load(&cli.manifest)?;
+            watch(
+                manifest,
+                &cli.manifest,
+                Duration::from_secs(*interval_secs),
+                None,
+            )
+            .await
+        }
+        Some(Command::Explain { txs, json }) => {
+            let manifest = PoolManifest::load(&cli.manifest)?;
+            let pool = manifest.load_pool()?;
+            // the txids go to the node together, raw txs need no node
+            let txids: Vec<Txid> = txs.iter().filter_map(|tx| tx.parse().ok()).collect();
+            let mut infos = HashMap::new();
+            if !txids.is_empty() {
+                let rpc = AsyncRpc::connect(&pool.config).await?;
+                let fetcher = RpcFetcher::new(vec![rpc], pool.config.rpc_fetch.batch)?;
+                infos = fetcher.transaction_infos(&txids).await?;
+            }
+            let mut explanations = Vec::new();
+            for tx in txs {
+                let explanation = match Txid::from_str(tx) {
+                    Ok(txid) => {
+                        let info = infos
+                            .get(&txid)
+                            .ok_or_else(|| anyhow!("the node doesn't know {}", txid))?;
+                        let mut explanation = explain_tx(&pool, &info.transaction()?)?;
+                        explanation.confirmations = Some(info.confirmations.unwrap_or(0));
+                        explanation
+                    }
+                    Err(_) => explain_tx(
+                        &pool,
+                        &deserialize_hex(tx.trim()).map_err(|e| {
+                            anyhow!("{} is neither a txid nor a raw transaction: {}", tx, e)
+                        })?,
+                    )?,
+                };
+                explanations.push(explanation);
+            }
+            if *json {
+                match explanations.as_slice() {
+                    [explanation] => println!("{}", serde_json::to_string_pretty(explanation)?),
+                    _ => println!("{}", serde_json::to_string_pretty(&explanations)?),
+                }
+            } else {
+                for (i, explanation) in explanations.iter().enumerate() {
+                    if i > 0 {
+                        println!();
+                    }
+                    print_explanation(&pool, explanation);
+                }
+            }
+            Ok(())
+        }
+        Some(Command::Export {
+            user,
+            branch_only,
+            output,
+        }) => {
+            let manifest = PoolManifest::load(&cli.manifest)?;
+            let export = export_pool(&manifest, *user, *branch_only)?;
+            write_export(&export, output.as_deref())
+        }
+        Some(Command::VerifyExport { file }) => verify_export_file(file),
+        Some(Command::Debug { command }) => match command {
+            DebugCommand::Diff {
+                level,
+                tx,
+                funding_tx,
+                json,
+            } => {
+                let manifest = PoolManifest::load(&cli.manifest)?;
+                manifest.lifecycle.require_funded()?;
+                let pool = manifest.load_pool()?;
+                let funding_tx = match funding_tx {
+                    Some(path) => read_raw_tx(path)?,
+                    None => {
+                        let funding_txid = manifest
+                            .funding_txid
+                            .ok_or_else(|| anyhow!("the manifest has no funding txid"))?;
+                        let rpc = AsyncRpc::connect(&pool.config).await?;
+                        rpc.run(move |c| c.get_raw_transaction(&funding_txid, None))
+                            .await?
+                    }
+                };
+                if Some(funding_tx.compute_txid()) != manifest.funding_txid {
+                    bail!("that is not the funding tx in the manifest");
+                }
+                let observed = match tx.as_deref().map(|tx| (tx, Txid::from_str(tx))) {
+                    Some((tx, Err(_))) => deserialize_hex(tx.trim()).map_err(|e| {
+                        anyhow!("{} is neither a txid nor a raw transaction: {}", tx, e)
+                    })?,
+                    txid => {
+                        let txid = match txid {
+                            Some((_, Ok(txid))) => txid,
+                            _ => {
+                                // the node the level spends is known, the tx that spent it only if checkpointed
+                                let spend = rebuild_level(&pool, &funding_tx, *level)?;
+                                let node = spend.tx.input[0].previous_output;
+                                manifest
+                                    .checkpoints
+                                    .iter()
+                                    .find(|checkpoint| checkpoint.spent == Some(node))
+                                    .map(|checkpoint| checkpoint.txid)
+                                    .ok_or_else(|| {
+                                        anyhow!(
+                                            "no checkpoint spends {}, pass the tx seen at level {}",
+                                            node,
+                                            level
+                                        )
+                                    })?
+                            }
+                        };
+                        let rpc = AsyncRpc::connect(&pool.config).await?;
+                        rpc.run(move |c| c.get_raw_transaction(&txid, None)).await?
+                    }
+                };
+                let diff = diff_level(&pool, &funding_tx, *level, &observed)?;
+                if *json {
+                    println!("{}", serde_json::to_string_pretty(&diff)?);
+                } else {
+                    print_level_diff(&diff);
+                }
+                Ok(())
+            }
+        },
+        Some(Command::Audit {
+            funding_tx,
+            execute_scripts,
+            json,
+        }) => {
+            let manifest = PoolManifest::load(&cli.manifest)?;
+            let root_script = Address::from_str(&manifest.root_address)?
+                .assume_checked()
+                .script_pubkey();
+            let funding_tx = match (funding_tx, manifest.funding_txid) {
+                (Some(path), _) => Some(read_raw_tx(path)?),
+                (None, Some(funding_txid)) => {
+                    let rpc = AsyncRpc::connect(&NetworkConfig::new(manifest.profile)).await?;
+                    Some(
+                        rpc.run(move |c| c.get_raw_transaction(&funding_txid, None))
+                            .await?,
+                    )
+                }
+                (None, None) => None,
+            };
+            let funding = match &funding_tx {
+                Some(tx) => {
+                    let vout = tx
+                        .output
+                        .iter()
+                        .position(|output| output.script_pubkey == root_script)
+                        .ok_or_else(|| {
+                            anyhow!(
+                                "{} doesn't pay the manifest's root address",
+                                tx.compute_txid()
+                            )
+                        })?;
+                    OutPoint::new(tx.compute_txid(), vout as u32)
+                }
+                None => {
+                    // nothing signed yet, the templates don't depend on the outpoint
+                    warn!("the pool isn't funded, checking against the manifest's root address");
+                    OutPoint::null()
+                }
+            };
+            let mut report = verify_pool(&manifest, &root_script);
+            if *execute_scripts {
+                report.executions = execute_pool_scripts(&manifest, funding)?;
+            }
+            if *json {
+                println!("{}", serde_json::to_string_pretty(&report)?);
+            } else {
+                print_verification(&report);
+            }
+            if !report.is_valid() {
+                bail!("the pool doesn't check out, don't fund or sign anything for it");
+            }
+            Ok(())
+        }
+        Some(Command::ExportPsbts {
+            output_dir,
+            funding_tx,
+            psbt_v0,
+            json,
+        }) => {
+            let manifest = PoolManifest::load(&cli.manifest)?;
+            manifest.lifecycle.require_funded()?;
+            let pool = manifest.load_pool()?;
+            let funding_tx = match funding_tx {
+                Some(path) => read_raw_tx(path)?,
+                None => {
+                    let funding_txid = manifest
+                        .funding_txid
+                        .ok_or_else(|| anyhow!("the manifest has no funding txid"))?;
+                    let rpc = AsyncRpc::connect(&pool.config).await?;
+                    rpc.run(move |c| c.get_raw_transaction(&funding_txid, None))
+                        .await?
+                }
+            };
+            if Some(funding_tx.compute_txid()) != manifest.funding_txid {
+                bail!("that is not the funding tx in the manifest");
+            }
+            let version = if *psbt_v0 {
+                PsbtVersion::V0
+            } else {
+                PsbtVersion::V2
+            };
+            let spends = export_psbts(&pool, &funding_tx, output_dir, version)?;
+            if *json {
+                println!("{}", serde_json::to_string_pretty(&spends)?);
+            } else {
+                for spend in &spends {
+                    let who = spend
+                        .spender
+                        .map_or("every user".to_string(), |user| format!("user {}", user));
+                    println!(
+                        "{} leaves {}: {} ({})",
+                        who,
+                        spend.users,
+                        spend.file.display(),
+                        spend.txid
+                    );
+                }
+            }
+            Ok(())
+        }
+        Some(Command::ExportPackages {
+            output_dir,
+            funding_tx,
+            fee_rate,
+            json,
+        }) => {
+            let manifest = PoolManifest::load(&cli.manifest)?;
+            manifest.lifecycle.require_funded()?;
+            let pool = manifest.load_pool()?;
+            let rpc = AsyncRpc::connect(&pool.config).await?;
+            let funding_tx = match funding_tx {
+                Some(path) => read_raw_tx(path)?,
+                None => {
+                    let funding_txid = manifest
+                        .funding_txid
+                        .ok_or_else(|| anyhow!("the manifest has no funding txid"))?;
+                    rpc.run(move |c| c.get_raw_transaction(&funding_txid, None))
+                        .await?
+                }
+            };
+            if Some(funding_tx.compute_txid()) != manifest.funding_txid {
+                bail!("that is not the funding tx in the manifest");
+            }
+            let fee_payer = connect_fee_payer(&pool.config, &rpc).await?;
+            let fee_rate = match fee_rate {
+                Some(sat_vb) => sat_vb * 1000,
+                None => estimate_fee_rate(&rpc, &pool.config).await,
+            };
+            let packages =
+                export_packages(&pool, &funding_tx, &fee_payer, fee_rate, output_dir).await?;
+            if *json {
+                println!("{}", serde_json::to_string_pretty(&packages)?);
+            } else {
+                for package in &packages {
+                    let who = package
+                        .spender
+                        .map_or("every user".to_string(), |user| format!("user {}", user));
+                    println!(
+                        "{} leaves {}: {} ({} + {})",
+                        who,
+                        package.users,
+                        package.file.display(),
+                        package.parent_txid,
+                        package.child_txid
+                    );
+                }
+                println!(
+                    "send each one in order with: bitcoin-cli submitpackage \"$(cat <file>)\""
+                );
+            }
+            Ok(())
+        }
+        Some(Command::FinalizePsbt { file, output }) => {
+            let txid = finalize_psbt_file(file, output.as_deref())?;
+            println!("{} is final", txid);
+            Ok(())
+        }
+        Some(Command::BroadcastPsbt { file }) => {
+            let rpc = AsyncRpc::connect(&NetworkConfig::new(cli.network)).await?;
+            let txid = broadcast_psbt(&rpc, read_psbt(file)?).await?;
+            println!("{}", txid);
+            Ok(())
+        }
+        Some(Command::ProveMembership { address, output }) => {
+            let manifest = PoolManifest::load(&cli.manifest)?;
+            let proof = prove_membership(&manifest, address)?;
+            write_membership_proof(&proof, output.as_deref())
+        }
+        Some(Command::VerifyMembership { file }) => verify_membership_file(file),
+        Some(Command::ProveReserves { funding_tx, output }) => {
+            let manifest = PoolManifest::load(&cli.manifest)?;
+            let funding_tx = match funding_tx {
+                Some(path) => read_raw_tx(path)?,
+                None => {
+                    let funding_txid = manifest
+                        .funding_txid
+                        .ok_or_else(|| anyhow!("the manifest has no funding txid"))?;
+                    let rpc = AsyncRpc::connect(&NetworkConfig::new(manifest.profile)).await?;
+                    rpc.run(move |c| c.get_raw_transaction(&funding_txid, None))
+                        .await?
+                }
+            };
+            let proof = prove_reserves(&manifest, &funding_tx)?;
+            write_reserves_proof(&proof, output.as_deref())
+        }
+        Some(Command::VerifyReserves {
+            file,
+            esplora_url,
+            json,
+        }) => {
+            let proof: ReservesProof = serde_json::from_str(&fs::read_to_string(file)?)?;
+            let esplora = esplora_url.as_deref().map(Esplora::new).transpose()?;
+            let verified = verify_reserves(&proof, esplora.as_ref()).await?;
+            if *json {
+                println!("{}", serde_json::to_string_pretty(&verified)?);
+            } else {
+                print_verified_reserves(&verified);
+            }
+            Ok(())
+        }
+        Some(Command::Publish { output, to, json }) => {
+            let manifest = PoolManifest::load(&cli.manifest)?;
+            let to = to
+                .clone()
+                .or_else(|| Some(NetworkConfig::get_env_var("POOL_PUBLISH_TO", "")))
+                .filter(|to| !to.is_empty());
+            let publication = publish(&manifest, output, to.as_deref()).await?;
+            if *json {
+                println!("{}", serde_json::to_string_pretty(&publication)?);
+            } else {
+                print_publication(&publication);
+            }
+            Ok(())
+        }
+        Some(Command::VerifyRelease { file, coordinator }) => {
+            verify_release_file(file, *coordinator)
+        }
+        Some(Command::Fund { psbt, codes }) => {
+            let manifest = PoolManifest::load(&cli.manifest)?;
+            let rpc = AsyncRpc::connect(&NetworkConfig::new(manifest.profile)).await?;
+            fund_from_psbt(&rpc, manifest, &cli.manifest, psbt, codes).await?;
+            Ok(())
+        }
+        Some(Command::CloseAll) => {
+            let mut manifest = PoolManifest::load(&cli.manifest)?;
+            manifest.lifecycle.apply(Event::CloseAll)?;
+            let funding_txid = manifest
+                .funding_txid
+                .ok_or_else(|| anyhow!("the manifest has no funding txid"))?;
+            let pool = manifest.load_pool()?;
+            let rpc = AsyncRpc::connect(&pool.config).await?;
+            let fee_payer = connect_fee_payer(&pool.config, &rpc).await?;
+            let txid = process_close_all(
+                &pool.tree,
+                &pool.config,
+                &rpc,
+                &fee_payer,
+                &mut BroadcastQueue::new(&pool.config),
+                pool.backend.as_ref(),
+                &pool.addresses,
+                funding_txid,
+                &pool.anchor_addr,
+            )
+            .await?;
+            info!("every user paid out in {} \n", txid);
+            manifest.advance(Event::CloseAll)?;
+            manifest.write(&cli.manifest)
+        }
+        Some(Command::Close {
+            archive_dir,
+            report,
+            json,
+        }) => {
+            let closed = close_pool(&cli.manifest, archive_dir, *report).await?;
+            if *json {
+                println!("{}", serde_json::to_string_pretty(&closed)?);
+            } else {
+                print_closed_pool(&closed);
+            }
+            Ok(())
+        }
+        Some(Command::Abort { refund_to, json }) => {
+            let aborted = abort_pool(&cli.manifest, refund_to.clone()).await?;
+            if *json {
+                println!("{}", serde_json::to_string_pretty(&aborted)?);
+            } else {
+                print_aborted_pool(&aborted);
+            }
+            Ok(())
+        }
+        Some(Command::KeyClose { command }) => key_close(&cli.manifest, command).await,
+        #[cfg(feature = "elements")]
+        Some(Command::Elements { command }) => elements(&cli.manifest, command),
+        Some(Command::RemoveParticipant { user, rebalance }) => {
+            let manifest = PoolManifest::load(&cli.manifest)?;
+            let next = remove_participant(&manifest, *user, *rebalance)?;
+            next.write(&cli.manifest)?;
+            println!(
+                "user {} removed, fund {} with {} (revision {})",
+                user,
+                next.root_address,
+                next.funding_target()?,
+                next.revision
+            );
+            Ok(())
+        }
+        Some(Command::Reservations { release, json }) => {
+            let config = NetworkConfig::new(cli.network);
+            let rpc = AsyncRpc::connect(&config).await?;
+            if let Some(pool) = release {
+                release_pool(&rpc, &config, pool).await?;
+            }
+            let coins = reserved_coins(&rpc, &config).await?;
+            if *json {
+                println!("{}", serde_json::to_string_pretty(&coins)?);
+            } else {
+                print_reserved_coins(&coins);
+            }
+            Ok(())
+        }
+        Some(Command::Report { csv, output }) => {
+            let manifest = PoolManifest::load(&cli.manifest)?;
+            let ledger = pool_ledger(&manifest).await?;
+            if *csv {
+                write_ledger_csv(&ledger, &manifest.metadata, output.as_deref())
+            } else {
+                print_ledger_summary(&ledger, &manifest.metadata);
+                Ok(())
+            }
+        }
+        Some(Command::Costs { fee_rate, json }) => {
+            let mut config = NetworkConfig::new(cli.network);
+            // the splits of the pool in the manifest, if there is one
+            if let Ok(manifest) = PoolManifest::load(&cli.manifest) {
+                config.splits = manifest.splits;
+            }
+            let costs = pool_costs(&config, *fee_rate);
+            if *json {
+                println!("{}", serde_json::to_string_pretty(&costs)?);
+            } else {
+                print_costs(&costs);
+            }
+            Ok(())
+        }
+        Some(Command::Doctor { json }) => {
+            let checks = diagnose(cli.network, &cli.manifest).await;
+            if *json {
+                println!("{}", serde_json::to_string_pretty(&checks)?);
+            } else {
+                print_diagnosis(&checks);
+            }
+            diagnosis_result(&checks)
+        }
+        Some(Command::Footprint { json }) => {
+            let config = NetworkConfig::new(cli.network);
+            let footprint = pool_footprint(&config, backend_from_env(&config)?.as_ref())?;
+            if *json {
+                println!("{}", serde_json::to_string_pretty(&footprint)?);
+            } else {
+                print_footprint(&footprint);
+            }
+            Ok(())
+        }
+        Some(Command::TemplateFees { json }) => {
+            let pool = PoolManifest::load(&cli.manifest)?.load_pool()?;
+            let report = template_fee_report(
+                &pool.tree,
+                &pool.config,
+                pool.backend.as_ref(),
+                &pool.addresses,
+                &pool.anchor_addr,
+            )?;
+            if *json {
+                println!("{}", serde_json::to_string_pretty(&report)?);
+            } else {
+                print_template_fees(&report);
+            }
+            Ok(())
+        }
+        Some(Command::Privacy { json }) => {
+            let pool = PoolManifest::load(&cli.manifest)?.load_pool()?;
+            let report = privacy_report(
+                &pool.tree,
+                &pool.config,
+                pool.backend.as_ref(),
+                &pool.addresses,
+                &pool.anchor_addr,
+            )?;
+            if *json {
+                println!("{}", serde_json::to_string_pretty(&report)?);
+            } else {
+                print_privacy(&report);
+            }
+            Ok(())
+        }
+        Some(Command::PayrollSchedule { template }) => {
+            let config = NetworkConfig::new(cli.network);
+            let backend = backend_from_env(&config)?;
+            let registry = schedule_payroll(
+                &config,
+                backend.as_ref(),
+                PayrollTemplate::load(template, config.network)?,
+            )?;
+            registry.write(&cli.payroll)
+        }
+        Some(Command::State { command }) => match command {
+            StateCommand::Upgrade { output } => {
+                let output = output
+                    .clone()
+                    .unwrap_or_else(|| cli.manifest.with_extension(STATE_EXTENSION));
+                upgrade_state(&cli.manifest, &output)
+            }
+            StateCommand::ExportJson { output, canonical } => {
+                let manifest = PoolManifest::load(&cli.manifest)?;
+                let json = if *canonical {
+                    canonical_json(&manifest)?
+                } else {
+                    serde_json::to_string_pretty(&manifest)?
+                };
+                match output {
+                    Some(path) => Ok(fs::write(path, json)?),
+                    None => {
+                        println!("{}", json);
+                        Ok(())
+                    }
+                }
+            }
+        },
+        Some(Command::Manifest { command }) => match command {
+            ManifestCommand::Hash { json } => {
+                let manifest = PoolManifest::load(&cli.manifest)?;
+                let hash = manifest_hash(&manifest)?;
+                if *json {
+                    println!(
+                        "{}",
+                        serde_json::to_string_pretty(&serde_json::json!({
+                            "root_address": manifest.root_address,
+                            "sha256": hash.to_string(),
+                        }))?
+                    );
+                } else {
+                    println!("{}", hash);
+                }
+                Ok(())
+            }
+        },
+        Some(Command::Meta { command }) => {
+            let mut manifest = PoolManifest::load(&cli.manifest)?;
+            match command {
+                MetaCommand::Set { key, value, user } => {
+                    manifest.metadata.set(*user, key, value)?;
+                    manifest.write(&cli.manifest)
+                }
+                MetaCommand::Unset { key, user } => {
+                    if !manifest.metadata.unset(*user, key) {
+                        bail!("no {} label to remove", key);
+                    }
+                    manifest.write(&cli.manifest)
+                }
+                MetaCommand::Show { json } => {
+                    if *json {
+                        println!("{}", serde_json::to_string_pretty(&manifest.metadata)?);
+                    } else {
+                        print_metadata(&manifest.metadata);
+                    }
+                    Ok(())
+                }
+            }
+        }
+        Some(Command::Serve {
+            listen,
+            opens_in_secs,
+            window_secs,
+            follow,
+            interval_secs,
+        }) => {
+            let pool_id = cli
+                .pool_id
+                .clone()
+                .ok_or_else(|| anyhow!("serve needs the --pool-id users sign when registering"))?;
+            serve_registration(
+                NetworkConfig::new(cli.network),
+                pool_id,
+                *listen,
+                Duration::from_secs(*opens_in_secs),
+                Duration::from_secs(*window_secs),
+                cli.manifest.clone(),
+                cli.registrations
+                    .clone()
+                    .unwrap_or_else(|| "registrations.json".into()),
+                follow.then(|| Duration::from_secs(*interval_secs)),
+            )
+            .await
+        }
+        Some(Command::Demo { command }) => match command {
+            DemoCommand::Run { fresh } => run_demo(&cli, *fresh).await,
+        },
+        Some(Command::P2p { command }) => match command {
+            P2pCommand::Verify { codes, json } => {
+                let manifest = PoolManifest::load(&cli.manifest)?;
+                let check = cross_verify(&manifest, codes)?;
+                if *json {
+                    println!("{}", serde_json::to_string_pretty(&check)?);
+                } else {
+                    print_cross_check(&check);
+                }
+                require_agreement(&check.peers)
+            }
+        },
+        Some(Command::Research { command }) => match command {
+            ResearchCommand::Sweep {
+                users,
+                step,
+                shape,
+                fee_rate,
+                output,
+            } => {
+                let config = NetworkConfig::new(cli.network);
+                let backend = backend_from_env(&config)?;
+                let rows = sweep(&config, backend.as_ref(), *users, *step, shape, *fee_rate)?;
+                write_sweep_csv(&rows, output.as_deref())
+            }
+        },
+        Some(Command::Simulate { command }) => match command {
+            SimulateCommand::Fees {
+                scenario,
+                bump_after_blocks,
+                bump_max_fee_sats,
+                json,
+            } => {
+                let pool = PoolManifest::load(&cli.manifest)?.load_pool()?;
+                let scenario = FeeScenario::load(scenario)?;
+                let bump = bump_policy(
+                    pool.config.fee_bump.clone(),
+                    *bump_after_blocks,
+                    bump_max_fee_sats.map(Amount::from_sat),
+                )?;
+                let simulation = simulate_fees(
+                    &pool.tree,
+                    &pool.config,
+                    pool.backend.as_ref(),
+                    &pool.addresses,
+                    &pool.anchor_addr,
+                    &scenario,
+                    bump.as_ref(),
+                )?;
+                if *json {
+                    println!("{}", serde_json::to_string_pretty(&simulation)?);
+                } else {
+                    print_fee_simulation(&simulation);
+                }
+                Ok(())
+            }
+        },
+        Some(Command::Vault { command }) => match command {
+            VaultCommand::Create {
+                amount_sats,
+                hot_key,
+                cold_address,
+                delay,
+                output,
+                json,
+            } => {
+                let config = NetworkConfig::new(cli.network);
+                let cold_address = cold_address.clone().require_network(config.network)?;
+                let spec = VaultSpec::new(
+                    &config,
+                    Amount::from_sat(*amount_sats),
+                    *hot_key,
+                    &cold_address,
+                    *delay,
+                )?;
+                let vault = Vault::new(spec)?;
+                vault.spec.save(output)?;
+                if *json {
+                    println!("{}", serde_json::to_string_pretty(&vault.info())?);
+                } else {
+                    print_vault(&vault.info());
+                }
+                Ok(())
+            }
+            VaultCommand::Unvault { vault, utxo } => {
+                let vault = Vault::load(vault)?;
+                println!("{}", serialize_hex(&vault.unvault_tx(*utxo)));
+                Ok(())
+            }
+            VaultCommand::ToCold { vault, utxo } => {
+                let vault = Vault::load(vault)?;
+                println!("{}", serialize_hex(&vault.tocold_tx(*utxo)));
+                Ok(())
+            }
+            VaultCommand::Withdraw {
+                vault,
+                utxo,
+                to,
+                fee_sats,
+            } => {
+                let vault = Vault::load(vault)?;
+                let to = to.clone().require_network(vault.spec.network)?;
+                let tx = vault.withdraw_tx(
+                    *utxo,
+                    &to,
+                    Amount::from_sat(*fee_sats),
+                    &hot_key_from_env()?,
+                )?;
+                println!("{}", serialize_hex(&tx));
+                Ok(())
+            }
+        },
+        Some(Command::Payroll { epoch }) => run_payroll(&cli.payroll, *epoch).await,
+        Some(Command::Version { formats: false, .. }) => {
+            println!("op_ctv_payment_pool {}", env!("CARGO_PKG_VERSION"));
+            Ok(())
+        }
+        Some(Command::Version { compare, json, .. }) => {
+            let ours = formats(&NetworkConfig::new(cli.network))?;
+            if *json {
+                println!("{}", serde_json::to_string_pretty(&ours)?);
+            } else {
+                print_formats(&ours);
+            }
+            match compare {
+                Some(path) => {
+                    let theirs: Formats = serde_json::from_str(&fs::read_to_string(path)?)?;
+                    require_compatible(&ours, &theirs)?;
+                    info!("{} derives the same pools as this build", path.display());
+                    Ok(())
+                }
+                None => Ok(()),
+            }
+        }
+        Some(Command::Completions { shell }) => {
+            write_completions(*shell, &mut std::io::stdout());
+            Ok(())
+        }
+    }
+}
 
-    let config = NetworkConfig::new();
-    let rpc = config.bitcoin_rpc()?;
+async fn run_payroll(registry_path: &Path, epoch: Option<u32>) -> Result<()> {
+    let mut registry = PayrollRegistry::load(registry_path)?;
+    let first = registry
+        .epochs
+        .first()
+        .ok_or_else(|| anyhow!("payroll registry has no epochs"))?;
+    let config = NetworkConfig::new(first.pool.profile);
+    let rpc = AsyncRpc::connect(&config).await?;
+    config.check_chain(&rpc).await?;
+
+    let epoch = match epoch {
+        Some(epoch) => epoch,
+        None => {
+            let height = rpc.run(|c| c.get_block_count()).await? as u32;
+            registry.current_epoch(height).ok_or_else(|| {
+                anyhow!(
+                    "height {} is outside every pay period of the payroll",
+                    height
+                )
+            })?
+        }
+    };
+    info!("paying payroll epoch {} \n", epoch);
 
     let mining_address = rpc
-        .get_new_address(Some("messing with ctv"), None)?
+        .run(|c| c.get_new_address(Some("messing with ctv"), None))
+        .await?
         .require_network(config.network)?;
+    if config.is_regtest() {
+        fund_regtest_wallet(&rpc, &mining_address, required_funding()).await?;
+    } else if let Some(faucet) = &config.faucet {
+        faucet
+            .fund_wallet(&rpc, config.network, funding_budget()?)
+            .await?;
+    }
+    let fee_payer = connect_fee_payer(&config, &rpc).await?;
+    let _miner = spawn_miner(&config, rpc.clone(), mining_address);
 
-    #[cfg(feature = "regtest")]
-    if rpc.get_balance(None, None)? < (AMOUNT_PER_USER) * POOL_USERS.try_into()? {
-        let _ = rpc.generate_to_address(101, &mining_address);
+    run_payroll_epoch(&rpc, &fee_payer, &mut registry, registry_path, epoch).await
+}
+
+// `elements`: create a pool on elementsregtest, or write its unwind for elements-cli
+#[cfg(feature = "elements")]
+fn elements(manifest_path: &Path, command: &ElementsCommand) -> Result<()> {
+    match command {
+        ElementsCommand::Create { addresses } => {
+            let addresses: Vec<String> = fs::read_to_string(addresses)?
+                .lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty())
+                .map(String::from)
+                .collect();
+            let manifest = elements_pool(&addresses)?;
+            manifest.write(manifest_path)?;
+            let root = Address::from_str(&manifest.root_address)?.assume_checked();
+            println!(
+                "fund {} with {} of asset {}",
+                elements_address(&root.script_pubkey(), &ELEMENTS_REGTEST)?,
+                manifest.funding_target()?,
+                AssetId::from_env(&ELEMENTS_REGTEST)?
+            );
+            Ok(())
+        }
+        ElementsCommand::Unwind {
+            funding,
+            output_dir,
+        } => {
+            let manifest = PoolManifest::load(manifest_path)?;
+            let pool = manifest.load_pool()?;
+            let backend = IntrospectionBackend::new(AssetId::from_env(&ELEMENTS_REGTEST)?);
+            let txs = elements_unwind(&pool, &backend, *funding)?;
+            write_elements_unwind(&txs, output_dir)?;
+            for (level, tx) in txs.iter().enumerate() {
+                println!("level {}: {}", level, tx.txid());
+            }
+            println!(
+                "{} txs in {}, send them in order with `elements-cli sendrawtransaction`",
+                txs.len(),
+                output_dir.display()
+            );
+            Ok(())
+        }
     }
+}
 
-    let anchor_addr = Address::from_str(config.fee_anchor_addr)?.require_network(config.network)?;
+// `key-close`: start the members' signing session, or finish it with the key path spend, or past
+// its deadline with the root's leaves
+async fn key_close(manifest_path: &Path, command: &KeyCloseCommand) -> Result<()> {
+    let mut manifest = PoolManifest::load(manifest_path)?;
+    let funding_txid = manifest
+        .funding_txid
+        .ok_or_else(|| anyhow!("the manifest has no funding txid"))?;
+    let pool = manifest.load_pool()?;
+    let rpc = AsyncRpc::connect(&pool.config).await?;
+    match command {
+        KeyCloseCommand::Start {
+            fee_rate,
+            timeout_secs,
+            session,
+        } => {
+            let funding_tx = rpc
+                .run(move |c| c.get_raw_transaction(&funding_txid, None))
+                .await?;
+            let started = KeyCloseSession::start(
+                &manifest,
+                &pool,
+                &funding_tx,
+                *fee_rate,
+                unix_now() + timeout_secs,
+            )?;
+            started.write(session)?;
+            println!(
+                "key close of {} written to {}, every member adds a nonce then signs before {}",
+                started.root_address,
+                session.display(),
+                started.deadline
+            );
+            Ok(())
+        }
+        KeyCloseCommand::Finish { session, dry_run } => {
+            let session = KeyCloseSession::load(session)?;
+            let missing = session.missing();
+            if missing.is_empty() {
+                let tx = session.finish()?;
+                if *dry_run {
+                    println!("{}", serialize_hex(&tx));
+                    return Ok(());
+                }
+                let txid = BroadcastQueue::new(&pool.config)
+                    .send(&rpc, tx, BroadcastKind::PoolSpend)
+                    .await?;
+                info!("every user paid out through the key path in {} \n", txid);
+                manifest.advance(Event::KeyClose)?;
+                return manifest.write(manifest_path);
+            }
+            if !session.expired(unix_now()) {
+                bail!(
+                    "users {:?} haven't signed yet, they have until {}",
+                    missing,
+                    session.deadline
+                );
+            }
+            info!(
+                "users {:?} didn't sign by {}, spending the root through its leaves \n",
+                missing, session.deadline
+            );
+            let fee_payer = connect_fee_payer(&pool.config, &rpc).await?;
+            let mut queue = BroadcastQueue::new(&pool.config);
+            match KeyCloseSession::fallback(&pool)? {
+                KeyCloseFallback::CloseAll => {
+                    let txid = process_close_all(
+                        &pool.tree,
+                        &pool.config,
+                        &rpc,
+                        &fee_payer,
+                        &mut queue,
+                        pool.backend.as_ref(),
+                        &pool.addresses,
+                        funding_txid,
+                        &pool.anchor_addr,
+                    )
+                    .await?;
+                    info!("every user paid out in {} \n", txid);
+                    manifest.advance(Event::CloseAll)?;
+                }
+                KeyCloseFallback::Unwind(first) => {
+                    let txid = process_pool_spend(
+                        &pool.tree,
+                        &pool.config,
+                        &rpc,
+                        &fee_payer,
+                        &mut queue,
+                        pool.backend.as_ref(),
+                        first,
+                        &pool.addresses,
+                        funding_txid,
+                        &pool.anchor_addr,
+                    )
+                    .await?;
+                    info!(
+                        "user {} left in {}, `demo run` unwinds the rest \n",
+                        first, txid
+                    );
+                    manifest.advance(Event::Withdraw(first))?;
+                }
+            }
+            manifest.write(manifest_path)
+        }
+    }
+}
 
-    info!("Creating pool with {} users \n", POOL_USERS);
+// `demo run`: the pool the manifest left unfinished, or a new one. Unlike the plain demo it waits
+// on the node for confirmations when there is no miner and no esplora.
+async fn run_demo(cli: &Cli, fresh: bool) -> Result<()> {
+    let mut demo = DemoRun {
+        resume: None,
+        withdrawn: 0,
+    };
+    if !fresh && cli.manifest.exists() {
+        let manifest = PoolManifest::load(&cli.manifest)?;
+        if let Some(resume) = demo_resume(&manifest, cli.network)? {
+            info!(
+                "resuming the pool in {}: {} users have left, funding {:?} \n",
+                cli.manifest.display(),
+                resume.withdrawn,
+                resume.funding_txid
+            );
+            demo.resume = Some(manifest);
+            demo.withdrawn = resume.withdrawn;
+        }
+    }
+    run_pool(cli, Some(demo)).await
+}
 
-    // TODO: allow for input for address list with weights.
-    // the leaves of the CTV tree are the withdraw addresses
-    let withdraw_addresses: Vec<Address> = (0..POOL_USERS)
-        .map(|_| {
-            rpc.get_new_address(None, None)
-                .unwrap()
-                .require_network(config.network)
-                .unwrap()
-        })
-        .collect();
-
-    let (init_wallets_txid, fee) = send_funding_transaction(&rpc, &config, FEE_AMOUNT);
-    info!("Initial funding transaction ID: {}", init_wallets_txid);
-
-    #[cfg(feature = "regtest")]
-    let _ = rpc.generate_to_address(1, &mining_address);
-    // Log all withdraw addresses
-    for (i, addr) in withdraw_addresses.iter().enumerate() {
-        info!("User {} withdraw address: {}", i, addr);
+// how `demo run` differs from the plain demo
+struct DemoRun {
+    // the unfinished pool it picks up
+    resume: Option<PoolManifest>,
+    // users of `resume` that have left
+    withdrawn: usize,
+}
+
+async fn run_pool(cli: &Cli, demo: Option<DemoRun>) -> Result<()> {
+    let mut config = NetworkConfig::new(cli.network);
+    let rpc = AsyncRpc::connect(&config).await?;
+    let mut backend = backend_from_env(&config)?;
+
+    config.check_chain(&rpc).await?;
+    if config.require_ctv && !backend.requires_presigning() && !config.ctv_active(&rpc).await? {
+        bail!(
+            "OP_CTV is not active on this node, connect to a bitcoin inquisition node or use COVENANT_BACKEND=presigned"
+        );
+    }
+    if let Some(recovery) = &config.recovery {
+        // the sweep is only restricted by OP_CTV, there is nothing to presign it with
+        if backend.requires_presigning() {
+            bail!("RECOVERY_ADDRESS needs OP_CTV, it can't be used with the presigned backend");
+        }
+        info!(
+            "pool nodes can be swept to {} after {} blocks \n",
+            recovery.address, recovery.timeout
+        );
+    }
+    if let Some(delay) = config.max_unwind_delay() {
+        // otherwise a node could be swept before anyone is allowed to leave it
+        if config
+            .recovery
+            .as_ref()
+            .is_some_and(|recovery| recovery.timeout <= delay)
+        {
+            bail!(
+                "RECOVERY_TIMEOUT_BLOCKS has to be longer than UNWIND_DELAY_BLOCKS and every UNWIND_LEVEL_DELAYS"
+            );
+        }
+        if config.level_delays.is_empty() {
+            info!("every unwind spend waits {} blocks \n", delay);
+        } else {
+            info!(
+                "cooldown pool, each level waits {:?} blocks from the root down, {} after that \n",
+                config.level_delays,
+                config.unwind_delay.unwrap_or_default()
+            );
+        }
+    }
+
+    let mining_address = rpc
+        .run(|c| c.get_new_address(Some("messing with ctv"), None))
+        .await?
+        .require_network(config.network)?;
+
+    if config.is_regtest() {
+        fund_regtest_wallet(
+            &rpc,
+            &mining_address,
+            (AMOUNT_PER_USER) * POOL_USERS.try_into()?,
+        )
+        .await?;
+    } else if let Some(faucet) = &config.faucet {
+        faucet
+            .fund_wallet(&rpc, config.network, funding_budget()?)
+            .await?;
+    }
+    let fee_payer = connect_fee_payer(&config, &rpc).await?;
+    if config.anchor_amount.is_some() {
+        if config.fee_wallet_name.is_some() && config.is_regtest() {
+            let fee_payer_address = fee_payer
+                .run(|c| c.get_new_address(None, None))
+                .await?
+                .require_network(config.network)?;
+            fund_regtest_wallet(&fee_payer, &fee_payer_address, FEE_WALLET_LOW_BALANCE).await?;
+        }
+        check_fee_payer_balance(&fee_payer, Amount::ZERO).await?;
+    } else if config.fee_wallet_name.is_some() {
+        warn!(
+            "FEE_WALLET is set but {} has no fee anchors, it won't be used",
+            config.network
+        );
     }
 
-    ////////////////////////////////////////////////////////////////////////////
-    /////////////////////////////CREATE LAST POOL //////////////////////////////
-    ////////////////////////////////////////////////////////////////////////////
-    let mut pools = Vec::new();
-    //The last pool will always be the same, regardless of how many users are in the pool (it will allow 2 users to withdraw)
-    let exit_pool_leaves = create_exit_pool(&withdraw_addresses, &anchor_addr)?;
-    // the taproot spend info for the last pool is the leaves of the CTV tree
-    pools.push(exit_pool_leaves);
+    let _miner = spawn_miner(&config, rpc.clone(), mining_address.clone());
 
-    /////////////////////////////////////////////////////////////////////////////
-    /////////////////////////////CREATE ALL OTHER POOLS//////////////////////////
-    ////////////////////////////////////////////////////////////////////////////
-    // pop from the front (the leaves are the first in the vec) and create a node and add it to the back of the vec
-    create_all_pools(&withdraw_addresses, &anchor_addr, &config, &mut pools);
+    let anchor_addr =
+        Address::from_str(&config.fee_anchor_addr)?.require_network(config.network)?;
 
-    let total_taproot_spend_info: usize = pools.iter().map(|pool| pool.len()).sum();
+    let resume = demo.as_ref().and_then(|demo| demo.resume.clone());
+    let (withdraw_addresses, pools, mut manifest) = match resume {
+        Some(manifest) => {
+            let pool = manifest.load_pool()?;
+            config = pool.config;
+            backend = pool.backend;
+            (pool.addresses, pool.tree, manifest)
+        }
+        None => create_pool(cli, &rpc, &mut config, backend.as_ref(), &anchor_addr).await?,
+    };
+    let pool_0_addr = pools.root()?.address(&config);
 
-    info!(
-        "total taproot addresses across all pools: {} for {} users \n",
-        total_taproot_spend_info, POOL_USERS
-    );
+    let destination = if cli.fund_directly {
+        FundingDestination::EntryPool(pool_0_addr.clone())
+    } else {
+        let staging = rpc.run(|c| c.get_raw_change_address(None)).await?;
+        FundingDestination::Staging(staging.require_network(config.network)?)
+    };
+    let change_address = match &cli.change_address {
+        Some(address) => Some(address.clone().require_network(config.network)?),
+        None => None,
+    };
 
-    ////////////////////////////////////////////////////////////////////////////
-    //////////////////////CREATE FIRST POOL/////////////////////////////////////
-    ////////////////////////////////////////////////////////////////////////////
+    // without a miner, wait for the network with esplora before building on a tx
+    let mut confirmations = ConfirmationTracker::from_config(&config)?;
+    // `demo run` also waits on a public network without esplora, polling the node
+    let poll_node = demo.is_some();
 
-    let pool_0 = create_entry_pool_withdraw_hashes(
-        &withdraw_addresses,
-        pools.last().unwrap(),
-        &anchor_addr,
-        &config,
-        (AMOUNT_PER_USER) * (POOL_USERS - 1).try_into()?,
-    );
-    let pool_0_spend_info = create_pool_address(pool_0.clone())?;
-    let mut pool_0_map = HashMap::new();
-    pool_0_map.insert(vec![0], pool_0_spend_info.clone());
-    pools.push(pool_0_map);
-    // we have the root of the CTV tree
+    let pool_funding_txid = match manifest.funding_txid {
+        // resumed, the funding went out before the last run stopped
+        Some(txid) => {
+            info!("Pool already funded by {}", txid);
+            wait_stage(
+                &rpc,
+                &config,
+                &mut confirmations,
+                txid,
+                ConfirmationStage::Funding,
+                poll_node,
+            )
+            .await?;
+            txid
+        }
+        None => {
+            let (init_wallets_txid, funding_spk) =
+                send_funding_transaction(&rpc, &config, &destination, change_address.as_ref())
+                    .await?;
+            info!("Initial funding transaction ID: {}", init_wallets_txid);
+            wait_stage(
+                &rpc,
+                &config,
+                &mut confirmations,
+                init_wallets_txid,
+                ConfirmationStage::Funding,
+                poll_node,
+            )
+            .await?;
 
-    //////////////////////////////////////////////////////////////////////////////////
-    /////////////////////////////FUND POOL WITH PSBT//////////////////////////////////
-    /////////////////////////////////////////////////////////////////////////////////
+            //////////////////////////////////////////////////////////////////////////////////
+            /////////////////////////////FUND POOL WITH PSBT//////////////////////////////////
+            /////////////////////////////////////////////////////////////////////////////////
 
-    //the first pools address
-    let pool_0_addr = Address::p2tr_tweaked(pool_0_spend_info.output_key(), config.network);
-    info!("Initial pool address: {}", pool_0_addr);
+            let pool_funding_txid = match destination {
+                FundingDestination::EntryPool(_) => init_wallets_txid,
+                //here we will simulate the pool psbt funding transaction
+                FundingDestination::Staging(_) => {
+                    simulate_psbt_signing(
+                        &rpc,
+                        &config,
+                        init_wallets_txid,
+                        &funding_spk,
+                        &pool_0_addr,
+                    )
+                    .await?
+                }
+            };
+            info!("Pool funding transaction details:");
+            info!("  Transaction ID: {}", pool_funding_txid);
+            info!("  Source TXID: {}", init_wallets_txid);
+            info!("  Destination: {}", redact::short(&pool_0_addr));
+            trace!("  Destination: {}", pool_0_addr);
 
-    //here we will simulate the pool psbt funding transaction
-    let pool_funding_txid = simulate_psbt_signing(&rpc, init_wallets_txid, &pool_0_addr, fee)?;
-    info!("Pool funding transaction details:");
-    info!("  Transaction ID: {}", pool_funding_txid);
-    info!("  Source TXID: {}", init_wallets_txid);
-    info!("  Destination: {}", pool_0_addr);
+            manifest.funding_txid = Some(pool_funding_txid);
+            manifest.advance(Event::Fund)?;
+            manifest.write(&cli.manifest)?;
 
-    #[cfg(feature = "regtest")]
-    let _ = rpc.generate_to_address(1, &mining_address);
+            // funded directly the pool tx is the one already confirmed
+            if pool_funding_txid != init_wallets_txid {
+                wait_stage(
+                    &rpc,
+                    &config,
+                    &mut confirmations,
+                    pool_funding_txid,
+                    ConfirmationStage::Funding,
+                    poll_node,
+                )
+                .await?;
+            }
+            pool_funding_txid
+        }
+    };
+    METRICS.set_pools_tracked(1);
+
+    // where a later `watch` picks up from, once the txs are confirmed
+    let node_scripts = pools.node_scripts(&config);
+    if checkpoint_tx(&rpc, &mut manifest, pool_funding_txid, &node_scripts).await {
+        manifest.write(&cli.manifest)?;
+    }
+
+    // without OP_CTV the templates have to be signed now, before the key is deleted.
+    // in a real pool this has to happen before the funding tx is signed, here we just simulate it
+    if backend.requires_presigning() {
+        let funding_tx = rpc
+            .run(move |c| c.get_raw_transaction(&pool_funding_txid, None))
+            .await?;
+        presign_unwind(
+            &pools,
+            &config,
+            backend.as_mut(),
+            &withdraw_addresses,
+            &funding_tx,
+            &anchor_addr,
+        )?;
+    }
 
     ////////////////////////////////////////////////////////////////////////////////////////////////////////////////////////////////////////////////
     ////we are going to test spending, but for the PoC we will just spend in the order of addresses so for example, for a 10 user pool it will be///
     /////////////////////Alice -> Bob -> Carol -> Danny -> Eve -> Frank -> George -> Helen -> Igor && Jao///////////////////////////////////////////
     ////////////////////////////////////////////////////////////////////////////////////////////////////////////////////////////////////////////////
 
+    // the spends a resumed demo made before it stopped, the templates fix their txids
+    let (withdrawn, planned) = match &demo {
+        Some(DemoRun {
+            resume: Some(resumed),
+            withdrawn,
+        }) => {
+            let funding_tx = rpc
+                .run(move |c| c.get_raw_transaction(&pool_funding_txid, None))
+                .await?;
+            let pool = resumed.load_pool()?;
+            (*withdrawn, planned_unwind(&pool, &funding_tx)?)
+        }
+        _ => (0, Vec::new()),
+    };
+
     let mut current_txid = pool_funding_txid;
-    for i in 0..=(POOL_USERS - 2) {
+    let mut exits = Vec::new();
+    let mut queue = BroadcastQueue::new(&config);
+    // users removed before funding have no exit, the members leave in order
+    let members = config.pool_root()?;
+    METRICS.set_pending_withdrawals(members.len() - withdrawn);
+    for (level, i) in config.spenders()?.into_iter().enumerate() {
+        if level < withdrawn {
+            current_txid = planned[level];
+            exits.push((i, current_txid));
+            continue;
+        }
         info!("Processing withdrawal for user {}:", i);
         info!("  Current TXID: {}", current_txid);
-        info!("  Withdraw address: {}", withdraw_addresses[i]);
-        current_txid = process_pool_spend(
-            &pools,
-            &config,
+        info!(
+            "  Withdraw address: {}",
+            redact::short(&withdraw_addresses[i.index()])
+        );
+        trace!("  Withdraw address: {}", withdraw_addresses[i.index()]);
+        current_txid = match planned.get(level) {
+            // sent by the run that stopped, before it could record it
+            Some(&txid) if node_knows(&rpc, txid).await => {
+                info!("  already broadcast");
+                txid
+            }
+            _ => {
+                process_pool_spend(
+                    &pools,
+                    &config,
+                    &rpc,
+                    &fee_payer,
+                    &mut queue,
+                    backend.as_ref(),
+                    i,
+                    &withdraw_addresses,
+                    current_txid,
+                    &anchor_addr,
+                )
+                .await?
+            }
+        };
+        // the next level only builds on this spend once it is as deep as its stage asks
+        let stage = ConfirmationStage::of_spend(i, &config)?;
+        wait_stage(
             &rpc,
-            i,
-            &withdraw_addresses,
+            &config,
+            &mut confirmations,
             current_txid,
-            &anchor_addr,
-            &mining_address,
-        )?;
+            stage,
+            poll_node,
+        )
+        .await?;
         info!("  New TXID: {}", current_txid);
+        manifest.advance(Event::Withdraw(i))?;
+        checkpoint_tx(&rpc, &mut manifest, current_txid, &node_scripts).await;
+        manifest.write(&cli.manifest)?;
+        exits.push((i, current_txid));
+        METRICS.set_pending_withdrawals(members.len() - exits.len());
+    }
+    // the final exit pays the last two members
+    let last = members
+        .user_indices()
+        .last()
+        .ok_or_else(|| anyhow!("the pool has no members"))?;
+    exits.push((last, current_txid));
+    METRICS.set_pending_withdrawals(0);
+    METRICS.set_pools_tracked(0);
+
+    let labels = pool_labels(
+        &pools,
+        &config,
+        &withdraw_addresses,
+        pool_funding_txid,
+        &exits,
+    )?;
+    write_labels(&labels, &cli.labels)?;
+
+    for (user, txid) in exits {
+        let address = &withdraw_addresses[user.index()];
+        // with registered addresses the exits are not wallet txs, the users make their own receipts
+        match create_receipt(&rpc, config.network, user, txid, address).await {
+            Ok(Some(receipt)) => {
+                write_receipt(&receipt, &cli.receipts_dir)?;
+            }
+            Err(e) => warn!("no receipt for user {}: {}", user, e),
+            Ok(None) => warn!(
+                "exit for user {} not confirmed yet, create the receipt later with: receipt --user {} --txid {} --address {}",
+                user, user, txid, address
+            ),
+        }
     }
 
     Ok(())
 }
+
+// Wait until `txid` is as deep as `stage` asks. The plain demo only waits with a miner or esplora,
+// `poll_node` also waits on the node for the real network.
+async fn wait_stage(
+    rpc: &AsyncRpc,
+    config: &NetworkConfig,
+    tracker: &mut Option<ConfirmationTracker>,
+    txid: Txid,
+    stage: ConfirmationStage,
+    poll_node: bool,
+) -> Result<()> {
+    if poll_node || config.block_interval.is_some() || tracker.is_some() {
+        wait_for_depth(rpc, config, tracker, txid, stage).await?;
+    }
+    Ok(())
+}
+
+// the demo's pool: the users' registered addresses or wallet addresses, its tree and manifest
+async fn create_pool(
+    cli: &Cli,
+    rpc: &AsyncRpc,
+    config: &mut NetworkConfig,
+    backend: &dyn CovenantBackend,
+    anchor_addr: &Address,
+) -> Result<(Vec<Address>, PoolTree, PoolManifest)> {
+    info!("Creating pool with {} users \n", POOL_USERS);
+
+    // TODO: allow for input for address list with weights.
+    // the leaves of the CTV tree are the withdraw addresses
+    let RegisteredUsers {
+        addresses: withdraw_addresses,
+        derivations,
+        splits,
+        anchor_keys,
+        musig_keys,
+    } = match &cli.registrations {
+        // collaborative pool, every address comes with a proof its owner registered for this pool
+        Some(path) => {
+            let pool_id = cli
+                .pool_id
+                .as_ref()
+                .ok_or_else(|| anyhow!("--registrations needs the --pool-id the users signed"))?;
+            // the last pool's manifest says which xpub children were paid already
+            let previous = if cli.manifest.exists() {
+                Some(PoolManifest::load(&cli.manifest)?)
+            } else {
+                None
+            };
+            load_registrations(path, config.network, pool_id, previous.as_ref())?
+        }
+        // demo, the wallet plays every user
+        None => {
+            let mut addresses = Vec::with_capacity(POOL_USERS);
+            for _ in 0..POOL_USERS {
+                addresses.push(
+                    rpc.run(|c| c.get_new_address(None, None))
+                        .await?
+                        .require_network(config.network)?,
+                );
+            }
+            RegisteredUsers {
+                addresses,
+                ..Default::default()
+            }
+        }
+    };
+    // the leaves of users with splits pay all of their addresses
+    config.splits = splits;
+    // and those of users with an anchor key pay their own anchor
+    config.anchor_keys = anchor_keys;
+    // and a root with everyone's musig key can be closed through its key path
+    config.musig_keys = musig_keys;
+
+    // registration order stays out of the tree
+    let (withdraw_addresses, derivations, leaf_order) = match &cli.pool_id {
+        Some(pool_id) if cli.registrations.is_some() => {
+            shuffle_registrations(pool_id, &withdraw_addresses, &derivations)?
+        }
+        _ => (withdraw_addresses, derivations, Vec::new()),
+    };
+
+    // Log all withdraw addresses
+    for (i, addr) in withdraw_addresses.iter().enumerate() {
+        info!("User {} withdraw address: {}", i, redact::short(addr));
+        trace!("User {} withdraw address: {}", i, addr);
+    }
+
+    let pools = build_pools(&withdraw_addresses, anchor_addr, config, backend)?;
+    let pool_0_spend_info = pools.spend_info(&NodePath::root())?;
+
+    //the first pools address
+    let pool_0_addr = Address::p2tr_tweaked(pool_0_spend_info.output_key(), config.network);
+    info!("Initial pool address: {}", redact::short(&pool_0_addr));
+    trace!("Initial pool address: {}", pool_0_addr);
+
+    let mut manifest = PoolManifest::new(
+        config,
+        backend,
+        anchor_addr,
+        &withdraw_addresses,
+        &pool_0_addr,
+    );
+    manifest.pool_id = cli.pool_id.clone();
+    manifest.derivations = derivations;
+    manifest.leaf_order = leaf_order;
+    manifest.write(&cli.manifest)?;
+
+    Ok((withdraw_addresses, pools, manifest))
+}
@@ -0,0 +1,446 @@
+use std::{fs, path::Path, str::FromStr};
+
+use anyhow::{anyhow, bail, Result};
+use bitcoin::{taproot::LeafVersion, Address, Amount, Network, ScriptBuf, Txid, XOnlyPublicKey};
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+#[cfg(feature = "elements")]
+use crate::elements::{AssetId, IntrospectionBackend};
+use crate::{
+    checkpoints::Checkpoint,
+    config::{NetworkConfig, FEE_AMOUNT},
+    covenant::{CovenantBackend, CtvBackend, EphemeralSignerBackend, INTROSPECTION_BACKEND_NAME},
+    ctv_scripts::{TemplateVersion, TreeLayout},
+    error_codes::{coded, ErrorCode},
+    fee_policy::{parse_fee_policy, FeePolicy, FixedFee},
+    ids::{NodePath, PoolId, UserIndex},
+    lifecycle::{Event, Lifecycle},
+    metadata::PoolMetadata,
+    musig::{check_musig_keys, MusigKeys},
+    payouts::{check_all_splits, jittered_share, PayoutJitter, PayoutSplits},
+    policy::POLICY_BACKEND_NAME,
+    pools::build_pools,
+    profile::NetworkProfile,
+    recovery::RecoveryPath,
+    registration::{leaf_order, xpub_address, AddressDerivation},
+    removal::{check_removals, pool_members, Removal},
+    state::{decode_state, encode_state, is_binary_path},
+    template::Bip119Ctv,
+    tree::PoolTree,
+    user_anchor::{check_anchor_keys, AnchorKeys},
+    AMOUNT_PER_USER, POOL_USERS,
+};
+
+// Everything that went into the pool tree. Small enough to hand to every member,
+// anyone holding it can rebuild the whole tree offline and check the root address.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PoolManifest {
+    pub profile: NetworkProfile,
+    pub network: Network,
+    pub tx_version: i32,
+    pub anchor_address: String,
+    pub anchor_amount: Option<Amount>,
+    pub covenant: String,
+    // the presigned backend's (now deleted) key is part of every leaf script
+    pub covenant_key: Option<XOnlyPublicKey>,
+    pub amount_per_user: Amount,
+    pub withdraw_addresses: Vec<String>,
+    pub root_address: String,
+    pub funding_txid: Option<Txid>,
+    // timeout leaf on every node, see recovery.rs
+    #[serde(default)]
+    pub recovery_address: Option<String>,
+    #[serde(default)]
+    pub recovery_timeout: Option<u16>,
+    // relative timelock on every unwind spend, part of the template hashes
+    #[serde(default)]
+    pub unwind_delay: Option<u16>,
+    // what the users signed when registering their addresses (BIP-322)
+    #[serde(default)]
+    pub pool_id: Option<PoolId>,
+    // older manifests are all balanced
+    #[serde(default)]
+    pub tree_layout: TreeLayout,
+    // and all TapScript
+    #[serde(default = "tapscript")]
+    pub leaf_version: LeafVersion,
+    // withdraw addresses derived from a registered xpub, the next pool pays the child after these
+    #[serde(default)]
+    pub derivations: Vec<AddressDerivation>,
+    // registration of the user at every position in the tree (see registration::leaf_order),
+    // empty when the addresses are in the order they were given
+    #[serde(default)]
+    pub leaf_order: Vec<UserIndex>,
+    // how the tree and its templates were built, older manifests are all v1
+    #[serde(default)]
+    pub template_version: TemplateVersion,
+    // the coordinator's labels on the pool and its users, see metadata.rs
+    #[serde(default)]
+    pub metadata: PoolMetadata,
+    // registered, funded, how far the unwind got, see lifecycle.rs
+    #[serde(default)]
+    pub lifecycle: Lifecycle,
+    // the root has a leaf paying every user at once, see pools::close_all_exit
+    #[serde(default)]
+    pub close_all_leaf: bool,
+    // the block of every confirmed pool tx, where a restart picks up from, see checkpoints.rs
+    #[serde(default)]
+    pub checkpoints: Vec<Checkpoint>,
+    // withdraw address -> the other addresses its exit also pays, see payouts.rs
+    #[serde(default)]
+    pub splits: PayoutSplits,
+    // what each user's share was moved by, by position in the tree, empty without jitter. See payouts.rs
+    #[serde(default)]
+    pub payout_jitter: PayoutJitter,
+    // cooldown of each level from the root down, part of the template hashes like unwind_delay
+    #[serde(default)]
+    pub level_delays: Vec<u16>,
+    // what the templates' fees came from (FeePolicy::spec), older manifests all paid FEE_AMOUNT
+    #[serde(default = "fixed_fee")]
+    pub fee_policy: String,
+    // the scripts the templates pay, checked against the addresses above and the config on load.
+    // Empty for older manifests, their addresses are all there is
+    #[serde(default)]
+    pub committed_scripts: CommittedScripts,
+    // withdraw address -> key the anchor of that user's exits pays, see user_anchor.rs
+    #[serde(default)]
+    pub anchor_keys: AnchorKeys,
+    // participants taken out before funding, the pool is funded at the node without them. See removal.rs
+    #[serde(default)]
+    pub removed: Vec<Removal>,
+    // bumped every time the pool is rebuilt after it was handed out, e.g. for a removal
+    #[serde(default)]
+    pub revision: u32,
+    // withdraw address -> key the user signs a key path close with, the root's internal key is
+    // their aggregate. Empty for a root only spendable through its leaves, see musig.rs
+    #[serde(default)]
+    pub musig_keys: MusigKeys,
+}
+
+// scriptPubKeys as they were when the pool was built
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CommittedScripts {
+    pub anchor: ScriptBuf,
+    // by position in the tree
+    pub withdraw: Vec<ScriptBuf>,
+    pub recovery: Option<ScriptBuf>,
+}
+
+impl CommittedScripts {
+    pub fn new(
+        anchor_addr: &Address,
+        addresses: &[Address],
+        recovery: Option<&RecoveryPath>,
+    ) -> Self {
+        Self {
+            anchor: anchor_addr.script_pubkey(),
+            withdraw: addresses.iter().map(Address::script_pubkey).collect(),
+            recovery: recovery.map(|recovery| recovery.address.script_pubkey()),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.anchor.is_empty()
+    }
+}
+
+fn tapscript() -> LeafVersion {
+    LeafVersion::TapScript
+}
+
+pub fn fixed_fee() -> String {
+    FixedFee(FEE_AMOUNT).spec()
+}
+
+// a manifest turned back into the objects the pool code works with
+pub struct LoadedPool {
+    pub config: NetworkConfig,
+    pub backend: Box<dyn CovenantBackend>,
+    pub addresses: Vec<Address>,
+    pub anchor_addr: Address,
+    pub tree: PoolTree,
+}
+
+impl PoolManifest {
+    pub fn new(
+        config: &NetworkConfig,
+        backend: &dyn CovenantBackend,
+        anchor_addr: &Address,
+        addresses: &[Address],
+        root_address: &Address,
+    ) -> Self {
+        Self {
+            profile: config.profile,
+            network: config.network,
+            tx_version: config.tx_version,
+            anchor_address: anchor_addr.to_string(),
+            anchor_amount: config.anchor_amount,
+            covenant: backend.name().to_string(),
+            covenant_key: backend.leaf_key(),
+            amount_per_user: AMOUNT_PER_USER,
+            withdraw_addresses: addresses.iter().map(|a| a.to_string()).collect(),
+            root_address: root_address.to_string(),
+            funding_txid: None,
+            recovery_address: config.recovery.as_ref().map(|r| r.address.to_string()),
+            recovery_timeout: config.recovery.as_ref().map(|r| r.timeout),
+            unwind_delay: config.unwind_delay,
+            pool_id: None,
+            tree_layout: config.tree_layout,
+            leaf_version: config.leaf_version,
+            derivations: Vec::new(),
+            leaf_order: Vec::new(),
+            template_version: config.template_version,
+            metadata: PoolMetadata::default(),
+            lifecycle: Lifecycle::Registered,
+            close_all_leaf: config.close_all_leaf,
+            checkpoints: Vec::new(),
+            splits: config.splits.clone(),
+            payout_jitter: config.payout_jitter.clone(),
+            level_delays: config.level_delays.clone(),
+            fee_policy: config.fee_policy.spec(),
+            committed_scripts: CommittedScripts::new(
+                anchor_addr,
+                addresses,
+                config.recovery.as_ref(),
+            ),
+            anchor_keys: config.anchor_keys.clone(),
+            removed: config.removed.clone(),
+            revision: 0,
+            musig_keys: config.musig_keys.clone(),
+        }
+    }
+
+    // the node the pool is funded at, see NetworkConfig::pool_root
+    pub fn pool_root(&self) -> Result<NodePath> {
+        pool_members(&self.removed)
+    }
+
+    // what the funding output has to hold: the share of every user the pool is funded for
+    pub fn funding_target(&self) -> Result<Amount> {
+        self.pool_root()?
+            .user_indices()
+            .try_fold(Amount::ZERO, |total, user| {
+                total.checked_add(jittered_share(user, &self.payout_jitter))
+            })
+            .ok_or_else(|| anyhow!("the funding target overflows"))
+    }
+
+    // move the lifecycle on, refusing anything the pool's state doesn't allow
+    pub fn advance(&mut self, event: Event) -> Result<()> {
+        let next = self.lifecycle.apply_in(event, &self.pool_root()?)?;
+        info!("pool {} is now {} \n", self.root_address, next);
+        self.lifecycle = next;
+        Ok(())
+    }
+
+    // json, or the binary state format for a .ctvpool path
+    pub fn write(&self, path: &Path) -> Result<()> {
+        if is_binary_path(path) {
+            fs::write(path, encode_state(self)?)?;
+        } else {
+            fs::write(path, serde_json::to_string_pretty(self)?)?;
+        }
+        info!("pool manifest written to {} \n", path.display());
+        Ok(())
+    }
+
+    // either format, older binary versions are migrated on load
+    pub fn load(path: &Path) -> Result<Self> {
+        Ok(decode_state(&fs::read(path)?)?.0)
+    }
+
+    // Rebuild the tree and make sure it ends up at the recorded root address.
+    // Can take a while for big pools, it is the same work as creating the pool.
+    pub fn load_pool(&self) -> Result<LoadedPool> {
+        let pool = self.rebuild_pool(NetworkConfig::new(self.profile))?;
+        let root = pool
+            .tree
+            .node(&pool.config.pool_root()?)?
+            .address(&pool.config);
+        if root.to_string() != self.root_address {
+            return Err(coded(
+                ErrorCode::TemplateMismatch,
+                format!(
+                    "rebuilt pool address {} does not match the manifest ({})",
+                    root, self.root_address
+                ),
+            ));
+        }
+        Ok(pool)
+    }
+
+    // Refuse a config whose scripts moved away from the ones the pool's templates pay. Anchor children
+    // are built from the config, with another anchor script they can't find the anchor they spend.
+    fn check_config_drift(
+        &self,
+        config: &NetworkConfig,
+        anchor_addr: &Address,
+        recovery: Option<&RecoveryPath>,
+    ) -> Result<()> {
+        let committed_anchor = if self.committed_scripts.is_empty() {
+            anchor_addr.script_pubkey()
+        } else {
+            self.committed_scripts.anchor.clone()
+        };
+        if self.anchor_amount.is_some() {
+            let configured = Address::from_str(&config.fee_anchor_addr)?
+                .require_network(self.network)?
+                .script_pubkey();
+            if configured != committed_anchor {
+                bail!(
+                    "the fee anchor changed since the pool was built: its templates pay {} but the config has {} (POOL_FEE_ANCHOR_ADDR)",
+                    committed_anchor.to_hex_string(),
+                    configured.to_hex_string()
+                );
+            }
+        }
+        if let (Some(configured), Some(recovery)) = (&config.recovery, recovery) {
+            if configured.address != recovery.address {
+                bail!(
+                    "the recovery address changed since the pool was built: its nodes sweep to {} but the config has {} (RECOVERY_ADDRESS)",
+                    recovery.address,
+                    configured.address
+                );
+            }
+        }
+        Ok(())
+    }
+
+    // The tree as the manifest describes it, without checking the root. Everything that goes into
+    // the tree comes from the manifest, `config` only adds the connection settings.
+    pub fn rebuild_pool(&self, mut config: NetworkConfig) -> Result<LoadedPool> {
+        if self.withdraw_addresses.len() != POOL_USERS {
+            bail!(
+                "manifest has {} users but this build is for {} (POOL_USERS)",
+                self.withdraw_addresses.len(),
+                POOL_USERS
+            );
+        }
+        if self.amount_per_user != AMOUNT_PER_USER {
+            bail!(
+                "manifest has {} per user but this build uses {} (AMOUNT_PER_USER)",
+                self.amount_per_user,
+                AMOUNT_PER_USER
+            );
+        }
+
+        config.tx_version = self.tx_version;
+        config.anchor_amount = self.anchor_amount;
+        config.unwind_delay = self.unwind_delay;
+        config.level_delays = self.level_delays.clone();
+        config.tree_layout = self.tree_layout;
+        config.close_all_leaf = self.close_all_leaf;
+        config.splits = self.splits.clone();
+        config.anchor_keys = self.anchor_keys.clone();
+        config.musig_keys = self.musig_keys.clone();
+        config.payout_jitter = self.payout_jitter.clone();
+        check_removals(&self.removed, &self.payout_jitter)?;
+        config.removed = self.removed.clone();
+        config.leaf_version = self.leaf_version;
+        // the fees are committed already, the floor only guards new pools
+        config.min_template_fee_rate = None;
+        config.template_version = self.template_version;
+        let recovery = match (&self.recovery_address, self.recovery_timeout) {
+            (Some(address), Some(timeout)) => Some(RecoveryPath {
+                address: Address::from_str(address)?.require_network(self.network)?,
+                timeout,
+            }),
+            _ => None,
+        };
+        let anchor_addr = Address::from_str(&self.anchor_address)?.require_network(self.network)?;
+        self.check_config_drift(&config, &anchor_addr, recovery.as_ref())?;
+        // anchor children look for the anchor the templates pay
+        config.fee_anchor_addr = self.anchor_address.clone();
+        config.recovery = recovery;
+        // a custom policy already on the config stays, a built in one is parsed back
+        if config.fee_policy.spec() != self.fee_policy {
+            config.fee_policy = parse_fee_policy(&self.fee_policy).map_err(|e| {
+                anyhow!(
+                    "{}, a pool built with a custom FeePolicy has to be rebuilt with it on the config",
+                    e
+                )
+            })?;
+        }
+        let ctv = Bip119Ctv {
+            tx_version: self.tx_version,
+        };
+        let backend: Box<dyn CovenantBackend> = match (self.covenant.as_str(), self.covenant_key) {
+            ("presigned", Some(key)) => Box::new(EphemeralSignerBackend::watch_only(ctv, key)),
+            ("presigned", None) => bail!("presigned manifest is missing the covenant key"),
+            #[cfg(feature = "elements")]
+            (INTROSPECTION_BACKEND_NAME, _) => {
+                let params = self
+                    .profile
+                    .elements()
+                    .ok_or_else(|| anyhow!("an introspection pool needs an Elements profile"))?;
+                Box::new(IntrospectionBackend::new(AssetId::from_env(&params)?))
+            }
+            #[cfg(not(feature = "elements"))]
+            (INTROSPECTION_BACKEND_NAME, _) => bail!(
+                "this pool was built for Elements, rebuild with `--features elements`"
+            ),
+            (POLICY_BACKEND_NAME, _) => bail!(
+                "this pool's leaves come from a custom LeafPolicy, rebuild it with the PolicyBackend it was built with"
+            ),
+            _ => Box::new(CtvBackend::from(ctv)),
+        };
+
+        let addresses = self
+            .withdraw_addresses
+            .iter()
+            .map(|a| Ok(Address::from_str(a)?.require_network(self.network)?))
+            .collect::<Result<Vec<_>>>()?;
+        if !self.leaf_order.is_empty() {
+            let pool_id = self
+                .pool_id
+                .as_ref()
+                .ok_or_else(|| anyhow!("manifest has a leaf order but no pool id"))?;
+            if self.leaf_order != leaf_order(pool_id) {
+                bail!("leaf order is not the shuffle of pool id {:?}", pool_id);
+            }
+        }
+        for derivation in &self.derivations {
+            let derived = xpub_address(&derivation.xpub, derivation.index, self.network)?;
+            if addresses.get(derivation.user.index()) != Some(&derived) {
+                bail!(
+                    "user {}'s withdraw address is not child {} of their xpub ({})",
+                    derivation.user,
+                    derivation.index,
+                    derived
+                );
+            }
+        }
+        for address in self.splits.keys() {
+            if !self.withdraw_addresses.contains(address) {
+                bail!(
+                    "manifest splits the exit of {}, which is not in the pool",
+                    address
+                );
+            }
+        }
+        check_all_splits(&self.splits, self.network)?;
+        check_anchor_keys(&self.anchor_keys, &self.withdraw_addresses)?;
+        check_musig_keys(&self.musig_keys, &self.withdraw_addresses)?;
+        if !self.committed_scripts.is_empty() {
+            let recorded =
+                CommittedScripts::new(&anchor_addr, &addresses, config.recovery.as_ref());
+            if recorded != self.committed_scripts {
+                return Err(coded(
+                    ErrorCode::TemplateMismatch,
+                    "the manifest's addresses don't match the scripts its pool was built with, it was edited or is corrupt",
+                ));
+            }
+        }
+
+        let pools = build_pools(&addresses, &anchor_addr, &config, backend.as_ref())?;
+
+        Ok(LoadedPool {
+            config,
+            backend,
+            addresses,
+            anchor_addr,
+            tree: pools,
+        })
+    }
+}
@@ -0,0 +1,283 @@
+use std::{fs, path::Path};
+
+use anyhow::{anyhow, bail, Result};
+use bitcoin::{
+    absolute, hex::FromHex, key::Keypair, transaction, Address, Amount, Network, OutPoint,
+    Transaction, TxIn, TxOut, Txid, Witness,
+};
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use crate::{
+    ctv_scripts::ctv_script,
+    esplora::Esplora,
+    export::{ExportedLeaf, ExportedNode, PoolExport},
+    ids::{NodePath, UserIndex},
+    user_anchor::{anchor_child, anchor_key_address, tx_fee},
+    verify::verify_leaf_proof,
+};
+
+pub const DEFAULT_MEMBER_STATE: &str = "member.json";
+
+// What a participant keeps of the pool: their export (`export --user`, every node they are in
+// with their own leaf, or `--branch-only`), checked when imported, and where they were last seen.
+// No manifest, no other user's leaves and no node of their own, the chain is read over Esplora.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MemberState {
+    pub user: UserIndex,
+    pub export: PoolExport,
+    #[serde(default)]
+    pub position: Option<Position>,
+}
+
+// the pool node output holding the user
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Position {
+    pub node: NodePath,
+    pub outpoint: OutPoint,
+    pub amount: Amount,
+    pub confirmed: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum MemberStatus {
+    // the root isn't funded (or esplora hasn't seen the funding tx yet)
+    NotFunded,
+    InPool { position: Position },
+    // none of the user's nodes holds anything any more: they were paid or the node was swept
+    Left,
+}
+
+impl MemberState {
+    // Check every leaf of the export against its node address before keeping it
+    pub fn import(export: PoolExport) -> Result<Self> {
+        let user = export
+            .user
+            .ok_or_else(|| anyhow!("this is an export of the whole pool, use `export --user`"))?;
+        if export.tx_version.is_none() {
+            bail!("the export doesn't have the pools' tx version, export it again");
+        }
+        for node in &export.nodes {
+            if !node.users.contains(user) {
+                bail!(
+                    "the export has node {}, which user {} isn't in",
+                    node.users,
+                    user
+                );
+            }
+            let address = node_address(&export, node)?;
+            for leaf in &node.leaves {
+                verify_leaf_proof(&leaf.leaf_script, &leaf.proof, &address.script_pubkey())
+                    .map_err(|e| anyhow!("leaf of node {}: {}", node.users, e))?;
+            }
+        }
+        info!(
+            "imported {} pool nodes of user {}, every leaf checked against its node address \n",
+            export.nodes.len(),
+            user
+        );
+        Ok(Self {
+            user,
+            export,
+            position: None,
+        })
+    }
+
+    pub fn import_file(path: &Path) -> Result<Self> {
+        Self::import(serde_json::from_str(&fs::read_to_string(path)?)?)
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
+    }
+
+    pub fn write(&self, path: &Path) -> Result<()> {
+        fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    fn node(&self, users: &NodePath) -> Result<&ExportedNode> {
+        self.export
+            .nodes
+            .iter()
+            .find(|node| &node.users == users)
+            .ok_or_else(|| anyhow!("node {} isn't in the export", users))
+    }
+
+    // Where the user is now: the smallest of their nodes with an output on chain or in the mempool
+    pub async fn locate(&mut self, esplora: &Esplora) -> Result<MemberStatus> {
+        let mut nodes: Vec<&ExportedNode> = self.export.nodes.iter().collect();
+        nodes.sort_by_key(|node| node.users.len());
+        for node in nodes {
+            let address = node_address(&self.export, node)?;
+            // anyone can send to a node address, the pool's output is the big one
+            let Some(utxo) = esplora
+                .address_utxos(&address)
+                .await?
+                .into_iter()
+                .max_by_key(|utxo| utxo.value)
+            else {
+                continue;
+            };
+            let position = Position {
+                node: node.users.clone(),
+                outpoint: OutPoint::new(utxo.txid, utxo.vout),
+                amount: utxo.value,
+                confirmed: utxo.status.confirmed,
+            };
+            self.position = Some(position.clone());
+            return Ok(MemberStatus::InPool { position });
+        }
+        let funded = match self.export.funding_txid {
+            Some(txid) => esplora.tx_status(txid).await?.is_some(),
+            None => false,
+        };
+        if !funded && self.position.is_none() {
+            return Ok(MemberStatus::NotFunded);
+        }
+        self.position = None;
+        Ok(MemberStatus::Left)
+    }
+
+    // The txs taking the user from `position` out of the pool: their own leaf when the export has
+    // it, otherwise the planned unwind (a --branch-only export) down to the node where it is.
+    pub fn exit_chain(&self, position: &Position) -> Result<Vec<Transaction>> {
+        let version = self
+            .export
+            .tx_version
+            .ok_or_else(|| anyhow!("the export doesn't have the pools' tx version"))?;
+        let mut users = position.node.clone();
+        let mut outpoint = position.outpoint;
+        let mut chain = Vec::new();
+        loop {
+            let node = self.node(&users)?;
+            let own = node
+                .leaves
+                .iter()
+                .find(|leaf| leaf.spender.is_none() || leaf.spender == Some(self.user));
+            let leaf = own
+                .or_else(|| node.leaves.first())
+                .ok_or_else(|| anyhow!("the export has no leaf out of node {}", users))?;
+            let tx = leaf_spend(version, outpoint, leaf)?;
+            let txid = tx.compute_txid();
+            if own.is_some() {
+                chain.push(tx);
+                return Ok(chain);
+            }
+            // the other users' leaf pays the node the user moves on to
+            let (vout, next) = tx
+                .output
+                .iter()
+                .enumerate()
+                .find_map(|(vout, output)| {
+                    self.export.nodes.iter().find_map(|node| {
+                        let address = node_address(&self.export, node).ok()?;
+                        (address.script_pubkey() == output.script_pubkey)
+                            .then(|| (vout, node.users.clone()))
+                    })
+                })
+                .ok_or_else(|| {
+                    anyhow!(
+                        "the leaf out of {} pays none of user {}'s nodes",
+                        users,
+                        self.user
+                    )
+                })?;
+            chain.push(tx);
+            outpoint = OutPoint::new(txid, vout as u32);
+            users = next;
+        }
+    }
+}
+
+fn node_address(export: &PoolExport, node: &ExportedNode) -> Result<Address> {
+    Ok(node
+        .address
+        .parse::<Address<_>>()?
+        .require_network(export.network)?)
+}
+
+// The spend of `outpoint` through `leaf`. Only a bare `<template hash> OP_CTV` leaf can be spent
+// with nothing but the leaf script and control block, a presigned leaf needs the signature the
+// coordinator made (the `next_steps` of its webhooks).
+pub fn leaf_spend(version: i32, outpoint: OutPoint, leaf: &ExportedLeaf) -> Result<Transaction> {
+    let template_hash = <[u8; 32]>::from_hex(&leaf.template_hash)?;
+    if leaf.leaf_script != ctv_script(template_hash) {
+        bail!("the leaf isn't a bare OP_CTV leaf, its spend needs the coordinator's presigned tx");
+    }
+    let mut witness = Witness::new();
+    witness.push(leaf.leaf_script.as_bytes());
+    witness.push(Vec::<u8>::from_hex(&leaf.control_block)?);
+    Ok(Transaction {
+        version: transaction::Version(version),
+        lock_time: absolute::LockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: outpoint,
+            sequence: leaf.sequence,
+            witness,
+            ..Default::default()
+        }],
+        output: leaf.outputs.clone(),
+    })
+}
+
+// Send the exit chain parent first, stops at the first tx esplora doesn't take
+pub async fn broadcast_chain(esplora: &Esplora, chain: &[Transaction]) -> Result<Vec<Txid>> {
+    let mut txids = Vec::new();
+    for tx in chain {
+        let txid = esplora.broadcast(tx).await?;
+        info!("broadcast {} \n", txid);
+        txids.push(txid);
+    }
+    Ok(txids)
+}
+
+// The user's CPFP of `parent_txid`, an exit paying the anchor key of `keypair`: the package pays
+// `sat_per_vb`, topped up from coins at the anchor key's address when the anchor alone is too small
+pub async fn bump_exit(
+    esplora: &Esplora,
+    parent_txid: Txid,
+    keypair: &Keypair,
+    sat_per_vb: u64,
+    network: Network,
+) -> Result<Transaction> {
+    let parent = esplora
+        .tx(parent_txid)
+        .await?
+        .ok_or_else(|| anyhow!("esplora hasn't seen {}", parent_txid))?;
+    let mut spent = Vec::new();
+    for input in &parent.input {
+        let previous = input.previous_output;
+        let tx = esplora
+            .tx(previous.txid)
+            .await?
+            .ok_or_else(|| anyhow!("esplora hasn't seen {}", previous.txid))?;
+        let output = tx
+            .output
+            .get(previous.vout as usize)
+            .ok_or_else(|| anyhow!("{} has no output {}", previous.txid, previous.vout))?;
+        spent.push(output.clone());
+    }
+    let parent_fee = tx_fee(&parent, &spent)?;
+
+    let address = anchor_key_address(keypair.x_only_public_key().0, network);
+    let coins: Vec<(OutPoint, TxOut)> = esplora
+        .address_utxos(&address)
+        .await?
+        .into_iter()
+        .filter(|utxo| utxo.txid != parent_txid)
+        .map(|utxo| {
+            (
+                OutPoint::new(utxo.txid, utxo.vout),
+                TxOut {
+                    value: utxo.value,
+                    script_pubkey: address.script_pubkey(),
+                },
+            )
+        })
+        .collect();
+    // the anchor alone first, coins only when it can't pay the child
+    anchor_child(&parent, parent_fee, keypair, &[], sat_per_vb, network)
+        .or_else(|_| anchor_child(&parent, parent_fee, keypair, &coins, sat_per_vb, network))
+}
@@ -0,0 +1,192 @@
+use std::{fs, path::Path};
+
+use anyhow::{anyhow, bail, Result};
+use bitcoin::{
+    address::NetworkUnchecked, hex::DisplayHex, script::Instruction, Address, Amount, Network,
+    ScriptBuf, Sequence, TxOut, Txid,
+};
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use crate::{
+    ctv_scripts::{calc_ctv_hash, OP_SECURETHEBAG},
+    ids::UserIndex,
+    manifest::{LoadedPool, PoolManifest},
+    payouts::user_scripts,
+    pools::node_exit,
+    verify::{verify_leaf_proof, LeafProof},
+};
+
+// Proof that `address` is paid by a leaf of the pool's root output: the template the leaf commits to
+// and the leaf's path up to the output key. An auditor checks it with verify_membership, without the
+// manifest, the other users or a node.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MembershipProof {
+    pub network: Network,
+    pub address: Address<NetworkUnchecked>,
+    pub user: UserIndex,
+    pub root_address: Address<NetworkUnchecked>,
+    pub funding_txid: Option<Txid>,
+    // the spend the leaf allows: the tx version, the input's nSequence and every output
+    pub tx_version: i32,
+    pub sequence: Sequence,
+    pub outputs: Vec<TxOut>,
+    pub template_hash: String,
+    pub leaf_script: ScriptBuf,
+    // tapleaf hash, merkle path, internal and output key
+    pub proof: LeafProof,
+}
+
+// What a proof shows once checked
+#[derive(Debug, Clone, Serialize)]
+pub struct VerifiedMembership {
+    pub address: String,
+    pub user: UserIndex,
+    pub root_address: String,
+    // what the leaf pays the address
+    pub amount: Amount,
+    // the leaf checks the template with OP_CTV, otherwise it is only as good as the presigning
+    // key being deleted
+    pub ctv_enforced: bool,
+    pub merkle_path_length: usize,
+}
+
+// The leaf `address` leaves the root by: the user's withdraw address or one of their splits
+pub fn prove_membership(manifest: &PoolManifest, address: &str) -> Result<MembershipProof> {
+    prove_loaded_membership(&manifest.load_pool()?, manifest, address)
+}
+
+pub fn prove_loaded_membership(
+    pool: &LoadedPool,
+    manifest: &PoolManifest,
+    address: &str,
+) -> Result<MembershipProof> {
+    let address = address
+        .parse::<Address<NetworkUnchecked>>()?
+        .require_network(manifest.network)
+        .map_err(|_| anyhow!("{} is not a {} address", address, manifest.network))?;
+    let script_pubkey = address.script_pubkey();
+    let root = pool.config.pool_root()?;
+    let user = root
+        .user_indices()
+        .find(|user| {
+            user_scripts(&pool.addresses[user.index()], &pool.config).contains(&script_pubkey)
+        })
+        .ok_or_else(|| anyhow!("{} is not paid by this pool", address))?;
+
+    let exit = node_exit(
+        &pool.tree,
+        &pool.config,
+        pool.backend.as_ref(),
+        &pool.addresses,
+        &pool.anchor_addr,
+        &root,
+        user,
+    )?;
+    let leaf_script = pool.backend.leaf_script(exit.template_hash);
+    let proof = LeafProof::new(&exit.spend_info, &leaf_script, pool.config.leaf_version)?;
+    let root_address = Address::p2tr_tweaked(exit.spend_info.output_key(), manifest.network);
+
+    Ok(MembershipProof {
+        network: manifest.network,
+        address: address.as_unchecked().clone(),
+        user,
+        root_address: root_address.as_unchecked().clone(),
+        funding_txid: manifest.funding_txid,
+        tx_version: pool.config.tx_version,
+        sequence: exit.sequence,
+        outputs: exit.outputs,
+        template_hash: exit.template_hash.to_lower_hex_string(),
+        leaf_script,
+        proof,
+    })
+}
+
+// Check a proof on its own: the outputs pay the address, they are the template the leaf commits
+// to and the leaf is in the tap tree of the root address
+pub fn verify_membership(proof: &MembershipProof) -> Result<VerifiedMembership> {
+    let address = proof.address.clone().require_network(proof.network)?;
+    let root_address = proof.root_address.clone().require_network(proof.network)?;
+    let script_pubkey = address.script_pubkey();
+    let amount = proof
+        .outputs
+        .iter()
+        .find(|output| output.script_pubkey == script_pubkey)
+        .map(|output| output.value)
+        .ok_or_else(|| anyhow!("the template doesn't pay {}", address))?;
+
+    let template_hash = calc_ctv_hash(proof.tx_version, &proof.outputs, &[proof.sequence]);
+    if template_hash.to_lower_hex_string() != proof.template_hash {
+        bail!(
+            "the outputs hash to template {}, the proof is for {}",
+            template_hash.to_lower_hex_string(),
+            proof.template_hash
+        );
+    }
+    let instructions = proof
+        .leaf_script
+        .instructions()
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| anyhow!("the leaf script doesn't parse: {}", e))?;
+    let pushes_template = |instruction: &Instruction| match instruction {
+        Instruction::PushBytes(bytes) => bytes.as_bytes() == template_hash,
+        Instruction::Op(_) => false,
+    };
+    if !instructions.iter().any(pushes_template) {
+        bail!(
+            "the leaf script doesn't commit to template {}",
+            proof.template_hash
+        );
+    }
+    let ctv_enforced = instructions
+        .windows(2)
+        .any(|pair| pushes_template(&pair[0]) && pair[1] == Instruction::Op(OP_SECURETHEBAG));
+
+    verify_leaf_proof(
+        &proof.leaf_script,
+        &proof.proof,
+        &root_address.script_pubkey(),
+    )?;
+
+    Ok(VerifiedMembership {
+        address: address.to_string(),
+        user: proof.user,
+        root_address: root_address.to_string(),
+        amount,
+        ctv_enforced,
+        merkle_path_length: proof.proof.siblings.len(),
+    })
+}
+
+pub fn write_membership_proof(proof: &MembershipProof, output: Option<&Path>) -> Result<()> {
+    let json = serde_json::to_string_pretty(proof)?;
+    match output {
+        Some(path) => {
+            fs::write(path, json)?;
+            info!(
+                "membership proof for {} written to {} \n",
+                proof.address.assume_checked_ref(),
+                path.display()
+            );
+        }
+        None => println!("{}", json),
+    }
+    Ok(())
+}
+
+pub fn verify_membership_file(path: &Path) -> Result<()> {
+    let proof: MembershipProof = serde_json::from_str(&fs::read_to_string(path)?)?;
+    let verified = verify_membership(&proof)?;
+    println!(
+        "{} (user {}) is paid {} by a leaf of {}, {} sibling hashes",
+        verified.address,
+        verified.user,
+        verified.amount,
+        verified.root_address,
+        verified.merkle_path_length
+    );
+    if !verified.ctv_enforced {
+        println!("the leaf doesn't check the template with OP_CTV, it holds only if the presigning key was deleted");
+    }
+    Ok(())
+}
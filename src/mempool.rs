@@ -0,0 +1,280 @@
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    time::Duration,
+};
+
+use anyhow::Result;
+use bitcoin::{consensus::encode::serialize_hex, Transaction, Txid};
+use bitcoincore_rpc::RpcApi;
+use tracing::info;
+
+use crate::{
+    broadcast::{broadcast, rpc_reject, BroadcastKind},
+    config::NetworkConfig,
+    rpc_helper::AsyncRpc,
+};
+
+// how often a deferred tx looks for room when no miner sets the pace
+const DEFAULT_POLL: Duration = Duration::from_secs(30);
+
+// How long an unconfirmed chain bitcoind accepts, counts include the tx itself.
+// POOL_MEMPOOL_ANCESTORS / POOL_MEMPOOL_DESCENDANTS for a node with other -limit*count settings.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MempoolLimits {
+    pub ancestors: usize,
+    pub descendants: usize,
+}
+
+impl MempoolLimits {
+    // -limitancestorcount / -limitdescendantcount defaults
+    pub const CORE_DEFAULT: Self = Self {
+        ancestors: 25,
+        descendants: 25,
+    };
+    // a v3 (TRUC) tx has at most one unconfirmed parent and one unconfirmed child
+    pub const TRUC: Self = Self {
+        ancestors: 2,
+        descendants: 2,
+    };
+
+    // the limits txs of `tx_version` are held to
+    pub fn for_tx_version(self, tx_version: i32) -> Self {
+        if tx_version == 3 {
+            Self {
+                ancestors: self.ancestors.min(Self::TRUC.ancestors),
+                descendants: self.descendants.min(Self::TRUC.descendants),
+            }
+        } else {
+            self
+        }
+    }
+}
+
+// an unconfirmed tx of the chain
+#[derive(Debug, Clone)]
+struct Unconfirmed {
+    // its unconfirmed parents the queue knows
+    parents: Vec<Txid>,
+    // unconfirmed ancestors the queue didn't send, as the node counted them
+    outside_ancestors: usize,
+}
+
+#[derive(Debug, Clone)]
+struct Waiting {
+    tx: Transaction,
+    kind: BroadcastKind,
+}
+
+// Pool txs go out through this so an unwind running ahead of the blocks doesn't hit bitcoind's
+// chain limits: it keeps the unconfirmed txs of the pool's chain, and a tx that would make the chain
+// too long waits in the queue until enough of it confirms.
+#[derive(Debug)]
+pub struct BroadcastQueue {
+    limits: MempoolLimits,
+    poll: Duration,
+    unconfirmed: HashMap<Txid, Unconfirmed>,
+    waiting: VecDeque<Waiting>,
+}
+
+impl BroadcastQueue {
+    pub fn new(config: &NetworkConfig) -> Self {
+        Self::with_limits(
+            config.mempool_limits.for_tx_version(config.tx_version),
+            config.block_interval.unwrap_or(DEFAULT_POLL),
+        )
+    }
+
+    pub fn with_limits(limits: MempoolLimits, poll: Duration) -> Self {
+        Self {
+            limits,
+            poll,
+            unconfirmed: HashMap::new(),
+            waiting: VecDeque::new(),
+        }
+    }
+
+    pub fn limits(&self) -> MempoolLimits {
+        self.limits
+    }
+
+    // txs still waiting for room
+    pub fn waiting(&self) -> usize {
+        self.waiting.len()
+    }
+
+    // `txid` is in the mempool spending `parents`, which of them are unconfirmed the queue works out
+    pub fn track(&mut self, txid: Txid, parents: &[Txid], outside_ancestors: usize) {
+        let parents = parents
+            .iter()
+            .filter(|parent| self.unconfirmed.contains_key(*parent))
+            .copied()
+            .collect();
+        self.unconfirmed.insert(
+            txid,
+            Unconfirmed {
+                parents,
+                outside_ancestors,
+            },
+        );
+    }
+
+    // `txid` left the mempool in a block, its ancestors had to be confirmed before it
+    pub fn confirmed(&mut self, txid: Txid) {
+        for ancestor in self.ancestors(&[txid]) {
+            self.unconfirmed.remove(&ancestor);
+        }
+        self.unconfirmed.remove(&txid);
+        for unconfirmed in self.unconfirmed.values_mut() {
+            unconfirmed.parents.retain(|parent| *parent != txid);
+        }
+    }
+
+    // the unconfirmed txs the queue knows among `parents` and all of their ancestors
+    fn ancestors(&self, parents: &[Txid]) -> HashSet<Txid> {
+        let mut ancestors = HashSet::new();
+        let mut next: Vec<Txid> = parents.to_vec();
+        while let Some(txid) = next.pop() {
+            if let Some(unconfirmed) = self.unconfirmed.get(&txid) {
+                if ancestors.insert(txid) {
+                    next.extend(&unconfirmed.parents);
+                }
+            }
+        }
+        ancestors
+    }
+
+    // ancestors of a tx spending `parents`, itself included. Outside ancestors shared by two
+    // parents are counted twice, which only ever makes it wait longer.
+    pub fn ancestor_count(&self, parents: &[Txid]) -> usize {
+        let ancestors = self.ancestors(parents);
+        let outside: usize = ancestors
+            .iter()
+            .map(|txid| self.unconfirmed[txid].outside_ancestors)
+            .sum();
+        ancestors.len() + outside + 1
+    }
+
+    // unconfirmed descendants of `txid`, itself included
+    pub fn descendant_count(&self, txid: Txid) -> usize {
+        self.unconfirmed
+            .keys()
+            .filter(|other| **other != txid && self.ancestors(&[**other]).contains(&txid))
+            .count()
+            + 1
+    }
+
+    // whether a tx spending `parents` stays within the limits, for itself and every ancestor
+    pub fn fits(&self, parents: &[Txid]) -> bool {
+        self.ancestor_count(parents) <= self.limits.ancestors
+            && self
+                .ancestors(parents)
+                .into_iter()
+                .all(|ancestor| self.descendant_count(ancestor) < self.limits.descendants)
+    }
+
+    // queue `tx`, it goes out with the next flush there is room in
+    pub fn push(&mut self, tx: Transaction, kind: BroadcastKind) {
+        self.waiting.push_back(Waiting { tx, kind });
+    }
+
+    // Broadcast the waiting txs in order until one doesn't fit, a later one may spend it.
+    // Returns the txids sent.
+    pub async fn flush(&mut self, rpc: &AsyncRpc) -> Result<Vec<Txid>> {
+        let mut sent = Vec::new();
+        while let Some(waiting) = self.waiting.front() {
+            let (txid, kind, parents) = (
+                waiting.tx.compute_txid(),
+                waiting.kind,
+                parents_of(&waiting.tx),
+            );
+            self.learn_parents(rpc, &parents).await?;
+            if !self.fits(&parents) {
+                info!(
+                    "{} {} deferred until more of its {} unconfirmed ancestors confirm (mempool limits {} ancestors, {} descendants) \n",
+                    kind,
+                    txid,
+                    self.ancestor_count(&parents) - 1,
+                    self.limits.ancestors,
+                    self.limits.descendants
+                );
+                break;
+            }
+            let waiting = self.waiting.pop_front().expect("front was some");
+            let txid = broadcast(rpc, serialize_hex(&waiting.tx), waiting.kind).await?;
+            self.track(txid, &parents, 0);
+            sent.push(txid);
+        }
+        Ok(sent)
+    }
+
+    // Drop the txs that left the mempool. Anything gone from it was mined (or replaced, then the
+    // chain behind it is gone too).
+    pub async fn refresh(&mut self, rpc: &AsyncRpc) -> Result<()> {
+        let tracked: Vec<Txid> = self.unconfirmed.keys().copied().collect();
+        for txid in tracked {
+            if self.unconfirmed.contains_key(&txid) && !in_mempool(rpc, txid).await? {
+                self.confirmed(txid);
+            }
+        }
+        Ok(())
+    }
+
+    // Queue `tx` and wait until it is broadcast, looking for room after every poll. The blocks
+    // since the last send may have made room already, so it looks before the first try too.
+    pub async fn send(
+        &mut self,
+        rpc: &AsyncRpc,
+        tx: Transaction,
+        kind: BroadcastKind,
+    ) -> Result<Txid> {
+        let txid = tx.compute_txid();
+        self.push(tx, kind);
+        loop {
+            self.refresh(rpc).await?;
+            if self.flush(rpc).await?.contains(&txid) {
+                return Ok(txid);
+            }
+            tokio::time::sleep(self.poll).await;
+        }
+    }
+
+    // parents in the mempool the queue didn't send (e.g. the funding tx), with their own ancestors
+    async fn learn_parents(&mut self, rpc: &AsyncRpc, parents: &[Txid]) -> Result<()> {
+        for &parent in parents {
+            if self.unconfirmed.contains_key(&parent) {
+                continue;
+            }
+            let entry = rpc.run(move |c| c.get_mempool_entry(&parent)).await;
+            match entry {
+                Ok(entry) => self.track(
+                    parent,
+                    &[],
+                    (entry.ancestor_count as usize).saturating_sub(1),
+                ),
+                Err(e) if rpc_reject(&e).is_some() => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+}
+
+fn parents_of(tx: &Transaction) -> Vec<Txid> {
+    let mut parents: Vec<Txid> = tx
+        .input
+        .iter()
+        .map(|input| input.previous_output.txid)
+        .collect();
+    parents.sort();
+    parents.dedup();
+    parents
+}
+
+// getmempoolentry answers with an rpc error for a tx that isn't in the mempool
+pub async fn in_mempool(rpc: &AsyncRpc, txid: Txid) -> Result<bool> {
+    match rpc.run(move |c| c.get_mempool_entry(&txid)).await {
+        Ok(_) => Ok(true),
+        Err(e) if rpc_reject(&e).is_some() => Ok(false),
+        Err(e) => Err(e),
+    }
+}
@@ -0,0 +1,87 @@
+use std::collections::BTreeMap;
+
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::ids::UserIndex;
+
+// Labels the coordinator keeps on the pool and on its users (names, emails, internal ids), so a
+// real group can be run from the manifest alone. Nothing of it goes into the tree or the templates.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PoolMetadata {
+    #[serde(default)]
+    pub pool: BTreeMap<String, String>,
+    // by position in the tree, like every other user number
+    #[serde(default)]
+    pub users: BTreeMap<UserIndex, BTreeMap<String, String>>,
+}
+
+impl PoolMetadata {
+    pub fn is_empty(&self) -> bool {
+        self.pool.is_empty() && self.users.is_empty()
+    }
+
+    // the pool's labels, or a user's
+    fn labels_mut(&mut self, user: Option<UserIndex>) -> &mut BTreeMap<String, String> {
+        match user {
+            Some(user) => self.users.entry(user).or_default(),
+            None => &mut self.pool,
+        }
+    }
+
+    pub fn set(&mut self, user: Option<UserIndex>, key: &str, value: &str) -> Result<()> {
+        let key = key.trim();
+        if key.is_empty() {
+            bail!("metadata keys can't be empty");
+        }
+        if key.contains(['=', ';', '\n']) || value.contains('\n') {
+            bail!(
+                "metadata keys can't contain '=', ';' or newlines, values can't contain newlines"
+            );
+        }
+        self.labels_mut(user)
+            .insert(key.to_string(), value.to_string());
+        Ok(())
+    }
+
+    // false if there was nothing to remove
+    pub fn unset(&mut self, user: Option<UserIndex>, key: &str) -> bool {
+        let removed = self.labels_mut(user).remove(key.trim()).is_some();
+        self.users.retain(|_, labels| !labels.is_empty());
+        removed
+    }
+
+    // a user's labels on one line, e.g. "email=ana@example.com; name=Ana"
+    pub fn user_summary(&self, user: UserIndex) -> Option<String> {
+        self.users.get(&user).map(summary)
+    }
+
+    pub fn pool_summary(&self) -> Option<String> {
+        (!self.pool.is_empty()).then(|| summary(&self.pool))
+    }
+}
+
+fn summary(labels: &BTreeMap<String, String>) -> String {
+    labels
+        .iter()
+        .map(|(key, value)| format!("{}={}", key, value))
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
+pub fn print_metadata(metadata: &PoolMetadata) {
+    if metadata.is_empty() {
+        println!("no metadata, add some with `meta set`");
+        return;
+    }
+    println!("pool");
+    for (key, value) in &metadata.pool {
+        println!("  {}: {}", key, value);
+    }
+    for (user, labels) in &metadata.users {
+        println!("user {}", user);
+        for (key, value) in labels {
+            println!("  {}: {}", key, value);
+        }
+    }
+}
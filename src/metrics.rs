@@ -0,0 +1,166 @@
+use std::{
+    fmt::Write,
+    net::SocketAddr,
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+use anyhow::Result;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+use tracing::{info, warn};
+
+// Process wide counters and gauges, scraped by prometheus from /metrics when --metrics-addr is set
+pub struct Metrics {
+    pools_tracked: AtomicU64,
+    // users still inside a pool, every one of them has a withdrawal ahead
+    pending_withdrawals: AtomicU64,
+    broadcasts_succeeded: AtomicU64,
+    broadcasts_failed: AtomicU64,
+    anchor_sats_spent: AtomicU64,
+    rpc_calls: AtomicU64,
+    rpc_latency_micros: AtomicU64,
+}
+
+pub static METRICS: Metrics = Metrics::new();
+
+impl Metrics {
+    const fn new() -> Self {
+        Self {
+            pools_tracked: AtomicU64::new(0),
+            pending_withdrawals: AtomicU64::new(0),
+            broadcasts_succeeded: AtomicU64::new(0),
+            broadcasts_failed: AtomicU64::new(0),
+            anchor_sats_spent: AtomicU64::new(0),
+            rpc_calls: AtomicU64::new(0),
+            rpc_latency_micros: AtomicU64::new(0),
+        }
+    }
+
+    pub fn set_pools_tracked(&self, pools: usize) {
+        self.pools_tracked.store(pools as u64, Ordering::Relaxed);
+    }
+
+    pub fn set_pending_withdrawals(&self, users: usize) {
+        self.pending_withdrawals
+            .store(users as u64, Ordering::Relaxed);
+    }
+
+    // count a send_raw_transaction by its outcome and hand the result back
+    pub fn record_broadcast<T>(&self, result: Result<T>) -> Result<T> {
+        let counter = match result {
+            Ok(_) => &self.broadcasts_succeeded,
+            Err(_) => &self.broadcasts_failed,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        result
+    }
+
+    pub fn add_anchor_sats(&self, sats: u64) {
+        self.anchor_sats_spent.fetch_add(sats, Ordering::Relaxed);
+    }
+
+    pub fn record_rpc(&self, latency: Duration) {
+        self.rpc_calls.fetch_add(1, Ordering::Relaxed);
+        self.rpc_latency_micros
+            .fetch_add(latency.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    // prometheus text exposition format
+    pub fn render(&self) -> String {
+        let load = |value: &AtomicU64| value.load(Ordering::Relaxed);
+        let mut out = String::new();
+        let mut metric = |name: &str, kind: &str, help: &str, value: String| {
+            let _ = write!(
+                out,
+                "# HELP {name} {help}\n# TYPE {name} {kind}\n{name} {value}\n"
+            );
+        };
+        metric(
+            "pool_pools_tracked",
+            "gauge",
+            "Pools this process is creating, unwinding or watching",
+            load(&self.pools_tracked).to_string(),
+        );
+        metric(
+            "pool_pending_withdrawals",
+            "gauge",
+            "Users still inside a pool",
+            load(&self.pending_withdrawals).to_string(),
+        );
+        metric(
+            "pool_broadcasts_succeeded_total",
+            "counter",
+            "Transactions accepted by the node",
+            load(&self.broadcasts_succeeded).to_string(),
+        );
+        metric(
+            "pool_broadcasts_failed_total",
+            "counter",
+            "Transactions the node rejected",
+            load(&self.broadcasts_failed).to_string(),
+        );
+        metric(
+            "pool_anchor_sats_spent_total",
+            "counter",
+            "Sats the fee payer spent on anchor children",
+            load(&self.anchor_sats_spent).to_string(),
+        );
+        let _ = write!(
+            out,
+            "# HELP pool_rpc_latency_seconds Bitcoin Core rpc round trips\n\
+             # TYPE pool_rpc_latency_seconds summary\n\
+             pool_rpc_latency_seconds_sum {}\n\
+             pool_rpc_latency_seconds_count {}\n",
+            load(&self.rpc_latency_micros) as f64 / 1e6,
+            load(&self.rpc_calls)
+        );
+        out
+    }
+}
+
+// Bind now so a taken port fails the command, then answer scrapes in the background
+pub async fn spawn_metrics_server(addr: SocketAddr) -> Result<()> {
+    let listener = TcpListener::bind(addr).await?;
+    info!("serving metrics on http://{}/metrics \n", addr);
+    tokio::spawn(async move {
+        loop {
+            match listener.accept().await {
+                Ok((stream, _)) => {
+                    tokio::spawn(async move {
+                        if let Err(e) = answer_scrape(stream).await {
+                            warn!("metrics request failed: {}", e);
+                        }
+                    });
+                }
+                Err(e) => warn!("metrics listener: {}", e),
+            }
+        }
+    });
+    Ok(())
+}
+
+// just enough http for a prometheus scrape, the request line is all that is looked at
+async fn answer_scrape(mut stream: TcpStream) -> Result<()> {
+    let mut buf = [0u8; 1024];
+    let read = stream.read(&mut buf).await?;
+    let request = String::from_utf8_lossy(&buf[..read]);
+    let path = request.split_whitespace().nth(1).unwrap_or_default();
+
+    let (status, body) = if path == "/metrics" {
+        ("200 OK", METRICS.render())
+    } else {
+        ("404 Not Found", "only /metrics is served\n".to_string())
+    };
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await?;
+    Ok(())
+}
@@ -0,0 +1,118 @@
+use std::time::Duration;
+
+use anyhow::Result;
+use bitcoin::{Address, Amount, Txid};
+use bitcoincore_rpc::RpcApi;
+use tokio::task::JoinHandle;
+use tracing::{info, warn};
+
+use crate::{config::NetworkConfig, rpc_helper::AsyncRpc};
+
+// coinbase outputs need 100 confirmations before they can be spent
+const COINBASE_MATURITY: u64 = 101;
+
+// Mines a block every `config.block_interval` in the background, so regtest confirmations arrive
+// on their own like on a real network instead of being mined inline after every broadcast.
+// Returns None on networks without a block interval.
+pub fn spawn_miner(
+    config: &NetworkConfig,
+    rpc: AsyncRpc,
+    mining_address: Address,
+) -> Option<JoinHandle<()>> {
+    let block_interval = config.block_interval?;
+    info!("mining a block every {:?} \n", block_interval);
+
+    let handle = tokio::spawn(async move {
+        let mut interval = tokio::time::interval(block_interval);
+        // the first tick fires straight away
+        interval.tick().await;
+        loop {
+            interval.tick().await;
+            let mining_address = mining_address.clone();
+            let mined = rpc
+                .run(move |c| c.generate_to_address(1, &mining_address))
+                .await;
+            if let Err(e) = mined {
+                warn!("background miner failed to mine a block: {}", e);
+            }
+        }
+    });
+
+    Some(handle)
+}
+
+// fresh regtest wallets have nothing to spend, mine until the coinbases cover the pool
+pub async fn fund_regtest_wallet(
+    rpc: &AsyncRpc,
+    mining_address: &Address,
+    needed: Amount,
+) -> Result<()> {
+    if rpc.run(|c| c.get_balance(None, None)).await? < needed {
+        let mining_address = mining_address.clone();
+        rpc.run(move |c| c.generate_to_address(COINBASE_MATURITY, &mining_address))
+            .await?;
+    }
+    Ok(())
+}
+
+// Wait for the background miner to confirm `txid`. Does nothing when no miner is running.
+pub async fn wait_for_confirmation(
+    rpc: &AsyncRpc,
+    config: &NetworkConfig,
+    txid: Txid,
+) -> Result<()> {
+    wait_for_confirmations(rpc, config, txid, 1).await
+}
+
+// Wait for the background miner to bury `txid` `blocks` deep. Does nothing when no miner is running.
+pub async fn wait_for_confirmations(
+    rpc: &AsyncRpc,
+    config: &NetworkConfig,
+    txid: Txid,
+    blocks: u32,
+) -> Result<()> {
+    let Some(block_interval) = config.block_interval else {
+        return Ok(());
+    };
+
+    loop {
+        let confirmations = rpc
+            .run(move |c| c.get_transaction(&txid, None))
+            .await?
+            .info
+            .confirmations;
+        if i64::from(confirmations) >= i64::from(blocks.max(1)) {
+            info!("{} confirmed {} blocks deep \n", txid, confirmations);
+            return Ok(());
+        }
+        tokio::time::sleep(block_interval.min(Duration::from_secs(1))).await;
+    }
+}
+
+// Wait until `txid` has `blocks` confirmations, so a spend relative timelocked on it is final.
+// Without a miner this is waiting for the real network.
+pub async fn wait_for_maturity(
+    rpc: &AsyncRpc,
+    config: &NetworkConfig,
+    txid: Txid,
+    blocks: u16,
+) -> Result<()> {
+    let poll = config
+        .block_interval
+        .map_or(Duration::from_secs(30), |interval| {
+            interval.min(Duration::from_secs(1))
+        });
+    info!("waiting for {} to have {} confirmations \n", txid, blocks);
+
+    loop {
+        let confirmations = rpc
+            .run(move |c| c.get_raw_transaction_info(&txid, None))
+            .await?
+            .confirmations
+            .unwrap_or(0);
+        if confirmations >= u32::from(blocks) {
+            return Ok(());
+        }
+        tokio::time::sleep(poll).await;
+    }
+}
@@ -0,0 +1,1096 @@
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    fmt,
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+use bitcoin::{
+    absolute,
+    address::NetworkUnchecked,
+    block::{Header, Version},
+    consensus::encode::{deserialize_hex, serialize_hex},
+    constants::genesis_block,
+    hashes::{sha256, Hash},
+    key::{Keypair, Secp256k1},
+    relative,
+    script::Builder,
+    secp256k1::SecretKey,
+    transaction, Address, Amount, Block, BlockHash, CompactTarget, Network, OutPoint, Script,
+    ScriptBuf, Sequence, Transaction, TxIn, TxOut, Txid, Witness,
+};
+use bitcoincore_rpc::{
+    jsonrpc::{self, error::RpcError, Request, Response, Transport},
+    Client,
+};
+use serde_json::{json, Value};
+
+use crate::rpc_helper::AsyncRpc;
+
+// coinbase outputs are only spendable this deep, like on the real chain
+const COINBASE_MATURITY: u32 = 100;
+// what every mined block pays, no halvings
+const BLOCK_SUBSIDY: Amount = Amount::from_sat(50 * 100_000_000);
+// bitcoind's default -minrelaytxfee (per kvB)
+const DEFAULT_RELAY_FEE: Amount = Amount::from_sat(1_000);
+// bitcoind's codes for the rejects RejectReason decodes
+const RPC_VERIFY_ERROR: i32 = -25;
+const RPC_VERIFY_REJECTED: i32 = -26;
+const RPC_VERIFY_ALREADY_IN_CHAIN: i32 = -27;
+const RPC_INVALID_ADDRESS_OR_KEY: i32 = -5;
+const RPC_INVALID_PARAMETER: i32 = -8;
+const RPC_DESERIALIZATION_ERROR: i32 = -22;
+const RPC_METHOD_NOT_FOUND: i32 = -32601;
+
+#[derive(Debug, Clone)]
+struct MockTx {
+    tx: Transaction,
+    // the block it was mined in, None while it is in the mempool
+    height: Option<u32>,
+    coinbase: bool,
+}
+
+#[derive(Debug)]
+struct Chain {
+    network: Network,
+    blocks: Vec<Block>,
+    txs: HashMap<Txid, MockTx>,
+    // in the order they were accepted, parents before their children
+    mempool: Vec<Txid>,
+    // outputs nothing spends yet, mined or in the mempool
+    utxos: HashMap<OutPoint, TxOut>,
+    spent_by: HashMap<OutPoint, Txid>,
+    wallet: HashSet<ScriptBuf>,
+    // wallet scripts without a key, listunspent has their coins as not spendable
+    watch_only: HashSet<ScriptBuf>,
+    // wallet scripts paid more than once, listed as reused with avoid_reuse set
+    reused: HashSet<ScriptBuf>,
+    avoid_reuse: bool,
+    private_keys: bool,
+    next_key: u32,
+    // mine this many blocks after every accepted tx
+    auto_mine: Option<u32>,
+    fee_rate: Option<Amount>,
+    relay_fee: Amount,
+    // -mempoolfullrbf, a conflicting tx paying enough replaces the mempool txs it conflicts with
+    full_rbf: bool,
+    // lockunspent, listunspent leaves these out
+    locked: HashSet<OutPoint>,
+    failures: HashMap<String, VecDeque<RpcError>>,
+}
+
+// An in-memory bitcoind for tests and examples. It answers the JSON-RPC calls the pool makes
+// through a bitcoincore_rpc Client, so pools, rpc_helper, the miner and lifecycle code run against
+// it unchanged. There is no ChainBackend trait to implement, the node is only ever reached through
+// AsyncRpc, so the mock sits below it as a jsonrpc Transport.
+// Spends are checked for missing or spent inputs, mempool conflicts, fees and relative timelocks,
+// scripts aren't run: covenant templates and wallet signatures are taken as they are.
+#[derive(Debug, Clone)]
+pub struct MockBackend {
+    chain: Arc<Mutex<Chain>>,
+}
+
+impl MockBackend {
+    pub fn new(network: Network) -> Self {
+        let genesis = genesis_block(network);
+        Self {
+            chain: Arc::new(Mutex::new(Chain {
+                network,
+                blocks: vec![genesis],
+                txs: HashMap::new(),
+                mempool: Vec::new(),
+                utxos: HashMap::new(),
+                spent_by: HashMap::new(),
+                wallet: HashSet::new(),
+                watch_only: HashSet::new(),
+                reused: HashSet::new(),
+                avoid_reuse: false,
+                private_keys: true,
+                next_key: 0,
+                auto_mine: None,
+                fee_rate: None,
+                relay_fee: DEFAULT_RELAY_FEE,
+                full_rbf: false,
+                locked: HashSet::new(),
+                failures: HashMap::new(),
+            })),
+        }
+    }
+
+    // an AsyncRpc whose calls all go to this mock
+    pub fn rpc(&self) -> AsyncRpc {
+        AsyncRpc::from_client(Client::from_jsonrpc(jsonrpc::Client::with_transport(
+            self.clone(),
+        )))
+    }
+
+    fn chain(&self) -> MutexGuard<'_, Chain> {
+        self.chain.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    // a fresh wallet address
+    pub fn new_address(&self) -> Address {
+        self.chain().new_address()
+    }
+
+    // Script an output into existence, paid by a tx with nothing the mock checks behind it.
+    // It sits in the mempool until the next block.
+    pub fn add_output(&self, output: TxOut) -> OutPoint {
+        let mut chain = self.chain();
+        let nonce = chain.txs.len() as u64;
+        let tx = Transaction {
+            version: transaction::Version::TWO,
+            lock_time: absolute::LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: OutPoint {
+                    txid: Txid::from_byte_array(
+                        sha256::Hash::hash(&nonce.to_le_bytes()).to_byte_array(),
+                    ),
+                    vout: 0,
+                },
+                ..Default::default()
+            }],
+            output: vec![output],
+        };
+        let txid = tx.compute_txid();
+        chain.insert(tx, false);
+        chain.mempool.push(txid);
+        OutPoint { txid, vout: 0 }
+    }
+
+    // a wallet coin of `amount`, unconfirmed until the next block
+    pub fn add_utxo(&self, amount: Amount) -> OutPoint {
+        let script_pubkey = self.new_address().script_pubkey();
+        self.add_output(TxOut {
+            value: amount,
+            script_pubkey,
+        })
+    }
+
+    // a wallet coin of `amount` the wallet only watches, it has no key to sign for it
+    pub fn add_watch_only_utxo(&self, amount: Amount) -> OutPoint {
+        let script_pubkey = self.new_address().script_pubkey();
+        self.chain().watch_only.insert(script_pubkey.clone());
+        self.add_output(TxOut {
+            value: amount,
+            script_pubkey,
+        })
+    }
+
+    // a wallet coin of `amount` on an address that was paid before
+    pub fn add_reused_utxo(&self, amount: Amount) -> OutPoint {
+        let script_pubkey = self.new_address().script_pubkey();
+        self.chain().reused.insert(script_pubkey.clone());
+        self.add_output(TxOut {
+            value: amount,
+            script_pubkey,
+        })
+    }
+
+    // the wallet's avoid_reuse flag, listunspent only says which coins are reused with it set
+    pub fn set_avoid_reuse(&self, avoid_reuse: bool) {
+        self.chain().avoid_reuse = avoid_reuse;
+    }
+
+    // off, the wallet is watch-only (created with disable_private_keys)
+    pub fn set_private_keys(&self, private_keys: bool) {
+        self.chain().private_keys = private_keys;
+    }
+
+    // mine `blocks` blocks to a wallet address, the first takes the whole mempool
+    pub fn mine(&self, blocks: u32) -> Vec<BlockHash> {
+        let mut chain = self.chain();
+        let script = chain.new_address().script_pubkey();
+        (0..blocks).map(|_| chain.mine_block(&script)).collect()
+    }
+
+    // mine `blocks` blocks after every accepted tx, None leaves txs in the mempool
+    pub fn auto_mine(&self, blocks: Option<u32>) {
+        self.chain().auto_mine = blocks;
+    }
+
+    // what estimatesmartfee answers (per kvB), None for "Insufficient data or no feerate found"
+    pub fn set_fee_rate(&self, fee_rate: Option<Amount>) {
+        self.chain().fee_rate = fee_rate;
+    }
+
+    // -minrelaytxfee (per kvB), the regtest node of the Dockerfile runs with 0
+    pub fn set_relay_fee(&self, relay_fee: Amount) {
+        self.chain().relay_fee = relay_fee;
+    }
+
+    // Take replacements: a tx conflicting with mempool txs evicts them (and their descendants) if
+    // it pays their fees plus the relay fee for its own size. Off, conflicts are refused.
+    pub fn set_full_rbf(&self, full_rbf: bool) {
+        self.chain().full_rbf = full_rbf;
+    }
+
+    // the wallet coins lockunspent is holding
+    pub fn locked(&self) -> Vec<OutPoint> {
+        let mut locked: Vec<OutPoint> = self.chain().locked.iter().copied().collect();
+        locked.sort();
+        locked
+    }
+
+    // the next call of `method` fails with this rpc error, once. Queued failures go in order.
+    pub fn fail_next(&self, method: &str, code: i32, message: &str) {
+        self.chain()
+            .failures
+            .entry(method.to_string())
+            .or_default()
+            .push_back(RpcError {
+                code,
+                message: message.to_string(),
+                data: None,
+            });
+    }
+
+    pub fn height(&self) -> u32 {
+        self.chain().height()
+    }
+
+    pub fn mempool(&self) -> Vec<Txid> {
+        self.chain().mempool.clone()
+    }
+
+    // None for a tx the mock never saw, 0 while it is in the mempool
+    pub fn confirmations(&self, txid: Txid) -> Option<u32> {
+        let chain = self.chain();
+        chain.txs.get(&txid).map(|tx| chain.confirmations(tx))
+    }
+
+    pub fn transaction(&self, txid: Txid) -> Option<Transaction> {
+        self.chain().txs.get(&txid).map(|tx| tx.tx.clone())
+    }
+
+    fn handle(&self, method: &str, params: &[Value]) -> Result<Value, RpcError> {
+        let mut chain = self.chain();
+        if let Some(error) = chain
+            .failures
+            .get_mut(method)
+            .and_then(|failures| failures.pop_front())
+        {
+            return Err(error);
+        }
+        chain.call(method, params)
+    }
+}
+
+impl Transport for MockBackend {
+    fn send_request(&self, request: Request) -> Result<Response, jsonrpc::Error> {
+        let params: Vec<Value> = match request.params {
+            Some(params) => serde_json::from_str(params.get())?,
+            None => Vec::new(),
+        };
+        let (result, error) = match self.handle(request.method, &params) {
+            Ok(result) => (Some(serde_json::value::to_raw_value(&result)?), None),
+            Err(error) => (None, Some(error)),
+        };
+        Ok(Response {
+            result,
+            error,
+            id: request.id,
+            jsonrpc: Some("2.0".to_string()),
+        })
+    }
+
+    fn send_batch(&self, requests: &[Request]) -> Result<Vec<Response>, jsonrpc::Error> {
+        requests
+            .iter()
+            .map(|request| self.send_request(request.clone()))
+            .collect()
+    }
+
+    fn fmt_target(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "mock bitcoind")
+    }
+}
+
+fn rpc_error(code: i32, message: impl Into<String>) -> RpcError {
+    RpcError {
+        code,
+        message: message.into(),
+        data: None,
+    }
+}
+
+fn param<T: serde::de::DeserializeOwned>(params: &[Value], i: usize) -> Result<T, RpcError> {
+    let value = params
+        .get(i)
+        .ok_or_else(|| rpc_error(RPC_INVALID_PARAMETER, format!("missing parameter {}", i)))?;
+    serde_json::from_value(value.clone())
+        .map_err(|e| rpc_error(RPC_INVALID_PARAMETER, format!("parameter {}: {}", i, e)))
+}
+
+// an optional parameter, missing or null is the default
+fn param_or<T: serde::de::DeserializeOwned>(
+    params: &[Value],
+    i: usize,
+    default: T,
+) -> Result<T, RpcError> {
+    match params.get(i) {
+        None | Some(Value::Null) => Ok(default),
+        Some(_) => param(params, i),
+    }
+}
+
+// verbose flags are a bool or a verbosity number
+fn verbose(params: &[Value], i: usize, default: bool) -> bool {
+    match params.get(i) {
+        Some(Value::Bool(verbose)) => *verbose,
+        Some(Value::Number(verbosity)) => verbosity.as_u64() != Some(0),
+        _ => default,
+    }
+}
+
+fn script_json(script: &Script, network: Network) -> Value {
+    json!({
+        "asm": script.to_asm_string(),
+        "hex": script.to_hex_string(),
+        "address": Address::from_script(script, network).ok().map(|a| a.to_string()),
+    })
+}
+
+impl Chain {
+    fn height(&self) -> u32 {
+        self.blocks.len() as u32 - 1
+    }
+
+    fn tip(&self) -> &Block {
+        self.blocks
+            .last()
+            .expect("the genesis block is always there")
+    }
+
+    fn confirmations(&self, tx: &MockTx) -> u32 {
+        tx.height.map_or(0, |height| self.height() - height + 1)
+    }
+
+    fn new_address(&mut self) -> Address {
+        self.next_key += 1;
+        let secp = Secp256k1::new();
+        let seed = sha256::Hash::hash(format!("mock wallet {}", self.next_key).as_bytes());
+        let key = SecretKey::from_slice(seed.as_byte_array()).expect("a hash is a valid key");
+        let (xonly, _) = Keypair::from_secret_key(&secp, &key).x_only_public_key();
+        let address = Address::p2tr(&secp, xonly, None, self.network);
+        self.wallet.insert(address.script_pubkey());
+        address
+    }
+
+    fn insert(&mut self, tx: Transaction, coinbase: bool) {
+        let txid = tx.compute_txid();
+        if !coinbase {
+            for input in &tx.input {
+                self.utxos.remove(&input.previous_output);
+                self.spent_by.insert(input.previous_output, txid);
+            }
+        }
+        for (vout, output) in tx.output.iter().enumerate() {
+            self.utxos.insert(
+                OutPoint {
+                    txid,
+                    vout: vout as u32,
+                },
+                output.clone(),
+            );
+        }
+        self.txs.insert(
+            txid,
+            MockTx {
+                tx,
+                height: None,
+                coinbase,
+            },
+        );
+    }
+
+    fn mine_block(&mut self, script_pubkey: &Script) -> BlockHash {
+        let height = self.height() + 1;
+        let coinbase = Transaction {
+            version: transaction::Version::TWO,
+            lock_time: absolute::LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: OutPoint::null(),
+                script_sig: Builder::new().push_int(i64::from(height)).into_script(),
+                sequence: Sequence::MAX,
+                witness: Witness::new(),
+            }],
+            output: vec![TxOut {
+                value: BLOCK_SUBSIDY,
+                script_pubkey: script_pubkey.to_owned(),
+            }],
+        };
+        self.insert(coinbase.clone(), true);
+        let mut txdata = vec![coinbase];
+        for txid in std::mem::take(&mut self.mempool) {
+            txdata.push(self.txs[&txid].tx.clone());
+        }
+        for tx in &txdata {
+            if let Some(mined) = self.txs.get_mut(&tx.compute_txid()) {
+                mined.height = Some(height);
+            }
+        }
+
+        let mut block = Block {
+            header: Header {
+                version: Version::TWO,
+                prev_blockhash: self.tip().block_hash(),
+                merkle_root: Hash::all_zeros(),
+                time: self.tip().header.time + 600,
+                bits: CompactTarget::from_consensus(0x207fffff),
+                nonce: 0,
+            },
+            txdata,
+        };
+        block.header.merkle_root = block.compute_merkle_root().expect("blocks have a coinbase");
+        let hash = block.block_hash();
+        self.blocks.push(block);
+        hash
+    }
+
+    // the wallet's unspent coins with at least `min_conf` confirmations, mature coinbases only
+    fn wallet_coins(&self, min_conf: u32) -> Vec<(OutPoint, TxOut, u32)> {
+        let mut coins: Vec<_> = self
+            .utxos
+            .iter()
+            .filter(|(_, output)| self.wallet.contains(&output.script_pubkey))
+            .filter_map(|(outpoint, output)| {
+                let tx = &self.txs[&outpoint.txid];
+                let confirmations = self.confirmations(tx);
+                let mature = !tx.coinbase || confirmations >= COINBASE_MATURITY;
+                (mature && confirmations >= min_conf)
+                    .then(|| (*outpoint, output.clone(), confirmations))
+            })
+            .collect();
+        // oldest first, like coin selection would see them
+        coins.sort_by_key(|(outpoint, _, confirmations)| (u32::MAX - confirmations, *outpoint));
+        coins
+    }
+
+    fn block(&self, hash: BlockHash) -> Result<(u32, &Block), RpcError> {
+        self.blocks
+            .iter()
+            .enumerate()
+            .find(|(_, block)| block.block_hash() == hash)
+            .map(|(height, block)| (height as u32, block))
+            .ok_or_else(|| rpc_error(RPC_INVALID_ADDRESS_OR_KEY, "Block not found"))
+    }
+
+    fn tx(&self, txid: Txid) -> Result<&MockTx, RpcError> {
+        self.txs.get(&txid).ok_or_else(|| {
+            rpc_error(
+                RPC_INVALID_ADDRESS_OR_KEY,
+                "No such mempool or blockchain transaction. Use gettransaction for wallet transactions.",
+            )
+        })
+    }
+
+    fn mempool_tx(&self, txid: Txid) -> Result<&MockTx, RpcError> {
+        match self.txs.get(&txid) {
+            Some(tx) if tx.height.is_none() => Ok(tx),
+            _ => Err(rpc_error(
+                RPC_INVALID_ADDRESS_OR_KEY,
+                "Transaction not in mempool",
+            )),
+        }
+    }
+
+    // the value of a mempool tx's inputs minus its outputs
+    fn fee(&self, tx: &Transaction) -> Amount {
+        let input: Amount = tx
+            .input
+            .iter()
+            .filter_map(|input| self.prevout(input.previous_output))
+            .map(|output| output.value)
+            .sum();
+        let output: Amount = tx.output.iter().map(|output| output.value).sum();
+        input.checked_sub(output).unwrap_or(Amount::ZERO)
+    }
+
+    fn prevout(&self, outpoint: OutPoint) -> Option<TxOut> {
+        self.txs
+            .get(&outpoint.txid)
+            .and_then(|tx| tx.tx.output.get(outpoint.vout as usize))
+            .cloned()
+    }
+
+    // mempool parents of `txid`
+    fn depends(&self, txid: Txid) -> Vec<Txid> {
+        let mut parents: Vec<Txid> = self.txs[&txid]
+            .tx
+            .input
+            .iter()
+            .map(|input| input.previous_output.txid)
+            .filter(|parent| self.mempool.contains(parent))
+            .collect();
+        parents.sort();
+        parents.dedup();
+        parents
+    }
+
+    // mempool children of `txid`
+    fn spent_by_mempool(&self, txid: Txid) -> Vec<Txid> {
+        self.mempool
+            .iter()
+            .copied()
+            .filter(|child| self.depends(*child).contains(&txid))
+            .collect()
+    }
+
+    // `txid` and its mempool ancestors, or descendants
+    fn family(&self, txid: Txid, next: impl Fn(&Self, Txid) -> Vec<Txid>) -> HashSet<Txid> {
+        let mut family = HashSet::new();
+        let mut todo = vec![txid];
+        while let Some(txid) = todo.pop() {
+            if family.insert(txid) {
+                todo.extend(next(self, txid));
+            }
+        }
+        family
+    }
+
+    // What sendrawtransaction would refuse, or the mempool txs it replaces. Scripts aren't checked.
+    fn check(&self, tx: &Transaction) -> Result<HashSet<Txid>, RpcError> {
+        let txid = tx.compute_txid();
+        if let Some(known) = self.txs.get(&txid) {
+            return Err(match known.height {
+                Some(_) => rpc_error(
+                    RPC_VERIFY_ALREADY_IN_CHAIN,
+                    "Transaction already in block chain",
+                ),
+                None => rpc_error(RPC_VERIFY_ALREADY_IN_CHAIN, "txn-already-in-mempool"),
+            });
+        }
+        let mut conflicts = HashSet::new();
+        for input in &tx.input {
+            if self.utxos.contains_key(&input.previous_output) {
+                continue;
+            }
+            match self
+                .spent_by
+                .get(&input.previous_output)
+                .filter(|spender| self.txs[*spender].height.is_none())
+            {
+                Some(spender) if self.full_rbf => {
+                    conflicts.extend(self.family(*spender, Self::spent_by_mempool));
+                }
+                Some(_) => return Err(rpc_error(RPC_VERIFY_REJECTED, "txn-mempool-conflict")),
+                None => {
+                    return Err(rpc_error(
+                        RPC_VERIFY_ERROR,
+                        "bad-txns-inputs-missingorspent",
+                    ))
+                }
+            }
+        }
+        for input in &tx.input {
+            let parent = &self.txs[&input.previous_output.txid];
+            if parent.coinbase && self.confirmations(parent) < COINBASE_MATURITY {
+                return Err(rpc_error(
+                    RPC_VERIFY_REJECTED,
+                    "bad-txns-premature-spend-of-coinbase",
+                ));
+            }
+            if tx.version.0 >= 2 {
+                if let Some(relative::LockTime::Blocks(blocks)) =
+                    input.sequence.to_relative_lock_time()
+                {
+                    // the spend would be in the next block
+                    if self.confirmations(parent) < u32::from(blocks.value()) {
+                        return Err(rpc_error(RPC_VERIFY_REJECTED, "non-BIP68-final"));
+                    }
+                }
+            }
+        }
+        let input: Amount = tx
+            .input
+            .iter()
+            .filter_map(|input| self.prevout(input.previous_output))
+            .map(|output| output.value)
+            .sum();
+        let output: Amount = tx.output.iter().map(|output| output.value).sum();
+        let Some(fee) = input.checked_sub(output) else {
+            return Err(rpc_error(RPC_VERIFY_REJECTED, "bad-txns-in-belowout"));
+        };
+        let needed = self.relay_fee * tx.vsize() as u64 / 1000;
+        if fee < needed {
+            return Err(rpc_error(
+                RPC_VERIFY_REJECTED,
+                format!(
+                    "min relay fee not met, {} < {}",
+                    fee.to_sat(),
+                    needed.to_sat()
+                ),
+            ));
+        }
+        let replaced: Amount = conflicts
+            .iter()
+            .map(|txid| self.fee(&self.txs[txid].tx))
+            .sum();
+        if !conflicts.is_empty() && fee < replaced + needed {
+            return Err(rpc_error(
+                RPC_VERIFY_REJECTED,
+                format!(
+                    "insufficient fee, rejecting replacement {}; new fee {} < {} + {}",
+                    txid,
+                    fee.to_sat(),
+                    replaced.to_sat(),
+                    needed.to_sat()
+                ),
+            ));
+        }
+        Ok(conflicts)
+    }
+
+    // take `txids` out of the mempool as if they were never sent, their inputs unspent again
+    fn evict(&mut self, txids: &HashSet<Txid>) {
+        for txid in txids {
+            let Some(evicted) = self.txs.remove(txid) else {
+                continue;
+            };
+            for input in &evicted.tx.input {
+                self.spent_by.remove(&input.previous_output);
+                if let Some(prevout) = self.prevout(input.previous_output) {
+                    self.utxos.insert(input.previous_output, prevout);
+                }
+            }
+            for vout in 0..evicted.tx.output.len() {
+                self.utxos.remove(&OutPoint {
+                    txid: *txid,
+                    vout: vout as u32,
+                });
+            }
+        }
+        self.mempool.retain(|txid| !txids.contains(txid));
+    }
+
+    fn call(&mut self, method: &str, params: &[Value]) -> Result<Value, RpcError> {
+        let network = self.network;
+        Ok(match method {
+            "getblockcount" => json!(self.height()),
+            "getbestblockhash" => json!(self.tip().block_hash()),
+            "getblockhash" => {
+                let height: usize = param(params, 0)?;
+                let block = self
+                    .blocks
+                    .get(height)
+                    .ok_or_else(|| rpc_error(RPC_INVALID_PARAMETER, "Block height out of range"))?;
+                json!(block.block_hash())
+            }
+            "getblock" => {
+                let (_, block) = self.block(param(params, 0)?)?;
+                if verbose(params, 1, true) {
+                    return Err(rpc_error(
+                        RPC_INVALID_PARAMETER,
+                        "the mock only answers getblock with verbosity 0",
+                    ));
+                }
+                json!(serialize_hex(block))
+            }
+            "getblockheader" => {
+                let (height, block) = self.block(param(params, 0)?)?;
+                if !verbose(params, 1, true) {
+                    return Ok(json!(serialize_hex(&block.header)));
+                }
+                let header = &block.header;
+                json!({
+                    "hash": block.block_hash(),
+                    "confirmations": self.height() - height + 1,
+                    "height": height,
+                    "version": header.version.to_consensus(),
+                    "merkleroot": header.merkle_root,
+                    "time": header.time,
+                    "mediantime": header.time,
+                    "nonce": header.nonce,
+                    "bits": format!("{:08x}", header.bits.to_consensus()),
+                    "difficulty": 1.0,
+                    "chainwork": format!("{:064x}", height + 1),
+                    "nTx": block.txdata.len(),
+                    "previousblockhash": (height > 0).then_some(header.prev_blockhash),
+                    "nextblockhash": self.blocks.get(height as usize + 1).map(Block::block_hash),
+                })
+            }
+            "getblockchaininfo" => json!({
+                "chain": network.to_core_arg(),
+                "blocks": self.height(),
+                "headers": self.height(),
+                "bestblockhash": self.tip().block_hash(),
+                "difficulty": 1.0,
+                "mediantime": self.tip().header.time,
+                "verificationprogress": 1.0,
+                "initialblockdownload": false,
+                "chainwork": format!("{:064x}", self.blocks.len()),
+                "size_on_disk": 0,
+                "pruned": false,
+                "warnings": "",
+            }),
+            "getnetworkinfo" => json!({
+                "version": 280000,
+                "subversion": "/MockBackend/",
+                "protocolversion": 70016,
+                "localservices": "0000000000000409",
+                "localrelay": true,
+                "timeoffset": 0,
+                "connections": 0,
+                "networkactive": true,
+                "networks": [],
+                "relayfee": self.relay_fee.to_btc(),
+                "incrementalfee": self.relay_fee.to_btc(),
+                "localaddresses": [],
+                "warnings": "",
+            }),
+            "estimatesmartfee" => match self.fee_rate {
+                Some(fee_rate) => json!({ "feerate": fee_rate.to_btc(), "blocks": 2 }),
+                None => json!({
+                    "errors": ["Insufficient data or no feerate found"],
+                    "blocks": 0,
+                }),
+            },
+            "getmempoolentry" => {
+                let txid: Txid = param(params, 0)?;
+                let tx = &self.mempool_tx(txid)?.tx;
+                let ancestors = self.family(txid, Self::depends);
+                let descendants = self.family(txid, Self::spent_by_mempool);
+                let vsize = |family: &HashSet<Txid>| -> usize {
+                    family.iter().map(|txid| self.txs[txid].tx.vsize()).sum()
+                };
+                let fees = |family: &HashSet<Txid>| -> f64 {
+                    family
+                        .iter()
+                        .map(|txid| self.fee(&self.txs[txid].tx))
+                        .sum::<Amount>()
+                        .to_btc()
+                };
+                json!({
+                    "vsize": tx.vsize(),
+                    "weight": tx.weight().to_wu(),
+                    "time": self.tip().header.time,
+                    "height": self.height(),
+                    "descendantcount": descendants.len(),
+                    "descendantsize": vsize(&descendants),
+                    "ancestorcount": ancestors.len(),
+                    "ancestorsize": vsize(&ancestors),
+                    "wtxid": tx.compute_wtxid(),
+                    "fees": {
+                        "base": self.fee(tx).to_btc(),
+                        "modified": self.fee(tx).to_btc(),
+                        "ancestor": fees(&ancestors),
+                        "descendant": fees(&descendants),
+                    },
+                    "depends": self.depends(txid),
+                    "spentby": self.spent_by_mempool(txid),
+                    "bip125-replaceable": false,
+                })
+            }
+            "getrawtransaction" => {
+                let txid: Txid = param(params, 0)?;
+                let mock = self.tx(txid)?;
+                if !verbose(params, 1, false) {
+                    return Ok(json!(serialize_hex(&mock.tx)));
+                }
+                let tx = &mock.tx;
+                let block = mock.height.map(|height| &self.blocks[height as usize]);
+                let vin: Vec<Value> = tx
+                    .input
+                    .iter()
+                    .map(|input| {
+                        let witness: Vec<String> = input
+                            .witness
+                            .iter()
+                            .map(|item| item.iter().map(|b| format!("{:02x}", b)).collect())
+                            .collect();
+                        if mock.coinbase {
+                            json!({
+                                "coinbase": input.script_sig.to_hex_string(),
+                                "sequence": input.sequence.0,
+                            })
+                        } else {
+                            json!({
+                                "txid": input.previous_output.txid,
+                                "vout": input.previous_output.vout,
+                                "scriptSig": {
+                                    "asm": input.script_sig.to_asm_string(),
+                                    "hex": input.script_sig.to_hex_string(),
+                                },
+                                "txinwitness": witness,
+                                "sequence": input.sequence.0,
+                            })
+                        }
+                    })
+                    .collect();
+                let vout: Vec<Value> = tx
+                    .output
+                    .iter()
+                    .enumerate()
+                    .map(|(n, output)| {
+                        json!({
+                            "value": output.value.to_btc(),
+                            "n": n,
+                            "scriptPubKey": script_json(&output.script_pubkey, network),
+                        })
+                    })
+                    .collect();
+                json!({
+                    "in_active_chain": block.map(|_| true),
+                    "hex": serialize_hex(tx),
+                    "txid": txid,
+                    "hash": tx.compute_wtxid(),
+                    "size": tx.total_size(),
+                    "vsize": tx.vsize(),
+                    "version": tx.version.0,
+                    "locktime": tx.lock_time.to_consensus_u32(),
+                    "vin": vin,
+                    "vout": vout,
+                    "blockhash": block.map(Block::block_hash),
+                    "confirmations": block.map(|_| self.confirmations(mock)),
+                    "time": block.map(|block| block.header.time),
+                    "blocktime": block.map(|block| block.header.time),
+                })
+            }
+            "gettxout" => {
+                let txid: Txid = param(params, 0)?;
+                let vout: u32 = param(params, 1)?;
+                let include_mempool = param_or(params, 2, true)?;
+                let outpoint = OutPoint { txid, vout };
+                let Some(tx) = self.txs.get(&txid) else {
+                    return Ok(Value::Null);
+                };
+                let unspent = if include_mempool {
+                    self.utxos.contains_key(&outpoint)
+                } else {
+                    // the chain's view, mempool spends don't count
+                    tx.height.is_some()
+                        && (self.utxos.contains_key(&outpoint)
+                            || self
+                                .spent_by
+                                .get(&outpoint)
+                                .is_some_and(|spender| self.txs[spender].height.is_none()))
+                };
+                match tx.tx.output.get(vout as usize) {
+                    Some(output) if unspent => json!({
+                        "bestblock": self.tip().block_hash(),
+                        "confirmations": self.confirmations(tx),
+                        "value": output.value.to_btc(),
+                        "scriptPubKey": script_json(&output.script_pubkey, network),
+                        "coinbase": tx.coinbase,
+                    }),
+                    _ => Value::Null,
+                }
+            }
+            "sendrawtransaction" => {
+                let hex: String = param(params, 0)?;
+                let tx: Transaction = deserialize_hex(&hex).map_err(|e| {
+                    rpc_error(
+                        RPC_DESERIALIZATION_ERROR,
+                        format!("TX decode failed: {}", e),
+                    )
+                })?;
+                let replaced = self.check(&tx)?;
+                self.evict(&replaced);
+                let txid = tx.compute_txid();
+                self.insert(tx, false);
+                self.mempool.push(txid);
+                if let Some(blocks) = self.auto_mine {
+                    let script = self.new_address().script_pubkey();
+                    for _ in 0..blocks {
+                        self.mine_block(&script);
+                    }
+                }
+                json!(txid)
+            }
+            "generatetoaddress" => {
+                let blocks: u32 = param(params, 0)?;
+                let address: String = param(params, 1)?;
+                let address = address
+                    .parse::<Address<NetworkUnchecked>>()
+                    .ok()
+                    .and_then(|address| address.require_network(network).ok())
+                    .ok_or_else(|| rpc_error(RPC_INVALID_ADDRESS_OR_KEY, "Invalid address"))?;
+                let script = address.script_pubkey();
+                let hashes: Vec<BlockHash> =
+                    (0..blocks).map(|_| self.mine_block(&script)).collect();
+                json!(hashes)
+            }
+            "listwallets" => json!(["mock"]),
+            "getnewaddress" | "getrawchangeaddress" => json!(self.new_address().to_string()),
+            "getbalance" => {
+                let min_conf = param_or(params, 1, 0)?;
+                let balance: Amount = self
+                    .wallet_coins(min_conf)
+                    .into_iter()
+                    .map(|(_, output, _)| output.value)
+                    .sum();
+                json!(balance.to_btc())
+            }
+            "listunspent" => {
+                let min_conf = param_or(params, 0, 1)?;
+                let max_conf = param_or(params, 1, 9_999_999)?;
+                let options: Value = param_or(params, 4, Value::Null)?;
+                let minimum = options["minimumAmount"].as_f64().unwrap_or(0.0);
+                let coins: Vec<Value> = self
+                    .wallet_coins(min_conf)
+                    .into_iter()
+                    .filter(|(_, _, confirmations)| *confirmations <= max_conf)
+                    .filter(|(outpoint, _, _)| !self.locked.contains(outpoint))
+                    .filter(|(_, output, _)| output.value.to_btc() >= minimum)
+                    .map(|(outpoint, output, confirmations)| {
+                        let mut coin = json!({
+                            "txid": outpoint.txid,
+                            "vout": outpoint.vout,
+                            "address": Address::from_script(&output.script_pubkey, network)
+                                .ok()
+                                .map(|a| a.to_string()),
+                            "scriptPubKey": output.script_pubkey.to_hex_string(),
+                            "amount": output.value.to_btc(),
+                            "confirmations": confirmations,
+                            "spendable": !self.watch_only.contains(&output.script_pubkey),
+                            "solvable": true,
+                            "safe": true,
+                        });
+                        if self.avoid_reuse {
+                            coin["reused"] = json!(self.reused.contains(&output.script_pubkey));
+                        }
+                        coin
+                    })
+                    .collect();
+                json!(coins)
+            }
+            "getwalletinfo" => json!({
+                "walletname": "mock",
+                "private_keys_enabled": self.private_keys,
+                "avoid_reuse": self.avoid_reuse,
+                "descriptors": true,
+            }),
+            "lockunspent" => {
+                let unlock: bool = param(params, 0)?;
+                let coins: Option<Vec<Value>> = param_or(params, 1, None)?;
+                // no list at all unlocks everything, an empty one nothing
+                let unlock_all = unlock && coins.is_none();
+                let outpoints = coins
+                    .unwrap_or_default()
+                    .iter()
+                    .map(|coin| {
+                        serde_json::from_value::<Txid>(coin["txid"].clone())
+                            .ok()
+                            .zip(coin["vout"].as_u64())
+                            .map(|(txid, vout)| OutPoint::new(txid, vout as u32))
+                            .ok_or_else(|| {
+                                rpc_error(
+                                    RPC_INVALID_PARAMETER,
+                                    "Invalid parameter, expected txid and vout",
+                                )
+                            })
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+                // like bitcoind, every coin is checked before any is (un)locked
+                for outpoint in &outpoints {
+                    if unlock && !self.locked.contains(outpoint) {
+                        return Err(rpc_error(
+                            RPC_INVALID_PARAMETER,
+                            "Invalid parameter, expected locked output",
+                        ));
+                    }
+                    if !unlock {
+                        if !self.utxos.contains_key(outpoint) {
+                            return Err(rpc_error(
+                                RPC_INVALID_PARAMETER,
+                                "Invalid parameter, expected unspent output",
+                            ));
+                        }
+                        if self.locked.contains(outpoint) {
+                            return Err(rpc_error(
+                                RPC_INVALID_PARAMETER,
+                                "Invalid parameter, output already locked",
+                            ));
+                        }
+                    }
+                }
+                if unlock_all {
+                    self.locked.clear();
+                } else if unlock {
+                    for outpoint in &outpoints {
+                        self.locked.remove(outpoint);
+                    }
+                } else {
+                    self.locked.extend(outpoints);
+                }
+                json!(true)
+            }
+            "listlockunspent" => {
+                let mut locked: Vec<&OutPoint> = self.locked.iter().collect();
+                locked.sort();
+                json!(locked
+                    .into_iter()
+                    .map(|outpoint| json!({ "txid": outpoint.txid, "vout": outpoint.vout }))
+                    .collect::<Vec<_>>())
+            }
+            "signrawtransactionwithwallet" => {
+                let hex: String = param(params, 0)?;
+                let mut tx: Transaction = deserialize_hex(&hex).map_err(|e| {
+                    rpc_error(
+                        RPC_DESERIALIZATION_ERROR,
+                        format!("TX decode failed: {}", e),
+                    )
+                })?;
+                // a key path signature's worth of witness, so sizes and fees come out right
+                let mut complete = true;
+                for input in &mut tx.input {
+                    let script = self
+                        .prevout(input.previous_output)
+                        .map(|output| output.script_pubkey.clone());
+                    let Some(script) = script.filter(|script| self.wallet.contains(script)) else {
+                        continue;
+                    };
+                    if self.watch_only.contains(&script) || !self.private_keys {
+                        complete = false;
+                    } else if input.witness.is_empty() {
+                        input.witness.push([1u8; 64]);
+                    }
+                }
+                json!({ "hex": serialize_hex(&tx), "complete": complete })
+            }
+            "gettransaction" => {
+                let txid: Txid = param(params, 0)?;
+                let mock = self.txs.get(&txid).ok_or_else(|| {
+                    rpc_error(
+                        RPC_INVALID_ADDRESS_OR_KEY,
+                        "Invalid or non-wallet transaction id",
+                    )
+                })?;
+                let tx = &mock.tx;
+                let block = mock.height.map(|height| &self.blocks[height as usize]);
+                // what the wallet gained from it
+                let received: Amount = tx
+                    .output
+                    .iter()
+                    .filter(|output| self.wallet.contains(&output.script_pubkey))
+                    .map(|output| output.value)
+                    .sum();
+                let sent: Amount = tx
+                    .input
+                    .iter()
+                    .filter_map(|input| self.prevout(input.previous_output))
+                    .filter(|output| self.wallet.contains(&output.script_pubkey))
+                    .map(|output| output.value)
+                    .sum();
+                let time = block.map_or(self.tip().header.time, |block| block.header.time);
+                json!({
+                    "amount": (received.to_signed().unwrap_or_default()
+                        - sent.to_signed().unwrap_or_default())
+                    .to_btc(),
+                    "confirmations": self.confirmations(mock),
+                    "blockhash": block.map(Block::block_hash),
+                    "blockheight": mock.height,
+                    "blocktime": block.map(|block| block.header.time),
+                    "txid": txid,
+                    "time": time,
+                    "timereceived": time,
+                    "bip125-replaceable": "no",
+                    "walletconflicts": [],
+                    "details": [],
+                    "hex": serialize_hex(tx),
+                })
+            }
+            _ => return Err(rpc_error(RPC_METHOD_NOT_FOUND, "Method not found")),
+        })
+    }
+}
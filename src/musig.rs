@@ -0,0 +1,351 @@
+use std::{collections::BTreeMap, fmt, str::FromStr};
+
+use anyhow::{anyhow, bail, Error, Result};
+use bitcoin::{
+    hashes::Hash,
+    hex::{DisplayHex, FromHex},
+    key::{Parity, Secp256k1},
+    secp256k1::{schnorr, PublicKey, SecretKey},
+    taproot::{TapNodeHash, TapTweakHash},
+    Address, XOnlyPublicKey,
+};
+use musig2::{
+    secp::{MaybeScalar, Scalar},
+    SecNonceBuilder,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{config::NetworkConfig, ctv_scripts::NUMS_INTERNAL_KEY, ids::NodePath};
+
+// MuSig2 (BIP-327) for the key path of the pool's root: key aggregation with the taproot tweak,
+// nonces, partial signatures and their aggregation into one BIP-340 signature. The crypto is the
+// musig2 crate's, these types keep the hex encodings the key close sessions are stored with.
+// https://github.com/bitcoin/bips/blob/master/bip-0327.mediawiki
+
+// withdraw address -> the key its user signs the key path close with, from their registration.
+// The pool root's internal key is the aggregate of its members' keys when every user registered one.
+pub type MusigKeys = BTreeMap<String, PublicKey>;
+
+// The aggregate of the signers' keys and the tweaks applied to it (KeyAgg and ApplyTweak)
+#[derive(Debug, Clone)]
+pub struct KeyAggContext {
+    keys: Vec<PublicKey>,
+    inner: musig2::KeyAggContext,
+}
+
+impl KeyAggContext {
+    // `keys` in signing order, the order is part of the aggregate
+    pub fn new(keys: &[PublicKey]) -> Result<Self> {
+        if keys.is_empty() {
+            bail!("no keys to aggregate");
+        }
+        let inner = musig2::KeyAggContext::new(keys.iter().copied())
+            .map_err(|e| anyhow!("the keys don't aggregate: {}", e))?;
+        Ok(Self {
+            keys: keys.to_vec(),
+            inner,
+        })
+    }
+
+    // Q + t*G, with Q negated first to an even y for an x-only tweak
+    pub fn tweak(&mut self, tweak: [u8; 32], x_only: bool) -> Result<()> {
+        let t = Scalar::try_from(tweak)
+            .map_err(|_| anyhow!("{} is not a tweak below the curve order", tweak.as_hex()))?;
+        self.inner = self
+            .inner
+            .clone()
+            .with_tweak(t, x_only)
+            .map_err(|e| anyhow!("{}", e))?;
+        Ok(())
+    }
+
+    // the taproot output key of the aggregate as internal key with `merkle_root`
+    pub fn tap_tweak(&mut self, merkle_root: Option<TapNodeHash>) -> Result<()> {
+        let tweak = TapTweakHash::from_key_and_tweak(self.x_only_key(), merkle_root);
+        self.tweak(tweak.to_byte_array(), true)
+    }
+
+    pub fn x_only_key(&self) -> XOnlyPublicKey {
+        self.inner.aggregated_pubkey()
+    }
+
+    pub fn keys(&self) -> &[PublicKey] {
+        &self.keys
+    }
+}
+
+fn hex_bytes<const N: usize>(hex: &str, what: &str) -> Result<[u8; N]> {
+    <[u8; N]>::from_hex(hex).map_err(|e| anyhow!("{} is not {} bytes of hex: {}", what, N, e))
+}
+
+// Two nonce points, what a signer hands the others before anyone signs
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct PubNonce([u8; 66]);
+
+impl PubNonce {
+    fn inner(&self) -> Result<musig2::PubNonce> {
+        musig2::PubNonce::from_bytes(&self.0).map_err(|e| anyhow!("{}", e))
+    }
+}
+
+impl FromStr for PubNonce {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let nonce = Self(hex_bytes(s, "a public nonce")?);
+        nonce.inner()?;
+        Ok(nonce)
+    }
+}
+
+impl TryFrom<String> for PubNonce {
+    type Error = Error;
+
+    fn try_from(s: String) -> Result<Self> {
+        s.parse()
+    }
+}
+
+impl From<PubNonce> for String {
+    fn from(nonce: PubNonce) -> String {
+        nonce.0.to_lower_hex_string()
+    }
+}
+
+// The two secret nonces behind a PubNonce and the key they are for. Signs once: `sign` takes it by
+// value and it has to be deleted after, a nonce used on two messages gives the secret key away.
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct SecNonce(musig2::SecNonce);
+
+impl SecNonce {
+    pub fn public(&self) -> PubNonce {
+        PubNonce(self.0.public_nonce().serialize())
+    }
+
+    // the key the nonce signs for, the last 33 of BIP-327's 97 bytes
+    pub fn key(&self) -> PublicKey {
+        PublicKey::from_slice(&self.0.serialize()[64..]).expect("a secret nonce holds its key")
+    }
+}
+
+impl fmt::Debug for SecNonce {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "SecNonce({})", self.key())
+    }
+}
+
+impl FromStr for SecNonce {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let bytes: [u8; 97] = hex_bytes(s, "a secret nonce")?;
+        Ok(Self(
+            musig2::SecNonce::from_bytes(&bytes).map_err(|e| anyhow!("{}", e))?,
+        ))
+    }
+}
+
+impl TryFrom<String> for SecNonce {
+    type Error = Error;
+
+    fn try_from(s: String) -> Result<Self> {
+        s.parse()
+    }
+}
+
+impl From<SecNonce> for String {
+    fn from(nonce: SecNonce) -> String {
+        nonce.0.serialize().to_lower_hex_string()
+    }
+}
+
+// NonceGen for signing `message` with `secret` under `aggregate`. `rand` has to be fresh
+// randomness every time, the secret key and message only harden it against a bad source
+pub fn nonce_gen(
+    secret: &SecretKey,
+    aggregate: &XOnlyPublicKey,
+    message: &[u8; 32],
+    rand: [u8; 32],
+) -> Result<(SecNonce, PubNonce)> {
+    let nonce = SecNonce(
+        SecNonceBuilder::from_seckey(rand, *secret)
+            .with_aggregated_pubkey(aggregate.public_key(Parity::Even))
+            .with_message(message)
+            .build(),
+    );
+    let public = nonce.public();
+    Ok((nonce, public))
+}
+
+// The sum of every signer's nonce points, infinity kept as 33 zero bytes (NonceAgg)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AggNonce(musig2::AggNonce);
+
+impl AggNonce {
+    pub fn new(nonces: &[PubNonce]) -> Result<Self> {
+        let nonces = nonces
+            .iter()
+            .enumerate()
+            .map(|(i, nonce)| {
+                nonce
+                    .inner()
+                    .map_err(|e| anyhow!("nonce {} is not two points: {}", i, e))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Self(musig2::AggNonce::sum(nonces)))
+    }
+}
+
+// A signer's share of the signature
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct PartialSig([u8; 32]);
+
+impl PartialSig {
+    fn inner(&self) -> Result<MaybeScalar> {
+        MaybeScalar::try_from(self.0)
+            .map_err(|_| anyhow!("{} is not below the curve order", self.0.as_hex()))
+    }
+}
+
+impl FromStr for PartialSig {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let sig = Self(hex_bytes(s, "a partial signature")?);
+        sig.inner()?;
+        Ok(sig)
+    }
+}
+
+impl TryFrom<String> for PartialSig {
+    type Error = Error;
+
+    fn try_from(s: String) -> Result<Self> {
+        s.parse()
+    }
+}
+
+impl From<PartialSig> for String {
+    fn from(sig: PartialSig) -> String {
+        sig.0.to_lower_hex_string()
+    }
+}
+
+// What every signer works out the same from the keys, the nonces and the message
+#[derive(Debug, Clone)]
+pub struct SigningSession {
+    context: KeyAggContext,
+    nonce: AggNonce,
+    message: [u8; 32],
+}
+
+impl SigningSession {
+    pub fn new(context: KeyAggContext, nonce: &AggNonce, message: [u8; 32]) -> Result<Self> {
+        Ok(Self {
+            context,
+            nonce: nonce.clone(),
+            message,
+        })
+    }
+
+    pub fn message(&self) -> &[u8; 32] {
+        &self.message
+    }
+
+    // Sign with `secret` and the nonce made for this session, checking the result like the others will
+    pub fn sign(&self, nonce: SecNonce, secret: &SecretKey) -> Result<PartialSig> {
+        let key = PublicKey::from_secret_key(&Secp256k1::new(), secret);
+        if key != nonce.key() {
+            bail!("the nonce was made for key {}, not {}", nonce.key(), key);
+        }
+        if !self.context.keys.contains(&key) {
+            bail!("key {} is not one of the signers'", key);
+        }
+        let public = nonce.public();
+        let s: MaybeScalar = musig2::sign_partial(
+            &self.context.inner,
+            *secret,
+            nonce.0,
+            &self.nonce.0,
+            self.message,
+        )
+        .map_err(|e| anyhow!("key {} can't sign: {}", key, e))?;
+        let sig = PartialSig(s.serialize());
+        self.verify(&sig, &public, &key)?;
+        Ok(sig)
+    }
+
+    // PartialSigVerify: `sig` is `key`'s share for the nonce it published
+    pub fn verify(&self, sig: &PartialSig, nonce: &PubNonce, key: &PublicKey) -> Result<()> {
+        musig2::verify_partial(
+            &self.context.inner,
+            sig.inner()?,
+            &self.nonce.0,
+            *key,
+            &nonce.inner()?,
+            self.message,
+        )
+        .map_err(|_| anyhow!("the partial signature of key {} doesn't verify", key))
+    }
+
+    // PartialSigAgg: the BIP-340 signature of the tweaked aggregate, checked before it is returned
+    pub fn aggregate(&self, sigs: &[PartialSig]) -> Result<schnorr::Signature> {
+        let sigs = sigs
+            .iter()
+            .map(PartialSig::inner)
+            .collect::<Result<Vec<_>>>()?;
+        musig2::aggregate_partial_signatures(&self.context.inner, &self.nonce.0, sigs, self.message)
+            .map_err(|e| anyhow!("the aggregated signature doesn't verify: {}", e))
+    }
+}
+
+// The members' keys in node order, when every one of them registered one
+pub fn member_keys(
+    users: &NodePath,
+    addresses: &[Address],
+    keys: &MusigKeys,
+) -> Option<Vec<PublicKey>> {
+    users
+        .users()
+        .iter()
+        .map(|&user| keys.get(&addresses.get(user)?.to_string()).copied())
+        .collect()
+}
+
+// every key belongs to a user of the pool and, if any user has one, every user does
+pub fn check_musig_keys(keys: &MusigKeys, withdraw_addresses: &[String]) -> Result<()> {
+    for address in keys.keys() {
+        if !withdraw_addresses.contains(address) {
+            bail!(
+                "musig key registered for {}, which is not in the pool",
+                address
+            );
+        }
+    }
+    if !keys.is_empty() && keys.len() != withdraw_addresses.len() {
+        bail!(
+            "{} of {} users registered a musig key, a key path close needs every one",
+            keys.len(),
+            withdraw_addresses.len()
+        );
+    }
+    Ok(())
+}
+
+// The internal key of the node of `users`: the aggregate of the members' musig keys for the node the
+// pool is funded at, when the users registered them, otherwise the NUMS point nobody can sign for
+pub fn internal_key(
+    users: &NodePath,
+    addresses: &[Address],
+    config: &NetworkConfig,
+) -> Result<XOnlyPublicKey> {
+    if config.musig_keys.is_empty() || *users != config.pool_root()? {
+        return Ok(XOnlyPublicKey::from_str(NUMS_INTERNAL_KEY)?);
+    }
+    let keys = member_keys(users, addresses, &config.musig_keys)
+        .ok_or_else(|| anyhow!("not every user of {} registered a musig key", users))?;
+    Ok(KeyAggContext::new(&keys)?.x_only_key())
+}
@@ -0,0 +1,156 @@
+use anyhow::Result;
+use bitcoin::{consensus::encode::serialize_hex, OutPoint, Transaction, TxOut, Txid};
+use serde::Serialize;
+use tracing::info;
+
+use crate::{
+    ids::{NodePath, UserIndex},
+    manifest::LoadedPool,
+    pools::node_exit,
+};
+
+// What one participant can broadcast from their own node to move on, no coordinator needed
+#[derive(Debug, Clone, Serialize)]
+pub struct NextStep {
+    pub user: UserIndex,
+    pub address: String,
+    pub txid: Txid,
+    pub tx_hex: String,
+    pub explainer: String,
+}
+
+// Every user in `node` gets the template that lets them leave it, spending the node output at `outpoint`.
+// The presigned backend only has signatures for the planned unwind, users without one get no step.
+pub fn exit_steps(
+    pool: &LoadedPool,
+    node: &NodePath,
+    outpoint: OutPoint,
+    prevout: &TxOut,
+) -> Vec<NextStep> {
+    node.user_indices()
+        .filter_map(
+            |user| match exit_step(pool, node, user, outpoint, prevout) {
+                Ok(step) => Some(step),
+                Err(e) => {
+                    info!(
+                        "no self broadcast exit for user {} of {}: {}",
+                        user, node, e
+                    );
+                    None
+                }
+            },
+        )
+        .collect()
+}
+
+fn exit_step(
+    pool: &LoadedPool,
+    node: &NodePath,
+    user: UserIndex,
+    outpoint: OutPoint,
+    prevout: &TxOut,
+) -> Result<NextStep> {
+    // the exit pool has one template paying both users
+    let spender = if node.is_exit() {
+        node.user_indices().next().unwrap_or(user)
+    } else {
+        user
+    };
+    let exit = node_exit(
+        &pool.tree,
+        &pool.config,
+        pool.backend.as_ref(),
+        &pool.addresses,
+        &pool.anchor_addr,
+        node,
+        spender,
+    )?;
+    let tx = pool
+        .backend
+        .finalize(exit.template_spend(outpoint, prevout.clone(), &pool.config))?;
+
+    let address = &pool.addresses[user.index()];
+    let paid = tx
+        .output
+        .iter()
+        .find(|output| output.script_pubkey == address.script_pubkey())
+        .map(|output| output.value)
+        .unwrap_or_default();
+    let mut explainer = if node.is_exit() {
+        format!(
+            "This is the last pool. Broadcast this transaction from your own node (sendrawtransaction) \
+             to pay out both remaining users, {} of it to your address {}.",
+            paid, address
+        )
+    } else {
+        format!(
+            "Broadcast this transaction from your own node (sendrawtransaction) to leave the pool of {} users: \
+             it pays {} to your address {} and moves the other {} users to their next pool.",
+            node.len(),
+            paid,
+            address,
+            node.len() - 1
+        )
+    };
+    explainer.push_str(&broadcast_notes(pool, &tx));
+    if let Some(delay) = pool.config.unwind_delay_for(node) {
+        explainer.push_str(&format!(
+            " It can only be mined once the pool output is {} blocks deep.",
+            delay
+        ));
+    }
+
+    Ok(NextStep {
+        user,
+        address: address.to_string(),
+        txid: tx.compute_txid(),
+        tx_hex: serialize_hex(&tx),
+        explainer,
+    })
+}
+
+// Once a node's recovery timeout has passed anyone can broadcast the sweep, so every user in it gets it
+pub fn sweep_steps(pool: &LoadedPool, node: &NodePath, sweep: &Transaction) -> Vec<NextStep> {
+    let Some(recovery) = &pool.config.recovery else {
+        return Vec::new();
+    };
+    let mut explainer = format!(
+        "Nobody unwound the pool of {} users in time. Anyone can broadcast this transaction (sendrawtransaction), \
+         it sweeps the whole node to the recovery address {}.",
+        node.len(),
+        recovery.address
+    );
+    explainer.push_str(&broadcast_notes(pool, sweep));
+
+    node.user_indices()
+        .map(|user| NextStep {
+            user,
+            address: pool.addresses[user.index()].to_string(),
+            txid: sweep.compute_txid(),
+            tx_hex: serialize_hex(sweep),
+            explainer: explainer.clone(),
+        })
+        .collect()
+}
+
+// the fee was fixed when the pool was built, only an anchor child can raise it
+fn broadcast_notes(pool: &LoadedPool, tx: &Transaction) -> String {
+    let Some(anchor_vout) = tx
+        .output
+        .iter()
+        .position(|output| output.script_pubkey == pool.anchor_addr.script_pubkey())
+    else {
+        return " Its fee was fixed when the pool was built and can't be bumped.".to_string();
+    };
+    let mut notes = format!(
+        " Its fee was fixed when the pool was built, if that is too low spend its anchor output (vout {}) \
+         in a child transaction (CPFP).",
+        anchor_vout
+    );
+    if pool.config.tx_version == 3 {
+        notes.push_str(
+            " It is a v3 transaction, submit it together with the child (submitpackage).",
+        );
+    }
+    notes
+}
@@ -0,0 +1,113 @@
+use anyhow::{bail, Result};
+use bitcoin::{
+    hashes::{sha256, Hash, HashEngine},
+    hex::DisplayHex,
+};
+use serde::Serialize;
+use tracing::info;
+
+use crate::{lifecycle::Lifecycle, manifest::PoolManifest, metadata::PoolMetadata};
+
+// bytes of the hash in a verification code, 20 hex digits
+const CODE_BYTES: usize = 10;
+
+// A participant's code compared with ours
+#[derive(Debug, Clone, Serialize)]
+pub struct PeerCode {
+    pub code: String,
+    pub matches: bool,
+}
+
+// What `p2p verify` found: the root we rebuilt, our code and how the others' compare
+#[derive(Debug, Clone, Serialize)]
+pub struct CrossCheck {
+    pub root_address: String,
+    pub code: String,
+    pub peers: Vec<PeerCode>,
+}
+
+// Short code everyone reads out to the others (call, chat, in person) to agree on the pool without a
+// coordinator: a hash of the root address and everything in the manifest that goes into the tree.
+// Funding, lifecycle and labels are left out, they differ between participants and change over time.
+pub fn verification_code(manifest: &PoolManifest) -> Result<String> {
+    let mut tree = manifest.clone();
+    tree.funding_txid = None;
+    tree.lifecycle = Lifecycle::Registered;
+    tree.metadata = PoolMetadata::default();
+
+    let mut engine = sha256::Hash::engine();
+    engine.input(b"ctv payment pool verification");
+    engine.input(manifest.root_address.as_bytes());
+    engine.input(&postcard::to_stdvec(&tree)?);
+    let hash = sha256::Hash::from_engine(engine);
+
+    Ok(hash.as_byte_array()[..CODE_BYTES]
+        .chunks(2)
+        .map(|group| group.to_lower_hex_string())
+        .collect::<Vec<_>>()
+        .join("-"))
+}
+
+// dashes, spaces and case don't matter when a code is typed back in
+fn normalize(code: &str) -> String {
+    code.chars()
+        .filter(char::is_ascii_alphanumeric)
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
+
+pub fn compare_codes(own: &str, codes: &[String]) -> Vec<PeerCode> {
+    codes
+        .iter()
+        .map(|code| PeerCode {
+            code: code.clone(),
+            matches: normalize(code) == normalize(own),
+        })
+        .collect()
+}
+
+// Rebuild the pool from the manifest (the root has to match) and compare our code with `codes`
+pub fn cross_verify(manifest: &PoolManifest, codes: &[String]) -> Result<CrossCheck> {
+    manifest.load_pool()?;
+    let code = verification_code(manifest)?;
+    info!(
+        "rebuilt {} from the manifest, verification code {} \n",
+        manifest.root_address, code
+    );
+    Ok(CrossCheck {
+        root_address: manifest.root_address.clone(),
+        peers: compare_codes(&code, codes),
+        code,
+    })
+}
+
+// every participant has to have built the same pool
+pub fn require_agreement(peers: &[PeerCode]) -> Result<()> {
+    let mismatched: Vec<&str> = peers
+        .iter()
+        .filter(|peer| !peer.matches)
+        .map(|peer| peer.code.as_str())
+        .collect();
+    if !mismatched.is_empty() {
+        bail!(
+            "{} of {} verification codes don't match this manifest ({}), someone has a different pool, don't fund it",
+            mismatched.len(),
+            peers.len(),
+            mismatched.join(", ")
+        );
+    }
+    Ok(())
+}
+
+pub fn print_cross_check(check: &CrossCheck) {
+    println!("root address: {}", check.root_address);
+    println!("your code:    {}", check.code);
+    if check.peers.is_empty() {
+        println!("read it out to the other participants and pass theirs with --code");
+        return;
+    }
+    for peer in &check.peers {
+        let status = if peer.matches { "match" } else { "MISMATCH" };
+        println!("  {:<24} {}", peer.code, status);
+    }
+}
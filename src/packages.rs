@@ -0,0 +1,212 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, bail, Result};
+use bitcoin::{consensus::encode::serialize_hex, Amount, OutPoint, Transaction, TxOut, Txid};
+use bitcoincore_rpc::{json::SignRawTransactionInput, RpcApi};
+use serde::Serialize;
+use tracing::info;
+
+use crate::{
+    ids::{NodePath, UserIndex},
+    manifest::LoadedPool,
+    pools::{
+        anchor_child, anchor_output, close_all_template, cpfp_change, pool_spend_template, FeeCoin,
+        CPFP_CHILD_VSIZE,
+    },
+    rpc_helper::{fee_for_vsize, AsyncRpc},
+    standardness::check_standard,
+};
+
+pub const DEFAULT_PACKAGE_DIR: &str = "packages";
+
+// One step of the unwind as a package: the template and the anchor child paying for it, written
+// as the json array `bitcoin-cli submitpackage` takes
+#[derive(Debug, Clone, Serialize)]
+pub struct UnwindPackage {
+    pub users: NodePath,
+    // None for the close-all spend
+    pub spender: Option<UserIndex>,
+    pub parent_txid: Txid,
+    pub child_txid: Txid,
+    pub child_fee: Amount,
+    // the fee payer coin the child spends, the change of the step before's child after the first
+    pub fee_coin: OutPoint,
+    pub file: PathBuf,
+}
+
+// the argument of submitpackage, parent first
+pub fn package_json(parent: &Transaction, child: &Transaction) -> Result<String> {
+    Ok(serde_json::to_string(&[
+        serialize_hex(parent),
+        serialize_hex(child),
+    ])?)
+}
+
+// A confirmed coin of the fee payer covering `fee` with change above dust
+async fn pick_fee_coin(fee_payer: &AsyncRpc, fee: Amount) -> Result<FeeCoin> {
+    let unspent = fee_payer
+        .run(|c| c.list_unspent(Some(1), None, None, None, None))
+        .await?;
+    let utxo = unspent
+        .into_iter()
+        .find(|utxo| cpfp_change(utxo.amount, fee).is_ok())
+        .ok_or_else(|| {
+            anyhow!(
+                "no single confirmed coin in the fee payer wallet covers the {} the anchor children pay",
+                fee
+            )
+        })?;
+    Ok(FeeCoin {
+        outpoint: OutPoint::new(utxo.txid, utxo.vout),
+        txout: TxOut {
+            value: utxo.amount,
+            script_pubkey: utxo.script_pub_key,
+        },
+    })
+}
+
+// The anchor child of `parent` spending `fee_coin`, signed by the fee payer wallet and checked for
+// relay. Nothing is broadcast.
+async fn signed_child(
+    pool: &LoadedPool,
+    fee_payer: &AsyncRpc,
+    parent: &Transaction,
+    fee_coin: &FeeCoin,
+    fee: Amount,
+) -> Result<Transaction> {
+    let (anchor_vout, anchor) = anchor_output(&pool.config, parent)?;
+    let change_address = fee_payer.run(|c| c.get_raw_change_address(None)).await?;
+    let child = anchor_child(
+        &pool.config,
+        OutPoint::new(parent.compute_txid(), anchor_vout),
+        fee_coin,
+        fee,
+        change_address.assume_checked().script_pubkey(),
+    )?;
+    // the coin is the change of a child that isn't on chain yet after the first step
+    let fee_input = SignRawTransactionInput {
+        txid: fee_coin.outpoint.txid,
+        vout: fee_coin.outpoint.vout,
+        script_pub_key: fee_coin.txout.script_pubkey.clone(),
+        redeem_script: None,
+        amount: Some(fee_coin.txout.value),
+    };
+    let child_hex = serialize_hex(&child);
+    let signed = fee_payer
+        .run(move |c| c.sign_raw_transaction_with_wallet(child_hex, Some(&[fee_input]), None))
+        .await?;
+    if !signed.complete {
+        bail!(
+            "the fee payer wallet couldn't sign the anchor child of {}",
+            parent.compute_txid()
+        );
+    }
+    let child = signed.transaction()?;
+    check_standard(fee_payer, &child, &[anchor, fee_coin.txout.clone()]).await?;
+    Ok(child)
+}
+
+// A package for every step of the planned unwind (user 0 leaves first), each child paying `fee_rate`
+// (sat/kvB) on its own size like the children the pool broadcasts itself, and one for the close-all
+// spend if the pool has one. One coin of the fee payer pays for all of them: each child spends the
+// change of the one before, the close-all child the coin itself as it replaces the whole unwind.
+// The children are signed with the fee payer wallet, nothing is broadcast.
+pub async fn export_packages(
+    pool: &LoadedPool,
+    funding_tx: &Transaction,
+    fee_payer: &AsyncRpc,
+    fee_rate: u64,
+    dir: &Path,
+) -> Result<Vec<UnwindPackage>> {
+    if pool.config.anchor_amount.is_none() {
+        bail!("the pool's templates have no fee anchor to attach a child to, send each one with sendrawtransaction");
+    }
+    let fee = fee_for_vsize(fee_rate, CPFP_CHILD_VSIZE)?;
+    let spenders = pool.config.spenders()?;
+    let total_fee = fee
+        .checked_mul(spenders.len() as u64)
+        .ok_or_else(|| anyhow!("the fees of the anchor children overflow"))?;
+    let first_coin = pick_fee_coin(fee_payer, total_fee).await?;
+    info!(
+        "anchor children pay {} each from {}",
+        fee, first_coin.outpoint
+    );
+    fs::create_dir_all(dir)?;
+
+    let mut packages = Vec::new();
+    let mut write = |parent: &Transaction,
+                     child: &Transaction,
+                     fee_coin: &FeeCoin,
+                     name: String,
+                     spender: Option<UserIndex>,
+                     users| {
+        let file = dir.join(name);
+        fs::write(&file, package_json(parent, child)?)?;
+        info!("{} written \n", file.display());
+        packages.push(UnwindPackage {
+            users,
+            spender,
+            parent_txid: parent.compute_txid(),
+            child_txid: child.compute_txid(),
+            child_fee: fee,
+            fee_coin: fee_coin.outpoint,
+            file,
+        });
+        Ok::<_, anyhow::Error>(())
+    };
+
+    let mut previous_tx = funding_tx.clone();
+    let mut fee_coin = first_coin.clone();
+    for user in spenders {
+        let spend = pool_spend_template(
+            &pool.tree,
+            &pool.config,
+            pool.backend.as_ref(),
+            user,
+            &pool.addresses,
+            &previous_tx,
+            &pool.anchor_addr,
+        )?;
+        let parent = pool.backend.finalize(spend)?;
+        let child = signed_child(pool, fee_payer, &parent, &fee_coin, fee).await?;
+        write(
+            &parent,
+            &child,
+            &fee_coin,
+            format!("unwind_user_{}.json", user),
+            Some(user),
+            pool.config.unwind_node(user)?,
+        )?;
+        // the change, the last output of the child
+        let change_vout = child.output.len() - 1;
+        fee_coin = FeeCoin {
+            outpoint: OutPoint::new(child.compute_txid(), change_vout as u32),
+            txout: child.output[change_vout].clone(),
+        };
+        previous_tx = parent;
+    }
+    if pool.tree.root()?.close_all.is_some() && pool.config.pool_root()?.is_root() {
+        let spend = close_all_template(
+            &pool.tree,
+            &pool.config,
+            pool.backend.as_ref(),
+            &pool.addresses,
+            funding_tx,
+            &pool.anchor_addr,
+        )?;
+        let parent = pool.backend.finalize(spend)?;
+        let child = signed_child(pool, fee_payer, &parent, &first_coin, fee).await?;
+        write(
+            &parent,
+            &child,
+            &first_coin,
+            "close_all.json".to_string(),
+            None,
+            NodePath::root(),
+        )?;
+    }
+    Ok(packages)
+}
@@ -0,0 +1,208 @@
+use std::collections::BTreeMap;
+
+use anyhow::{anyhow, bail, Result};
+use bitcoin::{
+    address::NetworkUnchecked, Address, Amount, Network, ScriptBuf, SignedAmount, TxOut,
+};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    addresses::check_address,
+    config::{NetworkConfig, DEFAULT_FEE_RATE, DUST_AMOUNT, FEE_AMOUNT},
+    ids::UserIndex,
+    removal::redistributed,
+    AMOUNT_PER_USER,
+};
+
+// Part of a user's exit paid to another of their addresses, e.g. a cold wallet next to the hot one
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SplitPayout {
+    pub address: Address<NetworkUnchecked>,
+    pub amount: Amount,
+}
+
+// withdraw address -> the splits of its exit, the withdraw address itself gets what is left
+pub type PayoutSplits = BTreeMap<String, Vec<SplitPayout>>;
+
+// Sats each user's share of the pool is moved by, by position in the tree, so the payouts aren't
+// all the same round amount. Sums to zero, the pool holds what it always did. Empty without jitter.
+pub type PayoutJitter = Vec<i64>;
+
+// what an extra output costs at DEFAULT_FEE_RATE (sat/kvB, rounded up), paid by the user splitting
+pub fn split_output_fee(output: &TxOut) -> Amount {
+    Amount::from_sat((output.size() as u64 * DEFAULT_FEE_RATE).div_ceil(1000))
+}
+
+// what `splits` take out of an exit, the split amounts and the fee of their outputs
+pub fn split_total(splits: &[SplitPayout]) -> Amount {
+    split_outputs(splits)
+        .iter()
+        .map(|output| output.value + split_output_fee(output))
+        .sum()
+}
+
+fn split_outputs(splits: &[SplitPayout]) -> Vec<TxOut> {
+    splits
+        .iter()
+        .map(|split| TxOut {
+            value: split.amount,
+            script_pubkey: split.address.assume_checked_ref().script_pubkey(),
+        })
+        .collect()
+}
+
+// The outputs paying `amount` to `address`: what is left after its splits to the address itself,
+// then every split in order. Anything without splits (e.g. the next pool node) is one output.
+pub fn payout_outputs(address: &Address, amount: Amount, config: &NetworkConfig) -> Vec<TxOut> {
+    let splits = config
+        .splits
+        .get(&address.to_string())
+        .map(Vec::as_slice)
+        .unwrap_or_default();
+    // check_splits made sure every exit covers them
+    let rest = amount
+        .checked_sub(split_total(splits))
+        .unwrap_or(Amount::ZERO);
+    let mut outputs = vec![TxOut {
+        value: rest,
+        script_pubkey: address.script_pubkey(),
+    }];
+    outputs.extend(split_outputs(splits));
+    outputs
+}
+
+// every script paying the user at `address`, their withdraw address first
+pub fn user_scripts(address: &Address, config: &NetworkConfig) -> Vec<ScriptBuf> {
+    let splits = config
+        .splits
+        .get(&address.to_string())
+        .map(Vec::as_slice)
+        .unwrap_or_default();
+    std::iter::once(address.script_pubkey())
+        .chain(
+            split_outputs(splits)
+                .into_iter()
+                .map(|output| output.script_pubkey),
+        )
+        .collect()
+}
+
+// Splits of one user: addresses the pool can pay on `network`, no dust, and the smallest exit
+// (AMOUNT_PER_USER - FEE_AMOUNT) still leaves more than dust for the withdraw address
+pub fn check_splits(splits: &[SplitPayout], network: Network) -> Result<()> {
+    for split in splits {
+        let address = check_address(&split.address, network).map_err(|e| anyhow!("{}", e))?;
+        let dust = address.script_pubkey().minimal_non_dust();
+        if split.amount < dust {
+            bail!(
+                "split of {} to {} is dust (at least {})",
+                split.amount,
+                address,
+                dust
+            );
+        }
+    }
+    let smallest_exit = AMOUNT_PER_USER - FEE_AMOUNT;
+    let total = split_total(splits);
+    if smallest_exit.checked_sub(total).unwrap_or(Amount::ZERO) < DUST_AMOUNT {
+        bail!(
+            "splits take {} with their fees, the {} exit would leave less than {} for the withdraw address",
+            total,
+            smallest_exit,
+            DUST_AMOUNT
+        );
+    }
+    Ok(())
+}
+
+// `splits` keyed by the withdraw address, checking every one
+pub fn check_all_splits(splits: &PayoutSplits, network: Network) -> Result<()> {
+    for (address, user_splits) in splits {
+        check_splits(user_splits, network).map_err(|e| anyhow!("{}: {}", address, e))?;
+    }
+    Ok(())
+}
+
+// Jitter of at most `max` either way for each of `users` users. The draws rarely cancel out, what
+// is left over is spread back over the users (none of them beyond `max`) so the jitter sums to zero.
+pub fn draw_payout_jitter(max: Amount, users: usize, rng: &mut impl Rng) -> PayoutJitter {
+    let max = max.to_sat() as i64;
+    let mut jitter: PayoutJitter = (0..users).map(|_| rng.gen_range(-max..=max)).collect();
+    let mut rest = -jitter.iter().sum::<i64>();
+    for sats in &mut jitter {
+        let adjust = rest.clamp(-max - *sats, max - *sats);
+        *sats += adjust;
+        rest -= adjust;
+    }
+    jitter
+}
+
+// what `user` has in the pool: AMOUNT_PER_USER moved by their jitter. They leave with it minus the fee.
+pub fn user_share(user: UserIndex, config: &NetworkConfig) -> Amount {
+    jittered_share(user, &config.payout_jitter)
+}
+
+// user_share for the jitter of a manifest, without a config
+pub fn jittered_share(user: UserIndex, jitter: &PayoutJitter) -> Amount {
+    let jitter = jitter.get(user.index()).copied().unwrap_or_default();
+    // check_payout_jitter keeps every share positive
+    AMOUNT_PER_USER
+        .to_signed()
+        .ok()
+        .and_then(|share| share.checked_add(SignedAmount::from_sat(jitter)))
+        .and_then(|share| share.to_unsigned().ok())
+        .unwrap_or(Amount::ZERO)
+}
+
+// The jitter has one entry per user, sums to zero (or to the shares of removed users handed to the
+// others, see removal.rs) and leaves every user's smallest exit (their share - FEE_AMOUNT, minus
+// their splits) above dust
+pub fn check_payout_jitter(addresses: &[Address], config: &NetworkConfig) -> Result<()> {
+    let jitter = &config.payout_jitter;
+    if jitter.is_empty() {
+        return Ok(());
+    }
+    if jitter.len() != addresses.len() {
+        bail!(
+            "payout jitter for {} users but the pool has {}",
+            jitter.len(),
+            addresses.len()
+        );
+    }
+    let total: i64 = jitter.iter().sum();
+    let redistributed = redistributed(&config.removed)?;
+    if total != 0 && redistributed == Amount::ZERO {
+        bail!(
+            "payout jitter sums to {} sat, it has to cancel out so the pool holds AMOUNT_PER_USER for every user",
+            total
+        );
+    }
+    if i64::try_from(redistributed.to_sat()) != Ok(total) {
+        bail!(
+            "payout jitter sums to {} sat but the removed users handed {} to the others",
+            total,
+            redistributed
+        );
+    }
+    for (user, address) in UserIndex::all().zip(addresses) {
+        let splits = config
+            .splits
+            .get(&address.to_string())
+            .map(Vec::as_slice)
+            .unwrap_or_default();
+        let smallest_exit = user_share(user, config)
+            .checked_sub(FEE_AMOUNT + split_total(splits))
+            .unwrap_or(Amount::ZERO);
+        if smallest_exit < DUST_AMOUNT {
+            bail!(
+                "a jitter of {} sat leaves user {} ({}) less than {} after the fee and their splits, lower POOL_PAYOUT_JITTER_SATS",
+                jitter[user.index()],
+                user,
+                address,
+                DUST_AMOUNT
+            );
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,323 @@
+use std::{fs, path::Path, str::FromStr};
+
+use anyhow::{anyhow, bail, Result};
+use bitcoin::{address::NetworkUnchecked, bip32::Xpub, Address, Amount, Network, Txid};
+use bitcoincore_rpc::RpcApi;
+use serde::{Deserialize, Serialize};
+use serde_json::value::RawValue;
+use tracing::info;
+
+use crate::{
+    addresses::{line_of, AddressChecker, AddressProblem},
+    config::{ConfirmationStage, NetworkConfig},
+    covenant::CovenantBackend,
+    esplora::ConfirmationTracker,
+    fund::required_funding,
+    ids::NodePath,
+    lifecycle::{Event, Lifecycle},
+    manifest::PoolManifest,
+    mempool::BroadcastQueue,
+    metrics::METRICS,
+    miner::wait_for_confirmations,
+    pools::{build_pools, process_pool_spend},
+    registration::xpub_address,
+    rpc_helper::AsyncRpc,
+    AMOUNT_PER_USER, POOL_USERS,
+};
+
+// Paid every period. A fixed address is reused by every epoch, an xpub gives a fresh
+// address per epoch (key path only p2tr of child <epoch>), so the pools don't share addresses.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PayrollRecipient {
+    pub name: String,
+    pub amount: Amount,
+    #[serde(default)]
+    pub address: Option<Address<NetworkUnchecked>>,
+    #[serde(default)]
+    pub xpub: Option<Xpub>,
+}
+
+// just enough of a template to find where each recipient is in the file
+#[derive(Deserialize)]
+struct RawTemplate<'a> {
+    #[serde(borrow)]
+    recipients: Vec<&'a RawValue>,
+}
+
+// What the treasury pays out every period, and when the periods are
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PayrollTemplate {
+    // height the first pay period starts at
+    pub start_height: u32,
+    pub epoch_blocks: u32,
+    pub epochs: u32,
+    pub recipients: Vec<PayrollRecipient>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PayrollEpoch {
+    pub epoch: u32,
+    pub start_height: u32,
+    pub pool: PoolManifest,
+    // one per unwind spend, recorded as they are broadcast
+    #[serde(default)]
+    pub exit_txids: Vec<Txid>,
+}
+
+// Every pay period's pool, computed up front so the addresses are known before any of them is funded
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PayrollRegistry {
+    pub template: PayrollTemplate,
+    pub epochs: Vec<PayrollEpoch>,
+}
+
+impl PayrollTemplate {
+    pub fn load(path: &Path, network: Network) -> Result<Self> {
+        let text = fs::read_to_string(path)?;
+        let template: Self = serde_json::from_str(&text)?;
+        // the line of every recipient, for the address errors
+        let raw: RawTemplate = serde_json::from_str(&text)?;
+        let lines: Vec<usize> = raw
+            .recipients
+            .iter()
+            .map(|recipient| line_of(&text, recipient))
+            .collect();
+        template.check(network, &lines)?;
+        Ok(template)
+    }
+
+    // The tree pays AMOUNT_PER_USER to each of POOL_USERS leaves, the template has to fit that. Every
+    // address has to be for `network` and no two recipients can be paid to the same one.
+    fn check(&self, network: Network, lines: &[usize]) -> Result<()> {
+        if self.epochs == 0 || self.epoch_blocks == 0 {
+            bail!("payroll template needs at least one epoch of at least one block");
+        }
+        if self.recipients.len() != POOL_USERS {
+            bail!(
+                "payroll template has {} recipients but this build is for {} (POOL_USERS)",
+                self.recipients.len(),
+                POOL_USERS
+            );
+        }
+        for recipient in &self.recipients {
+            if recipient.amount != AMOUNT_PER_USER {
+                bail!(
+                    "{} is paid {} but every leaf of the pool pays {} (AMOUNT_PER_USER)",
+                    recipient.name,
+                    recipient.amount,
+                    AMOUNT_PER_USER
+                );
+            }
+            if recipient.address.is_some() == recipient.xpub.is_some() {
+                bail!("{} needs either an address or an xpub", recipient.name);
+            }
+        }
+
+        let mut checker = AddressChecker::new(network);
+        for (i, recipient) in self.recipients.iter().enumerate() {
+            let line = lines.get(i).copied();
+            match (&recipient.address, &recipient.xpub) {
+                (Some(address), _) => {
+                    checker.check(i, line, address);
+                }
+                // two recipients with one xpub get the same address every epoch
+                (None, Some(xpub)) => match xpub_address(xpub, 0, network) {
+                    Ok(address) => {
+                        checker.checked(i, line, address);
+                    }
+                    Err(e) => checker.reject(i, line, AddressProblem::Invalid(e.to_string())),
+                },
+                (None, None) => {}
+            }
+        }
+        checker.finish()
+    }
+
+    // the withdraw addresses of pay period `epoch`, in template order
+    pub fn epoch_addresses(&self, config: &NetworkConfig, epoch: u32) -> Result<Vec<Address>> {
+        self.recipients
+            .iter()
+            .map(|recipient| match (&recipient.address, &recipient.xpub) {
+                (Some(address), _) => Ok(address.clone().require_network(config.network)?),
+                (None, Some(xpub)) => xpub_address(xpub, epoch, config.network),
+                (None, None) => bail!("{} needs either an address or an xpub", recipient.name),
+            })
+            .collect()
+    }
+}
+
+impl PayrollRegistry {
+    pub fn write(&self, path: &Path) -> Result<()> {
+        fs::write(path, serde_json::to_string_pretty(self)?)?;
+        info!("payroll registry written to {} \n", path.display());
+        Ok(())
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let mut registry: Self = serde_json::from_str(&fs::read_to_string(path)?)?;
+        // registries from before the lifecycle was kept only have the txids
+        for entry in &mut registry.epochs {
+            if entry.pool.funding_txid.is_some() && entry.pool.lifecycle == Lifecycle::Registered {
+                entry.pool.lifecycle = Lifecycle::from_record(true, entry.exit_txids.len());
+            }
+        }
+        Ok(registry)
+    }
+
+    // the pay period `height` falls in, None before the first and after the last one
+    pub fn current_epoch(&self, height: u32) -> Option<u32> {
+        let since_start = height.checked_sub(self.template.start_height)?;
+        let epoch = since_start / self.template.epoch_blocks;
+        (epoch < self.template.epochs).then_some(epoch)
+    }
+}
+
+// Build the pool of every pay period and record its root address. Only for OP_CTV, a presigned
+// pool needs its funding tx before the key is deleted, which future epochs don't have yet.
+pub fn schedule_payroll(
+    config: &NetworkConfig,
+    backend: &dyn CovenantBackend,
+    template: PayrollTemplate,
+) -> Result<PayrollRegistry> {
+    if backend.requires_presigning() {
+        bail!("payroll pools are computed in advance, that needs the ctv backend");
+    }
+    let anchor_addr =
+        Address::from_str(&config.fee_anchor_addr)?.require_network(config.network)?;
+
+    let mut epochs = Vec::with_capacity(template.epochs as usize);
+    for epoch in 0..template.epochs {
+        let addresses = template.epoch_addresses(config, epoch)?;
+        let pools = build_pools(&addresses, &anchor_addr, config, backend)?;
+        let root = Address::p2tr_tweaked(
+            pools.spend_info(&NodePath::root())?.output_key(),
+            config.network,
+        );
+        let start_height = template.start_height + epoch * template.epoch_blocks;
+        info!(
+            "payroll epoch {} (from height {}): {}",
+            epoch, start_height, root
+        );
+
+        epochs.push(PayrollEpoch {
+            epoch,
+            start_height,
+            pool: PoolManifest::new(config, backend, &anchor_addr, &addresses, &root),
+            exit_txids: Vec::new(),
+        });
+    }
+
+    Ok(PayrollRegistry { template, epochs })
+}
+
+// Fund the epoch's pool from the wallet (unless it already is) and unwind it, users leave in template order.
+// The registry is written after every step so an interrupted run picks up where it stopped.
+pub async fn run_payroll_epoch(
+    rpc: &AsyncRpc,
+    fee_payer: &AsyncRpc,
+    registry: &mut PayrollRegistry,
+    registry_path: &Path,
+    epoch: u32,
+) -> Result<()> {
+    let entry = registry
+        .epochs
+        .get(epoch as usize)
+        .ok_or_else(|| anyhow!("payroll has no epoch {}", epoch))?;
+    if entry.pool.lifecycle.is_finished() {
+        bail!(
+            "payroll epoch {} is already {}",
+            epoch,
+            entry.pool.lifecycle
+        );
+    }
+    let loaded = entry.pool.load_pool()?;
+    let config = &loaded.config;
+
+    let funding_txid = match entry.pool.funding_txid {
+        Some(txid) => txid,
+        None => {
+            let root =
+                Address::from_str(&entry.pool.root_address)?.require_network(config.network)?;
+            let txid = rpc
+                .run(move |c| {
+                    c.send_to_address(
+                        &root,
+                        required_funding(),
+                        None,
+                        None,
+                        None,
+                        None,
+                        None,
+                        None,
+                    )
+                })
+                .await?;
+            info!("payroll epoch {} funded: {} \n", epoch, txid);
+            let pool = &mut registry.epochs[epoch as usize].pool;
+            pool.funding_txid = Some(txid);
+            pool.advance(Event::Fund)?;
+            registry.write(registry_path)?;
+            txid
+        }
+    };
+    let funding_blocks = config.confirmations.get(ConfirmationStage::Funding);
+    wait_for_confirmations(rpc, config, funding_txid, funding_blocks).await?;
+    let mut confirmations = ConfirmationTracker::from_config(config)?;
+    if let Some(tracker) = &mut confirmations {
+        tracker
+            .wait(funding_txid, ConfirmationStage::Funding)
+            .await?;
+    }
+
+    // continue after the last recorded exit
+    let done = registry.epochs[epoch as usize].exit_txids.len();
+    METRICS.set_pools_tracked(1);
+    let members = loaded.config.pool_root()?.len();
+    METRICS.set_pending_withdrawals(members - done);
+    let mut current_txid = registry.epochs[epoch as usize]
+        .exit_txids
+        .last()
+        .copied()
+        .unwrap_or(funding_txid);
+    let mut queue = BroadcastQueue::new(&loaded.config);
+    for (level, spender) in loaded.config.spenders()?.into_iter().enumerate().skip(done) {
+        // the registry has to agree this user is next before anything is broadcast
+        registry.epochs[epoch as usize]
+            .pool
+            .lifecycle
+            .apply(Event::Withdraw(spender))?;
+        info!(
+            "paying {} (epoch {})",
+            registry.template.recipients[spender.index()].name,
+            epoch
+        );
+        current_txid = process_pool_spend(
+            &loaded.tree,
+            config,
+            rpc,
+            fee_payer,
+            &mut queue,
+            loaded.backend.as_ref(),
+            spender,
+            &loaded.addresses,
+            current_txid,
+            &loaded.anchor_addr,
+        )
+        .await?;
+        let stage = ConfirmationStage::of_spend(spender, &loaded.config)?;
+        wait_for_confirmations(rpc, config, current_txid, config.confirmations.get(stage)).await?;
+        if let Some(tracker) = &mut confirmations {
+            tracker.wait(current_txid, stage).await?;
+        }
+        let entry = &mut registry.epochs[epoch as usize];
+        entry.exit_txids.push(current_txid);
+        entry.pool.advance(Event::Withdraw(spender))?;
+        registry.write(registry_path)?;
+        METRICS.set_pending_withdrawals(members - level - 1);
+    }
+    METRICS.set_pending_withdrawals(0);
+    METRICS.set_pools_tracked(0);
+
+    info!("payroll epoch {} paid out \n", epoch);
+    Ok(())
+}
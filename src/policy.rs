@@ -0,0 +1,346 @@
+use anyhow::{anyhow, bail, Result};
+use bitcoin::{
+    opcodes::all::{
+        OP_CHECKSIG, OP_CHECKSIGVERIFY, OP_CSV, OP_DROP, OP_ELSE, OP_ENDIF, OP_IF, OP_VERIFY,
+    },
+    relative,
+    script::Builder,
+    ScriptBuf, Sequence, Transaction, TxOut, XOnlyPublicKey,
+};
+use miniscript::{policy::Concrete, Miniscript, Tap};
+use std::str::FromStr;
+
+use crate::{
+    covenant::{CovenantBackend, TemplateSpend},
+    ctv_scripts::OP_SECURETHEBAG,
+    template::{Bip119Ctv, TemplateCommitment},
+};
+
+// A leaf condition composed from pieces instead of hand written script, e.g.
+// `LeafPolicy::ctv(hash).and_older(144)` or `LeafPolicy::ctv(hash).or_key(operator)`.
+// Every policy compiles to one tapscript, an `or` is an OP_IF / OP_ELSE the witness picks a side of.
+// Fallback and recovery branches with keys (multisig, key + timelock) can be written in the miniscript
+// policy language, `LeafPolicy::ctv(hash).or_miniscript("thresh(2,pk(A),pk(B),pk(C))")`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LeafPolicy {
+    // the spending tx has to match this template hash (OP_CTV)
+    Ctv([u8; 32]),
+    // the spending input's nSequence has to be at least this many blocks (OP_CSV)
+    Older(u16),
+    // a schnorr signature of this key
+    Key(XOnlyPublicKey),
+    // a branch compiled from a miniscript policy, always needs a signature
+    Miniscript(Miniscript<XOnlyPublicKey, Tap>),
+    And(Box<LeafPolicy>, Box<LeafPolicy>),
+    Or(Box<LeafPolicy>, Box<LeafPolicy>),
+}
+
+// witness bytes of a schnorr signature with its length prefix, a sighash byte included like miniscript counts it
+const KEY_SATISFACTION_SIZE: usize = 1 + 65;
+
+// What the pool itself can satisfy a leaf with: the branches that need no signature
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CovenantPath {
+    // witness items before the script and control block, bottom of the stack first
+    pub stack: Vec<Vec<u8>>,
+    // relative timelock in blocks the spending input has to carry
+    pub older: Option<u16>,
+    // templates the path checks the spend against
+    pub templates: Vec<[u8; 32]>,
+}
+
+impl LeafPolicy {
+    pub fn ctv(template_hash: [u8; 32]) -> Self {
+        Self::Ctv(template_hash)
+    }
+
+    pub fn older(blocks: u16) -> Self {
+        Self::Older(blocks)
+    }
+
+    pub fn key(key: XOnlyPublicKey) -> Self {
+        Self::Key(key)
+    }
+
+    // Compile a miniscript policy (e.g. "and(pk(A),older(1000))", keys as x-only hex) to tapscript.
+    // Refused unless every way to satisfy it needs a signature and none can be malleated, a branch
+    // anyone can take would let anyone move the node.
+    pub fn miniscript(policy: &str) -> Result<Self> {
+        let concrete = Concrete::<XOnlyPublicKey>::from_str(policy)
+            .map_err(|e| anyhow!("invalid miniscript policy {}: {}", policy, e))?;
+        let miniscript = concrete.compile::<Tap>().map_err(|e| {
+            anyhow!(
+                "miniscript policy {} doesn't compile to tapscript: {}",
+                policy,
+                e
+            )
+        })?;
+        miniscript
+            .sanity_check()
+            .map_err(|e| anyhow!("miniscript policy {} is not safe to use: {}", policy, e))?;
+        Ok(Self::Miniscript(miniscript))
+    }
+
+    pub fn and(self, other: LeafPolicy) -> Self {
+        Self::And(Box::new(self), Box::new(other))
+    }
+
+    pub fn or(self, other: LeafPolicy) -> Self {
+        Self::Or(Box::new(self), Box::new(other))
+    }
+
+    pub fn and_older(self, blocks: u16) -> Self {
+        self.and(Self::older(blocks))
+    }
+
+    pub fn and_key(self, key: XOnlyPublicKey) -> Self {
+        self.and(Self::key(key))
+    }
+
+    pub fn or_key(self, key: XOnlyPublicKey) -> Self {
+        self.or(Self::key(key))
+    }
+
+    pub fn or_miniscript(self, policy: &str) -> Result<Self> {
+        Ok(self.or(Self::miniscript(policy)?))
+    }
+
+    // the tapscript, minimally encoded so the spend code rebuilds the exact leaf
+    pub fn script(&self) -> ScriptBuf {
+        self.push(Builder::new(), false).into_script()
+    }
+
+    // `verify` leaves nothing on the stack (or fails), otherwise one true element is left
+    fn push(&self, builder: Builder, verify: bool) -> Builder {
+        match self {
+            Self::Ctv(template_hash) => {
+                let builder = builder
+                    .push_slice(template_hash)
+                    .push_opcode(OP_SECURETHEBAG);
+                if verify {
+                    builder.push_opcode(OP_DROP)
+                } else {
+                    builder
+                }
+            }
+            Self::Older(blocks) => {
+                let builder = builder.push_int((*blocks).into()).push_opcode(OP_CSV);
+                if verify {
+                    builder.push_opcode(OP_DROP)
+                } else {
+                    builder
+                }
+            }
+            Self::Key(key) => builder.push_x_only_key(key).push_opcode(if verify {
+                OP_CHECKSIGVERIFY
+            } else {
+                OP_CHECKSIG
+            }),
+            Self::Miniscript(miniscript) => {
+                let mut script = builder.into_script().into_bytes();
+                script.extend(miniscript.encode().as_bytes());
+                let builder = Builder::from(script);
+                if verify {
+                    builder.push_opcode(OP_VERIFY)
+                } else {
+                    builder
+                }
+            }
+            Self::And(first, second) => second.push(first.push(builder, true), verify),
+            Self::Or(first, second) => {
+                let builder = second
+                    .push(
+                        first
+                            .push(builder.push_opcode(OP_IF), false)
+                            .push_opcode(OP_ELSE),
+                        false,
+                    )
+                    .push_opcode(OP_ENDIF);
+                if verify {
+                    builder.push_opcode(OP_VERIFY)
+                } else {
+                    builder
+                }
+            }
+        }
+    }
+
+    // How the pool spends the leaf without any key, none if every branch needs a signature.
+    // An `or` takes the first side that checks a template.
+    pub fn covenant_path(&self) -> Option<CovenantPath> {
+        match self {
+            Self::Ctv(template_hash) => Some(CovenantPath {
+                templates: vec![*template_hash],
+                ..Default::default()
+            }),
+            Self::Older(blocks) => Some(CovenantPath {
+                older: Some(*blocks),
+                ..Default::default()
+            }),
+            Self::Key(_) | Self::Miniscript(_) => None,
+            Self::And(first, second) => {
+                let first = first.covenant_path()?;
+                let second = second.covenant_path()?;
+                // the first part runs first, its items go on top
+                let mut stack = second.stack;
+                stack.extend(first.stack);
+                let mut templates = first.templates;
+                templates.extend(second.templates);
+                Some(CovenantPath {
+                    stack,
+                    older: first.older.max(second.older),
+                    templates,
+                })
+            }
+            Self::Or(first, second) => {
+                let (mut path, selector) = match (first.covenant_path(), second.covenant_path()) {
+                    (Some(first), Some(second))
+                        if first.templates.is_empty() && !second.templates.is_empty() =>
+                    {
+                        (second, vec![])
+                    }
+                    (Some(first), _) => (first, vec![1]),
+                    (None, Some(second)) => (second, vec![]),
+                    (None, None) => return None,
+                };
+                // OP_IF takes the selector off the top, tapscript wants it empty or exactly 0x01
+                path.stack.push(selector);
+                Some(path)
+            }
+        }
+    }
+
+    // Most witness bytes any branch can take before the script and control block, length prefixes
+    // included, for fee accounting of the key spends. None if nothing can satisfy it.
+    pub fn max_satisfaction_size(&self) -> Option<usize> {
+        match self {
+            Self::Ctv(_) | Self::Older(_) => Some(0),
+            Self::Key(_) => Some(KEY_SATISFACTION_SIZE),
+            Self::Miniscript(miniscript) => miniscript.max_satisfaction_size().ok(),
+            Self::And(first, second) => {
+                Some(first.max_satisfaction_size()? + second.max_satisfaction_size()?)
+            }
+            // the selector: 0x01 for the IF side, an empty item for the ELSE side
+            Self::Or(first, second) => first
+                .max_satisfaction_size()
+                .map(|size| size + 2)
+                .max(second.max_satisfaction_size().map(|size| size + 1)),
+        }
+    }
+}
+
+// OP_CTV with the leaves built from a policy per template, e.g. CTV or an operator key, CTV after
+// a timelock. `policy` gets each template hash and returns the leaf locking a node to it, so it can
+// differ from leaf to leaf. The pool builder takes it like any other backend, building a node checks
+// that the pool can still spend every leaf on its own, with the sequence its templates commit to.
+//
+// The policy isn't part of the manifest, a pool built with it can only be rebuilt with the same backend.
+pub struct PolicyBackend {
+    commitment: Bip119Ctv,
+    policy: Box<dyn Fn([u8; 32]) -> LeafPolicy + Send + Sync>,
+}
+
+// the manifest's covenant name for these pools
+pub const POLICY_BACKEND_NAME: &str = "bip119-ctv-policy";
+
+impl PolicyBackend {
+    pub fn new(
+        commitment: Bip119Ctv,
+        policy: impl Fn([u8; 32]) -> LeafPolicy + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            commitment,
+            policy: Box::new(policy),
+        }
+    }
+
+    pub fn policy(&self, template_hash: [u8; 32]) -> LeafPolicy {
+        (self.policy)(template_hash)
+    }
+
+    // the largest witness (before the script and control block) any branch of the leaf takes, key
+    // branches included, what a fallback spend of it has to budget fees for
+    pub fn max_witness_size(&self, template_hash: [u8; 32]) -> Option<usize> {
+        self.policy(template_hash).max_satisfaction_size()
+    }
+
+    // the path the pool spends the leaf of `template_hash` with, an error if it can't
+    pub fn covenant_path(
+        &self,
+        template_hash: [u8; 32],
+        sequence: Sequence,
+    ) -> Result<CovenantPath> {
+        let policy = self.policy(template_hash);
+        let path = policy.covenant_path().ok_or_else(|| {
+            anyhow!(
+                "every branch of leaf {} needs a signature, the pool can't spend it",
+                policy.script().to_asm_string()
+            )
+        })?;
+        if !path.templates.contains(&template_hash) {
+            bail!(
+                "leaf {} doesn't check its own template, anyone could spend it",
+                policy.script().to_asm_string()
+            );
+        }
+        if let Some(blocks) = path.older {
+            let satisfied = sequence.to_relative_lock_time().is_some_and(|lock_time| {
+                relative::LockTime::from_height(blocks).is_implied_by(lock_time)
+            });
+            if !satisfied {
+                bail!(
+                    "leaf {} needs a {} block relative timelock but the templates commit to sequence {} (UNWIND_DELAY_BLOCKS)",
+                    policy.script().to_asm_string(),
+                    blocks,
+                    sequence
+                );
+            }
+        }
+        Ok(path)
+    }
+}
+
+impl CovenantBackend for PolicyBackend {
+    fn name(&self) -> &'static str {
+        POLICY_BACKEND_NAME
+    }
+
+    fn template_hash(&self, outputs: &[TxOut], sequence: Sequence) -> [u8; 32] {
+        self.commitment.template_hash(outputs, sequence)
+    }
+
+    fn leaf_script(&self, template_hash: [u8; 32]) -> ScriptBuf {
+        self.policy(template_hash).script()
+    }
+
+    fn witness_items(&self, template_hash: [u8; 32]) -> Vec<usize> {
+        self.policy(template_hash)
+            .covenant_path()
+            .map(|path| path.stack.iter().map(Vec::len).collect())
+            .unwrap_or_default()
+    }
+
+    fn check_leaf(&self, template_hash: [u8; 32], sequence: Sequence) -> Result<()> {
+        self.covenant_path(template_hash, sequence).map(|_| ())
+    }
+
+    fn finalize(&self, mut spend: TemplateSpend) -> Result<Transaction> {
+        let sequence = spend.tx.input[0].sequence;
+        let path = self.covenant_path(spend.template_hash, sequence)?;
+        let script_ver = (self.leaf_script(spend.template_hash), spend.leaf_version);
+        let control_block = spend.spend_info.control_block(&script_ver).ok_or_else(|| {
+            anyhow!(
+                "leaf for template {} not found in pool tree",
+                spend.tx.compute_txid()
+            )
+        })?;
+
+        let input = &mut spend.tx.input[0];
+        for item in path.stack {
+            input.witness.push(item);
+        }
+        input.witness.push(script_ver.0.into_bytes());
+        input.witness.push(control_block.serialize());
+        Ok(spend.tx)
+    }
+}
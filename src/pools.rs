@@ -1,124 +1,236 @@
-use anyhow::Result;
-use std::{collections::HashMap, vec};
+use anyhow::{anyhow, bail, Result};
+use std::{collections::HashMap, env, str::FromStr, vec};
 
 use bitcoin::{
     absolute, consensus::encode::serialize_hex, opcodes::all::OP_RETURN, script::Builder,
-    taproot::TaprootSpendInfo, transaction, Address, Amount, OutPoint, Sequence, Transaction, TxIn,
-    TxOut, Txid,
+    taproot::TaprootSpendInfo, transaction, Address, Amount, OutPoint, ScriptBuf, Sequence,
+    Transaction, TxIn, TxOut, Txid,
 };
-use bitcoincore_rpc::{Client, RpcApi};
+use bitcoincore_rpc::{json::SignRawTransactionInput, RpcApi};
 use itertools::Itertools;
-use tracing::info;
+use tracing::{info, trace};
 
 use crate::{
-    config::{NetworkConfig, DEFAULT_FEE_RATE, FEE_AMOUNT, TX_VERSION},
-    ctv_scripts::{create_pool_address, create_withdraw_ctv_hash, spend_ctv},
+    broadcast::{broadcast, BroadcastKind},
+    config::{NetworkConfig, DUST_AMOUNT},
+    covenant::{CovenantBackend, TemplateSpend},
+    ctv_scripts::{
+        create_close_all_outputs, create_withdraw_ctv_hash, create_withdraw_outputs,
+        TemplateVersion,
+    },
+    error_codes::{coded, ErrorCode},
+    fee_bump::{FeeBumper, PendingSpend},
+    ids::{NodePath, UserIndex},
+    mempool::BroadcastQueue,
+    metrics::METRICS,
+    miner::{wait_for_confirmation, wait_for_maturity},
+    musig::{check_musig_keys, internal_key},
+    payouts::{check_payout_jitter, user_share},
+    progress::TreeProgress,
+    redact,
+    rpc_helper::{check_fee_payer_balance, estimate_fee_rate, fee_for_vsize, AsyncRpc},
+    spill::SpillFile,
+    standardness::check_standard,
+    template_fees::check_template_fees,
+    tree::{estimated_tree_bytes, PoolLevel, PoolNode, PoolTree},
+    user_anchor::has_pool_anchor,
     AMOUNT_PER_USER, POOL_USERS,
 };
 
+// what a node of `users` users holds
+pub fn node_amount(users: usize) -> Result<Amount> {
+    AMOUNT_PER_USER
+        .checked_mul(users.try_into()?)
+        .ok_or_else(|| anyhow!("the amount of a {} user pool overflows", users))
+}
+
+// what the node of `users` holds: every user's share, node_amount unless the pool has payout jitter
+pub fn node_value(users: &NodePath, config: &NetworkConfig) -> Result<Amount> {
+    if config.payout_jitter.is_empty() {
+        return node_amount(users.len());
+    }
+    users
+        .user_indices()
+        .try_fold(Amount::ZERO, |total, user| {
+            total.checked_add(user_share(user, config))
+        })
+        .ok_or_else(|| anyhow!("the amount of the pool of users {} overflows", users))
+}
+
+// What the anchor child pays back to the fee payer from `coin` after `fee`.
+// The change has to stay above dust or the child won't relay.
+pub fn cpfp_change(coin: Amount, fee: Amount) -> Result<Amount> {
+    coin.checked_sub(fee)
+        .filter(|change| *change >= DUST_AMOUNT)
+        .ok_or_else(|| {
+            anyhow!(
+                "a coin of {} can't pay the {} anchor child fee and leave change above dust",
+                coin,
+                fee
+            )
+        })
+}
+
 pub fn create_entry_pool_withdraw_hashes(
     addresses: &[Address],
-    second_pool_addresses: &HashMap<Vec<usize>, TaprootSpendInfo>,
+    second_pool_addresses: &PoolLevel,
     anchor_addr: &Address,
     config: &NetworkConfig,
-    pool_exit_ammount: Amount,
-) -> Vec<[u8; 32]> {
+    backend: &dyn CovenantBackend,
+) -> Result<Vec<[u8; 32]>> {
     info!("Creating entry pool withdraw hashes:");
     info!("  Number of addresses: {}", addresses.len());
-    info!("  Pool exit amount: {}", pool_exit_ammount);
     let mut entry_pool_withdraw_hashes = Vec::new();
 
-    for (i, address) in addresses.iter().enumerate() {
-        let users: Vec<_> = (0..POOL_USERS).filter(|&x| x != i).collect();
-        info!("  Processing user {} withdraw hash:", i);
-        info!("    Address: {}", address);
-        info!("    Remaining users: {:?}", users);
-
-        let key = users.clone();
-        let triple_spend_info = &second_pool_addresses[&key];
-        let addr = Address::p2tr_tweaked(triple_spend_info.output_key(), config.network);
-        info!("    Next pool address: {}", addr);
-        
-        let ctv_hash = create_withdraw_ctv_hash(&addr, address, anchor_addr, pool_exit_ammount);
+    for (user, address) in UserIndex::all().zip(addresses) {
+        let users = NodePath::root().without(user)?;
+        info!("  Processing user {} withdraw hash:", user);
+        info!("    Address: {}", redact::short(address));
+        trace!("    Address: {}", address);
+        info!("    Remaining users: {}", users);
+
+        let addr = second_pool_addresses
+            .get(&users)
+            .ok_or_else(|| anyhow!("no pool for users {}", users))?
+            .address(config);
+        info!("    Next pool address: {}", redact::short(&addr));
+        trace!("    Next pool address: {}", addr);
+
+        let pool_exit_amount = node_value(&users, config)?;
+        info!("    Pool exit amount: {}", pool_exit_amount);
+
+        let ctv_hash = create_withdraw_ctv_hash(
+            &NodePath::root(),
+            &addr,
+            address,
+            anchor_addr,
+            pool_exit_amount,
+            user_share(user, config),
+            config,
+            backend,
+        )?;
         entry_pool_withdraw_hashes.push(ctv_hash);
     }
 
-    entry_pool_withdraw_hashes
+    Ok(entry_pool_withdraw_hashes)
 }
 
 pub fn create_exit_pool(
     addresses: &[Address],
     anchor_addr: &Address,
-) -> Result<HashMap<Vec<usize>, TaprootSpendInfo>> {
-    let exit_pool: Result<HashMap<Vec<usize>, TaprootSpendInfo>> = (0..POOL_USERS)
+    config: &NetworkConfig,
+    backend: &dyn CovenantBackend,
+    progress: &mut TreeProgress,
+) -> Result<PoolLevel> {
+    progress.start_level(2);
+    let exit_pool: Result<PoolLevel> = (0..POOL_USERS)
         .combinations(2)
-        .map(|mut combo| {
-            combo.sort();
+        .map(|combo| {
             let i = combo[0];
             let j = combo[1];
+            let users = NodePath::new(combo)?;
 
             let ctv_hash = create_withdraw_ctv_hash(
+                &users,
                 &addresses[i],
                 &addresses[j],
                 anchor_addr,
-                AMOUNT_PER_USER,
-            );
+                user_share(UserIndex::new(i)?, config),
+                user_share(UserIndex::new(j)?, config),
+                config,
+                backend,
+            )?;
             // Create the Taproot tree with all the CTV hashes and leaves
-            let spend_info = create_pool_address(vec![ctv_hash])?;
-            info!("  Created TaprootSpendInfo for users {:?}:", combo);
-            info!("    Output key: {}", spend_info.output_key());
-            info!("    Merkle root: {:?}", spend_info.merkle_root());
-            Ok((combo, spend_info))
+            let node = PoolNode::new(
+                users.clone(),
+                vec![ctv_hash],
+                None,
+                internal_key(&users, addresses, config)?,
+                config,
+                backend,
+                anchor_addr,
+            )?;
+            info!("  Created TaprootSpendInfo for users {}:", users);
+            info!(
+                "    Output key: {}",
+                redact::short(node.spend_info.output_key())
+            );
+            trace!("    Output key: {}", node.spend_info.output_key());
+            info!("    Merkle root: {:?}", node.spend_info.merkle_root());
+            progress.node_done(1);
+            Ok((users, node))
         })
         .collect();
+    progress.finish_level();
 
     exit_pool
 }
 
 pub fn create_pool(
-    target_pool: &HashMap<Vec<usize>, TaprootSpendInfo>,
+    target_pool: &PoolLevel,
     pool_size: usize,
     addresses: &[Address],
     anchor_addr: &Address,
     config: &NetworkConfig,
-) -> HashMap<Vec<usize>, TaprootSpendInfo> {
-    let mut new_pool: HashMap<Vec<usize>, TaprootSpendInfo> = HashMap::new();
+    backend: &dyn CovenantBackend,
+    progress: &mut TreeProgress,
+) -> Result<PoolLevel> {
+    let mut new_pool: PoolLevel = HashMap::new();
 
     let num_users = addresses.len();
     info!("Creating addresses for {} user pool \n", pool_size);
+    progress.start_level(pool_size);
 
     //iterate over all possible spending combinations of users in the pool
     for users in (0..num_users).combinations(pool_size) {
+        let users = NodePath::new(users)?;
         let mut ctv_hashes = Vec::new();
 
-        for &user in &users {
-            let remaining_users: Vec<_> = users.iter().copied().filter(|&u| u != user).collect();
-            let spend_info = &target_pool[&remaining_users];
-
-            let withdrawal_address = Address::p2tr_tweaked(spend_info.output_key(), config.network);
+        for user in users.user_indices() {
+            let remaining_users = users.without(user)?;
+            let withdrawal_address = target_pool
+                .get(&remaining_users)
+                .ok_or_else(|| anyhow!("no pool for users {}", remaining_users))?
+                .address(config);
             let ctv_hash = create_withdraw_ctv_hash(
+                &users,
                 &withdrawal_address,
-                &addresses[user],
+                &addresses[user.index()],
                 anchor_addr,
-                (AMOUNT_PER_USER) * remaining_users.len().try_into().unwrap(),
-            );
+                node_value(&remaining_users, config)?,
+                user_share(user, config),
+                config,
+                backend,
+            )?;
 
             ctv_hashes.push(ctv_hash);
         }
 
-        let spend_info = create_pool_address(ctv_hashes).unwrap();
-        new_pool.insert(users, spend_info);
+        let node = PoolNode::new(
+            users.clone(),
+            ctv_hashes,
+            None,
+            internal_key(&users, addresses, config)?,
+            config,
+            backend,
+            anchor_addr,
+        )?;
+        progress.node_done(users.len());
+        new_pool.insert(users, node);
     }
+    progress.finish_level();
 
-    new_pool
+    Ok(new_pool)
 }
 
 pub fn create_all_pools(
     addresses: &[Address],
     anchor_addr: &Address,
     config: &NetworkConfig,
-    pools: &mut Vec<HashMap<Vec<usize>, TaprootSpendInfo>>,
-) {
+    backend: &dyn CovenantBackend,
+    pools: &mut PoolTree,
+    progress: &mut TreeProgress,
+) -> Result<()> {
     for pool_num in (1..=POOL_USERS).rev() {
         let users_in_pool = POOL_USERS - pool_num;
 
@@ -126,277 +238,687 @@ pub fn create_all_pools(
             continue;
         }
 
-        let previous_pool = pools.last().unwrap();
+        let previous_pool = pools.top()?;
 
-        let new_pool = create_pool(previous_pool, users_in_pool, addresses, anchor_addr, config);
+        let new_pool = create_pool(
+            previous_pool,
+            users_in_pool,
+            addresses,
+            anchor_addr,
+            config,
+            backend,
+            progress,
+        )?;
 
-        pools.push(new_pool);
+        pools.push_level(new_pool)?;
     }
+
+    Ok(())
 }
 
-#[allow(clippy::too_many_arguments)]
-pub fn send_from_pool(
-    pools: &[HashMap<Vec<usize>, TaprootSpendInfo>],
+// Every pool from the exit pool (2 users) up to the entry pool, which is the last one and keyed by NodePath::root().
+// Only depends on the addresses, the config and the backend, so the same inputs always give the same tree.
+// the whole tree, built the way the pool's template version builds it
+pub fn build_pools(
+    addresses: &[Address],
+    anchor_addr: &Address,
     config: &NetworkConfig,
-    pool_num: usize,
-    pool_combo: Vec<usize>,
-    withdraw_address: Address,
+    backend: &dyn CovenantBackend,
+) -> Result<PoolTree> {
+    match config.template_version {
+        TemplateVersion::V1 => build_pools_v1(addresses, anchor_addr, config, backend),
+    }
+}
+
+fn build_pools_v1(
+    addresses: &[Address],
     anchor_addr: &Address,
-    pool_exit_ammount: Amount,
-    previous_txid: Txid,
-    previous_pool_combo: Vec<usize>,
-    vout: u32,
-) -> String {
+    config: &NetworkConfig,
+    backend: &dyn CovenantBackend,
+) -> Result<PoolTree> {
+    // the amounts, fees and anchors every template is built from
+    config.validate()?;
+    // every share goes into the node amounts, a bad one would make templates pay dust or more than the node has
+    check_payout_jitter(addresses, config)?;
+    // the root's key path needs every member's key
+    check_musig_keys(
+        &config.musig_keys,
+        &addresses.iter().map(Address::to_string).collect::<Vec<_>>(),
+    )?;
+
+    ////////////////////////////////////////////////////////////////////////////
+    /////////////////////////////CREATE LAST POOL //////////////////////////////
+    ////////////////////////////////////////////////////////////////////////////
+    let mut pools = match config.tree_memory_budget {
+        Some(budget) if estimated_tree_bytes(POOL_USERS) > budget => {
+            let spill = SpillFile::create(&env::temp_dir())?;
+            info!(
+                "the tree of {} users is estimated at {} MB, over the {} MB memory budget, levels built on go to {}",
+                POOL_USERS,
+                estimated_tree_bytes(POOL_USERS).div_ceil(1024 * 1024),
+                budget / (1024 * 1024),
+                spill.path().display()
+            );
+            PoolTree::spilling(spill)
+        }
+        _ => PoolTree::default(),
+    };
+    let mut progress = TreeProgress::start(config.progress);
+    //The last pool will always be the same, regardless of how many users are in the pool (it will allow 2 users to withdraw)
+    let exit_pool_leaves =
+        create_exit_pool(addresses, anchor_addr, config, backend, &mut progress)?;
+    // the taproot spend info for the last pool is the leaves of the CTV tree
+    pools.push_level(exit_pool_leaves)?;
+
+    /////////////////////////////////////////////////////////////////////////////
+    /////////////////////////////CREATE ALL OTHER POOLS//////////////////////////
+    ////////////////////////////////////////////////////////////////////////////
+    // pop from the front (the leaves are the first in the vec) and create a node and add it to the back of the vec
+    create_all_pools(
+        addresses,
+        anchor_addr,
+        config,
+        backend,
+        &mut pools,
+        &mut progress,
+    )?;
+
     info!(
-        "init withdrawal from pool {}, combo: {:?} \n",
-        pool_num, pool_combo
+        "total taproot addresses across all pools: {} for {} users \n",
+        pools.len(),
+        POOL_USERS
     );
-    let withdraw_hash = create_withdraw_ctv_hash(
-        &Address::p2tr_tweaked(pools[pool_num][&pool_combo].output_key(), config.network),
-        &withdraw_address,
+
+    ////////////////////////////////////////////////////////////////////////////
+    //////////////////////CREATE FIRST POOL/////////////////////////////////////
+    ////////////////////////////////////////////////////////////////////////////
+    progress.start_level(POOL_USERS);
+    let pool_0 =
+        create_entry_pool_withdraw_hashes(addresses, pools.top()?, anchor_addr, config, backend)?;
+    // the optional leaf closing the pool for everyone in one tx
+    let close_all = if config.close_all_leaf {
+        Some(backend.template_hash(
+            &create_close_all_outputs(addresses, anchor_addr, config)?,
+            config.unwind_sequence(&NodePath::root()),
+        ))
+    } else {
+        None
+    };
+    let pool_0_node = PoolNode::new(
+        NodePath::root(),
+        pool_0,
+        close_all,
+        internal_key(&NodePath::root(), addresses, config)?,
+        config,
+        backend,
         anchor_addr,
-        pool_exit_ammount,
-    );
+    )?;
+    let mut pool_0_map = HashMap::new();
+    progress.node_done(POOL_USERS);
+    progress.finish_level();
+    progress.finish();
+    pool_0_map.insert(NodePath::root(), pool_0_node);
+    pools.push_level(pool_0_map)?;
+    // we have the root of the CTV tree
+
+    // CTV locks every fee in, a template relay policy strands can't be fixed once the pool is funded
+    check_template_fees(&pools, config, backend, addresses, anchor_addr)?;
+
+    Ok(pools)
+}
 
-    let tx_out = [
-        TxOut {
-            value: pool_exit_ammount,
-            script_pubkey: Address::p2tr_tweaked(
-                pools[pool_num][&pool_combo].output_key(),
-                config.network,
-            )
-            .script_pubkey(),
-        },
-        TxOut {
-            value: AMOUNT_PER_USER - FEE_AMOUNT,
-            script_pubkey: withdraw_address.script_pubkey(),
-        },
-        #[cfg(feature = "regtest")]
-        TxOut {
-            value: FEE_AMOUNT,
-            script_pubkey: anchor_addr.script_pubkey(),
-        },
-    ];
+// One leaf of a pool node: `spender` leaves the node of `users` and the rest moves to the next pool.
+// In the exit pool (2 users) there is only one leaf, paying both.
+#[derive(Debug, Clone)]
+pub struct NodeExit {
+    pub users: NodePath,
+    pub spend_info: TaprootSpendInfo,
+    pub outputs: Vec<TxOut>,
+    // nSequence the spending input must have
+    pub sequence: Sequence,
+    pub template_hash: [u8; 32],
+}
 
-    let inputs = vec![TxIn {
-        previous_output: OutPoint {
-            txid: previous_txid,
-            vout,
-        },
-        sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
-        ..Default::default()
-    }];
+impl NodeExit {
+    // the leaf's template spending the node output at `outpoint`
+    pub fn template_spend(
+        self,
+        outpoint: OutPoint,
+        prevout: TxOut,
+        config: &NetworkConfig,
+    ) -> TemplateSpend {
+        let input = TxIn {
+            previous_output: outpoint,
+            // has to match the sequence the template committed to
+            sequence: self.sequence,
+            ..Default::default()
+        };
 
-    let unsigned_tx = Transaction {
-        version: transaction::Version(TX_VERSION),
-        lock_time: absolute::LockTime::ZERO,
-        input: inputs,
-        output: tx_out.to_vec(),
-    };
+        TemplateSpend {
+            tx: Transaction {
+                version: transaction::Version(config.tx_version),
+                lock_time: absolute::LockTime::ZERO,
+                input: vec![input],
+                output: self.outputs,
+            },
+            prevout,
+            spend_info: self.spend_info,
+            template_hash: self.template_hash,
+            leaf_version: config.leaf_version,
+        }
+    }
+}
 
-    let parent_tx = spend_ctv(
-        unsigned_tx,
-        pools[pool_num + 1][&previous_pool_combo].clone(),
-        withdraw_hash,
-    );
+pub fn node_exit(
+    pools: &PoolTree,
+    config: &NetworkConfig,
+    backend: &dyn CovenantBackend,
+    addresses: &[Address],
+    anchor_addr: &Address,
+    users: &NodePath,
+    spender: UserIndex,
+) -> Result<NodeExit> {
+    if !users.contains(spender) {
+        bail!("user {} can not leave the pool of users {}", spender, users);
+    }
 
-    let parent_serialized_tx = serialize_hex(&parent_tx);
-    info!(
-        "withdrawal from pool {}, parent tx: {} \n",
-        pool_num, parent_serialized_tx
-    );
-    parent_serialized_tx
+    let node = pools.node(users)?;
+
+    //the user who waits to leave last gets some extra sats!
+    let outputs = if users.is_exit() {
+        create_withdraw_outputs(
+            users,
+            &addresses[users.users()[0]],
+            &addresses[users.users()[1]],
+            anchor_addr,
+            user_share(UserIndex::new(users.users()[0])?, config),
+            user_share(UserIndex::new(users.users()[1])?, config),
+            config,
+        )
+    } else {
+        let remaining_users = users.without(spender)?;
+        let next_pool = pools.node(&remaining_users)?.address(config);
+        create_withdraw_outputs(
+            users,
+            &next_pool,
+            &addresses[spender.index()],
+            anchor_addr,
+            node_value(&remaining_users, config)?,
+            user_share(spender, config),
+            config,
+        )
+    }?;
+    let sequence = config.unwind_sequence(users);
+    let template_hash = backend.template_hash(&outputs, sequence);
+    if template_hash != node.template(spender)? {
+        bail!(
+            "the template of user {} leaving the pool of users {} doesn't match the tree",
+            spender,
+            users
+        );
+    }
+
+    Ok(NodeExit {
+        users: users.clone(),
+        spend_info: node.spend_info.clone(),
+        outputs,
+        sequence,
+        template_hash,
+    })
 }
 
-#[allow(clippy::too_many_arguments)]
-pub fn process_pool_spend(
-    pools: &[HashMap<Vec<usize>, TaprootSpendInfo>],
+// Build the (unsigned) template that lets `spender_index` leave the pool, spending the pool output of `previous_tx`.
+// No RPC needed, so the whole unwind can be computed before anything is broadcast.
+pub fn pool_spend_template(
+    pools: &PoolTree,
     config: &NetworkConfig,
-    rpc: &Client,
-    spender_index: usize,
+    backend: &dyn CovenantBackend,
+    spender_index: UserIndex,
     addresses: &[Address],
-    previous_txid: Txid,
+    previous_tx: &Transaction,
     anchor_addr: &Address,
-    mining_address: &Address,
-) -> Result<Txid> {
+) -> Result<TemplateSpend> {
+    let previous_txid = previous_tx.compute_txid();
     info!("Processing pool spend for user {}:", spender_index);
     info!("  Previous transaction ID: {}", previous_txid);
-    
-    let pool_amount = (AMOUNT_PER_USER) * (POOL_USERS - spender_index).try_into()?;
-    info!("  Pool amount: {}", pool_amount);
 
-    let previous_tx: Transaction = rpc.get_raw_transaction(&previous_txid, None).unwrap();
-    let vout = previous_tx
-        .output
-        .iter()
-        .position(|vout| vout.value == pool_amount)
-        .unwrap() as u32;
-    info!("  Vout for pool amount: {}", vout);
+    let users = config.unwind_node(spender_index)?;
+    let (outpoint, prevout) = node_prevout(pools, &users, previous_tx)?;
+    info!("  Pool amount: {}", prevout.value);
+    info!("  Vout for pool amount: {}", outpoint.vout);
 
-    //create final exit tx for last two users
-    if spender_index == POOL_USERS - 2 {
+    info!("  Pool users: {}", users);
+    if users.is_exit() {
         info!("Processing final exit transaction for last two users");
-        let last_index = addresses.len() - 1;
-        let second_last_index = last_index - 1;
-        info!("  Second last user index: {}", second_last_index);
-        info!("  Last user index: {}", last_index);
-
-        let last_pool_withdraw_hash = create_withdraw_ctv_hash(
-            &addresses[second_last_index],
-            &addresses[last_index],
-            anchor_addr,
-            AMOUNT_PER_USER,
-        );
+    }
 
-        let last_pool_tx_out = [
-            TxOut {
-                //the user who waits to leave last gets some extra sats!
-                value: AMOUNT_PER_USER,
-                script_pubkey: addresses[second_last_index].script_pubkey(),
-            },
-            TxOut {
-                value: AMOUNT_PER_USER - FEE_AMOUNT,
-                script_pubkey: addresses[last_index].script_pubkey(),
-            },
-            #[cfg(feature = "regtest")]
-            TxOut {
-                value: FEE_AMOUNT,
-                script_pubkey: anchor_addr.script_pubkey(),
-            },
-        ];
+    let exit = node_exit(
+        pools,
+        config,
+        backend,
+        addresses,
+        anchor_addr,
+        &users,
+        spender_index,
+    )?;
 
-        let inputs = vec![TxIn {
-            previous_output: OutPoint {
-                txid: previous_txid,
-                vout,
-            },
-            sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
-            ..Default::default()
-        }];
+    Ok(exit.template_spend(outpoint, prevout, config))
+}
 
-        let unsigned_tx = Transaction {
-            version: transaction::Version(TX_VERSION),
-            lock_time: absolute::LockTime::ZERO,
-            input: inputs,
-            output: last_pool_tx_out.to_vec(),
-        };
+// The output of `previous_tx` paying the node of `users`: by script so a change output of the same
+// value can't be picked, and by value so an output to the node's address with another amount isn't.
+// Of two outputs alike the first is taken, the templates don't commit to the outpoint so either spends.
+fn node_prevout(
+    pools: &PoolTree,
+    users: &NodePath,
+    previous_tx: &Transaction,
+) -> Result<(OutPoint, TxOut)> {
+    let previous_txid = previous_tx.compute_txid();
+    let node = pools.node(users)?;
+    let node_spk = ScriptBuf::new_p2tr_tweaked(node.spend_info.output_key());
+    let mut paying = previous_tx
+        .output
+        .iter()
+        .enumerate()
+        .filter(|(_, output)| output.script_pubkey == node_spk)
+        .peekable();
+    let Some(&(_, first)) = paying.peek() else {
+        bail!("{} does not pay the pool of users {}", previous_txid, users);
+    };
+    let first = first.value;
+    let (vout, prevout) = paying
+        .find(|(_, output)| output.value == node.amount)
+        .ok_or_else(|| {
+            anyhow!(
+                "{} pays {} to the pool of users {}, the templates need {}",
+                previous_txid,
+                first,
+                users,
+                node.amount
+            )
+        })?;
+    Ok((OutPoint::new(previous_txid, vout as u32), prevout.clone()))
+}
 
-        let final_tx = spend_ctv(
-            unsigned_tx,
-            pools[0][&vec![second_last_index, last_index]].clone(),
-            last_pool_withdraw_hash,
+// The close-all leaf of the root: every user paid in one tx instead of walking every level
+pub fn close_all_exit(
+    pools: &PoolTree,
+    config: &NetworkConfig,
+    backend: &dyn CovenantBackend,
+    addresses: &[Address],
+    anchor_addr: &Address,
+) -> Result<NodeExit> {
+    if !config.pool_root()?.is_root() {
+        return Err(coded(
+            ErrorCode::InvalidState,
+            "the close-all leaf is on the root, a pool short of removed participants is funded below it",
+        ));
+    }
+    let root = pools.root()?;
+    let Some(close_all) = root.close_all else {
+        bail!(
+            "this pool has no close-all leaf, it has to be created with POOL_CLOSE_ALL_LEAF=true"
         );
+    };
+    let outputs = create_close_all_outputs(addresses, anchor_addr, config)?;
+    let sequence = config.unwind_sequence(&NodePath::root());
+    let template_hash = backend.template_hash(&outputs, sequence);
+    if template_hash != close_all {
+        return Err(coded(
+            ErrorCode::TemplateMismatch,
+            "the close-all template doesn't match the tree",
+        ));
+    }
 
-        let serialized_tx = serialize_hex(&final_tx);
-        info!("Final exit tx: {} \n", serialized_tx);
-        let txid = rpc.send_raw_transaction(serialized_tx)?;
-        info!("Final exit txid: {} \n", txid);
-
-        #[cfg(feature = "regtest")]
-        let _ = rpc.generate_to_address(1, mining_address);
-
-        #[cfg(feature = "regtest")]
-        cpfp_tx(rpc, txid);
+    Ok(NodeExit {
+        users: NodePath::root(),
+        spend_info: root.spend_info.clone(),
+        outputs,
+        sequence,
+        template_hash,
+    })
+}
 
-        #[cfg(feature = "regtest")]
-        let _ = rpc.generate_to_address(1, mining_address);
+// the (unsigned) close-all spend of the root output of `funding_tx`
+pub fn close_all_template(
+    pools: &PoolTree,
+    config: &NetworkConfig,
+    backend: &dyn CovenantBackend,
+    addresses: &[Address],
+    funding_tx: &Transaction,
+    anchor_addr: &Address,
+) -> Result<TemplateSpend> {
+    let (outpoint, prevout) = node_prevout(pools, &NodePath::root(), funding_tx)?;
+    let exit = close_all_exit(pools, config, backend, addresses, anchor_addr)?;
+    Ok(exit.template_spend(outpoint, prevout, config))
+}
 
-        return Ok(txid);
+#[allow(clippy::too_many_arguments)]
+pub async fn process_pool_spend(
+    pools: &PoolTree,
+    config: &NetworkConfig,
+    rpc: &AsyncRpc,
+    fee_payer: &AsyncRpc,
+    queue: &mut BroadcastQueue,
+    backend: &dyn CovenantBackend,
+    spender_index: UserIndex,
+    addresses: &[Address],
+    previous_txid: Txid,
+    anchor_addr: &Address,
+) -> Result<Txid> {
+    let previous_tx: Transaction = rpc
+        .run(move |c| c.get_raw_transaction(&previous_txid, None))
+        .await?;
+    // the spend is not final until the pool output is old enough
+    if let Some(delay) = config.unwind_delay_for(&config.unwind_node(spender_index)?) {
+        wait_for_maturity(rpc, config, previous_txid, delay).await?;
     }
 
-    let pool_exit_amount = (AMOUNT_PER_USER) * (POOL_USERS - spender_index - 1).try_into()?;
-    info!("  Pool exit amount: {}", pool_exit_amount);
+    let template = pool_spend_template(
+        pools,
+        config,
+        backend,
+        spender_index,
+        addresses,
+        &previous_tx,
+        anchor_addr,
+    )?;
+    send_template(
+        config,
+        rpc,
+        fee_payer,
+        queue,
+        backend,
+        template,
+        &format!("user {}", spender_index),
+    )
+    .await
+}
 
-    let recipient_pool: Vec<usize> = ((spender_index + 1)..POOL_USERS).collect();
-    info!("  Recipient pool users: {:?}", recipient_pool);
+// Close the pool for every user at once through the root's close-all leaf
+#[allow(clippy::too_many_arguments)]
+pub async fn process_close_all(
+    pools: &PoolTree,
+    config: &NetworkConfig,
+    rpc: &AsyncRpc,
+    fee_payer: &AsyncRpc,
+    queue: &mut BroadcastQueue,
+    backend: &dyn CovenantBackend,
+    addresses: &[Address],
+    funding_txid: Txid,
+    anchor_addr: &Address,
+) -> Result<Txid> {
+    let funding_tx: Transaction = rpc
+        .run(move |c| c.get_raw_transaction(&funding_txid, None))
+        .await?;
+    if let Some(delay) = config.unwind_delay_for(&NodePath::root()) {
+        wait_for_maturity(rpc, config, funding_txid, delay).await?;
+    }
 
-    let previous_pool: Vec<usize> = if spender_index == 0 {
-        vec![(0)]
-    } else {
-        (spender_index..POOL_USERS).collect()
-    };
-    info!("  Previous pool users: {:?}", previous_pool);
+    let template = close_all_template(pools, config, backend, addresses, &funding_tx, anchor_addr)?;
+    send_template(
+        config,
+        rpc,
+        fee_payer,
+        queue,
+        backend,
+        template,
+        "every user",
+    )
+    .await
+}
 
-    let pool_num = pools.len() - 2 - spender_index;
-    info!("  Pool number: {}", pool_num);
+// finalize, lint and broadcast a pool spend (once the mempool has room for it), then pay for it
+// through its anchor
+async fn send_template(
+    config: &NetworkConfig,
+    rpc: &AsyncRpc,
+    fee_payer: &AsyncRpc,
+    queue: &mut BroadcastQueue,
+    backend: &dyn CovenantBackend,
+    template: TemplateSpend,
+    spender: &str,
+) -> Result<Txid> {
+    let prevout = template.prevout.clone();
+    let spend_tx = backend.finalize(template)?;
+    check_standard(rpc, &spend_tx, std::slice::from_ref(&prevout)).await?;
 
-    let withdraw_parent_serialized_tx = send_from_pool(
-        pools,
-        config,
-        pool_num,
-        recipient_pool,
-        addresses[spender_index].clone(),
-        anchor_addr,
-        pool_exit_amount,
-        previous_txid,
-        previous_pool,
-        vout,
+    let serialized_tx = serialize_hex(&spend_tx);
+    info!(
+        "withdrawal for {}, parent tx: {} \n",
+        spender,
+        redact::hex(&serialized_tx)
     );
+    trace!("withdrawal for {}, parent tx: {}", spender, serialized_tx);
 
-    let withdraw_parent_txid = rpc.send_raw_transaction(withdraw_parent_serialized_tx)?;
-    info!("{} parent txid: {} \n", spender_index, withdraw_parent_txid);
+    let withdraw_parent_txid = queue
+        .send(rpc, spend_tx.clone(), BroadcastKind::PoolSpend)
+        .await?;
+    info!("{} parent txid: {} \n", spender, withdraw_parent_txid);
 
     //use p2a for fee management on regtest (i was having trouble with v3 transactions propagating on signet)
-    #[cfg(feature = "regtest")]
-    let _ = rpc.generate_to_address(1, mining_address);
+    //v3 only allows one unconfirmed child, so the next withdrawal has to wait for this one to confirm
+    // an anchor keyed to the leaving user is theirs to bump (pool-member bump)
+    let user_anchor = config.anchor_amount.is_some() && !has_pool_anchor(&spend_tx, config);
+    if user_anchor {
+        info!(
+            "{} parent pays the anchor key {} registered, they bump it \n",
+            withdraw_parent_txid, spender
+        );
+    }
+    if config.is_regtest() {
+        wait_for_confirmation(rpc, config, withdraw_parent_txid).await?;
+        if !user_anchor {
+            let child_txid = cpfp_tx(fee_payer, config, withdraw_parent_txid).await?;
+            // the child counts against the parent's descendants
+            queue.track(child_txid, &[withdraw_parent_txid], 0);
+        }
+    } else if let (Some(policy), Some(_), false) =
+        (&config.fee_bump, config.anchor_amount, user_anchor)
+    {
+        // the spend pays its committed fee, the anchor only once it gets stuck
+        let height = rpc.run(|c| c.get_block_count()).await?;
+        let mut bumper = FeeBumper::new(policy.clone());
+        bumper.track(PendingSpend::new(&spend_tx, &[prevout], height, spender));
+        bumper
+            .wait(rpc, fee_payer, config, withdraw_parent_txid)
+            .await?;
+    }
 
-    #[cfg(feature = "regtest")]
-    cpfp_tx(rpc, withdraw_parent_txid);
+    Ok(withdraw_parent_txid)
+}
 
-    #[cfg(feature = "regtest")]
-    let _ = rpc.generate_to_address(1, mining_address);
+// Presign every template of the unwind the demo walks (users leave in address order),
+// chaining each template on the txid of the previous one, and the close-all spend if the pool has
+// one, then delete the key.
+pub fn presign_unwind(
+    pools: &PoolTree,
+    config: &NetworkConfig,
+    backend: &mut dyn CovenantBackend,
+    addresses: &[Address],
+    funding_tx: &Transaction,
+    anchor_addr: &Address,
+) -> Result<()> {
+    let mut previous_tx = funding_tx.clone();
+    for i in config.spenders()? {
+        let template = pool_spend_template(
+            pools,
+            config,
+            &*backend,
+            i,
+            addresses,
+            &previous_tx,
+            anchor_addr,
+        )?;
+        backend.presign(&template)?;
+        previous_tx = template.tx;
+    }
+    // the close-all leaf spends the funding output too
+    if pools.root()?.close_all.is_some() && config.pool_root()?.is_root() {
+        let template =
+            close_all_template(pools, config, &*backend, addresses, funding_tx, anchor_addr)?;
+        backend.presign(&template)?;
+    }
+    backend.seal();
 
-    Ok(withdraw_parent_txid)
+    Ok(())
 }
 
-pub fn cpfp_tx(rpc: &Client, parent_txid: Txid) {
+// estimated size of the anchor child, the fee payer pays this at the estimated fee rate
+pub const CPFP_CHILD_VSIZE: u64 = 68 // SegWit input size
+    + 34 // SegWit output size
+    + 10; // Version, locktime, and input/output count
+
+// the child spends the anchor with a coin from `rpc`'s wallet, which is the fee payer. Returns its txid.
+pub async fn cpfp_tx(rpc: &AsyncRpc, config: &NetworkConfig, parent_txid: Txid) -> Result<Txid> {
     info!("Spending child transaction...");
 
-    let change_address = rpc.get_raw_change_address(None).unwrap();
+    let fee_rate = estimate_fee_rate(rpc, config).await;
+    let total_fee = fee_for_vsize(fee_rate, CPFP_CHILD_VSIZE)?;
+    check_fee_payer_balance(rpc, total_fee).await?;
+
+    let (child_txid, _) = send_anchor_child(rpc, config, parent_txid, total_fee, None).await?;
+    METRICS.add_anchor_sats(total_fee.to_sat());
+
+    Ok(child_txid)
+}
+
+// the fee payer's coin an anchor child spends, a replacement of the child has to spend it again
+#[derive(Debug, Clone)]
+pub struct FeeCoin {
+    pub outpoint: OutPoint,
+    pub txout: TxOut,
+}
+
+// Sign, check and broadcast a child spending the anchor of `parent_txid` and paying `fee`. It is
+// funded by `fee_coin` when it replaces a child spending that coin, otherwise by a single
+// confirmed coin of `rpc`'s wallet. Returns its txid and the coin it spent.
+pub async fn send_anchor_child(
+    rpc: &AsyncRpc,
+    config: &NetworkConfig,
+    parent_txid: Txid,
+    fee: Amount,
+    fee_coin: Option<FeeCoin>,
+) -> Result<(Txid, FeeCoin)> {
+    let change_address = rpc.run(|c| c.get_raw_change_address(None)).await?;
+
+    let fee_coin = match fee_coin {
+        Some(coin) => coin,
+        None => {
+            let unspent = rpc
+                .run(|c| c.list_unspent(Some(1), None, None, None, None))
+                .await?;
+            let utxo = unspent
+                .into_iter()
+                .find(|utxo| cpfp_change(utxo.amount, fee).is_ok())
+                .ok_or_else(|| {
+                    anyhow!(
+                        "no single confirmed coin in the fee payer wallet covers the {} anchor child",
+                        fee
+                    )
+                })?;
+            FeeCoin {
+                outpoint: OutPoint::new(utxo.txid, utxo.vout),
+                txout: TxOut {
+                    value: utxo.amount,
+                    script_pubkey: utxo.script_pub_key,
+                },
+            }
+        }
+    };
+
+    let parent = rpc
+        .run(move |c| c.get_raw_transaction(&parent_txid, None))
+        .await?;
+    let (anchor_vout, anchor) = anchor_output(config, &parent)?;
+    let child_spend = anchor_child(
+        config,
+        OutPoint {
+            txid: parent_txid,
+            vout: anchor_vout,
+        },
+        &fee_coin,
+        fee,
+        change_address.assume_checked().script_pubkey(),
+    )?;
+
+    let child_serialized_tx = serialize_hex(&child_spend);
 
-    let input_size = 68; // SegWit input size
-    let output_size = 34; // SegWit output size
-    let fixed_overhead = 10; // Version, locktime, and input/output count
+    info!("\nchild tx: {}", redact::hex(&child_serialized_tx));
+    trace!("child tx: {}", child_serialized_tx);
 
-    let estimated_tx_size = (input_size) + (output_size) + fixed_overhead;
+    // the coin may be spent by the child this one replaces already, so the wallet is told what it is
+    let fee_input = SignRawTransactionInput {
+        txid: fee_coin.outpoint.txid,
+        vout: fee_coin.outpoint.vout,
+        script_pub_key: fee_coin.txout.script_pubkey.clone(),
+        redeem_script: None,
+        amount: Some(fee_coin.txout.value),
+    };
+    let signed_child_tx = rpc
+        .run(move |c| {
+            c.sign_raw_transaction_with_wallet(child_serialized_tx, Some(&[fee_input]), None)
+        })
+        .await?;
+    check_standard(
+        rpc,
+        &signed_child_tx.transaction()?,
+        &[anchor, fee_coin.txout.clone()],
+    )
+    .await?;
 
-    let fee_rate = rpc
-        .estimate_smart_fee(1, None)
-        .ok()
-        .and_then(|estimate| estimate.fee_rate.map(|rate| rate.to_sat()))
-        .unwrap_or(DEFAULT_FEE_RATE);
+    let child_txid = broadcast(rpc, &signed_child_tx.hex, BroadcastKind::AnchorChild).await?;
 
-    let total_fee = fee_rate * estimated_tx_size / 1000;
+    info!("\nchild txid: {}", child_txid);
 
-    let unspent = rpc.list_unspent(Some(1), None, None, None, None).unwrap();
+    Ok((child_txid, fee_coin))
+}
 
-    let matching_utxo = unspent
-        .into_iter()
-        .find(|utxo| utxo.amount >= Amount::from_sat(total_fee))
-        .unwrap();
+// The pool's fee anchor of `parent` and where it is, after the payouts, which split users stretch
+// over several outputs
+pub fn anchor_output(config: &NetworkConfig, parent: &Transaction) -> Result<(u32, TxOut)> {
+    let anchor_script = Address::from_str(&config.fee_anchor_addr)?
+        .assume_checked()
+        .script_pubkey();
+    parent
+        .output
+        .iter()
+        .enumerate()
+        .find(|(_, output)| output.script_pubkey == anchor_script)
+        .map(|(vout, output)| (vout as u32, output.clone()))
+        .ok_or_else(|| {
+            anyhow!(
+                "{} has no fee anchor output of the pool, a user's keyed anchor is theirs to bump",
+                parent.compute_txid()
+            )
+        })
+}
 
+// The unsigned child spending `anchor` and `fee_coin`, paying `fee` and the rest of the coin to
+// `change_script`
+pub fn anchor_child(
+    config: &NetworkConfig,
+    anchor: OutPoint,
+    fee_coin: &FeeCoin,
+    fee: Amount,
+    change_script: ScriptBuf,
+) -> Result<Transaction> {
     let op_return_script = Builder::new()
         .push_opcode(OP_RETURN)
         .push_slice(b"\xe2\x9a\x93 \xF0\x9F\xA5\xAA \xe2\x9a\x93")
         .into_script();
 
-    let child_spend = Transaction {
-        version: transaction::Version(TX_VERSION),
+    Ok(Transaction {
+        version: transaction::Version(config.tx_version),
         lock_time: absolute::LockTime::ZERO,
         input: vec![
             TxIn {
-                previous_output: OutPoint {
-                    txid: parent_txid,
-                    vout: 1,
-                },
+                previous_output: anchor,
                 sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
                 ..Default::default()
             },
             TxIn {
-                previous_output: OutPoint {
-                    txid: matching_utxo.txid,
-                    vout: matching_utxo.vout,
-                },
+                previous_output: fee_coin.outpoint,
                 sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
                 ..Default::default()
             },
@@ -407,21 +929,9 @@ pub fn cpfp_tx(rpc: &Client, parent_txid: Txid) {
                 script_pubkey: op_return_script,
             },
             TxOut {
-                value: matching_utxo.amount - Amount::from_sat(total_fee),
-                script_pubkey: change_address.assume_checked().script_pubkey(),
+                value: cpfp_change(fee_coin.txout.value, fee)?,
+                script_pubkey: change_script,
             },
         ],
-    };
-
-    let child_serialized_tx = serialize_hex(&child_spend);
-
-    info!("\nchild tx: {}", child_serialized_tx);
-
-    let signed_child_tx = rpc
-        .sign_raw_transaction_with_wallet(child_serialized_tx, None, None)
-        .unwrap();
-
-    let child_txid = rpc.send_raw_transaction(&signed_child_tx.hex).unwrap();
-
-    info!("\nchild txid: {}", child_txid);
+    })
 }
@@ -0,0 +1,386 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashSet},
+    str::FromStr,
+};
+
+use anyhow::{anyhow, Result};
+use bitcoin::{
+    opcodes::all::OP_NOP4, script::Instruction, Address, Amount, ScriptBuf, XOnlyPublicKey,
+};
+use serde::Serialize;
+
+use crate::{
+    config::NetworkConfig,
+    covenant::CovenantBackend,
+    ctv_scripts::NUMS_INTERNAL_KEY,
+    pools::{close_all_exit, node_exit, NodeExit},
+    tree::PoolTree,
+};
+
+// what other P2A users put in their anchors, the output's dust limit
+pub const COMMON_P2A_ANCHOR_AMOUNT: Amount = Amount::from_sat(240);
+// payouts that are a multiple of this look picked by a person (or a pool), not left over as change
+const ROUND_AMOUNT: u64 = 1000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Low,
+    Medium,
+    High,
+}
+
+// One way the pool's txs stand out on chain, with the setting that helps if there is one
+#[derive(Debug, Clone, Serialize)]
+pub struct PrivacyFinding {
+    pub kind: &'static str,
+    pub severity: Severity,
+    pub detail: String,
+    pub suggestion: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PrivacyReport {
+    pub templates: usize,
+    // outputs of the templates paying a user, the next node and the anchor aside
+    pub payouts: usize,
+    pub distinct_payout_amounts: usize,
+    pub most_common_payout: Amount,
+    // payouts of exactly most_common_payout
+    pub uniform_payouts: usize,
+    // payouts that are a multiple of 1000 sat
+    pub round_payouts: usize,
+    // withdraw addresses given more than once
+    pub reused_addresses: usize,
+    // node amounts that only a node of one size has, so the amount tells how many users are left
+    pub revealing_node_amounts: usize,
+    pub node_amounts: usize,
+    pub anchor_outputs: usize,
+    pub anchor_amount: Option<Amount>,
+    pub tx_version: i32,
+    // templates with a relative timelock in nSequence
+    pub timelocked_spends: usize,
+    // every spend is a script path spend, these reveal an OP_CTV leaf
+    pub ctv_leaf_reveals: usize,
+    // sibling hashes in the control blocks, 2^depth bounds the leaves of the node
+    pub min_leaf_depth: usize,
+    pub max_leaf_depth: usize,
+    // the BIP-341 unspendable internal key, anyone can tell there is no key path
+    pub nums_internal_key: bool,
+    pub findings: Vec<PrivacyFinding>,
+}
+
+fn reveals_ctv(script: &ScriptBuf) -> bool {
+    script
+        .instructions()
+        .any(|instruction| matches!(instruction, Ok(Instruction::Op(op)) if op == OP_NOP4))
+}
+
+// every template with the leaf it spends, node by node in level order, the close-all template last
+fn templates(
+    pools: &PoolTree,
+    config: &NetworkConfig,
+    backend: &dyn CovenantBackend,
+    addresses: &[Address],
+    anchor_addr: &Address,
+) -> Result<Vec<(NodeExit, ScriptBuf)>> {
+    let mut templates = Vec::new();
+    for (users, node) in pools.iter_nodes() {
+        let spenders = users.user_indices().take(node.templates.len());
+        for (spender, script) in spenders.zip(&node.leaf_scripts) {
+            let exit = node_exit(
+                pools,
+                config,
+                backend,
+                addresses,
+                anchor_addr,
+                users,
+                spender,
+            )?;
+            templates.push((exit, script.clone()));
+        }
+    }
+    if let Some(close_all) = pools.root()?.close_all {
+        let exit = close_all_exit(pools, config, backend, addresses, anchor_addr)?;
+        templates.push((exit, backend.leaf_script(close_all)));
+    }
+    Ok(templates)
+}
+
+// How much the pool's txs give away on chain: what ties them together, what their amounts and
+// scripts tell about the users, and the settings that would blur it
+pub fn privacy_report(
+    pools: &PoolTree,
+    config: &NetworkConfig,
+    backend: &dyn CovenantBackend,
+    addresses: &[Address],
+    anchor_addr: &Address,
+) -> Result<PrivacyReport> {
+    let templates = templates(pools, config, backend, addresses, anchor_addr)?;
+    let node_scripts = pools.node_scripts(config);
+    let anchor_script = anchor_addr.script_pubkey();
+    let nums = XOnlyPublicKey::from_str(NUMS_INTERNAL_KEY)?;
+
+    let mut payout_amounts: BTreeMap<Amount, usize> = BTreeMap::new();
+    let mut anchor_outputs = 0;
+    let mut timelocked_spends = 0;
+    let mut ctv_leaf_reveals = 0;
+    let mut depths = Vec::new();
+    let mut nums_internal_key = true;
+    for (exit, script) in &templates {
+        for output in &exit.outputs {
+            if config.anchor_amount.is_some() && output.script_pubkey == anchor_script {
+                anchor_outputs += 1;
+            } else if !node_scripts.contains_key(&output.script_pubkey) {
+                *payout_amounts.entry(output.value).or_default() += 1;
+            }
+        }
+        if exit.sequence.is_relative_lock_time() {
+            timelocked_spends += 1;
+        }
+        if reveals_ctv(script) {
+            ctv_leaf_reveals += 1;
+        }
+        let control_block = exit
+            .spend_info
+            .control_block(&(script.clone(), config.leaf_version))
+            .ok_or_else(|| anyhow!("no control block for a leaf of the pool of {}", exit.users))?;
+        depths.push(control_block.merkle_branch.len());
+        nums_internal_key &= exit.spend_info.internal_key() == nums;
+    }
+
+    let payouts = payout_amounts.values().sum();
+    let (most_common_payout, uniform_payouts) = payout_amounts
+        .iter()
+        .max_by_key(|(_, count)| **count)
+        .map(|(amount, count)| (*amount, *count))
+        .unwrap_or((Amount::ZERO, 0));
+    let round_payouts = payout_amounts
+        .iter()
+        .filter(|(amount, _)| amount.to_sat() % ROUND_AMOUNT == 0)
+        .map(|(_, count)| count)
+        .sum();
+
+    let distinct_addresses: HashSet<&Address> = addresses.iter().collect();
+    let reused_addresses = addresses.len() - distinct_addresses.len();
+
+    let mut sizes_by_amount: BTreeMap<Amount, BTreeSet<usize>> = BTreeMap::new();
+    for (users, node) in pools.iter_nodes() {
+        sizes_by_amount
+            .entry(node.amount)
+            .or_default()
+            .insert(users.users().len());
+    }
+    let revealing_node_amounts = sizes_by_amount
+        .values()
+        .filter(|sizes| sizes.len() == 1)
+        .count();
+
+    let mut report = PrivacyReport {
+        templates: templates.len(),
+        payouts,
+        distinct_payout_amounts: payout_amounts.len(),
+        most_common_payout,
+        uniform_payouts,
+        round_payouts,
+        reused_addresses,
+        revealing_node_amounts,
+        node_amounts: sizes_by_amount.len(),
+        anchor_outputs,
+        anchor_amount: config.anchor_amount,
+        tx_version: config.tx_version,
+        timelocked_spends,
+        ctv_leaf_reveals,
+        min_leaf_depth: depths.iter().copied().min().unwrap_or(0),
+        max_leaf_depth: depths.iter().copied().max().unwrap_or(0),
+        nums_internal_key,
+        findings: Vec::new(),
+    };
+    report.findings = findings(&report, config);
+    Ok(report)
+}
+
+fn findings(report: &PrivacyReport, config: &NetworkConfig) -> Vec<PrivacyFinding> {
+    let mut findings = Vec::new();
+    if report.reused_addresses > 0 {
+        findings.push(PrivacyFinding {
+            kind: "address_reuse",
+            severity: Severity::High,
+            detail: format!(
+                "{} withdraw addresses are given more than once, their payouts are linked to each other",
+                report.reused_addresses
+            ),
+            suggestion: Some("have every user register a fresh address (or an xpub)".to_string()),
+        });
+    }
+    if report.payouts > 1 && report.uniform_payouts * 2 > report.payouts {
+        findings.push(PrivacyFinding {
+            kind: "uniform_amounts",
+            severity: Severity::High,
+            detail: format!(
+                "{} of {} payouts are exactly {} sat, one exit found on chain points to the others",
+                report.uniform_payouts,
+                report.payouts,
+                report.most_common_payout.to_sat()
+            ),
+            suggestion: Some(
+                "randomize the amounts with POOL_PAYOUT_JITTER_SATS, or have users register \
+                 payout splits of uneven amounts"
+                    .to_string(),
+            ),
+        });
+    }
+    if report.round_payouts > 0 {
+        findings.push(PrivacyFinding {
+            kind: "round_amounts",
+            severity: Severity::Low,
+            detail: format!(
+                "{} of {} payouts are a multiple of {} sat, which change outputs rarely are",
+                report.round_payouts, report.payouts, ROUND_AMOUNT
+            ),
+            suggestion: Some("split amounts that aren't round numbers".to_string()),
+        });
+    }
+    if report.revealing_node_amounts > 0 {
+        findings.push(PrivacyFinding {
+            kind: "node_amounts",
+            severity: Severity::Medium,
+            detail: format!(
+                "{} of {} node amounts belong to nodes of a single size, the value of a pool \
+                 output tells how many users are still in it",
+                report.revealing_node_amounts, report.node_amounts
+            ),
+            suggestion: None,
+        });
+    }
+    if let Some(anchor_amount) = report.anchor_amount {
+        let (severity, suggestion) = if anchor_amount == COMMON_P2A_ANCHOR_AMOUNT {
+            (
+                Severity::Medium,
+                "POOL_ANCHOR_AMOUNT_SATS=0 drops the anchors, the fee then comes out of the \
+                 withdrawal and can't be bumped"
+                    .to_string(),
+            )
+        } else {
+            (
+                Severity::High,
+                format!(
+                    "POOL_ANCHOR_AMOUNT_SATS={} is what other anchor users pick, or 0 drops the \
+                     anchors (the fee then comes out of the withdrawal and can't be bumped)",
+                    COMMON_P2A_ANCHOR_AMOUNT.to_sat()
+                ),
+            )
+        };
+        findings.push(PrivacyFinding {
+            kind: "anchor_fingerprint",
+            severity,
+            detail: format!(
+                "{} templates carry a {} sat anchor to {}, the same output on every pool tx ties \
+                 them together",
+                report.anchor_outputs,
+                anchor_amount.to_sat(),
+                config.fee_anchor_addr
+            ),
+            suggestion: Some(suggestion),
+        });
+    }
+    if report.tx_version != 2 {
+        findings.push(PrivacyFinding {
+            kind: "tx_version",
+            severity: Severity::Medium,
+            detail: format!(
+                "every pool tx is version {}, most txs on chain are version 2",
+                report.tx_version
+            ),
+            suggestion: Some(
+                "POOL_TX_VERSION=2, anchored templates need version 3 (TRUC) to relay as a package"
+                    .to_string(),
+            ),
+        });
+    }
+    if report.timelocked_spends > 0 {
+        findings.push(PrivacyFinding {
+            kind: "relative_timelock",
+            severity: Severity::Low,
+            detail: format!(
+                "{} templates set a relative timelock in nSequence, few wallets do",
+                report.timelocked_spends
+            ),
+            suggestion: Some(
+                "leave UNWIND_DELAY_BLOCKS unset if the pool doesn't need it".to_string(),
+            ),
+        });
+    }
+    if report.ctv_leaf_reveals > 0 || report.nums_internal_key {
+        findings.push(PrivacyFinding {
+            kind: "leaf_reveals",
+            severity: Severity::High,
+            detail: format!(
+                "{} spends reveal an OP_CTV leaf{}, with {} to {} sibling hashes bounding how many \
+                 users the node had",
+                report.ctv_leaf_reveals,
+                if report.nums_internal_key {
+                    " under the well known unspendable internal key"
+                } else {
+                    ""
+                },
+                report.min_leaf_depth,
+                report.max_leaf_depth
+            ),
+            suggestion: Some(
+                "POOL_TREE_LAYOUT=weighted keeps the planned exits near the root with fewer \
+                 siblings. Only a cooperative key path (not in this pool) would hide the leaves"
+                    .to_string(),
+            ),
+        });
+    }
+    findings.sort_by_key(|finding| std::cmp::Reverse(finding.severity));
+    findings
+}
+
+pub fn print_privacy(report: &PrivacyReport) {
+    println!("templates: {}", report.templates);
+    println!(
+        "payouts: {} ({} distinct amounts, {} of {} sat, {} round)",
+        report.payouts,
+        report.distinct_payout_amounts,
+        report.uniform_payouts,
+        report.most_common_payout.to_sat(),
+        report.round_payouts
+    );
+    println!("reused withdraw addresses: {}", report.reused_addresses);
+    println!(
+        "node amounts revealing the users left: {} of {}",
+        report.revealing_node_amounts, report.node_amounts
+    );
+    match report.anchor_amount {
+        Some(amount) => println!(
+            "anchor outputs: {} of {} sat",
+            report.anchor_outputs,
+            amount.to_sat()
+        ),
+        None => println!("anchor outputs: none"),
+    }
+    println!("tx version: {}", report.tx_version);
+    println!("timelocked spends: {}", report.timelocked_spends);
+    println!(
+        "OP_CTV leaf reveals: {} (control blocks of {} to {} siblings{})",
+        report.ctv_leaf_reveals,
+        report.min_leaf_depth,
+        report.max_leaf_depth,
+        if report.nums_internal_key {
+            ", unspendable internal key"
+        } else {
+            ""
+        }
+    );
+    for finding in &report.findings {
+        println!(
+            "{:?} {}: {}",
+            finding.severity, finding.kind, finding.detail
+        );
+        if let Some(suggestion) = &finding.suggestion {
+            println!("  try: {}", suggestion);
+        }
+    }
+}
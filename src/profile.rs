@@ -0,0 +1,167 @@
+use std::{sync::Arc, time::Duration};
+
+use bitcoin::{taproot::LeafVersion, Network};
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "elements")]
+use crate::elements::{ElementsParams, ELEMENTS_LEAF_VERSION, ELEMENTS_REGTEST};
+use crate::{
+    config::{ConfirmationTargets, NetworkConfig, DEFAULT_SIGNET_CHALLENGE, FEE_AMOUNT},
+    ctv_scripts::{TemplateVersion, TreeLayout},
+    fee_policy::FixedFee,
+    fetch::RpcFetchLimits,
+    mempool::MempoolLimits,
+    musig::MusigKeys,
+    payouts::{PayoutJitter, PayoutSplits},
+    progress::ProgressMode,
+    user_anchor::AnchorKeys,
+};
+
+// Named network presets, picked at runtime with --network.
+// Every field of a preset can be overridden with POOL_* env vars, see NetworkConfig::new.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NetworkProfile {
+    // old manifests and scripts still say regtest / signet
+    #[value(name = "regtest-local", alias = "regtest")]
+    #[serde(rename = "regtest-local", alias = "regtest")]
+    RegtestLocal,
+    Testnet4,
+    #[value(name = "signet-public", alias = "signet")]
+    #[serde(rename = "signet-public", alias = "signet")]
+    SignetPublic,
+    Inquisition,
+    // elementsd -chain=elementsregtest, the covenant is its introspection opcodes (src/elements.rs)
+    #[cfg(feature = "elements")]
+    #[value(name = "elements-regtest")]
+    #[serde(rename = "elements-regtest")]
+    ElementsRegtest,
+}
+
+impl NetworkProfile {
+    // the profile picked by the cargo feature, so `cargo run --features regtest` still works without --network
+    #[allow(clippy::needless_return, unreachable_code)]
+    pub fn compiled_default() -> Self {
+        #[cfg(feature = "regtest")]
+        return Self::RegtestLocal;
+        #[cfg(feature = "inquisition")]
+        return Self::Inquisition;
+        #[cfg(feature = "signet")]
+        return Self::SignetPublic;
+        Self::Testnet4
+    }
+
+    // env var holding the wallet name on this network, POOL_WALLET works everywhere
+    pub fn wallet_env_var(self) -> Option<&'static str> {
+        match self {
+            Self::RegtestLocal => None,
+            Self::Testnet4 => Some("TESTNET4_WALLET"),
+            Self::SignetPublic => Some("SIGNET_WALLET"),
+            Self::Inquisition => Some("INQUISITION_WALLET"),
+            #[cfg(feature = "elements")]
+            Self::ElementsRegtest => None,
+        }
+    }
+
+    // the Elements chain of the profile, None on bitcoin
+    #[cfg(feature = "elements")]
+    pub fn elements(self) -> Option<ElementsParams> {
+        match self {
+            Self::ElementsRegtest => Some(ELEMENTS_REGTEST),
+            _ => None,
+        }
+    }
+
+    // the preset without any env var applied
+    pub fn preset(self) -> NetworkConfig {
+        // what every public network shares, each preset only spells out where it differs
+        let base = NetworkConfig {
+            profile: self,
+            network: Network::Testnet4,
+            rpc_url: "http://localhost:48332".to_string(),
+            fee_anchor_addr: "tb1pfees9rn5nz".to_string(),
+            // only needed once we talk to the node, offline commands work without it
+            wallet_name: String::new(),
+            fee_wallet_name: None,
+            tx_version: 2,
+            anchor_amount: None,
+            conf_target: 2,
+            signet_challenge: None,
+            require_ctv: false,
+            block_interval: None,
+            recovery: None,
+            unwind_delay: None,
+            level_delays: Vec::new(),
+            progress: ProgressMode::Bar,
+            tree_layout: TreeLayout::Weighted,
+            leaf_version: LeafVersion::TapScript,
+            template_version: TemplateVersion::LATEST,
+            webhook: None,
+            faucet: None,
+            esplora_url: None,
+            proxy: None,
+            confirmations: ConfirmationTargets::uniform(1),
+            anti_fee_sniping: true,
+            close_all_leaf: false,
+            splits: PayoutSplits::new(),
+            anchor_keys: AnchorKeys::new(),
+            musig_keys: MusigKeys::new(),
+            allowed_witness_versions: Vec::new(),
+            mempool_limits: MempoolLimits::CORE_DEFAULT,
+            min_template_fee_rate: None,
+            payout_jitter: PayoutJitter::new(),
+            removed: Vec::new(),
+            fee_bump: None,
+            log_sensitive: false,
+            rpc_fetch: RpcFetchLimits::DEFAULT,
+            coin_reservations: None,
+            tree_memory_budget: None,
+            env_problems: Vec::new(),
+            fee_policy: Arc::new(FixedFee(FEE_AMOUNT)),
+        };
+        match self {
+            // in regtest we use P2A and v3 transactions to spend
+            Self::RegtestLocal => NetworkConfig {
+                network: Network::Regtest,
+                rpc_url: "http://localhost:18443".to_string(),
+                fee_anchor_addr: "bcrt1pfeesnyr2tx".to_string(),
+                wallet_name: "simple_ctv".to_string(),
+                tx_version: 3,
+                anchor_amount: Some(FEE_AMOUNT),
+                conf_target: 1,
+                block_interval: Some(Duration::from_secs(2)),
+                log_sensitive: true,
+                ..base
+            },
+            Self::Testnet4 => base,
+            Self::SignetPublic => NetworkConfig {
+                network: Network::Signet,
+                rpc_url: "http://localhost:38332".to_string(),
+                signet_challenge: Some(DEFAULT_SIGNET_CHALLENGE.to_string()),
+                ..base
+            },
+            // the fee comes out of every exit in an explicit fee output, no anchors
+            #[cfg(feature = "elements")]
+            Self::ElementsRegtest => NetworkConfig {
+                network: Network::Regtest,
+                rpc_url: "http://localhost:18884".to_string(),
+                fee_anchor_addr: "bcrt1pfeesnyr2tx".to_string(),
+                wallet_name: "simple_ctv".to_string(),
+                conf_target: 1,
+                leaf_version: LeafVersion::from_consensus(ELEMENTS_LEAF_VERSION)
+                    .expect("0xc4 is a leaf version"),
+                log_sensitive: true,
+                ..base
+            },
+            // bitcoin inquisition nodes on the default signet, where OP_CTV is actually enforced
+            Self::Inquisition => NetworkConfig {
+                network: Network::Signet,
+                rpc_url: "http://localhost:38332".to_string(),
+                signet_challenge: Some(DEFAULT_SIGNET_CHALLENGE.to_string()),
+                require_ctv: true,
+                ..base
+            }, //wen mainnet
+        }
+    }
+}
@@ -0,0 +1,140 @@
+use std::{str::FromStr, time::Instant};
+
+use anyhow::{bail, Error, Result};
+use indicatif::{ProgressBar, ProgressStyle};
+use serde::Serialize;
+
+use crate::{costs::binomial, POOL_USERS};
+
+// How tree construction reports progress, POOL_PROGRESS env var
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProgressMode {
+    // progress bar on stderr, hidden when stderr is not a terminal
+    Bar,
+    // one json line per finished level on stderr, for scripts and log collectors
+    Json,
+    Off,
+}
+
+impl FromStr for ProgressMode {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "bar" => Ok(Self::Bar),
+            "json" => Ok(Self::Json),
+            "off" => Ok(Self::Off),
+            other => bail!("unknown progress mode {}, expected bar, json or off", other),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct LevelEvent {
+    event: &'static str,
+    users: usize,
+    nodes_done: u64,
+    nodes_total: u64,
+    hashes_done: u64,
+    hashes_total: u64,
+    elapsed_secs: f64,
+    eta_secs: f64,
+}
+
+// Counts the template hashes of the tree as it is built, level by level from the exit pool up
+pub struct TreeProgress {
+    mode: ProgressMode,
+    bar: Option<ProgressBar>,
+    started: Instant,
+    users: usize,
+    nodes_done: u64,
+    nodes_total: u64,
+    hashes_done: u64,
+    hashes_total: u64,
+}
+
+impl TreeProgress {
+    pub fn start(mode: ProgressMode) -> Self {
+        let n = POOL_USERS as u64;
+        // the exit pool has one template per node, every other node one per user
+        let nodes_total = (2..=n).map(|users| binomial(n, users)).sum();
+        let hashes_total = (2..=n)
+            .map(|users| binomial(n, users) * if users == 2 { 1 } else { users })
+            .sum();
+
+        let bar = (mode == ProgressMode::Bar).then(|| {
+            let bar = ProgressBar::new(hashes_total);
+            bar.set_style(
+                ProgressStyle::with_template(
+                    "{msg} [{bar:40}] {pos}/{len} template hashes, {elapsed} elapsed, eta {eta}",
+                )
+                .expect("valid progress template")
+                .progress_chars("=> "),
+            );
+            bar
+        });
+
+        Self {
+            mode,
+            bar,
+            started: Instant::now(),
+            users: 2,
+            nodes_done: 0,
+            nodes_total,
+            hashes_done: 0,
+            hashes_total,
+        }
+    }
+
+    pub fn start_level(&mut self, users: usize) {
+        self.users = users;
+        if let Some(bar) = &self.bar {
+            bar.set_message(format!("{} user nodes", users));
+        }
+    }
+
+    pub fn node_done(&mut self, hashes: usize) {
+        self.nodes_done += 1;
+        self.hashes_done += hashes as u64;
+        if let Some(bar) = &self.bar {
+            bar.inc(hashes as u64);
+        }
+    }
+
+    pub fn finish_level(&self) {
+        self.emit("level");
+    }
+
+    pub fn finish(self) {
+        self.emit("done");
+        if let Some(bar) = self.bar {
+            bar.finish_and_clear();
+        }
+    }
+
+    fn emit(&self, event: &'static str) {
+        if self.mode != ProgressMode::Json {
+            return;
+        }
+        let elapsed_secs = self.started.elapsed().as_secs_f64();
+        // hashes are the bulk of the work, assume the rest goes at the same rate
+        let eta_secs = if self.hashes_done == 0 {
+            0.0
+        } else {
+            elapsed_secs * (self.hashes_total - self.hashes_done) as f64 / self.hashes_done as f64
+        };
+        let event = LevelEvent {
+            event,
+            users: self.users,
+            nodes_done: self.nodes_done,
+            nodes_total: self.nodes_total,
+            hashes_done: self.hashes_done,
+            hashes_total: self.hashes_total,
+            elapsed_secs,
+            eta_secs,
+        };
+        if let Ok(json) = serde_json::to_string(&event) {
+            eprintln!("{}", json);
+        }
+    }
+}
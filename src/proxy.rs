@@ -0,0 +1,70 @@
+use std::sync::RwLock;
+
+use anyhow::{anyhow, bail, Result};
+use reqwest::Url;
+
+// SOCKS5 proxy every http request of the pool goes through: esplora, faucet, webhooks and release
+// uploads. Set from the config's proxy (POOL_PROXY), direct connections until then.
+// The bitcoind rpc connection is not proxied, it is the operator's own node.
+static PROXY: RwLock<Option<String>> = RwLock::new(None);
+
+pub fn set_proxy(url: Option<&str>) {
+    *PROXY.write().unwrap() = url.map(str::to_string);
+}
+
+pub fn proxy() -> Option<String> {
+    PROXY.read().unwrap().clone()
+}
+
+// socks5h://host:port resolves names at the proxy, which tor needs for .onion hosts and which keeps
+// the lookups of clearnet hosts off the local resolver. socks5:// resolves them here.
+pub fn parse_proxy(url: &str) -> Result<String> {
+    let parsed = Url::parse(url).map_err(|e| anyhow!("{}: {}", url, e))?;
+    if !matches!(parsed.scheme(), "socks5" | "socks5h") {
+        bail!(
+            "{} is not a SOCKS5 proxy, use socks5h://host:port (e.g. socks5h://127.0.0.1:9050 for tor)",
+            url
+        );
+    }
+    if parsed.host_str().is_none() || parsed.port().is_none() {
+        bail!("{} needs a host and a port", url);
+    }
+    Ok(url.to_string())
+}
+
+// .onion hosts only resolve inside tor, through a proxy that resolves names itself
+pub fn check_endpoint(url: &str, proxy: Option<&str>) -> Result<()> {
+    let parsed = Url::parse(url).map_err(|e| anyhow!("{}: {}", url, e))?;
+    let onion = parsed
+        .host_str()
+        .is_some_and(|host| host.ends_with(".onion"));
+    if !onion {
+        return Ok(());
+    }
+    match proxy {
+        None => bail!("{} is an onion service, set POOL_PROXY to reach it", url),
+        Some(proxy) if !proxy.starts_with("socks5h://") => bail!(
+            "{} is an onion service, {} has to resolve it: use socks5h:// instead of socks5://",
+            url,
+            proxy
+        ),
+        Some(_) => Ok(()),
+    }
+}
+
+// a client sending everything through `proxy`, none for direct connections
+pub fn http_client_via(proxy: Option<&str>) -> Result<reqwest::Client> {
+    let mut builder = reqwest::Client::builder();
+    if let Some(proxy) = proxy {
+        builder = builder.proxy(reqwest::Proxy::all(proxy)?);
+    }
+    Ok(builder.build()?)
+}
+
+// Client for a request to `url` through the configured proxy. Errors rather than connecting
+// directly, so a bad proxy setting never leaks the request to the clearnet.
+pub fn http_client(url: &str) -> Result<reqwest::Client> {
+    let proxy = proxy();
+    check_endpoint(url, proxy.as_deref())?;
+    http_client_via(proxy.as_deref())
+}
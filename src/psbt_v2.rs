@@ -0,0 +1,343 @@
+use anyhow::{anyhow, bail, Context, Result};
+use bitcoin::{
+    absolute,
+    base64::{prelude::BASE64_STANDARD, Engine},
+    consensus::encode::{deserialize, deserialize_partial, serialize, VarInt},
+    transaction, Amount, OutPoint, Psbt, ScriptBuf, Sequence, Transaction, TxIn, TxOut, Txid,
+};
+use serde::Serialize;
+
+// BIP-370 PSBTs. rust-bitcoin only reads and writes version 0 (BIP-174), so a version 2 PSBT is
+// the version 0 one with its maps rewritten: the unsigned tx is taken apart into fields of the
+// global, input and output maps and the rest, the tap fields among it, is encoded the same in both.
+
+const MAGIC: &[u8] = b"psbt\xff";
+
+const GLOBAL_UNSIGNED_TX: u8 = 0x00;
+const GLOBAL_TX_VERSION: u8 = 0x02;
+const GLOBAL_FALLBACK_LOCKTIME: u8 = 0x03;
+const GLOBAL_INPUT_COUNT: u8 = 0x04;
+const GLOBAL_OUTPUT_COUNT: u8 = 0x05;
+const GLOBAL_TX_MODIFIABLE: u8 = 0x06;
+const GLOBAL_VERSION: u8 = 0xfb;
+const IN_PREVIOUS_TXID: u8 = 0x0e;
+const IN_OUTPUT_INDEX: u8 = 0x0f;
+const IN_SEQUENCE: u8 = 0x10;
+const IN_REQUIRED_TIME_LOCKTIME: u8 = 0x11;
+const IN_REQUIRED_HEIGHT_LOCKTIME: u8 = 0x12;
+const OUT_AMOUNT: u8 = 0x03;
+const OUT_SCRIPT: u8 = 0x04;
+
+// the fields version 2 has in place of the unsigned tx, none of them may be in a version 0 PSBT
+const GLOBAL_V2_ONLY: &[u8] = &[
+    GLOBAL_TX_VERSION,
+    GLOBAL_FALLBACK_LOCKTIME,
+    GLOBAL_INPUT_COUNT,
+    GLOBAL_OUTPUT_COUNT,
+    GLOBAL_TX_MODIFIABLE,
+    GLOBAL_VERSION,
+];
+const IN_V2_ONLY: &[u8] = &[
+    IN_PREVIOUS_TXID,
+    IN_OUTPUT_INDEX,
+    IN_SEQUENCE,
+    IN_REQUIRED_TIME_LOCKTIME,
+    IN_REQUIRED_HEIGHT_LOCKTIME,
+];
+const OUT_V2_ONLY: &[u8] = &[OUT_AMOUNT, OUT_SCRIPT];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PsbtVersion {
+    V0,
+    V2,
+}
+
+// key (its type and key data) and value of every field of one map, in the order they came
+type Map = Vec<(Vec<u8>, Vec<u8>)>;
+
+fn take<'a>(bytes: &'a [u8], pos: &mut usize, len: usize) -> Result<&'a [u8]> {
+    let slice = pos
+        .checked_add(len)
+        .and_then(|end| bytes.get(*pos..end))
+        .ok_or_else(|| anyhow!("the PSBT ends in the middle of a field"))?;
+    *pos += len;
+    Ok(slice)
+}
+
+fn read_len(bytes: &[u8], pos: &mut usize) -> Result<usize> {
+    let rest = bytes
+        .get(*pos..)
+        .ok_or_else(|| anyhow!("the PSBT ends in the middle of a field"))?;
+    let (len, used): (VarInt, usize) = deserialize_partial(rest)?;
+    *pos += used;
+    Ok(usize::try_from(len.0)?)
+}
+
+fn read_map(bytes: &[u8], pos: &mut usize) -> Result<Map> {
+    let mut map = Map::new();
+    loop {
+        let key_len = read_len(bytes, pos)?;
+        if key_len == 0 {
+            return Ok(map);
+        }
+        let key = take(bytes, pos, key_len)?.to_vec();
+        let value_len = read_len(bytes, pos)?;
+        let value = take(bytes, pos, value_len)?.to_vec();
+        map.push((key, value));
+    }
+}
+
+fn write_map(out: &mut Vec<u8>, map: &Map) {
+    for (key, value) in map {
+        out.extend(serialize(&VarInt(key.len() as u64)));
+        out.extend(key);
+        out.extend(serialize(&VarInt(value.len() as u64)));
+        out.extend(value);
+    }
+    out.push(0x00);
+}
+
+// the value of the field of `key_type` without key data
+fn field(map: &Map, key_type: u8) -> Option<&[u8]> {
+    map.iter()
+        .find(|(key, _)| key[..] == [key_type])
+        .map(|(_, value)| value.as_slice())
+}
+
+fn required<'a>(map: &'a Map, key_type: u8, what: &str) -> Result<&'a [u8]> {
+    field(map, key_type).ok_or_else(|| anyhow!("the version 2 PSBT has no {}", what))
+}
+
+fn u32_field(value: &[u8], what: &str) -> Result<u32> {
+    let bytes: [u8; 4] = value
+        .try_into()
+        .map_err(|_| anyhow!("the PSBT's {} isn't 4 bytes", what))?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+// the fields of `map` whose type isn't one of `types`, or that have key data
+fn without(map: Map, types: &[u8]) -> Map {
+    map.into_iter()
+        .filter(|(key, _)| key.len() != 1 || !types.contains(&key[0]))
+        .collect()
+}
+
+fn read_magic(bytes: &[u8]) -> Result<usize> {
+    if !bytes.starts_with(MAGIC) {
+        bail!("not a PSBT, it doesn't start with the psbt magic");
+    }
+    Ok(MAGIC.len())
+}
+
+// The version 2 serialization of `psbt`. Without PSBT_GLOBAL_TX_MODIFIABLE neither inputs nor
+// outputs can be added, what a template spend needs: its template commits to all of them.
+pub fn serialize_v2(psbt: &Psbt) -> Result<Vec<u8>> {
+    let bytes = psbt.serialize();
+    let tx = &psbt.unsigned_tx;
+    let mut pos = read_magic(&bytes)?;
+
+    let mut global = without(
+        read_map(&bytes, &mut pos)?,
+        &[GLOBAL_UNSIGNED_TX, GLOBAL_VERSION],
+    );
+    global.extend([
+        (vec![GLOBAL_TX_VERSION], tx.version.0.to_le_bytes().to_vec()),
+        (
+            vec![GLOBAL_FALLBACK_LOCKTIME],
+            tx.lock_time.to_consensus_u32().to_le_bytes().to_vec(),
+        ),
+        (
+            vec![GLOBAL_INPUT_COUNT],
+            serialize(&VarInt(tx.input.len() as u64)),
+        ),
+        (
+            vec![GLOBAL_OUTPUT_COUNT],
+            serialize(&VarInt(tx.output.len() as u64)),
+        ),
+        (vec![GLOBAL_VERSION], 2u32.to_le_bytes().to_vec()),
+    ]);
+    global.sort();
+    let mut out = MAGIC.to_vec();
+    write_map(&mut out, &global);
+
+    for input in &tx.input {
+        let mut map = read_map(&bytes, &mut pos)?;
+        map.extend([
+            (
+                vec![IN_PREVIOUS_TXID],
+                serialize(&input.previous_output.txid),
+            ),
+            (
+                vec![IN_OUTPUT_INDEX],
+                input.previous_output.vout.to_le_bytes().to_vec(),
+            ),
+            (
+                vec![IN_SEQUENCE],
+                input.sequence.to_consensus_u32().to_le_bytes().to_vec(),
+            ),
+        ]);
+        map.sort();
+        write_map(&mut out, &map);
+    }
+    for output in &tx.output {
+        let mut map = read_map(&bytes, &mut pos)?;
+        map.extend([
+            (
+                vec![OUT_AMOUNT],
+                output.value.to_sat().to_le_bytes().to_vec(),
+            ),
+            (vec![OUT_SCRIPT], output.script_pubkey.to_bytes()),
+        ]);
+        map.sort();
+        write_map(&mut out, &map);
+    }
+    Ok(out)
+}
+
+// BIP-370's locktime: the highest an input requires, by height if every input requiring one can
+// take a height, by time otherwise, and the fallback when no input requires one
+fn locktime(heights: &[Option<u32>], times: &[Option<u32>], fallback: u32) -> Result<u32> {
+    let locked: Vec<(Option<u32>, Option<u32>)> = heights
+        .iter()
+        .zip(times)
+        .map(|(height, time)| (*height, *time))
+        .filter(|(height, time)| height.is_some() || time.is_some())
+        .collect();
+    if locked.is_empty() {
+        return Ok(fallback);
+    }
+    if locked.iter().all(|(height, _)| height.is_some()) {
+        return Ok(locked
+            .iter()
+            .filter_map(|(height, _)| *height)
+            .max()
+            .unwrap_or(0));
+    }
+    if locked.iter().all(|(_, time)| time.is_some()) {
+        return Ok(locked
+            .iter()
+            .filter_map(|(_, time)| *time)
+            .max()
+            .unwrap_or(0));
+    }
+    bail!("the PSBT's inputs require both a height and a time locktime, no tx satisfies them all")
+}
+
+// A version 2 PSBT as the version 0 one rust-bitcoin works with
+pub fn deserialize_v2(bytes: &[u8]) -> Result<Psbt> {
+    let mut pos = read_magic(bytes)?;
+    let global = read_map(bytes, &mut pos)?;
+    let version = u32_field(required(&global, GLOBAL_VERSION, "version")?, "version")?;
+    if version != 2 {
+        bail!("PSBT version {} isn't version 2", version);
+    }
+    let tx_version = u32_field(
+        required(&global, GLOBAL_TX_VERSION, "tx version")?,
+        "tx version",
+    )?;
+    let fallback = field(&global, GLOBAL_FALLBACK_LOCKTIME)
+        .map(|value| u32_field(value, "fallback locktime"))
+        .transpose()?
+        .unwrap_or(0);
+    let input_count: VarInt = deserialize(required(&global, GLOBAL_INPUT_COUNT, "input count")?)?;
+    let output_count: VarInt =
+        deserialize(required(&global, GLOBAL_OUTPUT_COUNT, "output count")?)?;
+
+    let mut input_maps = Vec::new();
+    let mut inputs = Vec::new();
+    let mut heights = Vec::new();
+    let mut times = Vec::new();
+    for i in 0..input_count.0 {
+        let map = read_map(bytes, &mut pos)?;
+        let txid: Txid = deserialize(required(&map, IN_PREVIOUS_TXID, "previous txid")?)
+            .with_context(|| format!("input {} has an invalid previous txid", i))?;
+        let vout = u32_field(
+            required(&map, IN_OUTPUT_INDEX, "output index")?,
+            "output index",
+        )?;
+        let sequence = field(&map, IN_SEQUENCE)
+            .map(|value| u32_field(value, "sequence"))
+            .transpose()?
+            .map_or(Sequence::MAX, Sequence::from_consensus);
+        heights.push(
+            field(&map, IN_REQUIRED_HEIGHT_LOCKTIME)
+                .map(|value| u32_field(value, "required height locktime"))
+                .transpose()?,
+        );
+        times.push(
+            field(&map, IN_REQUIRED_TIME_LOCKTIME)
+                .map(|value| u32_field(value, "required time locktime"))
+                .transpose()?,
+        );
+        inputs.push(TxIn {
+            previous_output: OutPoint::new(txid, vout),
+            sequence,
+            ..Default::default()
+        });
+        input_maps.push(without(map, IN_V2_ONLY));
+    }
+    let mut output_maps = Vec::new();
+    let mut outputs = Vec::new();
+    for _ in 0..output_count.0 {
+        let map = read_map(bytes, &mut pos)?;
+        let amount: [u8; 8] = required(&map, OUT_AMOUNT, "output amount")?
+            .try_into()
+            .map_err(|_| anyhow!("the PSBT's output amount isn't 8 bytes"))?;
+        outputs.push(TxOut {
+            value: Amount::from_sat(u64::from_le_bytes(amount)),
+            script_pubkey: ScriptBuf::from_bytes(
+                required(&map, OUT_SCRIPT, "output script")?.to_vec(),
+            ),
+        });
+        output_maps.push(without(map, OUT_V2_ONLY));
+    }
+
+    let tx = Transaction {
+        version: transaction::Version(tx_version as i32),
+        lock_time: absolute::LockTime::from_consensus(locktime(&heights, &times, fallback)?),
+        input: inputs,
+        output: outputs,
+    };
+    let mut global = without(global, GLOBAL_V2_ONLY);
+    global.push((vec![GLOBAL_UNSIGNED_TX], serialize(&tx)));
+    global.sort();
+    let mut v0 = MAGIC.to_vec();
+    write_map(&mut v0, &global);
+    for map in input_maps.iter().chain(&output_maps) {
+        write_map(&mut v0, map);
+    }
+    Ok(Psbt::deserialize(&v0)?)
+}
+
+// the version in the global map, version 0 when it has none
+pub fn psbt_version(bytes: &[u8]) -> Result<PsbtVersion> {
+    let mut pos = read_magic(bytes)?;
+    let global = read_map(bytes, &mut pos)?;
+    match field(&global, GLOBAL_VERSION)
+        .map(|value| u32_field(value, "version"))
+        .transpose()?
+    {
+        None | Some(0) => Ok(PsbtVersion::V0),
+        Some(2) => Ok(PsbtVersion::V2),
+        Some(version) => bail!("PSBT version {} isn't supported", version),
+    }
+}
+
+// Binary PSBT of either version, and the version it was in
+pub fn decode_psbt(bytes: &[u8]) -> Result<(Psbt, PsbtVersion)> {
+    let version = psbt_version(bytes)?;
+    let psbt = match version {
+        PsbtVersion::V0 => Psbt::deserialize(bytes)?,
+        PsbtVersion::V2 => deserialize_v2(bytes)?,
+    };
+    Ok((psbt, version))
+}
+
+// base64, what wallets and files carry
+pub fn encode_psbt(psbt: &Psbt, version: PsbtVersion) -> Result<String> {
+    Ok(match version {
+        PsbtVersion::V0 => psbt.to_string(),
+        PsbtVersion::V2 => BASE64_STANDARD.encode(serialize_v2(psbt)?),
+    })
+}
@@ -0,0 +1,369 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::Read,
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::Duration,
+};
+
+use anyhow::{anyhow, bail, Result};
+use bitcoin::{
+    hashes::{sha256, Hash},
+    hex::{DisplayHex, FromHex},
+    key::{Keypair, Secp256k1},
+    secp256k1::{schnorr::Signature, Message, SecretKey},
+    Network, PrivateKey, Txid, XOnlyPublicKey,
+};
+use miniscript::descriptor::checksum::desc_checksum;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tracing::{info, warn};
+
+use crate::{
+    config::NetworkConfig,
+    export::export_loaded_pool,
+    labels::{address_labels, pool_labels, Bip329Label},
+    manifest::PoolManifest,
+    metadata::PoolMetadata,
+    proxy::http_client,
+};
+
+// the signed listing, first in the archive
+pub const RELEASE_FILE: &str = "release.json";
+const UPLOAD_TIMEOUT: Duration = Duration::from_secs(60);
+
+// One artifact in the archive and what it hashes to
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReleaseFile {
+    pub path: String,
+    pub sha256: String,
+    pub size: u64,
+}
+
+// What the coordinator signs: the pool and every file of the archive
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Release {
+    pub network: Network,
+    pub root_address: String,
+    pub funding_txid: Option<Txid>,
+    pub files: Vec<ReleaseFile>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SignedRelease {
+    #[serde(flatten)]
+    pub release: Release,
+    pub coordinator: XOnlyPublicKey,
+    // BIP-340 signature of the sha256 of the release json
+    pub signature: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Publication {
+    pub archive: PathBuf,
+    pub sha256: String,
+    pub size: u64,
+    pub coordinator: XOnlyPublicKey,
+    pub files: Vec<ReleaseFile>,
+    // where it was uploaded to, if anywhere
+    pub uploaded_to: Option<String>,
+}
+
+impl Release {
+    pub fn message(&self) -> Result<Message> {
+        let digest = sha256::Hash::hash(&serde_json::to_vec(self)?);
+        Ok(Message::from_digest(digest.to_byte_array()))
+    }
+}
+
+// POOL_COORDINATOR_KEY, a WIF or a 32 byte hex secret key
+pub fn coordinator_key_from_env() -> Result<Keypair> {
+    let key = NetworkConfig::get_env_var("POOL_COORDINATOR_KEY", "");
+    if key.is_empty() {
+        bail!("POOL_COORDINATOR_KEY is not set, releases are signed with the coordinator key");
+    }
+    let secret = match PrivateKey::from_wif(key.trim()) {
+        Ok(private_key) => private_key.inner,
+        Err(_) => SecretKey::from_str(key.trim())
+            .map_err(|_| anyhow!("POOL_COORDINATOR_KEY is neither a WIF nor a hex secret key"))?,
+    };
+    Ok(Keypair::from_secret_key(&Secp256k1::new(), &secret))
+}
+
+fn file_sha256(contents: &[u8]) -> String {
+    sha256::Hash::hash(contents).to_string()
+}
+
+// Every node address as an importdescriptors request, watch-only in any wallet
+fn node_descriptors(labels: &[Bip329Label]) -> Result<serde_json::Value> {
+    let descriptors = labels
+        .iter()
+        .filter(|label| label.kind == "addr" && label.label.starts_with("ctv pool node"))
+        .map(|label| {
+            let descriptor = format!("addr({})", label.reference);
+            let checksum = desc_checksum(&descriptor)?;
+            Ok(json!({
+                "desc": format!("{}#{}", descriptor, checksum),
+                "timestamp": "now",
+                "label": label.label,
+            }))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    Ok(serde_json::Value::Array(descriptors))
+}
+
+// The artifacts of the pool by their path in the archive: the manifest, an exit kit per user,
+// the node descriptors and the BIP-329 labels. The coordinator's metadata (names, emails,
+// internal ids) stays out, everyone in the pool gets the archive.
+pub fn release_files(manifest: &PoolManifest) -> Result<BTreeMap<String, Vec<u8>>> {
+    let pool = manifest.load_pool()?;
+    let mut files = BTreeMap::new();
+
+    let mut shared = manifest.clone();
+    shared.metadata = PoolMetadata::default();
+    files.insert(
+        "manifest.json".to_string(),
+        serde_json::to_vec_pretty(&shared)?,
+    );
+
+    for user in pool.config.pool_root()?.user_indices() {
+        let export = export_loaded_pool(&pool, manifest, Some(user), false)?;
+        files.insert(
+            format!("exits/user-{}.json", user),
+            serde_json::to_vec_pretty(&export)?,
+        );
+    }
+
+    let labels = match manifest.funding_txid {
+        Some(funding_txid) => {
+            pool_labels(&pool.tree, &pool.config, &pool.addresses, funding_txid, &[])?
+        }
+        None => address_labels(&pool.tree, &pool.config, &pool.addresses),
+    };
+    files.insert(
+        "descriptors.json".to_string(),
+        serde_json::to_vec_pretty(&node_descriptors(&labels)?)?,
+    );
+    let mut jsonl = Vec::new();
+    for label in &labels {
+        jsonl.extend(serde_json::to_vec(label)?);
+        jsonl.push(b'\n');
+    }
+    files.insert("labels.jsonl".to_string(), jsonl);
+    Ok(files)
+}
+
+fn append(
+    archive: &mut tar::Builder<impl std::io::Write>,
+    path: &str,
+    contents: &[u8],
+) -> Result<()> {
+    // no timestamps or owners, the same pool always makes the same archive
+    let mut header = tar::Header::new_gnu();
+    header.set_size(contents.len() as u64);
+    header.set_mode(0o644);
+    header.set_mtime(0);
+    header.set_cksum();
+    archive.append_data(&mut header, path, contents)?;
+    Ok(())
+}
+
+// Sign the listing of `files` and pack it with them into one .tar.zst
+pub fn build_release(
+    manifest: &PoolManifest,
+    files: &BTreeMap<String, Vec<u8>>,
+    coordinator: &Keypair,
+) -> Result<(SignedRelease, Vec<u8>)> {
+    let release = Release {
+        network: manifest.network,
+        root_address: manifest.root_address.clone(),
+        funding_txid: manifest.funding_txid,
+        files: files
+            .iter()
+            .map(|(path, contents)| ReleaseFile {
+                path: path.clone(),
+                sha256: file_sha256(contents),
+                size: contents.len() as u64,
+            })
+            .collect(),
+    };
+    // deterministic nonce, republishing the same pool gives the same archive
+    let signature = Secp256k1::new().sign_schnorr_no_aux_rand(&release.message()?, coordinator);
+    let signed = SignedRelease {
+        release,
+        coordinator: coordinator.x_only_public_key().0,
+        signature: signature.as_ref().to_lower_hex_string(),
+    };
+
+    let mut archive = tar::Builder::new(zstd::Encoder::new(Vec::new(), 0)?);
+    append(
+        &mut archive,
+        RELEASE_FILE,
+        &serde_json::to_vec_pretty(&signed)?,
+    )?;
+    for (path, contents) in files {
+        append(&mut archive, path, contents)?;
+    }
+    let bytes = archive.into_inner()?.finish()?;
+    Ok((signed, bytes))
+}
+
+fn read_archive(bytes: &[u8]) -> Result<BTreeMap<String, Vec<u8>>> {
+    let mut archive = tar::Archive::new(zstd::Decoder::new(bytes)?);
+    let mut files = BTreeMap::new();
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let path = entry.path()?.to_string_lossy().into_owned();
+        let mut contents = Vec::new();
+        entry.read_to_end(&mut contents)?;
+        if files.insert(path.clone(), contents).is_some() {
+            bail!("{} is in the archive twice", path);
+        }
+    }
+    Ok(files)
+}
+
+// Check the signature over the listing and every file against it. With `coordinator` the
+// release also has to be signed by that key, without it only the archive's own key is checked.
+pub fn verify_release(bytes: &[u8], coordinator: Option<XOnlyPublicKey>) -> Result<SignedRelease> {
+    let mut files = read_archive(bytes)?;
+    let signed: SignedRelease = serde_json::from_slice(
+        &files
+            .remove(RELEASE_FILE)
+            .ok_or_else(|| anyhow!("no {} in the archive", RELEASE_FILE))?,
+    )?;
+    if let Some(expected) = coordinator {
+        if signed.coordinator != expected {
+            bail!(
+                "release signed by {}, not by the coordinator {}",
+                signed.coordinator,
+                expected
+            );
+        }
+    }
+    let signature = Signature::from_slice(&Vec::from_hex(&signed.signature)?)?;
+    Secp256k1::new()
+        .verify_schnorr(&signature, &signed.release.message()?, &signed.coordinator)
+        .map_err(|_| anyhow!("the release signature doesn't match its files"))?;
+
+    for file in &signed.release.files {
+        let contents = files
+            .remove(&file.path)
+            .ok_or_else(|| anyhow!("{} is signed for but missing from the archive", file.path))?;
+        if file_sha256(&contents) != file.sha256 || contents.len() as u64 != file.size {
+            bail!("{} was changed after the release was signed", file.path);
+        }
+    }
+    if let Some(path) = files.keys().next() {
+        bail!("{} is in the archive but not in the signed release", path);
+    }
+    Ok(signed)
+}
+
+pub fn verify_release_file(path: &Path, coordinator: Option<XOnlyPublicKey>) -> Result<()> {
+    let signed = verify_release(&fs::read(path)?, coordinator)?;
+    info!(
+        "{} files of the pool at {} signed by {} \n",
+        signed.release.files.len(),
+        signed.release.root_address,
+        signed.coordinator
+    );
+    if coordinator.is_none() {
+        warn!(
+            "the archive is intact, check {} is the coordinator's key (or pass --coordinator)",
+            signed.coordinator
+        );
+    }
+    Ok(())
+}
+
+// Where releases go: POOL_PUBLISH_TO or --to. A directory (or file:// url) gets a copy, an
+// http(s) url a PUT, which covers webdav (credentials in the url) and S3 presigned urls.
+// A url ending in / gets the archive's file name appended.
+pub async fn upload_release(to: &str, archive: &Path, bytes: &[u8]) -> Result<String> {
+    let name = archive
+        .file_name()
+        .ok_or_else(|| anyhow!("{} is not a file", archive.display()))?
+        .to_string_lossy();
+    if !to.starts_with("http://") && !to.starts_with("https://") {
+        let dir = PathBuf::from(to.strip_prefix("file://").unwrap_or(to));
+        fs::create_dir_all(&dir)?;
+        let path = dir.join(name.as_ref());
+        fs::write(&path, bytes)?;
+        return Ok(path.display().to_string());
+    }
+
+    let mut url = reqwest::Url::parse(to)?;
+    if url.path().ends_with('/') {
+        url = url.join(&name)?;
+    }
+    let username = url.username().to_string();
+    let password = url.password().map(str::to_string);
+    let _ = url.set_username("");
+    let _ = url.set_password(None);
+
+    let mut request = http_client(url.as_str())?
+        .put(url.clone())
+        .timeout(UPLOAD_TIMEOUT)
+        .header("Content-Type", "application/zstd")
+        .body(bytes.to_vec());
+    if !username.is_empty() {
+        request = request.basic_auth(username, password);
+    }
+    let response = request.send().await?;
+    if !response.status().is_success() {
+        bail!("{} answered {}", url, response.status());
+    }
+    // presigned urls carry their signature in the query
+    url.set_query(None);
+    Ok(url.to_string())
+}
+
+// Bundle, sign and write the release, then upload it if there is somewhere to
+pub async fn publish(
+    manifest: &PoolManifest,
+    output: &Path,
+    to: Option<&str>,
+) -> Result<Publication> {
+    let coordinator = coordinator_key_from_env()?;
+    let files = release_files(manifest)?;
+    let (signed, bytes) = build_release(manifest, &files, &coordinator)?;
+    fs::write(output, &bytes)?;
+    info!(
+        "{} files of the pool signed by {} and written to {} \n",
+        signed.release.files.len(),
+        signed.coordinator,
+        output.display()
+    );
+
+    let uploaded_to = match to {
+        Some(to) => {
+            let location = upload_release(to, output, &bytes).await?;
+            info!("release uploaded to {} \n", location);
+            Some(location)
+        }
+        None => None,
+    };
+    Ok(Publication {
+        archive: output.to_path_buf(),
+        sha256: file_sha256(&bytes),
+        size: bytes.len() as u64,
+        coordinator: signed.coordinator,
+        files: signed.release.files,
+        uploaded_to,
+    })
+}
+
+pub fn print_publication(publication: &Publication) {
+    println!("archive: {}", publication.archive.display());
+    println!("sha256: {}", publication.sha256);
+    println!("size: {} bytes", publication.size);
+    println!("coordinator: {}", publication.coordinator);
+    for file in &publication.files {
+        println!("  {} ({} bytes)", file.path, file.size);
+    }
+    if let Some(location) = &publication.uploaded_to {
+        println!("uploaded to: {}", location);
+    }
+}
@@ -0,0 +1,197 @@
+use std::{fs, path::Path, path::PathBuf};
+
+use anyhow::{anyhow, bail, Result};
+use bitcoin::{
+    block::Header,
+    consensus::encode::{deserialize_hex, serialize_hex},
+    hex::DisplayHex,
+    Address, MerkleBlock, Network, Transaction, Txid,
+};
+use bitcoincore_rpc::RpcApi;
+use serde::{Deserialize, Serialize};
+use tracing::{info, trace};
+
+use crate::{ids::UserIndex, redact, rpc_helper::AsyncRpc};
+
+// how many headers (the block with the exit + the ones on top of it) go in a receipt
+pub const RECEIPT_HEADERS: usize = 6;
+
+// Everything a user needs to show a third party their withdrawal happened, without the coordinator.
+// All bitcoin data is consensus encoded hex so it can be checked with any other tool too.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WithdrawalReceipt {
+    pub user: UserIndex,
+    pub network: Network,
+    pub withdraw_address: String,
+    pub txid: Txid,
+    pub tx: String,
+    // gettxoutproof output for the block containing the tx
+    pub merkle_proof: String,
+    // headers starting at the block containing the tx, each one building on the previous
+    pub headers: Vec<String>,
+}
+
+pub struct VerifiedReceipt {
+    pub txid: Txid,
+    pub amount: bitcoin::Amount,
+    pub block_hash: bitcoin::BlockHash,
+    pub confirmations: usize,
+    pub tip_hash: bitcoin::BlockHash,
+}
+
+// returns None if the exit is not confirmed yet
+pub async fn create_receipt(
+    rpc: &AsyncRpc,
+    network: Network,
+    user: UserIndex,
+    txid: Txid,
+    withdraw_address: &Address,
+) -> Result<Option<WithdrawalReceipt>> {
+    let wallet_tx = rpc.run(move |c| c.get_transaction(&txid, None)).await?;
+    let Some(block_hash) = wallet_tx.info.blockhash else {
+        return Ok(None);
+    };
+
+    let merkle_proof = rpc
+        .run(move |c| c.get_tx_out_proof(&[txid], Some(&block_hash)))
+        .await?;
+
+    let mut headers = Vec::new();
+    let mut next_hash = Some(block_hash);
+    while let Some(hash) = next_hash {
+        if headers.len() == RECEIPT_HEADERS {
+            break;
+        }
+        let header_info = rpc.run(move |c| c.get_block_header_info(&hash)).await?;
+        let header = rpc.run(move |c| c.get_block_header(&hash)).await?;
+        headers.push(serialize_hex(&header));
+        next_hash = header_info.next_block_hash;
+    }
+
+    Ok(Some(WithdrawalReceipt {
+        user,
+        network,
+        withdraw_address: withdraw_address.to_string(),
+        txid,
+        tx: wallet_tx.hex.to_lower_hex_string(),
+        merkle_proof: merkle_proof.to_lower_hex_string(),
+        headers,
+    }))
+}
+
+pub fn write_receipt(receipt: &WithdrawalReceipt, dir: &Path) -> Result<PathBuf> {
+    fs::create_dir_all(dir)?;
+    let path = dir.join(format!("user_{}_{}.json", receipt.user, receipt.txid));
+    fs::write(&path, serde_json::to_string_pretty(receipt)?)?;
+    info!(
+        "withdrawal receipt for user {}: {} \n",
+        receipt.user,
+        path.display()
+    );
+    Ok(path)
+}
+
+// Offline check: the tx pays the withdraw address, it is committed to by the merkle proof,
+// and the proof's block is the first of a chain of headers with valid proof of work at a target
+// the network allows.
+// The verifier still has to compare the tip hash with a node/explorer they trust.
+pub fn verify_receipt(receipt: &WithdrawalReceipt) -> Result<VerifiedReceipt> {
+    let tx: Transaction = deserialize_hex(&receipt.tx)?;
+    let txid = tx.compute_txid();
+    if txid != receipt.txid {
+        bail!("receipt tx hashes to {} but claims {}", txid, receipt.txid);
+    }
+
+    let withdraw_address = receipt
+        .withdraw_address
+        .parse::<Address<_>>()?
+        .require_network(receipt.network)?;
+    let amount = tx
+        .output
+        .iter()
+        .find(|out| out.script_pubkey == withdraw_address.script_pubkey())
+        .map(|out| out.value)
+        .ok_or_else(|| anyhow!("tx {} does not pay {}", txid, withdraw_address))?;
+
+    let merkle_block: MerkleBlock = deserialize_hex(&receipt.merkle_proof)?;
+    let mut matches = Vec::new();
+    let mut indexes = Vec::new();
+    let merkle_root = merkle_block
+        .txn
+        .extract_matches(&mut matches, &mut indexes)
+        .map_err(|e| anyhow!("invalid merkle proof: {:?}", e))?;
+    if merkle_root != merkle_block.header.merkle_root {
+        bail!("merkle proof does not match the block header merkle root");
+    }
+    if !matches.contains(&txid) {
+        bail!("merkle proof does not include {}", txid);
+    }
+
+    let headers = receipt
+        .headers
+        .iter()
+        .map(|h| deserialize_hex::<Header>(h))
+        .collect::<Result<Vec<_>, _>>()?;
+    let first = headers
+        .first()
+        .ok_or_else(|| anyhow!("receipt has no block headers"))?;
+    if *first != merkle_block.header {
+        bail!("first header is not the block from the merkle proof");
+    }
+
+    // a header sets its own target, one above the network's limit is work nobody had to do
+    let max_target = receipt.network.params().max_attainable_target;
+    let mut previous_hash = None;
+    for header in &headers {
+        if header.target() > max_target {
+            bail!(
+                "header {} claims an easier target than {} allows",
+                header.block_hash(),
+                receipt.network
+            );
+        }
+        let hash = header.validate_pow(header.target()).map_err(|e| {
+            anyhow!(
+                "header {} has invalid proof of work: {}",
+                header.block_hash(),
+                e
+            )
+        })?;
+        if let Some(previous_hash) = previous_hash {
+            if header.prev_blockhash != previous_hash {
+                bail!("header {} does not build on {}", hash, previous_hash);
+            }
+        }
+        previous_hash = Some(hash);
+    }
+
+    Ok(VerifiedReceipt {
+        txid,
+        amount,
+        block_hash: first.block_hash(),
+        confirmations: headers.len(),
+        tip_hash: previous_hash.unwrap(),
+    })
+}
+
+pub fn verify_receipt_file(path: &Path) -> Result<()> {
+    let receipt: WithdrawalReceipt = serde_json::from_str(&fs::read_to_string(path)?)?;
+    let verified = verify_receipt(&receipt)?;
+
+    info!("receipt for user {} is valid:", receipt.user);
+    info!(
+        "  Withdraw address: {}",
+        redact::short(&receipt.withdraw_address)
+    );
+    trace!("  Withdraw address: {}", receipt.withdraw_address);
+    info!("  Amount: {}", verified.amount);
+    info!("  TXID: {}", verified.txid);
+    info!("  Block: {}", verified.block_hash);
+    info!("  Confirmations in receipt: {}", verified.confirmations);
+    info!(
+        "  Check this tip is on the {} chain you trust: {}",
+        receipt.network, verified.tip_hash
+    );
+
+    Ok(())
+}
@@ -0,0 +1,142 @@
+use std::str::FromStr;
+
+use anyhow::{anyhow, Result};
+use bitcoin::{
+    absolute, taproot::TaprootSpendInfo, transaction, Address, Amount, Network, OutPoint,
+    ScriptBuf, Sequence, Transaction, TxIn, TxOut,
+};
+
+use crate::{
+    config::{ConfigProblem, NetworkConfig, FEE_AMOUNT},
+    ctv_scripts::calc_ctv_hash,
+    policy::LeafPolicy,
+};
+
+// blocks a pool node has to sit unspent before it can be swept, about a day
+pub const DEFAULT_RECOVERY_TIMEOUT: u16 = 144;
+
+// Optional extra leaf on every pool node: once the node has been unspent for `timeout` blocks
+// anyone can sweep it to `address` (e.g. a multisig the members agreed on), so a pool nobody
+// unwinds doesn't lock the funds forever.
+#[derive(Debug, Clone)]
+pub struct RecoveryPath {
+    pub address: Address,
+    pub timeout: u16,
+}
+
+impl RecoveryPath {
+    // RECOVERY_ADDRESS enables it, RECOVERY_TIMEOUT_BLOCKS changes the timeout
+    pub fn from_env(network: Network) -> Result<Option<Self>, ConfigProblem> {
+        let address = NetworkConfig::get_env_var("RECOVERY_ADDRESS", "");
+        if address.is_empty() {
+            return Ok(None);
+        }
+
+        let address = Address::from_str(&address)
+            .map_err(|_| ConfigProblem::invalid_value("RECOVERY_ADDRESS", &address))?
+            .require_network(network)
+            .map_err(|_| ConfigProblem::InvalidEnv {
+                var: "RECOVERY_ADDRESS".to_string(),
+                reason: format!("is not a {} address", network),
+            })?;
+        let timeout = NetworkConfig::get_env_var(
+            "RECOVERY_TIMEOUT_BLOCKS",
+            &DEFAULT_RECOVERY_TIMEOUT.to_string(),
+        );
+        let timeout = timeout
+            .parse()
+            .map_err(|_| ConfigProblem::invalid_value("RECOVERY_TIMEOUT_BLOCKS", &timeout))?;
+
+        Ok(Some(Self { address, timeout }))
+    }
+
+    pub fn sequence(&self) -> Sequence {
+        Sequence::from_height(self.timeout)
+    }
+
+    // everything in a node holding `amount` goes to the recovery address, minus the fee like a withdrawal
+    pub fn outputs(
+        &self,
+        amount: Amount,
+        anchor_addr: &Address,
+        config: &NetworkConfig,
+    ) -> Vec<TxOut> {
+        let mut outputs = vec![TxOut {
+            value: amount - FEE_AMOUNT,
+            script_pubkey: self.address.script_pubkey(),
+        }];
+
+        if let Some(anchor_amount) = config.anchor_amount {
+            outputs.push(TxOut {
+                value: anchor_amount,
+                script_pubkey: anchor_addr.script_pubkey(),
+            });
+        }
+
+        outputs
+    }
+
+    // CTV commits to the input sequence, so the sweep can only be spent with the timeout set
+    pub fn template_hash(&self, outputs: &[TxOut], config: &NetworkConfig) -> [u8; 32] {
+        calc_ctv_hash(config.tx_version, outputs, &[self.sequence()])
+    }
+
+    // <timeout> OP_CSV OP_DROP <sweep hash> OP_CTV
+    pub fn leaf_script(&self, template_hash: [u8; 32]) -> ScriptBuf {
+        LeafPolicy::older(self.timeout)
+            .and(LeafPolicy::ctv(template_hash))
+            .script()
+    }
+
+    pub fn node_leaf(
+        &self,
+        amount: Amount,
+        anchor_addr: &Address,
+        config: &NetworkConfig,
+    ) -> ScriptBuf {
+        let outputs = self.outputs(amount, anchor_addr, config);
+        self.leaf_script(self.template_hash(&outputs, config))
+    }
+
+    // the fully signed (well, witnessed) sweep of the node at `outpoint`
+    pub fn sweep_tx(
+        &self,
+        outpoint: OutPoint,
+        amount: Amount,
+        spend_info: &TaprootSpendInfo,
+        anchor_addr: &Address,
+        config: &NetworkConfig,
+    ) -> Result<Transaction> {
+        let leaf_script = self.node_leaf(amount, anchor_addr, config);
+        let control_block = spend_info
+            .control_block(&(leaf_script.clone(), config.leaf_version))
+            .ok_or_else(|| anyhow!("pool node has no recovery leaf"))?;
+
+        let mut input = TxIn {
+            previous_output: outpoint,
+            sequence: self.sequence(),
+            ..Default::default()
+        };
+        input.witness.push(leaf_script.into_bytes());
+        input.witness.push(control_block.serialize());
+
+        Ok(Transaction {
+            version: transaction::Version(config.tx_version),
+            lock_time: absolute::LockTime::ZERO,
+            input: vec![input],
+            output: self.outputs(amount, anchor_addr, config),
+        })
+    }
+}
+
+// the recovery leaf for a node holding `amount`, if the pool has a recovery path
+pub fn recovery_leaf(
+    config: &NetworkConfig,
+    amount: Amount,
+    anchor_addr: &Address,
+) -> Option<ScriptBuf> {
+    config
+        .recovery
+        .as_ref()
+        .map(|recovery| recovery.node_leaf(amount, anchor_addr, config))
+}
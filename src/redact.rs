@@ -0,0 +1,46 @@
+use std::{
+    fmt::Display,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use bitcoin::hashes::{sha256, Hash};
+
+// Whether info logs carry raw tx hex, keys, addresses and wallet coins in full. Set from the config's
+// log_sensitive (POOL_LOG_SENSITIVE, on by default on regtest only), redacted until then.
+// Every redacted line has a trace! next to it with the full value, RUST_LOG=op_ctv_payment_pool=trace.
+static LOG_SENSITIVE: AtomicBool = AtomicBool::new(false);
+
+pub fn set_log_sensitive(enabled: bool) {
+    LOG_SENSITIVE.store(enabled, Ordering::Relaxed);
+}
+
+pub fn log_sensitive() -> bool {
+    LOG_SENSITIVE.load(Ordering::Relaxed)
+}
+
+// characters kept at each end of a shortened value, enough to tell addresses apart in a log
+const KEEP_START: usize = 8;
+const KEEP_END: usize = 4;
+
+// Raw tx or psbt hex as its size and the start of the sha256 of the hex (not the txid), so two
+// log lines about the same tx can still be matched up
+pub fn hex(hex: &str) -> String {
+    if log_sensitive() {
+        return hex.to_string();
+    }
+    let digest = sha256::Hash::hash(hex.as_bytes()).to_string();
+    format!("<{} bytes, sha256 {}..>", hex.len() / 2, &digest[..12])
+}
+
+// An address, key, script or the txid of a wallet coin, with only both ends kept
+pub fn short(value: impl Display) -> String {
+    let value = value.to_string();
+    if log_sensitive() || !value.is_ascii() || value.len() <= KEEP_START + KEEP_END + 2 {
+        return value;
+    }
+    format!(
+        "{}..{}",
+        &value[..KEEP_START],
+        &value[value.len() - KEEP_END..]
+    )
+}
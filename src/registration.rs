@@ -0,0 +1,338 @@
+use std::{collections::HashMap, fs, path::Path};
+
+use anyhow::{anyhow, bail, Result};
+use bitcoin::{
+    address::NetworkUnchecked,
+    bip32::{ChildNumber, Xpub},
+    hashes::{sha256, Hash, HashEngine},
+    key::Secp256k1,
+    secp256k1::PublicKey,
+    Address, Network, NetworkKind, XOnlyPublicKey,
+};
+use serde::{Deserialize, Serialize};
+use tracing::{info, trace, warn};
+
+use crate::{
+    addresses::{check_address, json_entries, AddressChecker, AddressProblem},
+    bip322,
+    ids::{PoolId, UserIndex},
+    lifecycle::Lifecycle,
+    manifest::PoolManifest,
+    musig::MusigKeys,
+    payouts::{check_splits, PayoutSplits, SplitPayout},
+    redact,
+    user_anchor::AnchorKeys,
+    POOL_USERS,
+};
+
+// A participant's withdraw address, with a BIP-322 signature over the pool id made with its key.
+// Stops people registering addresses they don't control (e.g. someone else's) to grief the pool.
+// Instead of an address a participant can register an xpub, every pool then pays a fresh address
+// derived from it and the signature is made with its first address (child 0).
+// A participant can also split their exit over more of their addresses, the splits are part of
+// the signed message (see registration_message) so nobody else can redirect any of it. So is the
+// anchor key a participant bumping their own exit registers, and the key they sign a key path
+// close with.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Registration {
+    #[serde(default)]
+    pub address: Option<Address<NetworkUnchecked>>,
+    #[serde(default)]
+    pub xpub: Option<Xpub>,
+    pub signature: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub splits: Vec<SplitPayout>,
+    // key the anchor of the user's exit pays instead of the pool's, see user_anchor.rs
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub anchor_key: Option<XOnlyPublicKey>,
+    // key the user signs the pool root's key path with, see musig.rs and key_close.rs
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub musig_key: Option<PublicKey>,
+}
+
+// What a registration signs: the pool id, then one "address:sats" line per split, an
+// "anchor:<key>" line for an anchor key and a "musig:<key>" line for a musig key
+pub fn registration_message(
+    pool_id: &PoolId,
+    splits: &[SplitPayout],
+    anchor_key: Option<&XOnlyPublicKey>,
+    musig_key: Option<&PublicKey>,
+) -> String {
+    let mut message = pool_id.as_str().to_string();
+    for split in splits {
+        message.push_str(&format!(
+            "\n{}:{}",
+            split.address.assume_checked_ref(),
+            split.amount.to_sat()
+        ));
+    }
+    if let Some(key) = anchor_key {
+        message.push_str(&format!("\nanchor:{}", key));
+    }
+    if let Some(key) = musig_key {
+        message.push_str(&format!("\nmusig:{}", key));
+    }
+    message
+}
+
+// Which child of a registered xpub a user's withdraw address is, so the next pool can move on to a fresh one
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AddressDerivation {
+    pub user: UserIndex,
+    pub xpub: Xpub,
+    pub index: u32,
+}
+
+// key path only p2tr of child `index`, the same derivation payroll uses per epoch
+pub fn xpub_address(xpub: &Xpub, index: u32, network: Network) -> Result<Address> {
+    if xpub.network != NetworkKind::from(network) {
+        bail!("xpub {} is not for {}", xpub, network);
+    }
+    let secp = Secp256k1::verification_only();
+    let child = xpub.derive_pub(&secp, &[ChildNumber::from_normal_idx(index)?])?;
+    Ok(Address::p2tr(&secp, child.to_x_only_pub(), None, network))
+}
+
+// the child after the last one `previous` used for `xpub`, or the first one.
+// `previous` being this same pool (e.g. built by serve) keeps the child it was built with, and so
+// does a cancelled one: nothing was ever paid to it, the next pool takes its addresses over
+fn next_index(xpub: &Xpub, pool_id: &PoolId, previous: Option<&PoolManifest>) -> u32 {
+    let same_pool = previous.is_some_and(|manifest| {
+        manifest.pool_id.as_ref() == Some(pool_id) || manifest.lifecycle == Lifecycle::Cancelled
+    });
+    previous
+        .into_iter()
+        .flat_map(|manifest| &manifest.derivations)
+        .filter(|derivation| derivation.xpub == *xpub)
+        .map(|derivation| {
+            if same_pool {
+                derivation.index
+            } else {
+                derivation.index.saturating_add(1)
+            }
+        })
+        .max()
+        .unwrap_or(0)
+}
+
+// What the registrations give the pool: the withdraw addresses in order, and the splits, anchor keys
+// and musig keys keyed by the withdraw address they belong to
+#[derive(Debug, Default)]
+pub struct RegisteredUsers {
+    pub addresses: Vec<Address>,
+    pub derivations: Vec<AddressDerivation>,
+    pub splits: PayoutSplits,
+    pub anchor_keys: AnchorKeys,
+    pub musig_keys: MusigKeys,
+}
+
+// Read a json list of registrations and keep the withdraw addresses, in order, if every proof checks out.
+// All bad entries are reported at once so the coordinator can chase them in one go.
+// xpubs get the child after the one they were paid to in `previous`, the manifest of the last pool.
+pub fn load_registrations(
+    path: &Path,
+    network: Network,
+    pool_id: &PoolId,
+    previous: Option<&PoolManifest>,
+) -> Result<RegisteredUsers> {
+    let text = fs::read_to_string(path)?;
+    let entries = json_entries(&text)?;
+    if entries.len() != POOL_USERS {
+        bail!(
+            "{} registrations but the pool is for {} users (POOL_USERS)",
+            entries.len(),
+            POOL_USERS
+        );
+    }
+
+    let mut addresses = Vec::new();
+    let mut derivations = Vec::new();
+    let mut splits = PayoutSplits::new();
+    let mut anchor_keys = AnchorKeys::new();
+    let mut musig_keys = MusigKeys::new();
+    let mut checker = AddressChecker::new(network);
+    let mut failed = 0;
+    for (i, (line, raw)) in entries.into_iter().enumerate() {
+        let registration: Registration = match serde_json::from_str(raw.get()) {
+            Ok(registration) => registration,
+            Err(e) => {
+                checker.reject(i, Some(line), AddressProblem::Invalid(e.to_string()));
+                continue;
+            }
+        };
+        // network, script and duplicates first, a proof for an address we can't pay is no use
+        if let Some(address) = &registration.address {
+            if checker.check(i, Some(line), address).is_none() {
+                continue;
+            }
+        }
+        // a split to another user's address would pay them twice
+        if !registration
+            .splits
+            .iter()
+            .all(|split| checker.check(i, Some(line), &split.address).is_some())
+        {
+            continue;
+        }
+        match verify_registration(
+            UserIndex::new(i)?,
+            &registration,
+            network,
+            pool_id,
+            previous,
+        ) {
+            Ok((address, derivation)) => {
+                // an xpub's child can still be someone else's address
+                if derivation.is_some() && checker.checked(i, Some(line), address.clone()).is_none()
+                {
+                    continue;
+                }
+                info!("registration {} verified: {}", i, redact::short(&address));
+                trace!("registration {} verified: {}", i, address);
+                if let Some(key) = registration.anchor_key {
+                    anchor_keys.insert(address.to_string(), key);
+                }
+                if let Some(key) = registration.musig_key {
+                    musig_keys.insert(address.to_string(), key);
+                }
+                if !registration.splits.is_empty() {
+                    splits.insert(address.to_string(), registration.splits);
+                }
+                addresses.push(address);
+                derivations.extend(derivation);
+            }
+            Err(e) => {
+                warn!("registration {} (line {}): {}", i, line, e);
+                failed += 1;
+            }
+        }
+    }
+
+    for error in checker.errors() {
+        warn!("registration {}", error);
+    }
+    checker.finish()?;
+    if failed > 0 {
+        bail!(
+            "{} of {} registrations have no valid ownership proof over pool id {:?}",
+            failed,
+            POOL_USERS,
+            pool_id
+        );
+    }
+    // they would all be paid to the same address
+    let mut xpubs = HashMap::new();
+    for derivation in &derivations {
+        if let Some(first) = xpubs.insert(derivation.xpub, derivation.user) {
+            bail!(
+                "users {} and {} registered the same xpub",
+                first,
+                derivation.user
+            );
+        }
+    }
+
+    Ok(RegisteredUsers {
+        addresses,
+        derivations,
+        splits,
+        anchor_keys,
+        musig_keys,
+    })
+}
+
+// Check the ownership proof of `user`'s registration, returns the address the pool pays them
+// and, for an xpub, which child that is
+pub fn verify_registration(
+    user: UserIndex,
+    registration: &Registration,
+    network: Network,
+    pool_id: &PoolId,
+    previous: Option<&PoolManifest>,
+) -> Result<(Address, Option<AddressDerivation>)> {
+    // the address that signed and the one the pool pays, the same unless an xpub was registered
+    let (signer, address, derivation) = match (&registration.address, &registration.xpub) {
+        (Some(address), None) => {
+            let address = check_address(address, network).map_err(|e| anyhow!("{}", e))?;
+            (address.clone(), address, None)
+        }
+        (None, Some(xpub)) => {
+            let index = next_index(xpub, pool_id, previous);
+            let derivation = AddressDerivation {
+                user,
+                xpub: *xpub,
+                index,
+            };
+            (
+                xpub_address(xpub, 0, network)?,
+                xpub_address(xpub, index, network)?,
+                Some(derivation),
+            )
+        }
+        _ => bail!("needs either an address or an xpub"),
+    };
+    check_splits(&registration.splits, network)?;
+    bip322::verify_simple(
+        &signer,
+        &registration_message(
+            pool_id,
+            &registration.splits,
+            registration.anchor_key.as_ref(),
+            registration.musig_key.as_ref(),
+        ),
+        &registration.signature,
+    )
+    .map_err(|e| anyhow!("{}: {}", signer, e))?;
+    Ok((address, derivation))
+}
+
+// Where every registration ends up in the tree: `order[position]` is the registration at that
+// position. A Fisher-Yates shuffle seeded from the pool id, so the tree doesn't give away who
+// registered first but anyone with the pool id gets the same order.
+pub fn leaf_order(pool_id: &PoolId) -> Vec<UserIndex> {
+    let mut seed = sha256::Hash::engine();
+    seed.input(b"ctv payment pool leaf order");
+    seed.input(pool_id.as_str().as_bytes());
+    let seed = sha256::Hash::from_engine(seed);
+
+    let mut order: Vec<UserIndex> = UserIndex::all().collect();
+    for (counter, i) in (1..order.len()).rev().enumerate() {
+        let mut draw = sha256::Hash::engine();
+        draw.input(seed.as_byte_array());
+        draw.input(&(counter as u64).to_le_bytes());
+        let draw = sha256::Hash::from_engine(draw);
+        let mut bytes = [0u8; 8];
+        bytes.copy_from_slice(&draw.as_byte_array()[..8]);
+        // the modulo bias is negligible for any pool that fits a block
+        let j = (u64::from_le_bytes(bytes) % (i as u64 + 1)) as usize;
+        order.swap(i, j);
+    }
+    order
+}
+
+// Put registrations in leaf order, derivations then name the user by their position in the tree
+pub fn shuffle_registrations(
+    pool_id: &PoolId,
+    addresses: &[Address],
+    derivations: &[AddressDerivation],
+) -> Result<(Vec<Address>, Vec<AddressDerivation>, Vec<UserIndex>)> {
+    let order = leaf_order(pool_id);
+    let mut positions = vec![0; order.len()];
+    for (position, registration) in order.iter().enumerate() {
+        positions[registration.index()] = position;
+    }
+    let shuffled = order
+        .iter()
+        .map(|registration| addresses[registration.index()].clone())
+        .collect();
+    let derivations = derivations
+        .iter()
+        .map(|derivation| {
+            Ok(AddressDerivation {
+                user: UserIndex::new(positions[derivation.user.index()])?,
+                ..derivation.clone()
+            })
+        })
+        .collect::<Result<_>>()?;
+    Ok((shuffled, derivations, order))
+}
@@ -0,0 +1,177 @@
+use anyhow::{anyhow, bail, Result};
+use bitcoin::Amount;
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use crate::{
+    config::NetworkConfig,
+    error_codes::{coded, ErrorCode},
+    ids::{NodePath, UserIndex},
+    manifest::PoolManifest,
+    payouts::{jittered_share, PayoutJitter},
+    POOL_USERS,
+};
+
+// A participant who dropped out before the pool was funded. Their position stays in the tree (every
+// other user's templates are built around it), the pool is funded at the node without them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Removal {
+    pub user: UserIndex,
+    // what their share added to the others', zero when the funding target went down instead
+    pub redistributed: Amount,
+}
+
+// What happens to the share of a participant who is removed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum Rebalance {
+    // the pool is funded with less, everyone else keeps their share
+    #[default]
+    ReduceFunding,
+    // the funding stays, their share goes to the others in proportion to what they have
+    ProRata,
+}
+
+// A pool needs at least this many users left, like a config (ConfigProblem::TooFewUsers)
+pub const MIN_MEMBERS: usize = 3;
+
+// the node the pool is funded at: every user but the removed ones
+pub fn pool_members(removed: &[Removal]) -> Result<NodePath> {
+    let members: Vec<usize> = UserIndex::all()
+        .filter(|user| !removed.iter().any(|removal| removal.user == *user))
+        .map(UserIndex::index)
+        .collect();
+    if members.len() < MIN_MEMBERS {
+        bail!(
+            "{} of {} users removed, a pool needs at least {} left",
+            removed.len(),
+            POOL_USERS,
+            MIN_MEMBERS
+        );
+    }
+    NodePath::new(members)
+}
+
+// The removals a manifest or config records: users in the pool, each once, in the order removed
+pub fn check_removals(removed: &[Removal], jitter: &PayoutJitter) -> Result<()> {
+    for (i, removal) in removed.iter().enumerate() {
+        if removed[..i]
+            .iter()
+            .any(|earlier| earlier.user == removal.user)
+        {
+            bail!("user {} is removed twice", removal.user);
+        }
+    }
+    pool_members(removed)?;
+    let redistributed = redistributed(removed)?;
+    if redistributed > Amount::ZERO && jitter.is_empty() {
+        bail!(
+            "{} of removed shares redistributed but the pool has no per user shares (payout jitter)",
+            redistributed
+        );
+    }
+    Ok(())
+}
+
+// what the removed users' shares added to the others'
+pub fn redistributed(removed: &[Removal]) -> Result<Amount> {
+    removed
+        .iter()
+        .try_fold(Amount::ZERO, |total, removal| {
+            total.checked_add(removal.redistributed)
+        })
+        .ok_or_else(|| anyhow!("the redistributed shares overflow"))
+}
+
+// `share` handed to `members` in proportion to what each has, the sats rounding leaves over one
+// each from the first member on. Moves `jitter` (one entry per position) by what each one gets
+fn redistribute(jitter: &mut PayoutJitter, members: &NodePath, share: Amount) -> Result<()> {
+    let shares: Vec<u64> = members
+        .user_indices()
+        .map(|user| jittered_share(user, jitter).to_sat())
+        .collect();
+    let total: u128 = shares.iter().map(|&sats| sats as u128).sum();
+    let mut handed_out = 0;
+    let mut extras: Vec<u64> = shares
+        .iter()
+        .map(|&sats| {
+            let extra = (share.to_sat() as u128 * sats as u128 / total) as u64;
+            handed_out += extra;
+            extra
+        })
+        .collect();
+    for extra in extras
+        .iter_mut()
+        .take((share.to_sat() - handed_out) as usize)
+    {
+        *extra += 1;
+    }
+    for (user, extra) in members.user_indices().zip(extras) {
+        jitter[user.index()] = jitter[user.index()]
+            .checked_add(i64::try_from(extra)?)
+            .ok_or_else(|| anyhow!("the share of user {} overflows", user))?;
+    }
+    Ok(())
+}
+
+// Take `user` out of a pool that isn't funded yet. The tree is rebuilt and the pool funded at the
+// node of the users left, with the removed share going where `rebalance` says. The manifest comes
+// back with the new root address and its revision bumped, every member needs the new one.
+pub fn remove_participant(
+    manifest: &PoolManifest,
+    user: UserIndex,
+    rebalance: Rebalance,
+) -> Result<PoolManifest> {
+    if manifest.lifecycle.is_funded() || manifest.funding_txid.is_some() {
+        return Err(coded(
+            ErrorCode::InvalidState,
+            format!(
+                "can't remove user {} from a pool that is {}, only before it is funded",
+                user, manifest.lifecycle
+            ),
+        ));
+    }
+    if manifest.lifecycle.is_finished() {
+        return Err(coded(
+            ErrorCode::InvalidState,
+            format!(
+                "can't remove user {} from a pool that is {}",
+                user, manifest.lifecycle
+            ),
+        ));
+    }
+    if manifest.removed.iter().any(|removal| removal.user == user) {
+        bail!("user {} was removed already", user);
+    }
+
+    let mut removed = manifest.removed.clone();
+    removed.push(Removal {
+        user,
+        redistributed: Amount::ZERO,
+    });
+    let members = pool_members(&removed)?;
+    let mut next = manifest.clone();
+    if rebalance == Rebalance::ProRata {
+        if next.payout_jitter.is_empty() {
+            next.payout_jitter = vec![0; POOL_USERS];
+        }
+        let share = jittered_share(user, &next.payout_jitter);
+        redistribute(&mut next.payout_jitter, &members, share)?;
+        removed.last_mut().expect("just pushed").redistributed = share;
+    }
+    next.removed = removed;
+
+    let pool = next.rebuild_pool(NetworkConfig::new(next.profile))?;
+    let root = pool.tree.node(&members)?.address(&pool.config);
+    next.root_address = root.to_string();
+    next.revision += 1;
+    info!(
+        "user {} removed, the pool of users {} is funded with {} at {}, revision {} \n",
+        user,
+        members,
+        next.funding_target()?,
+        next.root_address,
+        next.revision
+    );
+    Ok(next)
+}
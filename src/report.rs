@@ -0,0 +1,526 @@
+use std::{collections::HashMap, fmt::Write as _, fs, path::Path};
+
+use anyhow::{anyhow, bail, Result};
+use bitcoin::{Address, Amount, Network, OutPoint, Script, Transaction, TxOut, Txid};
+use bitcoincore_rpc::RpcApi;
+use serde::Serialize;
+use tracing::{info, warn};
+
+use crate::{
+    fetch::RpcFetcher,
+    ids::{NodePath, UserIndex},
+    manifest::PoolManifest,
+    metadata::PoolMetadata,
+    payouts::user_scripts,
+    rpc_helper::{connect_fee_payer, AsyncRpc},
+    watch::{classify_spend, next_node_output},
+    webhooks::PoolEvent,
+};
+
+// blocks to keep looking for anchor children after the last pool tx
+const ANCHOR_LOOKAHEAD: u64 = 6;
+
+// One line of the pool ledger. `level` is the number of users in the node a pool tx spent.
+#[derive(Debug, Clone, Serialize)]
+pub struct LedgerEntry {
+    // funding_input, funding_change, funding_fee, pool_deposit, payout, pool_fee, anchor, cpfp_fee,
+    // recovery_sweep, unexpected_spend or in_pool (still unspent at the tip)
+    pub kind: &'static str,
+    pub level: Option<usize>,
+    pub user: Option<UserIndex>,
+    pub address: Option<String>,
+    pub amount: Amount,
+    pub txid: Txid,
+    pub vout: Option<u32>,
+    pub block_height: Option<u64>,
+    pub block_time: Option<u64>,
+}
+
+struct Ledger {
+    network: Network,
+    entries: Vec<LedgerEntry>,
+}
+
+impl Ledger {
+    #[allow(clippy::too_many_arguments)]
+    fn push(
+        &mut self,
+        kind: &'static str,
+        level: Option<usize>,
+        user: Option<UserIndex>,
+        script_pubkey: Option<&Script>,
+        amount: Amount,
+        txid: Txid,
+        vout: Option<u32>,
+        block: Option<(u64, u64)>,
+    ) {
+        self.entries.push(LedgerEntry {
+            kind,
+            level,
+            user,
+            address: script_pubkey.and_then(|spk| {
+                Address::from_script(spk, self.network)
+                    .ok()
+                    .map(|address| address.to_string())
+            }),
+            amount,
+            txid,
+            vout,
+            block_height: block.map(|(height, _)| height),
+            block_time: block.map(|(_, time)| time),
+        });
+    }
+}
+
+// the output spent at `outpoint`, from the txs fetched already, one of the wallets or the node
+async fn prevout(
+    wallets: &[&AsyncRpc],
+    fetched: &HashMap<Txid, Transaction>,
+    outpoint: OutPoint,
+) -> Result<TxOut> {
+    let txid = outpoint.txid;
+    if let Some(tx) = fetched.get(&txid) {
+        return tx
+            .output
+            .get(outpoint.vout as usize)
+            .cloned()
+            .ok_or_else(|| anyhow!("{} has no output {}", txid, outpoint.vout));
+    }
+    for wallet in wallets {
+        if let Ok(wallet_tx) = wallet.run(move |c| c.get_transaction(&txid, None)).await {
+            let tx = wallet_tx.transaction()?;
+            return tx
+                .output
+                .get(outpoint.vout as usize)
+                .cloned()
+                .ok_or_else(|| anyhow!("{} has no output {}", txid, outpoint.vout));
+        }
+    }
+    let tx = wallets[0]
+        .run(move |c| c.get_raw_transaction(&txid, None))
+        .await?;
+    tx.output
+        .get(outpoint.vout as usize)
+        .cloned()
+        .ok_or_else(|| anyhow!("{} has no output {}", txid, outpoint.vout))
+}
+
+// what the inputs of `tx` other than `skip` bring in
+async fn total_in(
+    wallets: &[&AsyncRpc],
+    fetched: &HashMap<Txid, Transaction>,
+    tx: &Transaction,
+    skip: Option<OutPoint>,
+) -> Result<Amount> {
+    let mut total = Amount::ZERO;
+    for input in &tx.input {
+        if Some(input.previous_output) != skip {
+            total += prevout(wallets, fetched, input.previous_output)
+                .await?
+                .value;
+        }
+    }
+    Ok(total)
+}
+
+fn spent_txids(tx: &Transaction) -> Vec<Txid> {
+    tx.input
+        .iter()
+        .map(|input| input.previous_output.txid)
+        .collect()
+}
+
+fn total_out(tx: &Transaction) -> Amount {
+    tx.output.iter().map(|output| output.value).sum()
+}
+
+// `spent` minus the outputs of `tx`, None if the tx took more than that from somewhere else
+fn fee(spent: Amount, tx: &Transaction) -> Option<Amount> {
+    spent.checked_sub(total_out(tx))
+}
+
+// Every sat in and out of the pool: the funding tx, then the pool txs found by following the pool
+// output block by block from the funding block, with the anchor children paying for them.
+pub async fn pool_ledger(manifest: &PoolManifest) -> Result<Vec<LedgerEntry>> {
+    manifest.lifecycle.require_funded()?;
+    let funding_txid = manifest
+        .funding_txid
+        .ok_or_else(|| anyhow!("the manifest has no funding txid, the pool was never funded"))?;
+    let pool = manifest.load_pool()?;
+    let config = &pool.config;
+    let rpc = AsyncRpc::connect(config).await?;
+    let fee_payer = connect_fee_payer(config, &rpc).await?;
+    let wallets = [&rpc, &fee_payer];
+    // blocks and the txs their inputs spend, many per round trip
+    let fetcher = RpcFetcher::connect(&rpc, config).await?;
+    let mut ledger = Ledger {
+        network: config.network,
+        entries: Vec::new(),
+    };
+
+    let funding = rpc
+        .run(move |c| c.get_transaction(&funding_txid, None))
+        .await?;
+    let (Some(funding_height), Some(funding_time)) =
+        (funding.info.blockheight, funding.info.blocktime)
+    else {
+        bail!("funding tx {} is not confirmed yet", funding_txid);
+    };
+    let funding_block = Some((u64::from(funding_height), funding_time));
+    let funding_tx = funding.transaction()?;
+
+    let root = pool.config.pool_root()?;
+    let root_spk = Address::p2tr_tweaked(pool.tree.spend_info(&root)?.output_key(), config.network)
+        .script_pubkey();
+    let mut funding_in = Amount::ZERO;
+    // the funding tx spends wallet coins, so every input can be looked up
+    let fetched = fetcher.transactions(&spent_txids(&funding_tx)).await?;
+    for input in &funding_tx.input {
+        let spent = prevout(&wallets, &fetched, input.previous_output).await?;
+        funding_in += spent.value;
+        ledger.push(
+            "funding_input",
+            None,
+            None,
+            Some(&spent.script_pubkey),
+            spent.value,
+            input.previous_output.txid,
+            Some(input.previous_output.vout),
+            None,
+        );
+    }
+    // node output -> users in it and the output itself
+    let mut tracked: HashMap<OutPoint, (NodePath, TxOut)> = HashMap::new();
+    for (vout, output) in funding_tx.output.iter().enumerate() {
+        let kind = if output.script_pubkey == root_spk {
+            tracked.insert(
+                OutPoint::new(funding_txid, vout as u32),
+                (root.clone(), output.clone()),
+            );
+            "pool_deposit"
+        } else {
+            "funding_change"
+        };
+        ledger.push(
+            kind,
+            None,
+            None,
+            Some(&output.script_pubkey),
+            output.value,
+            funding_txid,
+            Some(vout as u32),
+            funding_block,
+        );
+    }
+    if tracked.is_empty() {
+        bail!("funding tx {} does not pay the pool root", funding_txid);
+    }
+    ledger.push(
+        "funding_fee",
+        None,
+        None,
+        None,
+        fee(funding_in, &funding_tx).unwrap_or_default(),
+        funding_txid,
+        None,
+        funding_block,
+    );
+
+    // anchor output -> users in the node the pool tx spent
+    let mut anchors: HashMap<OutPoint, usize> = HashMap::new();
+    let anchor_spk = pool.anchor_addr.script_pubkey();
+    let tip = rpc.run(|c| c.get_block_count()).await?;
+    let mut last_pool_height = u64::from(funding_height);
+    let mut blocks = fetcher.scan(u64::from(funding_height)..=tip);
+    while let Some((height, _, block)) = blocks.next_block().await? {
+        if tracked.is_empty()
+            && (anchors.is_empty() || height > last_pool_height + ANCHOR_LOOKAHEAD)
+        {
+            break;
+        }
+        let at = Some((height, u64::from(block.header.time)));
+        // what the anchor children in the block spend besides the anchor
+        let children: Vec<Txid> = block
+            .txdata
+            .iter()
+            .filter(|tx| {
+                tx.input
+                    .iter()
+                    .any(|input| anchors.contains_key(&input.previous_output))
+            })
+            .flat_map(spent_txids)
+            .collect();
+        let fetched = fetcher.transactions(&children).await?;
+
+        for tx in &block.txdata {
+            let txid = tx.compute_txid();
+            for input in &tx.input {
+                let outpoint = input.previous_output;
+                if let Some(level) = anchors.remove(&outpoint) {
+                    // what the fee payer added on top of the anchor, the anchor itself is already counted
+                    match total_in(&wallets, &fetched, tx, Some(outpoint)).await {
+                        Ok(fee_payer_in) => ledger.push(
+                            "cpfp_fee",
+                            Some(level),
+                            None,
+                            None,
+                            fee(fee_payer_in, tx).unwrap_or_default(),
+                            txid,
+                            None,
+                            at,
+                        ),
+                        Err(e) => warn!("fee of anchor child {} unknown: {}", txid, e),
+                    }
+                    continue;
+                }
+                let Some((node, spent)) = tracked.remove(&outpoint) else {
+                    continue;
+                };
+                last_pool_height = height;
+
+                if let Some(pool_fee) = fee(spent.value, tx) {
+                    ledger.push(
+                        "pool_fee",
+                        Some(node.len()),
+                        None,
+                        None,
+                        pool_fee,
+                        txid,
+                        None,
+                        at,
+                    );
+                }
+                if let Some(vout) = tx
+                    .output
+                    .iter()
+                    .position(|output| output.script_pubkey == anchor_spk)
+                {
+                    anchors.insert(OutPoint::new(txid, vout as u32), node.len());
+                    ledger.push(
+                        "anchor",
+                        Some(node.len()),
+                        None,
+                        Some(&anchor_spk),
+                        tx.output[vout].value,
+                        txid,
+                        Some(vout as u32),
+                        at,
+                    );
+                }
+
+                match classify_spend(&pool, &node, outpoint, tx, height)? {
+                    PoolEvent::WithdrawalConfirmed { paid, .. } => {
+                        // a payout line for every address the user split their exit to
+                        for user in &paid {
+                            for spk in user_scripts(&pool.addresses[user.index()], &pool.config) {
+                                if let Some(vout) = tx
+                                    .output
+                                    .iter()
+                                    .position(|output| output.script_pubkey == spk)
+                                {
+                                    ledger.push(
+                                        "payout",
+                                        Some(node.len()),
+                                        Some(*user),
+                                        Some(&spk),
+                                        tx.output[vout].value,
+                                        txid,
+                                        Some(vout as u32),
+                                        at,
+                                    );
+                                }
+                            }
+                        }
+                        if let Some((next, next_outpoint, output)) =
+                            next_node_output(&pool, &node, paid[0], tx)?
+                        {
+                            tracked.insert(next_outpoint, (next, output));
+                        }
+                    }
+                    PoolEvent::RecoverySwept { .. } => {
+                        let output = &tx.output[0];
+                        ledger.push(
+                            "recovery_sweep",
+                            Some(node.len()),
+                            None,
+                            Some(&output.script_pubkey),
+                            output.value,
+                            txid,
+                            Some(0),
+                            at,
+                        );
+                    }
+                    _ => {
+                        warn!("pool node {} left in unexpected tx {}", node, txid);
+                        ledger.push(
+                            "unexpected_spend",
+                            Some(node.len()),
+                            None,
+                            None,
+                            spent.value,
+                            txid,
+                            None,
+                            at,
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    for (outpoint, (node, output)) in tracked {
+        ledger.push(
+            "in_pool",
+            Some(node.len()),
+            None,
+            Some(&output.script_pubkey),
+            output.value,
+            outpoint.txid,
+            Some(outpoint.vout),
+            None,
+        );
+    }
+
+    info!(
+        "pool ledger: {} entries from block {} to {} \n",
+        ledger.entries.len(),
+        funding_height,
+        tip
+    );
+    Ok(ledger.entries)
+}
+
+// unix time as UTC, e.g. 2025-01-31 17:04:05
+fn utc_time(unix: u64) -> String {
+    let days = (unix / 86_400) as i64;
+    let secs = unix % 86_400;
+    // Howard Hinnant's civil_from_days
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+        year,
+        month,
+        day,
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60
+    )
+}
+
+// quoted if it has to be, labels are free text
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+pub fn ledger_csv(entries: &[LedgerEntry], metadata: &PoolMetadata) -> String {
+    let mut csv =
+        "kind,level,user,address,amount_sats,txid,vout,block_height,block_time,user_metadata\n"
+            .to_string();
+    let opt = |value: Option<String>| value.unwrap_or_default();
+    for entry in entries {
+        let _ = writeln!(
+            csv,
+            "{},{},{},{},{},{},{},{},{},{}",
+            entry.kind,
+            opt(entry.level.map(|level| level.to_string())),
+            opt(entry.user.map(|user| user.to_string())),
+            opt(entry.address.clone()),
+            entry.amount.to_sat(),
+            entry.txid,
+            opt(entry.vout.map(|vout| vout.to_string())),
+            opt(entry.block_height.map(|height| height.to_string())),
+            opt(entry.block_time.map(utc_time)),
+            csv_field(&opt(entry
+                .user
+                .and_then(|user| metadata.user_summary(user)))),
+        );
+    }
+    csv
+}
+
+pub fn write_ledger_csv(
+    entries: &[LedgerEntry],
+    metadata: &PoolMetadata,
+    output: Option<&Path>,
+) -> Result<()> {
+    let csv = ledger_csv(entries, metadata);
+    match output {
+        Some(path) => {
+            fs::write(path, csv)?;
+            info!("pool ledger written to {} \n", path.display());
+        }
+        None => print!("{}", csv),
+    }
+    Ok(())
+}
+
+pub fn print_ledger_summary(entries: &[LedgerEntry], metadata: &PoolMetadata) {
+    if let Some(pool) = metadata.pool_summary() {
+        println!("{}\n", pool);
+    }
+    let total = |kinds: &[&str]| -> Amount {
+        entries
+            .iter()
+            .filter(|entry| kinds.contains(&entry.kind))
+            .map(|entry| entry.amount)
+            .sum()
+    };
+    let rows = [
+        ("deposited into the pool", total(&["pool_deposit"])),
+        ("paid out to users", total(&["payout"])),
+        ("swept to recovery", total(&["recovery_sweep"])),
+        ("pool tx fees", total(&["pool_fee"])),
+        ("anchors", total(&["anchor"])),
+        ("left in unexpected txs", total(&["unexpected_spend"])),
+        ("still in the pool", total(&["in_pool"])),
+        ("funding tx fee", total(&["funding_fee"])),
+        ("anchor children (fee payer)", total(&["cpfp_fee"])),
+    ];
+    for (label, amount) in rows {
+        println!("{:>28}: {:>12} sats", label, amount.to_sat());
+    }
+    let accounted = total(&[
+        "payout",
+        "recovery_sweep",
+        "pool_fee",
+        "anchor",
+        "unexpected_spend",
+        "in_pool",
+    ]);
+    if accounted != total(&["pool_deposit"]) {
+        println!(
+            "\nthe pool txs account for {} sats of the {} deposited, some pool txs were not found",
+            accounted.to_sat(),
+            total(&["pool_deposit"]).to_sat()
+        );
+    }
+    if !metadata.users.is_empty() {
+        println!();
+        for user in metadata.users.keys() {
+            let paid: Amount = entries
+                .iter()
+                .filter(|entry| entry.kind == "payout" && entry.user == Some(*user))
+                .map(|entry| entry.amount)
+                .sum();
+            println!(
+                "{:>28}: {:>12} sats  {}",
+                format!("paid to user {}", user),
+                paid.to_sat(),
+                metadata.user_summary(*user).unwrap_or_default()
+            );
+        }
+    }
+    println!("\nrun with --csv for every entry");
+}
@@ -0,0 +1,331 @@
+use std::{collections::HashMap, fmt::Write as _, fs, path::Path, str::FromStr};
+
+use anyhow::{anyhow, bail, Result};
+use bitcoin::{
+    absolute, taproot::TaprootSpendInfo, transaction, Address, Amount, OutPoint, ScriptBuf,
+    Sequence, Transaction, TxIn, TxOut, Witness,
+};
+use clap::ValueEnum;
+use serde::Serialize;
+use tracing::info;
+
+use crate::{
+    config::NetworkConfig,
+    costs::binomial,
+    covenant::CovenantBackend,
+    ctv_scripts::create_pool_address,
+    footprint::SCHNORR_SIG_SIZE,
+    pools::{node_amount, CPFP_CHILD_VSIZE},
+    recovery::recovery_leaf,
+    AMOUNT_PER_USER,
+};
+
+// past this the linear tree doesn't fit in an f64, let alone on disk
+pub const MAX_SWEEP_USERS: usize = 1000;
+
+// How a pool tree splits as users leave
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Shape {
+    // this pool: a node for every subset of users with a leaf per user, one user leaves per tx
+    Linear,
+    // every node splits its users in two halves with a single template, users at the bottom
+    Binary,
+}
+
+// pool sizes to sweep, "3..512", "3..=512" or a single size
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UsersRange {
+    pub first: usize,
+    pub last: usize,
+}
+
+impl FromStr for UsersRange {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (first, last) = match s.split_once("..") {
+            Some((first, last)) => (first, last.trim_start_matches('=')),
+            None => (s, s),
+        };
+        let range = Self {
+            first: first.trim().parse()?,
+            last: last.trim().parse()?,
+        };
+        if range.first < 3 || range.first > range.last {
+            bail!("{} is not a range of pool sizes, pools start at 3 users", s);
+        }
+        if range.last > MAX_SWEEP_USERS {
+            bail!("sweeps go up to {} users", MAX_SWEEP_USERS);
+        }
+        Ok(range)
+    }
+}
+
+// One pool size of one shape. Vbytes include the anchor children when the network uses anchors.
+#[derive(Debug, Clone, Serialize)]
+pub struct SweepRow {
+    pub shape: Shape,
+    pub users: usize,
+    // nodes and templates that have to be computed, an f64 as the linear tree doubles per user
+    pub tree_nodes: f64,
+    pub templates: f64,
+    // every tx of an unwind until each user has their own output
+    pub unwind_txs: u64,
+    pub unwind_vbytes: u64,
+    // what a single user broadcasts to get out when nobody else does anything
+    pub worst_case_user_txs: u64,
+    pub worst_case_user_vbytes: u64,
+    pub worst_case_user_fee: Amount,
+}
+
+// template hashes only matter by size, but they have to differ to be separate leaves
+fn stand_in_hash(i: usize) -> [u8; 32] {
+    let mut hash = [0xab; 32];
+    hash[..8].copy_from_slice(&(i as u64).to_le_bytes());
+    hash
+}
+
+// what every pool tx of the sweep is built from
+struct Sweeper<'a> {
+    config: &'a NetworkConfig,
+    backend: &'a dyn CovenantBackend,
+    anchor_addr: Address,
+    // anchor child per pool tx
+    child_vsize: u64,
+}
+
+impl Sweeper<'_> {
+    // a node of `users` users with `templates` template leaves and the recovery leaf
+    fn node(&self, users: usize, templates: usize) -> Result<(TaprootSpendInfo, Vec<ScriptBuf>)> {
+        let hashes: Vec<[u8; 32]> = (0..templates).map(stand_in_hash).collect();
+        let spend_info = create_pool_address(
+            hashes.clone(),
+            self.backend,
+            recovery_leaf(self.config, node_amount(users)?, &self.anchor_addr),
+            self.config.tree_layout,
+            self.config.leaf_version,
+        )?;
+        let scripts = hashes
+            .into_iter()
+            .map(|hash| self.backend.leaf_script(hash))
+            .collect();
+        Ok((spend_info, scripts))
+    }
+
+    // A pool tx spending `script` of `node` into `paid` outputs (and the anchor), signed for real
+    // sizes. Every paid output is a p2tr, the next node or a user.
+    fn spend_vsize(&self, node: &TaprootSpendInfo, script: &ScriptBuf, paid: usize) -> Result<u64> {
+        let control_block = node
+            .control_block(&(script.clone(), self.config.leaf_version))
+            .ok_or_else(|| anyhow!("leaf is not in its node"))?;
+        let mut witness = Witness::new();
+        if self.backend.requires_presigning() {
+            witness.push([0; SCHNORR_SIG_SIZE]);
+        }
+        witness.push(script.as_bytes());
+        witness.push(control_block.serialize());
+
+        let p2tr = Address::p2tr_tweaked(node.output_key(), self.config.network).script_pubkey();
+        let mut output = vec![
+            TxOut {
+                value: AMOUNT_PER_USER,
+                script_pubkey: p2tr,
+            };
+            paid
+        ];
+        if let Some(anchor_amount) = self.config.anchor_amount {
+            output.push(TxOut {
+                value: anchor_amount,
+                script_pubkey: self.anchor_addr.script_pubkey(),
+            });
+        }
+        let tx = Transaction {
+            version: transaction::Version(self.config.tx_version),
+            lock_time: absolute::LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: OutPoint::null(),
+                script_sig: ScriptBuf::new(),
+                sequence: Sequence::ZERO,
+                witness,
+            }],
+            output,
+        };
+        Ok(tx.vsize() as u64 + self.child_vsize)
+    }
+
+    // (planned unwind spend, worst leaf spend) of a linear node of every size up to `last`.
+    // A node looks the same whichever users are in it, so one per size is enough.
+    fn linear_nodes(&self, last: usize) -> Result<Vec<(u64, u64)>> {
+        let mut spends = vec![(0, 0); last + 1];
+        for (users, spend) in spends.iter_mut().enumerate().skip(2) {
+            // the exit node has one template paying both users
+            let templates = if users == 2 { 1 } else { users };
+            let (node, scripts) = self.node(users, templates)?;
+            let next = self.spend_vsize(&node, &scripts[0], 2)?;
+            let mut worst = next;
+            for script in &scripts[1..] {
+                worst = worst.max(self.spend_vsize(&node, script, 2)?);
+            }
+            *spend = (next, worst);
+        }
+        Ok(spends)
+    }
+
+    // a binary node of `users` users with everything below it, memoized per size
+    fn binary_subtree(
+        &self,
+        users: usize,
+        subtrees: &mut HashMap<usize, Subtree>,
+    ) -> Result<Subtree> {
+        if users < 2 {
+            return Ok(Subtree::default());
+        }
+        if let Some(subtree) = subtrees.get(&users) {
+            return Ok(*subtree);
+        }
+        let (node, scripts) = self.node(users, 1)?;
+        let spend = self.spend_vsize(&node, &scripts[0], 2)?;
+        let left = self.binary_subtree(users.div_ceil(2), subtrees)?;
+        let right = self.binary_subtree(users / 2, subtrees)?;
+        // the bigger half is never the shorter way down
+        let subtree = Subtree {
+            txs: 1 + left.txs + right.txs,
+            vbytes: spend + left.vbytes + right.vbytes,
+            path_txs: 1 + left.path_txs,
+            path_vbytes: spend + left.path_vbytes.max(right.path_vbytes),
+        };
+        subtrees.insert(users, subtree);
+        Ok(subtree)
+    }
+}
+
+// every tx below a binary node, and the ones on its deepest path
+#[derive(Debug, Clone, Copy, Default)]
+struct Subtree {
+    txs: u64,
+    vbytes: u64,
+    path_txs: u64,
+    path_vbytes: u64,
+}
+
+// Build the pools of every size in `users` (every `step`th) in each shape, without broadcasting
+// anything, and measure what unwinding them takes. Uses the configured backend, layout, anchors and
+// recovery path, but not POOL_USERS.
+pub fn sweep(
+    config: &NetworkConfig,
+    backend: &dyn CovenantBackend,
+    users: UsersRange,
+    step: usize,
+    shapes: &[Shape],
+    fee_rate_sat_vb: u64,
+) -> Result<Vec<SweepRow>> {
+    let sweeper = Sweeper {
+        config,
+        backend,
+        anchor_addr: Address::from_str(&config.fee_anchor_addr)?.require_network(config.network)?,
+        child_vsize: if config.anchor_amount.is_some() {
+            CPFP_CHILD_VSIZE
+        } else {
+            0
+        },
+    };
+    let sizes: Vec<usize> = (users.first..=users.last).step_by(step.max(1)).collect();
+    let fee = |vbytes: u64| Amount::from_sat(vbytes * fee_rate_sat_vb);
+
+    let mut rows = Vec::new();
+    for shape in shapes {
+        info!(
+            "sweeping {:?} pools of {} to {} users",
+            shape, users.first, users.last
+        );
+        match shape {
+            Shape::Linear => {
+                let nodes = sweeper.linear_nodes(users.last)?;
+                for &n in &sizes {
+                    let unwind_vbytes = nodes[2..=n].iter().map(|(next, _)| next).sum();
+                    let worst = nodes[n].1;
+                    let subsets = 2f64.powi(n as i32);
+                    rows.push(SweepRow {
+                        shape: *shape,
+                        users: n,
+                        // every subset of two or more users, with a leaf per user but one for the pairs
+                        tree_nodes: subsets - n as f64 - 1.0,
+                        templates: n as f64 * subsets / 2.0
+                            - n as f64
+                            - binomial(n as u64, 2) as f64,
+                        unwind_txs: n as u64 - 1,
+                        unwind_vbytes,
+                        worst_case_user_txs: 1,
+                        worst_case_user_vbytes: worst,
+                        worst_case_user_fee: fee(worst),
+                    });
+                }
+            }
+            Shape::Binary => {
+                let mut subtrees = HashMap::new();
+                for &n in &sizes {
+                    let tree = sweeper.binary_subtree(n, &mut subtrees)?;
+                    rows.push(SweepRow {
+                        shape: *shape,
+                        users: n,
+                        tree_nodes: tree.txs as f64,
+                        templates: tree.txs as f64,
+                        unwind_txs: tree.txs,
+                        unwind_vbytes: tree.vbytes,
+                        worst_case_user_txs: tree.path_txs,
+                        worst_case_user_vbytes: tree.path_vbytes,
+                        worst_case_user_fee: fee(tree.path_vbytes),
+                    });
+                }
+            }
+        }
+    }
+    Ok(rows)
+}
+
+// exact while it fits the f64 mantissa, scientific after that
+fn count(value: f64) -> String {
+    if value < 2f64.powi(53) {
+        format!("{}", value as u64)
+    } else {
+        format!("{:e}", value)
+    }
+}
+
+pub fn sweep_csv(rows: &[SweepRow]) -> String {
+    let mut csv = String::from("shape,users,tree_nodes,templates,unwind_txs,unwind_vbytes,worst_case_user_txs,worst_case_user_vbytes,worst_case_user_fee_sats\n");
+    for row in rows {
+        let shape = match row.shape {
+            Shape::Linear => "linear",
+            Shape::Binary => "binary",
+        };
+        let _ = writeln!(
+            csv,
+            "{},{},{},{},{},{},{},{},{}",
+            shape,
+            row.users,
+            count(row.tree_nodes),
+            count(row.templates),
+            row.unwind_txs,
+            row.unwind_vbytes,
+            row.worst_case_user_txs,
+            row.worst_case_user_vbytes,
+            row.worst_case_user_fee.to_sat()
+        );
+    }
+    csv
+}
+
+// to `output`, or stdout
+pub fn write_sweep_csv(rows: &[SweepRow], output: Option<&Path>) -> Result<()> {
+    let csv = sweep_csv(rows);
+    match output {
+        Some(path) => {
+            fs::write(path, csv)?;
+            info!("{} sweep rows written to {} \n", rows.len(), path.display());
+        }
+        None => print!("{}", csv),
+    }
+    Ok(())
+}
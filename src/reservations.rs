@@ -0,0 +1,342 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{anyhow, bail, Context, Result};
+use bitcoin::{Amount, OutPoint, TxOut};
+use bitcoincore_rpc::{jsonrpc::serde_json, RpcApi};
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use crate::{
+    config::NetworkConfig,
+    rpc_helper::{fee_for_vsize, AsyncRpc, WALLET_INPUT_WITNESS_VSIZE},
+};
+
+// non-witness part of a wallet input: outpoint, empty scriptSig and sequence
+pub const WALLET_INPUT_VSIZE: u64 = 41 + WALLET_INPUT_WITNESS_VSIZE;
+// tries at locking a fresh set of coins when another funding locked some of ours first
+pub const RESERVE_ATTEMPTS: usize = 3;
+
+// A wallet coin held for the funding tx of one pool
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CoinReservation {
+    pub outpoint: OutPoint,
+    // the address the funding tx pays, the pool root or its staging output
+    pub pool: String,
+    // unix seconds
+    pub reserved_at: u64,
+}
+
+// Coins funding txs are being built from. The node's lockunspent keeps them out of every other
+// listunspent on the wallet and refuses a coin that is locked already, so two pools funded at once
+// never pick the same coin. The node forgets its locks on restart, so with POOL_RESERVATIONS_FILE
+// set the reservations are also kept on disk: a coin another pool holds there is never selected,
+// and a funding PSBT spending one is refused.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CoinReservations {
+    pub coins: Vec<CoinReservation>,
+}
+
+impl CoinReservations {
+    // no file is no reservations
+    pub fn load(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let json = fs::read_to_string(path)
+            .with_context(|| format!("can't read the reservations {}", path.display()))?;
+        Ok(serde_json::from_str(&json)?)
+    }
+
+    // The file goes away with the last reservation. Written next to it and renamed over it, so a
+    // reader never sees half a file.
+    pub fn write(&self, path: &Path) -> Result<()> {
+        if self.coins.is_empty() {
+            if path.exists() {
+                fs::remove_file(path)?;
+            }
+            return Ok(());
+        }
+        let temp = sibling(path, "tmp");
+        fs::write(&temp, serde_json::to_string_pretty(self)?)
+            .with_context(|| format!("can't write the reservations {}", temp.display()))?;
+        fs::rename(&temp, path)
+            .with_context(|| format!("can't replace the reservations {}", path.display()))?;
+        Ok(())
+    }
+
+    pub fn holder(&self, outpoint: &OutPoint) -> Option<&str> {
+        self.coins
+            .iter()
+            .find(|coin| coin.outpoint == *outpoint)
+            .map(|coin| coin.pool.as_str())
+    }
+
+    // the coins of `outpoints` some other pool holds
+    pub fn conflicts<'a>(
+        &'a self,
+        pool: &str,
+        outpoints: &'a [OutPoint],
+    ) -> Vec<(OutPoint, &'a str)> {
+        outpoints
+            .iter()
+            .filter_map(|outpoint| {
+                self.holder(outpoint)
+                    .filter(|holder| *holder != pool)
+                    .map(|holder| (*outpoint, holder))
+            })
+            .collect()
+    }
+
+    pub fn reserve(&mut self, pool: &str, outpoints: &[OutPoint], now: u64) -> Result<()> {
+        if let Some((outpoint, holder)) = self.conflicts(pool, outpoints).first() {
+            bail!("coin {} is reserved for the pool at {}", outpoint, holder);
+        }
+        for outpoint in outpoints {
+            if self.holder(outpoint).is_none() {
+                self.coins.push(CoinReservation {
+                    outpoint: *outpoint,
+                    pool: pool.to_string(),
+                    reserved_at: now,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    // drop what `pool` holds, returning its coins
+    pub fn release(&mut self, pool: &str) -> Vec<OutPoint> {
+        let (released, kept) = self.coins.drain(..).partition(|coin| coin.pool == pool);
+        self.coins = kept;
+        released
+            .into_iter()
+            .map(|coin: CoinReservation| coin.outpoint)
+            .collect()
+    }
+}
+
+// `path` with `extension` added, e.g. reservations.json.lock
+fn sibling(path: &Path, extension: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".");
+    name.push(extension);
+    PathBuf::from(name)
+}
+
+// An exclusive lock on the reservations at `path`, held from loading them until the changed ones
+// are written so two fundings, in this process or another, don't drop each other's reservations.
+// The lock is on a file next to them, the reservations themselves are replaced on every write.
+#[derive(Debug)]
+pub struct ReservationsLock {
+    file: File,
+}
+
+impl ReservationsLock {
+    pub async fn acquire(path: &Path) -> Result<Self> {
+        let lock_path = sibling(path, "lock");
+        // waiting for it blocks the thread, not the runtime
+        tokio::task::spawn_blocking(move || {
+            let file = OpenOptions::new()
+                .create(true)
+                .truncate(false)
+                .write(true)
+                .open(&lock_path)
+                .with_context(|| format!("can't open {}", lock_path.display()))?;
+            file.lock()
+                .map_err(|e| anyhow!("can't lock {}: {}", lock_path.display(), e))?;
+            Ok(Self { file })
+        })
+        .await?
+    }
+}
+
+impl Drop for ReservationsLock {
+    fn drop(&mut self) {
+        let _ = self.file.unlock();
+    }
+}
+
+// Largest coins first until they cover `target` and the fee of a tx of `base_vsize` (everything
+// but the inputs) with them as inputs. None when all of them don't.
+pub fn select_coins(
+    mut coins: Vec<(OutPoint, TxOut)>,
+    target: Amount,
+    fee_rate: u64,
+    base_vsize: u64,
+) -> Result<Option<Vec<(OutPoint, TxOut)>>> {
+    coins.sort_by_key(|(outpoint, coin)| (std::cmp::Reverse(coin.value), *outpoint));
+    let mut selected = Vec::new();
+    let mut total = Amount::ZERO;
+    for coin in coins {
+        total += coin.1.value;
+        selected.push(coin);
+        let vsize = base_vsize + WALLET_INPUT_VSIZE * selected.len() as u64;
+        if total >= target + fee_for_vsize(fee_rate, vsize)? {
+            return Ok(Some(selected));
+        }
+    }
+    Ok(None)
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or_default()
+}
+
+// Lock `outpoints` in the node and record them for `pool`. Fails without holding anything when a
+// coin is locked already or reserved for another pool, the caller picks other coins.
+pub async fn reserve_coins(
+    rpc: &AsyncRpc,
+    config: &NetworkConfig,
+    pool: &str,
+    outpoints: &[OutPoint],
+) -> Result<()> {
+    let _lock = match &config.coin_reservations {
+        Some(path) => Some(ReservationsLock::acquire(path).await?),
+        None => None,
+    };
+    let mut reservations = match &config.coin_reservations {
+        Some(path) => {
+            let reservations = CoinReservations::load(path)?;
+            if let Some((outpoint, holder)) = reservations.conflicts(pool, outpoints).first() {
+                bail!("coin {} is reserved for the pool at {}", outpoint, holder);
+            }
+            Some(reservations)
+        }
+        None => None,
+    };
+
+    let coins = outpoints.to_vec();
+    rpc.run(move |c| c.lock_unspent(&coins))
+        .await
+        .context("another funding locked one of the coins first")?;
+    if let (Some(path), Some(reservations)) = (&config.coin_reservations, &mut reservations) {
+        reservations.reserve(pool, outpoints, now())?;
+        reservations.write(path)?;
+    }
+    info!("  Reserved {} coins for {}", outpoints.len(), pool);
+    Ok(())
+}
+
+// Give back what `pool` holds. `unlock` when the funding tx never made it out, its coins are
+// spendable again; a broadcast one spent them and only the record goes.
+pub async fn release_coins(
+    rpc: &AsyncRpc,
+    config: &NetworkConfig,
+    pool: &str,
+    outpoints: &[OutPoint],
+    unlock: bool,
+) -> Result<()> {
+    if unlock {
+        let coins = outpoints.to_vec();
+        if let Err(e) = rpc.run(move |c| c.unlock_unspent(&coins)).await {
+            warn!("couldn't unlock the coins reserved for {}: {}", pool, e);
+        }
+    }
+    if let Some(path) = &config.coin_reservations {
+        let _lock = ReservationsLock::acquire(path).await?;
+        let mut reservations = CoinReservations::load(path)?;
+        reservations.release(pool);
+        reservations.write(path)?;
+    }
+    Ok(())
+}
+
+// Refuse a funding tx for `pool` spending a coin another pool holds
+pub fn check_unreserved(config: &NetworkConfig, pool: &str, outpoints: &[OutPoint]) -> Result<()> {
+    let Some(path) = &config.coin_reservations else {
+        return Ok(());
+    };
+    let reservations = CoinReservations::load(path)?;
+    if let Some((outpoint, holder)) = reservations.conflicts(pool, outpoints).first() {
+        bail!(
+            "coin {} is reserved for the funding of the pool at {}, it would double spend it",
+            outpoint,
+            holder
+        );
+    }
+    Ok(())
+}
+
+// What the wallet and the reservations file hold
+#[derive(Debug, Clone, Serialize)]
+pub struct ReservedCoins {
+    // lockunspent, whoever locked them
+    pub locked: Vec<OutPoint>,
+    pub reservations: Vec<CoinReservation>,
+}
+
+pub async fn reserved_coins(rpc: &AsyncRpc, config: &NetworkConfig) -> Result<ReservedCoins> {
+    let locked: Vec<serde_json::Value> = rpc.run(|c| c.call("listlockunspent", &[])).await?;
+    let locked = locked
+        .into_iter()
+        .map(|coin| {
+            Ok(OutPoint::new(
+                serde_json::from_value(coin["txid"].clone())?,
+                coin["vout"].as_u64().unwrap_or_default() as u32,
+            ))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let reservations = match &config.coin_reservations {
+        Some(path) => CoinReservations::load(path)?.coins,
+        None => Vec::new(),
+    };
+    Ok(ReservedCoins {
+        locked,
+        reservations,
+    })
+}
+
+// Unlock and forget what `pool` holds, after a funding that died half way
+pub async fn release_pool(rpc: &AsyncRpc, config: &NetworkConfig, pool: &str) -> Result<usize> {
+    let Some(path) = &config.coin_reservations else {
+        bail!("POOL_RESERVATIONS_FILE isn't set, there is no record of which pool locked what");
+    };
+    let _lock = ReservationsLock::acquire(path).await?;
+    let mut reservations = CoinReservations::load(path)?;
+    let released = reservations.release(pool);
+    let locked = reserved_coins(rpc, config).await?.locked;
+    let unlock: Vec<OutPoint> = released
+        .iter()
+        .filter(|outpoint| locked.contains(outpoint))
+        .copied()
+        .collect();
+    if !unlock.is_empty() {
+        rpc.run(move |c| c.unlock_unspent(&unlock)).await?;
+    }
+    reservations.write(path)?;
+    info!("released {} coins of {} \n", released.len(), pool);
+    Ok(released.len())
+}
+
+pub fn print_reserved_coins(coins: &ReservedCoins) {
+    if coins.locked.is_empty() && coins.reservations.is_empty() {
+        println!("no coins are reserved");
+        return;
+    }
+    for reservation in &coins.reservations {
+        let state = if coins.locked.contains(&reservation.outpoint) {
+            "locked"
+        } else {
+            "not locked in the node"
+        };
+        println!(
+            "{} for {} ({})",
+            reservation.outpoint, reservation.pool, state
+        );
+    }
+    for outpoint in &coins.locked {
+        if !coins
+            .reservations
+            .iter()
+            .any(|reservation| reservation.outpoint == *outpoint)
+        {
+            println!("{} locked, not by a pool", outpoint);
+        }
+    }
+}
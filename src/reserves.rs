@@ -0,0 +1,280 @@
+use std::{fs, path::Path};
+
+use anyhow::{anyhow, bail, Result};
+use bitcoin::{
+    consensus::encode::{deserialize_hex, serialize_hex},
+    hashes::{sha256, Hash},
+    Amount, Network, OutPoint, Transaction,
+};
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use crate::{
+    esplora::Esplora,
+    ids::UserIndex,
+    manifest::{LoadedPool, PoolManifest},
+    payouts::{user_scripts, user_share},
+    pools::{node_exit, node_value},
+};
+
+// Proof that the pool's one funding output backs every member's claim: the funding tx, the
+// manifest the tree is rebuilt from and what each user is owed. Anyone checks it with
+// verify_reserves, without a node or anything from the coordinator but this file.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReservesProof {
+    pub network: Network,
+    pub root_address: String,
+    pub funding_outpoint: OutPoint,
+    pub funding_value: Amount,
+    // raw hex, its txid is the outpoint's
+    pub funding_tx: String,
+    // of the manifest's json, for a member to compare with the copy they were given
+    pub manifest_sha256: String,
+    pub manifest: PoolManifest,
+    pub claims: Vec<Claim>,
+    pub total_claims: Amount,
+}
+
+// What one user is owed: their share of the pool, and what the root's leaf for them pays them
+// once the template's fee is taken
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Claim {
+    pub user: UserIndex,
+    pub address: String,
+    pub share: Amount,
+    pub root_exit: Amount,
+}
+
+// What a proof shows once checked
+#[derive(Debug, Clone, Serialize)]
+pub struct VerifiedReserves {
+    pub root_address: String,
+    pub funding_outpoint: OutPoint,
+    pub funding_value: Amount,
+    pub users: usize,
+    pub total_claims: Amount,
+    // held above the claims, what the templates can't pay out (0 for a pool funded exactly)
+    pub surplus: Amount,
+    pub covenant: String,
+    // whether esplora still has the funding output unspent, None when it wasn't asked
+    pub unspent: Option<bool>,
+}
+
+pub fn manifest_sha256(manifest: &PoolManifest) -> Result<String> {
+    Ok(sha256::Hash::hash(&serde_json::to_vec(manifest)?).to_string())
+}
+
+// every user's claim on the node the pool is funded at, in tree order
+fn claims(pool: &LoadedPool) -> Result<Vec<Claim>> {
+    let root = pool.config.pool_root()?;
+    root.user_indices()
+        .map(|user| {
+            let address = &pool.addresses[user.index()];
+            let exit = node_exit(
+                &pool.tree,
+                &pool.config,
+                pool.backend.as_ref(),
+                &pool.addresses,
+                &pool.anchor_addr,
+                &root,
+                user,
+            )?;
+            let scripts = user_scripts(address, &pool.config);
+            let root_exit = exit
+                .outputs
+                .iter()
+                .filter(|output| scripts.contains(&output.script_pubkey))
+                .map(|output| output.value)
+                .sum();
+            Ok(Claim {
+                user,
+                address: address.to_string(),
+                share: user_share(user, &pool.config),
+                root_exit,
+            })
+        })
+        .collect()
+}
+
+// The proof for the funded pool of `manifest`, `funding_tx` being its funding tx
+pub fn prove_reserves(manifest: &PoolManifest, funding_tx: &Transaction) -> Result<ReservesProof> {
+    manifest.lifecycle.require_funded()?;
+    let txid = funding_tx.compute_txid();
+    if manifest.funding_txid != Some(txid) {
+        bail!("{} is not the funding tx in the manifest", txid);
+    }
+    let pool = manifest.load_pool()?;
+    let root_script = pool.tree.root()?.address(&pool.config).script_pubkey();
+    let (vout, output) = funding_tx
+        .output
+        .iter()
+        .enumerate()
+        .find(|(_, output)| output.script_pubkey == root_script)
+        .ok_or_else(|| anyhow!("{} doesn't pay the pool address", txid))?;
+    let claims = claims(&pool)?;
+    let total_claims = claims.iter().map(|claim| claim.share).sum();
+
+    Ok(ReservesProof {
+        network: manifest.network,
+        root_address: manifest.root_address.clone(),
+        funding_outpoint: OutPoint::new(txid, vout as u32),
+        funding_value: output.value,
+        funding_tx: serialize_hex(funding_tx),
+        manifest_sha256: manifest_sha256(manifest)?,
+        manifest: manifest.clone(),
+        claims,
+        total_claims,
+    })
+}
+
+// Check a proof on its own: the funding output pays the root the manifest rebuilds to, the claims
+// are the ones the rebuilt tree gives and the output holds all of them. With `esplora` the output
+// also has to be unspent still.
+pub async fn verify_reserves(
+    proof: &ReservesProof,
+    esplora: Option<&Esplora>,
+) -> Result<VerifiedReserves> {
+    let funding_tx: Transaction = deserialize_hex(&proof.funding_tx)?;
+    let txid = funding_tx.compute_txid();
+    if txid != proof.funding_outpoint.txid {
+        bail!(
+            "the funding tx is {}, the proof is for an output of {}",
+            txid,
+            proof.funding_outpoint.txid
+        );
+    }
+    let output = funding_tx
+        .output
+        .get(proof.funding_outpoint.vout as usize)
+        .ok_or_else(|| {
+            anyhow!(
+                "the funding tx has no output {}",
+                proof.funding_outpoint.vout
+            )
+        })?;
+    if output.value != proof.funding_value {
+        bail!(
+            "the funding output holds {}, the proof says {}",
+            output.value,
+            proof.funding_value
+        );
+    }
+
+    let manifest = &proof.manifest;
+    if manifest_sha256(manifest)? != proof.manifest_sha256 {
+        bail!("the manifest doesn't hash to the proof's manifest_sha256");
+    }
+    if manifest.network != proof.network
+        || manifest.root_address != proof.root_address
+        || manifest.funding_txid != Some(txid)
+    {
+        bail!("the manifest is for another pool or funding tx than the proof");
+    }
+    // rebuilds every node and checks the root address
+    let pool = manifest.load_pool()?;
+    let root_script = pool.tree.root()?.address(&pool.config).script_pubkey();
+    if output.script_pubkey != root_script {
+        bail!(
+            "the funding output doesn't pay the pool address {}",
+            proof.root_address
+        );
+    }
+
+    let claims = claims(&pool)?;
+    if claims != proof.claims {
+        bail!("the claims aren't the ones the manifest's tree gives");
+    }
+    let total_claims: Amount = claims.iter().map(|claim| claim.share).sum();
+    if total_claims != proof.total_claims {
+        bail!(
+            "the claims add up to {}, the proof says {}",
+            total_claims,
+            proof.total_claims
+        );
+    }
+    // the root's templates spend exactly what the root node holds
+    let root_value = node_value(&pool.config.pool_root()?, &pool.config)?;
+    if root_value != total_claims {
+        bail!(
+            "the pool's root holds {} but its users are owed {}",
+            root_value,
+            total_claims
+        );
+    }
+    let surplus = proof
+        .funding_value
+        .checked_sub(total_claims)
+        .ok_or_else(|| {
+            anyhow!(
+                "the funding output holds {}, {} short of the {} the users are owed",
+                proof.funding_value,
+                total_claims - proof.funding_value,
+                total_claims
+            )
+        })?;
+
+    let unspent = match esplora {
+        Some(esplora) => {
+            let address = pool.tree.root()?.address(&pool.config);
+            let utxos = esplora.address_utxos(&address).await?;
+            Some(
+                utxos
+                    .iter()
+                    .any(|utxo| OutPoint::new(utxo.txid, utxo.vout) == proof.funding_outpoint),
+            )
+        }
+        None => None,
+    };
+
+    Ok(VerifiedReserves {
+        root_address: proof.root_address.clone(),
+        funding_outpoint: proof.funding_outpoint,
+        funding_value: proof.funding_value,
+        users: claims.len(),
+        total_claims,
+        surplus,
+        covenant: manifest.covenant.clone(),
+        unspent,
+    })
+}
+
+pub fn write_reserves_proof(proof: &ReservesProof, output: Option<&Path>) -> Result<()> {
+    let json = serde_json::to_string_pretty(proof)?;
+    match output {
+        Some(path) => {
+            fs::write(path, json)?;
+            info!(
+                "proof of reserves for {} written to {} \n",
+                proof.root_address,
+                path.display()
+            );
+        }
+        None => println!("{}", json),
+    }
+    Ok(())
+}
+
+pub fn print_verified_reserves(verified: &VerifiedReserves) {
+    println!(
+        "{} holds {} for {} users owed {} in total ({} surplus)",
+        verified.funding_outpoint,
+        verified.funding_value,
+        verified.users,
+        verified.total_claims,
+        verified.surplus
+    );
+    println!(
+        "every claim is a leaf of {} ({} covenant)",
+        verified.root_address, verified.covenant
+    );
+    if verified.covenant == "presigned" {
+        println!("the leaves don't check their templates with OP_CTV, they hold only if the presigning key was deleted");
+    }
+    match verified.unspent {
+        Some(true) => println!("the funding output is unspent"),
+        Some(false) => {
+            println!("the funding output is spent, the pool has started unwinding or was swept")
+        }
+        None => {}
+    }
+}
@@ -1,134 +1,443 @@
-use std::collections::HashMap;
+use std::{sync::Arc, time::Instant};
 
-use anyhow::Result;
+use anyhow::{anyhow, bail, Result};
 use bitcoin::{
-    absolute, consensus::encode::serialize_hex, transaction, Address, Amount, OutPoint, Sequence,
-    Transaction, TxIn, TxOut, Txid,
+    absolute, amount::CheckedSum, consensus::encode::serialize_hex, hex::DisplayHex, transaction,
+    Address, Amount, OutPoint, Script, ScriptBuf, Sequence, Transaction, TxIn, TxOut, Txid,
 };
-use bitcoincore_rpc::{
-    json::{self, GetTransactionResultDetail},
-    jsonrpc::serde_json,
-    Client, RpcApi,
-};
-use serde_json::json;
-use tracing::{info, debug};
+use bitcoincore_rpc::{json::GetTransactionResultDetail, Client, RpcApi};
+use rand::Rng;
+use tracing::{debug, info, trace, warn};
 
 use crate::{
-    config::{NetworkConfig, DEFAULT_FEE_RATE, DUST_AMOUNT, INIT_WALLET_AMOUNT_FEE, TX_VERSION},
+    broadcast::{broadcast, BroadcastKind},
+    config::{NetworkConfig, DEFAULT_FEE_RATE, DUST_AMOUNT, FEE_WALLET_LOW_BALANCE},
+    fund::required_funding,
+    metrics::METRICS,
+    redact,
+    reservations::{
+        release_coins, reserve_coins, select_coins, CoinReservations, RESERVE_ATTEMPTS,
+        WALLET_INPUT_VSIZE,
+    },
+    standardness::check_standard,
+    wallet_coins::spendable_coins,
     AMOUNT_PER_USER, POOL_USERS,
 };
 
-pub fn send_funding_transaction(rpc: &Client, config: &NetworkConfig, fee_amount: Amount) -> (Txid, Amount) {
+// Shared handle to the node. bitcoincore_rpc is blocking, so every call runs on tokio's blocking pool
+// and a slow broadcast or wallet call never stalls the other tasks on the runtime.
+#[derive(Clone)]
+pub struct AsyncRpc(Arc<Client>);
+
+impl AsyncRpc {
+    pub async fn connect(config: &NetworkConfig) -> Result<Self> {
+        Self::connect_wallet(config, &config.wallet_name).await
+    }
+
+    pub async fn connect_wallet(config: &NetworkConfig, wallet_name: &str) -> Result<Self> {
+        let config = config.clone();
+        let wallet_name = wallet_name.to_string();
+        let client = tokio::task::spawn_blocking(move || config.wallet_rpc(&wallet_name)).await??;
+        Ok(Self(Arc::new(client)))
+    }
+
+    // a handle on a client built elsewhere, e.g. over the mock's transport
+    pub fn from_client(client: Client) -> Self {
+        Self(Arc::new(client))
+    }
+
+    pub async fn run<T, F>(&self, f: F) -> Result<T>
+    where
+        F: FnOnce(&Client) -> bitcoincore_rpc::Result<T> + Send + 'static,
+        T: Send + 'static,
+    {
+        let client = self.0.clone();
+        Ok(tokio::task::spawn_blocking(move || {
+            let started = Instant::now();
+            let result = f(&client);
+            METRICS.record_rpc(started.elapsed());
+            result
+        })
+        .await??)
+    }
+}
+
+// The wallet paying for anchor children: FEE_WALLET if set, so pool and operational funds stay apart,
+// otherwise the main wallet
+pub async fn connect_fee_payer(config: &NetworkConfig, rpc: &AsyncRpc) -> Result<AsyncRpc> {
+    match &config.fee_wallet_name {
+        Some(name) => {
+            info!("anchor children are paid from the {} wallet \n", name);
+            AsyncRpc::connect_wallet(config, name).await
+        }
+        None => Ok(rpc.clone()),
+    }
+}
+
+// estimatesmartfee for the config's target in sat/kvB, DEFAULT_FEE_RATE when the node has no estimate
+pub async fn estimate_fee_rate(rpc: &AsyncRpc, config: &NetworkConfig) -> u64 {
+    let conf_target = config.conf_target;
+    rpc.run(move |c| c.estimate_smart_fee(conf_target, None))
+        .await
+        .ok()
+        .and_then(|estimate| estimate.fee_rate.map(|rate| rate.to_sat()))
+        .unwrap_or(DEFAULT_FEE_RATE)
+}
+
+// the fee payer has to cover `fee` from confirmed coins, warn early when it is running low
+pub async fn check_fee_payer_balance(fee_payer: &AsyncRpc, fee: Amount) -> Result<Amount> {
+    let balance = fee_payer.run(|c| c.get_balance(Some(1), None)).await?;
+    if balance < fee {
+        bail!(
+            "fee payer wallet has {} confirmed but {} is needed for the anchor child, top it up",
+            balance,
+            fee
+        );
+    }
+    if balance < FEE_WALLET_LOW_BALANCE {
+        warn!(
+            "fee payer wallet is running low: {} left (warning below {})",
+            balance, FEE_WALLET_LOW_BALANCE
+        );
+    }
+    Ok(balance)
+}
+
+// vsize of the staging spend (one wallet input, the pool output), paid for by the staging output
+pub const STAGING_SPEND_VSIZE: u64 = 150;
+// witness of a wallet input, p2wpkh (p2tr is smaller)
+pub const WALLET_INPUT_WITNESS_VSIZE: u64 = 27;
+
+// Where the funding tx sends the pool's money
+#[derive(Debug, Clone)]
+pub enum FundingDestination {
+    // straight to the entry pool, exactly what the templates spend
+    EntryPool(Address),
+    // a wallet output the pool psbt spends later, carrying that tx's fee on top
+    Staging(Address),
+}
+
+impl FundingDestination {
+    // `fee_rate` in sat/kvB
+    pub fn output(&self, fee_rate: u64) -> Result<TxOut> {
+        Ok(match self {
+            Self::EntryPool(address) => TxOut {
+                value: required_funding(),
+                script_pubkey: address.script_pubkey(),
+            },
+            Self::Staging(address) => TxOut {
+                value: required_funding()
+                    .checked_add(fee_for_vsize(fee_rate, STAGING_SPEND_VSIZE)?)
+                    .ok_or_else(|| anyhow!("staging amount overflows"))?,
+                script_pubkey: address.script_pubkey(),
+            },
+        })
+    }
+}
+
+// fee for `vsize` vbytes at `fee_rate` sat/kvB
+pub fn fee_for_vsize(fee_rate: u64, vsize: u64) -> Result<Amount> {
+    fee_rate
+        .checked_mul(vsize)
+        .map(|fee| Amount::from_sat(fee / 1000))
+        .ok_or_else(|| anyhow!("fee for {} vB at {} sat/kvB overflows", vsize, fee_rate))
+}
+
+// Split `total_input` into the destination output, `fee` and change to `change_spk`.
+// Change below dust can't be relayed, it goes to the fee instead of getting an output.
+pub fn funding_outputs(
+    total_input: Amount,
+    destination: TxOut,
+    fee: Amount,
+    change_spk: ScriptBuf,
+) -> Result<Vec<TxOut>> {
+    let needed = destination
+        .value
+        .checked_add(fee)
+        .ok_or_else(|| anyhow!("funding amount overflows"))?;
+    let change = total_input.checked_sub(needed).ok_or_else(|| {
+        anyhow!(
+            "inputs of {} can't cover {} for the pool plus a fee of {}",
+            total_input,
+            destination.value,
+            fee
+        )
+    })?;
+
+    let mut outputs = vec![destination];
+    if change >= DUST_AMOUNT {
+        outputs.push(TxOut {
+            value: change,
+            script_pubkey: change_spk,
+        });
+    } else if change > Amount::ZERO {
+        info!("  Change of {} would be dust, adding it to the fee", change);
+    }
+    Ok(outputs)
+}
+
+// anti fee sniping like Bitcoin Core's wallet: the tip height, one `draw` in ten up to 99 blocks back
+pub fn funding_lock_time(tip_height: u32, draw: u32) -> Result<absolute::LockTime> {
+    let height = if draw.is_multiple_of(10) {
+        tip_height.saturating_sub(draw / 10 % 100)
+    } else {
+        tip_height
+    };
+    Ok(absolute::LockTime::from_height(height)?)
+}
+
+// Fund `destination` from the wallet's coins, change goes to `change_address` or a fresh wallet change address.
+// Returns the txid and the script of the destination output.
+pub async fn send_funding_transaction(
+    rpc: &AsyncRpc,
+    config: &NetworkConfig,
+    destination: &FundingDestination,
+    change_address: Option<&Address>,
+) -> Result<(Txid, ScriptBuf)> {
     info!("Creating funding transaction:");
     info!("  Amount per user: {}", AMOUNT_PER_USER);
     info!("  Number of users: {}", POOL_USERS);
-    info!("  Total amount: {}", AMOUNT_PER_USER * POOL_USERS.try_into().unwrap());
-    
-    let change_address = rpc.get_raw_change_address(None).unwrap();
-    let change_address_2 = rpc.get_raw_change_address(None).unwrap();
-    info!("  Change address: {:?}", change_address);
-    info!("  Change address 2: {:?}", change_address_2);
-
-    let unspent = rpc.list_unspent(Some(0), None, None, Some(true), None).unwrap();
-    info!("  Number of unspent outputs: {}", unspent.len());
-    
+    info!("  Total amount: {}", required_funding());
+
+    let change_spk = match change_address {
+        Some(address) => address.script_pubkey(),
+        None => rpc
+            .run(|c| c.get_raw_change_address(None))
+            .await?
+            .require_network(config.network)?
+            .script_pubkey(),
+    };
+    trace!("  Destination: {:?}", destination);
+    trace!("  Change script: {}", change_spk);
+    info!("  Change script: {}", redact::short(&change_spk));
+
+    let fee_rate = estimate_fee_rate(rpc, config).await;
+    let destination = destination.output(fee_rate)?;
+    let pool = Address::from_script(&destination.script_pubkey, config.network)?.to_string();
+    let coins =
+        reserve_funding_coins(rpc, config, &pool, &destination, &change_spk, fee_rate).await?;
+    let outpoints: Vec<OutPoint> = coins.iter().map(|(outpoint, _)| *outpoint).collect();
+
+    let funded = sign_and_send_funding(rpc, config, coins, destination, change_spk, fee_rate).await;
+    // broadcast, the coins are spent and only the record goes. Otherwise they are free again.
+    release_coins(rpc, config, &pool, &outpoints, funded.is_err()).await?;
+    funded
+}
+
+// Pick coins for `destination` that no other funding holds and reserve them, picking again when
+// a concurrent funding locks one of them first
+async fn reserve_funding_coins(
+    rpc: &AsyncRpc,
+    config: &NetworkConfig,
+    pool: &str,
+    destination: &TxOut,
+    change_spk: &ScriptBuf,
+    fee_rate: u64,
+) -> Result<Vec<(OutPoint, TxOut)>> {
+    // sized with the change output and no inputs, select_coins adds those
+    let base_vsize = Transaction {
+        version: transaction::Version(config.tx_version),
+        lock_time: absolute::LockTime::ZERO,
+        input: Vec::new(),
+        output: vec![
+            destination.clone(),
+            TxOut {
+                value: Amount::ZERO,
+                script_pubkey: change_spk.clone(),
+            },
+        ],
+    }
+    .vsize() as u64;
+
+    let mut attempt = 0;
+    loop {
+        attempt += 1;
+        // locked coins aren't listed, they belong to a funding in flight. Neither are coins
+        // costing more to spend than they hold.
+        let unspent = spendable_coins(rpc, 0, fee_for_vsize(fee_rate, WALLET_INPUT_VSIZE)?).await?;
+        let reservations = match &config.coin_reservations {
+            Some(path) => CoinReservations::load(path)?,
+            None => CoinReservations::default(),
+        };
+        let coins: Vec<(OutPoint, TxOut)> = unspent
+            .into_iter()
+            .filter(|(outpoint, _)| {
+                reservations
+                    .holder(outpoint)
+                    .is_none_or(|holder| holder == pool)
+            })
+            .collect();
+        info!("  Number of unspent outputs: {}", coins.len());
+
+        // too little is left to funding_outputs, it says how much is missing
+        let selected =
+            select_coins(coins.clone(), destination.value, fee_rate, base_vsize)?.unwrap_or(coins);
+        let outpoints: Vec<OutPoint> = selected.iter().map(|(outpoint, _)| *outpoint).collect();
+        match reserve_coins(rpc, config, pool, &outpoints).await {
+            Ok(()) => return Ok(selected),
+            Err(e) if attempt < RESERVE_ATTEMPTS => {
+                warn!("  {}, selecting other coins", e);
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+async fn sign_and_send_funding(
+    rpc: &AsyncRpc,
+    config: &NetworkConfig,
+    coins: Vec<(OutPoint, TxOut)>,
+    destination: TxOut,
+    change_spk: ScriptBuf,
+    fee_rate: u64,
+) -> Result<(Txid, ScriptBuf)> {
     let mut inputs = Vec::new();
+    let mut prevouts = Vec::new();
     let mut total_input = Amount::ZERO;
-    
-    for utxo in unspent {
+
+    for (outpoint, prevout) in coins {
         info!("  Using UTXO:");
-        info!("    TXID: {}", utxo.txid);
-        info!("    Vout: {}", utxo.vout);
-        info!("    Amount: {}", utxo.amount);
-        debug!("    UTXO details: {:?}", utxo);
-        
+        info!("    TXID: {}", redact::short(outpoint.txid));
+        info!("    Vout: {}", outpoint.vout);
+        info!("    Amount: {}", prevout.value);
+        trace!("    UTXO: {:?} {:?}", outpoint, prevout);
+
         inputs.push(TxIn {
-            previous_output: OutPoint {
-                txid: utxo.txid,
-                vout: utxo.vout,
-            },
+            previous_output: outpoint,
             sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
             ..Default::default()
         });
-        
-        total_input += utxo.amount;
+        total_input = total_input
+            .checked_add(prevout.value)
+            .ok_or_else(|| anyhow!("wallet coins add up to more than 21M BTC"))?;
+        prevouts.push(prevout);
         debug!("    Running total input: {}", total_input);
     }
-    
+
     info!("  Total input amount: {}", total_input);
-    info!("Total inputs: {:?}", inputs);
-    let fee = rpc.estimate_smart_fee(1, None).unwrap().fee_rate.unwrap();
-    // TODO: estimate the size of the transaction more better
-    let fee = Amount::from_sat((fee.to_sat() as f64 * 250.0) as u64); // Estimate for ~250 byte tx
-    info!("  Estimated fee: {} ({} sats/vB)", fee, fee.to_sat() as f64 / 250.0);
-    let amount_to_fund = AMOUNT_PER_USER * POOL_USERS.try_into().unwrap() + fee;
-    let change = total_input - amount_to_fund;
-    
-    if change < Amount::ZERO {
-        panic!("Not enough input to cover outputs and fee");
-    }
-    
-    let outputs = vec![
-        TxOut {
-            value: amount_to_fund,
-            script_pubkey: change_address.assume_checked().script_pubkey(),
-        },
-        TxOut {
-            value: change,
-            script_pubkey: change_address_2.assume_checked().script_pubkey(),
-        },
-    ];
-    info!("  Outputs: {:?}", outputs);
-    let total_output: Amount = outputs.iter().map(|out| Amount::from_sat(out.value)).sum();
-    if total_input < total_output {
-        panic!("Total input ({}) less than total output ({}), not enough for fees", total_input, total_output);
-    }
-    info!("  Fee amount: {}", total_input - total_output);
+    trace!("Total inputs: {:?}", inputs);
 
-    let unsigned_tx = Transaction {
-        version: transaction::Version(TX_VERSION),
-        lock_time: absolute::LockTime::ZERO,
+    let lock_time = if config.anti_fee_sniping {
+        let tip = rpc.run(|c| c.get_block_count()).await?;
+        funding_lock_time(u32::try_from(tip)?, rand::thread_rng().gen())?
+    } else {
+        absolute::LockTime::ZERO
+    };
+    info!("  Lock time: {}", lock_time);
+
+    // sized with the change output, if it ends up dropped the fee only gets a bit higher
+    let mut unsigned_tx = Transaction {
+        version: transaction::Version(config.tx_version),
+        lock_time,
         input: inputs,
-        output: outputs,
+        output: vec![
+            destination.clone(),
+            TxOut {
+                value: Amount::ZERO,
+                script_pubkey: change_spk.clone(),
+            },
+        ],
     };
-    
+    let vsize = unsigned_tx.vsize() as u64 + WALLET_INPUT_WITNESS_VSIZE * prevouts.len() as u64;
+    let fee = fee_for_vsize(fee_rate, vsize)?;
+    info!(
+        "  Estimated fee: {} ({} sats/vB, ~{} vB)",
+        fee,
+        fee_rate as f64 / 1000.0,
+        vsize
+    );
+
+    unsigned_tx.output = funding_outputs(total_input, destination, fee, change_spk)?;
+    info!("  Outputs: {}", unsigned_tx.output.len());
+    trace!("  Outputs: {:?}", unsigned_tx.output);
+    let total_output = unsigned_tx
+        .output
+        .iter()
+        .map(|out| out.value)
+        .checked_sum()
+        .ok_or_else(|| anyhow!("funding outputs overflow"))?;
+    let fee = total_input.checked_sub(total_output).ok_or_else(|| {
+        anyhow!(
+            "funding outputs pay {} but the inputs only have {}",
+            total_output,
+            total_input
+        )
+    })?;
+    info!("  Fee amount: {}", fee);
+    let funding_spk = unsigned_tx.output[0].script_pubkey.clone();
+
     let serialized_tx = serialize_hex(&unsigned_tx);
-    info!("  Serialized transaction: {:?}", serialized_tx);
-    
+    info!("  Serialized transaction: {}", redact::hex(&serialized_tx));
+    trace!("  Serialized transaction: {}", serialized_tx);
+
     let signed_tx = rpc
-        .sign_raw_transaction_with_wallet(serialized_tx, None, None)
-        .unwrap();
-    info!("  Signed transaction: {:?}", signed_tx.hex);
-    
-    let txid = rpc.send_raw_transaction(&signed_tx.hex).unwrap();
+        .run(move |c| c.sign_raw_transaction_with_wallet(serialized_tx, None, None))
+        .await?;
+    if !signed_tx.complete {
+        bail!("the wallet couldn't sign every input of the funding tx, it is missing a key");
+    }
+    let signed_hex = signed_tx.hex.to_lower_hex_string();
+    info!("  Signed transaction: {}", redact::hex(&signed_hex));
+    trace!("  Signed transaction: {}", signed_hex);
+    check_standard(rpc, &signed_tx.transaction()?, &prevouts).await?;
+
+    let txid = broadcast(rpc, &signed_tx.hex, BroadcastKind::Funding).await?;
     info!("  Transaction ID: {}", txid);
-    
-    (txid, fee)
+
+    Ok((txid, funding_spk))
 }
 
-pub fn simulate_psbt_signing(
-    rpc: &Client,
+pub async fn simulate_psbt_signing(
+    rpc: &AsyncRpc,
+    config: &NetworkConfig,
     previous_txid: Txid,
+    funding_spk: &Script,
     pool_address: &Address,
-    fee_amount: Amount,
 ) -> Result<Txid> {
     info!("Simulating PSBT signing:");
     info!("  Previous transaction ID: {}", previous_txid);
-    info!("  Pool address: {:?}", pool_address);
-    
-    let previous_tx: Transaction = rpc.get_raw_transaction(&previous_txid, None).unwrap();
+    info!("  Pool address: {}", redact::short(pool_address));
+    trace!("  Pool address: {}", pool_address);
+
+    let previous_tx: Transaction = rpc
+        .run(move |c| c.get_raw_transaction(&previous_txid, None))
+        .await?;
     info!("  Previous transaction outputs:");
     for (i, output) in previous_tx.output.iter().enumerate() {
         info!("    Output {}: Amount {}", i, output.value);
     }
-    
-    let vout = previous_tx
+
+    // by script, another output could have the same value
+    let mut funding_vouts = previous_tx
         .output
         .iter()
-        .position(|vout| vout.value == AMOUNT_PER_USER * POOL_USERS.try_into().unwrap() + fee_amount)
-        .unwrap() as u32;
+        .enumerate()
+        .filter(|(_, output)| output.script_pubkey.as_script() == funding_spk)
+        .map(|(vout, _)| vout as u32);
+    let vout = match (funding_vouts.next(), funding_vouts.next()) {
+        (Some(vout), None) => vout,
+        (None, _) => bail!("{} has no output to {}", previous_txid, funding_spk),
+        (Some(_), Some(_)) => bail!(
+            "{} pays {} more than once, can't tell which output funds the pool",
+            previous_txid,
+            funding_spk
+        ),
+    };
     info!("  Using vout: {}", vout);
-    
+
+    // only spend the outpoint once it is confirmed and still unspent
+    if rpc
+        .run(move |c| c.get_tx_out(&previous_txid, vout, Some(false)))
+        .await?
+        .is_none()
+    {
+        bail!(
+            "{}:{} is not confirmed or already spent",
+            previous_txid,
+            vout
+        );
+    }
+
     let inputs = vec![TxIn {
         previous_output: OutPoint {
             txid: previous_txid,
@@ -137,47 +446,65 @@ pub fn simulate_psbt_signing(
         sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
         ..Default::default()
     }];
-    
+
+    // exactly what the templates spend, the rest of the input is the fee
     let outputs = vec![TxOut {
-        value: AMOUNT_PER_USER * POOL_USERS.try_into().unwrap() + fee_amount,
+        value: required_funding(),
         script_pubkey: pool_address.script_pubkey(),
     }];
-    
+
     let unsigned_tx = Transaction {
-        version: transaction::Version(TX_VERSION),
+        version: transaction::Version(config.tx_version),
         lock_time: absolute::LockTime::ZERO,
         input: inputs,
         output: outputs,
     };
-    
+
     let serialized_tx = serialize_hex(&unsigned_tx);
-    info!("  Serialized transaction: {:?}", serialized_tx);
-    
+    info!("  Serialized transaction: {}", redact::hex(&serialized_tx));
+    trace!("  Serialized transaction: {}", serialized_tx);
+
     let signed_tx = rpc
-        .sign_raw_transaction_with_wallet(serialized_tx, None, None)
-        .unwrap();
-    info!("  Signed transaction: {:?}", signed_tx.hex);
-    
-    let txid = rpc.send_raw_transaction(&signed_tx.hex)?;
+        .run(move |c| c.sign_raw_transaction_with_wallet(serialized_tx, None, None))
+        .await?;
+    if !signed_tx.complete {
+        bail!("the wallet couldn't sign every input of the funding tx, it is missing a key");
+    }
+    let signed_hex = signed_tx.hex.to_lower_hex_string();
+    info!("  Signed transaction: {}", redact::hex(&signed_hex));
+    trace!("  Signed transaction: {}", signed_hex);
+    check_standard(
+        rpc,
+        &signed_tx.transaction()?,
+        &[previous_tx.output[vout as usize].clone()],
+    )
+    .await?;
+
+    let txid = broadcast(rpc, &signed_tx.hex, BroadcastKind::Funding).await?;
     info!("  Transaction ID: {}", txid);
-    
+
     Ok(txid)
 }
 
-pub fn get_vouts_from_init_tx(rpc: &Client, txid: &Txid) -> Vec<GetTransactionResultDetail> {
-    let tx = rpc.get_transaction(txid, None).unwrap();
+// the wallet's view of the outputs of `txid` paying one of `addresses`
+#[allow(dead_code)]
+pub async fn get_vouts_from_init_tx(
+    rpc: &AsyncRpc,
+    txid: Txid,
+    addresses: &[Address],
+) -> Result<Vec<GetTransactionResultDetail>> {
+    let tx = rpc.run(move |c| c.get_transaction(&txid, None)).await?;
     let tx_details = tx.details;
 
     let matched_vouts: Vec<GetTransactionResultDetail> = tx_details
         .iter()
         .filter(|vout| {
-            vout.amount
-                == (AMOUNT_PER_USER + INIT_WALLET_AMOUNT_FEE)
-                    .to_signed()
-                    .unwrap()
+            vout.address
+                .as_ref()
+                .is_some_and(|address| addresses.iter().any(|a| a.as_unchecked() == address))
         })
         .cloned()
         .collect();
 
-    matched_vouts
+    Ok(matched_vouts)
 }
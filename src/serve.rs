@@ -0,0 +1,561 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    fs,
+    net::{IpAddr, SocketAddr},
+    path::PathBuf,
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, bail, Result};
+use bitcoin::Address;
+use serde::Serialize;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    sync::{broadcast::error::RecvError, Notify},
+};
+use tracing::{info, trace, warn};
+
+use crate::{
+    config::NetworkConfig,
+    covenant::backend_from_env,
+    error_codes::{coded, error_code, ErrorCode},
+    event_stream::EventStream,
+    ids::{NodePath, PoolId, UserIndex},
+    lifecycle::Lifecycle,
+    manifest::PoolManifest,
+    pools::build_pools,
+    redact,
+    registration::{shuffle_registrations, verify_registration, AddressDerivation, Registration},
+    watch::watch,
+    webhooks::PoolEvent,
+    POOL_USERS,
+};
+
+// registration attempts one address may make per RATE_WINDOW, valid or not
+const RATE_LIMIT: usize = 5;
+const RATE_WINDOW: Duration = Duration::from_secs(60);
+const MAX_REQUEST_BYTES: usize = 16 * 1024;
+// a comment line on an idle event stream, so proxies don't close it
+const KEEPALIVE: Duration = Duration::from_secs(15);
+
+// Where the open pool is at, GET /pool
+#[derive(Debug, Clone, Serialize)]
+pub struct WindowStatus {
+    pub pool_id: PoolId,
+    pub network: bitcoin::Network,
+    pub registered: usize,
+    pub capacity: usize,
+    // seconds until registration opens, 0 once it has
+    pub opens_in_secs: u64,
+    // seconds until registration closes, 0 once it has
+    pub closes_in_secs: u64,
+    pub open: bool,
+    // a draft until the pool is built
+    pub lifecycle: Lifecycle,
+    pub root_address: Option<String>,
+}
+
+// Registrations taken so far, in the order they came in, and the pool once it is built
+struct Window {
+    config: NetworkConfig,
+    pool_id: PoolId,
+    opens: Instant,
+    deadline: Instant,
+    previous: Option<PoolManifest>,
+    registrations: Vec<Registration>,
+    addresses: Vec<Address>,
+    derivations: Vec<AddressDerivation>,
+    open: bool,
+    manifest: Option<PoolManifest>,
+    attempts: HashMap<IpAddr, VecDeque<Instant>>,
+}
+
+impl Window {
+    fn status(&self) -> WindowStatus {
+        let now = Instant::now();
+        WindowStatus {
+            pool_id: self.pool_id.clone(),
+            network: self.config.network,
+            registered: self.addresses.len(),
+            capacity: POOL_USERS,
+            opens_in_secs: self.opens.saturating_duration_since(now).as_secs(),
+            closes_in_secs: if self.open {
+                self.deadline.saturating_duration_since(now).as_secs()
+            } else {
+                0
+            },
+            open: self.open && now >= self.opens,
+            lifecycle: self
+                .manifest
+                .as_ref()
+                .map_or(Lifecycle::Draft, |manifest| manifest.lifecycle),
+            root_address: self
+                .manifest
+                .as_ref()
+                .map(|manifest| manifest.root_address.clone()),
+        }
+    }
+
+    // false once `ip` has used up its attempts for the current RATE_WINDOW
+    fn allow(&mut self, ip: IpAddr) -> bool {
+        let now = Instant::now();
+        let attempts = self.attempts.entry(ip).or_default();
+        while attempts
+            .front()
+            .is_some_and(|at| now.duration_since(*at) > RATE_WINDOW)
+        {
+            attempts.pop_front();
+        }
+        if attempts.len() >= RATE_LIMIT {
+            return false;
+        }
+        attempts.push_back(now);
+        true
+    }
+
+    fn register(&mut self, registration: Registration) -> Result<UserIndex> {
+        if !self.open {
+            return Err(coded(
+                ErrorCode::RegistrationClosed,
+                "registration is closed",
+            ));
+        }
+        let opens_in = self.opens.saturating_duration_since(Instant::now());
+        if !opens_in.is_zero() {
+            return Err(coded(
+                ErrorCode::RegistrationClosed,
+                format!("registration opens in {}s", opens_in.as_secs().max(1)),
+            ));
+        }
+        let user = UserIndex::new(self.addresses.len())?;
+        let (address, derivation) = verify_registration(
+            user,
+            &registration,
+            self.config.network,
+            &self.pool_id,
+            self.previous.as_ref(),
+        )?;
+        // every address a registration pays, split ones included, belongs to one user only
+        let registered: Vec<String> = self
+            .addresses
+            .iter()
+            .map(|address| address.to_string())
+            .chain(self.registrations.iter().flat_map(|registration| {
+                registration
+                    .splits
+                    .iter()
+                    .map(|split| split.address.assume_checked_ref().to_string())
+            }))
+            .collect();
+        let paid = std::iter::once(address.to_string()).chain(
+            registration
+                .splits
+                .iter()
+                .map(|split| split.address.assume_checked_ref().to_string()),
+        );
+        let mut seen = Vec::new();
+        for paid in paid {
+            if registered.contains(&paid) || seen.contains(&paid) {
+                bail!("{} is already registered", paid);
+            }
+            seen.push(paid);
+        }
+        if let Some(derivation) = &derivation {
+            if self.derivations.iter().any(|d| d.xpub == derivation.xpub) {
+                bail!("this xpub is already registered");
+            }
+        }
+        info!("user {} registered: {}", user, redact::short(&address));
+        trace!("user {} registered: {}", user, address);
+        self.registrations.push(registration);
+        self.addresses.push(address);
+        self.derivations.extend(derivation);
+        Ok(user)
+    }
+}
+
+// Open registration for a pool: once `opens_in` has passed anyone can POST a registration to
+// /register until the pool is full or `window` has passed, whichever comes first. A full pool is built, its manifest written to
+// `manifest_path` and published at /manifest, and served until the process is stopped. Every step
+// is streamed on /events, with `follow` the pool is then watched on chain every `follow` and its
+// funding and withdrawals streamed too.
+#[allow(clippy::too_many_arguments)]
+pub async fn serve_registration(
+    config: NetworkConfig,
+    pool_id: PoolId,
+    listen: SocketAddr,
+    opens_in: Duration,
+    window: Duration,
+    manifest_path: PathBuf,
+    registrations_path: PathBuf,
+    follow: Option<Duration>,
+) -> Result<()> {
+    // the presigned backend's key would be gone before anyone funds the pool
+    if backend_from_env(&config)?.requires_presigning() {
+        bail!("serve only builds pools with a consensus covenant, the presigned backend has to sign at funding time");
+    }
+    // the last pool's manifest says which xpub children were paid already
+    let previous = if manifest_path.exists() {
+        Some(PoolManifest::load(&manifest_path)?)
+    } else {
+        None
+    };
+
+    let state = Arc::new(Mutex::new(Window {
+        config: config.clone(),
+        pool_id: pool_id.clone(),
+        opens: Instant::now() + opens_in,
+        deadline: Instant::now() + opens_in + window,
+        previous,
+        registrations: Vec::new(),
+        addresses: Vec::new(),
+        derivations: Vec::new(),
+        open: true,
+        manifest: None,
+        attempts: HashMap::new(),
+    }));
+    let full = Arc::new(Notify::new());
+    let events = EventStream::new();
+
+    let listener = TcpListener::bind(listen).await?;
+    info!(
+        "registration for {:?} on http://{} for {} users, opening in {}s and closing {}s after \n",
+        pool_id,
+        listen,
+        POOL_USERS,
+        opens_in.as_secs(),
+        window.as_secs()
+    );
+    {
+        let state = state.clone();
+        let full = full.clone();
+        let events = events.clone();
+        tokio::spawn(async move {
+            loop {
+                match listener.accept().await {
+                    Ok((stream, peer)) => {
+                        let state = state.clone();
+                        let full = full.clone();
+                        let events = events.clone();
+                        tokio::spawn(async move {
+                            if let Err(e) = answer(stream, peer.ip(), &state, &full, &events).await
+                            {
+                                warn!("request from {} failed: {}", peer, e);
+                            }
+                        });
+                    }
+                    Err(e) => warn!("registration listener: {}", e),
+                }
+            }
+        });
+    }
+
+    tokio::select! {
+        _ = tokio::time::sleep(opens_in + window) => info!("registration window is over"),
+        _ = full.notified() => info!("pool is full"),
+    }
+    let (addresses, derivations, registrations) = {
+        let mut window = lock(&state)?;
+        window.open = false;
+        (
+            window.addresses.clone(),
+            window.derivations.clone(),
+            window.registrations.clone(),
+        )
+    };
+    events.publish(
+        Some(&pool_id),
+        "",
+        &PoolEvent::RegistrationClosed {
+            registered: addresses.len(),
+            capacity: POOL_USERS,
+        },
+        &[],
+    );
+    fs::write(
+        &registrations_path,
+        serde_json::to_string_pretty(&registrations)?,
+    )?;
+    if addresses.len() < POOL_USERS {
+        bail!(
+            "registration closed with {} of {} users, no pool built (registrations in {})",
+            addresses.len(),
+            POOL_USERS,
+            registrations_path.display()
+        );
+    }
+
+    // users who split their exit, by the address the pool pays them
+    let mut config = config;
+    config.splits = registrations
+        .iter()
+        .zip(&addresses)
+        .filter(|(registration, _)| !registration.splits.is_empty())
+        .map(|(registration, address)| (address.to_string(), registration.splits.clone()))
+        .collect();
+    // and users bumping their own exit
+    config.anchor_keys = registrations
+        .iter()
+        .zip(&addresses)
+        .filter_map(|(registration, address)| {
+            registration
+                .anchor_key
+                .map(|key| (address.to_string(), key))
+        })
+        .collect();
+    // and the keys of a key path close
+    config.musig_keys = registrations
+        .iter()
+        .zip(&addresses)
+        .filter_map(|(registration, address)| {
+            registration.musig_key.map(|key| (address.to_string(), key))
+        })
+        .collect();
+    let manifest = tokio::task::spawn_blocking(move || {
+        build_manifest(&config, pool_id, &addresses, derivations)
+    })
+    .await??;
+    manifest.write(&manifest_path)?;
+    info!(
+        "pool {} built, manifest in {} and at http://{}/manifest \n",
+        manifest.root_address,
+        manifest_path.display(),
+        listen
+    );
+    events.publish(
+        manifest.pool_id.as_ref(),
+        &manifest.root_address,
+        &PoolEvent::PoolBuilt {
+            root_address: manifest.root_address.clone(),
+        },
+        &[],
+    );
+    lock(&state)?.manifest = Some(manifest.clone());
+
+    if let Some(interval) = follow {
+        info!("following the pool on chain, its events streamed on /events \n");
+        if let Err(e) = watch(manifest, &manifest_path, interval, Some(events)).await {
+            warn!("stopped following the pool: {}", e);
+        }
+    }
+    // keep publishing the manifest
+    std::future::pending::<()>().await;
+    Ok(())
+}
+
+fn build_manifest(
+    config: &NetworkConfig,
+    pool_id: PoolId,
+    addresses: &[Address],
+    derivations: Vec<AddressDerivation>,
+) -> Result<PoolManifest> {
+    let backend = backend_from_env(config)?;
+    let anchor_addr =
+        Address::from_str(&config.fee_anchor_addr)?.require_network(config.network)?;
+    let (addresses, derivations, leaf_order) =
+        shuffle_registrations(&pool_id, addresses, &derivations)?;
+    let pools = build_pools(&addresses, &anchor_addr, config, backend.as_ref())?;
+    let root = Address::p2tr_tweaked(
+        pools.spend_info(&NodePath::root())?.output_key(),
+        config.network,
+    );
+    let mut manifest = PoolManifest::new(config, backend.as_ref(), &anchor_addr, &addresses, &root);
+    manifest.pool_id = Some(pool_id);
+    manifest.derivations = derivations;
+    manifest.leaf_order = leaf_order;
+    Ok(manifest)
+}
+
+fn lock(state: &Mutex<Window>) -> Result<std::sync::MutexGuard<'_, Window>> {
+    state
+        .lock()
+        .map_err(|_| anyhow!("registration state poisoned"))
+}
+
+// GET /pool, POST /register, GET /manifest, GET /events. Just enough http for curl and a small web page.
+async fn answer(
+    mut stream: TcpStream,
+    ip: IpAddr,
+    state: &Mutex<Window>,
+    full: &Notify,
+    events: &EventStream,
+) -> Result<()> {
+    let request = read_request(&mut stream).await?;
+    let (method, path, body) = (request.method, request.path, request.body);
+    if (method.as_str(), path.as_str()) == ("GET", "/events") {
+        return stream_events(stream, events, request.last_event_id).await;
+    }
+    let (status, body) = match (method.as_str(), path.as_str()) {
+        ("GET", "/pool") => ("200 OK", serde_json::to_string(&lock(state)?.status())?),
+        ("GET", "/manifest") => match &lock(state)?.manifest {
+            Some(manifest) => ("200 OK", serde_json::to_string_pretty(manifest)?),
+            None => (
+                "404 Not Found",
+                error_body(ErrorCode::NotFound, "the pool is not built yet"),
+            ),
+        },
+        ("POST", "/register") => {
+            let mut window = lock(state)?;
+            if !window.allow(ip) {
+                (
+                    "429 Too Many Requests",
+                    error_body(ErrorCode::RateLimited, "slow down"),
+                )
+            } else {
+                let result = serde_json::from_slice::<Registration>(&body)
+                    .map_err(anyhow::Error::from)
+                    .and_then(|registration| window.register(registration));
+                match result {
+                    Ok(user) => {
+                        events.publish(
+                            Some(&window.pool_id),
+                            "",
+                            &PoolEvent::UserRegistered {
+                                user,
+                                registered: window.addresses.len(),
+                                capacity: POOL_USERS,
+                            },
+                            &[],
+                        );
+                        if window.addresses.len() == POOL_USERS {
+                            window.open = false;
+                            full.notify_one();
+                        }
+                        ("200 OK", serde_json::json!({ "user": user }).to_string())
+                    }
+                    // whatever has no code of its own is something wrong with the registration
+                    Err(e) => {
+                        let code = match error_code(&e) {
+                            ErrorCode::Internal => ErrorCode::RegistrationInvalid,
+                            code => code,
+                        };
+                        ("400 Bad Request", error_body(code, &e.to_string()))
+                    }
+                }
+            }
+        }
+        _ => (
+            "404 Not Found",
+            error_body(
+                ErrorCode::NotFound,
+                "GET /pool, POST /register, GET /manifest or GET /events",
+            ),
+        ),
+    };
+
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await?;
+    Ok(())
+}
+
+// Server-sent events until the client goes away: the kept events it hasn't seen, then every new one
+async fn stream_events(
+    mut stream: TcpStream,
+    events: &EventStream,
+    last_event_id: Option<u64>,
+) -> Result<()> {
+    let (missed, mut receiver) = events.subscribe(last_event_id);
+    stream
+        .write_all(
+            b"HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-cache\r\nConnection: keep-alive\r\n\r\n",
+        )
+        .await?;
+    for event in missed {
+        stream.write_all(event.to_sse().as_bytes()).await?;
+    }
+    let mut keepalive = tokio::time::interval(KEEPALIVE);
+    loop {
+        let chunk = tokio::select! {
+            event = receiver.recv() => match event {
+                Ok(event) => event.to_sse(),
+                // a client this slow reconnects with its Last-Event-ID and gets the rest
+                Err(RecvError::Lagged(skipped)) => {
+                    trace!("event stream client fell {} events behind", skipped);
+                    return Ok(());
+                }
+                Err(RecvError::Closed) => return Ok(()),
+            },
+            _ = keepalive.tick() => ": keepalive\n\n".to_string(),
+        };
+        // the client closing the stream is how it ends
+        if stream.write_all(chunk.as_bytes()).await.is_err() {
+            return Ok(());
+        }
+    }
+}
+
+// {"code": "E_...", "error": "..."}, like --json-errors
+fn error_body(code: ErrorCode, error: &str) -> String {
+    serde_json::json!({ "code": code, "error": error }).to_string()
+}
+
+struct Request {
+    method: String,
+    path: String,
+    // the id of the last event an event stream client saw, when it reconnects
+    last_event_id: Option<u64>,
+    body: Vec<u8>,
+}
+
+// request line, the headers we use and the body, which is as long as Content-Length says
+async fn read_request(stream: &mut TcpStream) -> Result<Request> {
+    let mut buf = Vec::new();
+    let mut chunk = [0u8; 4096];
+    let header_end = loop {
+        let read = stream.read(&mut chunk).await?;
+        if read == 0 {
+            bail!("connection closed before the request was complete");
+        }
+        buf.extend_from_slice(&chunk[..read]);
+        if let Some(end) = buf.windows(4).position(|w| w == b"\r\n\r\n") {
+            break end + 4;
+        }
+        if buf.len() > MAX_REQUEST_BYTES {
+            bail!("request too large");
+        }
+    };
+
+    let head = String::from_utf8_lossy(&buf[..header_end]).to_string();
+    let mut request_line = head.lines().next().unwrap_or_default().split_whitespace();
+    let method = request_line.next().unwrap_or_default().to_string();
+    let path = request_line.next().unwrap_or_default().to_string();
+    let header = |wanted: &str| {
+        head.lines()
+            .filter_map(|line| line.split_once(':'))
+            .find(|(name, _)| name.trim().eq_ignore_ascii_case(wanted))
+            .map(|(_, value)| value.trim().to_string())
+    };
+    let content_length: usize = header("content-length")
+        .map(|value| value.parse())
+        .transpose()?
+        .unwrap_or(0);
+    let last_event_id = header("last-event-id").and_then(|value| value.parse().ok());
+    if content_length > MAX_REQUEST_BYTES {
+        bail!("request too large");
+    }
+
+    let mut body = buf[header_end..].to_vec();
+    while body.len() < content_length {
+        let read = stream.read(&mut chunk).await?;
+        if read == 0 {
+            bail!("connection closed before the body was complete");
+        }
+        body.extend_from_slice(&chunk[..read]);
+    }
+    body.truncate(content_length);
+    Ok(Request {
+        method,
+        path,
+        last_event_id,
+        body,
+    })
+}
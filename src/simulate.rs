@@ -0,0 +1,345 @@
+use std::{fs, path::Path};
+
+use anyhow::{anyhow, bail, Context, Result};
+use bitcoin::{hashes::Hash, Address, Amount, OutPoint, TxOut, Txid};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::NetworkConfig,
+    covenant::CovenantBackend,
+    fee_bump::{
+        next_bump, BumpAction, BumpedChild, FeeBumpPolicy, PendingSpend, DEFAULT_BUMP_JOURNAL,
+        DEFAULT_BUMP_MAX_FEE,
+    },
+    ids::{NodePath, UserIndex},
+    pools::{FeeCoin, CPFP_CHILD_VSIZE},
+    template_fees::{template_fees, LIKELY_MIN_RELAY_FEE_RATE},
+    tree::PoolTree,
+};
+
+// blocks simulated after the scenario's last point when it doesn't say how many, about a week
+pub const DEFAULT_TAIL_BLOCKS: u64 = 1008;
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct FeeratePoint {
+    // blocks after the one the funding tx confirms in
+    pub block: u64,
+    pub sat_per_vb: f64,
+}
+
+// A feerate time series for `simulate fees`: from a point's block until the next point, a package
+// has to pay at least its sat_per_vb to get into a block. Before the first point the first applies.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct FeeScenario {
+    #[serde(default)]
+    pub name: Option<String>,
+    pub points: Vec<FeeratePoint>,
+    // how many blocks to simulate, the last point's block and DEFAULT_TAIL_BLOCKS by default
+    #[serde(default)]
+    pub blocks: Option<u64>,
+}
+
+impl FeeScenario {
+    pub fn load(path: &Path) -> Result<Self> {
+        let json = fs::read_to_string(path)
+            .with_context(|| format!("can't read the fee scenario {}", path.display()))?;
+        let scenario: Self = serde_json::from_str(&json)
+            .with_context(|| format!("{} is not a fee scenario", path.display()))?;
+        scenario.check()?;
+        Ok(scenario)
+    }
+
+    pub fn check(&self) -> Result<()> {
+        if self.points.is_empty() {
+            bail!("the fee scenario has no points");
+        }
+        for pair in self.points.windows(2) {
+            if pair[1].block <= pair[0].block {
+                bail!(
+                    "the fee scenario's points have to be in block order, block {} comes after block {}",
+                    pair[1].block,
+                    pair[0].block
+                );
+            }
+        }
+        if let Some(point) = self
+            .points
+            .iter()
+            .find(|point| !point.sat_per_vb.is_finite() || point.sat_per_vb < 0.0)
+        {
+            bail!(
+                "the fee scenario's point at block {} has a fee rate of {} sat/vB",
+                point.block,
+                point.sat_per_vb
+            );
+        }
+        Ok(())
+    }
+
+    pub fn blocks(&self) -> u64 {
+        self.blocks.unwrap_or_else(|| {
+            self.points.last().map_or(0, |point| point.block) + DEFAULT_TAIL_BLOCKS
+        })
+    }
+
+    // sat/kvB a package needs to get into `block`
+    pub fn fee_rate_at(&self, block: u64) -> u64 {
+        let point = self
+            .points
+            .iter()
+            .take_while(|point| point.block <= block)
+            .last()
+            .or(self.points.first());
+        point.map_or(0, |point| (point.sat_per_vb * 1000.0).round() as u64)
+    }
+}
+
+// The bumping the simulation applies: the pool's (POOL_BUMP_AFTER_BLOCKS, POOL_BUMP_MAX_FEE_SATS)
+// with the command line's on top, to try anchor budgets before setting them
+pub fn bump_policy(
+    configured: Option<FeeBumpPolicy>,
+    after_blocks: Option<u64>,
+    max_fee: Option<Amount>,
+) -> Result<Option<FeeBumpPolicy>> {
+    let policy = match (configured, after_blocks) {
+        (Some(policy), None) => Some(policy),
+        (configured, Some(after_blocks)) => Some(FeeBumpPolicy {
+            after_blocks,
+            max_fee: configured
+                .as_ref()
+                .map_or(DEFAULT_BUMP_MAX_FEE, |policy| policy.max_fee),
+            journal: configured.map_or_else(|| DEFAULT_BUMP_JOURNAL.into(), |policy| policy.journal),
+        }),
+        (None, None) if max_fee.is_some() => bail!(
+            "a bump budget needs bumping turned on, with --bump-after-blocks or POOL_BUMP_AFTER_BLOCKS"
+        ),
+        (None, None) => None,
+    };
+    Ok(policy.map(|policy| FeeBumpPolicy {
+        max_fee: max_fee.unwrap_or(policy.max_fee),
+        ..policy
+    }))
+}
+
+// How one spend of the planned unwind fares under the scenario
+#[derive(Debug, Clone, Serialize)]
+pub struct SimulatedLevel {
+    pub level: usize,
+    pub users: NodePath,
+    pub spender: UserIndex,
+    pub vsize: u64,
+    // what the template pays itself
+    pub fee: Amount,
+    // the first block it can be mined in, once its node is as deep as the unwind delay asks. None
+    // when the level before never confirms
+    pub eligible: Option<u64>,
+    pub confirmed: Option<u64>,
+    // the anchor child it ends up with, replacements supersede the children before them
+    pub child_fee: Amount,
+    pub bumps: usize,
+    // the child pays the budget and it still doesn't get in
+    pub budget_spent: bool,
+    // sat/kvB of the spend and its child when it is mined, or the last it offered
+    pub fee_rate: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FeeSimulation {
+    pub scenario: Option<String>,
+    pub blocks: u64,
+    pub anchored: bool,
+    // the bumping applied, none without anchors as nothing can pay for a spend then
+    pub bump_after_blocks: Option<u64>,
+    pub bump_max_fee: Option<Amount>,
+    pub levels: Vec<SimulatedLevel>,
+    // the block the last level confirms in, None when the unwind doesn't finish within the scenario
+    pub unwound_at: Option<u64>,
+    // what the fee payer spends on anchor children over the whole unwind
+    pub child_fees: Amount,
+    pub max_child_fee: Amount,
+}
+
+// the child the simulation pretends to send, only its fee matters
+fn simulated_child(fee: Amount) -> BumpedChild {
+    BumpedChild {
+        txid: Txid::all_zeros(),
+        fee,
+        fee_coin: FeeCoin {
+            outpoint: OutPoint::null(),
+            txout: TxOut::NULL,
+        },
+    }
+}
+
+// sat/kvB a miner gets for the spend, with its child once it has one. The child also collects the
+// anchor's value, so the package pays all the template committed.
+fn paid_fee_rate(spend: &PendingSpend, committed_fee: Amount) -> u64 {
+    match &spend.child {
+        Some(child) => {
+            (committed_fee + child.fee).to_sat() * 1000 / (spend.vsize + CPFP_CHILD_VSIZE)
+        }
+        None => spend.fee.to_sat() * 1000 / spend.vsize,
+    }
+}
+
+// Replay `scenario` against the planned unwind (user 0 leaves first), block by block. Each spend goes
+// out as soon as its node is deep enough and pays its committed fee, then gets anchor children from
+// `bump` like the unwind gives stuck spends outside regtest, the node estimating what the last
+// block took. A block takes the spend once it pays the scenario's fee rate and the default min relay
+// fee. Block 0 is the funding tx's.
+pub fn simulate_fees(
+    pools: &PoolTree,
+    config: &NetworkConfig,
+    backend: &dyn CovenantBackend,
+    addresses: &[Address],
+    anchor_addr: &Address,
+    scenario: &FeeScenario,
+    bump: Option<&FeeBumpPolicy>,
+) -> Result<FeeSimulation> {
+    let fees = template_fees(pools, config, backend, addresses, anchor_addr)?;
+    let anchored = config.anchor_amount.is_some();
+    let bump = bump.filter(|_| anchored);
+    let blocks = scenario.blocks();
+
+    let mut levels = Vec::new();
+    // the block the node the next level spends was mined in
+    let mut created = Some(0);
+    for (level, spender) in config.spenders()?.into_iter().enumerate() {
+        let users = config.unwind_node(spender)?;
+        let template = fees
+            .iter()
+            .find(|template| template.users == users && template.spender == Some(spender))
+            .ok_or_else(|| {
+                anyhow!(
+                    "no template for user {} leaving the pool of users {}",
+                    spender,
+                    users
+                )
+            })?;
+        let delay = config
+            .unwind_delay_for(&users)
+            .map_or(1, |blocks| u64::from(blocks).max(1));
+        let eligible = created.map(|block| block + delay);
+
+        let mut spend = PendingSpend {
+            txid: Txid::all_zeros(),
+            label: format!("user {}", spender),
+            vsize: template.vsize,
+            fee: template.fee,
+            broadcast_height: eligible.unwrap_or(0).saturating_sub(1),
+            since: eligible.unwrap_or(0).saturating_sub(1),
+            child: None,
+            budget_spent: false,
+        };
+        let mut bumps = 0;
+        let mut confirmed = None;
+        for block in eligible.into_iter().flat_map(|eligible| eligible..blocks) {
+            let tip = block - 1;
+            if let Some((action, fee)) =
+                bump.and_then(|policy| next_bump(policy, &spend, tip, scenario.fee_rate_at(tip)))
+            {
+                spend.since = tip;
+                match action {
+                    BumpAction::BudgetSpent => spend.budget_spent = true,
+                    BumpAction::Child | BumpAction::Replace => {
+                        spend.child = Some(simulated_child(fee));
+                        bumps += 1;
+                    }
+                }
+            }
+            let needed = scenario.fee_rate_at(block).max(LIKELY_MIN_RELAY_FEE_RATE);
+            if paid_fee_rate(&spend, template.committed_fee) >= needed {
+                confirmed = Some(block);
+                break;
+            }
+        }
+        levels.push(SimulatedLevel {
+            level,
+            users,
+            spender,
+            vsize: template.vsize,
+            fee: template.fee,
+            eligible,
+            confirmed,
+            child_fee: spend.child.as_ref().map_or(Amount::ZERO, |child| child.fee),
+            bumps,
+            budget_spent: spend.budget_spent,
+            fee_rate: paid_fee_rate(&spend, template.committed_fee),
+        });
+        created = confirmed;
+    }
+
+    let child_fees = levels.iter().map(|level| level.child_fee).sum();
+    let max_child_fee = levels
+        .iter()
+        .map(|level| level.child_fee)
+        .max()
+        .unwrap_or(Amount::ZERO);
+    Ok(FeeSimulation {
+        scenario: scenario.name.clone(),
+        blocks,
+        anchored,
+        bump_after_blocks: bump.map(|policy| policy.after_blocks),
+        bump_max_fee: bump.map(|policy| policy.max_fee),
+        unwound_at: created,
+        levels,
+        child_fees,
+        max_child_fee,
+    })
+}
+
+pub fn print_fee_simulation(simulation: &FeeSimulation) {
+    println!(
+        "scenario: {} ({} blocks)",
+        simulation.scenario.as_deref().unwrap_or("unnamed"),
+        simulation.blocks
+    );
+    match (simulation.anchored, simulation.bump_after_blocks, simulation.bump_max_fee) {
+        (false, _, _) => println!("no anchors: every spend has only its committed fee"),
+        (true, Some(after_blocks), Some(max_fee)) => println!(
+            "anchor children after {} blocks stuck, doubling every {} blocks up to {} sat a spend",
+            after_blocks,
+            after_blocks,
+            max_fee.to_sat()
+        ),
+        (true, _, _) => println!(
+            "no bumping (POOL_BUMP_AFTER_BLOCKS or --bump-after-blocks), the anchors are never spent"
+        ),
+    }
+    for level in &simulation.levels {
+        let label = format!("level {} (user {})", level.level, level.spender);
+        match (level.eligible, level.confirmed) {
+            (None, _) => println!("{}: never sent, the level before is stuck", label),
+            (Some(eligible), Some(confirmed)) => println!(
+                "{}: sent at block {}, confirmed at block {} after {} blocks, {:.2} sat/vB, child {} sat after {} bumps",
+                label,
+                eligible,
+                confirmed,
+                confirmed - eligible + 1,
+                level.fee_rate as f64 / 1000.0,
+                level.child_fee.to_sat(),
+                level.bumps
+            ),
+            (Some(eligible), None) => println!(
+                "{}: sent at block {}, still unconfirmed at {:.2} sat/vB{}",
+                label,
+                eligible,
+                level.fee_rate as f64 / 1000.0,
+                if level.budget_spent {
+                    ", its child pays the whole budget"
+                } else {
+                    ""
+                }
+            ),
+        }
+    }
+    match simulation.unwound_at {
+        Some(block) => println!("unwound at block {}", block),
+        None => println!("not unwound within {} blocks", simulation.blocks),
+    }
+    println!(
+        "anchor children: {} sat in total, {} sat the most for one spend",
+        simulation.child_fees.to_sat(),
+        simulation.max_child_fee.to_sat()
+    );
+}
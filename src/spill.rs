@@ -0,0 +1,147 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{BufWriter, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    process,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use anyhow::{anyhow, bail, Result};
+use tracing::{trace, warn};
+
+use crate::tree::{PoolLevel, SerializablePoolNode};
+
+// tells the spill files of one process apart
+static SPILL_FILES: AtomicUsize = AtomicUsize::new(0);
+
+// A temp file the levels of a tree over its memory budget are written to once the next level is
+// built on them. The file is removed when the last tree using it is dropped.
+#[derive(Debug)]
+pub struct SpillFile {
+    path: PathBuf,
+    file: Mutex<File>,
+}
+
+impl SpillFile {
+    // a new empty file in `dir`
+    pub fn create(dir: &Path) -> Result<Arc<Self>> {
+        let path = dir.join(format!(
+            "op_ctv_pool_tree_{}_{}.spill",
+            process::id(),
+            SPILL_FILES.fetch_add(1, Ordering::Relaxed)
+        ));
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(&path)
+            .map_err(|e| anyhow!("can't create tree spill file {}: {}", path.display(), e))?;
+        trace!("spilling tree levels to {}", path.display());
+        Ok(Arc::new(Self {
+            path,
+            file: Mutex::new(file),
+        }))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    // Appends every node of `level`, each one length prefixed, and returns where they are
+    pub fn write_level(self: &Arc<Self>, level: &PoolLevel) -> Result<SpilledLevel> {
+        let mut file = self
+            .file
+            .lock()
+            .map_err(|_| anyhow!("tree spill file {} poisoned", self.path.display()))?;
+        let offset = file.seek(SeekFrom::End(0))?;
+        let mut writer = BufWriter::new(&mut *file);
+        let mut len = 0;
+        for node in level.values() {
+            let bytes = postcard::to_stdvec(&SerializablePoolNode::from(node))?;
+            writer.write_all(&u32::try_from(bytes.len())?.to_le_bytes())?;
+            writer.write_all(&bytes)?;
+            len += 4 + bytes.len() as u64;
+        }
+        writer.flush()?;
+        Ok(SpilledLevel {
+            file: self.clone(),
+            offset,
+            len,
+            nodes: level.len(),
+        })
+    }
+
+    fn read(&self, offset: u64, len: u64) -> Result<Vec<u8>> {
+        let mut file = self
+            .file
+            .lock()
+            .map_err(|_| anyhow!("tree spill file {} poisoned", self.path.display()))?;
+        file.seek(SeekFrom::Start(offset))?;
+        let mut bytes = vec![0; usize::try_from(len)?];
+        file.read_exact(&mut bytes)?;
+        Ok(bytes)
+    }
+}
+
+impl Drop for SpillFile {
+    fn drop(&mut self) {
+        if let Err(e) = fs::remove_file(&self.path) {
+            warn!(
+                "can't remove tree spill file {}: {}",
+                self.path.display(),
+                e
+            );
+        }
+    }
+}
+
+// One level of the tree in a spill file
+#[derive(Debug, Clone)]
+pub struct SpilledLevel {
+    file: Arc<SpillFile>,
+    offset: u64,
+    len: u64,
+    nodes: usize,
+}
+
+impl SpilledLevel {
+    pub fn len(&self) -> usize {
+        self.nodes
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes == 0
+    }
+
+    // The level read back, every node's tap tree rebuilt and checked against its output key
+    pub fn load(&self) -> Result<PoolLevel> {
+        let bytes = self.file.read(self.offset, self.len)?;
+        let mut level = PoolLevel::with_capacity(self.nodes);
+        let mut rest = bytes.as_slice();
+        while !rest.is_empty() {
+            let Some((prefix, after)) = rest.split_first_chunk::<4>() else {
+                bail!("tree spill file {} is cut short", self.file.path.display());
+            };
+            let len = u32::from_le_bytes(*prefix) as usize;
+            if after.len() < len {
+                bail!("tree spill file {} is cut short", self.file.path.display());
+            }
+            let (node, after) = after.split_at(len);
+            let node = postcard::from_bytes::<SerializablePoolNode>(node)?.into_node()?;
+            level.insert(node.users.clone(), node);
+            rest = after;
+        }
+        if level.len() != self.nodes {
+            bail!(
+                "tree spill file {} has {} nodes for a level of {}",
+                self.file.path.display(),
+                level.len(),
+                self.nodes
+            );
+        }
+        Ok(level)
+    }
+}
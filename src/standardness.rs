@@ -0,0 +1,228 @@
+use anyhow::{bail, Result};
+use bitcoin::{
+    policy::{MAX_STANDARD_TX_SIGOPS_COST, MAX_STANDARD_TX_WEIGHT},
+    Amount, OutPoint, Transaction, TxOut,
+};
+use bitcoincore_rpc::RpcApi;
+use tracing::warn;
+
+use crate::rpc_helper::AsyncRpc;
+
+// Bitcoin Core's relay policy (policy/policy.h, policy/truc_policy.h), checked before broadcast so a
+// bad template fails with a reason instead of an opaque "non-standard" / "min relay fee not met" from the node.
+const MAX_STANDARD_VERSION: i32 = 3;
+pub const MIN_STANDARD_TX_NONWITNESS_SIZE: usize = 65;
+const TRUC_MAX_VSIZE: usize = 10_000;
+const MAX_STANDARD_SCRIPTSIG_SIZE: usize = 1650;
+const MAX_OP_RETURN_RELAY: usize = 83;
+const MAX_STANDARD_P2WSH_SCRIPT_SIZE: usize = 3600;
+// witness stack items other than the script and control block, p2wsh and tapscript alike
+const MAX_STANDARD_WITNESS_ITEM_SIZE: usize = 80;
+const TAPROOT_ANNEX_TAG: u8 = 0x50;
+const TAPROOT_LEAF_MASK: u8 = 0xfe;
+const TAPROOT_LEAF_TAPSCRIPT: u8 = 0xc0;
+
+// Everything wrong with `tx` under standard relay policy, empty if a default node would relay it.
+// `prevouts` are the outputs spent by each input, in order.
+pub fn lint_transaction(
+    tx: &Transaction,
+    prevouts: &[TxOut],
+    min_relay_fee: Amount,
+) -> Vec<String> {
+    let mut problems = Vec::new();
+    if prevouts.len() != tx.input.len() {
+        problems.push(format!(
+            "{} inputs but {} spent outputs given, can't check the fee",
+            tx.input.len(),
+            prevouts.len()
+        ));
+        return problems;
+    }
+
+    if !(1..=MAX_STANDARD_VERSION).contains(&tx.version.0) {
+        problems.push(format!(
+            "version {} is non-standard, only 1 to {} relay",
+            tx.version.0, MAX_STANDARD_VERSION
+        ));
+    }
+    if tx.weight().to_wu() > u64::from(MAX_STANDARD_TX_WEIGHT) {
+        problems.push(format!(
+            "weight {} is over the standard limit of {}",
+            tx.weight(),
+            MAX_STANDARD_TX_WEIGHT
+        ));
+    }
+    if tx.version.0 == 3 && tx.vsize() > TRUC_MAX_VSIZE {
+        problems.push(format!(
+            "v3 (TRUC) transactions can be at most {} vB, this one is {} vB",
+            TRUC_MAX_VSIZE,
+            tx.vsize()
+        ));
+    }
+    if tx.base_size() < MIN_STANDARD_TX_NONWITNESS_SIZE {
+        problems.push(format!(
+            "{} bytes without witness, under the {} byte minimum (tx-size-small)",
+            tx.base_size(),
+            MIN_STANDARD_TX_NONWITNESS_SIZE
+        ));
+    }
+
+    for (i, (input, prevout)) in tx.input.iter().zip(prevouts).enumerate() {
+        if input.script_sig.len() > MAX_STANDARD_SCRIPTSIG_SIZE {
+            problems.push(format!(
+                "input {} scriptSig is {} bytes, over {}",
+                i,
+                input.script_sig.len(),
+                MAX_STANDARD_SCRIPTSIG_SIZE
+            ));
+        }
+
+        let witness: Vec<&[u8]> = input.witness.iter().collect();
+        if prevout.script_pubkey.is_p2tr() && witness.len() > 1 {
+            if witness
+                .last()
+                .is_some_and(|item| item.first() == Some(&TAPROOT_ANNEX_TAG))
+            {
+                problems.push(format!("input {} has a taproot annex", i));
+            }
+            // core only relays spends of leaf versions it knows (discourage upgradable taproot version)
+            if let Some(version) = witness
+                .last()
+                .and_then(|control_block| control_block.first())
+                .filter(|first| **first != TAPROOT_ANNEX_TAG)
+                .map(|first| first & TAPROOT_LEAF_MASK)
+                .filter(|version| *version != TAPROOT_LEAF_TAPSCRIPT)
+            {
+                problems.push(format!(
+                    "input {} spends a leaf with version {:#04x}, only 0xc0 relays",
+                    i, version
+                ));
+            }
+            // script path: <stack items> <script> <control block>
+            if witness.len() > 2 {
+                for item in &witness[..witness.len() - 2] {
+                    if item.len() > MAX_STANDARD_WITNESS_ITEM_SIZE {
+                        problems.push(format!(
+                            "input {} tapscript stack item of {} bytes, over {}",
+                            i,
+                            item.len(),
+                            MAX_STANDARD_WITNESS_ITEM_SIZE
+                        ));
+                    }
+                }
+            }
+        }
+        if prevout.script_pubkey.is_p2wsh() {
+            if let Some((script, items)) = witness.split_last() {
+                if script.len() > MAX_STANDARD_P2WSH_SCRIPT_SIZE {
+                    problems.push(format!(
+                        "input {} witness script is {} bytes, over {}",
+                        i,
+                        script.len(),
+                        MAX_STANDARD_P2WSH_SCRIPT_SIZE
+                    ));
+                }
+                if items
+                    .iter()
+                    .any(|item| item.len() > MAX_STANDARD_WITNESS_ITEM_SIZE)
+                {
+                    problems.push(format!(
+                        "input {} has a witness item over {} bytes",
+                        i, MAX_STANDARD_WITNESS_ITEM_SIZE
+                    ));
+                }
+            }
+        }
+    }
+
+    let mut op_returns = 0;
+    for (i, output) in tx.output.iter().enumerate() {
+        let script = &output.script_pubkey;
+        if script.is_op_return() {
+            op_returns += 1;
+            if script.len() > MAX_OP_RETURN_RELAY {
+                problems.push(format!(
+                    "output {} OP_RETURN is {} bytes, over {}",
+                    i,
+                    script.len(),
+                    MAX_OP_RETURN_RELAY
+                ));
+            }
+            continue;
+        }
+
+        if !(script.is_p2pkh() || script.is_p2sh() || script.is_witness_program()) {
+            problems.push(format!(
+                "output {} script {} is not a standard type",
+                i,
+                script.to_asm_string()
+            ));
+        }
+        if output.value < script.minimal_non_dust() {
+            problems.push(format!(
+                "output {} of {} is dust, at least {} is needed",
+                i,
+                output.value,
+                script.minimal_non_dust()
+            ));
+        }
+    }
+    if op_returns > 1 {
+        problems.push(format!("{} OP_RETURN outputs, only one relays", op_returns));
+    }
+
+    let sigops = tx.total_sigop_cost(|outpoint: &OutPoint| {
+        tx.input
+            .iter()
+            .position(|input| input.previous_output == *outpoint)
+            .map(|i| prevouts[i].clone())
+    });
+    if sigops > MAX_STANDARD_TX_SIGOPS_COST as usize {
+        problems.push(format!(
+            "sigop cost {} is over the standard limit of {}",
+            sigops, MAX_STANDARD_TX_SIGOPS_COST
+        ));
+    }
+
+    let input_value: Amount = prevouts.iter().map(|prevout| prevout.value).sum();
+    let output_value: Amount = tx.output.iter().map(|output| output.value).sum();
+    match input_value.checked_sub(output_value) {
+        None => problems.push(format!(
+            "outputs ({}) are worth more than the inputs ({})",
+            output_value, input_value
+        )),
+        Some(fee) => {
+            // the node's min relay fee is per kvB, rounded up like core does
+            let needed =
+                Amount::from_sat((min_relay_fee.to_sat() * tx.vsize() as u64).div_ceil(1000));
+            if fee < needed {
+                problems.push(format!(
+                    "fee {} for {} vB is under the node's min relay fee ({} needed)",
+                    fee,
+                    tx.vsize(),
+                    needed
+                ));
+            }
+        }
+    }
+
+    problems
+}
+
+// Lint against the connected node's min relay fee and refuse to go on if anything is non-standard
+pub async fn check_standard(rpc: &AsyncRpc, tx: &Transaction, prevouts: &[TxOut]) -> Result<()> {
+    let min_relay_fee = rpc.run(|c| c.get_network_info()).await?.relay_fee;
+    let problems = lint_transaction(tx, prevouts, min_relay_fee);
+    if problems.is_empty() {
+        return Ok(());
+    }
+
+    for problem in &problems {
+        warn!("{}: {}", tx.compute_txid(), problem);
+    }
+    bail!(
+        "{} would not be relayed: {}",
+        tx.compute_txid(),
+        problems.join(", ")
+    )
+}
@@ -0,0 +1,79 @@
+use std::{fs, path::Path};
+
+use anyhow::{anyhow, bail, Result};
+use tracing::info;
+
+use crate::{lifecycle::Lifecycle, manifest::PoolManifest};
+
+// Binary pool state: STATE_MAGIC, the schema version (u16 little endian), then the zstd compressed
+// postcard encoding of the manifest. Postcard has no field names, so any change to PoolManifest
+// needs a new STATE_VERSION, its line in formats::STATE_CHANGELOG, the old layout kept around for
+// `migrate` and a fixture of it in tests/fixtures.
+pub const STATE_MAGIC: &[u8; 4] = b"CTVP";
+pub const STATE_VERSION: u16 = 1;
+// manifests written to a path with this extension use the binary format, anything else is json
+pub const STATE_EXTENSION: &str = "ctvpool";
+const ZSTD_LEVEL: i32 = 19;
+
+pub fn is_binary_path(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext == STATE_EXTENSION)
+}
+
+pub fn encode_state(manifest: &PoolManifest) -> Result<Vec<u8>> {
+    let payload = zstd::encode_all(postcard::to_stdvec(manifest)?.as_slice(), ZSTD_LEVEL)?;
+    let mut bytes = Vec::with_capacity(STATE_MAGIC.len() + 2 + payload.len());
+    bytes.extend_from_slice(STATE_MAGIC);
+    bytes.extend_from_slice(&STATE_VERSION.to_le_bytes());
+    bytes.extend_from_slice(&payload);
+    Ok(bytes)
+}
+
+// Read a state file of any version, json manifests from before the binary format included.
+// Returns the version it was written with, 0 for json.
+pub fn decode_state(bytes: &[u8]) -> Result<(PoolManifest, u16)> {
+    let Some(rest) = bytes.strip_prefix(STATE_MAGIC) else {
+        let mut manifest: PoolManifest = serde_json::from_slice(bytes)
+            .map_err(|e| anyhow!("not a pool state file or json manifest: {}", e))?;
+        // json from before the lifecycle was recorded only says whether the pool was funded
+        if manifest.funding_txid.is_some() && manifest.lifecycle == Lifecycle::Registered {
+            manifest.lifecycle = Lifecycle::from_record(true, 0);
+        }
+        return Ok((manifest, 0));
+    };
+    if rest.len() < 2 {
+        bail!("pool state file is truncated");
+    }
+    let version = u16::from_le_bytes([rest[0], rest[1]]);
+    if version > STATE_VERSION {
+        bail!(
+            "pool state version {} is newer than this build understands ({})",
+            version,
+            STATE_VERSION
+        );
+    }
+    let payload = zstd::decode_all(&rest[2..])?;
+    Ok((migrate(version, &payload)?, version))
+}
+
+// bring an older binary layout up to the current PoolManifest, once there is one
+fn migrate(version: u16, payload: &[u8]) -> Result<PoolManifest> {
+    match version {
+        1 => Ok(postcard::from_bytes(payload)?),
+        _ => bail!("unknown pool state version {}", version),
+    }
+}
+
+// Rewrite any state file in the current binary version
+pub fn upgrade_state(input: &Path, output: &Path) -> Result<()> {
+    let (manifest, version) = decode_state(&fs::read(input)?)?;
+    let bytes = encode_state(&manifest)?;
+    fs::write(output, &bytes)?;
+    info!(
+        "pool state upgraded from version {} to {}: {} ({} bytes) \n",
+        version,
+        STATE_VERSION,
+        output.display(),
+        bytes.len()
+    );
+    Ok(())
+}
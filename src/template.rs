@@ -0,0 +1,36 @@
+use bitcoin::{ScriptBuf, Sequence, TxOut};
+
+use crate::ctv_scripts::{calc_ctv_hash, ctv_script};
+
+// What a covenant opcode commits to and how the leaf checks it.
+// OP_CTV is the only one implemented, but OP_TXHASH (+ OP_CSFS) style opcodes on inquisition
+// just need a different hash over the spending tx and a different leaf script, the tree building stays the same.
+pub trait TemplateCommitment: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    // hash of the spending tx that the leaf script will enforce, the pool fixes the outputs and the input's nSequence
+    fn template_hash(&self, outputs: &[TxOut], sequence: Sequence) -> [u8; 32];
+
+    // tapleaf script that only succeeds if the spending tx matches `template_hash`
+    fn leaf_script(&self, template_hash: [u8; 32]) -> ScriptBuf;
+}
+
+// BIP-119 OP_CHECKTEMPLATEVERIFY, the hash commits to the tx version so it has to match the network's
+#[derive(Debug, Clone, Copy)]
+pub struct Bip119Ctv {
+    pub tx_version: i32,
+}
+
+impl TemplateCommitment for Bip119Ctv {
+    fn name(&self) -> &'static str {
+        "bip119-ctv"
+    }
+
+    fn template_hash(&self, outputs: &[TxOut], sequence: Sequence) -> [u8; 32] {
+        calc_ctv_hash(self.tx_version, outputs, &[sequence])
+    }
+
+    fn leaf_script(&self, template_hash: [u8; 32]) -> ScriptBuf {
+        ctv_script(template_hash)
+    }
+}
@@ -0,0 +1,302 @@
+use anyhow::{anyhow, bail, Result};
+use bitcoin::{Address, Amount, OutPoint, ScriptBuf, TxOut, Witness};
+use serde::Serialize;
+use tracing::warn;
+
+use crate::{
+    config::NetworkConfig,
+    covenant::CovenantBackend,
+    ids::{NodePath, UserIndex},
+    pools::{close_all_exit, node_exit, NodeExit},
+    standardness::MIN_STANDARD_TX_NONWITNESS_SIZE,
+    tree::PoolTree,
+};
+
+// Bitcoin Core's default -minrelaytxfee (sat/kvB) for years, what most of the network still relays at
+pub const LIKELY_MIN_RELAY_FEE_RATE: u64 = 1000;
+
+// The fee one template locks in, CTV commits to its outputs so it can never change
+#[derive(Debug, Clone, Serialize)]
+pub struct TemplateFee {
+    pub users: NodePath,
+    // who leaves with it (the first of the two in the exit pool), none for the close-all template
+    pub spender: Option<UserIndex>,
+    pub fee: Amount,
+    // the fee and the anchor value, what the template itself brings towards getting mined
+    pub committed_fee: Amount,
+    pub vsize: u64,
+    pub non_witness_size: usize,
+    // sat/kvB
+    pub fee_rate: u64,
+    pub committed_fee_rate: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TemplateFeeReport {
+    pub templates: usize,
+    // with an anchor a child can pay for the template, without one its own fee is all there is
+    pub anchored: bool,
+    pub relay_fee_rate: u64,
+    // POOL_MIN_TEMPLATE_FEE_RATE in sat/kvB, if set
+    pub floor: Option<u64>,
+    // lowest committed fee rate
+    pub lowest: TemplateFee,
+    // fewest bytes without witness
+    pub smallest: TemplateFee,
+    // templates whose own fee rate is under relay_fee_rate
+    pub below_relay: usize,
+    pub warnings: Vec<String>,
+}
+
+impl TemplateFee {
+    // the fee rate held against the floor: its own without anchors, with the anchor value otherwise
+    pub fn floor_fee_rate(&self, anchored: bool) -> u64 {
+        if anchored {
+            self.committed_fee_rate
+        } else {
+            self.fee_rate
+        }
+    }
+}
+
+fn fee_rate(fee: Amount, vsize: u64) -> u64 {
+    fee.to_sat() * 1000 / vsize
+}
+
+// The fee and size of one leaf's spend, with a stand-in witness of the real size
+fn template_fee(
+    exit: NodeExit,
+    spender: Option<UserIndex>,
+    script: &ScriptBuf,
+    pools: &PoolTree,
+    config: &NetworkConfig,
+    backend: &dyn CovenantBackend,
+) -> Result<TemplateFee> {
+    let node = pools.node(&exit.users)?;
+    let prevout = TxOut {
+        value: node.amount,
+        script_pubkey: node.address(config).script_pubkey(),
+    };
+    let control_block = exit
+        .spend_info
+        .control_block(&(script.clone(), config.leaf_version))
+        .ok_or_else(|| anyhow!("no control block for a leaf of the pool of {}", exit.users))?;
+    let users = exit.users.clone();
+    let witness_items = backend.witness_items(exit.template_hash);
+    let mut tx = exit.template_spend(OutPoint::null(), prevout, config).tx;
+
+    let mut witness = Witness::new();
+    for size in witness_items {
+        witness.push(vec![0; size]);
+    }
+    witness.push(script.as_bytes());
+    witness.push(control_block.serialize());
+    tx.input[0].witness = witness;
+
+    let paid: Amount = tx.output.iter().map(|output| output.value).sum();
+    let Some(fee) = node.amount.checked_sub(paid) else {
+        bail!(
+            "a template of the pool of users {} pays out {}, more than the {} it holds",
+            users,
+            paid,
+            node.amount
+        );
+    };
+    let committed_fee = fee + config.anchor_amount.unwrap_or(Amount::ZERO);
+    let vsize = tx.vsize() as u64;
+
+    Ok(TemplateFee {
+        users,
+        spender,
+        fee,
+        committed_fee,
+        vsize,
+        non_witness_size: tx.base_size(),
+        fee_rate: fee_rate(fee, vsize),
+        committed_fee_rate: fee_rate(committed_fee, vsize),
+    })
+}
+
+// every template of the tree, node by node in level order, the close-all template last
+pub fn template_fees(
+    pools: &PoolTree,
+    config: &NetworkConfig,
+    backend: &dyn CovenantBackend,
+    addresses: &[Address],
+    anchor_addr: &Address,
+) -> Result<Vec<TemplateFee>> {
+    let mut fees = Vec::new();
+    for (users, node) in pools.iter_nodes() {
+        // the exit pool's one template pays both users
+        let spenders = users.user_indices().take(node.templates.len());
+        for (spender, script) in spenders.zip(&node.leaf_scripts) {
+            let exit = node_exit(
+                pools,
+                config,
+                backend,
+                addresses,
+                anchor_addr,
+                users,
+                spender,
+            )?;
+            fees.push(template_fee(
+                exit,
+                Some(spender),
+                script,
+                pools,
+                config,
+                backend,
+            )?);
+        }
+    }
+    if let Some(close_all) = pools.root()?.close_all {
+        let exit = close_all_exit(pools, config, backend, addresses, anchor_addr)?;
+        let script = backend.leaf_script(close_all);
+        fees.push(template_fee(exit, None, &script, pools, config, backend)?);
+    }
+    Ok(fees)
+}
+
+fn describe(template: &TemplateFee) -> String {
+    match template.spender {
+        Some(spender) => format!(
+            "user {} leaving the pool of users {}",
+            spender, template.users
+        ),
+        None => "the close-all template".to_string(),
+    }
+}
+
+// How close the committed fees come to what nodes relay, and whether any template is too small to relay at all
+pub fn template_fee_report(
+    pools: &PoolTree,
+    config: &NetworkConfig,
+    backend: &dyn CovenantBackend,
+    addresses: &[Address],
+    anchor_addr: &Address,
+) -> Result<TemplateFeeReport> {
+    let fees = template_fees(pools, config, backend, addresses, anchor_addr)?;
+    let anchored = config.anchor_amount.is_some();
+    let Some(lowest) = fees
+        .iter()
+        .min_by_key(|template| template.floor_fee_rate(anchored))
+        .cloned()
+    else {
+        bail!("the pool has no templates");
+    };
+    let smallest = fees
+        .iter()
+        .min_by_key(|template| template.non_witness_size)
+        .cloned()
+        .unwrap_or_else(|| lowest.clone());
+    let below_relay = fees
+        .iter()
+        .filter(|template| template.fee_rate < LIKELY_MIN_RELAY_FEE_RATE)
+        .count();
+
+    let mut warnings = Vec::new();
+    if !anchored && below_relay > 0 {
+        warnings.push(format!(
+            "{} of {} templates pay under {} sat/vB, lowest {:.2} sat/vB ({}). Nodes with the default \
+             min relay fee won't relay them and without an anchor nothing can bump them, the pool \
+             gets stuck there for good",
+            below_relay,
+            fees.len(),
+            LIKELY_MIN_RELAY_FEE_RATE as f64 / 1000.0,
+            lowest.fee_rate as f64 / 1000.0,
+            describe(&lowest)
+        ));
+    }
+    if smallest.non_witness_size < MIN_STANDARD_TX_NONWITNESS_SIZE {
+        warnings.push(format!(
+            "{} is {} bytes without witness, under the {} byte minimum (tx-size-small), no node relays it",
+            describe(&smallest),
+            smallest.non_witness_size,
+            MIN_STANDARD_TX_NONWITNESS_SIZE
+        ));
+    }
+
+    Ok(TemplateFeeReport {
+        templates: fees.len(),
+        anchored,
+        relay_fee_rate: LIKELY_MIN_RELAY_FEE_RATE,
+        floor: config.min_template_fee_rate,
+        lowest,
+        smallest,
+        below_relay,
+        warnings,
+    })
+}
+
+// Warn about templates relay policy would strand and refuse a tree with one under the floor
+// (POOL_MIN_TEMPLATE_FEE_RATE). Once the pool is funded its fees can't be fixed.
+pub fn check_template_fees(
+    pools: &PoolTree,
+    config: &NetworkConfig,
+    backend: &dyn CovenantBackend,
+    addresses: &[Address],
+    anchor_addr: &Address,
+) -> Result<TemplateFeeReport> {
+    let report = template_fee_report(pools, config, backend, addresses, anchor_addr)?;
+    for warning in &report.warnings {
+        warn!("{}", warning);
+    }
+    if let Some(floor) = report.floor {
+        let rate = report.lowest.floor_fee_rate(report.anchored);
+        if rate < floor {
+            bail!(
+                "{} commits to {:.2} sat/vB{}, under the {:.2} sat/vB floor (POOL_MIN_TEMPLATE_FEE_RATE)",
+                describe(&report.lowest),
+                rate as f64 / 1000.0,
+                if report.anchored {
+                    " with its anchor"
+                } else {
+                    ""
+                },
+                floor as f64 / 1000.0
+            );
+        }
+    }
+    Ok(report)
+}
+
+pub fn print_template_fees(report: &TemplateFeeReport) {
+    println!("templates: {}", report.templates);
+    println!(
+        "lowest fee rate: {:.2} sat/vB ({} sat for {} vB), {}",
+        report.lowest.fee_rate as f64 / 1000.0,
+        report.lowest.fee.to_sat(),
+        report.lowest.vsize,
+        describe(&report.lowest)
+    );
+    if report.anchored {
+        println!(
+            "with the anchor: {:.2} sat/vB ({} sat committed)",
+            report.lowest.committed_fee_rate as f64 / 1000.0,
+            report.lowest.committed_fee.to_sat()
+        );
+    }
+    println!(
+        "smallest: {} bytes without witness, {}",
+        report.smallest.non_witness_size,
+        describe(&report.smallest)
+    );
+    println!(
+        "under {:.2} sat/vB on their own: {}",
+        report.relay_fee_rate as f64 / 1000.0,
+        report.below_relay
+    );
+    if report.anchored && report.below_relay > 0 {
+        println!("  they relay as a package with their anchor child (TRUC 1p1c package relay)");
+    }
+    match report.floor {
+        Some(floor) => println!(
+            "floor: {:.2} sat/vB (POOL_MIN_TEMPLATE_FEE_RATE)",
+            floor as f64 / 1000.0
+        ),
+        None => println!("floor: none (POOL_MIN_TEMPLATE_FEE_RATE)"),
+    }
+    for warning in &report.warnings {
+        println!("warning: {}", warning);
+    }
+}
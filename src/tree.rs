@@ -0,0 +1,529 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, OnceLock},
+};
+
+use anyhow::{anyhow, bail, Result};
+use bitcoin::{
+    hashes::{sha256, Hash},
+    key::{Parity, Secp256k1, TweakedPublicKey},
+    taproot::{LeafVersion, NodeInfo, TapNodeHash, TaprootMerkleBranch, TaprootSpendInfo},
+    Address, Amount, ScriptBuf, XOnlyPublicKey,
+};
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "elements")]
+use crate::elements::elements_node_address;
+use crate::{
+    config::NetworkConfig,
+    covenant::CovenantBackend,
+    ctv_scripts::create_keyed_pool_address,
+    ids::{NodePath, UserIndex},
+    pools::node_value,
+    recovery::recovery_leaf,
+    spill::{SpillFile, SpilledLevel},
+};
+
+// One node of the pool: the users in it, what it holds and a template leaf per way out of it
+#[derive(Debug, Clone)]
+pub struct PoolNode {
+    pub users: NodePath,
+    pub amount: Amount,
+    // a template per leaving user in user order, the exit pool has one paying both
+    pub templates: Vec<[u8; 32]>,
+    // the leaf locking the node to each template, same order
+    pub leaf_scripts: Vec<ScriptBuf>,
+    pub recovery_script: Option<ScriptBuf>,
+    // the root's template paying every user at once, if the pool has the close-all leaf
+    pub close_all: Option<[u8; 32]>,
+    // the node each leaving user leaves behind, none for the exit pool
+    pub children: Vec<NodePath>,
+    pub spend_info: TaprootSpendInfo,
+}
+
+impl PoolNode {
+    // The node of `users` locked to `templates`, with the recovery leaf if the config has one. Only
+    // the root can have a close-all template. `internal_key` is the NUMS point but for a pool root
+    // with the users' musig keys, see musig::internal_key
+    pub fn new(
+        users: NodePath,
+        templates: Vec<[u8; 32]>,
+        close_all: Option<[u8; 32]>,
+        internal_key: XOnlyPublicKey,
+        config: &NetworkConfig,
+        backend: &dyn CovenantBackend,
+        anchor_addr: &Address,
+    ) -> Result<Self> {
+        let expected = if users.is_exit() { 1 } else { users.len() };
+        if templates.len() != expected {
+            bail!(
+                "the pool of users {} needs {} templates, got {}",
+                users,
+                expected,
+                templates.len()
+            );
+        }
+        if close_all.is_some() && !users.is_root() {
+            bail!(
+                "only the root can close the pool for everyone, not {}",
+                users
+            );
+        }
+        for template in templates.iter().chain(&close_all) {
+            backend.check_leaf(*template, config.unwind_sequence(&users))?;
+        }
+        let amount = node_value(&users, config)?;
+        let recovery_script = recovery_leaf(config, amount, anchor_addr);
+        let spend_info = create_keyed_pool_address(
+            templates.iter().copied().chain(close_all).collect(),
+            backend,
+            recovery_script.clone(),
+            config.tree_layout,
+            config.leaf_version,
+            internal_key,
+        )?;
+        let children = if users.is_exit() {
+            Vec::new()
+        } else {
+            users
+                .user_indices()
+                .map(|user| users.without(user))
+                .collect::<Result<_>>()?
+        };
+        Ok(Self {
+            amount,
+            leaf_scripts: templates
+                .iter()
+                .map(|&hash| backend.leaf_script(hash))
+                .collect(),
+            templates,
+            recovery_script,
+            close_all,
+            children,
+            spend_info,
+            users,
+        })
+    }
+
+    // the template `spender` leaves with
+    pub fn template(&self, spender: UserIndex) -> Result<[u8; 32]> {
+        let position = self
+            .users
+            .users()
+            .iter()
+            .position(|&user| user == spender.index())
+            .ok_or_else(|| {
+                anyhow!(
+                    "user {} is not in the pool of users {}",
+                    spender,
+                    self.users
+                )
+            })?;
+        // both users of the exit pool share its one template
+        Ok(self.templates[position.min(self.templates.len() - 1)])
+    }
+
+    pub fn address(&self, config: &NetworkConfig) -> Address {
+        // the same tree under Elements' tags
+        #[cfg(feature = "elements")]
+        if config.profile.elements().is_some() {
+            return elements_node_address(&self.spend_info, config.network)
+                .expect("every branch of a tap tree rust-bitcoin built is in its script map");
+        }
+        Address::p2tr_tweaked(self.spend_info.output_key(), config.network)
+    }
+}
+
+// A PoolNode as plain data, for writing it out. TaprootSpendInfo has no serde, so this keeps what
+// it is made of: every leaf with its merkle branch, the internal key, the merkle root and the output
+// key. Reading it back rebuilds the tap tree from the branches and checks it against the root and key.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SerializablePoolNode {
+    pub users: NodePath,
+    pub amount: Amount,
+    pub templates: Vec<sha256::Hash>,
+    pub leaf_scripts: Vec<ScriptBuf>,
+    pub recovery_script: Option<ScriptBuf>,
+    pub close_all: Option<sha256::Hash>,
+    pub children: Vec<NodePath>,
+    pub internal_key: XOnlyPublicKey,
+    pub merkle_root: Option<TapNodeHash>,
+    pub output_key: TweakedPublicKey,
+    // 0 even, 1 odd. Parity's own serde writes a number its deserializer won't take back from json
+    pub output_key_parity: u8,
+    // every leaf of the tap tree, a leaf in it more than once comes once per branch
+    pub leaves: Vec<SerializableLeaf>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SerializableLeaf {
+    pub script: ScriptBuf,
+    pub leaf_version: LeafVersion,
+    // sibling hashes from the leaf up, what its control block carries
+    pub merkle_branch: TaprootMerkleBranch,
+}
+
+impl From<&PoolNode> for SerializablePoolNode {
+    fn from(node: &PoolNode) -> Self {
+        let spend_info = &node.spend_info;
+        Self {
+            users: node.users.clone(),
+            amount: node.amount,
+            templates: node
+                .templates
+                .iter()
+                .map(|hash| sha256::Hash::from_byte_array(*hash))
+                .collect(),
+            leaf_scripts: node.leaf_scripts.clone(),
+            recovery_script: node.recovery_script.clone(),
+            close_all: node.close_all.map(sha256::Hash::from_byte_array),
+            children: node.children.clone(),
+            internal_key: spend_info.internal_key(),
+            merkle_root: spend_info.merkle_root(),
+            output_key: spend_info.output_key(),
+            output_key_parity: spend_info.output_key_parity().to_u8(),
+            leaves: spend_info
+                .script_map()
+                .iter()
+                .flat_map(|((script, leaf_version), branches)| {
+                    branches.iter().map(|branch| SerializableLeaf {
+                        script: script.clone(),
+                        leaf_version: *leaf_version,
+                        merkle_branch: branch.clone(),
+                    })
+                })
+                .collect(),
+        }
+    }
+}
+
+impl SerializablePoolNode {
+    // the spendable node back, failing if the leaves don't make up the merkle root and output key
+    pub fn into_node(self) -> Result<PoolNode> {
+        let spend_info = self.spend_info()?;
+        if spend_info.output_key() != self.output_key
+            || spend_info.output_key_parity() != Parity::from_u8(self.output_key_parity)?
+        {
+            bail!(
+                "the tap tree of node {} tweaks to {}, not its output key {}",
+                self.users,
+                spend_info.output_key(),
+                self.output_key
+            );
+        }
+        Ok(PoolNode {
+            users: self.users,
+            amount: self.amount,
+            templates: self
+                .templates
+                .iter()
+                .map(|hash| hash.to_byte_array())
+                .collect(),
+            leaf_scripts: self.leaf_scripts,
+            recovery_script: self.recovery_script,
+            close_all: self.close_all.map(|hash| hash.to_byte_array()),
+            children: self.children,
+            spend_info,
+        })
+    }
+
+    // Each leaf climbs its branch, combining with a sibling as soon as that subtree is built. A tree
+    // with the same shape comes out, NodeInfo orders every pair by hash like the original did.
+    fn spend_info(&self) -> Result<TaprootSpendInfo> {
+        if self.leaves.is_empty() {
+            bail!("node {} has no leaves", self.users);
+        }
+        let mut subtrees: HashMap<TapNodeHash, NodeInfo> = HashMap::new();
+        // where each leaf has got to: the subtree it is in and how many siblings it has climbed past
+        let mut climbs: Vec<(TapNodeHash, usize)> = Vec::with_capacity(self.leaves.len());
+        for leaf in &self.leaves {
+            let node = NodeInfo::new_leaf_with_ver(leaf.script.clone(), leaf.leaf_version);
+            climbs.push((node.node_hash(), 0));
+            subtrees.insert(node.node_hash(), node);
+        }
+        loop {
+            let mut climbed = false;
+            for ((at, height), leaf) in climbs.iter_mut().zip(&self.leaves) {
+                while let Some(sibling) = leaf.merkle_branch.as_slice().get(*height) {
+                    let Some(sibling) = subtrees.get(sibling).cloned() else {
+                        break;
+                    };
+                    let parent = NodeInfo::combine(subtrees[at].clone(), sibling)?;
+                    *at = parent.node_hash();
+                    *height += 1;
+                    subtrees.entry(*at).or_insert(parent);
+                    climbed = true;
+                }
+            }
+            if !climbed {
+                break;
+            }
+        }
+
+        let (root, _) = climbs[0];
+        let complete = climbs
+            .iter()
+            .zip(&self.leaves)
+            .all(|((at, height), leaf)| *at == root && *height == leaf.merkle_branch.len());
+        if !complete {
+            bail!(
+                "the leaves of node {} don't make up one tap tree, a branch has a hash none of them hash to",
+                self.users
+            );
+        }
+        if Some(root) != self.merkle_root {
+            bail!(
+                "the leaves of node {} hash to merkle root {}, not {:?}",
+                self.users,
+                root,
+                self.merkle_root
+            );
+        }
+        Ok(TaprootSpendInfo::from_node_info(
+            &Secp256k1::verification_only(),
+            self.internal_key,
+            subtrees.remove(&root).expect("the root was built"),
+        ))
+    }
+}
+
+// rough heap size of a node besides its leaves, and of a leaf besides its merkle branch
+const NODE_BYTES: u64 = 512;
+const LEAF_BYTES: u64 = 256;
+
+// Rough size in memory of the tree of `users` users, what the config's memory budget is checked
+// against: C(users, k) nodes of k users for every k from 2 up, each with a leaf per user and a
+// merkle branch per leaf. Saturates for pools far too big to build.
+pub fn estimated_tree_bytes(users: usize) -> u64 {
+    let users = users as u128;
+    let mut nodes: u128 = users;
+    let mut total: u128 = 0;
+    for size in 2..=users {
+        // C(users, size) from C(users, size - 1)
+        let Some(next) = nodes.checked_mul(users - size + 1) else {
+            return u64::MAX;
+        };
+        nodes = next / size;
+        let depth = u128::from(u128::BITS - size.leading_zeros());
+        let leaf = u128::from(LEAF_BYTES) + 32 * depth + 8 * size;
+        let node = u128::from(NODE_BYTES) + 16 * size + size * leaf;
+        total = match nodes
+            .checked_mul(node)
+            .and_then(|bytes| total.checked_add(bytes))
+        {
+            Some(total) => total,
+            None => return u64::MAX,
+        };
+    }
+    u64::try_from(total).unwrap_or(u64::MAX)
+}
+
+// every node with the same number of users, keyed by the users in it
+pub type PoolLevel = HashMap<NodePath, PoolNode>;
+
+// A level held in memory, or written to the tree's spill file and read back the first time it is used
+#[derive(Debug, Clone)]
+struct TreeLevel {
+    nodes: OnceLock<PoolLevel>,
+    spilled: Option<SpilledLevel>,
+}
+
+impl TreeLevel {
+    fn len(&self) -> usize {
+        match (self.nodes.get(), &self.spilled) {
+            (Some(nodes), _) => nodes.len(),
+            (None, Some(spilled)) => spilled.len(),
+            (None, None) => 0,
+        }
+    }
+
+    fn nodes(&self) -> Result<&PoolLevel> {
+        if let Some(nodes) = self.nodes.get() {
+            return Ok(nodes);
+        }
+        let spilled = self
+            .spilled
+            .as_ref()
+            .ok_or_else(|| anyhow!("a tree level is neither in memory nor spilled"))?;
+        // two threads loading it at once both read the file, the first one's copy is kept
+        let _ = self.nodes.set(spilled.load()?);
+        Ok(self.nodes.get().expect("the level was just loaded"))
+    }
+
+    // For the iterators, which can't fail: the file was written by this process and a level that
+    // doesn't read back means the temp dir was tampered with, there is nothing to go on with
+    fn loaded(&self) -> &PoolLevel {
+        self.nodes()
+            .unwrap_or_else(|e| panic!("a spilled tree level can't be read back: {}", e))
+    }
+}
+
+// The whole pool, a level per node size from the exit pools (2 users, level 0) up to the entry
+// pool, which is the last level and only holds NodePath::root(). A tree over the config's memory
+// budget spills every level but the last one pushed to a temp file, see spill.rs.
+#[derive(Debug, Clone, Default)]
+pub struct PoolTree {
+    levels: Vec<TreeLevel>,
+    spill: Option<Arc<SpillFile>>,
+}
+
+impl PoolTree {
+    // an empty tree writing its levels to `spill` as they are built on
+    pub fn spilling(spill: Arc<SpillFile>) -> Self {
+        Self {
+            levels: Vec::new(),
+            spill: Some(spill),
+        }
+    }
+
+    // levels are added from the exit pools up, each one a user bigger than the last
+    pub fn push_level(&mut self, level: PoolLevel) -> Result<()> {
+        let size = self.levels.len() + 2;
+        if let Some(node) = level.keys().find(|node| node.len() != size) {
+            bail!(
+                "level {} of the tree holds pools of {} users, got {}",
+                self.levels.len(),
+                size,
+                node
+            );
+        }
+        // only the top level is needed to build the next one
+        if let (Some(spill), Some(below)) = (&self.spill, self.levels.last_mut()) {
+            if let Some(nodes) = below.nodes.take() {
+                below.spilled = Some(spill.write_level(&nodes)?);
+            }
+        }
+        self.levels.push(TreeLevel {
+            nodes: OnceLock::from(level),
+            spilled: None,
+        });
+        Ok(())
+    }
+
+    // every node of a level, 0 for the exit pools, read back first if it was spilled
+    pub fn level(&self, level: usize) -> Result<&PoolLevel> {
+        self.levels
+            .get(level)
+            .ok_or_else(|| anyhow!("the tree has no level {}", level))?
+            .nodes()
+    }
+
+    pub fn level_count(&self) -> usize {
+        self.levels.len()
+    }
+
+    // levels written to the spill file, whether or not they have been read back since
+    pub fn spilled_levels(&self) -> usize {
+        self.levels
+            .iter()
+            .filter(|level| level.spilled.is_some())
+            .count()
+    }
+
+    // the last level pushed, the one the next level's templates pay into
+    pub fn top(&self) -> Result<&PoolLevel> {
+        self.levels
+            .last()
+            .ok_or_else(|| anyhow!("the tree has no levels yet"))?
+            .nodes()
+    }
+
+    pub fn len(&self) -> usize {
+        self.levels.iter().map(TreeLevel::len).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn node(&self, users: &NodePath) -> Result<&PoolNode> {
+        self.levels
+            .get(users.level())
+            .map(TreeLevel::nodes)
+            .transpose()?
+            .and_then(|level| level.get(users))
+            .ok_or_else(|| anyhow!("no pool for users {}", users))
+    }
+
+    pub fn root(&self) -> Result<&PoolNode> {
+        self.node(&NodePath::root())
+    }
+
+    // the taproot tree of `users`
+    pub fn spend_info(&self, users: &NodePath) -> Result<&TaprootSpendInfo> {
+        Ok(&self.node(users)?.spend_info)
+    }
+
+    // node scriptPubKey -> users in that node, to tell which outputs are pool nodes
+    pub fn node_scripts(&self, config: &NetworkConfig) -> HashMap<ScriptBuf, NodePath> {
+        self.iter_nodes()
+            .map(|(users, node)| (node.address(config).script_pubkey(), users.clone()))
+            .collect()
+    }
+
+    // every node in level order
+    pub fn node_paths(&self) -> Vec<NodePath> {
+        self.iter_nodes().map(|(users, _)| users.clone()).collect()
+    }
+
+    // Every node in level order: the entry pool first, then each level down to the exit pools, sorted
+    // by users within a level.
+    pub fn iter_nodes(&self) -> impl Iterator<Item = (&NodePath, &PoolNode)> {
+        self.levels
+            .iter()
+            .rev()
+            .flat_map(|level| level.loaded().iter().sorted_by(|(a, _), (b, _)| a.cmp(b)))
+    }
+
+    // Every node depth first from the root, a node before the nodes its users leave to, lowest user
+    // first. Nodes are reached from several parents (the same users can leave in any order), each one
+    // comes once, under the first parent that reaches it.
+    pub fn iter_nodes_depth_first(&self) -> DepthFirstNodes<'_> {
+        let mut nodes = DepthFirstNodes {
+            tree: self,
+            stack: Vec::new(),
+            seen: HashSet::new(),
+        };
+        nodes.push(&NodePath::root());
+        nodes
+    }
+}
+
+pub struct DepthFirstNodes<'a> {
+    tree: &'a PoolTree,
+    stack: Vec<(&'a NodePath, &'a PoolNode)>,
+    seen: HashSet<&'a NodePath>,
+}
+
+impl DepthFirstNodes<'_> {
+    // the node's entry in the tree, missing nodes are skipped
+    fn push(&mut self, users: &NodePath) {
+        if let Some(node) = self
+            .tree
+            .levels
+            .get(users.level())
+            .and_then(|level| level.loaded().get_key_value(users))
+        {
+            self.stack.push(node);
+        }
+    }
+}
+
+impl<'a> Iterator for DepthFirstNodes<'a> {
+    type Item = (&'a NodePath, &'a PoolNode);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some((users, node)) = self.stack.pop() {
+            if !self.seen.insert(users) {
+                continue;
+            }
+            // reversed so the lowest user's node comes off the stack first
+            for child in node.children.iter().rev() {
+                self.push(child);
+            }
+            return Some((users, node));
+        }
+        None
+    }
+}
@@ -0,0 +1,214 @@
+use std::{collections::BTreeMap, str::FromStr};
+
+use anyhow::{anyhow, bail, Result};
+use bitcoin::{
+    absolute,
+    hashes::Hash,
+    key::{Keypair, Secp256k1, TapTweak},
+    opcodes::all::OP_RETURN,
+    script::Builder,
+    secp256k1::Message,
+    sighash::{Prevouts, SighashCache, TapSighashType},
+    taproot, Address, Amount, Network, OutPoint, ScriptBuf, Sequence, Transaction, TxIn, TxOut,
+    Witness, XOnlyPublicKey,
+};
+
+use crate::{config::NetworkConfig, rpc_helper::fee_for_vsize};
+
+// withdraw address -> key of the anchor on the txs taking that user out, from their registration.
+// Users without one share the pool's anchor, which only the coordinator bumps.
+pub type AnchorKeys = BTreeMap<String, XOnlyPublicKey>;
+
+// a keyed anchor is a plain p2tr output, relay wants it above p2tr dust (P2A is 240)
+pub const KEYED_ANCHOR_DUST: Amount = Amount::from_sat(330);
+
+// key path only, the user spends it with nothing but their key
+pub fn anchor_key_address(key: XOnlyPublicKey, network: Network) -> Address {
+    Address::p2tr(&Secp256k1::verification_only(), key, None, network)
+}
+
+// What the anchor of the tx taking `withdraw_addr` out pays: their own key when they registered
+// one, otherwise the pool's anchor
+pub fn exit_anchor_script(
+    withdraw_addr: &Address,
+    anchor_addr: &Address,
+    config: &NetworkConfig,
+) -> Result<ScriptBuf> {
+    let Some(key) = config.anchor_keys.get(&withdraw_addr.to_string()) else {
+        return Ok(anchor_addr.script_pubkey());
+    };
+    match config.anchor_amount {
+        Some(amount) if amount >= KEYED_ANCHOR_DUST => {
+            Ok(anchor_key_address(*key, config.network).script_pubkey())
+        }
+        Some(amount) => bail!(
+            "{} registered an anchor key but the {} anchor is below the {} a keyed anchor needs",
+            withdraw_addr,
+            amount,
+            KEYED_ANCHOR_DUST
+        ),
+        None => bail!(
+            "{} registered an anchor key but {} has no anchors",
+            withdraw_addr,
+            config.network
+        ),
+    }
+}
+
+// whether the coordinator can bump `tx`: it pays the pool's anchor rather than a user's
+pub fn has_pool_anchor(tx: &Transaction, config: &NetworkConfig) -> bool {
+    let Ok(anchor) = Address::from_str(&config.fee_anchor_addr) else {
+        return false;
+    };
+    let script = anchor.assume_checked().script_pubkey();
+    tx.output
+        .iter()
+        .any(|output| output.script_pubkey == script)
+}
+
+// every key belongs to a user of the pool
+pub fn check_anchor_keys(keys: &AnchorKeys, withdraw_addresses: &[String]) -> Result<()> {
+    for address in keys.keys() {
+        if !withdraw_addresses.contains(address) {
+            bail!(
+                "anchor key registered for {}, which is not in the pool",
+                address
+            );
+        }
+    }
+    Ok(())
+}
+
+// the output of `parent` paying `key`'s anchor
+pub fn keyed_anchor(
+    parent: &Transaction,
+    key: XOnlyPublicKey,
+    network: Network,
+) -> Option<(u32, TxOut)> {
+    let script = anchor_key_address(key, network).script_pubkey();
+    parent
+        .output
+        .iter()
+        .enumerate()
+        .find(|(_, output)| output.script_pubkey == script)
+        .map(|(vout, output)| (vout as u32, output.clone()))
+}
+
+// The user's own CPFP of their exit: spends the anchor keyed to `keypair` (and any `coins` sent to
+// the same key's address beforehand) so `parent` and the child together pay `sat_per_vb`.
+// `parent_fee` is what the parent pays already. The change goes back to the anchor key's address,
+// change below dust is left to the miner.
+pub fn anchor_child(
+    parent: &Transaction,
+    parent_fee: Amount,
+    keypair: &Keypair,
+    coins: &[(OutPoint, TxOut)],
+    sat_per_vb: u64,
+    network: Network,
+) -> Result<Transaction> {
+    let secp = Secp256k1::new();
+    let key = keypair.x_only_public_key().0;
+    let address = anchor_key_address(key, network);
+    let (vout, anchor) = keyed_anchor(parent, key, network)
+        .ok_or_else(|| anyhow!("{} has no anchor for this key", parent.compute_txid()))?;
+    let mut prevouts = vec![anchor];
+    let mut input = vec![TxIn {
+        previous_output: OutPoint::new(parent.compute_txid(), vout),
+        sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+        ..Default::default()
+    }];
+    for (outpoint, txout) in coins {
+        if txout.script_pubkey != address.script_pubkey() {
+            bail!(
+                "coin {} isn't at the anchor key's address {}",
+                outpoint,
+                address
+            );
+        }
+        prevouts.push(txout.clone());
+        input.push(TxIn {
+            previous_output: *outpoint,
+            sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+            ..Default::default()
+        });
+    }
+    let total: Amount = prevouts.iter().map(|prevout| prevout.value).sum();
+
+    // sized with the change output and a signature on every input
+    let mut child = Transaction {
+        // a v3 parent only takes a v3 child
+        version: parent.version,
+        lock_time: absolute::LockTime::ZERO,
+        input,
+        output: vec![TxOut {
+            value: total,
+            script_pubkey: address.script_pubkey(),
+        }],
+    };
+    for input in &mut child.input {
+        input.witness.push([0; 64]);
+    }
+    let package = fee_for_vsize(
+        sat_per_vb * 1000,
+        parent.vsize() as u64 + child.vsize() as u64,
+    )?;
+    let fee = package
+        .checked_sub(parent_fee)
+        .unwrap_or_default()
+        .max(fee_for_vsize(sat_per_vb * 1000, child.vsize() as u64)?);
+    let change = total.checked_sub(fee).ok_or_else(|| {
+        anyhow!(
+            "the anchor and coins hold {}, the child has to pay {}. Send a coin to {} first",
+            total,
+            fee,
+            address
+        )
+    })?;
+    child.output = if change >= KEYED_ANCHOR_DUST {
+        vec![TxOut {
+            value: change,
+            script_pubkey: address.script_pubkey(),
+        }]
+    } else {
+        vec![TxOut {
+            value: Amount::ZERO,
+            // the payload keeps the child over the 65 byte minimum tx size
+            script_pubkey: Builder::new()
+                .push_opcode(OP_RETURN)
+                .push_slice(b"bump")
+                .into_script(),
+        }]
+    };
+
+    let tweaked = keypair.tap_tweak(&secp, None).to_keypair();
+    let mut signatures = Vec::new();
+    let mut cache = SighashCache::new(&child);
+    for index in 0..prevouts.len() {
+        let sighash = cache.taproot_key_spend_signature_hash(
+            index,
+            &Prevouts::All(&prevouts),
+            TapSighashType::Default,
+        )?;
+        let msg = Message::from_digest(sighash.to_byte_array());
+        signatures.push(taproot::Signature {
+            signature: secp.sign_schnorr(&msg, &tweaked),
+            sighash_type: TapSighashType::Default,
+        });
+    }
+    for (input, signature) in child.input.iter_mut().zip(signatures) {
+        input.witness = Witness::p2tr_key_spend(&signature);
+    }
+    Ok(child)
+}
+
+// what `tx` pays its miner, `spent` being the outputs it spends in input order
+pub fn tx_fee(tx: &Transaction, spent: &[TxOut]) -> Result<Amount> {
+    let inputs: Amount = spent.iter().map(|output| output.value).sum();
+    let outputs: Amount = tx.output.iter().map(|output| output.value).sum();
+    inputs.checked_sub(outputs).ok_or_else(|| {
+        anyhow!(
+            "{} pays out more than the outputs it spends",
+            tx.compute_txid()
+        )
+    })
+}
@@ -0,0 +1,390 @@
+use std::{fs, path::Path, str::FromStr};
+
+use anyhow::{anyhow, bail, Result};
+use bitcoin::{
+    absolute,
+    address::NetworkUnchecked,
+    hashes::Hash,
+    hex::DisplayHex,
+    key::{Keypair, Secp256k1},
+    secp256k1::{Message, SecretKey},
+    sighash::{Prevouts, SighashCache},
+    taproot::{self, LeafVersion, TapLeafHash, TaprootSpendInfo},
+    transaction, Address, Amount, Network, OutPoint, PrivateKey, ScriptBuf, Sequence,
+    TapSighashType, Transaction, TxIn, TxOut, XOnlyPublicKey,
+};
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use crate::{
+    config::{NetworkConfig, DUST_AMOUNT, FEE_AMOUNT},
+    covenant::CtvBackend,
+    ctv_scripts::{calc_ctv_hash, create_pool_address, ctv_script, spend_leaf, TreeLayout},
+    policy::LeafPolicy,
+    template::Bip119Ctv,
+};
+
+pub const DEFAULT_VAULT_FILE: &str = "vault.json";
+
+// blocks an unvault waits before the hot key can take it, about a day like the recovery timeout
+pub const DEFAULT_UNVAULT_DELAY: u16 = 144;
+
+// A single owner CTV vault, the canonical shape from the CTV vault write-ups: the vault output can
+// only be spent by the unvault tx, and the unvault output either goes to the cold address at once
+// (to-cold) or to the hot key once it has waited `delay` blocks. Like a pool's manifest, these fields
+// are all it takes to rebuild every script and tx.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VaultSpec {
+    pub network: Network,
+    #[serde(with = "bitcoin::amount::serde::as_sat")]
+    pub amount: Amount,
+    pub hot_key: XOnlyPublicKey,
+    pub cold_address: Address<NetworkUnchecked>,
+    pub delay: u16,
+    pub tx_version: i32,
+    // the fee anchor the unvault and to-cold txs carry, if the network uses one
+    pub anchor: Option<TxOut>,
+}
+
+impl VaultSpec {
+    // a vault with the network's tx version and fee anchor
+    pub fn new(
+        config: &NetworkConfig,
+        amount: Amount,
+        hot_key: XOnlyPublicKey,
+        cold_address: &Address,
+        delay: u16,
+    ) -> Result<Self> {
+        let anchor = match config.anchor_amount {
+            Some(value) => Some(TxOut {
+                value,
+                script_pubkey: Address::from_str(&config.fee_anchor_addr)?
+                    .require_network(config.network)?
+                    .script_pubkey(),
+            }),
+            None => None,
+        };
+        Ok(Self {
+            network: config.network,
+            amount,
+            hot_key,
+            cold_address: cold_address.as_unchecked().clone(),
+            delay,
+            tx_version: config.tx_version,
+            anchor,
+        })
+    }
+
+    pub fn unvault_amount(&self) -> Amount {
+        self.amount - FEE_AMOUNT
+    }
+
+    pub fn tocold_amount(&self) -> Amount {
+        self.unvault_amount() - FEE_AMOUNT
+    }
+
+    // <delay> OP_CSV OP_DROP <hot key> OP_CHECKSIG
+    pub fn hot_leaf(&self) -> ScriptBuf {
+        LeafPolicy::older(self.delay).and_key(self.hot_key).script()
+    }
+
+    // `value` to `script_pubkey` and the fee anchor, like a recovery sweep
+    fn outputs(&self, value: Amount, script_pubkey: ScriptBuf) -> Vec<TxOut> {
+        let mut outputs = vec![TxOut {
+            value,
+            script_pubkey,
+        }];
+        outputs.extend(self.anchor.clone());
+        outputs
+    }
+
+    // both covenant txs spend one input and can be replaced, the sequence is part of the template
+    fn template_hash(&self, outputs: &[TxOut]) -> [u8; 32] {
+        calc_ctv_hash(
+            self.tx_version,
+            outputs,
+            &[Sequence::ENABLE_RBF_NO_LOCKTIME],
+        )
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let json = fs::read_to_string(path)
+            .map_err(|e| anyhow!("can't read vault {}: {}", path.display(), e))?;
+        Ok(serde_json::from_str(&json)?)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        fs::write(path, serde_json::to_string_pretty(self)?)?;
+        info!("vault written to {} \n", path.display());
+        Ok(())
+    }
+}
+
+// A vault with its templates and tap trees worked out
+#[derive(Debug, Clone)]
+pub struct Vault {
+    pub spec: VaultSpec,
+    pub cold_address: Address,
+    // template of the unvault tx, the vault output's only leaf
+    pub unvault_hash: [u8; 32],
+    // template of the to-cold tx, one leaf of the unvault output next to the hot leaf
+    pub tocold_hash: [u8; 32],
+    pub vault_spend_info: TaprootSpendInfo,
+    pub unvault_spend_info: TaprootSpendInfo,
+}
+
+// What `vault create` prints
+#[derive(Debug, Clone, Serialize)]
+pub struct VaultInfo {
+    pub address: String,
+    pub amount: Amount,
+    pub unvault_address: String,
+    pub unvault_amount: Amount,
+    pub unvault_hash: String,
+    pub cold_address: String,
+    pub tocold_amount: Amount,
+    pub tocold_hash: String,
+    pub hot_key: XOnlyPublicKey,
+    pub delay: u16,
+}
+
+impl Vault {
+    pub fn new(spec: VaultSpec) -> Result<Self> {
+        if spec.delay == 0 {
+            bail!("an unvault delay of 0 blocks lets the hot key take the coins before anyone can send them to cold");
+        }
+        if spec
+            .amount
+            .checked_sub(FEE_AMOUNT * 2)
+            .is_none_or(|rest| rest < DUST_AMOUNT)
+        {
+            bail!(
+                "a vault of {} can't pay the fee of the unvault and the to-cold tx ({} each) and keep more than dust",
+                spec.amount,
+                FEE_AMOUNT
+            );
+        }
+        let cold_address = spec
+            .cold_address
+            .clone()
+            .require_network(spec.network)
+            .map_err(|_| anyhow!("the cold address is not a {} address", spec.network))?;
+        // only ever OP_CTV leaves, whatever backend the pools use
+        let backend = CtvBackend::from(Bip119Ctv {
+            tx_version: spec.tx_version,
+        });
+        let tocold_hash =
+            spec.template_hash(&spec.outputs(spec.tocold_amount(), cold_address.script_pubkey()));
+        let unvault_spend_info = create_pool_address(
+            vec![tocold_hash],
+            &backend,
+            Some(spec.hot_leaf()),
+            TreeLayout::Balanced,
+            LeafVersion::TapScript,
+        )?;
+        let unvault_address = Address::p2tr_tweaked(unvault_spend_info.output_key(), spec.network);
+        let unvault_hash = spec
+            .template_hash(&spec.outputs(spec.unvault_amount(), unvault_address.script_pubkey()));
+        let vault_spend_info = create_pool_address(
+            vec![unvault_hash],
+            &backend,
+            None,
+            TreeLayout::Balanced,
+            LeafVersion::TapScript,
+        )?;
+        Ok(Self {
+            spec,
+            cold_address,
+            unvault_hash,
+            tocold_hash,
+            vault_spend_info,
+            unvault_spend_info,
+        })
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        Self::new(VaultSpec::load(path)?)
+    }
+
+    // where the coins to vault are sent
+    pub fn address(&self) -> Address {
+        Address::p2tr_tweaked(self.vault_spend_info.output_key(), self.spec.network)
+    }
+
+    pub fn unvault_address(&self) -> Address {
+        Address::p2tr_tweaked(self.unvault_spend_info.output_key(), self.spec.network)
+    }
+
+    pub fn unvault_amount(&self) -> Amount {
+        self.spec.unvault_amount()
+    }
+
+    pub fn tocold_amount(&self) -> Amount {
+        self.spec.tocold_amount()
+    }
+
+    // the output the unvault tx spends
+    pub fn vault_output(&self) -> TxOut {
+        TxOut {
+            value: self.spec.amount,
+            script_pubkey: self.address().script_pubkey(),
+        }
+    }
+
+    // the output the to-cold and hot withdrawal txs spend
+    pub fn unvault_output(&self) -> TxOut {
+        TxOut {
+            value: self.unvault_amount(),
+            script_pubkey: self.unvault_address().script_pubkey(),
+        }
+    }
+
+    pub fn info(&self) -> VaultInfo {
+        VaultInfo {
+            address: self.address().to_string(),
+            amount: self.spec.amount,
+            unvault_address: self.unvault_address().to_string(),
+            unvault_amount: self.unvault_amount(),
+            unvault_hash: self.unvault_hash.to_lower_hex_string(),
+            cold_address: self.cold_address.to_string(),
+            tocold_amount: self.tocold_amount(),
+            tocold_hash: self.tocold_hash.to_lower_hex_string(),
+            hot_key: self.spec.hot_key,
+            delay: self.spec.delay,
+        }
+    }
+
+    fn unvault_outputs(&self) -> Vec<TxOut> {
+        self.spec.outputs(
+            self.unvault_amount(),
+            self.unvault_address().script_pubkey(),
+        )
+    }
+
+    fn tocold_outputs(&self) -> Vec<TxOut> {
+        self.spec
+            .outputs(self.tocold_amount(), self.cold_address.script_pubkey())
+    }
+
+    fn template_tx(&self, outpoint: OutPoint, output: Vec<TxOut>) -> Transaction {
+        Transaction {
+            version: transaction::Version(self.spec.tx_version),
+            lock_time: absolute::LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: outpoint,
+                sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+                ..Default::default()
+            }],
+            output,
+        }
+    }
+
+    // the witnessed unvault of the vault output at `vault`
+    pub fn unvault_tx(&self, vault: OutPoint) -> Transaction {
+        spend_leaf(
+            self.template_tx(vault, self.unvault_outputs()),
+            self.vault_spend_info.clone(),
+            ctv_script(self.unvault_hash),
+            LeafVersion::TapScript,
+        )
+    }
+
+    // the witnessed to-cold tx of the unvault output at `unvault`, valid as soon as the unvault is mined
+    pub fn tocold_tx(&self, unvault: OutPoint) -> Transaction {
+        spend_leaf(
+            self.template_tx(unvault, self.tocold_outputs()),
+            self.unvault_spend_info.clone(),
+            ctv_script(self.tocold_hash),
+            LeafVersion::TapScript,
+        )
+    }
+
+    // Everything in the unvault output at `unvault` to `to` minus `fee`, signed with the hot key.
+    // Only valid once the unvault has `delay` confirmations.
+    pub fn withdraw_tx(
+        &self,
+        unvault: OutPoint,
+        to: &Address,
+        fee: Amount,
+        hot_key: &Keypair,
+    ) -> Result<Transaction> {
+        if hot_key.x_only_public_key().0 != self.spec.hot_key {
+            bail!("that is not the vault's hot key {}", self.spec.hot_key);
+        }
+        let value = self
+            .unvault_amount()
+            .checked_sub(fee)
+            .filter(|value| *value >= DUST_AMOUNT)
+            .ok_or_else(|| {
+                anyhow!(
+                    "a fee of {} leaves no more than dust of the {} unvaulted",
+                    fee,
+                    self.unvault_amount()
+                )
+            })?;
+        let mut tx = Transaction {
+            version: transaction::Version(self.spec.tx_version),
+            lock_time: absolute::LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: unvault,
+                sequence: Sequence::from_height(self.spec.delay),
+                ..Default::default()
+            }],
+            output: vec![TxOut {
+                value,
+                script_pubkey: to.script_pubkey(),
+            }],
+        };
+
+        let leaf_script = self.spec.hot_leaf();
+        let control_block = self
+            .unvault_spend_info
+            .control_block(&(leaf_script.clone(), LeafVersion::TapScript))
+            .ok_or_else(|| anyhow!("the unvault output has no hot leaf"))?;
+        let sighash = SighashCache::new(&tx).taproot_script_spend_signature_hash(
+            0,
+            &Prevouts::All(&[&self.unvault_output()]),
+            TapLeafHash::from_script(&leaf_script, LeafVersion::TapScript),
+            TapSighashType::Default,
+        )?;
+        let signature = taproot::Signature {
+            signature: Secp256k1::new()
+                .sign_schnorr(&Message::from_digest(sighash.to_byte_array()), hot_key),
+            sighash_type: TapSighashType::Default,
+        };
+        tx.input[0].witness.push(signature.to_vec());
+        tx.input[0].witness.push(leaf_script.into_bytes());
+        tx.input[0].witness.push(control_block.serialize());
+        Ok(tx)
+    }
+}
+
+// VAULT_HOT_KEY, as WIF or hex, signs hot withdrawals
+pub fn hot_key_from_env() -> Result<Keypair> {
+    let key = NetworkConfig::get_env_var("VAULT_HOT_KEY", "");
+    if key.is_empty() {
+        bail!("VAULT_HOT_KEY is not set, hot withdrawals are signed with the hot key");
+    }
+    let secret = match PrivateKey::from_wif(key.trim()) {
+        Ok(private_key) => private_key.inner,
+        Err(_) => SecretKey::from_str(key.trim())
+            .map_err(|_| anyhow!("VAULT_HOT_KEY is neither a WIF nor a hex secret key"))?,
+    };
+    Ok(Keypair::from_secret_key(&Secp256k1::new(), &secret))
+}
+
+pub fn print_vault(info: &VaultInfo) {
+    println!("send {} to {}", info.amount, info.address);
+    println!(
+        "unvault: {} to {} (template {})",
+        info.unvault_amount, info.unvault_address, info.unvault_hash
+    );
+    println!(
+        "to-cold: {} to {} (template {}), any time after the unvault",
+        info.tocold_amount, info.cold_address, info.tocold_hash
+    );
+    println!(
+        "hot: key {} once the unvault has {} confirmations",
+        info.hot_key, info.delay
+    );
+}
@@ -0,0 +1,455 @@
+use std::collections::HashMap;
+
+use anyhow::{anyhow, bail, Result};
+use bitcoin::{
+    hex::DisplayHex,
+    key::{Secp256k1, TapTweak, XOnlyPublicKey},
+    taproot::{LeafVersion, TapLeafHash, TapNodeHash, TaprootSpendInfo},
+    Address, OutPoint, Script, ScriptBuf, TxOut, Txid,
+};
+use serde::{Deserialize, Serialize};
+use tracing::trace;
+
+use crate::{
+    ids::{NodePath, UserIndex},
+    interpreter::verify_input,
+    manifest::{LoadedPool, PoolManifest},
+    pools::{close_all_exit, node_exit},
+    progress::ProgressMode,
+};
+
+// One template leaf of one node, checked against the rebuilt node output key
+#[derive(Debug, Clone, Serialize)]
+pub struct LeafCheck {
+    pub node: NodePath,
+    // None for the exit pool leaf, it pays both users
+    pub spender: Option<UserIndex>,
+    pub template_hash: String,
+    // the control block proves the leaf is in the node's tap tree
+    pub committed: bool,
+    // the template pays the spender's address from the manifest
+    pub pays_spender: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct VerificationReport {
+    pub onchain_script_pubkey: ScriptBuf,
+    // None if the tree could not be rebuilt, see `errors`
+    pub rebuilt_script_pubkey: Option<ScriptBuf>,
+    pub matches_onchain: bool,
+    pub matches_manifest: bool,
+    pub nodes_checked: usize,
+    pub leaves: Vec<LeafCheck>,
+    // every templated spend executed with its witness, empty unless asked for
+    pub executions: Vec<ScriptExecution>,
+    pub errors: Vec<String>,
+}
+
+impl VerificationReport {
+    pub fn is_valid(&self) -> bool {
+        self.matches_onchain
+            && self.matches_manifest
+            && self.errors.is_empty()
+            && self
+                .leaves
+                .iter()
+                .all(|leaf| leaf.committed && leaf.pays_spender)
+            && self
+                .executions
+                .iter()
+                .all(|execution| execution.error.is_none())
+    }
+}
+
+// Rebuild the pool from the manifest and check it against the output that funds it on chain.
+// No node, no keys and no env vars, so auditors can embed it as is.
+pub fn verify_pool(manifest: &PoolManifest, onchain_spk: &ScriptBuf) -> VerificationReport {
+    let mut report = VerificationReport {
+        onchain_script_pubkey: onchain_spk.clone(),
+        rebuilt_script_pubkey: None,
+        matches_onchain: false,
+        matches_manifest: false,
+        nodes_checked: 0,
+        leaves: Vec::new(),
+        executions: Vec::new(),
+        errors: Vec::new(),
+    };
+
+    let mut config = manifest.profile.preset();
+    config.progress = ProgressMode::Off;
+    let pool = match manifest.rebuild_pool(config) {
+        Ok(pool) => pool,
+        Err(e) => {
+            report
+                .errors
+                .push(format!("could not rebuild the pool: {}", e));
+            return report;
+        }
+    };
+    if let Err(e) = check_pool(manifest, &pool, &mut report) {
+        report.errors.push(e.to_string());
+    }
+    report
+}
+
+fn check_pool(
+    manifest: &PoolManifest,
+    pool: &LoadedPool,
+    report: &mut VerificationReport,
+) -> Result<()> {
+    let secp = Secp256k1::verification_only();
+    let root = Address::p2tr_tweaked(
+        pool.tree
+            .spend_info(&pool.config.pool_root()?)?
+            .output_key(),
+        manifest.network,
+    );
+    report.matches_onchain = root.script_pubkey() == report.onchain_script_pubkey;
+    report.matches_manifest = root.to_string() == manifest.root_address;
+    report.rebuilt_script_pubkey = Some(root.script_pubkey());
+
+    for users in pool.tree.node_paths() {
+        // the exit pool has one leaf paying both users
+        let spenders: Vec<UserIndex> = if users.is_exit() {
+            users.user_indices().take(1).collect()
+        } else {
+            users.user_indices().collect()
+        };
+        for spender in spenders {
+            let exit = node_exit(
+                &pool.tree,
+                &pool.config,
+                pool.backend.as_ref(),
+                &pool.addresses,
+                &pool.anchor_addr,
+                &users,
+                spender,
+            )?;
+            let leaf_script = pool.backend.leaf_script(exit.template_hash);
+            let committed = exit
+                .spend_info
+                .control_block(&(leaf_script.clone(), pool.config.leaf_version))
+                .is_some_and(|control_block| {
+                    control_block.verify_taproot_commitment(
+                        &secp,
+                        exit.spend_info.output_key().to_x_only_public_key(),
+                        &leaf_script,
+                    )
+                });
+            let spender_spk = pool.addresses[spender.index()].script_pubkey();
+
+            report.leaves.push(LeafCheck {
+                node: users.clone(),
+                spender: (!users.is_exit()).then_some(spender),
+                template_hash: exit.template_hash.to_lower_hex_string(),
+                committed,
+                pays_spender: exit
+                    .outputs
+                    .iter()
+                    .any(|output| output.script_pubkey == spender_spk),
+            });
+        }
+        report.nodes_checked += 1;
+    }
+    Ok(())
+}
+
+pub fn print_verification(report: &VerificationReport) {
+    let rebuilt = report
+        .rebuilt_script_pubkey
+        .as_ref()
+        .map_or("nothing".to_string(), |spk| spk.to_hex_string());
+    println!(
+        "rebuilt root {}: {} the funding output, {} the manifest",
+        rebuilt,
+        if report.matches_onchain {
+            "matches"
+        } else {
+            "doesn't match"
+        },
+        if report.matches_manifest {
+            "matches"
+        } else {
+            "doesn't match"
+        }
+    );
+    let bad_leaves: Vec<&LeafCheck> = report
+        .leaves
+        .iter()
+        .filter(|leaf| !leaf.committed || !leaf.pays_spender)
+        .collect();
+    println!(
+        "{} nodes, {} leaves, {} bad",
+        report.nodes_checked,
+        report.leaves.len(),
+        bad_leaves.len()
+    );
+    for leaf in bad_leaves {
+        println!(
+            "  node {} leaf {}: committed {}, pays its user {}",
+            leaf.node, leaf.template_hash, leaf.committed, leaf.pays_spender
+        );
+    }
+    if !report.executions.is_empty() {
+        let skipped = report.executions.iter().filter(|e| e.skipped).count();
+        let failed: Vec<&ScriptExecution> = report
+            .executions
+            .iter()
+            .filter(|e| e.error.is_some())
+            .collect();
+        println!(
+            "{} spends executed, {} failed, {} skipped (not presigned)",
+            report.executions.len() - skipped,
+            failed.len(),
+            skipped
+        );
+        for execution in failed {
+            println!(
+                "  node {} {:?}: {}",
+                execution.node,
+                execution.leaf,
+                execution.error.as_deref().unwrap_or_default()
+            );
+        }
+    }
+    for error in &report.errors {
+        println!("error: {}", error);
+    }
+}
+
+// Which of a node's leaves an execution went through
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ExecutedLeaf {
+    // None for the exit pool leaf, it pays both users
+    Exit { spender: Option<UserIndex> },
+    CloseAll,
+    Recovery,
+}
+
+// One templated spend run through the interpreter against the node output it spends
+#[derive(Debug, Clone, Serialize)]
+pub struct ScriptExecution {
+    pub node: NodePath,
+    #[serde(flatten)]
+    pub leaf: ExecutedLeaf,
+    // None when the backend has no witness for it, a presigned pool only signed the planned unwind
+    pub txid: Option<Txid>,
+    pub skipped: bool,
+    pub error: Option<String>,
+}
+
+// Finalize every templated spend of every node, starting from the funding output at `funding`,
+// and execute each witness with the interpreter: the control block, the leaf script and the
+// template, signature or timelock it checks. Children are spent from the outputs their parent's
+// template creates, through the planned unwind where there is one, so presigned witnesses sign
+// the outpoints they will see.
+pub fn execute_pool_scripts(
+    manifest: &PoolManifest,
+    funding: OutPoint,
+) -> Result<Vec<ScriptExecution>> {
+    let mut config = manifest.profile.preset();
+    config.progress = ProgressMode::Off;
+    let pool = manifest.rebuild_pool(config)?;
+    let node_scripts: HashMap<ScriptBuf, NodePath> = pool
+        .tree
+        .iter_nodes()
+        .map(|(users, node)| {
+            (
+                ScriptBuf::new_p2tr_tweaked(node.spend_info.output_key()),
+                users.clone(),
+            )
+        })
+        .collect();
+    let pool_root = pool.config.pool_root()?;
+    let root = pool.tree.node(&pool_root)?;
+    let mut outputs: HashMap<NodePath, (OutPoint, TxOut)> = HashMap::from([(
+        pool_root,
+        (
+            funding,
+            TxOut {
+                value: root.amount,
+                script_pubkey: ScriptBuf::new_p2tr_tweaked(root.spend_info.output_key()),
+            },
+        ),
+    )]);
+
+    let mut executions = Vec::new();
+    for (users, node) in pool.tree.iter_nodes() {
+        let Some((outpoint, prevout)) = outputs.get(users).cloned() else {
+            continue;
+        };
+        let mut spends = Vec::new();
+        let spenders: Vec<UserIndex> = if users.is_exit() {
+            users.user_indices().take(1).collect()
+        } else {
+            users.user_indices().collect()
+        };
+        for spender in spenders {
+            let exit = node_exit(
+                &pool.tree,
+                &pool.config,
+                pool.backend.as_ref(),
+                &pool.addresses,
+                &pool.anchor_addr,
+                users,
+                spender,
+            )?;
+            let leaf = ExecutedLeaf::Exit {
+                spender: (!users.is_exit()).then_some(spender),
+            };
+            let planned = pool
+                .config
+                .unwind_node(spender)
+                .is_ok_and(|planned| planned == *users);
+            let spend = exit.template_spend(outpoint, prevout.clone(), &pool.config);
+            spends.push((leaf, planned, pool.backend.finalize(spend)));
+        }
+        if users.is_root() && node.close_all.is_some() {
+            let exit = close_all_exit(
+                &pool.tree,
+                &pool.config,
+                pool.backend.as_ref(),
+                &pool.addresses,
+                &pool.anchor_addr,
+            )?;
+            let spend = exit.template_spend(outpoint, prevout.clone(), &pool.config);
+            spends.push((ExecutedLeaf::CloseAll, false, pool.backend.finalize(spend)));
+        }
+        if let (Some(recovery), Some(_)) = (&pool.config.recovery, &node.recovery_script) {
+            let sweep = recovery.sweep_tx(
+                outpoint,
+                prevout.value,
+                &node.spend_info,
+                &pool.anchor_addr,
+                &pool.config,
+            );
+            spends.push((ExecutedLeaf::Recovery, false, sweep));
+        }
+
+        for (leaf, planned, tx) in spends {
+            let tx = match tx {
+                Ok(tx) => tx,
+                Err(e) if pool.backend.requires_presigning() => {
+                    executions.push(ScriptExecution {
+                        node: users.clone(),
+                        leaf,
+                        txid: None,
+                        skipped: true,
+                        error: None,
+                    });
+                    trace!("{}: {}", users, e);
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
+            let txid = tx.compute_txid();
+            for (vout, output) in tx.output.iter().enumerate() {
+                if let Some(child) = node_scripts.get(&output.script_pubkey) {
+                    let spent = (OutPoint::new(txid, vout as u32), output.clone());
+                    if planned {
+                        outputs.insert(child.clone(), spent);
+                    } else {
+                        outputs.entry(child.clone()).or_insert(spent);
+                    }
+                }
+            }
+            executions.push(ScriptExecution {
+                node: users.clone(),
+                leaf,
+                txid: Some(txid),
+                skipped: false,
+                error: verify_input(&tx, 0, std::slice::from_ref(&prevout))
+                    .err()
+                    .map(|e| e.to_string()),
+            });
+        }
+    }
+    Ok(executions)
+}
+
+// Inclusion proof for one leaf of a node: the sibling hashes from the leaf up to the tap tree root
+// and the key they tweak. O(log n) hashes, a user checks their own leaf without the rest of the tree.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LeafProof {
+    pub leaf_version: u8,
+    pub tapleaf_hash: TapLeafHash,
+    pub internal_key: XOnlyPublicKey,
+    // leaf first, each one is hashed with the running node hash (smaller hash first, BIP-341)
+    pub siblings: Vec<TapNodeHash>,
+    pub merkle_root: TapNodeHash,
+    pub output_key: XOnlyPublicKey,
+}
+
+impl LeafProof {
+    pub fn new(
+        spend_info: &TaprootSpendInfo,
+        leaf_script: &Script,
+        leaf_version: LeafVersion,
+    ) -> Result<Self> {
+        let control_block = spend_info
+            .control_block(&(leaf_script.to_owned(), leaf_version))
+            .ok_or_else(|| anyhow!("leaf is not in the tap tree"))?;
+        let merkle_root = spend_info
+            .merkle_root()
+            .ok_or_else(|| anyhow!("tap tree has no leaves"))?;
+        Ok(Self {
+            leaf_version: leaf_version.to_consensus(),
+            tapleaf_hash: TapLeafHash::from_script(leaf_script, leaf_version),
+            internal_key: spend_info.internal_key(),
+            siblings: control_block.merkle_branch.to_vec(),
+            merkle_root,
+            output_key: spend_info.output_key().to_x_only_public_key(),
+        })
+    }
+}
+
+// Check `proof` shows `leaf_script` is committed to by the taproot output `script_pubkey`.
+// Standalone, nothing from the pool is needed: hash the leaf, fold in the siblings, tweak the internal key.
+pub fn verify_leaf_proof(
+    leaf_script: &Script,
+    proof: &LeafProof,
+    script_pubkey: &Script,
+) -> Result<()> {
+    let leaf_version = LeafVersion::from_consensus(proof.leaf_version)?;
+    let leaf_hash = TapLeafHash::from_script(leaf_script, leaf_version);
+    if leaf_hash != proof.tapleaf_hash {
+        bail!(
+            "leaf script hashes to {}, the proof is for {}",
+            leaf_hash,
+            proof.tapleaf_hash
+        );
+    }
+
+    let merkle_root = proof
+        .siblings
+        .iter()
+        .fold(TapNodeHash::from(leaf_hash), |node, sibling| {
+            TapNodeHash::from_node_hashes(node, *sibling)
+        });
+    if merkle_root != proof.merkle_root {
+        bail!(
+            "siblings lead to root {}, the proof claims {}",
+            merkle_root,
+            proof.merkle_root
+        );
+    }
+
+    let secp = Secp256k1::verification_only();
+    let (output_key, _parity) = proof.internal_key.tap_tweak(&secp, Some(merkle_root));
+    if output_key.to_x_only_public_key() != proof.output_key {
+        bail!(
+            "internal key tweaked with the root is {}, the proof claims {}",
+            output_key,
+            proof.output_key
+        );
+    }
+    if ScriptBuf::new_p2tr_tweaked(output_key).as_script() != script_pubkey {
+        bail!(
+            "output key {} is not the one {} pays to",
+            output_key,
+            script_pubkey
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,142 @@
+use std::{collections::BTreeMap, fmt};
+
+use anyhow::{bail, Result};
+use bitcoin::{Amount, OutPoint, TxOut};
+use bitcoincore_rpc::{json::ListUnspentResultEntry, RpcApi};
+use serde::Deserialize;
+use serde_json::json;
+use tracing::info;
+
+use crate::rpc_helper::AsyncRpc;
+
+// the wallet flags of getwalletinfo that decide which coins can fund a pool
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct WalletFlags {
+    pub private_keys_enabled: bool,
+    // older nodes don't have it
+    #[serde(default)]
+    pub avoid_reuse: bool,
+}
+
+// A listunspent entry with what bitcoincore_rpc leaves out
+#[derive(Debug, Clone, Deserialize)]
+pub struct WalletCoin {
+    #[serde(flatten)]
+    pub entry: ListUnspentResultEntry,
+    // only listed by wallets with avoid_reuse set
+    #[serde(default)]
+    pub reused: bool,
+}
+
+impl WalletCoin {
+    pub fn outpoint(&self) -> OutPoint {
+        OutPoint::new(self.entry.txid, self.entry.vout)
+    }
+
+    pub fn txout(&self) -> TxOut {
+        TxOut {
+            value: self.entry.amount,
+            script_pubkey: self.entry.script_pub_key.clone(),
+        }
+    }
+}
+
+// Why a wallet coin can't go into a funding tx, worst first
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Unusable {
+    // a watch-only address or descriptor, the wallet has no key for it
+    WatchOnly,
+    // the wallet doesn't know the script well enough to sign, e.g. a multisig missing a cosigner's key
+    Unsolvable,
+    // its address was paid before and the wallet has avoid_reuse set
+    Reused,
+}
+
+impl Unusable {
+    pub fn of(coin: &WalletCoin, flags: &WalletFlags) -> Option<Self> {
+        if !coin.entry.spendable {
+            Some(Self::WatchOnly)
+        } else if !coin.entry.solvable {
+            Some(Self::Unsolvable)
+        } else if flags.avoid_reuse && coin.reused {
+            Some(Self::Reused)
+        } else {
+            None
+        }
+    }
+
+    // what to do about coins like this
+    pub fn guidance(&self) -> &'static str {
+        match self {
+            Self::WatchOnly => "fund from the wallet holding their keys with `fund --psbt`",
+            Self::Unsolvable => "import the descriptor with every key and script the wallet needs to sign",
+            Self::Reused => "`setwalletflag avoid_reuse false` lets the wallet spend them, at the privacy cost the flag guards against",
+        }
+    }
+}
+
+impl fmt::Display for Unusable {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Self::WatchOnly => "watch-only",
+            Self::Unsolvable => "unsolvable",
+            Self::Reused => "reused",
+        })
+    }
+}
+
+pub async fn wallet_flags(rpc: &AsyncRpc) -> Result<WalletFlags> {
+    rpc.run(|c| c.call("getwalletinfo", &[])).await
+}
+
+// Wallet coins with at least `min_conf` confirmations that a funding tx can spend. Unconfirmed
+// coins from someone else (unsafe) are left to the node, coins worth less than `minimum` (what an
+// input costs) to listunspent's query options, and watch-only, unsolvable and reused coins are
+// skipped here. Fails with what to do when the wallet has coins but none of them can be used.
+pub async fn spendable_coins(
+    rpc: &AsyncRpc,
+    min_conf: usize,
+    minimum: Amount,
+) -> Result<Vec<(OutPoint, TxOut)>> {
+    let flags = wallet_flags(rpc).await?;
+    if !flags.private_keys_enabled {
+        bail!("the wallet has private keys disabled, it can't sign a funding tx: fund from the wallet holding the keys with `fund --psbt`");
+    }
+    let params = [
+        json!(min_conf),
+        json!(9_999_999),
+        json!([]),
+        json!(false),
+        json!({ "minimumAmount": minimum.to_btc() }),
+    ];
+    let listed: Vec<WalletCoin> = rpc.run(move |c| c.call("listunspent", &params)).await?;
+
+    let mut skipped: BTreeMap<Unusable, (usize, Amount)> = BTreeMap::new();
+    let mut coins = Vec::new();
+    for coin in listed {
+        match Unusable::of(&coin, &flags) {
+            Some(reason) => {
+                let (count, amount) = skipped.entry(reason).or_default();
+                *count += 1;
+                *amount += coin.entry.amount;
+            }
+            None => coins.push((coin.outpoint(), coin.txout())),
+        }
+    }
+    for (reason, (count, amount)) in &skipped {
+        info!("  Skipping {} {} coins ({})", count, reason, amount);
+    }
+    if coins.is_empty() && !skipped.is_empty() {
+        let reasons: Vec<String> = skipped
+            .iter()
+            .map(|(reason, (count, amount))| {
+                format!("{} {} ({}): {}", count, reason, amount, reason.guidance())
+            })
+            .collect();
+        bail!(
+            "none of the wallet's coins can fund the pool, {}",
+            reasons.join("; ")
+        );
+    }
+    Ok(coins)
+}
@@ -0,0 +1,493 @@
+use std::{
+    collections::{HashMap, HashSet},
+    path::Path,
+    time::Duration,
+};
+
+use anyhow::{bail, Result};
+use bitcoin::{hex::DisplayHex, OutPoint, ScriptBuf, Transaction, TxOut};
+use bitcoincore_rpc::{json::ScanTxOutRequest, RpcApi};
+use tracing::{info, warn};
+
+use crate::{
+    broadcast::{broadcast, BroadcastKind},
+    checkpoints::{prune_reorged, record_checkpoint, unspent_nodes, Checkpoint},
+    event_stream::EventStream,
+    fee_bump::{FeeBumper, PendingSpend},
+    fetch::RpcFetcher,
+    ids::{NodePath, UserIndex},
+    key_close::is_key_close,
+    lifecycle::Event,
+    manifest::{LoadedPool, PoolManifest},
+    metrics::METRICS,
+    next_step::{exit_steps, sweep_steps, NextStep},
+    pools::{close_all_exit, cpfp_tx, node_exit},
+    rpc_helper::{connect_fee_payer, AsyncRpc},
+    standardness::check_standard,
+    webhooks::PoolEvent,
+};
+
+// Keep an eye on the pool, report its lifecycle to WEBHOOK_URL (and `events`, serve's stream) and sweep any node that has been
+// sitting unspent for longer than the recovery timeout. Stops once no pool node is left in the utxo set.
+// Funding by someone else and recovery sweeps are recorded in the manifest's lifecycle.
+pub async fn watch(
+    mut manifest: PoolManifest,
+    manifest_path: &Path,
+    interval: Duration,
+    events: Option<EventStream>,
+) -> Result<()> {
+    if manifest.lifecycle.is_finished() {
+        bail!("the pool is {}, nothing to watch", manifest.lifecycle);
+    }
+    let pool = manifest.load_pool()?;
+    let config = &pool.config;
+    if config.recovery.is_none() && config.webhook.is_none() && events.is_none() {
+        bail!("this pool was created without a recovery path and WEBHOOK_URL is not set, nothing to do");
+    }
+
+    let rpc = AsyncRpc::connect(config).await?;
+    let fee_payer = connect_fee_payer(config, &rpc).await?;
+    // the checkpoints' blocks and the blocks between two scans, fetched together
+    let fetcher = RpcFetcher::connect(&rpc, config).await?;
+
+    // node scriptPubKey -> users in that node
+    let nodes = pool.tree.node_scripts(config);
+    let descriptors: Vec<ScanTxOutRequest> = nodes
+        .keys()
+        .map(|spk| {
+            ScanTxOutRequest::Single(format!("raw({})", spk.as_bytes().to_lower_hex_string()))
+        })
+        .collect();
+
+    match &config.recovery {
+        Some(recovery) => info!(
+            "watching {} pool nodes, sweeping to {} after {} blocks \n",
+            nodes.len(),
+            recovery.address,
+            recovery.timeout
+        ),
+        None => info!("watching {} pool nodes \n", nodes.len()),
+    }
+    if let Some(webhook) = &config.webhook {
+        info!("posting pool events to {} \n", webhook.url);
+    }
+    METRICS.set_pools_tracked(1);
+
+    // a reorg since the last run takes its checkpoints with it, those txs are found again
+    if prune_reorged(&rpc, &fetcher, &mut manifest.checkpoints).await? > 0 {
+        manifest.write(manifest_path)?;
+    }
+
+    // nodes unspent at the last scan, and the height of that scan. A restart starts from the last
+    // checkpoint, so whatever was spent while we were down is only looked for in the blocks since
+    let mut tracked: HashMap<OutPoint, NodePath> = unspent_nodes(&manifest.checkpoints);
+    let mut scanned_height = manifest
+        .checkpoints
+        .last()
+        .map(|checkpoint| checkpoint.height);
+    if let Some(height) = scanned_height {
+        info!(
+            "picking up from the checkpoint at height {}, {} pool txs confirmed \n",
+            height,
+            manifest.checkpoints.len()
+        );
+    }
+    let mut seen = scanned_height.is_some();
+    let mut funded = manifest
+        .checkpoints
+        .iter()
+        .any(|checkpoint| checkpoint.spent.is_none());
+    let mut matured = HashSet::new();
+    let mut swept = HashSet::new();
+    // sweeps still in the mempool, with POOL_BUMP_AFTER_BLOCKS
+    let mut bumper = config.fee_bump.clone().map(FeeBumper::new);
+    let mut ticker = tokio::time::interval(interval);
+    loop {
+        ticker.tick().await;
+        if let Some(bumper) = &mut bumper {
+            bumper.tick(&rpc, &fee_payer, config).await?;
+        }
+
+        let request = descriptors.clone();
+        let scan = rpc
+            .run(move |c| c.scan_tx_out_set_blocking(&request))
+            .await?;
+        let tip = match scan.height {
+            Some(height) => height,
+            None => rpc.run(|c| c.get_block_count()).await?,
+        };
+        let unspent: HashMap<OutPoint, NodePath> = scan
+            .unspents
+            .iter()
+            .map(|utxo| {
+                (
+                    OutPoint::new(utxo.txid, utxo.vout),
+                    nodes[&utxo.script_pub_key].clone(),
+                )
+            })
+            .collect();
+
+        // whatever spent a node since the last scan is in one of the blocks in between
+        let spent: HashMap<OutPoint, NodePath> = tracked
+            .drain()
+            .filter(|(outpoint, _)| !unspent.contains_key(outpoint))
+            .collect();
+        if let (Some(from), false) = (scanned_height, spent.is_empty()) {
+            let spends =
+                find_spends(&fetcher, &pool, &nodes, &spent, &unspent, from + 1, tip).await?;
+            let mut checkpointed = false;
+            for (event, next_steps, checkpoint) in spends {
+                checkpointed |= record_checkpoint(&mut manifest.checkpoints, checkpoint);
+                if let PoolEvent::RecoverySwept { .. } = event {
+                    record(&mut manifest, manifest_path, Event::Recover)?;
+                }
+                notify(&manifest, &pool, events.as_ref(), event, &next_steps).await;
+            }
+            if checkpointed {
+                manifest.write(manifest_path)?;
+            }
+        }
+        tracked = unspent;
+        scanned_height = Some(tip);
+        seen |= !tracked.is_empty();
+
+        METRICS.set_pending_withdrawals(tracked.values().map(NodePath::len).sum());
+        if tracked.is_empty() {
+            if !seen && funding_pending(&rpc, &manifest).await {
+                info!("pool not funded yet, waiting for the funding tx to confirm");
+                continue;
+            }
+            METRICS.set_pools_tracked(0);
+            notify(
+                &manifest,
+                &pool,
+                events.as_ref(),
+                PoolEvent::PoolCompleted,
+                &[],
+            )
+            .await;
+            info!("no pool node left unspent, nothing to watch");
+            return Ok(());
+        }
+
+        for utxo in scan.unspents {
+            let outpoint = OutPoint::new(utxo.txid, utxo.vout);
+            let users = &nodes[&utxo.script_pub_key];
+            let prevout = TxOut {
+                value: utxo.amount,
+                script_pubkey: utxo.script_pub_key.clone(),
+            };
+            if users.is_root() && !funded {
+                funded = true;
+                // funded with some other wallet
+                if manifest.funding_txid.is_none() {
+                    manifest.funding_txid = Some(utxo.txid);
+                    record(&mut manifest, manifest_path, Event::Fund)?;
+                }
+                let height = utxo.height;
+                let checkpoint = Checkpoint {
+                    txid: utxo.txid,
+                    height,
+                    block_hash: rpc.run(move |c| c.get_block_hash(height)).await?,
+                    spent: None,
+                    nodes: vec![(outpoint, users.clone())],
+                };
+                if record_checkpoint(&mut manifest.checkpoints, checkpoint) {
+                    manifest.write(manifest_path)?;
+                }
+                let event = PoolEvent::FundingConfirmed {
+                    txid: utxo.txid,
+                    height: utxo.height,
+                };
+                let next_steps = exit_steps(&pool, users, outpoint, &prevout);
+                notify(&manifest, &pool, events.as_ref(), event, &next_steps).await;
+            }
+
+            let Some(recovery) = &config.recovery else {
+                continue;
+            };
+            if swept.contains(&outpoint) {
+                continue;
+            }
+
+            // the sweep can go in the next block once it is `timeout` blocks deep
+            let confirmations = tip + 1 - utxo.height;
+            if confirmations < u64::from(recovery.timeout) {
+                info!(
+                    "pool node {} at {} unspent for {} of {} blocks",
+                    users, outpoint, confirmations, recovery.timeout
+                );
+                continue;
+            }
+            let spend_info = pool.tree.spend_info(users)?;
+            let amount = pool.tree.node(users)?.amount;
+            let sweep =
+                recovery.sweep_tx(outpoint, amount, spend_info, &pool.anchor_addr, config)?;
+            if matured.insert(outpoint) {
+                let event = PoolEvent::RecoveryMatured {
+                    node: users.clone(),
+                    outpoint,
+                    blocks_unspent: confirmations,
+                };
+                notify(
+                    &manifest,
+                    &pool,
+                    events.as_ref(),
+                    event,
+                    &sweep_steps(&pool, users, &sweep),
+                )
+                .await;
+            }
+
+            if let Err(e) = check_standard(&rpc, &sweep, std::slice::from_ref(&prevout)).await {
+                warn!("recovery sweep of {} is not standard: {}", outpoint, e);
+                continue;
+            }
+            let txid = match broadcast(&rpc, &sweep, BroadcastKind::RecoverySweep).await {
+                Ok(txid) => txid,
+                Err(e) => {
+                    warn!("recovery sweep of {} failed: {}", outpoint, e);
+                    continue;
+                }
+            };
+            info!(
+                "pool node {} timed out, swept {} to {}: {}",
+                users, utxo.amount, recovery.address, txid
+            );
+            swept.insert(outpoint);
+
+            if config.anchor_amount.is_some() {
+                match &mut bumper {
+                    // due right away, the next tick pays its child and replaces it while it is stuck
+                    Some(bumper) => {
+                        let label = format!("recovery sweep of {}", users);
+                        bumper.track_due(PendingSpend::new(&sweep, &[prevout], tip, &label));
+                    }
+                    None => {
+                        cpfp_tx(&fee_payer, config, txid).await?;
+                    }
+                }
+            }
+        }
+    }
+}
+
+// Look through blocks `from..=to` for the txs spending the `spent` nodes and tell what they were,
+// with a checkpoint for each. A node one of them created that isn't `unspent` any more was spent
+// in these blocks too, so it is looked for as well.
+async fn find_spends(
+    fetcher: &RpcFetcher,
+    pool: &LoadedPool,
+    nodes: &HashMap<ScriptBuf, NodePath>,
+    spent: &HashMap<OutPoint, NodePath>,
+    unspent: &HashMap<OutPoint, NodePath>,
+    from: u64,
+    to: u64,
+) -> Result<Vec<(PoolEvent, Vec<NextStep>, Checkpoint)>> {
+    let mut spent = spent.clone();
+    let mut events = Vec::new();
+    let mut blocks = fetcher.scan(from..=to);
+    while let Some((height, hash, block)) = blocks.next_block().await? {
+        for tx in &block.txdata {
+            for input in &tx.input {
+                let Some(node) = spent.remove(&input.previous_output) else {
+                    continue;
+                };
+                let event = classify_spend(pool, &node, input.previous_output, tx, height)?;
+                let checkpoint =
+                    Checkpoint::of_tx(tx, Some(input.previous_output), height, hash, nodes);
+                for (outpoint, users) in &checkpoint.nodes {
+                    if !unspent.contains_key(outpoint) {
+                        spent.insert(*outpoint, users.clone());
+                    }
+                }
+                // after a withdrawal the users in the next pool get their exits from it
+                let next_steps = match &event {
+                    PoolEvent::WithdrawalConfirmed { node, paid, .. } if !node.is_exit() => {
+                        match next_node_output(pool, node, paid[0], tx)? {
+                            Some((next, outpoint, prevout)) => {
+                                exit_steps(pool, &next, outpoint, &prevout)
+                            }
+                            None => Vec::new(),
+                        }
+                    }
+                    _ => Vec::new(),
+                };
+                events.push((event, next_steps, checkpoint));
+            }
+        }
+    }
+    if !spent.is_empty() {
+        warn!(
+            "{} spent pool nodes not found in blocks {} to {}, reorg?",
+            spent.len(),
+            from,
+            to
+        );
+    }
+    Ok(events)
+}
+
+// a node can only leave by one of its templates or the recovery sweep, anything else is unexpected
+pub fn classify_spend(
+    pool: &LoadedPool,
+    node: &NodePath,
+    outpoint: OutPoint,
+    tx: &Transaction,
+    height: u64,
+) -> Result<PoolEvent> {
+    let txid = tx.compute_txid();
+    // the exit pool has one template paying both users
+    let spenders: Vec<_> = if node.is_exit() {
+        node.user_indices().take(1).collect()
+    } else {
+        node.user_indices().collect()
+    };
+    for spender in spenders {
+        let exit = node_exit(
+            &pool.tree,
+            &pool.config,
+            pool.backend.as_ref(),
+            &pool.addresses,
+            &pool.anchor_addr,
+            node,
+            spender,
+        )?;
+        if exit.outputs == tx.output {
+            return Ok(PoolEvent::WithdrawalConfirmed {
+                node: node.clone(),
+                paid: if node.is_exit() {
+                    node.user_indices().collect()
+                } else {
+                    vec![spender]
+                },
+                txid,
+                height,
+            });
+        }
+    }
+
+    if node.is_root() {
+        if let Ok(exit) = close_all_exit(
+            &pool.tree,
+            &pool.config,
+            pool.backend.as_ref(),
+            &pool.addresses,
+            &pool.anchor_addr,
+        ) {
+            if exit.outputs == tx.output {
+                return Ok(PoolEvent::WithdrawalConfirmed {
+                    node: node.clone(),
+                    paid: node.user_indices().collect(),
+                    txid,
+                    height,
+                });
+            }
+        }
+    }
+
+    // nothing about the key path is in the tree, the members agreed on whatever it pays
+    if is_key_close(pool, node, outpoint, tx) {
+        return Ok(PoolEvent::WithdrawalConfirmed {
+            node: node.clone(),
+            paid: node.user_indices().collect(),
+            txid,
+            height,
+        });
+    }
+
+    if let Some(recovery) = &pool.config.recovery {
+        if recovery.outputs(
+            pool.tree.node(node)?.amount,
+            &pool.anchor_addr,
+            &pool.config,
+        ) == tx.output
+        {
+            return Ok(PoolEvent::RecoverySwept {
+                node: node.clone(),
+                outpoint,
+                txid,
+                height,
+            });
+        }
+    }
+
+    Ok(PoolEvent::UnexpectedSpend {
+        node: node.clone(),
+        outpoint,
+        txid,
+        height,
+    })
+}
+
+// the pool `tx` moved the rest of `node` to after `spender` left, None for the exit pool
+pub fn next_node_output(
+    pool: &LoadedPool,
+    node: &NodePath,
+    spender: UserIndex,
+    tx: &Transaction,
+) -> Result<Option<(NodePath, OutPoint, TxOut)>> {
+    if node.is_exit() {
+        return Ok(None);
+    }
+    let next = node.without(spender)?;
+    let next_spk = ScriptBuf::new_p2tr_tweaked(pool.tree.spend_info(&next)?.output_key());
+    Ok(tx
+        .output
+        .iter()
+        .position(|output| output.script_pubkey == next_spk)
+        .map(|vout| {
+            let outpoint = OutPoint::new(tx.compute_txid(), vout as u32);
+            (next, outpoint, tx.output[vout].clone())
+        }))
+}
+
+async fn notify(
+    manifest: &PoolManifest,
+    pool: &LoadedPool,
+    events: Option<&EventStream>,
+    event: PoolEvent,
+    next_steps: &[NextStep],
+) {
+    match &event {
+        PoolEvent::UnexpectedSpend { .. } => warn!("pool event: {:?}", event),
+        _ => info!("pool event: {:?}", event),
+    }
+    if let Some(events) = events {
+        events.publish(
+            manifest.pool_id.as_ref(),
+            &manifest.root_address,
+            &event,
+            next_steps,
+        );
+    }
+    if let Some(webhook) = &pool.config.webhook {
+        webhook
+            .notify(
+                manifest.pool_id.as_ref(),
+                &manifest.root_address,
+                &event,
+                next_steps,
+            )
+            .await;
+    }
+}
+
+// keep what the chain shows in the manifest, a manifest that disagrees is left as it is
+fn record(manifest: &mut PoolManifest, manifest_path: &Path, event: Event) -> Result<()> {
+    match manifest.advance(event) {
+        Ok(()) => manifest.write(manifest_path),
+        Err(e) => {
+            warn!("not recorded in the manifest: {}", e);
+            Ok(())
+        }
+    }
+}
+
+// nothing was ever seen in the utxo set, the funding tx may still be on its way
+async fn funding_pending(rpc: &AsyncRpc, manifest: &PoolManifest) -> bool {
+    match manifest.funding_txid {
+        None => true,
+        Some(txid) => rpc.run(move |c| c.get_mempool_entry(&txid)).await.is_ok(),
+    }
+}
@@ -0,0 +1,214 @@
+use std::{fmt, time::Duration};
+
+use anyhow::{bail, Result};
+use bitcoin::{
+    hashes::{hmac, sha256, Hash, HashEngine},
+    hex::DisplayHex,
+    OutPoint, Txid,
+};
+use serde::Serialize;
+use tracing::{info, warn};
+
+use crate::{
+    config::{ConfigProblem, NetworkConfig},
+    ids::{NodePath, PoolId, UserIndex},
+    next_step::NextStep,
+    proxy::http_client,
+};
+
+// header carrying the hex HMAC-SHA256 of the request body, keyed with WEBHOOK_SECRET
+pub const SIGNATURE_HEADER: &str = "X-Pool-Signature";
+const DELIVERY_ATTEMPTS: u32 = 3;
+const DELIVERY_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Where pool lifecycle events are posted. Receivers check the signature header before trusting a payload.
+#[derive(Clone)]
+pub struct Webhook {
+    pub url: String,
+    secret: String,
+}
+
+// the secret stays out of logs and debug output
+impl fmt::Debug for Webhook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Webhook").field("url", &self.url).finish()
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum PoolEvent {
+    // `serve` took a registration, `user` is its place in registration order
+    UserRegistered {
+        user: UserIndex,
+        registered: usize,
+        capacity: usize,
+    },
+    // the window closed or the pool filled up
+    RegistrationClosed {
+        registered: usize,
+        capacity: usize,
+    },
+    // the manifest is written, the pool can be funded at `root_address`
+    PoolBuilt {
+        root_address: String,
+    },
+    // the root of the pool is in the utxo set
+    FundingConfirmed {
+        txid: Txid,
+        height: u64,
+    },
+    // `paid` left the pool in `txid`, both users for the exit pool
+    WithdrawalConfirmed {
+        node: NodePath,
+        paid: Vec<UserIndex>,
+        txid: Txid,
+        height: u64,
+    },
+    // a node was spent by something that is neither one of its templates nor the recovery sweep
+    UnexpectedSpend {
+        node: NodePath,
+        outpoint: OutPoint,
+        txid: Txid,
+        height: u64,
+    },
+    // the node's timeout has passed, the recovery sweep can be broadcast
+    RecoveryMatured {
+        node: NodePath,
+        outpoint: OutPoint,
+        blocks_unspent: u64,
+    },
+    RecoverySwept {
+        node: NodePath,
+        outpoint: OutPoint,
+        txid: Txid,
+        height: u64,
+    },
+    // no pool node is left in the utxo set, every user is out
+    PoolCompleted,
+    // `abort` gave the pool up before its funding confirmed, the registrations are void and
+    // `refund_txid` replaced the funding tx
+    PoolCancelled {
+        funding_txid: Option<Txid>,
+        refund_txid: Option<Txid>,
+        users: Vec<UserIndex>,
+    },
+}
+
+// what is posted to the webhook and streamed on serve's /events
+#[derive(Debug, Serialize)]
+pub struct WebhookPayload<'a> {
+    pub pool_id: Option<&'a PoolId>,
+    // empty until the pool is built
+    #[serde(skip_serializing_if = "str::is_empty")]
+    pub root_address: &'a str,
+    #[serde(flatten)]
+    pub event: &'a PoolEvent,
+    // what each affected user can broadcast themselves next
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    pub next_steps: &'a [NextStep],
+}
+
+impl Webhook {
+    // WEBHOOK_URL enables it, WEBHOOK_SECRET is required with it
+    pub fn from_env() -> Result<Option<Self>, ConfigProblem> {
+        let url = NetworkConfig::get_env_var("WEBHOOK_URL", "");
+        if url.is_empty() {
+            return Ok(None);
+        }
+        let secret = NetworkConfig::get_env_var("WEBHOOK_SECRET", "");
+        if secret.is_empty() {
+            return Err(ConfigProblem::InvalidEnv {
+                var: "WEBHOOK_SECRET".to_string(),
+                reason: "is not set but WEBHOOK_URL is, payloads have to be signed".to_string(),
+            });
+        }
+        Ok(Some(Self { url, secret }))
+    }
+
+    pub fn new(url: String, secret: String) -> Self {
+        Self { url, secret }
+    }
+
+    pub fn sign(&self, body: &[u8]) -> String {
+        let mut engine = hmac::HmacEngine::<sha256::Hash>::new(self.secret.as_bytes());
+        engine.input(body);
+        hmac::Hmac::<sha256::Hash>::from_engine(engine)
+            .to_byte_array()
+            .to_lower_hex_string()
+    }
+
+    // What a receiver does with a request: `header` is the SIGNATURE_HEADER value, `sha256=<hex>` of
+    // `body` under the shared secret. Compared in constant time, the mismatch gives nothing away
+    pub fn verify(&self, body: &[u8], header: &str) -> Result<()> {
+        let Some(signature) = header.strip_prefix("sha256=") else {
+            bail!("{} is not a sha256= signature", SIGNATURE_HEADER);
+        };
+        let expected = self.sign(body);
+        let differ = signature.len() != expected.len()
+            || signature
+                .bytes()
+                .zip(expected.bytes())
+                .fold(0, |acc, (a, b)| acc | (a ^ b))
+                != 0;
+        if differ {
+            bail!("{} doesn't match the body", SIGNATURE_HEADER);
+        }
+        Ok(())
+    }
+
+    // Post the event, retrying a couple of times. A receiver that is down is logged but never stops
+    // the caller, sweeping a node matters more than telling someone about it.
+    pub async fn notify(
+        &self,
+        pool_id: Option<&PoolId>,
+        root_address: &str,
+        event: &PoolEvent,
+        next_steps: &[NextStep],
+    ) {
+        let payload = WebhookPayload {
+            pool_id,
+            root_address,
+            event,
+            next_steps,
+        };
+        let body = match serde_json::to_vec(&payload) {
+            Ok(body) => body,
+            Err(e) => {
+                warn!("webhook payload for {:?}: {}", event, e);
+                return;
+            }
+        };
+
+        for attempt in 1..=DELIVERY_ATTEMPTS {
+            match self.post(&body).await {
+                Ok(()) => {
+                    info!("webhook delivered: {}", String::from_utf8_lossy(&body));
+                    return;
+                }
+                Err(e) => warn!(
+                    "webhook to {} failed (attempt {} of {}): {}",
+                    self.url, attempt, DELIVERY_ATTEMPTS, e
+                ),
+            }
+            if attempt < DELIVERY_ATTEMPTS {
+                tokio::time::sleep(Duration::from_secs(2u64.pow(attempt))).await;
+            }
+        }
+    }
+
+    async fn post(&self, body: &[u8]) -> Result<()> {
+        let response = http_client(&self.url)?
+            .post(&self.url)
+            .timeout(DELIVERY_TIMEOUT)
+            .header("Content-Type", "application/json")
+            .header(SIGNATURE_HEADER, format!("sha256={}", self.sign(body)))
+            .body(body.to_vec())
+            .send()
+            .await?;
+        if !response.status().is_success() {
+            bail!("receiver answered {}", response.status());
+        }
+        Ok(())
+    }
+}
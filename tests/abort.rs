@@ -0,0 +1,141 @@
+use bitcoin::Amount;
+
+use op_ctv_payment_pool::{
+    abort::refund_funding,
+    config::NetworkConfig,
+    mock::MockBackend,
+    profile::NetworkProfile,
+    rpc_helper::{send_funding_transaction, FundingDestination},
+};
+
+mod common;
+
+use common::anchor;
+
+#[tokio::test]
+async fn an_unconfirmed_funding_tx_is_replaced_by_a_refund() {
+    let config = NetworkConfig::new(NetworkProfile::RegtestLocal);
+    let mock = MockBackend::new(config.network);
+    mock.set_full_rbf(true);
+    let rpc = mock.rpc();
+    let coin = mock.add_utxo(Amount::from_btc(2.0).unwrap());
+    mock.mine(1);
+    let payer = mock.transaction(coin.txid).unwrap().output[0].clone();
+
+    let destination = FundingDestination::EntryPool(anchor(&config));
+    let (funding_txid, _) = send_funding_transaction(&rpc, &config, &destination, None)
+        .await
+        .unwrap();
+    assert_eq!(mock.mempool(), vec![funding_txid]);
+
+    let refund = refund_funding(&rpc, &config, funding_txid, None)
+        .await
+        .unwrap()
+        .unwrap();
+    // the pool output is gone, the payer's coin is back with them less two fees
+    assert_eq!(mock.mempool(), vec![refund.txid]);
+    assert_eq!(mock.confirmations(funding_txid), None);
+    let refund_tx = mock.transaction(refund.txid).unwrap();
+    assert_eq!(refund_tx.input[0].previous_output, coin);
+    assert_eq!(refund_tx.output.len(), 1);
+    assert_eq!(refund_tx.output[0].script_pubkey, payer.script_pubkey);
+    assert_eq!(refund.amount + refund.fee, payer.value);
+
+    // nothing left to replace
+    mock.mine(1);
+    assert!(refund_funding(&rpc, &config, funding_txid, None)
+        .await
+        .unwrap()
+        .is_none());
+}
+
+#[tokio::test]
+async fn a_confirmed_funding_tx_is_not_aborted() {
+    let config = NetworkConfig::new(NetworkProfile::RegtestLocal);
+    let mock = MockBackend::new(config.network);
+    let rpc = mock.rpc();
+    mock.add_utxo(Amount::from_btc(2.0).unwrap());
+    mock.mine(1);
+    let destination = FundingDestination::EntryPool(anchor(&config));
+    let (funding_txid, _) = send_funding_transaction(&rpc, &config, &destination, None)
+        .await
+        .unwrap();
+    mock.mine(1);
+
+    let refund_to = mock.new_address();
+    let error = refund_funding(&rpc, &config, funding_txid, Some(&refund_to))
+        .await
+        .unwrap_err();
+    assert!(error.to_string().contains("1 confirmations"), "{}", error);
+    assert_eq!(mock.confirmations(funding_txid), Some(1));
+}
+
+#[tokio::test]
+async fn a_node_without_full_rbf_keeps_the_funding_tx() {
+    let config = NetworkConfig::new(NetworkProfile::RegtestLocal);
+    let mock = MockBackend::new(config.network);
+    let rpc = mock.rpc();
+    mock.add_utxo(Amount::from_btc(2.0).unwrap());
+    mock.mine(1);
+    let destination = FundingDestination::EntryPool(anchor(&config));
+    let (funding_txid, _) = send_funding_transaction(&rpc, &config, &destination, None)
+        .await
+        .unwrap();
+
+    // a node without full rbf refuses the conflict and the funding tx stays
+    assert!(refund_funding(&rpc, &config, funding_txid, None)
+        .await
+        .is_err());
+    assert_eq!(mock.mempool(), vec![funding_txid]);
+}
+
+#[tokio::test]
+async fn a_funding_tx_only_the_chain_knows_is_not_gone() {
+    let config = NetworkConfig::new(NetworkProfile::RegtestLocal);
+    let mock = MockBackend::new(config.network);
+    let rpc = mock.rpc();
+    mock.add_utxo(Amount::from_btc(2.0).unwrap());
+    mock.mine(1);
+    let destination = FundingDestination::EntryPool(anchor(&config));
+    let (funding_txid, _) = send_funding_transaction(&rpc, &config, &destination, None)
+        .await
+        .unwrap();
+    mock.mine(1);
+
+    // another wallet paid it and the node has no txindex, only the utxo set has the funding
+    mock.fail_next("gettransaction", -5, "Invalid or non-wallet transaction id");
+    mock.fail_next(
+        "getrawtransaction",
+        -5,
+        "No such mempool transaction. Use -txindex or provide a block hash to enable blockchain transaction queries.",
+    );
+    let error = refund_funding(&rpc, &config, funding_txid, None)
+        .await
+        .unwrap_err();
+    assert!(error.to_string().contains("1 confirmations"), "{}", error);
+}
+
+#[tokio::test]
+async fn a_failing_node_does_not_make_the_funding_tx_gone() {
+    let config = NetworkConfig::new(NetworkProfile::RegtestLocal);
+    let mock = MockBackend::new(config.network);
+    let rpc = mock.rpc();
+    mock.add_utxo(Amount::from_btc(2.0).unwrap());
+    mock.mine(1);
+    let destination = FundingDestination::EntryPool(anchor(&config));
+    let (funding_txid, _) = send_funding_transaction(&rpc, &config, &destination, None)
+        .await
+        .unwrap();
+
+    mock.fail_next("gettransaction", -5, "Invalid or non-wallet transaction id");
+    mock.fail_next("getrawtransaction", -28, "Loading block index...");
+    let error = refund_funding(&rpc, &config, funding_txid, None)
+        .await
+        .unwrap_err();
+    assert!(
+        error.to_string().contains("Loading block index"),
+        "{}",
+        error
+    );
+    assert_eq!(mock.mempool(), vec![funding_txid]);
+}
@@ -0,0 +1,189 @@
+use bitcoin::{
+    address::NetworkUnchecked,
+    bip32::{ChildNumber, Xpub},
+    key::Secp256k1,
+    Address, Network, WitnessProgram, WitnessVersion,
+};
+use std::{fs, path::PathBuf, str::FromStr};
+
+use op_ctv_payment_pool::{
+    addresses::{check_address, AddressChecker, AddressErrors, AddressProblem},
+    ids::PoolId,
+    payroll::PayrollTemplate,
+    registration::{load_registrations, xpub_address},
+    POOL_USERS,
+};
+
+mod common;
+
+use common::address;
+
+fn unchecked(address: &Address) -> Address<NetworkUnchecked> {
+    address.to_string().parse().unwrap()
+}
+
+fn write(name: &str, text: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("{}-{}.json", name, std::process::id()));
+    fs::write(&path, text).unwrap();
+    path
+}
+
+#[test]
+fn addresses_are_checked_for_network_and_script() {
+    let good = address(0, Network::Regtest);
+    assert_eq!(
+        check_address(&unchecked(&good), Network::Regtest).unwrap(),
+        good
+    );
+
+    let testnet = address(0, Network::Testnet);
+    assert!(matches!(
+        check_address(&unchecked(&testnet), Network::Regtest),
+        Err(AddressProblem::WrongNetwork { .. })
+    ));
+
+    // a witness version no soft fork has defined yet
+    let future = Address::from_witness_program(
+        WitnessProgram::new(WitnessVersion::V2, &[1u8; 32]).unwrap(),
+        Network::Regtest,
+    );
+    assert!(matches!(
+        check_address(&unchecked(&future), Network::Regtest),
+        Err(AddressProblem::NonStandard { .. })
+    ));
+}
+
+#[test]
+fn checker_keeps_every_problem_and_finds_duplicates() {
+    let mut checker = AddressChecker::new(Network::Regtest);
+    let first = unchecked(&address(0, Network::Regtest));
+    assert!(checker.check(0, Some(2), &first).is_some());
+    assert!(checker.check(1, Some(3), &first).is_none());
+    assert!(checker
+        .check(2, Some(4), &unchecked(&address(2, Network::Bitcoin)))
+        .is_none());
+    assert!(checker
+        .check(3, Some(5), &unchecked(&address(3, Network::Regtest)))
+        .is_some());
+
+    let error = checker.finish().unwrap_err();
+    let errors = error.downcast_ref::<AddressErrors>().unwrap();
+    assert_eq!(errors.0.len(), 2);
+    assert_eq!(errors.0[0].entry, 1);
+    assert_eq!(errors.0[0].line, Some(3));
+    assert!(matches!(
+        errors.0[0].problem,
+        AddressProblem::Duplicate { first: 0, .. }
+    ));
+    assert_eq!(errors.0[1].entry, 2);
+    assert!(error.to_string().contains("entry 2 (line 4)"));
+}
+
+#[test]
+fn registration_files_report_bad_entries_with_their_lines() {
+    // one entry per line after the opening bracket, so entry i is on line i + 2
+    let mut entries: Vec<String> = (0..POOL_USERS)
+        .map(|i| {
+            format!(
+                r#"{{ "address": "{}", "signature": "AA==" }}"#,
+                address(i, Network::Regtest)
+            )
+        })
+        .collect();
+    entries[1] = format!(
+        r#"{{ "address": "{}", "signature": "AA==" }}"#,
+        address(1, Network::Testnet)
+    );
+    entries[3] = entries[0].clone();
+    entries[5] = r#"{ "address": 5, "signature": "AA==" }"#.to_string();
+    let path = write("registrations", &format!("[\n{}\n]\n", entries.join(",\n")));
+
+    let error = load_registrations(
+        &path,
+        Network::Regtest,
+        &"addresses test".parse::<PoolId>().unwrap(),
+        None,
+    )
+    .unwrap_err();
+    fs::remove_file(&path).unwrap();
+    let errors = error.downcast_ref::<AddressErrors>().unwrap();
+    let found: Vec<_> = errors
+        .0
+        .iter()
+        .map(|error| (error.entry, error.line))
+        .collect();
+    assert_eq!(found, vec![(1, Some(3)), (3, Some(5)), (5, Some(7))]);
+    assert!(matches!(
+        errors.0[0].problem,
+        AddressProblem::WrongNetwork { .. }
+    ));
+    assert!(matches!(
+        errors.0[1].problem,
+        AddressProblem::Duplicate { first: 0, .. }
+    ));
+    assert!(matches!(errors.0[2].problem, AddressProblem::Invalid(_)));
+}
+
+#[test]
+fn payroll_templates_refuse_shared_addresses() {
+    let recipients: Vec<String> = (0..POOL_USERS)
+        .map(|i| {
+            format!(
+                r#"    {{ "name": "user {}", "amount": 11000, "address": "{}" }}"#,
+                i,
+                address(i.min(POOL_USERS - 2), Network::Regtest)
+            )
+        })
+        .collect();
+    let text = format!(
+        "{{\n  \"start_height\": 200,\n  \"epoch_blocks\": 10,\n  \"epochs\": 2,\n  \"recipients\": [\n{}\n  ]\n}}\n",
+        recipients.join(",\n")
+    );
+    let path = write("payroll", &text);
+    let error = PayrollTemplate::load(&path, Network::Regtest).unwrap_err();
+    let errors = error.downcast_ref::<AddressErrors>().unwrap();
+    assert_eq!(errors.0.len(), 1);
+    // the last two recipients share an address, the last one is on the line before the closing bracket
+    assert_eq!(errors.0[0].entry, POOL_USERS - 1);
+    assert_eq!(errors.0[0].line, Some(POOL_USERS + 5));
+
+    assert!(PayrollTemplate::load(&path, Network::Testnet).is_err());
+    fs::remove_file(&path).unwrap();
+}
+
+// BIP-86's vector: the account key of m/86'/0'/0' of the "abandon ... about" mnemonic
+const BIP86_ACCOUNT_XPUB: &str = "xpub6BgBgsespWvERF3LHQu6CnqdvfEvtMcQjYrcRzx53QJjSxarj2afYWcLteoGVky7D3UKDP9QyrLprQ3VCECoY49yfdDEHGCtMMj92pReUsQ";
+
+#[test]
+fn xpub_children_are_the_bip86_addresses() {
+    let secp = Secp256k1::verification_only();
+    let account = Xpub::from_str(BIP86_ACCOUNT_XPUB).unwrap();
+    // a wallet registers its receive chain, m/86'/0'/0'/0
+    let receive = account
+        .derive_pub(&secp, &[ChildNumber::from_normal_idx(0).unwrap()])
+        .unwrap();
+    assert_eq!(
+        xpub_address(&receive, 0, Network::Bitcoin)
+            .unwrap()
+            .to_string(),
+        "bc1p5cyxnuxmeuwuvkwfem96lqzszd02n6xdcjrs20cac6yqjjwudpxqkedrcr"
+    );
+    assert_eq!(
+        xpub_address(&receive, 1, Network::Bitcoin)
+            .unwrap()
+            .to_string(),
+        "bc1p4qhjn9zdvkux4e44uhx8tc55attvtyu358kutcqkudyccelu0was9fqzwh"
+    );
+    let change = account
+        .derive_pub(&secp, &[ChildNumber::from_normal_idx(1).unwrap()])
+        .unwrap();
+    assert_eq!(
+        xpub_address(&change, 0, Network::Bitcoin)
+            .unwrap()
+            .to_string(),
+        "bc1p3qkhfews2uk44qtvauqyr2ttdsw7svhkl9nkm9s9c3x4ax5h60wqwruhk7"
+    );
+
+    // a mainnet xpub pays no other network
+    assert!(xpub_address(&receive, 0, Network::Regtest).is_err());
+}
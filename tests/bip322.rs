@@ -0,0 +1,103 @@
+use bitcoin::{
+    base64::{engine::general_purpose::STANDARD, Engine},
+    consensus,
+    hex::DisplayHex,
+    Address, Network, Witness,
+};
+
+use op_ctv_payment_pool::bip322::{message_hash, verify_simple};
+
+mod common;
+
+use common::{address, keypair, sign_p2tr};
+
+// the p2wpkh key and signatures of BIP-322's test vectors
+const VECTOR_ADDRESS: &str = "bc1q9vza2e8x573nczrlzms0wvx3gsqjx7vavgkx0l";
+const VECTOR_EMPTY: &str = "AkcwRAIgM2gBAQqvZX15ZiysmKmQpDrG83avLIT492QBzLnQIxYCIBaTpOaD20qRlEylyxFSeEA2ba9YOixpX8z46TSDtS40ASECx/EgAxlkQpQ9hYjgGu6EBCPMVPwVIVJqO4XCsMvViHI=";
+const VECTOR_HELLO: &str = "AkcwRAIgZRfIY3p7/DoVTty6YZbWS71bc5Vct9p9Fia83eRmw2QCICK/ENGfwLtptFluMGs2KsqoNSk89pO7F29zJLUx9a/sASECx/EgAxlkQpQ9hYjgGu6EBCPMVPwVIVJqO4XCsMvViHI=";
+
+fn vector_address() -> Address {
+    VECTOR_ADDRESS
+        .parse::<Address<_>>()
+        .unwrap()
+        .require_network(Network::Bitcoin)
+        .unwrap()
+}
+
+#[test]
+fn message_hashes_match_the_bip() {
+    assert_eq!(
+        message_hash(b"").to_lower_hex_string(),
+        "c90c269c4f8fcbe6880f72a721ddfbf1914268a794cbb21cfafee13770ae19f1"
+    );
+    assert_eq!(
+        message_hash(b"Hello World").to_lower_hex_string(),
+        "f0eb03b1a75ac6d9847f55c624a99169b5dccba2a31f5b23bea77ba270de0a7a"
+    );
+}
+
+#[test]
+fn the_bips_p2wpkh_signatures_verify() {
+    let address = vector_address();
+    verify_simple(&address, "", VECTOR_EMPTY).unwrap();
+    verify_simple(&address, "Hello World", VECTOR_HELLO).unwrap();
+    // each one only for its own message
+    assert!(verify_simple(&address, "Hello World", VECTOR_EMPTY).is_err());
+}
+
+#[test]
+fn a_p2tr_key_path_signature_round_trips() {
+    let signature = sign_p2tr(2, "register me");
+    verify_simple(&address(2, Network::Regtest), "register me", &signature).unwrap();
+    assert!(verify_simple(&address(2, Network::Regtest), "register you", &signature).is_err());
+}
+
+#[test]
+fn a_tampered_signature_is_refused() {
+    let mut witness: Witness =
+        consensus::deserialize(&STANDARD.decode(sign_p2tr(2, "register me")).unwrap()).unwrap();
+    let mut signature = witness.to_vec().remove(0);
+    signature[10] ^= 1;
+    witness = Witness::from_slice(&[signature]);
+    let tampered = STANDARD.encode(consensus::serialize(&witness));
+    let error = verify_simple(&address(2, Network::Regtest), "register me", &tampered).unwrap_err();
+    assert!(error.to_string().contains("invalid signature"), "{}", error);
+
+    let error = verify_simple(
+        &vector_address(),
+        "Hello World",
+        &VECTOR_HELLO.replace("ZRfI", "ZRfJ"),
+    )
+    .unwrap_err();
+    assert!(error.to_string().contains("invalid signature"), "{}", error);
+}
+
+#[test]
+fn a_signature_under_another_address_is_refused() {
+    let signature = sign_p2tr(2, "register me");
+    let error =
+        verify_simple(&address(3, Network::Regtest), "register me", &signature).unwrap_err();
+    assert!(error.to_string().contains("invalid signature"), "{}", error);
+
+    // the vector's key isn't the one of another p2wpkh address
+    let other = Address::p2wpkh(
+        &bitcoin::CompressedPublicKey(keypair(3).public_key()),
+        Network::Bitcoin,
+    );
+    let error = verify_simple(&other, "Hello World", VECTOR_HELLO).unwrap_err();
+    assert!(error.to_string().contains("does not belong"), "{}", error);
+}
+
+#[test]
+fn only_single_key_segwit_addresses_are_supported() {
+    let legacy = Address::p2pkh(
+        bitcoin::CompressedPublicKey(keypair(3).public_key()),
+        Network::Bitcoin,
+    );
+    let error = verify_simple(&legacy, "Hello World", VECTOR_HELLO).unwrap_err();
+    assert!(
+        error.to_string().contains("not a p2tr or p2wpkh"),
+        "{}",
+        error
+    );
+}
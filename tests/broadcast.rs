@@ -0,0 +1,68 @@
+use bitcoincore_rpc::jsonrpc;
+
+use op_ctv_payment_pool::broadcast::{classify, BroadcastError, BroadcastKind, RejectReason};
+
+#[test]
+fn reject_strings_are_decoded() {
+    let cases = [
+        (
+            "bad-txns-inputs-missingorspent",
+            RejectReason::MissingInputs,
+        ),
+        ("missing-inputs", RejectReason::MissingInputs),
+        ("txn-mempool-conflict", RejectReason::MempoolConflict),
+        ("min relay fee not met, 0 < 153", RejectReason::MinRelayFee),
+        (
+            "mempool min fee not met, 100 < 400",
+            RejectReason::MinRelayFee,
+        ),
+        ("non-BIP68-final", RejectReason::NonFinal),
+        (
+            "Transaction already in block chain",
+            RejectReason::AlreadyKnown,
+        ),
+        (
+            "non-mandatory-script-verify-flag (Witness program hash mismatch)",
+            RejectReason::ScriptVerify("Witness program hash mismatch".to_string()),
+        ),
+        (
+            "mandatory-script-verify-flag-failed (Script failed an OP_EQUALVERIFY operation)",
+            RejectReason::ScriptVerify("Script failed an OP_EQUALVERIFY operation".to_string()),
+        ),
+        ("dust", RejectReason::Other("dust".to_string())),
+    ];
+    for (message, reason) in cases {
+        assert_eq!(RejectReason::parse(message), reason, "{}", message);
+    }
+}
+
+#[test]
+fn rpc_rejects_become_typed_errors_with_pool_hints() {
+    let rpc_error =
+        bitcoincore_rpc::Error::JsonRpc(jsonrpc::Error::Rpc(jsonrpc::error::RpcError {
+            code: -25,
+            message: "bad-txns-inputs-missingorspent".to_string(),
+            data: None,
+        }));
+    let error = classify(rpc_error.into(), BroadcastKind::PoolSpend);
+
+    let broadcast = error.downcast_ref::<BroadcastError>().unwrap();
+    assert_eq!(broadcast.reason, RejectReason::MissingInputs);
+    assert_eq!(broadcast.code, -25);
+    assert!(error
+        .to_string()
+        .contains("parent pool spend not yet broadcast"));
+
+    // the same reject says something else about a funding tx
+    assert!(!RejectReason::MissingInputs
+        .hint(BroadcastKind::Funding)
+        .unwrap()
+        .contains("pool spend"));
+
+    // errors that aren't rejects are passed on as they are
+    let error = classify(
+        anyhow::anyhow!("connection refused"),
+        BroadcastKind::PoolSpend,
+    );
+    assert!(error.downcast_ref::<BroadcastError>().is_none());
+}
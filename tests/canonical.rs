@@ -0,0 +1,66 @@
+use serde_json::json;
+use std::fs;
+
+use op_ctv_payment_pool::{
+    canonical::{canonical_json, manifest_hash},
+    config::NetworkConfig,
+    manifest::PoolManifest,
+    profile::NetworkProfile,
+};
+
+mod common;
+
+use common::pool_manifest;
+
+fn manifest() -> PoolManifest {
+    pool_manifest(&NetworkConfig::new(NetworkProfile::RegtestLocal))
+}
+
+#[test]
+fn keys_are_sorted_and_nothing_else_is_added() {
+    let value = json!({
+        "b": [1, "a\nb", null, {"z": true, "y": -2}],
+        "a": {},
+        "\u{e000}": 0,
+        "\u{1f600}": 0,
+    });
+    // the emoji is a surrogate pair in UTF-16, so it sorts before U+E000 unlike in UTF-8
+    assert_eq!(
+        canonical_json(&value).unwrap(),
+        "{\"a\":{},\"b\":[1,\"a\\nb\",null,{\"y\":-2,\"z\":true}],\"\u{1f600}\":0,\"\u{e000}\":0}"
+    );
+}
+
+#[test]
+fn numbers_a_double_cant_hold_are_refused() {
+    assert!(canonical_json(&json!({ "fee": 1.5 })).is_err());
+    assert!(canonical_json(&json!([1u64 << 53])).is_err());
+    assert!(canonical_json(&json!([(1u64 << 53) - 1])).is_ok());
+}
+
+#[test]
+fn the_manifest_hash_is_the_same_in_every_format() {
+    let manifest = manifest();
+    let hash = manifest_hash(&manifest).unwrap();
+
+    let dir = std::env::temp_dir().join(format!("pool-canonical-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    for file in ["pool_manifest.json", "pool_manifest.ctvpool"] {
+        let path = dir.join(file);
+        manifest.write(&path).unwrap();
+        assert_eq!(
+            manifest_hash(&PoolManifest::load(&path).unwrap()).unwrap(),
+            hash
+        );
+    }
+    fs::remove_dir_all(dir).unwrap();
+
+    // read back from its own canonical form
+    let canonical = canonical_json(&manifest).unwrap();
+    let reparsed: PoolManifest = serde_json::from_str(&canonical).unwrap();
+    assert_eq!(canonical_json(&reparsed).unwrap(), canonical);
+
+    let mut changed = manifest.clone();
+    changed.close_all_leaf = !changed.close_all_leaf;
+    assert_ne!(manifest_hash(&changed).unwrap(), hash);
+}
@@ -0,0 +1,174 @@
+use bitcoin::{
+    absolute, hashes::Hash, transaction, Address, Amount, BlockHash, Network, OutPoint,
+    Transaction, TxIn, TxOut, Txid,
+};
+
+use op_ctv_payment_pool::{
+    checkpoints::{prune_reorged, record_checkpoint, unspent_nodes, Checkpoint},
+    config::NetworkConfig,
+    covenant::CtvBackend,
+    fetch::RpcFetcher,
+    ids::{NodePath, UserIndex},
+    manifest::PoolManifest,
+    mock::MockBackend,
+    pools::build_pools,
+    profile::NetworkProfile,
+    state::{decode_state, encode_state, STATE_VERSION},
+    tree::PoolTree,
+};
+
+mod common;
+
+use common::{addresses, anchor, backend};
+
+fn setup() -> (NetworkConfig, CtvBackend, Address, PoolTree) {
+    let config = NetworkConfig::new(NetworkProfile::RegtestLocal);
+    let backend = backend(&config);
+    let anchor_addr = anchor(&config);
+    let tree = build_pools(&addresses(config.network), &anchor_addr, &config, &backend).unwrap();
+    (config, backend, anchor_addr, tree)
+}
+
+// a tx spending `spent` and paying `outputs`
+fn tx(spent: OutPoint, outputs: Vec<TxOut>) -> Transaction {
+    Transaction {
+        version: transaction::Version(2),
+        lock_time: absolute::LockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: spent,
+            ..Default::default()
+        }],
+        output: outputs,
+    }
+}
+
+fn block(height: u64) -> BlockHash {
+    BlockHash::from_byte_array([height as u8; 32])
+}
+
+#[test]
+fn checkpoints_track_which_nodes_are_left() {
+    let (config, _, anchor_addr, tree) = setup();
+    let nodes = tree.node_scripts(&config);
+    let root = NodePath::root();
+    let next = root.without(UserIndex::new(0).unwrap()).unwrap();
+
+    let funding = tx(
+        OutPoint::new(Txid::all_zeros(), 0),
+        vec![TxOut {
+            value: Amount::from_sat(110_000),
+            script_pubkey: tree.root().unwrap().address(&config).script_pubkey(),
+        }],
+    );
+    let funding_checkpoint = Checkpoint::of_tx(&funding, None, 101, block(101), &nodes);
+    let root_outpoint = OutPoint::new(funding.compute_txid(), 0);
+    assert_eq!(
+        funding_checkpoint.nodes,
+        vec![(root_outpoint, root.clone())]
+    );
+
+    // user 0 leaves: their payout, the rest of the pool and the anchor
+    let exit = tx(
+        root_outpoint,
+        vec![
+            TxOut {
+                value: Amount::from_sat(6_000),
+                script_pubkey: addresses(config.network)[0].script_pubkey(),
+            },
+            TxOut {
+                value: Amount::from_sat(99_000),
+                script_pubkey: tree.node(&next).unwrap().address(&config).script_pubkey(),
+            },
+            TxOut {
+                value: Amount::from_sat(5_000),
+                script_pubkey: anchor_addr.script_pubkey(),
+            },
+        ],
+    );
+    let exit_checkpoint = Checkpoint::of_tx(&exit, Some(root_outpoint), 105, block(105), &nodes);
+    let next_outpoint = OutPoint::new(exit.compute_txid(), 1);
+    assert_eq!(exit_checkpoint.nodes, vec![(next_outpoint, next.clone())]);
+
+    let mut checkpoints = Vec::new();
+    // recorded out of order, kept in height order and only once
+    assert!(record_checkpoint(&mut checkpoints, exit_checkpoint.clone()));
+    assert!(record_checkpoint(
+        &mut checkpoints,
+        funding_checkpoint.clone()
+    ));
+    assert!(!record_checkpoint(&mut checkpoints, exit_checkpoint));
+    assert_eq!(
+        checkpoints.iter().map(|c| c.height).collect::<Vec<_>>(),
+        vec![101, 105]
+    );
+
+    let unspent = unspent_nodes(&checkpoints);
+    assert_eq!(unspent.len(), 1);
+    assert_eq!(unspent[&next_outpoint], next);
+    assert_eq!(
+        unspent_nodes(&checkpoints[..1])[&root_outpoint],
+        NodePath::root()
+    );
+}
+
+#[test]
+fn checkpoints_survive_the_binary_state() {
+    let (config, backend, anchor_addr, tree) = setup();
+    let root = tree.root().unwrap().address(&config);
+    let mut manifest = PoolManifest::new(
+        &config,
+        &backend,
+        &anchor_addr,
+        &addresses(config.network),
+        &root,
+    );
+    manifest.checkpoints.push(Checkpoint {
+        txid: Txid::all_zeros(),
+        height: 101,
+        block_hash: block(101),
+        spent: None,
+        nodes: vec![(OutPoint::new(Txid::all_zeros(), 0), NodePath::root())],
+    });
+
+    let (decoded, version) = decode_state(&encode_state(&manifest).unwrap()).unwrap();
+    assert_eq!(version, STATE_VERSION);
+    assert_eq!(decoded.checkpoints, manifest.checkpoints);
+}
+
+#[tokio::test]
+async fn checkpoints_a_reorg_took_out_are_dropped() {
+    let mock = MockBackend::new(Network::Regtest);
+    let hashes = mock.mine(10);
+    let rpc = mock.rpc();
+    let fetcher = RpcFetcher::new(vec![rpc.clone()], 2).unwrap();
+    let checkpoint = |height: u64, block_hash: BlockHash| Checkpoint {
+        txid: Txid::from_byte_array([height as u8; 32]),
+        height,
+        block_hash,
+        spent: None,
+        nodes: Vec::new(),
+    };
+    let mut checkpoints = vec![
+        checkpoint(2, hashes[1]),
+        checkpoint(5, hashes[4]),
+        // a block of another branch at a height the chain has, then one past the tip
+        checkpoint(8, block(8)),
+        checkpoint(12, block(12)),
+    ];
+    assert_eq!(
+        prune_reorged(&rpc, &fetcher, &mut checkpoints)
+            .await
+            .unwrap(),
+        2
+    );
+    assert_eq!(checkpoints.len(), 2);
+    assert_eq!(checkpoints[1].block_hash, hashes[4]);
+
+    // nothing left to drop
+    assert_eq!(
+        prune_reorged(&rpc, &fetcher, &mut checkpoints)
+            .await
+            .unwrap(),
+        0
+    );
+}
@@ -0,0 +1,21 @@
+use clap::CommandFactory;
+use clap_complete::Shell;
+
+use op_ctv_payment_pool::cli::{write_completions, Cli};
+
+#[test]
+fn cli_definition_is_consistent() {
+    Cli::command().debug_assert();
+}
+
+#[test]
+fn completions_cover_every_command() {
+    for shell in [Shell::Bash, Shell::Zsh, Shell::Fish] {
+        let mut script = Vec::new();
+        write_completions(shell, &mut script);
+        let script = String::from_utf8(script).unwrap();
+        for command in ["watch", "close-all", "payroll-schedule", "completions"] {
+            assert!(script.contains(command), "{:?} misses {}", shell, command);
+        }
+    }
+}
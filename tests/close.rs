@@ -0,0 +1,99 @@
+use bitcoin::{hashes::Hash, Amount, Txid};
+
+use op_ctv_payment_pool::{
+    close::settle,
+    ids::{NodePath, UserIndex},
+    lifecycle::Lifecycle,
+    report::LedgerEntry,
+    POOL_USERS,
+};
+
+fn entry(kind: &'static str, user: Option<UserIndex>, sats: u64) -> LedgerEntry {
+    LedgerEntry {
+        kind,
+        level: None,
+        user,
+        address: None,
+        amount: Amount::from_sat(sats),
+        txid: Txid::all_zeros(),
+        vout: Some(0),
+        block_height: None,
+        block_time: None,
+    }
+}
+
+// every user paid 9_000 with a 500 sat fee and a 500 sat anchor per pool tx
+fn unwound() -> Vec<LedgerEntry> {
+    let txs = POOL_USERS as u64 - 1;
+    let mut entries = vec![
+        entry("funding_input", None, 50_000_000),
+        entry(
+            "pool_deposit",
+            None,
+            POOL_USERS as u64 * 9_000 + txs * 1_000,
+        ),
+        entry("funding_change", None, 1_000_000),
+        entry("funding_fee", None, 2_000),
+    ];
+    for user in UserIndex::all() {
+        entries.push(entry("payout", Some(user), 9_000));
+    }
+    for _ in 0..txs {
+        entries.push(entry("pool_fee", None, 500));
+        entries.push(entry("anchor", None, 500));
+        entries.push(entry("cpfp_fee", None, 300));
+    }
+    entries
+}
+
+#[test]
+fn a_fully_paid_pool_settles() {
+    let settlement = settle(&unwound(), &NodePath::root()).unwrap();
+    assert_eq!(settlement.lifecycle, Lifecycle::Closed);
+    assert_eq!(settlement.users_paid.len(), POOL_USERS);
+    assert_eq!(
+        settlement.paid_out,
+        Amount::from_sat(POOL_USERS as u64 * 9_000)
+    );
+    assert_eq!(settlement.swept, Amount::ZERO);
+}
+
+#[test]
+fn a_recovery_sweep_settles_the_users_left() {
+    // the last three users never left, the sweep took their share
+    let mut entries: Vec<LedgerEntry> = unwound()
+        .into_iter()
+        .filter(|entry| entry.user.is_none_or(|user| user.index() < POOL_USERS - 3))
+        .collect();
+    entries.push(entry("recovery_sweep", None, 3 * 9_000));
+    let settlement = settle(&entries, &NodePath::root()).unwrap();
+    assert_eq!(settlement.lifecycle, Lifecycle::Recovered);
+    assert_eq!(settlement.users_paid.len(), POOL_USERS - 3);
+}
+
+#[test]
+fn unfinished_or_unbalanced_pools_are_refused() {
+    let mut left = unwound();
+    left.retain(|entry| entry.user != UserIndex::new(POOL_USERS - 1).ok());
+    left.push(entry("in_pool", None, 9_000));
+    let error = settle(&left, &NodePath::root()).unwrap_err();
+    assert!(error.to_string().contains("still in the pool"), "{}", error);
+
+    let mut unpaid = unwound();
+    unpaid.retain(|entry| entry.user != UserIndex::new(4).ok());
+    assert!(settle(&unpaid, &NodePath::root()).is_err());
+
+    let mut unexpected = unwound();
+    unexpected.push(entry("unexpected_spend", None, 9_000));
+    assert!(settle(&unexpected, &NodePath::root()).is_err());
+
+    // a missing pool fee leaves the deposit unaccounted for
+    let mut short = unwound();
+    let fee = short
+        .iter()
+        .position(|entry| entry.kind == "pool_fee")
+        .unwrap();
+    short.remove(fee);
+    let error = settle(&short, &NodePath::root()).unwrap_err();
+    assert!(error.to_string().contains("deposited"), "{}", error);
+}
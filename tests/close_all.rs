@@ -0,0 +1,145 @@
+use bitcoin::{
+    absolute, hashes::Hash, taproot::LeafVersion, transaction, OutPoint, ScriptBuf, Transaction,
+    TxIn, TxOut, Txid,
+};
+
+use op_ctv_payment_pool::{
+    config::{NetworkConfig, FEE_AMOUNT},
+    costs::pool_costs,
+    covenant::CovenantBackend,
+    ctv_scripts::{calc_ctv_hash, close_all_fee_share},
+    ids::UserIndex,
+    lifecycle::{Event, Lifecycle},
+    pools::{build_pools, close_all_template},
+    profile::NetworkProfile,
+    AMOUNT_PER_USER, POOL_USERS,
+};
+
+mod common;
+
+use common::{addresses, anchor, backend};
+
+fn config(close_all_leaf: bool) -> NetworkConfig {
+    let mut config = NetworkConfig::new(NetworkProfile::RegtestLocal);
+    config.close_all_leaf = close_all_leaf;
+    config
+}
+
+#[test]
+fn close_all_leaf_pays_every_user_in_one_tx() {
+    let config = config(true);
+    let backend = backend(&config);
+    let addresses = addresses(config.network);
+    let anchor_addr = anchor(&config);
+    let tree = build_pools(&addresses, &anchor_addr, &config, &backend).unwrap();
+    let root = tree.root().unwrap();
+    assert!(root.close_all.is_some());
+
+    let funding_tx = Transaction {
+        version: transaction::Version(2),
+        lock_time: absolute::LockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: OutPoint::new(Txid::all_zeros(), 0),
+            ..Default::default()
+        }],
+        output: vec![TxOut {
+            value: AMOUNT_PER_USER * POOL_USERS as u64,
+            script_pubkey: ScriptBuf::new_p2tr_tweaked(root.spend_info.output_key()),
+        }],
+    };
+    let template = close_all_template(
+        &tree,
+        &config,
+        &backend,
+        &addresses,
+        &funding_tx,
+        &anchor_addr,
+    )
+    .unwrap();
+
+    // every user, then the anchor
+    assert_eq!(template.tx.output.len(), POOL_USERS + 1);
+    for (output, address) in template.tx.output.iter().zip(&addresses) {
+        assert_eq!(output.script_pubkey, address.script_pubkey());
+        assert_eq!(
+            output.value,
+            AMOUNT_PER_USER - close_all_fee_share(FEE_AMOUNT, POOL_USERS)
+        );
+    }
+    assert!(close_all_fee_share(FEE_AMOUNT, POOL_USERS) * POOL_USERS as u64 >= FEE_AMOUNT);
+
+    let sequences: Vec<_> = template
+        .tx
+        .input
+        .iter()
+        .map(|input| input.sequence)
+        .collect();
+    assert_eq!(
+        calc_ctv_hash(template.tx.version.0, &template.tx.output, &sequences),
+        template.template_hash
+    );
+    assert_eq!(Some(template.template_hash), root.close_all);
+    let leaf = backend.leaf_script(template.template_hash);
+    assert!(root
+        .spend_info
+        .control_block(&(leaf, LeafVersion::TapScript))
+        .is_some());
+}
+
+#[test]
+fn pools_without_the_leaf_keep_their_root() {
+    let backend = |config: &NetworkConfig| backend(config);
+    let roots: Vec<_> = [false, true]
+        .into_iter()
+        .map(|close_all_leaf| {
+            let config = config(close_all_leaf);
+            let anchor_addr = anchor(&config);
+            let tree = build_pools(
+                &addresses(config.network),
+                &anchor_addr,
+                &config,
+                &backend(&config),
+            )
+            .unwrap();
+            let root = tree.root().unwrap().clone();
+            // only the root has it
+            assert!(tree
+                .iter_nodes()
+                .skip(1)
+                .all(|(_, node)| node.close_all.is_none()));
+            root
+        })
+        .collect();
+    assert!(roots[0].close_all.is_none());
+    assert_ne!(
+        roots[0].spend_info.output_key(),
+        roots[1].spend_info.output_key()
+    );
+}
+
+#[test]
+fn close_all_only_from_a_funded_root() {
+    assert_eq!(
+        Lifecycle::Funded.apply(Event::CloseAll).unwrap(),
+        Lifecycle::Closed
+    );
+    assert!(Lifecycle::Registered.apply(Event::CloseAll).is_err());
+    let after_one = Lifecycle::Funded
+        .apply(Event::Withdraw(UserIndex::new(0).unwrap()))
+        .unwrap();
+    assert!(after_one.apply(Event::CloseAll).is_err());
+}
+
+#[test]
+fn costs_compare_the_close_all_with_the_full_unwind() {
+    let costs = pool_costs(&config(true), 5);
+    assert!(costs.close_all.enabled);
+    assert!(costs.close_all.committed_fee < costs.committed_fees);
+    assert_eq!(
+        costs.close_all.savings,
+        costs.committed_fees + costs.anchor_budget
+            - costs.close_all.committed_fee
+            - costs.close_all.anchor_budget
+    );
+    assert!(!pool_costs(&config(false), 5).close_all.enabled);
+}
@@ -0,0 +1,147 @@
+use bitcoin::{consensus::encode::serialize_hex, Amount, Psbt, TxOut};
+use std::{fs, str::FromStr};
+
+use op_ctv_payment_pool::{
+    cold::{broadcast_psbt, export_psbts, finalize_pool_psbt, template_psbt},
+    config::NetworkConfig,
+    fund::{read_psbt, required_funding},
+    ids::UserIndex,
+    manifest::LoadedPool,
+    mock::MockBackend,
+    pools::pool_spend_template,
+    profile::NetworkProfile,
+    psbt_v2::PsbtVersion,
+    POOL_USERS,
+};
+
+mod common;
+
+use common::{address, loaded_pool};
+
+fn pool(close_all_leaf: bool) -> LoadedPool {
+    let mut config = NetworkConfig::new(NetworkProfile::RegtestLocal);
+    config.close_all_leaf = close_all_leaf;
+    loaded_pool(&config)
+}
+
+// the pool's funding output, in the mock's mempool
+fn fund(mock: &MockBackend, pool: &LoadedPool) -> bitcoin::Transaction {
+    let outpoint = mock.add_output(TxOut {
+        value: required_funding(),
+        script_pubkey: pool
+            .tree
+            .root()
+            .unwrap()
+            .address(&pool.config)
+            .script_pubkey(),
+    });
+    mock.transaction(outpoint.txid).unwrap()
+}
+
+fn temp_dir(name: &str) -> std::path::PathBuf {
+    std::env::temp_dir().join(format!("pool-psbts-{}-{}", name, std::process::id()))
+}
+
+#[test]
+fn finalized_psbts_are_the_spends_the_pool_would_broadcast() {
+    let pool = pool(true);
+    let mock = MockBackend::new(pool.config.network);
+    let funding_tx = fund(&mock, &pool);
+    let dir = temp_dir("export");
+    let spends = export_psbts(&pool, &funding_tx, &dir, PsbtVersion::V2).unwrap();
+    // the planned unwind and the close-all spend
+    assert_eq!(spends.len(), POOL_USERS);
+    assert!(spends[POOL_USERS - 1].spender.is_none());
+
+    let mut previous = funding_tx.clone();
+    for (user, spend) in UserIndex::all().zip(&spends).take(POOL_USERS - 1) {
+        let mut psbt = read_psbt(&spend.file).unwrap();
+        let input = &psbt.inputs[0];
+        assert_eq!(input.tap_scripts.len(), 1);
+        assert!(input.tap_internal_key.is_some());
+        assert!(input.tap_merkle_root.is_some());
+        assert_eq!(
+            psbt.unsigned_tx.input[0].previous_output.txid,
+            previous.compute_txid()
+        );
+
+        finalize_pool_psbt(&mut psbt).unwrap();
+        assert!(psbt.inputs[0].tap_scripts.is_empty());
+        let template = pool_spend_template(
+            &pool.tree,
+            &pool.config,
+            pool.backend.as_ref(),
+            user,
+            &pool.addresses,
+            &previous,
+            &pool.anchor_addr,
+        )
+        .unwrap();
+        let expected = pool.backend.finalize(template).unwrap();
+        let tx = psbt.extract_tx().unwrap();
+        assert_eq!(serialize_hex(&tx), serialize_hex(&expected));
+        assert_eq!(tx.compute_txid(), spend.txid);
+        previous = tx;
+    }
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn the_finalizer_only_takes_the_template() {
+    let pool = pool(false);
+    let mock = MockBackend::new(pool.config.network);
+    let funding_tx = fund(&mock, &pool);
+    let template = pool_spend_template(
+        &pool.tree,
+        &pool.config,
+        pool.backend.as_ref(),
+        UserIndex::new(0).unwrap(),
+        &pool.addresses,
+        &funding_tx,
+        &pool.anchor_addr,
+    )
+    .unwrap();
+    let psbt = template_psbt(&template, pool.backend.leaf_script(template.template_hash)).unwrap();
+
+    // an output moved
+    let mut moved = psbt.clone();
+    moved.unsigned_tx.output[1].value += Amount::from_sat(1);
+    assert!(finalize_pool_psbt(&mut moved).is_err());
+
+    // the leaf is put on an output it isn't in
+    let mut elsewhere = psbt.clone();
+    elsewhere.inputs[0]
+        .witness_utxo
+        .as_mut()
+        .unwrap()
+        .script_pubkey = address(40, pool.config.network).script_pubkey();
+    assert!(finalize_pool_psbt(&mut elsewhere).is_err());
+
+    let mut bare = psbt.clone();
+    bare.inputs[0].proprietary.clear();
+    assert!(finalize_pool_psbt(&mut bare).is_err());
+
+    // round trips through base64 like a wallet would hand it back
+    let mut through_base64 = Psbt::from_str(&psbt.to_string()).unwrap();
+    finalize_pool_psbt(&mut through_base64).unwrap();
+}
+
+#[tokio::test]
+async fn the_online_machine_only_broadcasts_finalized_psbts() {
+    let pool = pool(false);
+    let mock = MockBackend::new(pool.config.network);
+    mock.set_relay_fee(Amount::ZERO);
+    let rpc = mock.rpc();
+    let funding_tx = fund(&mock, &pool);
+    mock.mine(1);
+    let dir = temp_dir("broadcast");
+    let spends = export_psbts(&pool, &funding_tx, &dir, PsbtVersion::V2).unwrap();
+
+    let mut psbt = read_psbt(&spends[0].file).unwrap();
+    assert!(broadcast_psbt(&rpc, psbt.clone()).await.is_err());
+    finalize_pool_psbt(&mut psbt).unwrap();
+    let txid = broadcast_psbt(&rpc, psbt).await.unwrap();
+    assert_eq!(txid, spends[0].txid);
+    assert_eq!(mock.mempool(), vec![txid]);
+    fs::remove_dir_all(dir).unwrap();
+}
@@ -0,0 +1,95 @@
+use bitcoin::{Address, Network};
+use std::str::FromStr;
+
+use op_ctv_payment_pool::{
+    config::NetworkConfig,
+    manifest::{CommittedScripts, PoolManifest},
+    pools::build_pools,
+    profile::NetworkProfile,
+    recovery::RecoveryPath,
+    state::{decode_state, encode_state},
+    POOL_USERS,
+};
+
+mod common;
+
+use common::{address, addresses, anchor, backend};
+
+fn build_manifest(config: &NetworkConfig) -> PoolManifest {
+    let backend = backend(config);
+    let addresses = addresses(config.network);
+    let anchor_addr = anchor(config);
+    let tree = build_pools(&addresses, &anchor_addr, config, &backend).unwrap();
+    let root = tree.root().unwrap().address(config);
+    PoolManifest::new(config, &backend, &anchor_addr, &addresses, &root)
+}
+
+#[test]
+fn a_moved_anchor_is_refused_on_load() {
+    let config = NetworkConfig::new(NetworkProfile::RegtestLocal);
+    let manifest = build_manifest(&config);
+    assert_eq!(
+        manifest.committed_scripts.anchor,
+        Address::from_str(&config.fee_anchor_addr)
+            .unwrap()
+            .assume_checked()
+            .script_pubkey()
+    );
+    assert_eq!(manifest.committed_scripts.withdraw.len(), POOL_USERS);
+
+    let mut drifted = NetworkConfig::new(NetworkProfile::RegtestLocal);
+    drifted.fee_anchor_addr = address(99, Network::Regtest).to_string();
+    let error = manifest.rebuild_pool(drifted.clone()).err().unwrap();
+    assert!(
+        error.to_string().contains("POOL_FEE_ANCHOR_ADDR"),
+        "{}",
+        error
+    );
+
+    // without anchors the templates pay no anchor, the config's is replaced by the manifest's
+    let mut config = config;
+    config.anchor_amount = None;
+    let anchorless = build_manifest(&config);
+    let pool = anchorless.rebuild_pool(drifted).unwrap();
+    assert_eq!(pool.config.fee_anchor_addr, anchorless.anchor_address);
+}
+
+#[test]
+fn edited_addresses_no_longer_match_the_scripts() {
+    let config = NetworkConfig::new(NetworkProfile::RegtestLocal);
+    let mut manifest = build_manifest(&config);
+    manifest.withdraw_addresses[3] = address(199, Network::Regtest).to_string();
+    let error = manifest.load_pool().err().unwrap();
+    assert!(
+        error.to_string().contains("edited or is corrupt"),
+        "{}",
+        error
+    );
+
+    // older manifests have only their addresses, the root still has to match
+    manifest.committed_scripts = CommittedScripts::default();
+    let error = manifest.load_pool().err().unwrap();
+    assert!(error.to_string().contains("does not match"), "{}", error);
+}
+
+#[test]
+fn the_recovery_address_is_checked_against_the_config() {
+    let mut config = NetworkConfig::new(NetworkProfile::RegtestLocal);
+    config.recovery = Some(RecoveryPath {
+        address: address(49, Network::Regtest),
+        timeout: 1000,
+    });
+    let manifest = build_manifest(&config);
+    assert!(manifest.committed_scripts.recovery.is_some());
+
+    let (decoded, _) = decode_state(&encode_state(&manifest).unwrap()).unwrap();
+    assert_eq!(decoded.committed_scripts, manifest.committed_scripts);
+    assert!(decoded.rebuild_pool(config.clone()).is_ok());
+
+    config.recovery = Some(RecoveryPath {
+        address: address(50, Network::Regtest),
+        timeout: 1000,
+    });
+    let error = decoded.rebuild_pool(config).err().unwrap();
+    assert!(error.to_string().contains("RECOVERY_ADDRESS"), "{}", error);
+}
@@ -0,0 +1,92 @@
+// Fixtures the integration tests share: the reference users and the pool a config builds for them.
+// Every test crate uses some of them only.
+#![allow(dead_code)]
+
+use bitcoin::{
+    base64::{engine::general_purpose::STANDARD, Engine},
+    consensus,
+    hashes::Hash,
+    key::{Keypair, Secp256k1, TapTweak},
+    secp256k1::{Message, SecretKey},
+    sighash::{Prevouts, SighashCache},
+    taproot, Address, Network, TapSighashType, Witness,
+};
+use std::str::FromStr;
+
+use op_ctv_payment_pool::{
+    bip322::{to_sign, to_spend},
+    config::NetworkConfig,
+    covenant::CtvBackend,
+    manifest::{LoadedPool, PoolManifest},
+    pools::build_pools,
+    template::Bip119Ctv,
+    POOL_USERS,
+};
+
+// the key of reference user `i`, also used for addresses outside the pool (splits, recovery)
+pub fn keypair(i: usize) -> Keypair {
+    let key = SecretKey::from_slice(&[i as u8 + 1; 32]).unwrap();
+    Keypair::from_secret_key(&Secp256k1::new(), &key)
+}
+
+// key path p2tr address of reference user `i`
+pub fn address(i: usize, network: Network) -> Address {
+    let (xonly, _) = keypair(i).x_only_public_key();
+    Address::p2tr(&Secp256k1::new(), xonly, None, network)
+}
+
+// the POOL_USERS reference users, in tree order
+pub fn addresses(network: Network) -> Vec<Address> {
+    (0..POOL_USERS).map(|i| address(i, network)).collect()
+}
+
+// the BIP-322 simple signature of `message` a wallet makes for reference user `i`'s address
+pub fn sign_p2tr(i: usize, message: &str) -> String {
+    let secp = Secp256k1::new();
+    let address = address(i, Network::Regtest);
+    let to_spend = to_spend(&address.script_pubkey(), message.as_bytes());
+    let unsigned = to_sign(&to_spend, Witness::new());
+    let sighash = SighashCache::new(&unsigned)
+        .taproot_key_spend_signature_hash(
+            0,
+            &Prevouts::All(&[&to_spend.output[0]]),
+            TapSighashType::Default,
+        )
+        .unwrap();
+    let tweaked = keypair(i).tap_tweak(&secp, None).to_keypair();
+    let signature = taproot::Signature {
+        signature: secp.sign_schnorr(&Message::from_digest(sighash.to_byte_array()), &tweaked),
+        sighash_type: TapSighashType::Default,
+    };
+    STANDARD.encode(consensus::serialize(&Witness::from_slice(&[
+        signature.to_vec()
+    ])))
+}
+
+pub fn backend(config: &NetworkConfig) -> CtvBackend {
+    CtvBackend::from(Bip119Ctv {
+        tx_version: config.tx_version,
+    })
+}
+
+pub fn anchor(config: &NetworkConfig) -> Address {
+    Address::from_str(&config.fee_anchor_addr)
+        .unwrap()
+        .require_network(config.network)
+        .unwrap()
+}
+
+// the manifest of the reference users' pool under `config`
+pub fn pool_manifest(config: &NetworkConfig) -> PoolManifest {
+    let backend = backend(config);
+    let addresses = addresses(config.network);
+    let anchor_addr = anchor(config);
+    let tree = build_pools(&addresses, &anchor_addr, config, &backend).unwrap();
+    let root = tree.root().unwrap().address(config);
+    PoolManifest::new(config, &backend, &anchor_addr, &addresses, &root)
+}
+
+// and that pool loaded back from it
+pub fn loaded_pool(config: &NetworkConfig) -> LoadedPool {
+    pool_manifest(config).load_pool().unwrap()
+}
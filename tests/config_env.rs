@@ -0,0 +1,72 @@
+// Env vars are process wide, so the bad values get a test binary of their own
+use std::env;
+
+use op_ctv_payment_pool::{
+    config::{ConfigProblem, NetworkConfig},
+    profile::NetworkProfile,
+};
+
+#[test]
+fn bad_env_values_are_config_problems() {
+    let bad = [
+        ("POOL_TX_VERSION", "two"),
+        ("UNWIND_LEVEL_DELAYS", "0,144,a day"),
+        ("POOL_FEE_POLICY", "whatever the node says"),
+        ("POOL_PROXY", "http://127.0.0.1:9050"),
+        ("RECOVERY_ADDRESS", "not an address"),
+        ("POOL_BUMP_AFTER_BLOCKS", "soon"),
+        ("POOL_FAUCET_URL", "https://signetfaucet.com"),
+        ("WEBHOOK_URL", "https://example.com/hook"),
+        // more than any share, somebody's exit is dust
+        ("POOL_PAYOUT_JITTER_SATS", "1000000000000"),
+    ];
+    for (var, value) in bad {
+        env::set_var(var, value);
+    }
+    env::remove_var("WEBHOOK_SECRET");
+
+    // nothing panics, the preset's values stay
+    let config = NetworkConfig::new(NetworkProfile::RegtestLocal);
+    let preset = NetworkProfile::RegtestLocal.preset();
+    assert_eq!(config.tx_version, preset.tx_version);
+    assert_eq!(config.level_delays, preset.level_delays);
+    assert!(config.recovery.is_none());
+
+    let errors = config.validate().unwrap_err();
+    let mut vars: Vec<&str> = errors
+        .0
+        .iter()
+        .filter_map(|problem| match problem {
+            ConfigProblem::InvalidEnv { var, .. } => Some(var.as_str()),
+            _ => None,
+        })
+        .collect();
+    vars.sort();
+    let mut expected = vec![
+        "POOL_TX_VERSION",
+        "UNWIND_LEVEL_DELAYS",
+        "POOL_FEE_POLICY",
+        "POOL_PROXY",
+        "RECOVERY_ADDRESS",
+        "POOL_BUMP_AFTER_BLOCKS",
+        "POOL_FAUCET_URL",
+        "WEBHOOK_SECRET",
+    ];
+    expected.sort();
+    assert_eq!(vars, expected, "{}", errors);
+    assert!(errors
+        .0
+        .iter()
+        .any(|problem| matches!(problem, ConfigProblem::ExitBelowDust { .. })));
+    assert!(errors
+        .to_string()
+        .contains("POOL_TX_VERSION has an invalid value: two"));
+
+    for (var, _) in bad {
+        env::remove_var(var);
+    }
+    assert_eq!(
+        NetworkConfig::new(NetworkProfile::RegtestLocal).validate(),
+        Ok(())
+    );
+}
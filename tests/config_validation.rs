@@ -0,0 +1,71 @@
+use bitcoin::Amount;
+use std::sync::Arc;
+
+use op_ctv_payment_pool::{
+    config::{ConfigErrors, ConfigProblem, NetworkConfig},
+    fee_policy::{AnchorOnly, FixedFee},
+    pools::build_pools,
+    profile::NetworkProfile,
+    POOL_USERS,
+};
+
+mod common;
+
+use common::{addresses, anchor, backend};
+
+#[test]
+fn the_presets_are_valid() {
+    for profile in [
+        NetworkProfile::RegtestLocal,
+        NetworkProfile::SignetPublic,
+        NetworkProfile::Inquisition,
+        NetworkProfile::Testnet4,
+    ] {
+        assert_eq!(
+            NetworkConfig::new(profile).validate(),
+            Ok(()),
+            "{:?}",
+            profile
+        );
+    }
+}
+
+#[test]
+fn every_problem_is_reported_at_once() {
+    let mut config = NetworkConfig::new(NetworkProfile::RegtestLocal);
+    config.anchor_amount = Some(Amount::from_sat(100));
+    config.fee_policy = Arc::new(FixedFee(Amount::from_sat(50)));
+    let errors = config.validate().unwrap_err();
+    assert!(matches!(
+        errors.0.as_slice(),
+        [
+            ConfigProblem::AnchorBelowDust { .. },
+            ConfigProblem::FeeBelowAnchor { .. }
+        ]
+    ));
+    assert!(errors.to_string().starts_with("2 config problems"));
+
+    // a fee leaving every user dust
+    config.anchor_amount = Some(Amount::from_sat(5_000));
+    config.fee_policy = Arc::new(FixedFee(Amount::from_sat(10_800)));
+    let errors = config.validate().unwrap_err();
+    assert_eq!(errors.0.len(), POOL_USERS);
+    assert!(errors
+        .0
+        .iter()
+        .all(|problem| matches!(problem, ConfigProblem::ExitBelowDust { .. })));
+}
+
+#[test]
+fn library_callers_get_the_problems_from_build_pools() {
+    let mut config = NetworkConfig::new(NetworkProfile::RegtestLocal);
+    config.anchor_amount = None;
+    config.fee_policy = Arc::new(AnchorOnly);
+
+    let addresses = addresses(config.network);
+    let anchor = anchor(&config);
+    let backend = backend(&config);
+    let error = build_pools(&addresses, &anchor, &config, &backend).unwrap_err();
+    let errors = error.downcast_ref::<ConfigErrors>().unwrap();
+    assert!(matches!(errors.0.as_slice(), [ConfigProblem::NoFee { .. }]));
+}
@@ -0,0 +1,109 @@
+use bitcoin::Sequence;
+
+use op_ctv_payment_pool::{
+    config::NetworkConfig,
+    ids::{NodePath, UserIndex},
+    manifest::PoolManifest,
+    pools::{build_pools, close_all_exit, node_exit},
+    profile::NetworkProfile,
+    state::{decode_state, encode_state},
+    POOL_USERS,
+};
+
+mod common;
+
+use common::{addresses, anchor, backend};
+
+// no wait to leave the root, a day for the next two levels, UNWIND_DELAY_BLOCKS below that
+fn config() -> NetworkConfig {
+    let mut config = NetworkConfig::new(NetworkProfile::RegtestLocal);
+    config.level_delays = vec![0, 144, 144];
+    config.unwind_delay = Some(6);
+    config.close_all_leaf = true;
+    config
+}
+
+#[test]
+fn each_level_commits_to_its_own_sequence() {
+    let config = config();
+    let backend = backend(&config);
+    let addresses = addresses(config.network);
+    let anchor_addr = anchor(&config);
+    let tree = build_pools(&addresses, &anchor_addr, &config, &backend).unwrap();
+
+    let mut sequences = Vec::new();
+    for spender in UserIndex::all().take(POOL_USERS - 1) {
+        let users = NodePath::unwind(spender).unwrap();
+        let exit = node_exit(
+            &tree,
+            &config,
+            &backend,
+            &addresses,
+            &anchor_addr,
+            &users,
+            spender,
+        )
+        .unwrap();
+        assert_eq!(exit.sequence, config.unwind_sequence(&users));
+        sequences.push(exit.sequence);
+    }
+    assert_eq!(sequences[0], Sequence::ENABLE_RBF_NO_LOCKTIME);
+    assert_eq!(sequences[1], Sequence::from_height(144));
+    assert_eq!(sequences[2], Sequence::from_height(144));
+    assert!(sequences[3..]
+        .iter()
+        .all(|sequence| *sequence == Sequence::from_height(6)));
+
+    // closing the pool spends the root, it has the root's cooldown
+    let close_all = close_all_exit(&tree, &config, &backend, &addresses, &anchor_addr).unwrap();
+    assert_eq!(close_all.sequence, Sequence::ENABLE_RBF_NO_LOCKTIME);
+    assert_eq!(config.max_unwind_delay(), Some(144));
+}
+
+#[test]
+fn level_delays_change_the_templates() {
+    let mut config = NetworkConfig::new(NetworkProfile::RegtestLocal);
+    let backend = backend(&config);
+    let addresses = addresses(config.network);
+    let anchor_addr = anchor(&config);
+    config.unwind_delay = Some(6);
+    let uniform = build_pools(&addresses, &anchor_addr, &config, &backend).unwrap();
+
+    // the same delay on every level is the same pool
+    config.level_delays = vec![6; POOL_USERS - 1];
+    let listed = build_pools(&addresses, &anchor_addr, &config, &backend).unwrap();
+    assert_eq!(
+        listed.root().unwrap().address(&config),
+        uniform.root().unwrap().address(&config)
+    );
+
+    // a cooldown on the exit pool only changes every node above it
+    config.level_delays[POOL_USERS - 2] = 12;
+    let cooldown = build_pools(&addresses, &anchor_addr, &config, &backend).unwrap();
+    let exit_pool = NodePath::new(vec![0, 1]).unwrap();
+    assert_ne!(
+        cooldown.node(&exit_pool).unwrap().templates,
+        uniform.node(&exit_pool).unwrap().templates
+    );
+    assert_ne!(
+        cooldown.root().unwrap().address(&config),
+        uniform.root().unwrap().address(&config)
+    );
+}
+
+#[test]
+fn manifests_keep_the_level_delays() {
+    let config = config();
+    let backend = backend(&config);
+    let addresses = addresses(config.network);
+    let anchor_addr = anchor(&config);
+    let tree = build_pools(&addresses, &anchor_addr, &config, &backend).unwrap();
+    let root = tree.root().unwrap().address(&config);
+    let manifest = PoolManifest::new(&config, &backend, &anchor_addr, &addresses, &root);
+    assert_eq!(manifest.level_delays, config.level_delays);
+
+    let (decoded, _) = decode_state(&encode_state(&manifest).unwrap()).unwrap();
+    let pool = decoded.load_pool().unwrap();
+    assert_eq!(pool.config.level_delays, config.level_delays);
+    assert_eq!(pool.tree.root().unwrap().address(&pool.config), root);
+}
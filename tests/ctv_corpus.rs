@@ -0,0 +1,197 @@
+use bitcoin::{
+    hashes::{sha256, Hash},
+    hex::DisplayHex,
+    taproot::TapLeafHash,
+};
+use serde::{Deserialize, Serialize};
+use std::{fs, path::PathBuf};
+
+use op_ctv_payment_pool::{
+    config::NetworkConfig,
+    ctv_scripts::TreeLayout,
+    ids::{NodePath, UserIndex},
+    pools::build_pools,
+    profile::NetworkProfile,
+    recovery::RecoveryPath,
+    tree::{PoolNode, PoolTree},
+    POOL_USERS,
+};
+
+mod common;
+
+use common::{address, addresses, anchor, backend};
+
+// Set to rewrite the corpus from this build, after a change that is meant to move the addresses
+const UPDATE_VAR: &str = "UPDATE_CTV_CORPUS";
+
+fn corpus_path() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/ctv_corpus.json")
+}
+
+// One node as the corpus pins it: what it locks to and the key that comes out of it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct NodeFixture {
+    users: NodePath,
+    amount_sats: u64,
+    templates: Vec<String>,
+    leaf_scripts: Vec<String>,
+    leaf_hashes: Vec<String>,
+    recovery_script: Option<String>,
+    close_all: Option<String>,
+    merkle_root: Option<String>,
+    output_key: String,
+    address: String,
+}
+
+// The pool of the reference users under one config. Every node of the planned unwind is kept
+// whole, the rest of the tree by a digest of every node's address.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct CaseFixture {
+    name: String,
+    root_address: String,
+    nodes: usize,
+    // sha256 of "<users> <address>\n" for every node in level order
+    nodes_digest: String,
+    unwind: Vec<NodeFixture>,
+}
+
+struct Case {
+    name: &'static str,
+    layout: TreeLayout,
+    anchored: bool,
+    recovery: bool,
+    close_all_leaf: bool,
+}
+
+const CASES: &[Case] = &[
+    Case {
+        name: "balanced",
+        layout: TreeLayout::Balanced,
+        anchored: true,
+        recovery: false,
+        close_all_leaf: false,
+    },
+    Case {
+        name: "weighted_recovery",
+        layout: TreeLayout::Weighted,
+        anchored: true,
+        recovery: true,
+        close_all_leaf: false,
+    },
+    Case {
+        name: "balanced_close_all_no_anchor",
+        layout: TreeLayout::Balanced,
+        anchored: false,
+        recovery: false,
+        close_all_leaf: true,
+    },
+];
+
+fn build(case: &Case) -> (PoolTree, NetworkConfig) {
+    let mut config = NetworkConfig::new(NetworkProfile::RegtestLocal);
+    config.tree_layout = case.layout;
+    config.close_all_leaf = case.close_all_leaf;
+    if !case.anchored {
+        config.anchor_amount = None;
+    }
+    config.recovery = case.recovery.then(|| RecoveryPath {
+        address: address(40, config.network),
+        timeout: 144,
+    });
+    let backend = backend(&config);
+    let addresses = addresses(config.network);
+    let anchor_addr = anchor(&config);
+    let tree = build_pools(&addresses, &anchor_addr, &config, &backend).unwrap();
+    (tree, config)
+}
+
+fn node_fixture(node: &PoolNode, config: &NetworkConfig) -> NodeFixture {
+    NodeFixture {
+        users: node.users.clone(),
+        amount_sats: node.amount.to_sat(),
+        templates: node
+            .templates
+            .iter()
+            .map(|hash| hash.to_lower_hex_string())
+            .collect(),
+        leaf_scripts: node
+            .leaf_scripts
+            .iter()
+            .map(|script| script.to_hex_string())
+            .collect(),
+        leaf_hashes: node
+            .leaf_scripts
+            .iter()
+            .chain(&node.recovery_script)
+            .map(|script| TapLeafHash::from_script(script, config.leaf_version).to_string())
+            .collect(),
+        recovery_script: node
+            .recovery_script
+            .as_ref()
+            .map(|script| script.to_hex_string()),
+        close_all: node.close_all.map(|hash| hash.to_lower_hex_string()),
+        merkle_root: node.spend_info.merkle_root().map(|root| root.to_string()),
+        output_key: node.spend_info.output_key().to_string(),
+        address: node.address(config).to_string(),
+    }
+}
+
+fn case_fixture(case: &Case) -> CaseFixture {
+    let (tree, config) = build(case);
+    let mut addresses = String::new();
+    for (users, node) in tree.iter_nodes() {
+        addresses.push_str(&format!("{} {}\n", users, node.address(&config)));
+    }
+    // the node every user of the planned unwind spends, the root first
+    let unwind = UserIndex::all()
+        .take(POOL_USERS - 1)
+        .map(|user| {
+            node_fixture(
+                tree.node(&NodePath::unwind(user).unwrap()).unwrap(),
+                &config,
+            )
+        })
+        .collect();
+    CaseFixture {
+        name: case.name.to_string(),
+        root_address: tree.root().unwrap().address(&config).to_string(),
+        nodes: tree.iter_nodes().count(),
+        nodes_digest: sha256::Hash::hash(addresses.as_bytes()).to_string(),
+        unwind,
+    }
+}
+
+#[test]
+fn derived_hashes_scripts_and_keys_match_the_corpus() {
+    let computed: Vec<CaseFixture> = CASES.iter().map(case_fixture).collect();
+    let path = corpus_path();
+    if std::env::var_os(UPDATE_VAR).is_some() {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(
+            &path,
+            serde_json::to_string_pretty(&computed).unwrap() + "\n",
+        )
+        .unwrap();
+        return;
+    }
+    let corpus: Vec<CaseFixture> = serde_json::from_str(&fs::read_to_string(&path).unwrap())
+        .unwrap_or_else(|e| panic!("{} is not a corpus: {}", path.display(), e));
+    assert_eq!(
+        corpus.iter().map(|case| &case.name).collect::<Vec<_>>(),
+        computed.iter().map(|case| &case.name).collect::<Vec<_>>()
+    );
+    for (expected, actual) in corpus.iter().zip(&computed) {
+        for (expected_node, actual_node) in expected.unwind.iter().zip(&actual.unwind) {
+            assert_eq!(
+                expected_node, actual_node,
+                "case {}: the node of users {} changed, set {}=1 to rewrite the corpus if it is meant to",
+                expected.name, expected_node.users, UPDATE_VAR
+            );
+        }
+        assert_eq!(
+            expected, actual,
+            "case {}: the pool changed, set {}=1 to rewrite the corpus if it is meant to",
+            expected.name, UPDATE_VAR
+        );
+    }
+}
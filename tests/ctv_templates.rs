@@ -0,0 +1,325 @@
+use bitcoin::{
+    absolute,
+    consensus::{encode::deserialize_hex, Encodable},
+    hashes::{sha256, Hash},
+    hex::DisplayHex,
+    taproot::LeafVersion,
+    transaction, Amount, OutPoint, ScriptBuf, Sequence, Transaction, TxIn, TxOut, Txid,
+};
+
+use op_ctv_payment_pool::{
+    config::NetworkConfig,
+    covenant::CovenantBackend,
+    ctv_scripts::{calc_ctv_hash, ctv_hash},
+    ids::{NodePath, UserIndex},
+    pools::{build_pools, pool_spend_template},
+    profile::NetworkProfile,
+    rpc_helper::funding_lock_time,
+    AMOUNT_PER_USER, POOL_USERS,
+};
+
+mod common;
+
+use common::{addresses, anchor, backend};
+
+// BIP-119 DefaultCheckTemplateVerifyHash straight from the spec, computed from a whole tx
+fn standard_template_hash(tx: &Transaction, input_index: u32) -> [u8; 32] {
+    let mut buffer = Vec::new();
+    buffer.extend(tx.version.0.to_le_bytes());
+    buffer.extend(tx.lock_time.to_consensus_u32().to_le_bytes());
+    if tx.input.iter().any(|input| !input.script_sig.is_empty()) {
+        let mut script_sigs = Vec::new();
+        for input in &tx.input {
+            input.script_sig.consensus_encode(&mut script_sigs).unwrap();
+        }
+        buffer.extend(sha256::Hash::hash(&script_sigs).to_byte_array());
+    }
+    buffer.extend((tx.input.len() as u32).to_le_bytes());
+    let mut sequences = Vec::new();
+    for input in &tx.input {
+        input.sequence.consensus_encode(&mut sequences).unwrap();
+    }
+    buffer.extend(sha256::Hash::hash(&sequences).to_byte_array());
+    buffer.extend((tx.output.len() as u32).to_le_bytes());
+    let mut outputs = Vec::new();
+    for output in &tx.output {
+        output.consensus_encode(&mut outputs).unwrap();
+    }
+    buffer.extend(sha256::Hash::hash(&outputs).to_byte_array());
+    buffer.extend(input_index.to_le_bytes());
+    sha256::Hash::hash(&buffer).to_byte_array()
+}
+
+fn outputs() -> Vec<TxOut> {
+    vec![
+        TxOut {
+            value: Amount::from_sat(10_000),
+            script_pubkey: ScriptBuf::new_op_return([1; 8]),
+        },
+        TxOut {
+            value: Amount::from_sat(20_000),
+            script_pubkey: ScriptBuf::new_op_return([2; 8]),
+        },
+    ]
+}
+
+fn spend(tx_version: i32, sequences: &[Sequence], outputs: Vec<TxOut>) -> Transaction {
+    Transaction {
+        version: transaction::Version(tx_version),
+        lock_time: absolute::LockTime::ZERO,
+        input: sequences
+            .iter()
+            .map(|&sequence| TxIn {
+                sequence,
+                ..Default::default()
+            })
+            .collect(),
+        output: outputs,
+    }
+}
+
+#[test]
+fn hash_matches_the_spec_for_every_sequence_vector() {
+    let cases: [&[Sequence]; 4] = [
+        &[Sequence::ENABLE_RBF_NO_LOCKTIME],
+        &[Sequence::from_height(6)],
+        &[Sequence::from_512_second_intervals(10)],
+        &[Sequence::from_height(1), Sequence::MAX, Sequence::ZERO],
+    ];
+    for sequences in cases {
+        for version in [2, 3] {
+            let tx = spend(version, sequences, outputs());
+            assert_eq!(
+                calc_ctv_hash(version, &outputs(), sequences),
+                standard_template_hash(&tx, 0),
+                "sequences {:?}",
+                sequences
+            );
+        }
+    }
+}
+
+#[test]
+fn hash_commits_to_every_sequence() {
+    let base = calc_ctv_hash(3, &outputs(), &[Sequence::ENABLE_RBF_NO_LOCKTIME]);
+    assert_ne!(
+        base,
+        calc_ctv_hash(3, &outputs(), &[Sequence::from_height(6)])
+    );
+    assert_ne!(
+        calc_ctv_hash(3, &outputs(), &[Sequence::from_height(6)]),
+        calc_ctv_hash(3, &outputs(), &[Sequence::from_height(7)])
+    );
+    // order and count of the inputs are part of the template too
+    let two = [Sequence::from_height(1), Sequence::ZERO];
+    let swapped = [Sequence::ZERO, Sequence::from_height(1)];
+    assert_ne!(
+        calc_ctv_hash(3, &outputs(), &two),
+        calc_ctv_hash(3, &outputs(), &swapped)
+    );
+    assert_ne!(
+        base,
+        calc_ctv_hash(3, &outputs(), &[Sequence::ENABLE_RBF_NO_LOCKTIME; 2])
+    );
+}
+
+// (case, tx, input index, hash) from the spec's reference get_default_check_template_verify_hash
+const REFERENCE_VECTORS: [(&str, &str, u32, &str); 5] = [
+    (
+        "one input, no locktime",
+        "020000000111111111111111111111111111111111111111111111111111111111111111110000000000fdffffff021027000000000000225120aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa8813000000000000160014bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb00000000",
+        0,
+        "7f43e63b41af6908bf838f73b162b82ccb8d24d3ee568a34eb23803fadd7020c",
+    ),
+    (
+        "v3 relative timelock",
+        "03000000011111111111111111111111111111111111111111111111111111111111111111010000000006000000030852000000000000225120aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa7017000000000000225120aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa00000000000000000451024e7300000000",
+        0,
+        "aa7d0724cef9b9a85ace87d33fd3d20c78fb6f5efc1b65a66ce162c8ad657b99",
+    ),
+    (
+        "three inputs, block height locktime, last input",
+        "0300000003111111111111111111111111111111111111111111111111111111111111111100000000000100000022222222222222222222222222222222222222222222222222222222222222220700000000ffffffff333333333333333333333333333333333333333333333333333333333333333302000000000000000001d204000000000000160014bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb00350c00",
+        2,
+        "d376c5fd26020adf69c70cad21aa7bcc0179ace50a090b05c1d1b35505420bf0",
+    ),
+    (
+        "scriptSigs, time locktime",
+        "02000000021111111111111111111111111111111111111111111111111111111111111111000000000151feffffff22222222222222222222222222222222222222222222222222222222222222220100000000feffffff029f86010000000000225120aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa0000000000000000066a040102030400f15365",
+        1,
+        "3ce9ef0bf4ebaf97225772e67913ff9c1b35aa050ad9a71640c340f31c006635",
+    ),
+    (
+        "version 1, input index past the inputs",
+        "0100000001333333333333333333333333333333333333333333333333333333333333333303000000160014cccccccccccccccccccccccccccccccccccccccc00000000012202000000000000225120aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa00000000",
+        5,
+        "7f9976a1d120e17a95612acff78c40e0a6974755adc66c080a93879cfd6b1c3a",
+    ),
+];
+
+#[test]
+fn ctv_hash_matches_the_reference_vectors() {
+    for (case, tx, input_index, hash) in REFERENCE_VECTORS {
+        let tx: Transaction = deserialize_hex(tx).unwrap();
+        assert_eq!(
+            ctv_hash(&tx, input_index).to_lower_hex_string(),
+            hash,
+            "{}",
+            case
+        );
+        assert_eq!(
+            ctv_hash(&tx, input_index),
+            standard_template_hash(&tx, input_index),
+            "{}",
+            case
+        );
+    }
+}
+
+#[test]
+fn ctv_hash_commits_to_the_input_index() {
+    let tx: Transaction = deserialize_hex(REFERENCE_VECTORS[2].1).unwrap();
+    let hashes: Vec<String> = (0..3)
+        .map(|i| ctv_hash(&tx, i).to_lower_hex_string())
+        .collect();
+    assert_eq!(
+        hashes,
+        [
+            "c141483f490d735a00ef560c7fd77c4639d0c7db7471a5e33a02d1465f1800d3",
+            "37808aee8027910d3bb4314f855e57c679fc10a9bb2c1e5a86ff9ce607ac1708",
+            "d376c5fd26020adf69c70cad21aa7bcc0179ace50a090b05c1d1b35505420bf0",
+        ]
+    );
+}
+
+#[test]
+fn ctv_hash_ignores_outpoints_and_witnesses() {
+    let tx: Transaction = deserialize_hex(REFERENCE_VECTORS[2].1).unwrap();
+    let mut moved = tx.clone();
+    for input in &mut moved.input {
+        input.previous_output = OutPoint::new(Txid::all_zeros(), 9);
+        input.witness.push([7; 64]);
+    }
+    assert_eq!(ctv_hash(&tx, 2), ctv_hash(&moved, 2));
+
+    // but not the locktime, or a scriptSig on any input
+    let mut locked = tx.clone();
+    locked.lock_time = absolute::LockTime::from_height(800_001).unwrap();
+    assert_ne!(ctv_hash(&tx, 2), ctv_hash(&locked, 2));
+    let mut signed = tx.clone();
+    signed.input[0].script_sig = ScriptBuf::from_bytes(vec![0x51]);
+    assert_ne!(ctv_hash(&tx, 2), ctv_hash(&signed, 2));
+}
+
+#[test]
+fn pool_template_hash_is_the_ctv_hash_of_its_spend() {
+    let sequences = [Sequence::from_height(6), Sequence::ZERO];
+    for version in [2, 3] {
+        let tx = spend(version, &sequences, outputs());
+        assert_eq!(
+            calc_ctv_hash(version, &outputs(), &sequences),
+            ctv_hash(&tx, 0)
+        );
+    }
+}
+
+#[test]
+fn unwind_spend_carries_the_committed_sequence() {
+    let mut config = NetworkConfig::new(NetworkProfile::RegtestLocal);
+    config.recovery = None;
+    config.unwind_delay = Some(6);
+    let backend = backend(&config);
+    let addresses = addresses(config.network);
+    let anchor_addr = anchor(&config);
+
+    let pools = build_pools(&addresses, &anchor_addr, &config, &backend).unwrap();
+    let root = pools.spend_info(&NodePath::root()).unwrap();
+    let funding_tx = Transaction {
+        version: transaction::Version(2),
+        lock_time: absolute::LockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: OutPoint::new(Txid::all_zeros(), 0),
+            ..Default::default()
+        }],
+        output: vec![TxOut {
+            value: AMOUNT_PER_USER * POOL_USERS as u64,
+            script_pubkey: ScriptBuf::new_p2tr_tweaked(root.output_key()),
+        }],
+    };
+
+    let template = pool_spend_template(
+        &pools,
+        &config,
+        &backend,
+        UserIndex::new(0).unwrap(),
+        &addresses,
+        &funding_tx,
+        &anchor_addr,
+    )
+    .unwrap();
+
+    assert_eq!(template.tx.input[0].sequence, Sequence::from_height(6));
+    assert_eq!(
+        standard_template_hash(&template.tx, 0),
+        template.template_hash
+    );
+    assert_ne!(
+        backend.template_hash(&template.tx.output, Sequence::ENABLE_RBF_NO_LOCKTIME),
+        template.template_hash
+    );
+    // and that is the leaf the pool node actually has
+    let leaf = backend.leaf_script(template.template_hash);
+    assert!(template
+        .spend_info
+        .control_block(&(leaf, LeafVersion::TapScript))
+        .is_some());
+}
+
+#[test]
+fn entry_template_does_not_depend_on_the_funding_lock_time() {
+    let config = NetworkConfig::new(NetworkProfile::RegtestLocal);
+    let backend = backend(&config);
+    let addresses = addresses(config.network);
+    let anchor_addr = anchor(&config);
+    let pools = build_pools(&addresses, &anchor_addr, &config, &backend).unwrap();
+    let root = pools.spend_info(&NodePath::root()).unwrap();
+
+    let funding_tx = |lock_time| Transaction {
+        version: transaction::Version(2),
+        lock_time,
+        input: vec![TxIn {
+            previous_output: OutPoint::new(Txid::all_zeros(), 0),
+            sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+            ..Default::default()
+        }],
+        output: vec![TxOut {
+            value: AMOUNT_PER_USER * POOL_USERS as u64,
+            script_pubkey: ScriptBuf::new_p2tr_tweaked(root.output_key()),
+        }],
+    };
+    let entry = |funding_tx: &Transaction| {
+        pool_spend_template(
+            &pools,
+            &config,
+            &backend,
+            UserIndex::new(0).unwrap(),
+            &addresses,
+            funding_tx,
+            &anchor_addr,
+        )
+        .unwrap()
+    };
+
+    let unlocked = entry(&funding_tx(absolute::LockTime::ZERO));
+    for draw in [1, 10, 990, u32::MAX] {
+        let lock_time = funding_lock_time(840_000, draw).unwrap();
+        assert!(lock_time.to_consensus_u32() <= 840_000);
+        assert!(lock_time.to_consensus_u32() > 840_000 - 100);
+
+        let locked = entry(&funding_tx(lock_time));
+        assert_eq!(locked.template_hash, unlocked.template_hash);
+        assert_eq!(locked.tx.lock_time, absolute::LockTime::ZERO);
+        assert_eq!(locked.tx.output, unlocked.tx.output);
+        assert_eq!(standard_template_hash(&locked.tx, 0), locked.template_hash);
+    }
+}
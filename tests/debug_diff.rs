@@ -0,0 +1,87 @@
+use bitcoin::{
+    absolute, transaction, Amount, Network, OutPoint, Sequence, Transaction, TxIn, TxOut,
+};
+
+use op_ctv_payment_pool::{
+    config::NetworkConfig,
+    debug::{diff_level, rebuild_level},
+    ids::NodePath,
+    manifest::{LoadedPool, PoolManifest},
+    pools::{build_pools, node_value},
+    profile::NetworkProfile,
+    POOL_USERS,
+};
+
+mod common;
+
+use common::{address, addresses, anchor, backend};
+
+fn pool() -> (LoadedPool, Transaction) {
+    let config = NetworkConfig::new(NetworkProfile::RegtestLocal);
+    let backend = backend(&config);
+    let addresses = addresses(config.network);
+    let anchor_addr = anchor(&config);
+    let tree = build_pools(&addresses, &anchor_addr, &config, &backend).unwrap();
+    let root = tree.root().unwrap().address(&config);
+    let funding_tx = Transaction {
+        version: transaction::Version::TWO,
+        lock_time: absolute::LockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: OutPoint::null(),
+            ..Default::default()
+        }],
+        output: vec![TxOut {
+            value: node_value(&NodePath::root(), &config).unwrap(),
+            script_pubkey: root.script_pubkey(),
+        }],
+    };
+    let manifest = PoolManifest::new(&config, &backend, &anchor_addr, &addresses, &root);
+    (manifest.load_pool().unwrap(), funding_tx)
+}
+
+#[test]
+fn the_rebuilt_tx_is_identical_to_itself() {
+    let (pool, funding_tx) = pool();
+    let level = POOL_USERS - 2;
+    let spend = rebuild_level(&pool, &funding_tx, level).unwrap();
+    let observed = pool.backend.finalize(spend).unwrap();
+    let diff = diff_level(&pool, &funding_tx, level, &observed).unwrap();
+    assert!(diff.identical);
+    assert!(diff.differences.is_empty());
+    assert_eq!(
+        diff.observed_template_hash,
+        Some(diff.template_hash.clone())
+    );
+
+    assert!(rebuild_level(&pool, &funding_tx, POOL_USERS - 1).is_err());
+}
+
+#[test]
+fn a_tampered_tx_names_every_field_that_changed() {
+    let (pool, funding_tx) = pool();
+    let spend = rebuild_level(&pool, &funding_tx, 1).unwrap();
+    let mut observed = pool.backend.finalize(spend).unwrap();
+    observed.input[0].sequence = Sequence::ZERO;
+    observed.output[0].value -= Amount::from_sat(1);
+    observed.output[1].script_pubkey = address(98, Network::Regtest).script_pubkey();
+
+    let diff = diff_level(&pool, &funding_tx, 1, &observed).unwrap();
+    assert!(!diff.identical);
+    let fields: Vec<&str> = diff
+        .differences
+        .iter()
+        .map(|difference| difference.field.as_str())
+        .collect();
+    assert_eq!(
+        fields,
+        ["input 0 sequence", "output 0 amount", "output 1 script"]
+    );
+    assert_ne!(
+        diff.observed_template_hash,
+        Some(diff.template_hash.clone())
+    );
+    assert_eq!(
+        diff.differences[2].observed,
+        address(98, Network::Regtest).to_string()
+    );
+}
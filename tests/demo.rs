@@ -0,0 +1,112 @@
+use bitcoin::{Address, Amount, Txid};
+use bitcoincore_rpc::RpcApi;
+use std::str::FromStr;
+
+use op_ctv_payment_pool::{
+    config::NetworkConfig,
+    demo::{demo_resume, node_knows, planned_unwind, DemoResume},
+    ids::UserIndex,
+    lifecycle::Lifecycle,
+    manifest::PoolManifest,
+    mempool::BroadcastQueue,
+    mock::MockBackend,
+    pools::{build_pools, process_pool_spend},
+    profile::NetworkProfile,
+    rpc_helper::{send_funding_transaction, FundingDestination},
+    AMOUNT_PER_USER, POOL_USERS,
+};
+
+mod common;
+
+use common::{anchor, backend};
+
+fn manifest(mock: &MockBackend, config: &NetworkConfig) -> PoolManifest {
+    let backend = backend(config);
+    let addresses: Vec<Address> = (0..POOL_USERS).map(|_| mock.new_address()).collect();
+    let anchor_addr = anchor(config);
+    let pools = build_pools(&addresses, &anchor_addr, config, &backend).unwrap();
+    let root = pools.root().unwrap().address(config);
+    PoolManifest::new(config, &backend, &anchor_addr, &addresses, &root)
+}
+
+#[test]
+fn unfinished_pools_are_resumed_on_their_own_network() {
+    let config = NetworkConfig::new(NetworkProfile::RegtestLocal);
+    let mock = MockBackend::new(config.network);
+    let mut manifest = manifest(&mock, &config);
+
+    let resume = demo_resume(&manifest, NetworkProfile::RegtestLocal).unwrap();
+    assert_eq!(
+        resume,
+        Some(DemoResume {
+            funding_txid: None,
+            withdrawn: 0
+        })
+    );
+
+    manifest.lifecycle = Lifecycle::Unwinding(3);
+    let resume = demo_resume(&manifest, NetworkProfile::RegtestLocal).unwrap();
+    assert_eq!(resume.unwrap().withdrawn, 3);
+
+    // a pool on another network is never picked up by accident
+    let error = demo_resume(&manifest, NetworkProfile::Inquisition).unwrap_err();
+    assert!(error.to_string().contains("--fresh"), "{}", error);
+
+    // a finished pool makes way for a new one
+    manifest.lifecycle = Lifecycle::Closed;
+    assert!(demo_resume(&manifest, NetworkProfile::Inquisition)
+        .unwrap()
+        .is_none());
+}
+
+#[tokio::test]
+async fn the_planned_unwind_is_the_one_that_gets_broadcast() {
+    let config = NetworkConfig::new(NetworkProfile::RegtestLocal);
+    let mock = MockBackend::new(config.network);
+    mock.auto_mine(Some(1));
+    mock.set_relay_fee(Amount::ZERO);
+    let rpc = mock.rpc();
+    mock.add_utxo(AMOUNT_PER_USER * POOL_USERS as u64 + Amount::ONE_BTC);
+    mock.mine(1);
+
+    let manifest = manifest(&mock, &config);
+    let pool = manifest.load_pool().unwrap();
+    let (funding_txid, _) = send_funding_transaction(
+        &rpc,
+        &config,
+        &FundingDestination::EntryPool(pool.tree.root().unwrap().address(&config)),
+        None,
+    )
+    .await
+    .unwrap();
+    let funding_tx = rpc
+        .run(move |c| c.get_raw_transaction(&funding_txid, None))
+        .await
+        .unwrap();
+    let planned = planned_unwind(&pool, &funding_tx).unwrap();
+    assert_eq!(planned.len(), POOL_USERS - 1);
+
+    let mut queue = BroadcastQueue::new(&config);
+    let mut current_txid = funding_txid;
+    for i in UserIndex::all().take(POOL_USERS - 1) {
+        // a restart here would find the spends before this one on the node
+        assert!(!node_knows(&rpc, planned[i.index()]).await);
+        current_txid = process_pool_spend(
+            &pool.tree,
+            &pool.config,
+            &rpc,
+            &rpc,
+            &mut queue,
+            pool.backend.as_ref(),
+            i,
+            &pool.addresses,
+            current_txid,
+            &pool.anchor_addr,
+        )
+        .await
+        .unwrap();
+        assert_eq!(current_txid, planned[i.index()]);
+        assert!(node_knows(&rpc, current_txid).await);
+    }
+    assert!(!node_knows(&rpc, Txid::from_str(&"22".repeat(32)).unwrap()).await);
+}
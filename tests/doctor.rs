@@ -0,0 +1,117 @@
+// Env vars are process wide, so the broken setups get a test binary of their own
+use std::{env, fs, net::TcpListener, path::PathBuf};
+
+use op_ctv_payment_pool::{
+    config::NetworkConfig,
+    doctor::{diagnose, diagnosis_result, Check, Status},
+    profile::NetworkProfile,
+};
+use tokio::sync::Mutex;
+
+mod common;
+
+use common::pool_manifest;
+
+// the tests change the env doctor reads, one at a time
+static ENV: Mutex<()> = Mutex::const_new(());
+
+fn temp_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("doctor-{}-{}.json", name, std::process::id()))
+}
+
+// points the rpc at a port nobody listens on, so the node check fails right away
+fn node_down() {
+    let port = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    env::set_var("POOL_RPC_URL", format!("http://127.0.0.1:{}", port));
+    env::set_var("BITCOIN_RPC_USER", "doctor");
+    env::set_var("BITCOIN_RPC_PASS", "doctor");
+}
+
+fn check<'a>(checks: &'a [Check], name: &str) -> &'a Check {
+    checks
+        .iter()
+        .find(|check| check.name == name)
+        .unwrap_or_else(|| panic!("no {} check in {:?}", name, checks))
+}
+
+#[tokio::test]
+async fn a_broken_config_is_the_only_finding() {
+    let _env = ENV.lock().await;
+    node_down();
+    env::set_var("POOL_TX_VERSION", "two");
+    let checks = diagnose(NetworkProfile::RegtestLocal, &temp_path("unused")).await;
+    env::remove_var("POOL_TX_VERSION");
+
+    // nothing after the config is looked at, it would all run on the wrong values
+    assert_eq!(checks.len(), 1, "{:?}", checks);
+    let config = check(&checks, "config");
+    assert_eq!(config.status, Status::Fail);
+    assert!(
+        config.detail.contains("POOL_TX_VERSION"),
+        "{}",
+        config.detail
+    );
+    assert!(config.fix.as_ref().unwrap().contains("env vars"));
+    assert!(diagnosis_result(&checks).is_err());
+}
+
+#[tokio::test]
+async fn a_broken_manifest_fails_the_state_check() {
+    let _env = ENV.lock().await;
+    node_down();
+    let profile = NetworkProfile::RegtestLocal;
+    let manifest = pool_manifest(&NetworkConfig::new(profile));
+    let path = temp_path("manifest");
+
+    // without a node the chain checks are skipped but the state is still read
+    let checks = diagnose(profile, &path).await;
+    assert_eq!(check(&checks, "config").status, Status::Ok);
+    assert_eq!(check(&checks, "node").status, Status::Fail);
+    for name in ["network", "wallet", "funds", "ctv", "zmq"] {
+        assert_eq!(check(&checks, name).status, Status::Skipped, "{}", name);
+    }
+    let state = check(&checks, "state");
+    assert_eq!(state.status, Status::Skipped);
+    assert!(state.detail.contains("no manifest"), "{}", state.detail);
+
+    manifest.write(&path).unwrap();
+    let checks = diagnose(profile, &path).await;
+    let state = check(&checks, "state");
+    assert_eq!(state.status, Status::Ok, "{}", state.detail);
+    assert!(state.detail.contains(&manifest.root_address));
+
+    // not a manifest at all
+    fs::write(&path, "{\"network\": ").unwrap();
+    let checks = diagnose(profile, &path).await;
+    let state = check(&checks, "state");
+    assert_eq!(state.status, Status::Fail);
+    assert!(state.detail.contains("can't be read"), "{}", state.detail);
+
+    // a manifest whose tree doesn't rebuild to the root it records
+    let mut tampered = manifest.clone();
+    tampered.root_address = pool_manifest(&NetworkConfig {
+        close_all_leaf: true,
+        ..NetworkConfig::new(profile)
+    })
+    .root_address;
+    tampered.write(&path).unwrap();
+    let checks = diagnose(profile, &path).await;
+    let state = check(&checks, "state");
+    assert_eq!(state.status, Status::Fail);
+    assert!(state.detail.contains("is inconsistent"), "{}", state.detail);
+    assert!(state.detail.contains("does not match the manifest"));
+
+    // another network's manifest is only a warning, it's the wrong --network
+    let testnet = pool_manifest(&NetworkConfig::new(NetworkProfile::Testnet4));
+    testnet.write(&path).unwrap();
+    let checks = diagnose(profile, &path).await;
+    let state = check(&checks, "state");
+    assert_eq!(state.status, Status::Warn);
+    assert!(state.fix.as_ref().unwrap().contains("--network testnet4"));
+
+    fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,270 @@
+#![cfg(feature = "elements")]
+
+use bitcoin::{
+    consensus::serialize,
+    hashes::{sha256, sha256d, Hash, HashEngine},
+    hex::FromHex,
+    key::Secp256k1,
+    secp256k1::Scalar,
+    taproot::LeafVersion,
+    Address, Amount, Network, OutPoint, PubkeyHash, ScriptBuf, Sequence, TxOut, Txid, Witness,
+    XOnlyPublicKey,
+};
+use std::str::FromStr;
+
+use op_ctv_payment_pool::{
+    elements::{
+        check_introspection, elements_address, elements_pool, elements_script, elements_unwind,
+        introspection_script, AssetId, ElementsTaproot, ElementsTransaction, ElementsTxIn,
+        ElementsTxOut, IntrospectionBackend, ELEMENTS_LEAF_VERSION, ELEMENTS_REGTEST,
+    },
+    ids::NodePath,
+    manifest::PoolManifest,
+    profile::NetworkProfile,
+    POOL_USERS,
+};
+
+mod common;
+
+use common::{address, addresses};
+
+fn asset() -> AssetId {
+    AssetId::from_str(ELEMENTS_REGTEST.policy_asset).unwrap()
+}
+
+fn tagged(tag: &str, data: &[&[u8]]) -> [u8; 32] {
+    let tag = sha256::Hash::hash(tag.as_bytes());
+    let mut engine = sha256::Hash::engine();
+    engine.input(tag.as_ref());
+    engine.input(tag.as_ref());
+    for part in data {
+        engine.input(part);
+    }
+    sha256::Hash::from_engine(engine).to_byte_array()
+}
+
+// the reference users' ert1 addresses
+fn elements_addresses() -> Vec<String> {
+    addresses(Network::Regtest)
+        .iter()
+        .map(|address| elements_address(&address.script_pubkey(), &ELEMENTS_REGTEST).unwrap())
+        .collect()
+}
+
+#[test]
+fn addresses_use_the_chains_params() {
+    let script = address(0, Network::Regtest).script_pubkey();
+    let ert = elements_address(&script, &ELEMENTS_REGTEST).unwrap();
+    assert!(ert.starts_with("ert1p"), "{}", ert);
+    assert_eq!(elements_script(&ert, &ELEMENTS_REGTEST).unwrap(), script);
+    // a bitcoin address is somebody else's chain
+    let bcrt = address(0, Network::Regtest).to_string();
+    assert!(elements_script(&bcrt, &ELEMENTS_REGTEST).is_err());
+    assert_eq!(asset().to_string(), ELEMENTS_REGTEST.policy_asset);
+}
+
+#[test]
+fn control_blocks_commit_to_the_elements_output_key() {
+    let manifest = elements_pool(&elements_addresses()).unwrap();
+    let pool = manifest.load_pool().unwrap();
+    let spend_info = pool.tree.spend_info(&NodePath::root()).unwrap();
+    let taproot = ElementsTaproot::from_spend_info(spend_info).unwrap();
+    // not the key bitcoin's tags give
+    assert_ne!(
+        taproot.script_pubkey(),
+        Address::p2tr_tweaked(spend_info.output_key(), Network::Regtest).script_pubkey()
+    );
+    assert_eq!(
+        Address::from_str(&manifest.root_address)
+            .unwrap()
+            .assume_checked()
+            .script_pubkey(),
+        taproot.script_pubkey()
+    );
+
+    let secp = Secp256k1::verification_only();
+    for (script, version) in spend_info.script_map().keys() {
+        assert_eq!(version.to_consensus(), ELEMENTS_LEAF_VERSION);
+        let control_block = taproot.control_block(script, *version).unwrap();
+        assert_eq!(control_block[0] & 0xfe, ELEMENTS_LEAF_VERSION);
+        let internal_key = XOnlyPublicKey::from_slice(&control_block[1..33]).unwrap();
+        // the script with its compact size length, the leaves are longer than 252 bytes
+        let mut node = tagged(
+            "TapLeaf/elements",
+            &[&[ELEMENTS_LEAF_VERSION], &serialize(script)],
+        );
+        for sibling in control_block[33..].chunks(32) {
+            let (a, b) = if node.as_slice() < sibling {
+                (node.as_slice(), sibling)
+            } else {
+                (sibling, node.as_slice())
+            };
+            node = tagged("TapBranch/elements", &[a, b]);
+        }
+        let tweak = tagged("TapTweak/elements", &[&internal_key.serialize(), &node]);
+        let (output_key, parity) = internal_key
+            .add_tweak(&secp, &Scalar::from_be_bytes(tweak).unwrap())
+            .unwrap();
+        assert_eq!(output_key, taproot.output_key);
+        assert_eq!(control_block[0] & 1, parity.to_u8());
+    }
+}
+
+#[test]
+fn txs_use_elements_encoding() {
+    let tx = ElementsTransaction {
+        version: 2,
+        lock_time: 0,
+        input: vec![ElementsTxIn {
+            previous_output: OutPoint::new(Txid::all_zeros(), 1),
+            sequence: Sequence::MAX,
+            witness: Witness::from_slice(&[[0xaa]]),
+        }],
+        output: vec![ElementsTxOut {
+            asset: asset(),
+            value: Amount::from_sat(0x0102),
+            script_pubkey: ScriptBuf::from_bytes(vec![0x51]),
+        }],
+    };
+    let mut expected = vec![2, 0, 0, 0, 0, 1];
+    expected.extend([0; 32]);
+    expected.extend([1, 0, 0, 0, 0, 0xff, 0xff, 0xff, 0xff, 1, 1]);
+    let mut asset_bytes = <[u8; 32]>::from_hex(ELEMENTS_REGTEST.policy_asset).unwrap();
+    asset_bytes.reverse();
+    expected.extend(asset_bytes);
+    expected.extend([1, 0, 0, 0, 0, 0, 0, 1, 2, 0, 1, 0x51, 0, 0, 0, 0]);
+    assert_eq!(tx.serialize(false), expected);
+    assert_eq!(
+        tx.txid(),
+        Txid::from_raw_hash(sha256d::Hash::hash(&expected))
+    );
+
+    // the flag, then the input's two proofs, its witness and peg-in witness, then the output's proofs
+    let mut witness = expected.clone();
+    witness[4] = 1;
+    witness.extend([0, 0, 1, 1, 0xaa, 0, 0, 0]);
+    assert_eq!(tx.serialize(true), witness);
+}
+
+#[test]
+fn the_unwind_pays_every_user_through_the_introspection_leaves() {
+    let manifest = elements_pool(&elements_addresses()).unwrap();
+    assert_eq!(manifest.profile, NetworkProfile::ElementsRegtest);
+    let pool = manifest.load_pool().unwrap();
+    let backend = IntrospectionBackend::new(asset());
+    let funding = OutPoint::new(Txid::all_zeros(), 0);
+    let txs = elements_unwind(&pool, &backend, funding).unwrap();
+    assert_eq!(txs.len(), POOL_USERS - 1);
+    assert_eq!(txs[0].input[0].previous_output, funding);
+
+    let mut held = manifest.funding_target().unwrap();
+    for (level, tx) in txs.iter().enumerate() {
+        let fee = tx.output.last().unwrap();
+        assert!(fee.script_pubkey.is_empty());
+        assert!(tx.output.iter().all(|output| output.asset == asset()));
+        // the node's amount, all of it
+        let paid: Amount = tx.output.iter().map(|output| output.value).sum();
+        assert_eq!(paid, held);
+        if let Some(next) = txs.get(level + 1) {
+            let spent = next.input[0].previous_output;
+            assert_eq!(spent.txid, tx.txid());
+            held = tx.output[spent.vout as usize].value;
+        }
+
+        let leaf = ScriptBuf::from_bytes(tx.input[0].witness[0].to_vec());
+        check_introspection(&leaf, tx, 0).unwrap();
+        // paying anybody else, or anything else, isn't what the leaf allows
+        let mut tampered = tx.clone();
+        tampered.output[0].script_pubkey = address(POOL_USERS, Network::Regtest).script_pubkey();
+        assert!(check_introspection(&leaf, &tampered, 0).is_err());
+        let mut tampered = tx.clone();
+        tampered.output[0].value += Amount::from_sat(1);
+        assert!(check_introspection(&leaf, &tampered, 0).is_err());
+        let mut tampered = tx.clone();
+        tampered.output[0].asset = AssetId::from_str(&"11".repeat(32)).unwrap();
+        assert!(check_introspection(&leaf, &tampered, 0).is_err());
+    }
+}
+
+#[test]
+fn a_hand_built_tx_meets_the_leaf_or_not() {
+    // a taproot payout and a legacy one, which the leaf commits to by the hash of its script
+    let outputs = [
+        TxOut {
+            value: Amount::from_sat(5_000),
+            script_pubkey: address(0, Network::Regtest).script_pubkey(),
+        },
+        TxOut {
+            value: Amount::from_sat(3_000),
+            script_pubkey: ScriptBuf::new_p2pkh(&PubkeyHash::from_byte_array([4; 20])),
+        },
+    ];
+    let sequence = Sequence::from_height(10);
+    let leaf = introspection_script(asset(), &outputs, sequence);
+    let tx = ElementsTransaction {
+        version: 2,
+        lock_time: 0,
+        input: vec![ElementsTxIn {
+            previous_output: OutPoint::new(Txid::all_zeros(), 0),
+            sequence,
+            witness: Witness::new(),
+        }],
+        output: outputs
+            .iter()
+            .map(|output| ElementsTxOut {
+                asset: asset(),
+                value: output.value,
+                script_pubkey: output.script_pubkey.clone(),
+            })
+            .chain([ElementsTxOut {
+                asset: asset(),
+                value: Amount::from_sat(500),
+                script_pubkey: ScriptBuf::new(),
+            }])
+            .collect(),
+    };
+    check_introspection(&leaf, &tx, 0).unwrap();
+    // the fee output's value is whatever is left
+    let mut fee = tx.clone();
+    fee.output[2].value = Amount::from_sat(900);
+    check_introspection(&leaf, &fee, 0).unwrap();
+
+    let mut tampered = tx.clone();
+    tampered.input[0].sequence = Sequence::from_height(9);
+    assert!(check_introspection(&leaf, &tampered, 0).is_err());
+    let mut tampered = tx.clone();
+    tampered.input.push(tx.input[0].clone());
+    assert!(check_introspection(&leaf, &tampered, 0).is_err());
+    let mut tampered = tx.clone();
+    tampered.output.swap(0, 1);
+    assert!(check_introspection(&leaf, &tampered, 0).is_err());
+    let mut tampered = tx.clone();
+    tampered.output[1].script_pubkey = ScriptBuf::new_p2pkh(&PubkeyHash::from_byte_array([5; 20]));
+    assert!(check_introspection(&leaf, &tampered, 0).is_err());
+    let mut tampered = tx.clone();
+    tampered.output[1].value = Amount::from_sat(2_999);
+    assert!(check_introspection(&leaf, &tampered, 0).is_err());
+    let mut tampered = tx.clone();
+    tampered.output.pop();
+    assert!(check_introspection(&leaf, &tampered, 0).is_err());
+    let mut tampered = tx.clone();
+    tampered.output[2].script_pubkey = address(1, Network::Regtest).script_pubkey();
+    assert!(check_introspection(&leaf, &tampered, 0).is_err());
+}
+
+#[test]
+fn a_pool_needs_elements_addresses() {
+    let mut addresses = elements_addresses();
+    addresses[3] = address(3, Network::Regtest).to_string();
+    assert!(elements_pool(&addresses).is_err());
+    // and its leaves aren't bitcoin's
+    let manifest = elements_pool(&elements_addresses()).unwrap();
+    let json = serde_json::to_string(&manifest).unwrap();
+    assert!(json.contains("\"elements-regtest\""));
+    let manifest: PoolManifest = serde_json::from_str(&json).unwrap();
+    manifest.load_pool().unwrap();
+    assert_eq!(
+        manifest.leaf_version,
+        LeafVersion::from_consensus(ELEMENTS_LEAF_VERSION).unwrap()
+    );
+}
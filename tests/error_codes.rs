@@ -0,0 +1,119 @@
+use bitcoin::{Network, Txid};
+
+use op_ctv_payment_pool::{
+    config::NetworkConfig,
+    error_codes::{coded, error_code, ErrorCode, ErrorReport},
+    esplora::Esplora,
+    ids::UserIndex,
+    lifecycle::{Event, Lifecycle},
+    manifest::PoolManifest,
+    profile::NetworkProfile,
+};
+
+mod common;
+
+use common::{address, pool_manifest};
+
+fn manifest() -> PoolManifest {
+    pool_manifest(&NetworkConfig::new(NetworkProfile::RegtestLocal))
+}
+
+#[test]
+fn lifecycle_refusals_say_why_in_their_code() {
+    let not_funded = Lifecycle::Registered.require_funded().unwrap_err();
+    assert_eq!(error_code(&not_funded), ErrorCode::PoolNotFunded);
+
+    let twice = Lifecycle::Funded.apply(Event::Fund).unwrap_err();
+    assert_eq!(error_code(&twice), ErrorCode::InvalidState);
+    let out_of_order = Lifecycle::Funded
+        .apply(Event::Withdraw(UserIndex::new(3).unwrap()))
+        .unwrap_err();
+    assert_eq!(error_code(&out_of_order), ErrorCode::InvalidState);
+
+    // context on top keeps the code, the report has the whole chain
+    let wrapped = not_funded.context("report");
+    assert_eq!(error_code(&wrapped), ErrorCode::PoolNotFunded);
+    let report = serde_json::to_value(ErrorReport::new(&wrapped)).unwrap();
+    assert_eq!(report["code"], "E_POOL_NOT_FUNDED");
+    assert_eq!(
+        report["error"],
+        "report: the pool is registered, fund it first"
+    );
+}
+
+#[test]
+fn an_edited_manifest_is_a_template_mismatch() {
+    let mut manifest = manifest();
+    manifest.root_address = address(40, Network::Regtest).to_string();
+    let error = manifest.load_pool().err().unwrap();
+    assert_eq!(error_code(&error), ErrorCode::TemplateMismatch);
+}
+
+#[test]
+fn typed_errors_map_to_their_codes() {
+    let mut config = NetworkConfig::new(NetworkProfile::RegtestLocal);
+    config.anchor_amount = Some(bitcoin::Amount::from_sat(1));
+    let invalid = anyhow::Error::from(config.validate().unwrap_err());
+    assert_eq!(error_code(&invalid), ErrorCode::ConfigInvalid);
+
+    let json = anyhow::Error::from(serde_json::from_str::<Lifecycle>("{").unwrap_err());
+    assert_eq!(error_code(&json), ErrorCode::InvalidInput);
+    assert_eq!(
+        error_code(&anyhow::anyhow!("something else")),
+        ErrorCode::Internal
+    );
+    assert_eq!(
+        error_code(&coded(ErrorCode::RateLimited, "slow down")),
+        ErrorCode::RateLimited
+    );
+}
+
+// the codes are an interface: renaming one breaks every script matching on it
+#[test]
+fn every_code_keeps_its_string() {
+    let pinned = [
+        (ErrorCode::ConfigInvalid, "E_CONFIG_INVALID"),
+        (ErrorCode::AddressInvalid, "E_ADDRESS_INVALID"),
+        (ErrorCode::PoolNotFunded, "E_POOL_NOT_FUNDED"),
+        (ErrorCode::InvalidState, "E_INVALID_STATE"),
+        (ErrorCode::TemplateMismatch, "E_TEMPLATE_MISMATCH"),
+        (ErrorCode::BackendUnreachable, "E_BACKEND_UNREACHABLE"),
+        (ErrorCode::BroadcastRejected, "E_BROADCAST_REJECTED"),
+        (ErrorCode::RegistrationClosed, "E_REGISTRATION_CLOSED"),
+        (ErrorCode::RegistrationInvalid, "E_REGISTRATION_INVALID"),
+        (ErrorCode::RateLimited, "E_RATE_LIMITED"),
+        (ErrorCode::NotFound, "E_NOT_FOUND"),
+        (ErrorCode::InvalidInput, "E_INVALID_INPUT"),
+        (ErrorCode::Io, "E_IO"),
+        (ErrorCode::Internal, "E_INTERNAL"),
+    ];
+    assert_eq!(
+        pinned.map(|(code, _)| code),
+        ErrorCode::ALL,
+        "a code without a pinned string"
+    );
+    for (code, string) in pinned {
+        assert_eq!(code.as_str(), string);
+        assert_eq!(code.to_string(), string);
+        assert_eq!(
+            serde_json::to_value(code).unwrap(),
+            serde_json::json!(string)
+        );
+    }
+}
+
+#[tokio::test]
+async fn nothing_listening_is_an_unreachable_backend() {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    drop(listener);
+    let txid: Txid = "5505d34a5a121fd128fb9a0f05d5a0bf8c2bd399aa36456f4a28f481b3211683"
+        .parse()
+        .unwrap();
+    let error = Esplora::new(&url)
+        .unwrap()
+        .tx_status(txid)
+        .await
+        .unwrap_err();
+    assert_eq!(error_code(&error), ErrorCode::BackendUnreachable);
+}
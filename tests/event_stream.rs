@@ -0,0 +1,58 @@
+use op_ctv_payment_pool::{
+    event_stream::EventStream,
+    ids::{PoolId, UserIndex},
+    webhooks::PoolEvent,
+};
+
+fn registered(user: usize) -> PoolEvent {
+    PoolEvent::UserRegistered {
+        user: UserIndex::new(user).unwrap(),
+        registered: user + 1,
+        capacity: 10,
+    }
+}
+
+#[tokio::test]
+async fn a_reconnecting_client_gets_what_it_missed_then_new_events() {
+    let events = EventStream::new();
+    let pool_id: PoolId = "payroll-2026-10".parse().unwrap();
+    for user in 0..3 {
+        events.publish(Some(&pool_id), "", &registered(user), &[]);
+    }
+
+    let (missed, mut receiver) = events.subscribe(Some(1));
+    let ids: Vec<u64> = missed.iter().map(|event| event.id).collect();
+    assert_eq!(ids, [2, 3]);
+    assert_eq!(missed[0].name, "user_registered");
+    // no root address before the pool is built
+    assert!(!missed[0].data.contains("root_address"));
+    assert!(missed[0].data.contains("\"pool_id\""));
+
+    events.publish(
+        Some(&pool_id),
+        "bcrt1pexample",
+        &PoolEvent::PoolBuilt {
+            root_address: "bcrt1pexample".to_string(),
+        },
+        &[],
+    );
+    let built = receiver.recv().await.unwrap();
+    assert_eq!(built.id, 4);
+    assert_eq!(
+        built.to_sse(),
+        format!("id: 4\nevent: pool_built\ndata: {}\n\n", built.data)
+    );
+
+    // a new client gets everything kept
+    let (all, _) = events.subscribe(None);
+    assert_eq!(all.len(), 4);
+}
+
+#[test]
+fn events_without_fields_are_named_too() {
+    let events = EventStream::new();
+    events.publish(None, "bcrt1pexample", &PoolEvent::PoolCompleted, &[]);
+    let (all, _) = events.subscribe(None);
+    assert_eq!(all[0].name, "pool_completed");
+    assert!(all[0].data.contains("bcrt1pexample"));
+}
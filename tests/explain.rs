@@ -0,0 +1,102 @@
+use bitcoin::{
+    absolute::LockTime, hashes::Hash, transaction::Version, OutPoint, Sequence, Transaction, TxIn,
+    TxOut, Txid, Witness,
+};
+use std::{fs, path::PathBuf};
+
+use op_ctv_payment_pool::{
+    config::NetworkConfig,
+    explain::{explain_tx, render_explanation},
+    fund::required_funding,
+    ids::UserIndex,
+    manifest::LoadedPool,
+    pools::pool_spend_template,
+    profile::NetworkProfile,
+    POOL_USERS,
+};
+
+mod common;
+
+use common::loaded_pool;
+
+// Set to rewrite the pinned text from this build, after a change that is meant to move it
+const UPDATE_VAR: &str = "UPDATE_EXPLAIN";
+
+fn fixture_path() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/explain.txt")
+}
+
+// pays the root from a made up coin, so the txids down the unwind never change
+fn funding_tx(pool: &LoadedPool) -> Transaction {
+    Transaction {
+        version: Version::TWO,
+        lock_time: LockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: OutPoint::new(Txid::all_zeros(), 0),
+            script_sig: Default::default(),
+            sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+            witness: Witness::new(),
+        }],
+        output: vec![TxOut {
+            value: required_funding(),
+            script_pubkey: pool
+                .tree
+                .root()
+                .unwrap()
+                .address(&pool.config)
+                .script_pubkey(),
+        }],
+    }
+}
+
+#[test]
+fn explain_output_is_pinned() {
+    let pool = loaded_pool(&NetworkConfig::new(NetworkProfile::RegtestLocal));
+    let funding = funding_tx(&pool);
+    let mut text = String::new();
+    let mut section = |title: &str, tx: &Transaction| {
+        let explanation = explain_tx(&pool, tx).unwrap();
+        text.push_str(&format!("== {}\n", title));
+        text.push_str(&render_explanation(&pool, &explanation));
+        text.push('\n');
+    };
+    section("funding", &funding);
+
+    // the first exit as planned and as broadcast, then the exit pool
+    let mut previous = funding.clone();
+    for user in UserIndex::all().take(POOL_USERS - 1) {
+        let template = pool_spend_template(
+            &pool.tree,
+            &pool.config,
+            pool.backend.as_ref(),
+            user,
+            &pool.addresses,
+            &previous,
+            &pool.anchor_addr,
+        )
+        .unwrap();
+        if user.index() == 0 {
+            section("first exit, template", &template.tx);
+        }
+        let tx = pool.backend.finalize(template).unwrap();
+        if user.index() == 0 {
+            section("first exit, witnessed", &tx);
+        }
+        previous = tx;
+    }
+    section("exit pool", &previous);
+
+    let path = fixture_path();
+    if std::env::var_os(UPDATE_VAR).is_some() {
+        fs::write(&path, &text).unwrap();
+        return;
+    }
+    let pinned = fs::read_to_string(&path).unwrap();
+    assert_eq!(
+        pinned,
+        text,
+        "the explanation changed, set {}=1 to rewrite {} if it is meant to",
+        UPDATE_VAR,
+        path.display()
+    );
+}
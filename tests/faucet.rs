@@ -0,0 +1,88 @@
+use bitcoin::{Amount, Network};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpListener,
+};
+
+use op_ctv_payment_pool::{
+    faucet::{faucet_txid, Faucet},
+    fund::funding_budget,
+    mock::MockBackend,
+};
+
+// a faucet answering one claim, paying `amount` to the mock wallet. Returns its url and the form it got.
+async fn faucet(mock: MockBackend, amount: Amount) -> (String, tokio::task::JoinHandle<String>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}/claim", listener.local_addr().unwrap());
+    let claim = tokio::spawn(async move {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let mut request = Vec::new();
+        let mut buf = [0; 1024];
+        while !String::from_utf8_lossy(&request).contains("address=") {
+            let n = stream.read(&mut buf).await.unwrap();
+            request.extend_from_slice(&buf[..n]);
+        }
+        let txid = mock.add_utxo(amount).txid;
+        let body = format!("Payment of {} sent with txid {}", amount, txid);
+        let response = format!(
+            "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            body.len(),
+            body
+        );
+        stream.write_all(response.as_bytes()).await.unwrap();
+        let request = String::from_utf8_lossy(&request).to_string();
+        request.split("\r\n\r\n").nth(1).unwrap().to_string()
+    });
+    (url, claim)
+}
+
+#[test]
+fn faucet_replies_give_the_txid() {
+    let txid = "5505d34a5a121fd128fb9a0f05d5a0bf8c2bd399aa36456f4a28f481b3211683";
+    assert_eq!(
+        faucet_txid(&format!("Payment of 0.001 BTC sent with txid {}", txid)),
+        Some(txid.parse().unwrap())
+    );
+    assert_eq!(faucet_txid("Rate limited, try again in 3 hours"), None);
+}
+
+#[tokio::test]
+async fn an_empty_wallet_is_topped_up_from_the_faucet() {
+    let mock = MockBackend::new(Network::Signet);
+    let rpc = mock.rpc();
+    let (url, claim) = faucet(mock.clone(), Amount::from_sat(100_000_000)).await;
+    let faucet = Faucet::new(&url, Some("hunter2".to_string()));
+
+    faucet
+        .fund_wallet(&rpc, Network::Signet, funding_budget().unwrap())
+        .await
+        .unwrap();
+    let form = claim.await.unwrap();
+    assert!(form.starts_with("address=tb1p"), "{}", form);
+    assert!(form.ends_with("&password=hunter2"), "{}", form);
+    // the coins arrived unconfirmed, the funding tx can spend them
+    assert_eq!(mock.mempool().len(), 1);
+
+    // enough already, the faucet isn't asked again
+    faucet
+        .fund_wallet(&rpc, Network::Signet, funding_budget().unwrap())
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn a_faucet_paying_too_little_says_how_much_is_missing() {
+    let mock = MockBackend::new(Network::Signet);
+    let rpc = mock.rpc();
+    let (url, _) = faucet(mock.clone(), Amount::from_sat(10_000)).await;
+
+    let error = Faucet::new(&url, None)
+        .fund_wallet(&rpc, Network::Signet, funding_budget().unwrap())
+        .await
+        .unwrap_err();
+    assert!(
+        error.to_string().contains("more, send it to tb1p"),
+        "{}",
+        error
+    );
+}
@@ -0,0 +1,123 @@
+use bitcoin::{hashes::Hash, Amount, OutPoint, ScriptBuf, TxOut, Txid};
+use std::path::PathBuf;
+
+use op_ctv_payment_pool::{
+    fee_bump::{
+        append_journal, next_bump, read_journal, BumpAction, BumpRecord, BumpedChild,
+        FeeBumpPolicy, PendingSpend,
+    },
+    pools::FeeCoin,
+};
+
+fn policy(max_fee: u64) -> FeeBumpPolicy {
+    FeeBumpPolicy {
+        after_blocks: 3,
+        max_fee: Amount::from_sat(max_fee),
+        journal: PathBuf::from("unused.jsonl"),
+    }
+}
+
+// 200 vB paying 1 sat/vB, broadcast at height 100
+fn spend() -> PendingSpend {
+    PendingSpend {
+        txid: Txid::all_zeros(),
+        label: "user 0".to_string(),
+        vsize: 200,
+        fee: Amount::from_sat(200),
+        broadcast_height: 100,
+        since: 100,
+        child: None,
+        budget_spent: false,
+    }
+}
+
+fn with_child(mut spend: PendingSpend, fee: u64) -> PendingSpend {
+    spend.child = Some(BumpedChild {
+        txid: Txid::all_zeros(),
+        fee: Amount::from_sat(fee),
+        fee_coin: FeeCoin {
+            outpoint: OutPoint::null(),
+            txout: TxOut {
+                value: Amount::from_sat(100_000),
+                script_pubkey: ScriptBuf::new(),
+            },
+        },
+    });
+    spend
+}
+
+#[test]
+fn stuck_spends_get_a_child_then_replacements() {
+    let policy = policy(5_000);
+    // not stuck long enough yet
+    assert_eq!(next_bump(&policy, &spend(), 102, 0), None);
+
+    // the package goes from ~0.6 sat/vB to 2 sat/vB
+    assert_eq!(
+        next_bump(&policy, &spend(), 103, 0),
+        Some((BumpAction::Child, Amount::from_sat(424)))
+    );
+    // then doubles, or goes straight to the estimate when the node expects more
+    let bumped = with_child(spend(), 424);
+    assert_eq!(
+        next_bump(&policy, &bumped, 103, 0),
+        Some((BumpAction::Replace, Amount::from_sat(1_048)))
+    );
+    assert_eq!(
+        next_bump(&policy, &bumped, 103, 10_000),
+        Some((BumpAction::Replace, Amount::from_sat(2_920)))
+    );
+}
+
+#[test]
+fn bumps_stop_at_the_budget() {
+    let policy = policy(1_000);
+    let bumped = with_child(spend(), 424);
+    assert_eq!(
+        next_bump(&policy, &bumped, 103, 0),
+        Some((BumpAction::Replace, Amount::from_sat(1_000)))
+    );
+
+    // a replacement has to pay more than the child it replaces, the budget doesn't allow it
+    let spent = with_child(spend(), 1_000);
+    assert_eq!(
+        next_bump(&policy, &spent, 103, 0),
+        Some((BumpAction::BudgetSpent, Amount::from_sat(1_000)))
+    );
+    let mut spent = spent;
+    spent.budget_spent = true;
+    assert_eq!(next_bump(&policy, &spent, 200, 0), None);
+}
+
+#[test]
+fn decisions_are_appended_to_the_journal() {
+    let path = std::env::temp_dir().join(format!("pool-journal-{}.jsonl", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let record = BumpRecord {
+        time: 1_700_000_000,
+        height: 103,
+        spend: Txid::all_zeros(),
+        label: "user 0".to_string(),
+        action: BumpAction::Child,
+        blocks_unconfirmed: 3,
+        child: Some(Txid::all_zeros()),
+        replaced: None,
+        fee: Amount::from_sat(424),
+        package_fee_rate: 2_000,
+    };
+    let replaced = BumpRecord {
+        action: BumpAction::Replace,
+        replaced: record.child,
+        fee: Amount::from_sat(1_048),
+        ..record.clone()
+    };
+    append_journal(&path, &record).unwrap();
+    append_journal(&path, &replaced).unwrap();
+
+    let journal = read_journal(&path).unwrap();
+    assert_eq!(journal, vec![record, replaced]);
+    assert!(std::fs::read_to_string(&path)
+        .unwrap()
+        .contains("\"action\":\"replace\""));
+    std::fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,214 @@
+use bitcoin::Amount;
+use std::sync::Arc;
+
+use op_ctv_payment_pool::{
+    config::{NetworkConfig, FEE_AMOUNT},
+    fee_policy::{parse_fee_policy, AnchorOnly, FeePolicy, FeeRateFee, FixedFee, TemplateInfo},
+    ids::{NodePath, UserIndex},
+    manifest::PoolManifest,
+    payouts::user_share,
+    pools::{build_pools, node_exit},
+    profile::NetworkProfile,
+    tree::PoolTree,
+    POOL_USERS,
+};
+
+mod common;
+
+use common::{addresses, anchor, backend};
+
+// what `spender` is paid for leaving the node of `users`
+fn exit_payout(
+    tree: &PoolTree,
+    config: &NetworkConfig,
+    users: &NodePath,
+    spender: UserIndex,
+) -> Amount {
+    let addresses = addresses(config.network);
+    let exit = node_exit(
+        tree,
+        config,
+        &backend(config),
+        &addresses,
+        &anchor(config),
+        users,
+        spender,
+    )
+    .unwrap();
+    let script = addresses[spender.index()].script_pubkey();
+    exit.outputs
+        .iter()
+        .find(|output| output.script_pubkey == script)
+        .unwrap()
+        .value
+}
+
+// more for every user left in the node
+#[derive(Debug)]
+struct PerUser;
+
+impl FeePolicy for PerUser {
+    fn fee_for(&self, template: &TemplateInfo) -> Amount {
+        Amount::from_sat(100 * template.spent.len() as u64) + template.anchor.unwrap_or_default()
+    }
+
+    fn spec(&self) -> String {
+        "per-user:100".to_string()
+    }
+}
+
+#[test]
+fn the_default_policy_is_the_fixed_fee() {
+    let config = NetworkConfig::new(NetworkProfile::RegtestLocal);
+    assert_eq!(
+        config.fee_policy.spec(),
+        format!("fixed:{}", FEE_AMOUNT.to_sat())
+    );
+    let tree = build_pools(
+        &addresses(config.network),
+        &anchor(&config),
+        &config,
+        &backend(&config),
+    )
+    .unwrap();
+    let user = UserIndex::new(0).unwrap();
+    assert_eq!(
+        exit_payout(&tree, &config, &NodePath::root(), user),
+        user_share(user, &config) - FEE_AMOUNT
+    );
+}
+
+#[test]
+fn fee_rates_charge_bigger_templates_more() {
+    let mut config = NetworkConfig::new(NetworkProfile::RegtestLocal);
+    config.fee_policy = Arc::new(FeeRateFee { sat_per_vb: 10 });
+    let tree = build_pools(
+        &addresses(config.network),
+        &anchor(&config),
+        &config,
+        &backend(&config),
+    )
+    .unwrap();
+    let user = UserIndex::new(0).unwrap();
+    let root_fee = user_share(user, &config) - exit_payout(&tree, &config, &NodePath::root(), user);
+    let exit_pool = NodePath::new(vec![0, 1]).unwrap();
+    let exit_fee = user_share(UserIndex::new(1).unwrap(), &config)
+        - exit_payout(&tree, &config, &exit_pool, UserIndex::new(1).unwrap());
+    assert!(root_fee > exit_fee, "{} {}", root_fee, exit_fee);
+    // the estimate is an upper bound, never under the rate
+    assert!(root_fee >= config.anchor_amount.unwrap() + Amount::from_sat(10 * 150));
+
+    // a manifest rebuilds it from the spec
+    let addresses = addresses(config.network);
+    let root = tree.root().unwrap().address(&config);
+    let manifest = PoolManifest::new(
+        &config,
+        &backend(&config),
+        &anchor(&config),
+        &addresses,
+        &root,
+    );
+    assert_eq!(manifest.fee_policy, "feerate:10");
+    let pool = manifest.load_pool().unwrap();
+    assert_eq!(pool.tree.root().unwrap().address(&pool.config), root);
+}
+
+#[test]
+fn zero_fee_templates_need_an_anchor() {
+    let mut config = NetworkConfig::new(NetworkProfile::RegtestLocal);
+    config.fee_policy = Arc::new(AnchorOnly);
+    let tree = build_pools(
+        &addresses(config.network),
+        &anchor(&config),
+        &config,
+        &backend(&config),
+    )
+    .unwrap();
+    // the user only funds the anchor
+    let user = UserIndex::new(0).unwrap();
+    assert_eq!(
+        exit_payout(&tree, &config, &NodePath::root(), user),
+        user_share(user, &config) - config.anchor_amount.unwrap()
+    );
+
+    config.anchor_amount = None;
+    let error = build_pools(
+        &addresses(config.network),
+        &anchor(&config),
+        &config,
+        &backend(&config),
+    )
+    .unwrap_err();
+    assert!(error.to_string().contains("no anchor"), "{}", error);
+}
+
+#[test]
+fn a_fee_eating_the_whole_exit_is_refused() {
+    let mut config = NetworkConfig::new(NetworkProfile::RegtestLocal);
+    config.fee_policy = parse_fee_policy("feerate:1000").unwrap();
+    assert!(build_pools(
+        &addresses(config.network),
+        &anchor(&config),
+        &config,
+        &backend(&config),
+    )
+    .is_err());
+
+    assert!(parse_fee_policy("fixed").is_err());
+    assert!(parse_fee_policy("percent:1").is_err());
+    assert_eq!(
+        parse_fee_policy("anchor-only").unwrap().spec(),
+        "anchor-only"
+    );
+}
+
+#[test]
+fn a_fee_above_the_share_is_an_error_not_an_underflow() {
+    let mut config = NetworkConfig::new(NetworkProfile::RegtestLocal);
+    let addresses = addresses(config.network);
+    let tree = build_pools(&addresses, &anchor(&config), &config, &backend(&config)).unwrap();
+    // past validate and build_pools, straight to one exit
+    let share = user_share(UserIndex::new(0).unwrap(), &config);
+    config.fee_policy = Arc::new(FixedFee(share + Amount::from_sat(1)));
+    let spender = UserIndex::new(POOL_USERS - 2).unwrap();
+    let users = NodePath::unwind(spender).unwrap();
+    let error = node_exit(
+        &tree,
+        &config,
+        &backend(&config),
+        &addresses,
+        &anchor(&config),
+        &users,
+        spender,
+    )
+    .expect_err("the fee is more than the exit");
+    assert!(error.to_string().contains("fee policy"), "{}", error);
+}
+
+#[test]
+fn custom_policies_rebuild_with_the_policy_on_the_config() {
+    let mut config = NetworkConfig::new(NetworkProfile::RegtestLocal);
+    config.fee_policy = Arc::new(PerUser);
+    let addresses = addresses(config.network);
+    let tree = build_pools(&addresses, &anchor(&config), &config, &backend(&config)).unwrap();
+    let user = UserIndex::new(0).unwrap();
+    assert_eq!(
+        exit_payout(&tree, &config, &NodePath::root(), user),
+        user_share(user, &config)
+            - Amount::from_sat(100 * POOL_USERS as u64)
+            - config.anchor_amount.unwrap()
+    );
+
+    let root = tree.root().unwrap().address(&config);
+    let manifest = PoolManifest::new(
+        &config,
+        &backend(&config),
+        &anchor(&config),
+        &addresses,
+        &root,
+    );
+    // the built in policies don't know it
+    assert!(manifest.load_pool().is_err());
+    let pool = manifest.rebuild_pool(config.clone()).unwrap();
+    assert_eq!(pool.tree.root().unwrap().address(&pool.config), root);
+}
@@ -0,0 +1,106 @@
+use bitcoin::{hashes::Hash, Amount, Network, OutPoint, Txid};
+use bitcoincore_rpc::RpcApi;
+
+use op_ctv_payment_pool::{fetch::RpcFetcher, mock::MockBackend};
+
+#[tokio::test]
+async fn blocks_come_back_in_order_over_several_connections() {
+    let mock = MockBackend::new(Network::Regtest);
+    let hashes = mock.mine(23);
+    // batches of 2 over 3 connections, so each connection sends several
+    let fetcher = RpcFetcher::new(vec![mock.rpc(), mock.rpc(), mock.rpc()], 2).unwrap();
+    assert_eq!(fetcher.window(), 6);
+
+    let blocks = fetcher.blocks(1..=23).await.unwrap();
+    assert_eq!(blocks.len(), 23);
+    for ((height, hash, block), expected) in blocks.iter().zip(&hashes) {
+        assert_eq!(hash, expected);
+        assert_eq!(block.block_hash(), *expected);
+        assert_eq!(*expected, hashes[*height as usize - 1]);
+    }
+
+    // a scan hands out the same blocks one at a time and stops at the end
+    let mut scan = fetcher.scan(20..=23);
+    let mut heights = Vec::new();
+    while let Some((height, hash, _)) = scan.next_block().await.unwrap() {
+        assert_eq!(hash, hashes[height as usize - 1]);
+        heights.push(height);
+    }
+    assert_eq!(heights, vec![20, 21, 22, 23]);
+    // nothing new since the last scan at the tip
+    let tip = u64::from(mock.height());
+    assert!(fetcher
+        .scan(tip + 1..=tip)
+        .next_block()
+        .await
+        .unwrap()
+        .is_none());
+
+    // past the tip
+    assert!(fetcher.blocks(20..=24).await.is_err());
+}
+
+#[tokio::test]
+async fn unknown_txs_and_spent_outputs_are_left_out() {
+    let mock = MockBackend::new(Network::Regtest);
+    let coins: Vec<OutPoint> = (1..=5)
+        .map(|i| mock.add_utxo(Amount::from_sat(i * 10_000)))
+        .collect();
+    mock.mine(1);
+    let fetcher = RpcFetcher::new(vec![mock.rpc()], 2).unwrap();
+
+    let unknown = Txid::all_zeros();
+    let mut txids: Vec<Txid> = coins.iter().map(|coin| coin.txid).collect();
+    txids.push(unknown);
+    txids.push(coins[0].txid);
+    let txs = fetcher.transactions(&txids).await.unwrap();
+    assert_eq!(txs.len(), 5);
+    assert!(!txs.contains_key(&unknown));
+    assert_eq!(
+        txs[&coins[2].txid].output[0].value,
+        Amount::from_sat(30_000)
+    );
+
+    let mut outpoints = coins.clone();
+    outpoints.insert(1, OutPoint::new(unknown, 0));
+    let outs = fetcher.tx_outs(&outpoints, true).await.unwrap();
+    assert_eq!(outs.len(), 6);
+    assert!(outs[1].is_none());
+    assert_eq!(outs[0].as_ref().unwrap().value, Amount::from_sat(10_000));
+    assert_eq!(outs[5].as_ref().unwrap().value, Amount::from_sat(50_000));
+    assert_eq!(outs[5].as_ref().unwrap().confirmations, 1);
+
+    assert!(RpcFetcher::new(Vec::new(), 2).is_err());
+}
+
+#[tokio::test]
+async fn verbose_lookups_carry_the_confirmations() {
+    let mock = MockBackend::new(Network::Regtest);
+    let confirmed = mock.add_utxo(Amount::from_sat(10_000));
+    mock.mine(3);
+    let pending = mock.add_utxo(Amount::from_sat(20_000));
+    let fetcher = RpcFetcher::new(vec![mock.rpc(), mock.rpc()], 1).unwrap();
+
+    let unknown = Txid::all_zeros();
+    let infos = fetcher
+        .transaction_infos(&[confirmed.txid, unknown, pending.txid])
+        .await
+        .unwrap();
+    assert_eq!(infos.len(), 2);
+    assert_eq!(infos[&confirmed.txid].confirmations, Some(3));
+    assert_eq!(infos[&pending.txid].confirmations, None);
+    assert_eq!(
+        infos[&pending.txid].transaction().unwrap().output[0].value,
+        Amount::from_sat(20_000)
+    );
+
+    let hashes = fetcher.block_hashes(&[3, 1]).await.unwrap();
+    assert_eq!(
+        hashes[0],
+        mock.rpc().run(|c| c.get_block_hash(3)).await.unwrap()
+    );
+    assert_eq!(
+        hashes[1],
+        mock.rpc().run(|c| c.get_block_hash(1)).await.unwrap()
+    );
+}
@@ -0,0 +1,43 @@
+use op_ctv_payment_pool::{
+    config::NetworkConfig,
+    formats::{formats, incompatibilities, require_compatible, STATE_CHANGELOG},
+    profile::NetworkProfile,
+    state::STATE_VERSION,
+};
+
+#[test]
+fn every_state_version_is_in_the_changelog() {
+    let versions: Vec<u16> = STATE_CHANGELOG
+        .iter()
+        .map(|(version, _)| *version)
+        .collect();
+    assert_eq!(versions, (1..=STATE_VERSION).collect::<Vec<_>>());
+}
+
+#[test]
+fn a_build_derives_the_same_pools_as_itself() {
+    let config = NetworkConfig::new(NetworkProfile::RegtestLocal);
+    let ours = formats(&config).unwrap();
+    // derived twice, the same
+    assert_eq!(ours, formats(&config).unwrap());
+    let json: String = serde_json::to_string(&ours).unwrap();
+    let theirs = serde_json::from_str(&json).unwrap();
+    require_compatible(&ours, &theirs).unwrap();
+}
+
+#[test]
+fn a_different_root_or_newer_state_is_incompatible() {
+    let ours = formats(&NetworkConfig::new(NetworkProfile::RegtestLocal)).unwrap();
+    let mut theirs = ours.clone();
+    theirs.derivations[1].root_address = theirs.derivations[0].root_address.clone();
+    theirs.state_version += 1;
+    theirs.template_version = 2;
+    let problems = incompatibilities(&ours, &theirs);
+    assert_eq!(problems.len(), 3, "{:?}", problems);
+    assert!(problems[0].contains("template v2"));
+    assert!(problems[1].contains("share them as json"));
+    assert!(problems[2].contains("derives root"));
+
+    let signet = formats(&NetworkConfig::new(NetworkProfile::SignetPublic)).unwrap();
+    assert!(require_compatible(&ours, &signet).is_err());
+}
@@ -0,0 +1,81 @@
+use bitcoin::{Address, Amount, Network, ScriptBuf, TxOut};
+use std::str::FromStr;
+
+use op_ctv_payment_pool::{
+    config::DUST_AMOUNT,
+    fund::required_funding,
+    rpc_helper::{funding_outputs, FundingDestination, STAGING_SPEND_VSIZE},
+};
+
+const FEE: Amount = Amount::from_sat(1_234);
+
+fn destination() -> TxOut {
+    TxOut {
+        value: required_funding(),
+        script_pubkey: ScriptBuf::new_op_return([1; 8]),
+    }
+}
+
+fn change_spk() -> ScriptBuf {
+    ScriptBuf::new_op_return([2; 8])
+}
+
+// what the tx actually leaves to the miner
+fn implied_fee(total_input: Amount, outputs: &[TxOut]) -> Amount {
+    total_input - outputs.iter().map(|output| output.value).sum::<Amount>()
+}
+
+#[test]
+fn change_gets_exactly_what_is_left_after_the_fee() {
+    for extra in [DUST_AMOUNT, Amount::from_sat(10_000), Amount::ONE_BTC] {
+        let total_input = required_funding() + FEE + extra;
+        let outputs = funding_outputs(total_input, destination(), FEE, change_spk()).unwrap();
+
+        assert_eq!(outputs.len(), 2, "extra {}", extra);
+        assert_eq!(outputs[0], destination());
+        assert_eq!(outputs[1].value, extra);
+        assert_eq!(outputs[1].script_pubkey, change_spk());
+        assert_eq!(implied_fee(total_input, &outputs), FEE);
+    }
+}
+
+#[test]
+fn dust_change_goes_to_the_fee() {
+    for extra in [Amount::ZERO, Amount::ONE_SAT, DUST_AMOUNT - Amount::ONE_SAT] {
+        let total_input = required_funding() + FEE + extra;
+        let outputs = funding_outputs(total_input, destination(), FEE, change_spk()).unwrap();
+
+        assert_eq!(outputs, vec![destination()], "extra {}", extra);
+        assert_eq!(implied_fee(total_input, &outputs), FEE + extra);
+    }
+}
+
+#[test]
+fn one_sat_short_of_the_fee_is_refused() {
+    let total_input = required_funding() + FEE - Amount::ONE_SAT;
+    assert!(funding_outputs(total_input, destination(), FEE, change_spk()).is_err());
+    assert!(funding_outputs(required_funding(), destination(), FEE, change_spk()).is_err());
+}
+
+#[test]
+fn staging_output_carries_the_fee_of_its_spend() {
+    let address = Address::from_str("bcrt1pfeesnyr2tx")
+        .unwrap()
+        .require_network(Network::Regtest)
+        .unwrap();
+    let fee_rate = 5_000;
+
+    let entry = FundingDestination::EntryPool(address.clone())
+        .output(fee_rate)
+        .unwrap();
+    assert_eq!(entry.value, required_funding());
+    assert_eq!(entry.script_pubkey, address.script_pubkey());
+
+    let staging = FundingDestination::Staging(address)
+        .output(fee_rate)
+        .unwrap();
+    assert_eq!(
+        staging.value,
+        required_funding() + Amount::from_sat(fee_rate * STAGING_SPEND_VSIZE / 1000)
+    );
+}
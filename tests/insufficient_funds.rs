@@ -0,0 +1,73 @@
+use bitcoin::{Address, Amount, Network, ScriptBuf, TxOut};
+use std::str::FromStr;
+
+use op_ctv_payment_pool::{
+    config::DUST_AMOUNT,
+    pools::{cpfp_change, node_amount},
+    rpc_helper::{fee_for_vsize, funding_outputs, FundingDestination},
+    AMOUNT_PER_USER,
+};
+
+fn destination(value: Amount) -> TxOut {
+    TxOut {
+        value,
+        script_pubkey: ScriptBuf::new_op_return([1; 8]),
+    }
+}
+
+#[test]
+fn empty_wallet_is_an_error_not_a_panic() {
+    let outputs = funding_outputs(
+        Amount::ZERO,
+        destination(AMOUNT_PER_USER),
+        Amount::from_sat(500),
+        ScriptBuf::new(),
+    );
+    assert!(outputs.is_err());
+}
+
+#[test]
+fn fee_overflowing_the_destination_is_an_error() {
+    let outputs = funding_outputs(
+        Amount::MAX_MONEY,
+        destination(Amount::MAX),
+        Amount::ONE_SAT,
+        ScriptBuf::new(),
+    );
+    assert!(outputs.is_err());
+}
+
+#[test]
+fn fee_rate_overflow_is_an_error() {
+    assert!(fee_for_vsize(u64::MAX, 2).is_err());
+    assert_eq!(fee_for_vsize(5_000, 150).unwrap(), Amount::from_sat(750));
+
+    let address = Address::from_str("bcrt1pfeesnyr2tx")
+        .unwrap()
+        .require_network(Network::Regtest)
+        .unwrap();
+    assert!(FundingDestination::Staging(address.clone())
+        .output(u64::MAX)
+        .is_err());
+    // the entry pool output never carries a fee
+    assert!(FundingDestination::EntryPool(address)
+        .output(u64::MAX)
+        .is_ok());
+}
+
+#[test]
+fn anchor_child_needs_the_fee_plus_dust() {
+    let fee = Amount::from_sat(1_000);
+    assert_eq!(cpfp_change(fee + DUST_AMOUNT, fee).unwrap(), DUST_AMOUNT);
+    assert!(cpfp_change(fee + DUST_AMOUNT - Amount::ONE_SAT, fee).is_err());
+    assert!(cpfp_change(fee, fee).is_err());
+    assert!(cpfp_change(fee - Amount::ONE_SAT, fee).is_err());
+    assert!(cpfp_change(Amount::ZERO, fee).is_err());
+}
+
+#[test]
+fn node_amount_is_checked() {
+    assert_eq!(node_amount(3).unwrap(), AMOUNT_PER_USER * 3);
+    assert_eq!(node_amount(0).unwrap(), Amount::ZERO);
+    assert!(node_amount(usize::MAX).is_err());
+}
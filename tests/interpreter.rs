@@ -0,0 +1,207 @@
+use bitcoin::{
+    absolute,
+    hashes::Hash,
+    hex::DisplayHex,
+    key::Secp256k1,
+    opcodes::all::{OP_CHECKSIG, OP_CSV, OP_DROP, OP_RESERVED},
+    script::Builder,
+    secp256k1::Message,
+    sighash::{Prevouts, SighashCache, TapSighashType},
+    taproot::{LeafVersion, TapLeafHash, TaprootBuilder},
+    transaction, Amount, Network, OutPoint, ScriptBuf, Sequence, Transaction, TxIn, TxOut, Witness,
+    XOnlyPublicKey,
+};
+use std::str::FromStr;
+
+use op_ctv_payment_pool::{
+    config::NetworkConfig,
+    ctv_scripts::{ctv_hash, NUMS_INTERNAL_KEY},
+    interpreter::{template_hash, verify_input},
+    manifest::PoolManifest,
+    profile::NetworkProfile,
+    verify::{execute_pool_scripts, ExecutedLeaf},
+    POOL_USERS,
+};
+
+mod common;
+
+use common::{address, keypair, pool_manifest};
+
+fn manifest() -> PoolManifest {
+    pool_manifest(&NetworkConfig::new(NetworkProfile::RegtestLocal))
+}
+
+// the spend of a single leaf `script` output, the witness stack before the script given
+fn leaf_spend(script: &ScriptBuf, sequence: Sequence) -> (Transaction, TxOut) {
+    let secp = Secp256k1::new();
+    let spend_info = TaprootBuilder::new()
+        .add_leaf(0, script.clone())
+        .unwrap()
+        .finalize(&secp, XOnlyPublicKey::from_str(NUMS_INTERNAL_KEY).unwrap())
+        .unwrap();
+    let prevout = TxOut {
+        value: Amount::from_sat(10_000),
+        script_pubkey: ScriptBuf::new_p2tr_tweaked(spend_info.output_key()),
+    };
+    let mut witness = Witness::new();
+    witness.push(script.as_bytes());
+    witness.push(
+        spend_info
+            .control_block(&(script.clone(), LeafVersion::TapScript))
+            .unwrap()
+            .serialize(),
+    );
+    let tx = Transaction {
+        version: transaction::Version::TWO,
+        lock_time: absolute::LockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: OutPoint::null(),
+            sequence,
+            witness,
+            ..Default::default()
+        }],
+        output: vec![TxOut {
+            value: Amount::from_sat(9_000),
+            script_pubkey: address(8, Network::Regtest).script_pubkey(),
+        }],
+    };
+    (tx, prevout)
+}
+
+#[test]
+fn every_templated_spend_of_a_pool_executes() {
+    let manifest = manifest();
+    let executions = execute_pool_scripts(&manifest, OutPoint::null()).unwrap();
+    let failed: Vec<_> = executions.iter().filter(|e| e.error.is_some()).collect();
+    assert!(failed.is_empty(), "{:?}", failed);
+    assert!(executions.iter().all(|e| !e.skipped));
+    // every user's leaf of the root
+    let root_exits = executions
+        .iter()
+        .filter(|e| e.node.is_root() && matches!(e.leaf, ExecutedLeaf::Exit { .. }))
+        .count();
+    assert_eq!(root_exits, POOL_USERS);
+}
+
+#[test]
+fn a_tx_off_its_template_fails_op_ctv() {
+    let template = [7; 32];
+    let script = Builder::new()
+        .push_slice(template)
+        .push_opcode(bitcoin::opcodes::all::OP_NOP4)
+        .into_script();
+    let (tx, prevout) = leaf_spend(&script, Sequence::MAX);
+    let error = verify_input(&tx, 0, &[prevout]).unwrap_err();
+    assert!(error.to_string().contains("OP_CTV"), "{}", error);
+}
+
+#[test]
+fn op_csv_checks_the_input_sequence() {
+    let script = Builder::new()
+        .push_int(10)
+        .push_opcode(OP_CSV)
+        .push_opcode(OP_DROP)
+        .push_int(1)
+        .into_script();
+    let (tx, prevout) = leaf_spend(&script, Sequence::from_height(10));
+    verify_input(&tx, 0, &[prevout]).unwrap();
+    let (tx, prevout) = leaf_spend(&script, Sequence::from_height(9));
+    let error = verify_input(&tx, 0, &[prevout]).unwrap_err();
+    assert!(error.to_string().contains("only waits 9"), "{}", error);
+}
+
+#[test]
+fn signatures_are_checked_against_the_script_path_sighash() {
+    let secp = Secp256k1::new();
+    let key = keypair(2);
+    let script = Builder::new()
+        .push_x_only_key(&key.x_only_public_key().0)
+        .push_opcode(OP_CHECKSIG)
+        .into_script();
+    let (mut tx, prevout) = leaf_spend(&script, Sequence::MAX);
+    let sighash = SighashCache::new(&tx)
+        .taproot_script_spend_signature_hash(
+            0,
+            &Prevouts::All(std::slice::from_ref(&prevout)),
+            TapLeafHash::from_script(&script, LeafVersion::TapScript),
+            TapSighashType::Default,
+        )
+        .unwrap();
+    let signature = secp.sign_schnorr(&Message::from_digest(sighash.to_byte_array()), &key);
+    let mut witness = Witness::new();
+    witness.push(signature.as_ref());
+    for item in tx.input[0].witness.iter() {
+        witness.push(item);
+    }
+    tx.input[0].witness = witness;
+    verify_input(&tx, 0, std::slice::from_ref(&prevout)).unwrap();
+
+    // the signature no longer covers the outputs
+    tx.output[0].value -= Amount::from_sat(1);
+    assert!(verify_input(&tx, 0, &[prevout]).is_err());
+}
+
+#[test]
+fn an_op_success_leaf_is_refused() {
+    let script = Builder::new()
+        .push_int(1)
+        .push_opcode(OP_RESERVED)
+        .into_script();
+    let (tx, prevout) = leaf_spend(&script, Sequence::MAX);
+    let error = verify_input(&tx, 0, &[prevout]).unwrap_err();
+    assert!(
+        error.to_string().contains("spendable by anyone"),
+        "{}",
+        error
+    );
+}
+
+// A two input tx hashed by hand from BIP-119's DefaultCheckTemplateVerifyHash, not by either
+// implementation here: version 2, locktime 16, sequences fffffffd and 10, paying 1000 sat to OP_TRUE
+// and 2000 sat to a p2wpkh of 0x11 bytes
+#[test]
+fn template_hashes_follow_bip_119() {
+    let mut tx = Transaction {
+        version: transaction::Version::TWO,
+        lock_time: absolute::LockTime::from_consensus(16),
+        input: [Sequence::ENABLE_RBF_NO_LOCKTIME, Sequence(16)]
+            .into_iter()
+            .map(|sequence| TxIn {
+                sequence,
+                ..Default::default()
+            })
+            .collect(),
+        output: vec![
+            TxOut {
+                value: Amount::from_sat(1000),
+                script_pubkey: ScriptBuf::from_bytes(vec![0x51]),
+            },
+            TxOut {
+                value: Amount::from_sat(2000),
+                script_pubkey: ScriptBuf::new_p2wpkh(&bitcoin::WPubkeyHash::from_byte_array(
+                    [0x11; 20],
+                )),
+            },
+        ],
+    };
+    let vectors = [
+        (
+            0,
+            "c90376a18df2f3ada33c9278282d96f785711bb7b0230005e727f4bff92ab7b9",
+        ),
+        (
+            1,
+            "a9c77d58f085640e58af255ca0826ad8c3fecbf63e762424d8de3dce659f3c51",
+        ),
+    ];
+    for (input, hash) in vectors {
+        assert_eq!(template_hash(&tx, input).to_lower_hex_string(), hash);
+        assert_eq!(ctv_hash(&tx, input).to_lower_hex_string(), hash);
+    }
+
+    // a scriptSig on any input commits to all of them
+    tx.input[0].script_sig = ScriptBuf::from_bytes(vec![1, 2]);
+    let hash = "30c79a1cb87e2350717118aee35da61cf9ca88926708888bb7ccfe5804481248";
+    assert_eq!(template_hash(&tx, 0).to_lower_hex_string(), hash);
+    assert_eq!(ctv_hash(&tx, 0).to_lower_hex_string(), hash);
+}
@@ -0,0 +1,141 @@
+use bitcoin::{Amount, Network};
+use rand::{rngs::StdRng, SeedableRng};
+
+use op_ctv_payment_pool::{
+    config::{NetworkConfig, FEE_AMOUNT},
+    ids::{NodePath, UserIndex},
+    manifest::PoolManifest,
+    payouts::{check_payout_jitter, draw_payout_jitter, user_share},
+    pools::{build_pools, node_exit},
+    privacy::privacy_report,
+    profile::NetworkProfile,
+    state::{decode_state, encode_state, STATE_VERSION},
+    AMOUNT_PER_USER, POOL_USERS,
+};
+
+mod common;
+
+use common::{addresses, anchor, backend};
+
+fn config() -> NetworkConfig {
+    let mut config = NetworkConfig::new(NetworkProfile::RegtestLocal);
+    config.payout_jitter = draw_payout_jitter(
+        Amount::from_sat(500),
+        POOL_USERS,
+        &mut StdRng::seed_from_u64(7),
+    );
+    config
+}
+
+#[test]
+fn jitter_cancels_out_within_the_max() {
+    let mut rng = StdRng::seed_from_u64(1);
+    for max in [1, 10, 500, 2_000] {
+        for users in [1, 2, 5, 16] {
+            let jitter = draw_payout_jitter(Amount::from_sat(max), users, &mut rng);
+            assert_eq!(jitter.len(), users);
+            assert_eq!(jitter.iter().sum::<i64>(), 0);
+            assert!(jitter.iter().all(|sats| sats.unsigned_abs() <= max));
+        }
+    }
+}
+
+#[test]
+fn jittered_users_leave_with_their_own_share() {
+    let config = config();
+    let backend = backend(&config);
+    let addresses = addresses(config.network);
+    let anchor_addr = anchor(&config);
+    let tree = build_pools(&addresses, &anchor_addr, &config, &backend).unwrap();
+
+    // the pool still holds AMOUNT_PER_USER for every user, a node the shares of its users
+    assert_eq!(
+        tree.root().unwrap().amount,
+        AMOUNT_PER_USER * POOL_USERS as u64
+    );
+    let users = NodePath::new(vec![0, 2, 3]).unwrap();
+    let shares: Amount = users
+        .user_indices()
+        .map(|user| user_share(user, &config))
+        .sum();
+    assert_eq!(tree.node(&users).unwrap().amount, shares);
+
+    let mut payouts = Vec::new();
+    for spender in UserIndex::all() {
+        let exit = node_exit(
+            &tree,
+            &config,
+            &backend,
+            &addresses,
+            &anchor_addr,
+            &NodePath::root(),
+            spender,
+        )
+        .unwrap();
+        let payout = exit.outputs[1].value;
+        assert_eq!(payout, user_share(spender, &config) - FEE_AMOUNT);
+        payouts.push(payout);
+    }
+    payouts.sort();
+    payouts.dedup();
+    assert!(payouts.len() > 1, "{:?}", payouts);
+
+    let report = privacy_report(&tree, &config, &backend, &addresses, &anchor_addr).unwrap();
+    assert!(!report
+        .findings
+        .iter()
+        .any(|finding| finding.kind == "uniform_amounts"));
+}
+
+#[test]
+fn jitter_that_does_not_cancel_out_or_leaves_dust_is_refused() {
+    let addresses = addresses(Network::Regtest);
+    let mut config = config();
+    assert!(check_payout_jitter(&addresses, &config).is_ok());
+
+    config.payout_jitter[0] += 1;
+    assert!(check_payout_jitter(&addresses, &config).is_err());
+
+    // user 0 gives all but a few sats of their exit to user 1
+    let all = (AMOUNT_PER_USER - FEE_AMOUNT).to_sat() as i64 - 10;
+    config.payout_jitter = vec![0; POOL_USERS];
+    config.payout_jitter[0] = -all;
+    config.payout_jitter[1] = all;
+    let error = check_payout_jitter(&addresses, &config).unwrap_err();
+    assert!(
+        error.to_string().contains("POOL_PAYOUT_JITTER_SATS"),
+        "{}",
+        error
+    );
+    assert!(build_pools(&addresses, &anchor(&config), &config, &backend(&config)).is_err());
+
+    config.payout_jitter.pop();
+    assert!(check_payout_jitter(&addresses, &config).is_err());
+}
+
+#[test]
+fn manifests_keep_the_jitter() {
+    let config = config();
+    let backend = backend(&config);
+    let addresses = addresses(config.network);
+    let anchor_addr = anchor(&config);
+    let tree = build_pools(&addresses, &anchor_addr, &config, &backend).unwrap();
+    let root = tree.root().unwrap().address(&config);
+    let manifest = PoolManifest::new(&config, &backend, &anchor_addr, &addresses, &root);
+    assert_eq!(manifest.payout_jitter, config.payout_jitter);
+
+    let json: PoolManifest =
+        serde_json::from_str(&serde_json::to_string(&manifest).unwrap()).unwrap();
+    assert_eq!(json.payout_jitter, config.payout_jitter);
+    let (decoded, version) = decode_state(&encode_state(&manifest).unwrap()).unwrap();
+    assert_eq!(version, STATE_VERSION);
+    assert_eq!(decoded.payout_jitter, config.payout_jitter);
+
+    // the rebuilt pool is the one funded
+    let pool = decoded.load_pool().unwrap();
+    assert_eq!(pool.config.payout_jitter, config.payout_jitter);
+    assert_eq!(
+        pool.tree.root().unwrap().spend_info.output_key(),
+        tree.root().unwrap().spend_info.output_key()
+    );
+}
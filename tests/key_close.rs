@@ -0,0 +1,207 @@
+use bitcoin::{
+    absolute,
+    key::Secp256k1,
+    secp256k1::{Message, SecretKey},
+    transaction, Address, Network, OutPoint, Transaction, TxIn, TxOut, XOnlyPublicKey,
+};
+use std::str::FromStr;
+
+use op_ctv_payment_pool::{
+    config::NetworkConfig,
+    ctv_scripts::NUMS_INTERNAL_KEY,
+    export::export_pool,
+    ids::{NodePath, UserIndex},
+    key_close::{is_key_close, unix_now, KeyCloseFallback, KeyCloseSession},
+    lifecycle::{Event, Lifecycle},
+    manifest::PoolManifest,
+    pools::build_pools,
+    profile::NetworkProfile,
+    state::{decode_state, encode_state},
+    watch::classify_spend,
+    webhooks::PoolEvent,
+    POOL_USERS,
+};
+
+mod common;
+
+use common::{address, addresses, anchor, backend, keypair, pool_manifest};
+
+fn user(index: usize) -> UserIndex {
+    UserIndex::new(index).unwrap()
+}
+
+// the musig key of reference user `i`, apart from the key of their withdraw address
+fn musig_secret(i: usize) -> SecretKey {
+    keypair(POOL_USERS + i).secret_key()
+}
+
+fn config(close_all_leaf: bool) -> NetworkConfig {
+    let mut config = NetworkConfig::new(NetworkProfile::RegtestLocal);
+    config.close_all_leaf = close_all_leaf;
+    config.musig_keys = (0..POOL_USERS)
+        .map(|i| {
+            (
+                address(i, Network::Regtest).to_string(),
+                keypair(POOL_USERS + i).public_key(),
+            )
+        })
+        .collect();
+    config
+}
+
+fn funded(config: &NetworkConfig) -> (PoolManifest, Transaction) {
+    let mut manifest = pool_manifest(config);
+    manifest.advance(Event::Fund).unwrap();
+    let root = Address::from_str(&manifest.root_address)
+        .unwrap()
+        .assume_checked();
+    let funding_tx = Transaction {
+        version: transaction::Version::TWO,
+        lock_time: absolute::LockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: OutPoint::null(),
+            ..Default::default()
+        }],
+        output: vec![TxOut {
+            value: manifest.funding_target().unwrap(),
+            script_pubkey: root.script_pubkey(),
+        }],
+    };
+    (manifest, funding_tx)
+}
+
+fn session(manifest: &PoolManifest, funding_tx: &Transaction, deadline: u64) -> KeyCloseSession {
+    let pool = manifest.load_pool().unwrap();
+    KeyCloseSession::start(manifest, &pool, funding_tx, 2, deadline).unwrap()
+}
+
+#[test]
+fn the_root_is_keyed_to_the_members() {
+    let (manifest, _) = funded(&config(false));
+    let pool = manifest.load_pool().unwrap();
+    let root = pool.tree.spend_info(&NodePath::root()).unwrap();
+    assert_ne!(
+        root.internal_key(),
+        XOnlyPublicKey::from_str(NUMS_INTERNAL_KEY).unwrap()
+    );
+    // every node below it only has its leaves
+    let below = NodePath::root().without(user(0)).unwrap();
+    assert_eq!(
+        pool.tree.spend_info(&below).unwrap().internal_key(),
+        XOnlyPublicKey::from_str(NUMS_INTERNAL_KEY).unwrap()
+    );
+    let (decoded, _) = decode_state(&encode_state(&manifest).unwrap()).unwrap();
+    assert_eq!(decoded.musig_keys, manifest.musig_keys);
+    decoded.load_pool().unwrap();
+}
+
+#[test]
+fn the_members_sign_a_key_path_close() {
+    let (mut manifest, funding_tx) = funded(&config(false));
+    let mut session = session(&manifest, &funding_tx, unix_now() + 60);
+    let members: Vec<UserIndex> = UserIndex::all().collect();
+    assert_eq!(session.missing(), members);
+
+    let mut nonces = Vec::new();
+    for i in 0..POOL_USERS {
+        // somebody else's key is no use
+        assert!(session.add_nonce(user(i), &musig_secret(i + 1)).is_err());
+        nonces.push(session.add_nonce(user(i), &musig_secret(i)).unwrap());
+    }
+    // every nonce in, now the signatures are missing
+    assert_eq!(session.missing(), members);
+    assert!(session.finish().is_err());
+    for (i, nonce) in nonces.into_iter().enumerate() {
+        session.sign(user(i), &musig_secret(i), nonce).unwrap();
+    }
+    assert!(session.missing().is_empty());
+
+    let tx = session.finish().unwrap();
+    assert_eq!(tx.input[0].witness.len(), 1);
+    let signature = bitcoin::taproot::Signature::from_slice(&tx.input[0].witness[0]).unwrap();
+    let root = Address::from_str(&manifest.root_address)
+        .unwrap()
+        .assume_checked();
+    let output_key = XOnlyPublicKey::from_slice(&root.script_pubkey().as_bytes()[2..]).unwrap();
+    Secp256k1::new()
+        .verify_schnorr(
+            &signature.signature,
+            &Message::from_digest(session.sighash().unwrap()),
+            &output_key,
+        )
+        .unwrap();
+
+    // every member is paid what their exit leaf would pay at least
+    for i in 0..POOL_USERS {
+        let export = export_pool(&manifest, Some(user(i)), true).unwrap();
+        session.check_payout(&export, user(i), 2).unwrap();
+    }
+    // not when the session passes another member's address off as theirs
+    let export = export_pool(&manifest, Some(user(0)), true).unwrap();
+    let mut swapped = session.clone();
+    swapped.addresses.swap(0, 1);
+    assert!(swapped.check_payout(&export, user(0), 2).is_err());
+    // nor more fee than the member accepts
+    assert!(session.check_payout(&export, user(0), 1).is_err());
+
+    // the watcher tells it from a leaf
+    let pool = manifest.load_pool().unwrap();
+    let outpoint = tx.input[0].previous_output;
+    assert!(is_key_close(&pool, &NodePath::root(), outpoint, &tx));
+    match classify_spend(&pool, &NodePath::root(), outpoint, &tx, 1).unwrap() {
+        PoolEvent::WithdrawalConfirmed { paid, .. } => assert_eq!(paid, members),
+        event => panic!("{:?}", event),
+    }
+    manifest.advance(Event::KeyClose).unwrap();
+    assert_eq!(manifest.lifecycle, Lifecycle::Closed);
+}
+
+#[test]
+fn a_stalled_member_falls_back_to_the_leaves() {
+    let (manifest, funding_tx) = funded(&config(false));
+    let mut session = session(&manifest, &funding_tx, unix_now() - 1);
+    for i in 1..POOL_USERS {
+        session.add_nonce(user(i), &musig_secret(i)).unwrap();
+    }
+    assert_eq!(session.missing(), vec![user(0)]);
+    assert!(session.expired(unix_now()));
+    let pool = manifest.load_pool().unwrap();
+    assert_eq!(
+        KeyCloseSession::fallback(&pool).unwrap(),
+        KeyCloseFallback::Unwind(user(0))
+    );
+
+    let (manifest, _) = funded(&config(true));
+    let pool = manifest.load_pool().unwrap();
+    assert_eq!(
+        KeyCloseSession::fallback(&pool).unwrap(),
+        KeyCloseFallback::CloseAll
+    );
+}
+
+#[test]
+fn the_close_needs_every_members_key_and_a_whole_pool() {
+    // one user without a key leaves nobody able to sign the key path
+    let mut partial = config(false);
+    partial
+        .musig_keys
+        .remove(&address(3, Network::Regtest).to_string());
+    assert!(build_pools(
+        &addresses(Network::Regtest),
+        &anchor(&partial),
+        &partial,
+        &backend(&partial)
+    )
+    .is_err());
+
+    // once a user has left by their leaf the root is spent
+    let (mut manifest, funding_tx) = funded(&config(false));
+    manifest.advance(Event::Withdraw(user(0))).unwrap();
+    let pool = manifest.load_pool().unwrap();
+    assert!(KeyCloseSession::start(&manifest, &pool, &funding_tx, 2, unix_now()).is_err());
+
+    // and without keys there is no key path
+    let (manifest, funding_tx) = funded(&NetworkConfig::new(NetworkProfile::RegtestLocal));
+    let pool = manifest.load_pool().unwrap();
+    assert!(KeyCloseSession::start(&manifest, &pool, &funding_tx, 2, unix_now()).is_err());
+}
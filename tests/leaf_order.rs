@@ -0,0 +1,90 @@
+use op_ctv_payment_pool::{
+    config::NetworkConfig,
+    ids::{PoolId, UserIndex},
+    manifest::PoolManifest,
+    pools::build_pools,
+    profile::NetworkProfile,
+    registration::{leaf_order, shuffle_registrations},
+    POOL_USERS,
+};
+
+mod common;
+
+use common::{addresses, anchor, backend};
+
+fn pool_id(id: &str) -> PoolId {
+    id.parse().unwrap()
+}
+
+// the reference users' pool with their registrations shuffled under `id`
+fn shuffled_manifest(id: &str) -> PoolManifest {
+    let config = NetworkConfig::new(NetworkProfile::RegtestLocal);
+    let backend = backend(&config);
+    let anchor_addr = anchor(&config);
+    let (shuffled, _, order) =
+        shuffle_registrations(&pool_id(id), &addresses(config.network), &[]).unwrap();
+    let tree = build_pools(&shuffled, &anchor_addr, &config, &backend).unwrap();
+    let root = tree.root().unwrap().address(&config);
+    let mut manifest = PoolManifest::new(&config, &backend, &anchor_addr, &shuffled, &root);
+    manifest.pool_id = Some(pool_id(id));
+    manifest.leaf_order = order;
+    manifest
+}
+
+#[test]
+fn the_same_pool_id_always_gives_the_same_order() {
+    let order = leaf_order(&pool_id("march payroll"));
+    assert_eq!(order, leaf_order(&pool_id("march payroll")));
+    // a permutation of every registration
+    let mut sorted = order.clone();
+    sorted.sort();
+    assert_eq!(sorted, UserIndex::all().collect::<Vec<_>>());
+    assert_eq!(order.len(), POOL_USERS);
+}
+
+#[test]
+fn other_pool_ids_give_other_orders() {
+    let orders: Vec<Vec<UserIndex>> = ["march payroll", "april payroll", "march payroll "]
+        .iter()
+        .map(|id| leaf_order(&pool_id(id)))
+        .collect();
+    assert_ne!(orders[0], orders[1]);
+    assert_ne!(orders[0], orders[2]);
+    assert_ne!(orders[1], orders[2]);
+    // and not the registration order either
+    assert!(orders
+        .iter()
+        .all(|order| *order != UserIndex::all().collect::<Vec<_>>()));
+}
+
+#[test]
+fn registrations_follow_the_order() {
+    let network = bitcoin::Network::Regtest;
+    let registered = addresses(network);
+    let (shuffled, _, order) =
+        shuffle_registrations(&pool_id("march payroll"), &registered, &[]).unwrap();
+    for (position, registration) in order.iter().enumerate() {
+        assert_eq!(shuffled[position], registered[registration.index()]);
+    }
+}
+
+#[test]
+fn a_manifest_with_a_tampered_leaf_order_is_refused() {
+    let manifest = shuffled_manifest("march payroll");
+    manifest.load_pool().unwrap();
+
+    let mut tampered = manifest.clone();
+    tampered.leaf_order.swap(0, 1);
+    let error = tampered.load_pool().err().unwrap();
+    assert!(error.to_string().contains("leaf order"), "{}", error);
+
+    // the order of another pool id doesn't pass for this one
+    let mut other = manifest.clone();
+    other.leaf_order = leaf_order(&pool_id("april payroll"));
+    assert!(other.load_pool().is_err());
+
+    let mut without_id = manifest;
+    without_id.pool_id = None;
+    let error = without_id.load_pool().err().unwrap();
+    assert!(error.to_string().contains("no pool id"), "{}", error);
+}
@@ -0,0 +1,109 @@
+use op_ctv_payment_pool::{
+    ids::UserIndex,
+    lifecycle::{Event, Lifecycle},
+    POOL_USERS,
+};
+
+fn user(index: usize) -> UserIndex {
+    UserIndex::new(index).unwrap()
+}
+
+#[test]
+fn pool_goes_from_draft_to_closed() {
+    let mut state = Lifecycle::Draft
+        .apply(Event::Register)
+        .unwrap()
+        .apply(Event::Fund)
+        .unwrap();
+    assert_eq!(state, Lifecycle::Funded);
+
+    for spender in UserIndex::all().take(POOL_USERS - 1) {
+        state = state.apply(Event::Withdraw(spender)).unwrap();
+    }
+    assert_eq!(state, Lifecycle::Closed);
+    assert!(state.is_finished());
+}
+
+#[test]
+fn no_withdrawal_before_funding() {
+    for state in [Lifecycle::Draft, Lifecycle::Registered] {
+        assert!(state.apply(Event::Withdraw(user(0))).is_err());
+        assert!(state.apply(Event::Recover).is_err());
+        assert!(state.require_funded().is_err());
+    }
+}
+
+#[test]
+fn no_double_funding() {
+    for state in [
+        Lifecycle::Funded,
+        Lifecycle::Unwinding(1),
+        Lifecycle::Closed,
+        Lifecycle::Recovered,
+    ] {
+        assert!(state.apply(Event::Fund).is_err(), "{}", state);
+    }
+}
+
+#[test]
+fn withdrawals_go_out_once_and_in_order() {
+    let state = Lifecycle::Funded.apply(Event::Withdraw(user(0))).unwrap();
+    assert_eq!(state, Lifecycle::Unwinding(1));
+    assert!(state.apply(Event::Withdraw(user(0))).is_err());
+    assert!(state.apply(Event::Withdraw(user(2))).is_err());
+    assert_eq!(state.apply(Event::Recover).unwrap(), Lifecycle::Recovered);
+    assert!(Lifecycle::Closed.apply(Event::Recover).is_err());
+}
+
+#[test]
+fn only_finished_pools_are_archived_and_then_nothing_happens() {
+    assert_eq!(
+        Lifecycle::Closed.apply(Event::Archive).unwrap(),
+        Lifecycle::Archived
+    );
+    assert_eq!(
+        Lifecycle::Recovered.apply(Event::Archive).unwrap(),
+        Lifecycle::Archived
+    );
+    for state in [Lifecycle::Funded, Lifecycle::Unwinding(3)] {
+        assert!(state.apply(Event::Archive).is_err(), "{}", state);
+    }
+    let archived = Lifecycle::Archived;
+    assert!(archived.is_finished());
+    for event in [
+        Event::Fund,
+        Event::Withdraw(user(0)),
+        Event::CloseAll,
+        Event::Recover,
+        Event::Archive,
+    ] {
+        assert!(archived.apply(event).is_err(), "{}", event);
+    }
+}
+
+#[test]
+fn pools_are_aborted_until_someone_leaves() {
+    for state in [Lifecycle::Draft, Lifecycle::Registered, Lifecycle::Funded] {
+        assert_eq!(state.apply(Event::Abort).unwrap(), Lifecycle::Cancelled);
+    }
+    for state in [
+        Lifecycle::Unwinding(1),
+        Lifecycle::Closed,
+        Lifecycle::Recovered,
+        Lifecycle::Archived,
+        Lifecycle::Cancelled,
+    ] {
+        assert!(state.apply(Event::Abort).is_err(), "{}", state);
+    }
+    let cancelled = Lifecycle::Cancelled;
+    assert!(cancelled.is_finished());
+    assert!(!cancelled.is_funded());
+    for event in [
+        Event::Register,
+        Event::Fund,
+        Event::Withdraw(user(0)),
+        Event::Archive,
+    ] {
+        assert!(cancelled.apply(event).is_err(), "{}", event);
+    }
+}
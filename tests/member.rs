@@ -0,0 +1,116 @@
+use bitcoin::{hashes::Hash, hex::DisplayHex, Amount, Network, OutPoint, Txid};
+
+use op_ctv_payment_pool::{
+    config::NetworkConfig,
+    ctv_scripts::ctv_hash,
+    export::export_pool,
+    ids::{NodePath, UserIndex},
+    manifest::PoolManifest,
+    member::{MemberState, Position},
+    profile::NetworkProfile,
+};
+
+mod common;
+
+use common::{address, pool_manifest};
+
+fn manifest() -> PoolManifest {
+    pool_manifest(&NetworkConfig::new(NetworkProfile::RegtestLocal))
+}
+
+fn at_root(amount: u64) -> Position {
+    Position {
+        node: NodePath::root(),
+        outpoint: OutPoint::new(Txid::from_byte_array([7; 32]), 0),
+        amount: Amount::from_sat(amount),
+        confirmed: true,
+    }
+}
+
+#[test]
+fn a_member_leaves_from_any_of_their_nodes_in_one_tx() {
+    let manifest = manifest();
+    let user = UserIndex::new(3).unwrap();
+    let export = export_pool(&manifest, Some(user), false).unwrap();
+    let state = MemberState::import(export).unwrap();
+    assert_eq!(state.user, user);
+
+    let chain = state.exit_chain(&at_root(1_000_000)).unwrap();
+    assert_eq!(chain.len(), 1);
+    let tx = &chain[0];
+    let leaf = &state.export.nodes[0].leaves[0];
+    assert_eq!(state.export.nodes[0].users, NodePath::root());
+    // the tx is the template the leaf commits to, spent with the leaf and its control block
+    assert_eq!(ctv_hash(tx, 0).to_lower_hex_string(), leaf.template_hash);
+    assert_eq!(tx.input[0].witness.len(), 2);
+    assert_eq!(
+        tx.input[0].witness.nth(1).unwrap().to_lower_hex_string(),
+        leaf.control_block
+    );
+    assert!(tx
+        .output
+        .iter()
+        .any(|output| output.script_pubkey == address(3, Network::Regtest).script_pubkey()));
+}
+
+#[test]
+fn a_branch_export_walks_the_planned_unwind() {
+    let manifest = manifest();
+    let user = UserIndex::new(2).unwrap();
+    let export = export_pool(&manifest, Some(user), true).unwrap();
+    let state = MemberState::import(export).unwrap();
+
+    // users 0 and 1 leave first, then user 2's own leaf
+    let chain = state.exit_chain(&at_root(1_000_000)).unwrap();
+    assert_eq!(chain.len(), 3);
+    for pair in chain.windows(2) {
+        assert_eq!(
+            pair[1].input[0].previous_output.txid,
+            pair[0].compute_txid()
+        );
+    }
+    for (tx, node) in chain.iter().zip(&state.export.nodes) {
+        assert_eq!(
+            ctv_hash(tx, 0).to_lower_hex_string(),
+            node.leaves[0].template_hash
+        );
+    }
+    let paid = address(2, Network::Regtest).script_pubkey();
+    assert!(chain[2]
+        .output
+        .iter()
+        .any(|output| output.script_pubkey == paid));
+    assert!(!chain[1]
+        .output
+        .iter()
+        .any(|output| output.script_pubkey == paid));
+}
+
+#[test]
+fn exports_that_dont_check_out_are_refused() {
+    let manifest = manifest();
+    assert!(MemberState::import(export_pool(&manifest, None, false).unwrap()).is_err());
+
+    let user = UserIndex::new(1).unwrap();
+    let mut tampered = export_pool(&manifest, Some(user), false).unwrap();
+    tampered.nodes[1].leaves[0].outputs[0].value += Amount::from_sat(1);
+    // the proof still holds, the spend no longer matches the template
+    let state = MemberState::import(tampered).unwrap();
+    let position = Position {
+        node: state.export.nodes[1].users.clone(),
+        ..at_root(1_000_000)
+    };
+    let tx = &state.exit_chain(&position).unwrap()[0];
+    assert_ne!(
+        ctv_hash(tx, 0).to_lower_hex_string(),
+        state.export.nodes[1].leaves[0].template_hash
+    );
+
+    let mut moved = export_pool(&manifest, Some(user), false).unwrap();
+    moved.nodes[0].address = address(50, Network::Regtest).to_string();
+    assert!(MemberState::import(moved).is_err());
+
+    let mut old = export_pool(&manifest, Some(user), false).unwrap();
+    old.tx_version = None;
+    assert!(MemberState::import(old).is_err());
+}
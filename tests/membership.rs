@@ -0,0 +1,92 @@
+use bitcoin::{address::NetworkUnchecked, Address, Amount, Network};
+
+use op_ctv_payment_pool::{
+    config::{NetworkConfig, FEE_AMOUNT},
+    manifest::PoolManifest,
+    membership::{prove_membership, verify_membership},
+    payouts::{PayoutSplits, SplitPayout},
+    profile::NetworkProfile,
+    AMOUNT_PER_USER,
+};
+
+mod common;
+
+use common::{address, pool_manifest};
+
+// user 2 sends part of their exit to a second address
+fn manifest() -> PoolManifest {
+    let mut config = NetworkConfig::new(NetworkProfile::RegtestLocal);
+    let split: Address<NetworkUnchecked> =
+        address(100, config.network).to_string().parse().unwrap();
+    let mut splits = PayoutSplits::new();
+    splits.insert(
+        address(2, config.network).to_string(),
+        vec![SplitPayout {
+            address: split,
+            amount: Amount::from_sat(2_000),
+        }],
+    );
+    config.splits = splits;
+    pool_manifest(&config)
+}
+
+#[test]
+fn members_prove_their_leaf_of_the_pool() {
+    let manifest = manifest();
+    let withdraw = address(1, Network::Regtest).to_string();
+    let proof = prove_membership(&manifest, &withdraw).unwrap();
+    assert_eq!(proof.user.index(), 1);
+    assert_eq!(
+        proof.root_address.assume_checked_ref().to_string(),
+        manifest.root_address
+    );
+
+    // it survives being handed over as json
+    let json = serde_json::to_string(&proof).unwrap();
+    let proof = serde_json::from_str(&json).unwrap();
+    let verified = verify_membership(&proof).unwrap();
+    assert_eq!(verified.address, withdraw);
+    assert_eq!(verified.amount, AMOUNT_PER_USER - FEE_AMOUNT);
+    assert!(verified.ctv_enforced);
+    assert!(verified.merkle_path_length > 0);
+
+    // a split address is paid by its user's leaf
+    let split = address(100, Network::Regtest).to_string();
+    let proof = prove_membership(&manifest, &split).unwrap();
+    assert_eq!(proof.user.index(), 2);
+    assert_eq!(
+        verify_membership(&proof).unwrap().amount,
+        Amount::from_sat(2_000)
+    );
+
+    let stranger = address(200, Network::Regtest).to_string();
+    assert!(prove_membership(&manifest, &stranger).is_err());
+    assert!(prove_membership(&manifest, &address(1, Network::Testnet).to_string()).is_err());
+}
+
+#[test]
+fn tampered_proofs_are_refused() {
+    let manifest = manifest();
+    let proof = prove_membership(&manifest, &address(0, Network::Regtest).to_string()).unwrap();
+
+    // paying themselves more changes the template
+    let mut richer = proof.clone();
+    richer.outputs[1].value += Amount::from_sat(1_000);
+    let error = verify_membership(&richer).unwrap_err();
+    assert!(error.to_string().contains("hash to template"), "{}", error);
+
+    // another user's address isn't in these outputs
+    let mut other = proof.clone();
+    other.address = address(1, Network::Regtest).to_string().parse().unwrap();
+    assert!(verify_membership(&other).is_err());
+
+    // a leaf of some other pool
+    let mut elsewhere = proof.clone();
+    elsewhere.root_address = address(50, Network::Regtest).to_string().parse().unwrap();
+    assert!(verify_membership(&elsewhere).is_err());
+
+    // a merkle path that leads somewhere else
+    let mut shortened = proof;
+    shortened.proof.siblings.pop();
+    assert!(verify_membership(&shortened).is_err());
+}
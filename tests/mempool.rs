@@ -0,0 +1,70 @@
+use bitcoin::{hashes::Hash, Txid};
+use std::time::Duration;
+
+use op_ctv_payment_pool::mempool::{BroadcastQueue, MempoolLimits};
+
+fn txid(n: u8) -> Txid {
+    Txid::from_byte_array([n; 32])
+}
+
+fn queue(limits: MempoolLimits) -> BroadcastQueue {
+    BroadcastQueue::with_limits(limits, Duration::from_secs(1))
+}
+
+#[test]
+fn a_chain_waits_once_it_reaches_the_ancestor_limit() {
+    let mut queue = queue(MempoolLimits::CORE_DEFAULT);
+    // the funding tx and 23 pool spends on top of it
+    queue.track(txid(0), &[], 0);
+    for n in 1..24 {
+        assert!(queue.fits(&[txid(n - 1)]));
+        queue.track(txid(n), &[txid(n - 1)], 0);
+    }
+    assert_eq!(queue.ancestor_count(&[txid(23)]), 25);
+    assert!(queue.fits(&[txid(23)]));
+    queue.track(txid(24), &[txid(23)], 0);
+    assert!(!queue.fits(&[txid(24)]));
+
+    // the first blocks take part of the chain, the rest of the unwind has room again
+    queue.confirmed(txid(3));
+    assert_eq!(queue.ancestor_count(&[txid(24)]), 22);
+    assert!(queue.fits(&[txid(24)]));
+}
+
+#[test]
+fn truc_spends_wait_for_their_parent_and_its_child() {
+    let limits = MempoolLimits::CORE_DEFAULT.for_tx_version(3);
+    assert_eq!(limits, MempoolLimits::TRUC);
+    assert_eq!(
+        MempoolLimits::CORE_DEFAULT.for_tx_version(2),
+        MempoolLimits::CORE_DEFAULT
+    );
+
+    let mut queue = queue(limits);
+    // a confirmed parent isn't tracked, anything fits on it
+    assert!(queue.fits(&[txid(0)]));
+    queue.track(txid(1), &[txid(0)], 0);
+    assert!(queue.fits(&[txid(1)]));
+    // the anchor child of the first spend
+    queue.track(txid(2), &[txid(1)], 0);
+    assert_eq!(queue.descendant_count(txid(1)), 2);
+    // the next pool spend would be a second child
+    assert!(!queue.fits(&[txid(1)]));
+    queue.confirmed(txid(1));
+    assert!(queue.fits(&[txid(1)]));
+}
+
+#[test]
+fn ancestors_the_queue_did_not_send_count_too() {
+    let mut queue = queue(MempoolLimits {
+        ancestors: 5,
+        descendants: 25,
+    });
+    // a funding tx in the mempool on top of 3 unconfirmed wallet txs
+    queue.track(txid(0), &[], 3);
+    assert_eq!(queue.ancestor_count(&[txid(0)]), 5);
+    assert!(queue.fits(&[txid(0)]));
+    queue.track(txid(1), &[txid(0)], 0);
+    assert!(!queue.fits(&[txid(1)]));
+    assert_eq!(queue.waiting(), 0);
+}
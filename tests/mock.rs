@@ -0,0 +1,217 @@
+use bitcoin::{
+    absolute, consensus::encode::serialize_hex, transaction, Address, Amount, Network, OutPoint,
+    Sequence, Transaction, TxIn, TxOut,
+};
+use bitcoincore_rpc::RpcApi;
+use std::str::FromStr;
+
+use op_ctv_payment_pool::{
+    broadcast::{broadcast, BroadcastError, BroadcastKind, RejectReason},
+    config::NetworkConfig,
+    ids::UserIndex,
+    mempool::BroadcastQueue,
+    miner::fund_regtest_wallet,
+    mock::MockBackend,
+    pools::{build_pools, process_pool_spend},
+    profile::NetworkProfile,
+    rpc_helper::{send_funding_transaction, FundingDestination},
+    AMOUNT_PER_USER, POOL_USERS,
+};
+
+mod common;
+
+use common::{anchor, backend};
+
+// a one input one output spend of `outpoint`, paying `fee`
+fn spend(outpoint: OutPoint, value: Amount, fee: Amount, sequence: Sequence) -> Transaction {
+    Transaction {
+        version: transaction::Version::TWO,
+        lock_time: absolute::LockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: outpoint,
+            sequence,
+            ..Default::default()
+        }],
+        output: vec![TxOut {
+            value: value - fee,
+            script_pubkey: Address::from_str("bcrt1pfeesnyr2tx")
+                .unwrap()
+                .assume_checked()
+                .script_pubkey(),
+        }],
+    }
+}
+
+fn reject(error: anyhow::Error) -> RejectReason {
+    error
+        .downcast_ref::<BroadcastError>()
+        .unwrap()
+        .reason
+        .clone()
+}
+
+#[tokio::test]
+async fn the_whole_unwind_runs_against_the_mock() {
+    let config = NetworkConfig::new(NetworkProfile::RegtestLocal);
+    let backend = backend(&config);
+    let mock = MockBackend::new(config.network);
+    // every broadcast confirms straight away, nothing waits for the regtest miner
+    mock.auto_mine(Some(1));
+    // pool spends leave their fee to the anchor child
+    mock.set_relay_fee(Amount::ZERO);
+    let rpc = mock.rpc();
+
+    let mining_address = mock.new_address();
+    fund_regtest_wallet(&rpc, &mining_address, AMOUNT_PER_USER * POOL_USERS as u64)
+        .await
+        .unwrap();
+    assert_eq!(mock.height(), 101);
+
+    let addresses: Vec<Address> = (0..POOL_USERS).map(|_| mock.new_address()).collect();
+    let anchor_addr = anchor(&config);
+    let pools = build_pools(&addresses, &anchor_addr, &config, &backend).unwrap();
+    let pool_addr = pools.root().unwrap().address(&config);
+
+    let (funding_txid, _) = send_funding_transaction(
+        &rpc,
+        &config,
+        &FundingDestination::EntryPool(pool_addr),
+        None,
+    )
+    .await
+    .unwrap();
+    assert_eq!(mock.confirmations(funding_txid), Some(1));
+
+    let mut queue = BroadcastQueue::new(&config);
+    let mut current_txid = funding_txid;
+    for i in UserIndex::all().take(POOL_USERS - 1) {
+        current_txid = process_pool_spend(
+            &pools,
+            &config,
+            &rpc,
+            &rpc,
+            &mut queue,
+            &backend,
+            i,
+            &addresses,
+            current_txid,
+            &anchor_addr,
+        )
+        .await
+        .unwrap();
+        assert!(mock.confirmations(current_txid).unwrap() >= 1);
+    }
+
+    // the exit pool pays the last two users
+    let exit = mock.transaction(current_txid).unwrap();
+    assert_eq!(exit.output[0].script_pubkey, addresses[8].script_pubkey());
+    assert_eq!(exit.output[1].script_pubkey, addresses[9].script_pubkey());
+    assert!(mock.mempool().is_empty());
+}
+
+#[tokio::test]
+async fn confirmations_come_from_the_blocks_the_test_mines() {
+    let mock = MockBackend::new(Network::Regtest);
+    let rpc = mock.rpc();
+    let coin = mock.add_utxo(Amount::ONE_BTC);
+
+    // unconfirmed coins only show up with minconf 0
+    let unspent = rpc
+        .run(|c| c.list_unspent(Some(1), None, None, None, None))
+        .await
+        .unwrap();
+    assert!(unspent.is_empty());
+    assert_eq!(
+        rpc.run(|c| c.get_balance(Some(0), None)).await.unwrap(),
+        Amount::ONE_BTC
+    );
+
+    mock.mine(3);
+    let info = rpc
+        .run(move |c| c.get_raw_transaction_info(&coin.txid, None))
+        .await
+        .unwrap();
+    assert_eq!(info.confirmations, Some(3));
+    let unspent = rpc
+        .run(|c| c.list_unspent(Some(1), None, None, None, None))
+        .await
+        .unwrap();
+    assert_eq!(unspent.len(), 1);
+    assert_eq!(unspent[0].confirmations, 3);
+
+    // a relative timelock of 5 blocks isn't final on a coin 3 blocks deep
+    let locked = spend(
+        coin,
+        Amount::ONE_BTC,
+        Amount::from_sat(1_000),
+        Sequence::from_height(5),
+    );
+    let error = broadcast(&rpc, serialize_hex(&locked), BroadcastKind::PoolSpend)
+        .await
+        .unwrap_err();
+    assert_eq!(reject(error), RejectReason::NonFinal);
+    mock.mine(2);
+    let txid = broadcast(&rpc, serialize_hex(&locked), BroadcastKind::PoolSpend)
+        .await
+        .unwrap();
+    assert_eq!(mock.mempool(), vec![txid]);
+    let entry = rpc.run(move |c| c.get_mempool_entry(&txid)).await.unwrap();
+    assert_eq!(entry.ancestor_count, 1);
+    assert_eq!(entry.fees.base, Amount::from_sat(1_000));
+}
+
+#[tokio::test]
+async fn failures_are_injected_and_rejects_decoded() {
+    let mock = MockBackend::new(Network::Regtest);
+    let rpc = mock.rpc();
+    let coin = mock.add_utxo(Amount::ONE_BTC);
+    mock.mine(1);
+    let tx = spend(
+        coin,
+        Amount::ONE_BTC,
+        Amount::from_sat(1_000),
+        Sequence::ENABLE_RBF_NO_LOCKTIME,
+    );
+
+    // once, then the node answers again
+    mock.fail_next(
+        "sendrawtransaction",
+        -26,
+        "mempool min fee not met, 100 < 400",
+    );
+    let error = broadcast(&rpc, serialize_hex(&tx), BroadcastKind::PoolSpend)
+        .await
+        .unwrap_err();
+    assert_eq!(reject(error), RejectReason::MinRelayFee);
+    let txid = broadcast(&rpc, serialize_hex(&tx), BroadcastKind::PoolSpend)
+        .await
+        .unwrap();
+
+    let error = broadcast(&rpc, serialize_hex(&tx), BroadcastKind::PoolSpend)
+        .await
+        .unwrap_err();
+    assert_eq!(reject(error), RejectReason::AlreadyKnown);
+    let conflict = spend(
+        coin,
+        Amount::ONE_BTC,
+        Amount::from_sat(2_000),
+        Sequence::ENABLE_RBF_NO_LOCKTIME,
+    );
+    let error = broadcast(&rpc, serialize_hex(&conflict), BroadcastKind::PoolSpend)
+        .await
+        .unwrap_err();
+    assert_eq!(reject(error), RejectReason::MempoolConflict);
+
+    mock.mine(1);
+    assert_eq!(mock.confirmations(txid), Some(1));
+    let error = broadcast(&rpc, serialize_hex(&conflict), BroadcastKind::PoolSpend)
+        .await
+        .unwrap_err();
+    assert_eq!(reject(error), RejectReason::MissingInputs);
+
+    // other calls fail the same way, with the node's code
+    mock.fail_next("getblockcount", -28, "Loading block index...");
+    let error = rpc.run(|c| c.get_block_count()).await.unwrap_err();
+    assert!(error.to_string().contains("Loading block index"));
+    assert_eq!(rpc.run(|c| c.get_block_count()).await.unwrap(), 2);
+}
@@ -0,0 +1,133 @@
+use bitcoin::{
+    key::Secp256k1,
+    secp256k1::{Message, PublicKey, SecretKey},
+    taproot::TapNodeHash,
+    XOnlyPublicKey,
+};
+use std::str::FromStr;
+
+use op_ctv_payment_pool::musig::{nonce_gen, AggNonce, KeyAggContext, PartialSig, SigningSession};
+
+// the public keys of BIP-327's key_agg_vectors.json
+const KEYS: [&str; 3] = [
+    "02F9308A019258C31049344F85F89D5229B531C845836F99B08601F113BCE036F9",
+    "03DFF1D77F2A671C5F36183726DB2341BE58FEAE1DA2DECED843240F7B502BA659",
+    "023590A94E768F8E1815C2F24B4D80A8E3149316C3518CE7B7AD338368D038CA66",
+];
+
+fn secret(i: u8) -> SecretKey {
+    SecretKey::from_slice(&[i + 1; 32]).unwrap()
+}
+
+fn public(i: u8) -> PublicKey {
+    PublicKey::from_secret_key(&Secp256k1::new(), &secret(i))
+}
+
+#[test]
+fn keys_aggregate_like_the_bip_vectors() {
+    let vectors = [
+        (
+            vec![0, 1, 2],
+            "90539EEDE565F5D054F32CC0C220126889ED1E5D193BAF15AEF344FE59D4610C",
+        ),
+        (
+            vec![2, 1, 0],
+            "6204DE8B083426DC6EAF9502D27024D53FC826BF7D2012148A0575435DF54B2B",
+        ),
+        (
+            vec![0, 0, 0],
+            "B436E3BAD62B8CD409969A224731C193D051162D8C5AE8B109306127DA3AA935",
+        ),
+        (
+            vec![0, 0, 1, 1],
+            "69BC22BFA5D106306E48A20679DE1D7389386124D07571D0D872686028C26A3E",
+        ),
+    ];
+    for (indices, expected) in vectors {
+        let keys: Vec<PublicKey> = indices
+            .iter()
+            .map(|&i| PublicKey::from_str(KEYS[i]).unwrap())
+            .collect();
+        let aggregate = KeyAggContext::new(&keys).unwrap().x_only_key();
+        assert_eq!(
+            aggregate,
+            XOnlyPublicKey::from_str(expected).unwrap(),
+            "{:?}",
+            indices
+        );
+    }
+}
+
+// every signer's nonce and partial signature for `message`, under the key tweaked with `merkle_root`
+fn sign(
+    signers: &[u8],
+    merkle_root: Option<TapNodeHash>,
+    message: [u8; 32],
+) -> (SigningSession, Vec<PartialSig>) {
+    let keys: Vec<PublicKey> = signers.iter().map(|&i| public(i)).collect();
+    let mut context = KeyAggContext::new(&keys).unwrap();
+    context.tap_tweak(merkle_root).unwrap();
+    let nonces: Vec<_> = signers
+        .iter()
+        .map(|&i| nonce_gen(&secret(i), &context.x_only_key(), &message, [i; 32]).unwrap())
+        .collect();
+    let aggregate =
+        AggNonce::new(&nonces.iter().map(|(_, public)| *public).collect::<Vec<_>>()).unwrap();
+    let session = SigningSession::new(context, &aggregate, message).unwrap();
+    let sigs = signers
+        .iter()
+        .zip(nonces)
+        .map(|(&i, (nonce, _))| session.sign(nonce, &secret(i)).unwrap())
+        .collect();
+    (session, sigs)
+}
+
+#[test]
+fn partial_signatures_add_up_to_a_key_path_signature() {
+    let message = [7; 32];
+    for merkle_root in [None, Some(TapNodeHash::from_str(&"ab".repeat(32)).unwrap())] {
+        let (session, sigs) = sign(&[0, 1, 2, 3], merkle_root, message);
+        let signature = session.aggregate(&sigs).unwrap();
+        // a plain BIP-340 signature of the tweaked aggregate, what a key path spend carries
+        let keys: Vec<PublicKey> = (0..4).map(public).collect();
+        let mut context = KeyAggContext::new(&keys).unwrap();
+        context.tap_tweak(merkle_root).unwrap();
+        Secp256k1::new()
+            .verify_schnorr(
+                &signature,
+                &Message::from_digest(message),
+                &context.x_only_key(),
+            )
+            .unwrap();
+    }
+}
+
+#[test]
+fn a_missing_or_wrong_share_is_refused() {
+    let (session, mut sigs) = sign(&[0, 1, 2], None, [9; 32]);
+    assert!(session.aggregate(&sigs[..2]).is_err());
+    // someone else's share in its place
+    sigs[2] = sigs[1];
+    assert!(session.aggregate(&sigs).is_err());
+}
+
+#[test]
+fn a_nonce_only_signs_for_its_own_key() {
+    let keys: Vec<PublicKey> = (0..3).map(public).collect();
+    let context = KeyAggContext::new(&keys).unwrap();
+    let message = [3; 32];
+    let (nonce, public_nonce) =
+        nonce_gen(&secret(0), &context.x_only_key(), &message, [1; 32]).unwrap();
+    let aggregate = AggNonce::new(&[public_nonce, public_nonce, public_nonce]).unwrap();
+    let session = SigningSession::new(context, &aggregate, message).unwrap();
+    assert!(session.sign(nonce.clone(), &secret(1)).is_err());
+    // nor for a key outside the session
+    let (outsider, _) = nonce_gen(&secret(5), &keys_key(&keys), &message, [1; 32]).unwrap();
+    assert!(session.sign(outsider, &secret(5)).is_err());
+    let sig = session.sign(nonce, &secret(0)).unwrap();
+    assert!(session.verify(&sig, &public_nonce, &public(1)).is_err());
+}
+
+fn keys_key(keys: &[PublicKey]) -> XOnlyPublicKey {
+    KeyAggContext::new(keys).unwrap().x_only_key()
+}
@@ -0,0 +1,63 @@
+use bitcoin::{
+    absolute, consensus::encode::deserialize_hex, transaction, Amount, Transaction, TxIn, TxOut,
+};
+use bitcoincore_rpc::RpcApi;
+use std::{fs, time::Duration};
+
+use op_ctv_payment_pool::{
+    broadcast::{broadcast, set_outbox, BroadcastKind, Outbox},
+    mock::MockBackend,
+};
+
+// the outbox is process wide, so this is the only test of this binary
+#[tokio::test]
+async fn a_handed_off_tx_waits_in_the_outbox_until_someone_else_sends_it() {
+    let mock = MockBackend::new(bitcoin::Network::Regtest);
+    let rpc = mock.rpc();
+    let coin = mock.add_utxo(Amount::from_sat(100_000));
+    mock.mine(1);
+    let tx = Transaction {
+        version: transaction::Version::TWO,
+        lock_time: absolute::LockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: coin,
+            ..Default::default()
+        }],
+        output: vec![TxOut {
+            value: Amount::from_sat(99_000),
+            script_pubkey: mock.new_address().script_pubkey(),
+        }],
+    };
+    let txid = tx.compute_txid();
+
+    let dir = std::env::temp_dir().join(format!("pool-outbox-{}", std::process::id()));
+    set_outbox(Some(Outbox {
+        dir: dir.clone(),
+        poll: Duration::from_millis(10),
+    }));
+    let handed_off = {
+        let (rpc, tx) = (rpc.clone(), tx.clone());
+        tokio::spawn(async move { broadcast(&rpc, &tx, BroadcastKind::Funding).await })
+    };
+
+    let file = dir.join(format!("funding_tx-{}.hex", txid));
+    while !file.exists() {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    let written: Transaction = deserialize_hex(fs::read_to_string(&file).unwrap().trim()).unwrap();
+    assert_eq!(written, tx);
+    // still waiting, the node never got it from us
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert!(!handed_off.is_finished());
+    assert!(mock.mempool().is_empty());
+
+    // the operator's own infrastructure sends it
+    rpc.run(move |c| c.send_raw_transaction(&written))
+        .await
+        .unwrap();
+    assert_eq!(handed_off.await.unwrap().unwrap(), txid);
+    assert!(!file.exists());
+
+    set_outbox(None);
+    fs::remove_dir_all(dir).unwrap();
+}
@@ -0,0 +1,55 @@
+use bitcoin::{hashes::Hash, Network, Txid};
+
+use op_ctv_payment_pool::{
+    config::NetworkConfig,
+    lifecycle::Event,
+    manifest::PoolManifest,
+    p2p::{compare_codes, require_agreement, verification_code},
+    profile::NetworkProfile,
+};
+
+mod common;
+
+use common::{address, addresses, anchor, backend};
+
+// the code doesn't rebuild the tree, so any root will do
+fn manifest() -> PoolManifest {
+    let config = NetworkConfig::new(NetworkProfile::RegtestLocal);
+    let backend = backend(&config);
+    let addresses = addresses(config.network);
+    let anchor_addr = anchor(&config);
+    let root = address(99, config.network);
+    PoolManifest::new(&config, &backend, &anchor_addr, &addresses, &root)
+}
+
+#[test]
+fn code_only_covers_what_goes_into_the_tree() {
+    let shared = manifest();
+    let code = verification_code(&shared).unwrap();
+    assert_eq!(code.len(), 24);
+
+    // one participant funded and labelled their copy
+    let mut funded = shared.clone();
+    funded.funding_txid = Some(Txid::all_zeros());
+    funded.advance(Event::Fund).unwrap();
+    funded.metadata.set(None, "name", "payroll").unwrap();
+    assert_eq!(verification_code(&funded).unwrap(), code);
+
+    // another was handed a different address
+    let mut swapped = shared.clone();
+    swapped.withdraw_addresses[3] = address(199, Network::Regtest).to_string();
+    assert_ne!(verification_code(&swapped).unwrap(), code);
+}
+
+#[test]
+fn one_mismatched_code_refuses_the_pool() {
+    let code = verification_code(&manifest()).unwrap();
+    let typed = code.to_uppercase().replace('-', " ");
+    let peers = compare_codes(&code, std::slice::from_ref(&typed));
+    assert!(peers[0].matches);
+    assert!(require_agreement(&peers).is_ok());
+
+    let peers = compare_codes(&code, &[typed, "0000-1111-2222-3333-4444".to_string()]);
+    assert!(!peers[1].matches);
+    assert!(require_agreement(&peers).is_err());
+}
@@ -0,0 +1,98 @@
+use bitcoin::{consensus::encode::deserialize_hex, Amount, Transaction, TxOut};
+use bitcoincore_rpc::RpcApi;
+use std::fs;
+
+use op_ctv_payment_pool::{
+    config::NetworkConfig, fund::required_funding, manifest::LoadedPool, mock::MockBackend,
+    packages::export_packages, profile::NetworkProfile, POOL_USERS,
+};
+
+mod common;
+
+use common::loaded_pool;
+
+fn pool(close_all_leaf: bool, anchor_amount: Option<Amount>) -> LoadedPool {
+    let mut config = NetworkConfig::new(NetworkProfile::RegtestLocal);
+    config.close_all_leaf = close_all_leaf;
+    config.anchor_amount = anchor_amount;
+    loaded_pool(&config)
+}
+
+// the pool's funding output and a coin of the fee payer, both confirmed
+fn fund(mock: &MockBackend, pool: &LoadedPool) -> Transaction {
+    let outpoint = mock.add_output(TxOut {
+        value: required_funding(),
+        script_pubkey: pool
+            .tree
+            .root()
+            .unwrap()
+            .address(&pool.config)
+            .script_pubkey(),
+    });
+    mock.add_utxo(Amount::from_sat(100_000));
+    mock.mine(1);
+    mock.transaction(outpoint.txid).unwrap()
+}
+
+fn temp_dir(name: &str) -> std::path::PathBuf {
+    std::env::temp_dir().join(format!("pool-packages-{}-{}", name, std::process::id()))
+}
+
+#[tokio::test]
+async fn every_package_goes_through_the_node_in_order() {
+    let pool = pool(true, Some(Amount::from_sat(240)));
+    let mock = MockBackend::new(pool.config.network);
+    let rpc = mock.rpc();
+    let funding_tx = fund(&mock, &pool);
+    let dir = temp_dir("unwind");
+    let packages = export_packages(&pool, &funding_tx, &rpc, 2000, &dir)
+        .await
+        .unwrap();
+    // the planned unwind and the close-all spend
+    assert_eq!(packages.len(), POOL_USERS);
+    assert!(packages[POOL_USERS - 1].spender.is_none());
+    // nothing was broadcast
+    assert!(mock.mempool().is_empty());
+
+    let mut previous_parent = funding_tx.compute_txid();
+    for package in packages.iter().take(POOL_USERS - 1) {
+        let txs: Vec<String> =
+            serde_json::from_str(&fs::read_to_string(&package.file).unwrap()).unwrap();
+        assert_eq!(txs.len(), 2);
+        let parent: Transaction = deserialize_hex(&txs[0]).unwrap();
+        let child: Transaction = deserialize_hex(&txs[1]).unwrap();
+        assert_eq!(parent.compute_txid(), package.parent_txid);
+        assert_eq!(child.compute_txid(), package.child_txid);
+        assert_eq!(parent.input[0].previous_output.txid, previous_parent);
+        assert!(child
+            .input
+            .iter()
+            .any(|input| input.previous_output.txid == package.parent_txid));
+
+        for tx in [parent, child] {
+            rpc.run(move |c| c.send_raw_transaction(&tx)).await.unwrap();
+        }
+        mock.mine(1);
+        previous_parent = package.parent_txid;
+    }
+    // the last child spends the change the one before it left
+    assert_eq!(
+        packages[POOL_USERS - 2].fee_coin.txid,
+        packages[POOL_USERS - 3].child_txid
+    );
+    // the close-all child pays from the first coin, like the first step
+    assert_eq!(packages[POOL_USERS - 1].fee_coin, packages[0].fee_coin);
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[tokio::test]
+async fn a_pool_without_anchors_has_no_packages() {
+    let pool = pool(false, None);
+    let mock = MockBackend::new(pool.config.network);
+    let funding_tx = fund(&mock, &pool);
+    let dir = temp_dir("no-anchor");
+    assert!(export_packages(&pool, &funding_tx, &mock.rpc(), 2000, &dir)
+        .await
+        .is_err());
+    assert!(!dir.exists());
+}
@@ -0,0 +1,297 @@
+use bitcoin::{
+    absolute,
+    hashes::Hash,
+    key::{Keypair, Secp256k1},
+    secp256k1::SecretKey,
+    transaction, Address, Network, OutPoint, ScriptBuf, Transaction, TxIn, TxOut, Txid,
+    XOnlyPublicKey,
+};
+
+use op_ctv_payment_pool::{
+    config::NetworkConfig,
+    covenant::CovenantBackend,
+    ctv_scripts::ctv_script,
+    ids::{NodePath, UserIndex},
+    manifest::PoolManifest,
+    policy::{LeafPolicy, PolicyBackend},
+    pools::{build_pools, pool_spend_template},
+    profile::NetworkProfile,
+    recovery::RecoveryPath,
+    template::Bip119Ctv,
+    template_fees::template_fees,
+    tree::PoolTree,
+    AMOUNT_PER_USER, POOL_USERS,
+};
+
+mod common;
+
+use common::anchor;
+
+const HASH: [u8; 32] = [7; 32];
+
+fn key(seed: u8) -> XOnlyPublicKey {
+    let secp = Secp256k1::new();
+    let key = SecretKey::from_slice(&[seed; 32]).unwrap();
+    Keypair::from_secret_key(&secp, &key).x_only_public_key().0
+}
+
+fn addresses(network: Network) -> Vec<Address> {
+    let secp = Secp256k1::new();
+    (0..POOL_USERS)
+        .map(|i| Address::p2tr(&secp, key(i as u8 + 1), None, network))
+        .collect()
+}
+
+fn policy_backend(
+    config: &NetworkConfig,
+    policy: impl Fn([u8; 32]) -> LeafPolicy + Send + Sync + 'static,
+) -> PolicyBackend {
+    PolicyBackend::new(
+        Bip119Ctv {
+            tx_version: config.tx_version,
+        },
+        policy,
+    )
+}
+
+fn funding_tx(pools: &PoolTree) -> Transaction {
+    let root = pools.spend_info(&NodePath::root()).unwrap();
+    Transaction {
+        version: transaction::Version(2),
+        lock_time: absolute::LockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: OutPoint::new(Txid::all_zeros(), 0),
+            ..Default::default()
+        }],
+        output: vec![TxOut {
+            value: AMOUNT_PER_USER * POOL_USERS as u64,
+            script_pubkey: ScriptBuf::new_p2tr_tweaked(root.output_key()),
+        }],
+    }
+}
+
+#[test]
+fn policies_compile_to_tapscript() {
+    let hash = HASH.map(|b| format!("{:02x}", b)).concat();
+    assert_eq!(LeafPolicy::ctv(HASH).script(), ctv_script(HASH));
+    assert_eq!(
+        LeafPolicy::ctv(HASH).and_older(6).script().to_asm_string(),
+        format!(
+            "OP_PUSHBYTES_32 {} OP_NOP4 OP_DROP OP_PUSHNUM_6 OP_CSV",
+            hash
+        )
+    );
+    assert_eq!(
+        LeafPolicy::ctv(HASH)
+            .or_key(key(1))
+            .script()
+            .to_asm_string(),
+        format!(
+            "OP_IF OP_PUSHBYTES_32 {} OP_NOP4 OP_ELSE OP_PUSHBYTES_32 {} OP_CHECKSIG OP_ENDIF",
+            hash,
+            key(1)
+        )
+    );
+    // the recovery leaf is a timelock and a template
+    let recovery = RecoveryPath {
+        address: addresses(Network::Regtest)[0].clone(),
+        timeout: 144,
+    };
+    assert_eq!(
+        recovery.leaf_script(HASH),
+        LeafPolicy::older(144).and(LeafPolicy::ctv(HASH)).script()
+    );
+    // every one of them minimally encoded
+    let nested = LeafPolicy::ctv(HASH)
+        .and_older(1000)
+        .or(LeafPolicy::key(key(1)).and_key(key(2)));
+    assert!(nested
+        .script()
+        .instructions_minimal()
+        .all(|instruction| instruction.is_ok()));
+}
+
+#[test]
+fn the_covenant_path_picks_the_branch_without_keys() {
+    let path = LeafPolicy::ctv(HASH)
+        .or_key(key(1))
+        .covenant_path()
+        .unwrap();
+    assert_eq!(path.stack, vec![vec![1]]);
+    assert_eq!(path.templates, vec![HASH]);
+
+    // the template on the ELSE side is selected with an empty item
+    let path = LeafPolicy::key(key(1))
+        .or(LeafPolicy::ctv(HASH).and_older(6))
+        .covenant_path()
+        .unwrap();
+    assert_eq!(path.stack, vec![Vec::<u8>::new()]);
+    assert_eq!(path.older, Some(6));
+
+    assert!(LeafPolicy::ctv(HASH)
+        .and_key(key(1))
+        .covenant_path()
+        .is_none());
+}
+
+#[test]
+fn the_pool_builder_takes_a_policy_per_leaf() {
+    let mut config = NetworkConfig::new(NetworkProfile::RegtestLocal);
+    config.recovery = None;
+    let addresses = addresses(config.network);
+    let anchor_addr = anchor(&config);
+    // the operator can move any node, everyone else only along the templates
+    let operator = key(99);
+    let backend = policy_backend(&config, move |hash| LeafPolicy::ctv(hash).or_key(operator));
+    let pools = build_pools(&addresses, &anchor_addr, &config, &backend).unwrap();
+    let ctv = common::backend(&config);
+    let plain = build_pools(&addresses, &anchor_addr, &config, &ctv).unwrap();
+    assert_ne!(
+        pools.root().unwrap().address(&config),
+        plain.root().unwrap().address(&config)
+    );
+
+    let template = pool_spend_template(
+        &pools,
+        &config,
+        &backend,
+        UserIndex::new(0).unwrap(),
+        &addresses,
+        &funding_tx(&pools),
+        &anchor_addr,
+    )
+    .unwrap();
+    let script = backend.leaf_script(template.template_hash);
+    let tx = backend.finalize(template).unwrap();
+    let witness: Vec<&[u8]> = tx.input[0].witness.iter().collect();
+    // the IF side, the leaf and its control block
+    assert_eq!(witness.len(), 3);
+    assert_eq!(witness[0], [1]);
+    assert_eq!(witness[1], script.as_bytes());
+}
+
+#[test]
+fn leaves_the_pool_can_not_spend_are_refused_before_funding() {
+    let mut config = NetworkConfig::new(NetworkProfile::RegtestLocal);
+    config.recovery = None;
+    let addresses = addresses(config.network);
+    let anchor_addr = anchor(&config);
+
+    // the templates commit to the unwind sequence, it has to satisfy the timelock
+    let backend = policy_backend(&config, |hash| LeafPolicy::ctv(hash).and_older(6));
+    let error = build_pools(&addresses, &anchor_addr, &config, &backend).unwrap_err();
+    assert!(
+        error.to_string().contains("UNWIND_DELAY_BLOCKS"),
+        "{}",
+        error
+    );
+    config.unwind_delay = Some(6);
+    build_pools(&addresses, &anchor_addr, &config, &backend).unwrap();
+
+    let operator = key(99);
+    let backend = policy_backend(&config, move |hash| LeafPolicy::ctv(hash).and_key(operator));
+    let error = build_pools(&addresses, &anchor_addr, &config, &backend).unwrap_err();
+    assert!(error.to_string().contains("needs a signature"), "{}", error);
+
+    // a timelock alone lets anyone take the node anywhere
+    let backend = policy_backend(&config, |_| LeafPolicy::older(6));
+    let error = build_pools(&addresses, &anchor_addr, &config, &backend).unwrap_err();
+    assert!(
+        error.to_string().contains("doesn't check its own template"),
+        "{}",
+        error
+    );
+}
+
+#[test]
+fn the_manifest_of_a_policy_pool_asks_for_its_backend() {
+    let mut config = NetworkConfig::new(NetworkProfile::RegtestLocal);
+    config.recovery = None;
+    let addresses = addresses(config.network);
+    let anchor_addr = anchor(&config);
+    let operator = key(99);
+    let backend = policy_backend(&config, move |hash| LeafPolicy::ctv(hash).or_key(operator));
+    let pools = build_pools(&addresses, &anchor_addr, &config, &backend).unwrap();
+    let root = pools.root().unwrap().address(&config);
+
+    let manifest = PoolManifest::new(&config, &backend, &anchor_addr, &addresses, &root);
+    let error = manifest.load_pool().err().unwrap();
+    assert!(error.to_string().contains("PolicyBackend"), "{}", error);
+}
+
+#[test]
+fn miniscript_fallbacks_compile_and_are_sized() {
+    let multisig = format!("thresh(2,pk({}),pk({}),pk({}))", key(1), key(2), key(3));
+    let fallback = LeafPolicy::miniscript(&multisig).unwrap();
+    let LeafPolicy::Miniscript(miniscript) = &fallback else {
+        panic!("not a miniscript branch");
+    };
+    let fallback_size = miniscript.max_satisfaction_size().unwrap();
+    // two signatures and an empty item for the key that doesn't sign
+    assert_eq!(fallback_size, 2 * 66 + 1);
+
+    let policy = LeafPolicy::ctv(HASH).or_miniscript(&multisig).unwrap();
+    assert!(policy
+        .script()
+        .to_asm_string()
+        .starts_with("OP_IF OP_PUSHBYTES_32"));
+    assert!(policy.script().to_asm_string().contains("OP_CHECKSIGADD"));
+    // the pool still leaves through the template, key holders budget for the bigger side
+    assert_eq!(policy.covenant_path().unwrap().stack, vec![vec![1]]);
+    assert_eq!(policy.max_satisfaction_size(), Some(fallback_size + 1));
+    assert_eq!(
+        LeafPolicy::ctv(HASH).or_key(key(1)).max_satisfaction_size(),
+        Some(67)
+    );
+}
+
+#[test]
+fn miniscript_branches_anyone_could_take_are_refused() {
+    let error = LeafPolicy::miniscript("older(1000)").unwrap_err();
+    assert!(error.to_string().contains("doesn't compile"), "{}", error);
+    let error = LeafPolicy::miniscript("pk(not_a_key)").unwrap_err();
+    assert!(
+        error.to_string().contains("invalid miniscript policy"),
+        "{}",
+        error
+    );
+}
+
+#[test]
+fn recovery_keys_add_to_the_template_fees() {
+    let mut config = NetworkConfig::new(NetworkProfile::RegtestLocal);
+    config.recovery = None;
+    let addresses = addresses(config.network);
+    let anchor_addr = anchor(&config);
+    let recovery = LeafPolicy::miniscript(&format!("and(pk({}),older(1000))", key(99))).unwrap();
+    let backend = policy_backend(&config, move |hash| {
+        LeafPolicy::ctv(hash).or(recovery.clone())
+    });
+    let pools = build_pools(&addresses, &anchor_addr, &config, &backend).unwrap();
+    let ctv = common::backend(&config);
+    let plain = build_pools(&addresses, &anchor_addr, &config, &ctv).unwrap();
+
+    let fees = template_fees(&pools, &config, &backend, &addresses, &anchor_addr).unwrap();
+    let plain_fees = template_fees(&plain, &config, &ctv, &addresses, &anchor_addr).unwrap();
+    // the selector item and the longer leaf, every template the same outputs
+    assert_eq!(fees.len(), plain_fees.len());
+    for (fee, plain_fee) in fees.iter().zip(&plain_fees) {
+        assert!(fee.vsize > plain_fee.vsize);
+    }
+
+    // and finalize puts exactly the items the estimate counted
+    let template = pool_spend_template(
+        &pools,
+        &config,
+        &backend,
+        UserIndex::new(0).unwrap(),
+        &addresses,
+        &funding_tx(&pools),
+        &anchor_addr,
+    )
+    .unwrap();
+    let items = backend.witness_items(template.template_hash);
+    let tx = backend.finalize(template).unwrap();
+    assert_eq!(items, vec![1]);
+    assert_eq!(tx.input[0].witness.len(), items.len() + 2);
+}
@@ -0,0 +1,240 @@
+use bitcoin::{
+    absolute,
+    hashes::{sha256, Hash, HashEngine},
+    transaction, Address, Amount, OutPoint, Transaction, TxIn, TxOut,
+};
+use std::collections::HashSet;
+
+use op_ctv_payment_pool::{
+    config::NetworkConfig,
+    covenant::{CovenantBackend, CtvBackend},
+    ctv_scripts::create_pool_address,
+    ids::{NodePath, UserIndex},
+    pools::{build_pools, node_amount, node_exit, pool_spend_template},
+    profile::NetworkProfile,
+    recovery::recovery_leaf,
+    tree::PoolTree,
+    POOL_USERS,
+};
+
+mod common;
+
+use common::{addresses, anchor, backend};
+
+fn setup(unwind_delay: Option<u16>) -> (NetworkConfig, CtvBackend, Address, PoolTree) {
+    let mut config = NetworkConfig::new(NetworkProfile::RegtestLocal);
+    config.unwind_delay = unwind_delay;
+    let backend = backend(&config);
+    let anchor_addr = anchor(&config);
+    let tree = build_pools(&addresses(config.network), &anchor_addr, &config, &backend).unwrap();
+    (config, backend, anchor_addr, tree)
+}
+
+fn pools() -> PoolTree {
+    setup(None).3
+}
+
+// hash of every node's output key in level order
+fn fingerprint(tree: &PoolTree) -> String {
+    let mut engine = sha256::Hash::engine();
+    for (users, node) in tree.iter_nodes() {
+        engine.input(users.to_string().as_bytes());
+        engine.input(&node.spend_info.output_key().serialize());
+    }
+    sha256::Hash::from_engine(engine).to_string()
+}
+
+// roots and node keys of the tree as the nested spend info maps built it, before the tree type
+#[test]
+fn tree_matches_the_old_representation() {
+    let cases = [
+        (
+            None,
+            "bcrt1pr8mwdpa9vueqnzmld3vqqrczzundy723eae7jnwha92urs4xm4hqlv5xey",
+            "f21e1bb829b6ee9b30d0f9ddff2a201c3507c3aaf371f94190f4c5bbcafd2116",
+        ),
+        (
+            Some(6),
+            "bcrt1pzyckk935mlnmyqwrzdhw9r6slllx3dmh8md2kyex8dj55nrkh6vs8spep8",
+            "59748f6243f014f0de14a842420a77ecc121ef206ae2f8d185d258642541b24e",
+        ),
+    ];
+    for (delay, root, nodes) in cases {
+        let (config, _, _, tree) = setup(delay);
+        assert_eq!(tree.root().unwrap().address(&config).to_string(), root);
+        assert_eq!(fingerprint(&tree), nodes);
+    }
+}
+
+#[test]
+fn nodes_hold_their_templates_leaves_and_children() {
+    let (config, backend, anchor_addr, tree) = setup(None);
+    let addresses = addresses(config.network);
+
+    for (users, node) in tree.iter_nodes() {
+        assert_eq!(node.amount, node_amount(users.len()).unwrap());
+        let spenders: Vec<UserIndex> = if users.is_exit() {
+            users.user_indices().take(1).collect()
+        } else {
+            users.user_indices().collect()
+        };
+        // every template is the one the spend of that leaf is built from
+        let templates: Vec<[u8; 32]> = spenders
+            .iter()
+            .map(|&spender| {
+                node_exit(
+                    &tree,
+                    &config,
+                    &backend,
+                    &addresses,
+                    &anchor_addr,
+                    users,
+                    spender,
+                )
+                .unwrap()
+                .template_hash
+            })
+            .collect();
+        assert_eq!(node.templates, templates);
+        let scripts: Vec<_> = templates
+            .iter()
+            .map(|&hash| backend.leaf_script(hash))
+            .collect();
+        assert_eq!(node.leaf_scripts, scripts);
+
+        // and the taproot tree is built from them the way it always was
+        let spend_info = create_pool_address(
+            templates,
+            &backend,
+            recovery_leaf(&config, node.amount, &anchor_addr),
+            config.tree_layout,
+            config.leaf_version,
+        )
+        .unwrap();
+        assert_eq!(spend_info.output_key(), node.spend_info.output_key());
+
+        for child in &node.children {
+            assert_eq!(child.len() + 1, users.len());
+            assert!(tree.node(child).is_ok());
+        }
+        assert_eq!(node.children.is_empty(), users.is_exit());
+    }
+}
+
+#[test]
+fn both_orders_visit_every_node_once() {
+    let tree = pools();
+    let total = tree.len();
+
+    for nodes in [
+        tree.iter_nodes().collect::<Vec<_>>(),
+        tree.iter_nodes_depth_first().collect(),
+    ] {
+        assert_eq!(nodes.len(), total);
+        let distinct: HashSet<&NodePath> = nodes.iter().map(|(users, _)| *users).collect();
+        assert_eq!(distinct.len(), total);
+        assert!(nodes[0].0.is_root());
+        for (users, node) in &nodes {
+            assert_eq!(&node.users, *users);
+        }
+    }
+}
+
+#[test]
+fn level_order_goes_down_a_level_at_a_time() {
+    let tree = pools();
+    let sizes: Vec<usize> = tree.iter_nodes().map(|(users, _)| users.len()).collect();
+    assert!(sizes.windows(2).all(|pair| pair[0] >= pair[1]));
+    assert_eq!(*sizes.last().unwrap(), 2);
+}
+
+#[test]
+fn depth_first_follows_the_planned_unwind_down_first() {
+    let tree = pools();
+    let nodes: Vec<&NodePath> = tree
+        .iter_nodes_depth_first()
+        .map(|(users, _)| users)
+        .collect();
+
+    // user 0 leaves first, then user 1, ... down to the exit pool
+    for (depth, users) in nodes.iter().take(POOL_USERS - 1).enumerate() {
+        assert_eq!(users.users(), &(depth..POOL_USERS).collect::<Vec<_>>()[..]);
+    }
+
+    // every node comes after a parent it is reached from
+    let mut seen = HashSet::new();
+    for users in nodes {
+        if !users.is_root() {
+            let has_parent = (0..POOL_USERS)
+                .filter(|user| !users.users().contains(user))
+                .any(|user| {
+                    let mut parent = users.users().to_vec();
+                    parent.push(user);
+                    parent.sort();
+                    seen.contains(&NodePath::new(parent).unwrap())
+                });
+            assert!(has_parent, "{} came before its parents", users);
+        }
+        seen.insert(users.clone());
+    }
+}
+
+// a tx paying `outputs`, standing in for the funding tx
+fn paying(outputs: Vec<TxOut>) -> Transaction {
+    Transaction {
+        version: transaction::Version::TWO,
+        lock_time: absolute::LockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: OutPoint::null(),
+            ..Default::default()
+        }],
+        output: outputs,
+    }
+}
+
+#[test]
+fn the_spend_takes_the_output_paying_the_node() {
+    let (config, backend, anchor_addr, tree) = setup(None);
+    let addresses = addresses(config.network);
+    let root = tree.root().unwrap();
+    let node = TxOut {
+        value: root.amount,
+        script_pubkey: root.address(&config).script_pubkey(),
+    };
+    let spent = |funding_tx: &Transaction| {
+        pool_spend_template(
+            &tree,
+            &config,
+            &backend,
+            UserIndex::new(0).unwrap(),
+            &addresses,
+            funding_tx,
+            &anchor_addr,
+        )
+        .map(|spend| spend.tx.input[0].previous_output)
+    };
+
+    // change of the same value to another script, then the node's address with another amount
+    let change = TxOut {
+        script_pubkey: anchor_addr.script_pubkey(),
+        ..node.clone()
+    };
+    let short = TxOut {
+        value: root.amount - Amount::from_sat(1),
+        ..node.clone()
+    };
+    let funding_tx = paying(vec![change, short.clone(), node.clone()]);
+    assert_eq!(
+        spent(&funding_tx).unwrap(),
+        OutPoint::new(funding_tx.compute_txid(), 2)
+    );
+    // two outputs alike, the first of them
+    let funding_tx = paying(vec![short.clone(), node.clone(), node.clone()]);
+    assert_eq!(
+        spent(&funding_tx).unwrap(),
+        OutPoint::new(funding_tx.compute_txid(), 1)
+    );
+    // and none with the node's amount
+    let err = spent(&paying(vec![short])).unwrap_err().to_string();
+    assert!(err.contains("the templates need"), "{}", err);
+}
@@ -0,0 +1,185 @@
+use bitcoin::{
+    absolute, sighash::TapSighashType, transaction, Address, OutPoint, Transaction, TxIn, TxOut,
+};
+
+use op_ctv_payment_pool::{
+    config::NetworkConfig,
+    covenant::{CovenantBackend, EphemeralSignerBackend, TemplateSpend},
+    ids::UserIndex,
+    interpreter::verify_input,
+    pools::{build_pools, pool_spend_template, presign_unwind},
+    profile::NetworkProfile,
+    template::Bip119Ctv,
+    tree::PoolTree,
+};
+
+mod common;
+
+use common::{addresses, anchor};
+
+fn config() -> NetworkConfig {
+    NetworkConfig::new(NetworkProfile::RegtestLocal)
+}
+
+fn presigned(config: &NetworkConfig, sighash_type: TapSighashType) -> EphemeralSignerBackend {
+    EphemeralSignerBackend::new(
+        Bip119Ctv {
+            tx_version: config.tx_version,
+        },
+        sighash_type,
+    )
+    .unwrap()
+}
+
+// the pool under `backend` and a tx funding its root
+fn pool(
+    config: &NetworkConfig,
+    backend: &EphemeralSignerBackend,
+) -> (PoolTree, Vec<Address>, Address, Transaction) {
+    let addresses = addresses(config.network);
+    let anchor_addr = anchor(config);
+    let tree = build_pools(&addresses, &anchor_addr, config, backend).unwrap();
+    let funding_tx = Transaction {
+        version: transaction::Version::TWO,
+        lock_time: absolute::LockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: OutPoint::null(),
+            ..Default::default()
+        }],
+        output: vec![TxOut {
+            value: tree.root().unwrap().amount,
+            script_pubkey: tree.root().unwrap().address(config).script_pubkey(),
+        }],
+    };
+    (tree, addresses, anchor_addr, funding_tx)
+}
+
+fn first_exit(
+    config: &NetworkConfig,
+    backend: &EphemeralSignerBackend,
+    tree: &PoolTree,
+    addresses: &[Address],
+    anchor_addr: &Address,
+    funding_tx: &Transaction,
+) -> TemplateSpend {
+    pool_spend_template(
+        tree,
+        config,
+        backend,
+        UserIndex::new(0).unwrap(),
+        addresses,
+        funding_tx,
+        anchor_addr,
+    )
+    .unwrap()
+}
+
+#[test]
+fn a_sealed_backend_finalizes_what_it_presigned() {
+    let config = config();
+    for sighash_type in [
+        TapSighashType::Default,
+        TapSighashType::All,
+        TapSighashType::AllPlusAnyoneCanPay,
+    ] {
+        let mut backend = presigned(&config, sighash_type);
+        let (tree, addresses, anchor_addr, funding_tx) = pool(&config, &backend);
+        presign_unwind(
+            &tree,
+            &config,
+            &mut backend,
+            &addresses,
+            &funding_tx,
+            &anchor_addr,
+        )
+        .unwrap();
+
+        let spend = first_exit(
+            &config,
+            &backend,
+            &tree,
+            &addresses,
+            &anchor_addr,
+            &funding_tx,
+        );
+        let prevout = spend.prevout.clone();
+        let tx = backend.finalize(spend).unwrap();
+        // signature, leaf and control block, and the signature checks out against the leaf's key
+        assert_eq!(tx.input[0].witness.len(), 3);
+        let signature = &tx.input[0].witness[0];
+        let expected = backend.witness_items([0; 32])[0];
+        assert_eq!(signature.len(), expected, "{}", sighash_type);
+        verify_input(&tx, 0, &[prevout]).unwrap();
+    }
+}
+
+#[test]
+fn nothing_is_finalized_before_the_key_is_erased() {
+    let config = config();
+    let mut backend = presigned(&config, TapSighashType::Default);
+    let (tree, addresses, anchor_addr, funding_tx) = pool(&config, &backend);
+    let spend = first_exit(
+        &config,
+        &backend,
+        &tree,
+        &addresses,
+        &anchor_addr,
+        &funding_tx,
+    );
+    backend.presign(&spend).unwrap();
+    let error = backend.finalize(spend.clone()).unwrap_err();
+    assert!(error.to_string().contains("not been deleted"), "{}", error);
+
+    backend.seal();
+    backend.finalize(spend).unwrap();
+}
+
+#[test]
+fn nothing_is_signed_after_the_key_is_erased() {
+    let config = config();
+    let mut backend = presigned(&config, TapSighashType::Default);
+    let (tree, addresses, anchor_addr, funding_tx) = pool(&config, &backend);
+    let spend = first_exit(
+        &config,
+        &backend,
+        &tree,
+        &addresses,
+        &anchor_addr,
+        &funding_tx,
+    );
+    backend.seal();
+    let error = backend.presign(&spend).unwrap_err();
+    assert!(error.to_string().contains("already deleted"), "{}", error);
+    // and what was never signed has no signature to finalize with
+    let error = backend.finalize(spend).unwrap_err();
+    assert!(
+        error.to_string().contains("no presigned signature"),
+        "{}",
+        error
+    );
+}
+
+#[test]
+fn sighash_types_that_leave_outputs_open_are_refused() {
+    let config = config();
+    for sighash_type in [
+        TapSighashType::None,
+        TapSighashType::Single,
+        TapSighashType::NonePlusAnyoneCanPay,
+        TapSighashType::SinglePlusAnyoneCanPay,
+    ] {
+        let error = EphemeralSignerBackend::new(
+            Bip119Ctv {
+                tx_version: config.tx_version,
+            },
+            sighash_type,
+        )
+        .err()
+        .unwrap_or_else(|| panic!("{} accepted", sighash_type));
+        assert!(
+            error.to_string().contains("does not commit to all outputs"),
+            "{}",
+            error
+        );
+    }
+}
@@ -0,0 +1,112 @@
+use bitcoin::{address::NetworkUnchecked, Address, Amount};
+
+use op_ctv_payment_pool::{
+    config::NetworkConfig,
+    payouts::{PayoutSplits, SplitPayout},
+    pools::build_pools,
+    privacy::{privacy_report, PrivacyReport, Severity, COMMON_P2A_ANCHOR_AMOUNT},
+    profile::NetworkProfile,
+    POOL_USERS,
+};
+
+mod common;
+
+use common::{address, addresses, anchor, backend};
+
+fn report(config: &NetworkConfig, addresses: &[Address]) -> PrivacyReport {
+    let backend = backend(config);
+    let anchor_addr = anchor(config);
+    let pools = build_pools(addresses, &anchor_addr, config, &backend).unwrap();
+    privacy_report(&pools, config, &backend, addresses, &anchor_addr).unwrap()
+}
+
+fn kinds(report: &PrivacyReport) -> Vec<&str> {
+    report.findings.iter().map(|finding| finding.kind).collect()
+}
+
+fn severity(report: &PrivacyReport, kind: &str) -> Option<Severity> {
+    report
+        .findings
+        .iter()
+        .find(|finding| finding.kind == kind)
+        .map(|finding| finding.severity)
+}
+
+#[test]
+fn the_default_pool_stands_out() {
+    let config = NetworkConfig::new(NetworkProfile::RegtestLocal);
+    let addresses = addresses(config.network);
+    let report = report(&config, &addresses);
+
+    // every leaving user is paid the same round amount, the exit pool's second user the rest
+    assert_eq!(report.distinct_payout_amounts, 2);
+    assert_eq!(report.uniform_payouts, report.templates);
+    assert_eq!(report.round_payouts, report.payouts);
+    assert_eq!(report.anchor_outputs, report.templates);
+    assert_eq!(report.ctv_leaf_reveals, report.templates);
+    assert!(report.nums_internal_key);
+    assert_eq!(report.reused_addresses, 0);
+    assert_eq!(
+        severity(&report, "anchor_fingerprint"),
+        Some(Severity::High)
+    );
+    for kind in [
+        "uniform_amounts",
+        "node_amounts",
+        "tx_version",
+        "leaf_reveals",
+    ] {
+        assert!(kinds(&report).contains(&kind), "{:?}", kinds(&report));
+    }
+    assert!(!kinds(&report).contains(&"address_reuse"));
+    // worst first
+    assert_eq!(report.findings[0].severity, Severity::High);
+}
+
+#[test]
+fn uneven_splits_and_common_anchors_blur_the_pool() {
+    let mut config = NetworkConfig::new(NetworkProfile::RegtestLocal);
+    config.anchor_amount = Some(COMMON_P2A_ANCHOR_AMOUNT);
+    let addresses = addresses(config.network);
+    let mut splits = PayoutSplits::new();
+    for (i, user) in addresses.iter().enumerate() {
+        let split: Address<NetworkUnchecked> = address(100 + i, config.network)
+            .to_string()
+            .parse()
+            .unwrap();
+        splits.insert(
+            user.to_string(),
+            vec![SplitPayout {
+                address: split,
+                amount: Amount::from_sat(1_001 + 37 * i as u64),
+            }],
+        );
+    }
+    config.splits = splits;
+    let report = report(&config, &addresses);
+
+    assert!(report.distinct_payout_amounts > POOL_USERS);
+    assert!(!kinds(&report).contains(&"uniform_amounts"));
+    assert_eq!(
+        severity(&report, "anchor_fingerprint"),
+        Some(Severity::Medium)
+    );
+}
+
+#[test]
+fn without_anchors_nothing_ties_the_txs_together() {
+    let mut config = NetworkConfig::new(NetworkProfile::RegtestLocal);
+    config.anchor_amount = None;
+    config.tx_version = 2;
+    let mut addresses: Vec<Address> = (0..POOL_USERS)
+        .map(|i| address(i, config.network))
+        .collect();
+    addresses[1] = addresses[0].clone();
+    let report = report(&config, &addresses);
+
+    assert_eq!(report.anchor_outputs, 0);
+    assert!(!kinds(&report).contains(&"anchor_fingerprint"));
+    assert!(!kinds(&report).contains(&"tx_version"));
+    assert_eq!(report.reused_addresses, 1);
+    assert_eq!(severity(&report, "address_reuse"), Some(Severity::High));
+}
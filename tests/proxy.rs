@@ -0,0 +1,95 @@
+use bitcoin::Txid;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpListener,
+};
+
+use op_ctv_payment_pool::{
+    esplora::Esplora,
+    proxy::{check_endpoint, parse_proxy, set_proxy},
+};
+
+// A SOCKS5 proxy taking one connection and answering its http request with a 404 itself.
+// Returns its url and the host and port the client asked it to connect to.
+async fn socks5_proxy() -> (String, tokio::task::JoinHandle<(String, u16, String)>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("socks5h://{}", listener.local_addr().unwrap());
+    let connection = tokio::spawn(async move {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        // greeting: version, methods, no authentication picked
+        let mut greeting = [0; 2];
+        stream.read_exact(&mut greeting).await.unwrap();
+        let mut methods = vec![0; greeting[1] as usize];
+        stream.read_exact(&mut methods).await.unwrap();
+        stream.write_all(&[5, 0]).await.unwrap();
+        // connect by domain name (0x03), the name resolved here and not by the client
+        let mut request = [0; 5];
+        stream.read_exact(&mut request).await.unwrap();
+        assert_eq!(&request[..4], &[5, 1, 0, 3]);
+        let mut host = vec![0; request[4] as usize];
+        stream.read_exact(&mut host).await.unwrap();
+        let port = stream.read_u16().await.unwrap();
+        stream
+            .write_all(&[5, 0, 0, 1, 127, 0, 0, 1, 0, 0])
+            .await
+            .unwrap();
+
+        let mut http = Vec::new();
+        let mut buf = [0; 1024];
+        while !String::from_utf8_lossy(&http).contains("\r\n\r\n") {
+            let n = stream.read(&mut buf).await.unwrap();
+            http.extend_from_slice(&buf[..n]);
+        }
+        stream
+            .write_all(b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        let line = String::from_utf8_lossy(&http)
+            .lines()
+            .next()
+            .unwrap()
+            .to_string();
+        (String::from_utf8(host).unwrap(), port, line)
+    });
+    (url, connection)
+}
+
+#[test]
+fn only_socks5_proxies_reach_onion_services() {
+    assert!(parse_proxy("socks5h://127.0.0.1:9050").is_ok());
+    assert!(parse_proxy("socks5://127.0.0.1:9050").is_ok());
+    assert!(parse_proxy("http://127.0.0.1:8080").is_err());
+    assert!(parse_proxy("socks5h://127.0.0.1").is_err());
+
+    let onion = "http://explorerzydxu5ecjrkwceayqybizmpjjznk5izmitf2modhcusuqlid.onion/api";
+    assert!(check_endpoint(onion, None).is_err());
+    // a socks5:// proxy resolves names locally, where .onion doesn't exist
+    assert!(check_endpoint(onion, Some("socks5://127.0.0.1:9050")).is_err());
+    assert!(check_endpoint(onion, Some("socks5h://127.0.0.1:9050")).is_ok());
+    // clearnet hosts work either way
+    assert!(check_endpoint("https://mempool.space/signet/api", None).is_ok());
+}
+
+#[tokio::test]
+async fn esplora_requests_go_through_the_proxy() {
+    let (proxy, connection) = socks5_proxy().await;
+    set_proxy(Some(&proxy));
+    let onion = "http://explorerzydxu5ecjrkwceayqybizmpjjznk5izmitf2modhcusuqlid.onion/api";
+    let esplora = Esplora::new(onion).unwrap();
+    set_proxy(None);
+
+    let txid: Txid = "5505d34a5a121fd128fb9a0f05d5a0bf8c2bd399aa36456f4a28f481b3211683"
+        .parse()
+        .unwrap();
+    assert!(esplora.tx_status(txid).await.unwrap().is_none());
+    let (host, port, request) = connection.await.unwrap();
+    assert_eq!(
+        host,
+        "explorerzydxu5ecjrkwceayqybizmpjjznk5izmitf2modhcusuqlid.onion"
+    );
+    assert_eq!(port, 80);
+    assert_eq!(request, format!("GET /api/tx/{}/status HTTP/1.1", txid));
+
+    // and without it the onion is refused instead of looked up
+    assert!(Esplora::new(onion).is_err());
+}
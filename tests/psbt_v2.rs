@@ -0,0 +1,160 @@
+use bitcoin::{
+    absolute,
+    base64::{prelude::BASE64_STANDARD, Engine},
+    Psbt, TxOut,
+};
+use std::{fs, str::FromStr};
+
+use op_ctv_payment_pool::{
+    cold::{export_psbts, finalize_psbt_file, template_psbt},
+    config::NetworkConfig,
+    fund::{read_psbt, read_psbt_version, required_funding},
+    ids::UserIndex,
+    manifest::LoadedPool,
+    mock::MockBackend,
+    pools::pool_spend_template,
+    profile::NetworkProfile,
+    psbt_v2::{decode_psbt, deserialize_v2, encode_psbt, psbt_version, serialize_v2, PsbtVersion},
+};
+
+mod common;
+
+use common::loaded_pool;
+
+fn pool() -> LoadedPool {
+    loaded_pool(&NetworkConfig::new(NetworkProfile::RegtestLocal))
+}
+
+fn funding_tx(mock: &MockBackend, pool: &LoadedPool) -> bitcoin::Transaction {
+    let outpoint = mock.add_output(TxOut {
+        value: required_funding(),
+        script_pubkey: pool
+            .tree
+            .root()
+            .unwrap()
+            .address(&pool.config)
+            .script_pubkey(),
+    });
+    mock.transaction(outpoint.txid).unwrap()
+}
+
+fn first_spend_psbt(pool: &LoadedPool, funding_tx: &bitcoin::Transaction) -> Psbt {
+    let template = pool_spend_template(
+        &pool.tree,
+        &pool.config,
+        pool.backend.as_ref(),
+        UserIndex::new(0).unwrap(),
+        &pool.addresses,
+        funding_tx,
+        &pool.anchor_addr,
+    )
+    .unwrap();
+    template_psbt(&template, pool.backend.leaf_script(template.template_hash)).unwrap()
+}
+
+// one key-value pair of a PSBT map, the key without key data
+fn pair(key_type: u8, value: &[u8]) -> Vec<u8> {
+    let mut out = vec![1, key_type, value.len() as u8];
+    out.extend(value);
+    out
+}
+
+#[test]
+fn template_psbts_keep_their_tap_fields_through_version_2() {
+    let pool = pool();
+    let mock = MockBackend::new(pool.config.network);
+    let psbt = first_spend_psbt(&pool, &funding_tx(&mock, &pool));
+
+    let v2 = serialize_v2(&psbt).unwrap();
+    assert_eq!(psbt_version(&v2).unwrap(), PsbtVersion::V2);
+    assert_eq!(psbt_version(&psbt.serialize()).unwrap(), PsbtVersion::V0);
+    // no unsigned tx, a version 0 parser has to refuse it
+    assert!(Psbt::deserialize(&v2).is_err());
+
+    let back = deserialize_v2(&v2).unwrap();
+    assert_eq!(back, psbt);
+    assert_eq!(back.inputs[0].tap_scripts, psbt.inputs[0].tap_scripts);
+    assert_eq!(
+        back.inputs[0].tap_internal_key,
+        psbt.inputs[0].tap_internal_key
+    );
+    assert_eq!(
+        back.inputs[0].tap_merkle_root,
+        psbt.inputs[0].tap_merkle_root
+    );
+    assert_eq!(back.inputs[0].proprietary, psbt.inputs[0].proprietary);
+
+    // base64 of either version decodes to the same PSBT
+    for version in [PsbtVersion::V0, PsbtVersion::V2] {
+        let text = encode_psbt(&psbt, version).unwrap();
+        let (decoded, read) = decode_psbt(&BASE64_STANDARD.decode(text).unwrap()).unwrap();
+        assert_eq!(read, version);
+        assert_eq!(decoded, psbt);
+    }
+}
+
+#[test]
+fn exported_version_2_psbts_finalize_in_version_2() {
+    let pool = pool();
+    let mock = MockBackend::new(pool.config.network);
+    let funding_tx = funding_tx(&mock, &pool);
+    let dir = std::env::temp_dir().join(format!("pool-psbts-v2-{}", std::process::id()));
+
+    let spends = export_psbts(&pool, &funding_tx, &dir, PsbtVersion::V2).unwrap();
+    assert!(spends.iter().all(|spend| spend.version == PsbtVersion::V2));
+    let (psbt, version) = read_psbt_version(&spends[0].file).unwrap();
+    assert_eq!(version, PsbtVersion::V2);
+    assert_eq!(psbt, first_spend_psbt(&pool, &funding_tx));
+
+    let txid = finalize_psbt_file(&spends[0].file, None).unwrap();
+    assert_eq!(txid, spends[0].txid);
+    let (finalized, version) = read_psbt_version(&spends[0].file).unwrap();
+    assert_eq!(version, PsbtVersion::V2);
+    assert!(finalized.inputs[0].final_script_witness.is_some());
+
+    // and the compatibility flag's stay version 0
+    let spends = export_psbts(&pool, &funding_tx, &dir, PsbtVersion::V0).unwrap();
+    let text = fs::read_to_string(&spends[0].file).unwrap();
+    assert_eq!(
+        Psbt::from_str(&text).unwrap(),
+        read_psbt(&spends[0].file).unwrap()
+    );
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn the_locktime_is_the_highest_any_input_requires() {
+    let psbt = |locks: &[(u8, u32)]| {
+        let mut bytes = b"psbt\xff".to_vec();
+        bytes.extend(pair(0x02, &2u32.to_le_bytes()));
+        bytes.extend(pair(0x03, &7u32.to_le_bytes()));
+        bytes.extend(pair(0x04, &[locks.len().max(1) as u8]));
+        bytes.extend(pair(0x05, &[1]));
+        bytes.extend(pair(0xfb, &2u32.to_le_bytes()));
+        bytes.push(0x00);
+        for i in 0..locks.len().max(1) {
+            bytes.extend(pair(0x0e, &[i as u8 + 1; 32]));
+            bytes.extend(pair(0x0f, &0u32.to_le_bytes()));
+            if let Some((key_type, lock)) = locks.get(i) {
+                bytes.extend(pair(*key_type, &lock.to_le_bytes()));
+            }
+            bytes.push(0x00);
+        }
+        bytes.extend(pair(0x03, &1_000u64.to_le_bytes()));
+        bytes.extend(pair(0x04, &[0x51]));
+        bytes.push(0x00);
+        deserialize_v2(&bytes).map(|psbt| psbt.unsigned_tx.lock_time)
+    };
+    // nothing required, the fallback
+    assert_eq!(psbt(&[]).unwrap(), absolute::LockTime::from_consensus(7));
+    // heights and times, 0x12 and 0x11
+    assert_eq!(
+        psbt(&[(0x12, 800_000), (0x12, 800_100)]).unwrap(),
+        absolute::LockTime::from_consensus(800_100)
+    );
+    assert_eq!(
+        psbt(&[(0x11, 1_700_000_000), (0x11, 1_600_000_000)]).unwrap(),
+        absolute::LockTime::from_consensus(1_700_000_000)
+    );
+    assert!(psbt(&[(0x12, 800_000), (0x11, 1_700_000_000)]).is_err());
+}
@@ -0,0 +1,144 @@
+use std::{collections::BTreeMap, io::Read};
+
+use op_ctv_payment_pool::{
+    config::NetworkConfig,
+    export::PoolExport,
+    manifest::PoolManifest,
+    pools::build_pools,
+    profile::NetworkProfile,
+    publish::{build_release, release_files, upload_release, verify_release, RELEASE_FILE},
+    POOL_USERS,
+};
+
+mod common;
+
+use common::{addresses, anchor, backend, keypair};
+
+fn manifest() -> PoolManifest {
+    let config = NetworkConfig::new(NetworkProfile::RegtestLocal);
+    let backend = backend(&config);
+    let addresses = addresses(config.network);
+    let anchor_addr = anchor(&config);
+    let pools = build_pools(&addresses, &anchor_addr, &config, &backend).unwrap();
+    let root = pools.root().unwrap().address(&config);
+    let mut manifest = PoolManifest::new(&config, &backend, &anchor_addr, &addresses, &root);
+    manifest
+        .metadata
+        .set(None, "owner", "alice@example.com")
+        .unwrap();
+    manifest
+}
+
+fn unpack(bytes: &[u8]) -> BTreeMap<String, Vec<u8>> {
+    let mut archive = tar::Archive::new(zstd::Decoder::new(bytes).unwrap());
+    archive
+        .entries()
+        .unwrap()
+        .map(|entry| {
+            let mut entry = entry.unwrap();
+            let path = entry.path().unwrap().to_string_lossy().into_owned();
+            let mut contents = Vec::new();
+            entry.read_to_end(&mut contents).unwrap();
+            (path, contents)
+        })
+        .collect()
+}
+
+fn repack(files: &BTreeMap<String, Vec<u8>>) -> Vec<u8> {
+    let mut archive = tar::Builder::new(zstd::Encoder::new(Vec::new(), 0).unwrap());
+    for (path, contents) in files {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(contents.len() as u64);
+        header.set_cksum();
+        archive
+            .append_data(&mut header, path, &contents[..])
+            .unwrap();
+    }
+    archive.into_inner().unwrap().finish().unwrap()
+}
+
+#[test]
+fn the_release_holds_every_members_artifacts() {
+    let manifest = manifest();
+    let files = release_files(&manifest).unwrap();
+    assert_eq!(files.len(), POOL_USERS + 3);
+
+    // every user gets the leaves they can leave by, without the coordinator's labels
+    let shared: PoolManifest = serde_json::from_slice(&files["manifest.json"]).unwrap();
+    assert_eq!(shared.root_address, manifest.root_address);
+    assert!(shared.metadata.is_empty());
+    let export: PoolExport = serde_json::from_slice(&files["exits/user-3.json"]).unwrap();
+    assert_eq!(export.user.unwrap().index(), 3);
+    assert!(!export.nodes.is_empty());
+
+    let descriptors: Vec<serde_json::Value> =
+        serde_json::from_slice(&files["descriptors.json"]).unwrap();
+    let root = descriptors[0]["desc"].as_str().unwrap();
+    assert!(root.starts_with(&format!("addr({})#", manifest.root_address)));
+    let labels = String::from_utf8(files["labels.jsonl"].clone()).unwrap();
+    // no funding tx yet, the node and withdraw addresses
+    assert_eq!(labels.lines().count(), descriptors.len() + POOL_USERS);
+}
+
+#[test]
+fn members_check_the_release_against_the_coordinator_key() {
+    let manifest = manifest();
+    let files = release_files(&manifest).unwrap();
+    let coordinator = keypair(41);
+    let (signed, bytes) = build_release(&manifest, &files, &coordinator).unwrap();
+    let key = coordinator.x_only_public_key().0;
+    assert_eq!(signed.coordinator, key);
+    // the same pool always packs into the same bytes
+    assert_eq!(
+        build_release(&manifest, &files, &coordinator).unwrap().1,
+        bytes
+    );
+
+    let verified = verify_release(&bytes, Some(key)).unwrap();
+    assert_eq!(verified.release.files.len(), files.len());
+    let error = verify_release(&bytes, Some(keypair(42).x_only_public_key().0)).unwrap_err();
+    assert!(
+        error.to_string().contains("not by the coordinator"),
+        "{}",
+        error
+    );
+
+    // a swapped exit kit no longer matches the signed hashes
+    let mut tampered = unpack(&bytes);
+    assert!(tampered.contains_key(RELEASE_FILE));
+    tampered.insert(
+        "exits/user-0.json".to_string(),
+        files["exits/user-1.json"].clone(),
+    );
+    let error = verify_release(&repack(&tampered), None).unwrap_err();
+    assert!(
+        error.to_string().contains("user-0.json was changed"),
+        "{}",
+        error
+    );
+
+    // and anything added to it isn't signed for
+    let mut extra = unpack(&bytes);
+    extra.insert("exits/extra.json".to_string(), b"{}".to_vec());
+    let error = verify_release(&repack(&extra), None).unwrap_err();
+    assert!(
+        error.to_string().contains("not in the signed release"),
+        "{}",
+        error
+    );
+}
+
+#[tokio::test]
+async fn releases_upload_to_a_local_directory() {
+    let dir = std::env::temp_dir().join(format!("pool-releases-{}", std::process::id()));
+    let archive = std::path::Path::new("pool_release.tar.zst");
+    let location = upload_release(&format!("file://{}", dir.display()), archive, b"release")
+        .await
+        .unwrap();
+    assert_eq!(
+        location,
+        dir.join("pool_release.tar.zst").display().to_string()
+    );
+    assert_eq!(std::fs::read(&location).unwrap(), b"release");
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,130 @@
+use bitcoin::{
+    absolute,
+    block::{Header, Version},
+    consensus::encode::{deserialize_hex, serialize_hex},
+    hashes::Hash,
+    hex::{DisplayHex, FromHex},
+    Amount, Block, BlockHash, CompactTarget, MerkleBlock, Network, OutPoint, ScriptBuf, Sequence,
+    Transaction, TxIn, TxMerkleNode, TxOut, Witness,
+};
+
+use op_ctv_payment_pool::{
+    ids::UserIndex,
+    receipts::{verify_receipt, WithdrawalReceipt},
+};
+
+mod common;
+
+use common::address;
+
+// regtest's easiest target, a hash meets it every other try
+const EASY_BITS: u32 = 0x207fffff;
+const WITHDRAWN: Amount = Amount::from_sat(1_000_000);
+
+fn mined(prev_blockhash: BlockHash, merkle_root: TxMerkleNode) -> Header {
+    let mut header = Header {
+        version: Version::TWO,
+        prev_blockhash,
+        merkle_root,
+        time: 1_700_000_000,
+        bits: CompactTarget::from_consensus(EASY_BITS),
+        nonce: 0,
+    };
+    while header.validate_pow(header.target()).is_err() {
+        header.nonce += 1;
+    }
+    header
+}
+
+// A receipt for user 3's withdrawal, mined in a block with two more on top, all at EASY_BITS
+fn receipt(network: Network) -> WithdrawalReceipt {
+    let withdraw_address = address(3, network);
+    let spend = |script_sig: ScriptBuf, output: TxOut| Transaction {
+        version: bitcoin::transaction::Version::TWO,
+        lock_time: absolute::LockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: OutPoint::null(),
+            script_sig,
+            sequence: Sequence::MAX,
+            witness: Witness::new(),
+        }],
+        output: vec![output],
+    };
+    let coinbase = spend(
+        ScriptBuf::from_bytes(vec![1, 1]),
+        TxOut {
+            value: Amount::from_sat(50 * 100_000_000),
+            script_pubkey: ScriptBuf::new(),
+        },
+    );
+    let exit = spend(
+        ScriptBuf::new(),
+        TxOut {
+            value: WITHDRAWN,
+            script_pubkey: withdraw_address.script_pubkey(),
+        },
+    );
+    let txid = exit.compute_txid();
+
+    let mut block = Block {
+        header: mined(BlockHash::all_zeros(), TxMerkleNode::all_zeros()),
+        txdata: vec![coinbase, exit.clone()],
+    };
+    block.header = mined(BlockHash::all_zeros(), block.compute_merkle_root().unwrap());
+    let merkle_proof = MerkleBlock::from_block_with_predicate(&block, |t| *t == txid);
+
+    let mut headers = vec![block.header];
+    for _ in 0..2 {
+        let previous = headers.last().unwrap().block_hash();
+        headers.push(mined(previous, TxMerkleNode::all_zeros()));
+    }
+    WithdrawalReceipt {
+        user: UserIndex::new(3).unwrap(),
+        network,
+        withdraw_address: withdraw_address.to_string(),
+        txid,
+        tx: serialize_hex(&exit),
+        merkle_proof: serialize_hex(&merkle_proof),
+        headers: headers.iter().map(serialize_hex).collect(),
+    }
+}
+
+#[test]
+fn a_receipt_checks_out_offline() {
+    let receipt = receipt(Network::Regtest);
+    let verified = verify_receipt(&receipt).unwrap();
+    assert_eq!(verified.txid, receipt.txid);
+    assert_eq!(verified.amount, WITHDRAWN);
+    assert_eq!(verified.confirmations, 3);
+    let tip: Header = deserialize_hex(receipt.headers.last().unwrap()).unwrap();
+    assert_eq!(verified.tip_hash, tip.block_hash());
+}
+
+#[test]
+fn headers_setting_their_own_easy_target_are_refused() {
+    // regtest difficulty is trivial to forge, on mainnet or signet it isn't allowed
+    for network in [Network::Bitcoin, Network::Signet] {
+        let error = verify_receipt(&receipt(network)).err().unwrap();
+        assert!(error.to_string().contains("easier target"), "{}", error);
+    }
+}
+
+#[test]
+fn headers_have_to_build_on_each_other() {
+    let mut receipt = receipt(Network::Regtest);
+    let elsewhere = mined(BlockHash::all_zeros(), TxMerkleNode::all_zeros());
+    receipt.headers[1] = serialize_hex(&elsewhere);
+    let error = verify_receipt(&receipt).err().unwrap();
+    assert!(error.to_string().contains("does not build on"), "{}", error);
+}
+
+#[test]
+fn a_tampered_merkle_branch_is_refused() {
+    let mut receipt = receipt(Network::Regtest);
+    let mut proof = Vec::<u8>::from_hex(&receipt.merkle_proof).unwrap();
+    // header, tx count and hash count, then the coinbase's hash in the branch
+    proof[80 + 4 + 1] ^= 1;
+    receipt.merkle_proof = proof.to_lower_hex_string();
+    let error = verify_receipt(&receipt).err().unwrap();
+    assert!(error.to_string().contains("merkle root"), "{}", error);
+}
@@ -0,0 +1,24 @@
+use op_ctv_payment_pool::redact::{hex, set_log_sensitive, short};
+
+// the flag is global to the test binary, so both settings are checked in one test
+#[test]
+fn info_logs_redact_unless_sensitive_logging_is_on() {
+    let tx = "0200000001".repeat(20);
+    let address = "bcrt1p0xlxvlhemja6c4dqv22uapctqupfhlxm9h8z3k2e72q4k9hcz7vqc8gma6";
+
+    set_log_sensitive(false);
+    let redacted = hex(&tx);
+    assert!(redacted.starts_with("<100 bytes, sha256 "), "{}", redacted);
+    assert!(!redacted.contains("0200000001"));
+    // the same tx hex redacts the same way so log lines can be matched up
+    assert_eq!(hex(&tx), redacted);
+    assert_ne!(hex(&"0300000001".repeat(20)), redacted);
+    assert_eq!(short(address), "bcrt1p0x..gma6");
+    // too short to be worth hiding
+    assert_eq!(short("user 3"), "user 3");
+
+    set_log_sensitive(true);
+    assert_eq!(hex(&tx), tx);
+    assert_eq!(short(address), address);
+    set_log_sensitive(false);
+}
@@ -0,0 +1,243 @@
+use bitcoin::{
+    absolute, transaction, Address, Amount, Network, OutPoint, Sequence, Transaction, TxIn, TxOut,
+};
+use std::str::FromStr;
+
+use op_ctv_payment_pool::{
+    config::{ConfirmationStage, NetworkConfig},
+    demo::planned_unwind,
+    error_codes::{error_code, ErrorCode},
+    ids::{NodePath, UserIndex},
+    lifecycle::{Event, Lifecycle},
+    manifest::PoolManifest,
+    payouts::jittered_share,
+    pools::{node_exit, pool_spend_template},
+    profile::NetworkProfile,
+    removal::{remove_participant, Rebalance},
+    state::{decode_state, encode_state},
+    AMOUNT_PER_USER, POOL_USERS,
+};
+
+mod common;
+
+use common::{address, pool_manifest};
+
+fn user(index: usize) -> UserIndex {
+    UserIndex::new(index).unwrap()
+}
+
+fn manifest() -> PoolManifest {
+    pool_manifest(&NetworkConfig::new(NetworkProfile::RegtestLocal))
+}
+
+// a tx paying the manifest's root what it asks for
+fn funding_tx(manifest: &PoolManifest) -> Transaction {
+    let root = Address::from_str(&manifest.root_address)
+        .unwrap()
+        .assume_checked();
+    Transaction {
+        version: transaction::Version::TWO,
+        lock_time: absolute::LockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: OutPoint::null(),
+            ..Default::default()
+        }],
+        output: vec![TxOut {
+            value: manifest.funding_target().unwrap(),
+            script_pubkey: root.script_pubkey(),
+        }],
+    }
+}
+
+#[test]
+fn the_pool_of_the_others_is_funded_with_less() {
+    let manifest = manifest();
+    let removed = remove_participant(&manifest, user(4), Rebalance::ReduceFunding).unwrap();
+    assert_eq!(removed.revision, manifest.revision + 1);
+    assert_ne!(removed.root_address, manifest.root_address);
+    assert_eq!(
+        removed.funding_target().unwrap(),
+        AMOUNT_PER_USER * (POOL_USERS as u64 - 1)
+    );
+
+    // the root is the node of everyone else, in the tree every other user's templates are built in
+    let pool = removed.load_pool().unwrap();
+    let others = NodePath::root().without(user(4)).unwrap();
+    assert_eq!(pool.config.pool_root().unwrap(), others);
+    assert_eq!(
+        pool.tree
+            .node(&others)
+            .unwrap()
+            .address(&pool.config)
+            .to_string(),
+        removed.root_address
+    );
+    // and so does the binary state
+    let (decoded, _) = decode_state(&encode_state(&removed).unwrap()).unwrap();
+    assert_eq!(decoded.removed, removed.removed);
+    assert_eq!(decoded.revision, removed.revision);
+}
+
+#[test]
+fn the_unwind_skips_the_removed_user() {
+    let removed = remove_participant(&manifest(), user(4), Rebalance::ReduceFunding).unwrap();
+    let pool = removed.load_pool().unwrap();
+    let spenders = pool.config.spenders().unwrap();
+    assert_eq!(spenders.len(), POOL_USERS - 2);
+    assert!(!spenders.contains(&user(4)));
+
+    let mut previous_tx = funding_tx(&removed);
+    let mut lifecycle = Lifecycle::Funded;
+    let root = removed.pool_root().unwrap();
+    for spender in spenders {
+        let spend = pool_spend_template(
+            &pool.tree,
+            &pool.config,
+            pool.backend.as_ref(),
+            spender,
+            &pool.addresses,
+            &previous_tx,
+            &pool.anchor_addr,
+        )
+        .unwrap();
+        let payout = address(spender.index(), Network::Regtest).script_pubkey();
+        assert!(spend.tx.output.iter().any(|o| o.script_pubkey == payout));
+        lifecycle = lifecycle.apply_in(Event::Withdraw(spender), &root).unwrap();
+        previous_tx = spend.tx;
+    }
+    assert_eq!(lifecycle, Lifecycle::Closed);
+    // the removed user has no exit of their own
+    assert!(pool.config.unwind_node(user(4)).is_err());
+    assert!(Lifecycle::Unwinding(4)
+        .apply_in(Event::Withdraw(user(4)), &root)
+        .is_err());
+}
+
+#[test]
+fn a_pro_rata_share_goes_to_the_others() {
+    let manifest = manifest();
+    let removed = remove_participant(&manifest, user(0), Rebalance::ProRata).unwrap();
+    // the same funding, all of it owed to the users left
+    assert_eq!(
+        removed.funding_target().unwrap(),
+        AMOUNT_PER_USER * POOL_USERS as u64
+    );
+    assert_eq!(removed.removed[0].redistributed, AMOUNT_PER_USER);
+    let shares: Vec<Amount> = (1..POOL_USERS)
+        .map(|i| jittered_share(user(i), &removed.payout_jitter))
+        .collect();
+    assert!(shares.iter().all(|share| *share > AMOUNT_PER_USER));
+    assert_eq!(
+        shares.iter().copied().sum::<Amount>(),
+        AMOUNT_PER_USER * POOL_USERS as u64
+    );
+    removed.load_pool().unwrap();
+
+    // a second removal hands the first one's share on too
+    let again = remove_participant(&removed, user(9), Rebalance::ProRata).unwrap();
+    assert_eq!(again.revision, 2);
+    assert_eq!(
+        again.funding_target().unwrap(),
+        AMOUNT_PER_USER * POOL_USERS as u64
+    );
+    again.load_pool().unwrap();
+}
+
+#[test]
+fn only_unfunded_pools_lose_participants() {
+    let mut funded = manifest();
+    funded.advance(Event::Fund).unwrap();
+    let error = remove_participant(&funded, user(1), Rebalance::ReduceFunding).unwrap_err();
+    assert_eq!(error_code(&error), ErrorCode::InvalidState);
+
+    let removed = remove_participant(&manifest(), user(1), Rebalance::ReduceFunding).unwrap();
+    assert!(remove_participant(&removed, user(1), Rebalance::ReduceFunding).is_err());
+
+    // a pool keeps at least 3 users
+    let mut shrunk = manifest();
+    for i in 0..POOL_USERS - 3 {
+        shrunk = remove_participant(&shrunk, user(i), Rebalance::ReduceFunding).unwrap();
+    }
+    assert_eq!(shrunk.pool_root().unwrap().len(), 3);
+    assert!(remove_participant(&shrunk, user(POOL_USERS - 1), Rebalance::ReduceFunding).is_err());
+}
+
+#[test]
+fn a_removal_edited_into_the_manifest_is_refused() {
+    let mut edited = manifest();
+    let removed = remove_participant(&edited, user(2), Rebalance::ProRata).unwrap();
+    // the removal without rebuilding: the root is the full pool's
+    edited.removed = removed.removed.clone();
+    assert!(edited.load_pool().is_err());
+    // the rebuilt root without the redistributed shares
+    edited.root_address = removed.root_address.clone();
+    assert!(edited.load_pool().is_err());
+}
+
+#[test]
+fn levels_count_from_the_funded_node_after_a_removal() {
+    // no wait to leave the root, a day for the next two levels, 6 blocks below that
+    let mut config = NetworkConfig::new(NetworkProfile::RegtestLocal);
+    config.level_delays = vec![0, 144, 144];
+    config.unwind_delay = Some(6);
+    let removed =
+        remove_participant(&pool_manifest(&config), user(0), Rebalance::ReduceFunding).unwrap();
+    let pool = removed.load_pool().unwrap();
+    let spenders = pool.config.spenders().unwrap();
+    assert_eq!(spenders.first(), Some(&user(1)));
+
+    let mut sequences = Vec::new();
+    for &spender in &spenders {
+        let users = pool.config.unwind_node(spender).unwrap();
+        let exit = node_exit(
+            &pool.tree,
+            &pool.config,
+            pool.backend.as_ref(),
+            &pool.addresses,
+            &pool.anchor_addr,
+            &users,
+            spender,
+        )
+        .unwrap();
+        assert_eq!(exit.sequence, pool.config.unwind_sequence(&users));
+        sequences.push(exit.sequence);
+        // only the last exit pays out the pool
+        let stage = ConfirmationStage::of_spend(spender, &pool.config).unwrap();
+        if users.is_exit() {
+            assert_eq!(stage, ConfirmationStage::Payout);
+        } else {
+            assert_eq!(stage, ConfirmationStage::PoolSpend);
+        }
+    }
+    // the funded node is level 0 even though it is a level below the full tree's root
+    assert_eq!(sequences[0], Sequence::ENABLE_RBF_NO_LOCKTIME);
+    assert_eq!(sequences[1], Sequence::from_height(144));
+    assert_eq!(sequences[2], Sequence::from_height(144));
+    assert!(sequences[3..]
+        .iter()
+        .all(|sequence| *sequence == Sequence::from_height(6)));
+    assert_eq!(pool.config.max_unwind_delay(), Some(144));
+
+    // and the demo walks the members that are left, one spend each
+    let txids = planned_unwind(&pool, &funding_tx(&removed)).unwrap();
+    assert_eq!(txids.len(), spenders.len());
+    assert_eq!(txids.len(), POOL_USERS - 2);
+
+    // the last members of a pool shrunk to 3 still get a level each
+    let mut shrunk = pool_manifest(&config);
+    for i in 0..POOL_USERS - 3 {
+        shrunk = remove_participant(&shrunk, user(i), Rebalance::ReduceFunding).unwrap();
+    }
+    let pool = shrunk.load_pool().unwrap();
+    let root = pool.config.pool_root().unwrap();
+    assert_eq!(pool.config.unwind_delay_for(&root), None);
+    let last = pool.config.spenders().unwrap();
+    assert_eq!(
+        ConfirmationStage::of_spend(last[1], &pool.config).unwrap(),
+        ConfirmationStage::Payout
+    );
+    assert_eq!(
+        planned_unwind(&pool, &funding_tx(&shrunk)).unwrap().len(),
+        2
+    );
+}
@@ -0,0 +1,99 @@
+use bitcoin::{
+    absolute, hashes::Hash, transaction, OutPoint, ScriptBuf, Transaction, TxIn, TxOut, Txid,
+};
+use std::str::FromStr;
+
+use op_ctv_payment_pool::{
+    config::NetworkConfig,
+    covenant::CovenantBackend,
+    ids::{NodePath, UserIndex},
+    pools::{build_pools, pool_spend_template, CPFP_CHILD_VSIZE},
+    profile::NetworkProfile,
+    research::{sweep, Shape, UsersRange},
+    AMOUNT_PER_USER, POOL_USERS,
+};
+
+mod common;
+
+use common::{addresses, anchor, backend};
+
+fn range(users: usize) -> UsersRange {
+    UsersRange::from_str(&users.to_string()).unwrap()
+}
+
+#[test]
+fn linear_sweep_matches_the_unwind_of_a_real_pool() {
+    let mut config = NetworkConfig::new(NetworkProfile::RegtestLocal);
+    config.recovery = None;
+    let backend = backend(&config);
+    let addresses = addresses(config.network);
+    let anchor_addr = anchor(&config);
+    let pools = build_pools(&addresses, &anchor_addr, &config, &backend).unwrap();
+    let root = pools.spend_info(&NodePath::root()).unwrap();
+
+    let mut previous_tx = Transaction {
+        version: transaction::Version(2),
+        lock_time: absolute::LockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: OutPoint::new(Txid::all_zeros(), 0),
+            ..Default::default()
+        }],
+        output: vec![TxOut {
+            value: AMOUNT_PER_USER * POOL_USERS as u64,
+            script_pubkey: ScriptBuf::new_p2tr_tweaked(root.output_key()),
+        }],
+    };
+    let mut unwind_vbytes = 0;
+    for spender in UserIndex::all().take(POOL_USERS - 1) {
+        let template = pool_spend_template(
+            &pools,
+            &config,
+            &backend,
+            spender,
+            &addresses,
+            &previous_tx,
+            &anchor_addr,
+        )
+        .unwrap();
+        let tx = backend.finalize(template).unwrap();
+        unwind_vbytes += tx.vsize() as u64 + CPFP_CHILD_VSIZE;
+        previous_tx = tx;
+    }
+
+    let rows = sweep(&config, &backend, range(POOL_USERS), 1, &[Shape::Linear], 1).unwrap();
+    assert_eq!(rows.len(), 1);
+    assert_eq!(rows[0].unwind_txs, POOL_USERS as u64 - 1);
+    assert_eq!(rows[0].unwind_vbytes, unwind_vbytes);
+    assert_eq!(rows[0].tree_nodes as usize, pools.len());
+}
+
+#[test]
+fn binary_tree_unwinds_in_log2_steps_per_user() {
+    let config = NetworkConfig::new(NetworkProfile::RegtestLocal);
+    let backend = backend(&config);
+    let users = UsersRange::from_str("3..=64").unwrap();
+    let rows = sweep(&config, &backend, users, 1, &[Shape::Binary], 1).unwrap();
+    assert_eq!(rows.len(), 62);
+    for row in rows {
+        assert_eq!(row.unwind_txs, row.users as u64 - 1);
+        assert_eq!(
+            row.worst_case_user_txs,
+            (row.users as f64).log2().ceil() as u64
+        );
+        assert!(row.worst_case_user_vbytes < row.unwind_vbytes || row.users == 3);
+    }
+}
+
+#[test]
+fn pool_sizes_start_at_three() {
+    assert_eq!(
+        UsersRange::from_str("3..512").unwrap(),
+        UsersRange {
+            first: 3,
+            last: 512
+        }
+    );
+    for bad in ["2..10", "10..3", "3..100000", "x"] {
+        assert!(UsersRange::from_str(bad).is_err(), "{}", bad);
+    }
+}
@@ -0,0 +1,184 @@
+use bitcoin::{Amount, OutPoint, ScriptBuf, TxOut, Txid};
+use bitcoincore_rpc::RpcApi;
+use std::{collections::HashSet, str::FromStr};
+
+use op_ctv_payment_pool::{
+    config::NetworkConfig,
+    fund::required_funding,
+    mock::MockBackend,
+    profile::NetworkProfile,
+    reservations::{check_unreserved, reserve_coins, select_coins, CoinReservations},
+    rpc_helper::{send_funding_transaction, FundingDestination},
+};
+
+mod common;
+
+use common::{address, anchor};
+
+fn inputs(mock: &MockBackend, txid: Txid) -> HashSet<OutPoint> {
+    mock.transaction(txid)
+        .unwrap()
+        .input
+        .iter()
+        .map(|input| input.previous_output)
+        .collect()
+}
+
+#[test]
+fn largest_coins_are_selected_until_the_fee_is_covered() {
+    let coin = |vout, sats| {
+        (
+            OutPoint::new(Txid::from_str(&"11".repeat(32)).unwrap(), vout),
+            TxOut {
+                value: Amount::from_sat(sats),
+                script_pubkey: ScriptBuf::new(),
+            },
+        )
+    };
+    let coins = vec![coin(0, 1_000), coin(1, 60_000), coin(2, 50_000)];
+    let selected = select_coins(coins.clone(), Amount::from_sat(55_000), 1_000, 100)
+        .unwrap()
+        .unwrap();
+    assert_eq!(selected, vec![coins[1].clone()]);
+
+    // the fee of a second input tips it over
+    let selected = select_coins(coins.clone(), Amount::from_sat(59_900), 1_000, 100)
+        .unwrap()
+        .unwrap();
+    assert_eq!(selected, vec![coins[1].clone(), coins[2].clone()]);
+
+    assert!(select_coins(coins, Amount::from_sat(111_000), 1_000, 100)
+        .unwrap()
+        .is_none());
+}
+
+#[tokio::test]
+async fn concurrent_fundings_never_share_a_coin() {
+    let config = NetworkConfig::new(NetworkProfile::RegtestLocal);
+    let mock = MockBackend::new(config.network);
+    let rpc = mock.rpc();
+    for _ in 0..2 {
+        mock.add_utxo(required_funding() + Amount::from_sat(100_000));
+    }
+    mock.mine(1);
+
+    let first = FundingDestination::EntryPool(anchor(&config));
+    let second = FundingDestination::EntryPool(mock.new_address());
+    let (first, second) = tokio::join!(
+        send_funding_transaction(&rpc, &config, &first, None),
+        send_funding_transaction(&rpc, &config, &second, None),
+    );
+    let (first, _) = first.unwrap();
+    let (second, _) = second.unwrap();
+    assert_eq!(mock.mempool().len(), 2);
+    assert!(inputs(&mock, first).is_disjoint(&inputs(&mock, second)));
+}
+
+#[tokio::test]
+async fn a_failed_funding_gives_its_coins_back() {
+    let config = NetworkConfig::new(NetworkProfile::RegtestLocal);
+    let mock = MockBackend::new(config.network);
+    let rpc = mock.rpc();
+    let coin = mock.add_utxo(Amount::from_btc(2.0).unwrap());
+    mock.mine(1);
+
+    mock.fail_next("sendrawtransaction", -26, "min relay fee not met");
+    let destination = FundingDestination::EntryPool(anchor(&config));
+    assert!(send_funding_transaction(&rpc, &config, &destination, None)
+        .await
+        .is_err());
+    assert!(mock.locked().is_empty());
+
+    // a coin locked by someone else is left alone
+    rpc.run(move |c| c.lock_unspent(&[coin])).await.unwrap();
+    assert!(send_funding_transaction(&rpc, &config, &destination, None)
+        .await
+        .is_err());
+    rpc.run(move |c| c.unlock_unspent(&[coin])).await.unwrap();
+    let (txid, _) = send_funding_transaction(&rpc, &config, &destination, None)
+        .await
+        .unwrap();
+    assert_eq!(inputs(&mock, txid), HashSet::from([coin]));
+}
+
+#[tokio::test]
+async fn coins_reserved_on_disk_are_skipped_and_refused() {
+    let path = std::env::temp_dir().join(format!("pool-reservations-{}.json", std::process::id()));
+    let mut config = NetworkConfig::new(NetworkProfile::RegtestLocal);
+    config.coin_reservations = Some(path.clone());
+    let mock = MockBackend::new(config.network);
+    let rpc = mock.rpc();
+    let held = mock.add_utxo(Amount::from_btc(2.0).unwrap());
+    let free = mock.add_utxo(Amount::from_btc(1.0).unwrap());
+    mock.mine(1);
+
+    // another pool's funding was being built before the node restarted and forgot its locks
+    let other = mock.new_address().to_string();
+    let mut reservations = CoinReservations::default();
+    reservations.reserve(&other, &[held], 0).unwrap();
+    reservations.write(&path).unwrap();
+
+    let pool = anchor(&config);
+    let (txid, _) = send_funding_transaction(
+        &rpc,
+        &config,
+        &FundingDestination::EntryPool(pool.clone()),
+        None,
+    )
+    .await
+    .unwrap();
+    assert_eq!(inputs(&mock, txid), HashSet::from([free]));
+    // only the other pool's reservation is left
+    assert_eq!(CoinReservations::load(&path).unwrap().coins.len(), 1);
+
+    let error = check_unreserved(&config, &pool.to_string(), &[held]).unwrap_err();
+    assert!(error.to_string().contains(&other), "{}", error);
+    assert!(check_unreserved(&config, &other, &[held]).is_ok());
+
+    // the file goes with its last reservation
+    let mut reservations = CoinReservations::load(&path).unwrap();
+    assert_eq!(reservations.release(&other), vec![held]);
+    reservations.write(&path).unwrap();
+    assert!(!path.exists());
+    let _ = std::fs::remove_file(path.with_extension("json.lock"));
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn concurrent_reservations_keep_each_other() {
+    let path = std::env::temp_dir().join(format!(
+        "pool-reservations-concurrent-{}.json",
+        std::process::id()
+    ));
+    let mut config = NetworkConfig::new(NetworkProfile::RegtestLocal);
+    config.coin_reservations = Some(path.clone());
+
+    // wallets on their own nodes sharing the file, so only the file keeps their reservations apart
+    let reservations: Vec<_> = (0..8)
+        .map(|i| {
+            let config = config.clone();
+            tokio::spawn(async move {
+                let mock = MockBackend::new(config.network);
+                // the mocks are alike, the amounts keep their coins apart
+                let coin = mock.add_utxo(Amount::from_sat(100_000_000 + i as u64));
+                mock.mine(1);
+                let pool = address(i, config.network).to_string();
+                reserve_coins(&mock.rpc(), &config, &pool, &[coin])
+                    .await
+                    .unwrap();
+                (pool, coin)
+            })
+        })
+        .collect();
+    let mut reserved = Vec::new();
+    for reservation in reservations {
+        reserved.push(reservation.await.unwrap());
+    }
+
+    let on_disk = CoinReservations::load(&path).unwrap();
+    assert_eq!(on_disk.coins.len(), reserved.len());
+    for (pool, coin) in &reserved {
+        assert_eq!(on_disk.holder(coin), Some(pool.as_str()));
+    }
+    std::fs::remove_file(&path).unwrap();
+    let _ = std::fs::remove_file(path.with_extension("json.lock"));
+}
@@ -0,0 +1,106 @@
+use bitcoin::{absolute, transaction, Amount, Transaction, TxIn, TxOut};
+
+use op_ctv_payment_pool::{
+    config::NetworkConfig,
+    lifecycle::{Event, Lifecycle},
+    manifest::PoolManifest,
+    pools::{build_pools, node_amount},
+    profile::NetworkProfile,
+    reserves::{prove_reserves, verify_reserves},
+    POOL_USERS,
+};
+
+mod common;
+
+use common::{address, addresses, anchor, backend};
+
+// a manifest funded by a tx paying `amount` to its root, and that tx
+fn funded(amount: Amount) -> (PoolManifest, Transaction) {
+    let config = NetworkConfig::new(NetworkProfile::RegtestLocal);
+    let backend = backend(&config);
+    let addresses = addresses(config.network);
+    let anchor_addr = anchor(&config);
+    let tree = build_pools(&addresses, &anchor_addr, &config, &backend).unwrap();
+    let root = tree.root().unwrap().address(&config);
+    let mut manifest = PoolManifest::new(&config, &backend, &anchor_addr, &addresses, &root);
+
+    let funding_tx = Transaction {
+        version: transaction::Version::TWO,
+        lock_time: absolute::LockTime::ZERO,
+        input: vec![TxIn::default()],
+        output: vec![
+            TxOut {
+                value: Amount::from_sat(50_000),
+                script_pubkey: address(98, config.network).script_pubkey(),
+            },
+            TxOut {
+                value: amount,
+                script_pubkey: root.script_pubkey(),
+            },
+        ],
+    };
+    manifest.funding_txid = Some(funding_tx.compute_txid());
+    manifest.advance(Event::Fund).unwrap();
+    (manifest, funding_tx)
+}
+
+#[tokio::test]
+async fn the_funding_output_backs_every_claim() {
+    let amount = node_amount(POOL_USERS).unwrap();
+    let (manifest, funding_tx) = funded(amount);
+    let proof = prove_reserves(&manifest, &funding_tx).unwrap();
+    assert_eq!(proof.funding_outpoint.vout, 1);
+    assert_eq!(proof.claims.len(), POOL_USERS);
+    assert_eq!(proof.total_claims, amount);
+    // the root leaves pay each user their share less the committed fee
+    assert!(proof
+        .claims
+        .iter()
+        .all(|claim| claim.root_exit < claim.share && claim.root_exit > Amount::ZERO));
+
+    let json = serde_json::to_string(&proof).unwrap();
+    let verified = verify_reserves(&serde_json::from_str(&json).unwrap(), None)
+        .await
+        .unwrap();
+    assert_eq!(verified.surplus, Amount::ZERO);
+    assert_eq!(verified.users, POOL_USERS);
+    assert_eq!(verified.unspent, None);
+}
+
+#[tokio::test]
+async fn inflated_or_edited_proofs_are_refused() {
+    let amount = node_amount(POOL_USERS).unwrap();
+    let (manifest, funding_tx) = funded(amount);
+    let proof = prove_reserves(&manifest, &funding_tx).unwrap();
+
+    // claiming the output holds more than it does
+    let mut inflated = proof.clone();
+    inflated.funding_value = amount + Amount::from_sat(1);
+    assert!(verify_reserves(&inflated, None).await.is_err());
+
+    // a claim the tree doesn't give
+    let mut edited = proof.clone();
+    edited.claims[0].share = Amount::from_sat(1);
+    let error = verify_reserves(&edited, None).await.unwrap_err();
+    assert!(error.to_string().contains("claims"), "{}", error);
+
+    // a manifest that isn't the one hashed
+    let mut swapped = proof.clone();
+    swapped.manifest.withdraw_addresses.swap(0, 1);
+    let error = verify_reserves(&swapped, None).await.unwrap_err();
+    assert!(error.to_string().contains("manifest_sha256"), "{}", error);
+}
+
+#[tokio::test]
+async fn an_underfunded_pool_has_no_proof() {
+    let amount = node_amount(POOL_USERS).unwrap();
+    let (manifest, funding_tx) = funded(amount - Amount::from_sat(1_000));
+    let proof = prove_reserves(&manifest, &funding_tx).unwrap();
+    let error = verify_reserves(&proof, None).await.unwrap_err();
+    assert!(error.to_string().contains("short"), "{}", error);
+
+    // nor does one that isn't funded
+    let mut unfunded = manifest.clone();
+    unfunded.lifecycle = Lifecycle::Registered;
+    assert!(prove_reserves(&unfunded, &funding_tx).is_err());
+}
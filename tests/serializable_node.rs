@@ -0,0 +1,106 @@
+use bitcoin::{taproot::LeafVersion, ScriptBuf};
+
+use op_ctv_payment_pool::{
+    config::NetworkConfig,
+    ctv_scripts::TreeLayout,
+    pools::build_pools,
+    profile::NetworkProfile,
+    recovery::RecoveryPath,
+    tree::{PoolNode, PoolTree, SerializablePoolNode},
+};
+
+mod common;
+
+use common::{address, addresses, anchor, backend};
+
+fn tree(layout: TreeLayout, recovery: bool, close_all_leaf: bool) -> PoolTree {
+    let mut config = NetworkConfig::new(NetworkProfile::RegtestLocal);
+    config.tree_layout = layout;
+    config.close_all_leaf = close_all_leaf;
+    config.recovery = recovery.then(|| RecoveryPath {
+        address: address(40, config.network),
+        timeout: 144,
+    });
+    let backend = backend(&config);
+    let addresses = addresses(config.network);
+    let anchor_addr = anchor(&config);
+    build_pools(&addresses, &anchor_addr, &config, &backend).unwrap()
+}
+
+fn assert_same(node: &PoolNode, back: &PoolNode) {
+    assert_eq!(back.users, node.users);
+    assert_eq!(back.amount, node.amount);
+    assert_eq!(back.templates, node.templates);
+    assert_eq!(back.leaf_scripts, node.leaf_scripts);
+    assert_eq!(back.recovery_script, node.recovery_script);
+    assert_eq!(back.close_all, node.close_all);
+    assert_eq!(back.children, node.children);
+    assert_eq!(back.spend_info, node.spend_info);
+    // still spendable: every leaf gets the control block it had
+    for script in node.leaf_scripts.iter().chain(&node.recovery_script) {
+        let leaf = (script.clone(), LeafVersion::TapScript);
+        assert_eq!(
+            back.spend_info.control_block(&leaf),
+            node.spend_info.control_block(&leaf)
+        );
+    }
+}
+
+#[test]
+fn every_node_round_trips_through_json_and_postcard() {
+    for (layout, recovery, close_all_leaf) in [
+        (TreeLayout::Balanced, false, false),
+        (TreeLayout::Weighted, true, false),
+        (TreeLayout::Balanced, true, true),
+        (TreeLayout::Weighted, false, true),
+    ] {
+        let tree = tree(layout, recovery, close_all_leaf);
+        for (_, node) in tree.iter_nodes() {
+            let serializable = SerializablePoolNode::from(node);
+
+            let json = serde_json::to_string(&serializable).unwrap();
+            let from_json: SerializablePoolNode = serde_json::from_str(&json).unwrap();
+            assert_eq!(from_json, serializable);
+            assert_same(node, &from_json.into_node().unwrap());
+
+            let bytes = postcard::to_allocvec(&serializable).unwrap();
+            let from_postcard: SerializablePoolNode = postcard::from_bytes(&bytes).unwrap();
+            assert_same(node, &from_postcard.into_node().unwrap());
+
+            // the same node always writes the same bytes
+            assert_eq!(
+                serde_json::to_string(&SerializablePoolNode::from(node)).unwrap(),
+                json
+            );
+        }
+    }
+}
+
+#[test]
+fn nodes_that_dont_add_up_are_refused() {
+    let tree = tree(TreeLayout::Weighted, true, false);
+    let root = SerializablePoolNode::from(tree.root().unwrap());
+    assert!(root.leaves.len() > 2);
+
+    let mut swapped_leaf = root.clone();
+    swapped_leaf.leaves[0].script = ScriptBuf::from_bytes(vec![0x51]);
+    assert!(swapped_leaf.into_node().is_err());
+
+    let mut missing_leaf = root.clone();
+    missing_leaf.leaves.pop();
+    assert!(missing_leaf.into_node().is_err());
+
+    let mut other_key = root.clone();
+    let other = SerializablePoolNode::from(
+        tree.iter_nodes()
+            .map(|(_, node)| node)
+            .find(|node| node.users.len() == 2)
+            .unwrap(),
+    );
+    other_key.output_key = other.output_key;
+    assert!(other_key.into_node().is_err());
+
+    let mut other_root = root;
+    other_root.merkle_root = other.merkle_root;
+    assert!(other_root.into_node().is_err());
+}
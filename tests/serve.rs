@@ -0,0 +1,149 @@
+use std::{
+    net::{SocketAddr, TcpListener},
+    path::PathBuf,
+    time::Duration,
+};
+
+use bitcoin::Network;
+use serde_json::{json, Value};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+
+use op_ctv_payment_pool::{
+    config::NetworkConfig, ids::PoolId, profile::NetworkProfile,
+    registration::registration_message, serve::serve_registration,
+};
+
+mod common;
+
+use common::{address, sign_p2tr};
+
+fn pool_id() -> PoolId {
+    "window test".parse().unwrap()
+}
+
+// a port nobody listens on
+fn free_port() -> SocketAddr {
+    TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+}
+
+fn temp_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("pool-serve-{}-{}.json", name, std::process::id()))
+}
+
+// serve the reference pool's registration on `listen`, until the window closes
+fn serve(
+    name: &str,
+    listen: SocketAddr,
+    opens_in: Duration,
+    window: Duration,
+) -> tokio::task::JoinHandle<anyhow::Result<()>> {
+    let (manifest, registrations) = (
+        temp_path(&format!("{}-manifest", name)),
+        temp_path(&format!("{}-registrations", name)),
+    );
+    tokio::spawn(serve_registration(
+        NetworkConfig::new(NetworkProfile::RegtestLocal),
+        pool_id(),
+        listen,
+        opens_in,
+        window,
+        manifest,
+        registrations,
+        None,
+    ))
+}
+
+// the status line and json body of one request
+async fn request(listen: SocketAddr, method: &str, path: &str, body: &str) -> (String, Value) {
+    let mut stream = loop {
+        match TcpStream::connect(listen).await {
+            Ok(stream) => break stream,
+            // serve is still binding
+            Err(_) => tokio::time::sleep(Duration::from_millis(20)).await,
+        }
+    };
+    let request = format!(
+        "{} {} HTTP/1.1\r\nHost: {}\r\nContent-Length: {}\r\n\r\n{}",
+        method,
+        path,
+        listen,
+        body.len(),
+        body
+    );
+    stream.write_all(request.as_bytes()).await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    let (head, body) = response.split_once("\r\n\r\n").unwrap();
+    let status = head.lines().next().unwrap().to_string();
+    (status, serde_json::from_str(body).unwrap())
+}
+
+// reference user 0's signed registration
+fn registration() -> String {
+    let message = registration_message(&pool_id(), &[], None, None);
+    json!({
+        "address": address(0, Network::Regtest).to_string(),
+        "signature": sign_p2tr(0, &message),
+    })
+    .to_string()
+}
+
+#[tokio::test]
+async fn registering_before_the_window_opens_is_refused() {
+    let listen = free_port();
+    let _serve = serve(
+        "early",
+        listen,
+        Duration::from_secs(60),
+        Duration::from_secs(60),
+    );
+
+    let (_, status) = request(listen, "GET", "/pool", "").await;
+    assert_eq!(status["open"], false);
+    assert!(status["opens_in_secs"].as_u64().unwrap() > 0);
+    let (line, body) = request(listen, "POST", "/register", &registration()).await;
+    assert!(line.contains("400"), "{}", line);
+    assert_eq!(body["code"], "E_REGISTRATION_CLOSED");
+    assert!(
+        body["error"]
+            .as_str()
+            .unwrap()
+            .starts_with("registration opens in"),
+        "{}",
+        body
+    );
+    assert_eq!(request(listen, "GET", "/pool", "").await.1["registered"], 0);
+}
+
+#[tokio::test]
+async fn registering_after_the_window_closes_is_refused() {
+    let listen = free_port();
+    let serve = serve("late", listen, Duration::ZERO, Duration::from_secs(2));
+
+    // taken while the window is open
+    let (line, body) = request(listen, "POST", "/register", &registration()).await;
+    assert!(line.contains("200"), "{} {}", line, body);
+    assert_eq!(body["user"], 0);
+
+    // one of POOL_USERS registered, so no pool once it closes
+    let err = serve.await.unwrap().unwrap_err().to_string();
+    assert!(err.contains("no pool built"), "{}", err);
+    let (_, status) = request(listen, "GET", "/pool", "").await;
+    assert_eq!(status["open"], false);
+    assert_eq!(status["closes_in_secs"], 0);
+    let message = registration_message(&pool_id(), &[], None, None);
+    let late = json!({
+        "address": address(1, Network::Regtest).to_string(),
+        "signature": sign_p2tr(1, &message),
+    });
+    let (line, body) = request(listen, "POST", "/register", &late.to_string()).await;
+    assert!(line.contains("400"), "{}", line);
+    assert_eq!(body["code"], "E_REGISTRATION_CLOSED");
+    assert_eq!(body["error"], "registration is closed");
+}
@@ -0,0 +1,167 @@
+use bitcoin::Amount;
+use std::path::PathBuf;
+
+use op_ctv_payment_pool::{
+    config::NetworkConfig,
+    fee_bump::{FeeBumpPolicy, DEFAULT_BUMP_MAX_FEE},
+    pools::build_pools,
+    profile::NetworkProfile,
+    simulate::{bump_policy, simulate_fees, FeeScenario, FeeSimulation, FeeratePoint},
+    POOL_USERS,
+};
+
+mod common;
+
+use common::{addresses, anchor, backend};
+
+fn scenario(points: &[(u64, f64)], blocks: u64) -> FeeScenario {
+    FeeScenario {
+        name: Some("test".to_string()),
+        points: points
+            .iter()
+            .map(|&(block, sat_per_vb)| FeeratePoint { block, sat_per_vb })
+            .collect(),
+        blocks: Some(blocks),
+    }
+}
+
+fn policy(after_blocks: u64, max_fee: u64) -> FeeBumpPolicy {
+    FeeBumpPolicy {
+        after_blocks,
+        max_fee: Amount::from_sat(max_fee),
+        journal: PathBuf::from("unused.jsonl"),
+    }
+}
+
+fn simulate(
+    config: &NetworkConfig,
+    scenario: &FeeScenario,
+    bump: Option<&FeeBumpPolicy>,
+) -> FeeSimulation {
+    let backend = backend(config);
+    let addresses = addresses(config.network);
+    let anchor_addr = anchor(config);
+    let tree = build_pools(&addresses, &anchor_addr, config, &backend).unwrap();
+    simulate_fees(
+        &tree,
+        config,
+        &backend,
+        &addresses,
+        &anchor_addr,
+        scenario,
+        bump,
+    )
+    .unwrap()
+}
+
+#[test]
+fn scenario_rates_hold_until_the_next_point() {
+    let spike = scenario(&[(5, 2.0), (10, 150.5), (40, 0.25)], 100);
+    assert!(spike.check().is_ok());
+    assert_eq!(spike.fee_rate_at(0), 2_000);
+    assert_eq!(spike.fee_rate_at(9), 2_000);
+    assert_eq!(spike.fee_rate_at(10), 150_500);
+    assert_eq!(spike.fee_rate_at(500), 250);
+
+    let mut open_ended = spike.clone();
+    open_ended.blocks = None;
+    assert_eq!(open_ended.blocks(), 40 + 1008);
+
+    let check = |points: &[(u64, f64)]| scenario(points, 10).check();
+    assert!(check(&[]).is_err());
+    assert!(check(&[(10, 2.0), (10, 3.0)]).is_err());
+    assert!(check(&[(0, -1.0)]).is_err());
+}
+
+#[test]
+fn committed_fees_get_through_a_quiet_mempool_and_wait_out_a_spike() {
+    let mut config = NetworkConfig::new(NetworkProfile::RegtestLocal);
+    config.anchor_amount = None;
+
+    let quiet = simulate(&config, &scenario(&[(0, 1.0)], 1000), None);
+    assert!(!quiet.anchored);
+    assert_eq!(quiet.levels.len(), POOL_USERS - 1);
+    for level in &quiet.levels {
+        assert_eq!(level.confirmed, level.eligible);
+        assert_eq!(level.bumps, 0);
+    }
+    assert_eq!(quiet.unwound_at, quiet.levels.last().unwrap().confirmed);
+    assert_eq!(quiet.child_fees, Amount::ZERO);
+
+    // each level waits out the unwind delay on top of its node's confirmation
+    config.unwind_delay = Some(10);
+    let delayed = simulate(&config, &scenario(&[(0, 1.0)], 1000), None);
+    let confirmed: Vec<_> = delayed.levels.iter().map(|level| level.confirmed).collect();
+    let expected: Vec<_> = (1..POOL_USERS as u64)
+        .map(|level| Some(level * 10))
+        .collect();
+    assert_eq!(confirmed, expected);
+    config.unwind_delay = None;
+
+    // nothing pays 1000 sat/vB, the first spend waits for the spike to pass
+    let spike = simulate(&config, &scenario(&[(0, 1000.0), (50, 1.0)], 1000), None);
+    assert_eq!(spike.levels[0].confirmed, Some(50));
+    assert!(spike.unwound_at.unwrap() > quiet.unwound_at.unwrap());
+
+    // and a spike that never ends strands the unwind at its first level
+    let stuck = simulate(&config, &scenario(&[(0, 1000.0)], 1000), None);
+    assert_eq!(stuck.levels[0].confirmed, None);
+    assert!(stuck.levels[1..]
+        .iter()
+        .all(|level| level.eligible.is_none()));
+    assert_eq!(stuck.unwound_at, None);
+}
+
+#[test]
+fn anchored_spends_need_children_within_the_budget() {
+    let config = NetworkConfig::new(NetworkProfile::RegtestLocal);
+    let quiet = scenario(&[(0, 5.0)], 1000);
+
+    // the templates only fund their anchors, nothing pays for them without bumping
+    let unbumped = simulate(&config, &quiet, None);
+    assert!(unbumped.anchored);
+    assert_eq!(unbumped.levels[0].confirmed, None);
+    assert_eq!(unbumped.unwound_at, None);
+
+    let generous = policy(3, DEFAULT_BUMP_MAX_FEE.to_sat());
+    let bumped = simulate(&config, &quiet, Some(&generous));
+    assert_eq!(bumped.bump_after_blocks, Some(3));
+    assert!(bumped.unwound_at.is_some());
+    for level in &bumped.levels {
+        let eligible = level.eligible.unwrap();
+        // the first child goes out once the spend has waited after_blocks
+        assert_eq!(level.confirmed, Some(eligible + 3));
+        assert_eq!(level.bumps, 1);
+        assert!(level.fee_rate >= 5_000);
+    }
+    assert_eq!(
+        bumped.child_fees,
+        bumped.levels.iter().map(|level| level.child_fee).sum()
+    );
+
+    // a budget below what a busier mempool asks leaves the first spend where it is
+    let busy = scenario(&[(0, 50.0)], 1000);
+    let tight = simulate(&config, &busy, Some(&policy(3, 500)));
+    assert_eq!(tight.levels[0].confirmed, None);
+    assert!(tight.levels[0].budget_spent);
+    assert_eq!(tight.levels[0].child_fee, Amount::from_sat(500));
+}
+
+#[test]
+fn command_line_bumping_overrides_the_pool_settings() {
+    let configured = policy(6, 10_000);
+    assert_eq!(
+        bump_policy(Some(configured.clone()), None, None).unwrap(),
+        Some(configured.clone())
+    );
+    let overridden = bump_policy(Some(configured), Some(2), Some(Amount::from_sat(3_000)))
+        .unwrap()
+        .unwrap();
+    assert_eq!(overridden.after_blocks, 2);
+    assert_eq!(overridden.max_fee, Amount::from_sat(3_000));
+
+    let fresh = bump_policy(None, Some(4), None).unwrap().unwrap();
+    assert_eq!(fresh.max_fee, DEFAULT_BUMP_MAX_FEE);
+    assert_eq!(bump_policy(None, None, None).unwrap(), None);
+    assert!(bump_policy(None, None, Some(Amount::from_sat(3_000))).is_err());
+}
@@ -0,0 +1,168 @@
+use bitcoin::{address::NetworkUnchecked, Address, Amount, Network};
+
+use op_ctv_payment_pool::{
+    config::{NetworkConfig, DUST_AMOUNT, FEE_AMOUNT},
+    costs::pool_costs,
+    ids::{NodePath, PoolId, UserIndex},
+    manifest::PoolManifest,
+    payouts::{check_splits, split_total, user_scripts, PayoutSplits, SplitPayout},
+    pools::{build_pools, node_exit},
+    profile::NetworkProfile,
+    registration::registration_message,
+    state::{decode_state, encode_state},
+    AMOUNT_PER_USER,
+};
+
+mod common;
+
+use common::{address, addresses, anchor, backend};
+
+fn unchecked(address: &Address) -> Address<NetworkUnchecked> {
+    address.to_string().parse().unwrap()
+}
+
+fn split(i: usize, sats: u64) -> SplitPayout {
+    SplitPayout {
+        address: unchecked(&address(i, Network::Regtest)),
+        amount: Amount::from_sat(sats),
+    }
+}
+
+// user 3 sends part of their exit to two more of their addresses
+fn config() -> NetworkConfig {
+    let mut config = NetworkConfig::new(NetworkProfile::RegtestLocal);
+    let mut splits = PayoutSplits::new();
+    splits.insert(
+        address(3, config.network).to_string(),
+        vec![split(100, 2_000), split(101, 1_000)],
+    );
+    config.splits = splits;
+    config
+}
+
+#[test]
+fn split_users_leave_with_every_address() {
+    let config = config();
+    let backend = backend(&config);
+    let addresses = addresses(config.network);
+    let anchor_addr = anchor(&config);
+    let tree = build_pools(&addresses, &anchor_addr, &config, &backend).unwrap();
+    let taken = split_total(&config.splits[&addresses[3].to_string()]);
+    assert!(taken > Amount::from_sat(3_000));
+
+    // next pool, the rest of the exit, the splits in order, the anchor
+    let spender = UserIndex::new(3).unwrap();
+    let exit = node_exit(
+        &tree,
+        &config,
+        &backend,
+        &addresses,
+        &anchor_addr,
+        &NodePath::root(),
+        spender,
+    )
+    .unwrap();
+    let values: Vec<_> = exit.outputs.iter().map(|output| output.value).collect();
+    assert_eq!(exit.outputs.len(), 5);
+    assert_eq!(values[1], AMOUNT_PER_USER - FEE_AMOUNT - taken);
+    assert_eq!(values[2], Amount::from_sat(2_000));
+    assert_eq!(values[3], Amount::from_sat(1_000));
+    let scripts: Vec<_> = exit.outputs[1..4]
+        .iter()
+        .map(|output| output.script_pubkey.clone())
+        .collect();
+    assert_eq!(scripts, user_scripts(&addresses[3], &config));
+    assert_eq!(exit.outputs[4].script_pubkey, anchor_addr.script_pubkey());
+
+    // in the exit pool the first user is paid in full, minus their splits
+    let exit = node_exit(
+        &tree,
+        &config,
+        &backend,
+        &addresses,
+        &anchor_addr,
+        &NodePath::new(vec![3, 5]).unwrap(),
+        spender,
+    )
+    .unwrap();
+    assert_eq!(exit.outputs.len(), 5);
+    assert_eq!(exit.outputs[0].value, AMOUNT_PER_USER - taken);
+    assert_eq!(exit.outputs[3].script_pubkey, addresses[5].script_pubkey());
+    assert_eq!(exit.outputs[3].value, AMOUNT_PER_USER - FEE_AMOUNT);
+
+    // users who don't split have the same exits either way
+    let plain = NetworkConfig::new(NetworkProfile::RegtestLocal);
+    let plain_tree = build_pools(&addresses, &anchor_addr, &plain, &backend).unwrap();
+    let users = NodePath::new(vec![0, 1, 2]).unwrap();
+    assert_eq!(
+        tree.node(&users).unwrap().spend_info.output_key(),
+        plain_tree.node(&users).unwrap().spend_info.output_key()
+    );
+    assert_ne!(
+        tree.root().unwrap().spend_info.output_key(),
+        plain_tree.root().unwrap().spend_info.output_key()
+    );
+}
+
+#[test]
+fn splits_have_to_leave_the_withdraw_address_more_than_dust() {
+    assert!(check_splits(&[split(100, 2_000)], Network::Regtest).is_ok());
+    // dust
+    assert!(check_splits(&[split(100, 100)], Network::Regtest).is_err());
+    // another network
+    let testnet = SplitPayout {
+        address: unchecked(&address(100, Network::Testnet)),
+        amount: Amount::from_sat(2_000),
+    };
+    assert!(check_splits(&[testnet], Network::Regtest).is_err());
+    // the whole smallest exit
+    let smallest_exit = (AMOUNT_PER_USER - FEE_AMOUNT - DUST_AMOUNT).to_sat();
+    assert!(check_splits(&[split(100, smallest_exit)], Network::Regtest).is_err());
+}
+
+#[test]
+fn registrations_sign_their_splits() {
+    let pool_id = "splits test".parse::<PoolId>().unwrap();
+    // registrations without splits sign what they always did
+    assert_eq!(
+        registration_message(&pool_id, &[], None, None),
+        "splits test"
+    );
+    assert_eq!(
+        registration_message(&pool_id, &[split(100, 2_000)], None, None),
+        format!("splits test\n{}:2000", address(100, Network::Regtest))
+    );
+}
+
+#[test]
+fn manifests_keep_the_splits() {
+    let config = config();
+    let backend = backend(&config);
+    let addresses = addresses(config.network);
+    let anchor_addr = anchor(&config);
+    let tree = build_pools(&addresses, &anchor_addr, &config, &backend).unwrap();
+    let root = tree.root().unwrap().address(&config);
+    let manifest = PoolManifest::new(&config, &backend, &anchor_addr, &addresses, &root);
+    assert_eq!(manifest.splits, config.splits);
+
+    let (decoded, _) = decode_state(&encode_state(&manifest).unwrap()).unwrap();
+    assert_eq!(decoded.splits, config.splits);
+    let pool = decoded.load_pool().unwrap();
+    assert_eq!(pool.config.splits, config.splits);
+
+    // only users of the pool can split
+    let mut stranger = manifest.clone();
+    stranger.splits.insert(
+        address(200, config.network).to_string(),
+        vec![split(100, 2_000)],
+    );
+    assert!(stranger.load_pool().is_err());
+
+    let costs = pool_costs(&config, 5);
+    assert_eq!(costs.splits.users, 1);
+    assert_eq!(costs.splits.extra_outputs, 2);
+    assert_eq!(
+        costs.splits.fee,
+        split_total(&config.splits[&addresses[3].to_string()]) - Amount::from_sat(3_000)
+    );
+}
@@ -0,0 +1,74 @@
+use bitcoin::{
+    absolute, hashes::Hash, transaction, Amount, Network, OutPoint, ScriptBuf, Transaction, TxIn,
+    TxOut, Txid, WPubkeyHash, Witness,
+};
+
+use op_ctv_payment_pool::standardness::lint_transaction;
+
+mod common;
+
+use common::address;
+
+const MIN_RELAY_FEE: Amount = Amount::from_sat(1_000);
+
+// a p2wpkh coin of `value`, spent by `spend`
+fn prevout(value: Amount) -> TxOut {
+    TxOut {
+        value,
+        script_pubkey: ScriptBuf::new_p2wpkh(&WPubkeyHash::from_byte_array([1; 20])),
+    }
+}
+
+fn spend(outputs: Vec<TxOut>) -> Transaction {
+    Transaction {
+        version: transaction::Version::TWO,
+        lock_time: absolute::LockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: OutPoint::new(Txid::all_zeros(), 0),
+            witness: Witness::from_slice(&[[0x30; 72].as_slice(), [0x02; 33].as_slice()]),
+            ..Default::default()
+        }],
+        output: outputs,
+    }
+}
+
+fn payout(value: u64) -> TxOut {
+    TxOut {
+        value: Amount::from_sat(value),
+        script_pubkey: address(0, Network::Regtest).script_pubkey(),
+    }
+}
+
+#[test]
+fn a_standard_spend_has_nothing_to_lint() {
+    let tx = spend(vec![payout(10_000)]);
+    assert_eq!(
+        lint_transaction(&tx, &[prevout(Amount::from_sat(11_000))], MIN_RELAY_FEE),
+        Vec::<String>::new()
+    );
+}
+
+#[test]
+fn a_dust_output_is_refused() {
+    // p2tr needs 330 sat
+    let tx = spend(vec![payout(10_000), payout(329)]);
+    assert_eq!(
+        lint_transaction(&tx, &[prevout(Amount::from_sat(11_000))], MIN_RELAY_FEE),
+        vec!["output 1 of 0.00000329 BTC is dust, at least 0.00000330 BTC is needed".to_string()]
+    );
+}
+
+#[test]
+fn an_oversized_tx_is_refused() {
+    let tx = spend(vec![payout(1_000); 10_000]);
+    assert!(tx.weight().to_wu() > 400_000);
+    // enough fee for its size, the weight is all that's wrong with it
+    let value = Amount::from_sat(10_000 * 1_000 + tx.vsize() as u64);
+    assert_eq!(
+        lint_transaction(&tx, &[prevout(value)], MIN_RELAY_FEE),
+        vec![format!(
+            "weight {} is over the standard limit of 400000",
+            tx.weight()
+        )]
+    );
+}
@@ -0,0 +1,118 @@
+use bitcoin::{hashes::Hash, Txid};
+use std::{fs, path::PathBuf};
+
+use op_ctv_payment_pool::{
+    canonical::canonical_json,
+    config::NetworkConfig,
+    ctv_scripts::TemplateVersion,
+    ids::UserIndex,
+    lifecycle::Event,
+    manifest::PoolManifest,
+    pools::build_pools,
+    profile::NetworkProfile,
+    state::{decode_state, encode_state, STATE_VERSION},
+};
+
+mod common;
+
+use common::{addresses, anchor, backend, pool_manifest};
+
+fn fixture(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures")
+        .join(name)
+}
+
+// the manifest every fixture was written from: the reference pool, funded and labelled so the
+// optional fields aren't all empty
+fn reference_manifest() -> PoolManifest {
+    let mut manifest = pool_manifest(&NetworkConfig::new(NetworkProfile::RegtestLocal));
+    manifest.advance(Event::Fund).unwrap();
+    manifest.funding_txid = Some(Txid::from_byte_array([7; 32]));
+    manifest.metadata.set(None, "name", "fixture").unwrap();
+    manifest
+        .metadata
+        .set(Some(UserIndex::new(2).unwrap()), "team", "ops")
+        .unwrap();
+    manifest
+}
+
+// every version this build reads, with the file written at it. A new STATE_VERSION adds its own
+// fixture, the old ones stay as they are
+const FIXTURES: &[(u16, &str)] = &[(0, "state_v0.json"), (1, "state_v1.ctvpool")];
+
+#[test]
+fn every_kept_version_loads_into_the_current_manifest() {
+    assert_eq!(FIXTURES.last().unwrap().0, STATE_VERSION);
+    let expected = canonical_json(&reference_manifest()).unwrap();
+    for (version, name) in FIXTURES {
+        let (manifest, read) = decode_state(&fs::read(fixture(name)).unwrap())
+            .unwrap_or_else(|e| panic!("{}: {}", name, e));
+        assert_eq!(read, *version, "{}", name);
+        assert_eq!(canonical_json(&manifest).unwrap(), expected, "{}", name);
+        manifest.load_pool().unwrap();
+    }
+}
+
+#[test]
+fn the_current_version_reads_back_what_it_wrote() {
+    let manifest = reference_manifest();
+    let (decoded, version) = decode_state(&encode_state(&manifest).unwrap()).unwrap();
+    assert_eq!(version, STATE_VERSION);
+    assert_eq!(
+        canonical_json(&decoded).unwrap(),
+        canonical_json(&manifest).unwrap()
+    );
+}
+
+#[test]
+fn a_newer_or_unknown_version_is_refused() {
+    let mut bytes = encode_state(&reference_manifest()).unwrap();
+    bytes[4..6].copy_from_slice(&(STATE_VERSION + 1).to_le_bytes());
+    assert!(decode_state(&bytes).is_err());
+    bytes[4..6].copy_from_slice(&0u16.to_le_bytes());
+    assert!(decode_state(&bytes).is_err());
+}
+
+#[test]
+fn the_manifest_picks_the_template_version_it_was_built_with() {
+    let config = NetworkConfig::new(NetworkProfile::RegtestLocal);
+    // every version this build still rebuilds gives the pool its own construction made
+    for version in TemplateVersion::ALL {
+        let mut config = config.clone();
+        config.template_version = *version;
+        let manifest = pool_manifest(&config);
+        assert_eq!(manifest.template_version, *version);
+        let pool = manifest.load_pool().unwrap();
+        assert_eq!(pool.config.template_version, *version);
+        let tree = build_pools(
+            &addresses(config.network),
+            &anchor(&config),
+            &config,
+            &backend(&config),
+        )
+        .unwrap();
+        assert_eq!(
+            pool.tree.root().unwrap().address(&pool.config),
+            tree.root().unwrap().address(&config),
+            "{}",
+            version
+        );
+    }
+
+    // json from before the versions is v1
+    let mut json = serde_json::to_value(reference_manifest()).unwrap();
+    json.as_object_mut().unwrap().remove("template_version");
+    let (manifest, _) = decode_state(json.to_string().as_bytes()).unwrap();
+    assert_eq!(manifest.template_version, TemplateVersion::V1);
+    manifest.load_pool().unwrap();
+
+    // a pool from a newer release isn't rebuilt with the wrong templates
+    json["template_version"] = (u16::from(TemplateVersion::LATEST) + 1).into();
+    let error = decode_state(json.to_string().as_bytes()).unwrap_err();
+    assert!(
+        error.to_string().contains("is unknown to this build"),
+        "{}",
+        error
+    );
+}
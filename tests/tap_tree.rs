@@ -0,0 +1,70 @@
+use bitcoin::{
+    opcodes::all::OP_DROP,
+    script::Builder,
+    taproot::{LeafVersion, TaprootBuilderError, TAPROOT_CONTROL_MAX_NODE_COUNT},
+    ScriptBuf, XOnlyPublicKey,
+};
+use std::str::FromStr;
+
+use op_ctv_payment_pool::ctv_scripts::{huffman_tree, weighted_tree, NUMS_INTERNAL_KEY};
+
+// `leaves` distinct leaves weighted like the Fibonacci numbers, each one outweighing every lighter
+// leaf together so huffman hangs them off a single path: the lightest two end up `leaves - 1` deep
+fn fibonacci_leaves(leaves: usize) -> Vec<(u128, ScriptBuf)> {
+    let (mut a, mut b) = (1u128, 1u128);
+    (0..leaves)
+        .map(|i| {
+            let weight = a;
+            (a, b) = (b, a + b);
+            let script = Builder::new()
+                .push_int(i as i64)
+                .push_opcode(OP_DROP)
+                .push_int(1)
+                .into_script();
+            (weight, script)
+        })
+        .collect()
+}
+
+#[test]
+fn a_tap_tree_past_the_depth_limit_falls_back_to_balanced() {
+    let internal_key = XOnlyPublicKey::from_str(NUMS_INTERNAL_KEY).unwrap();
+    let leaves = fibonacci_leaves(TAPROOT_CONTROL_MAX_NODE_COUNT + 3);
+
+    // refused at the combine that would put the lightest leaves past the limit
+    let error = huffman_tree(leaves.clone(), LeafVersion::TapScript).unwrap_err();
+    assert_eq!(
+        error,
+        TaprootBuilderError::InvalidMerkleTreeDepth(TAPROOT_CONTROL_MAX_NODE_COUNT)
+    );
+    assert_eq!(
+        error.to_string(),
+        "Merkle Tree depth(128) must be less than 128"
+    );
+
+    // every leaf is still there, at most log2 of the leaf count deep
+    let spend_info = weighted_tree(leaves.clone(), LeafVersion::TapScript, internal_key).unwrap();
+    for (_, script) in &leaves {
+        let control_block = spend_info
+            .control_block(&(script.clone(), LeafVersion::TapScript))
+            .unwrap();
+        assert!(control_block.merkle_branch.len() <= 8);
+    }
+}
+
+#[test]
+fn a_tap_tree_within_the_limit_stays_weighted() {
+    let internal_key = XOnlyPublicKey::from_str(NUMS_INTERNAL_KEY).unwrap();
+    let leaves = fibonacci_leaves(20);
+    let spend_info = weighted_tree(leaves.clone(), LeafVersion::TapScript, internal_key).unwrap();
+    let depth = |script: &ScriptBuf| {
+        spend_info
+            .control_block(&(script.clone(), LeafVersion::TapScript))
+            .unwrap()
+            .merkle_branch
+            .len()
+    };
+    // the heaviest leaf right under the root, the lightest at the bottom
+    assert_eq!(depth(&leaves[19].1), 1);
+    assert_eq!(depth(&leaves[0].1), 19);
+}
@@ -0,0 +1,85 @@
+use bitcoin::Amount;
+
+use op_ctv_payment_pool::{
+    config::{NetworkConfig, FEE_AMOUNT},
+    costs::binomial,
+    pools::build_pools,
+    profile::NetworkProfile,
+    template_fees::{template_fee_report, LIKELY_MIN_RELAY_FEE_RATE},
+    POOL_USERS,
+};
+
+mod common;
+
+use common::{addresses, anchor, backend};
+
+// a template per user per node, the exit pools have one
+fn template_count() -> usize {
+    let n = POOL_USERS as u64;
+    let templates = (3..=n).map(|users| binomial(n, users) * users).sum::<u64>() + binomial(n, 2);
+    templates as usize
+}
+
+#[test]
+fn anchored_templates_leave_their_fee_to_the_anchor() {
+    let config = NetworkConfig::new(NetworkProfile::RegtestLocal);
+    let backend = backend(&config);
+    let addresses = addresses(config.network);
+    let anchor_addr = anchor(&config);
+    let tree = build_pools(&addresses, &anchor_addr, &config, &backend).unwrap();
+
+    let report = template_fee_report(&tree, &config, &backend, &addresses, &anchor_addr).unwrap();
+    assert!(report.anchored);
+    assert_eq!(report.templates, template_count());
+    // zero fee on their own, the anchor child pays for the package
+    assert_eq!(report.lowest.fee, Amount::ZERO);
+    assert_eq!(report.below_relay, report.templates);
+    assert_eq!(report.lowest.committed_fee, FEE_AMOUNT);
+    assert!(report.lowest.committed_fee_rate > LIKELY_MIN_RELAY_FEE_RATE);
+    assert!(report.warnings.is_empty(), "{:?}", report.warnings);
+}
+
+#[test]
+fn unanchored_templates_pay_their_own_way() {
+    let mut config = NetworkConfig::new(NetworkProfile::RegtestLocal);
+    config.anchor_amount = None;
+    config.close_all_leaf = true;
+    let backend = backend(&config);
+    let addresses = addresses(config.network);
+    let anchor_addr = anchor(&config);
+    let tree = build_pools(&addresses, &anchor_addr, &config, &backend).unwrap();
+
+    let report = template_fee_report(&tree, &config, &backend, &addresses, &anchor_addr).unwrap();
+    assert!(!report.anchored);
+    assert_eq!(report.templates, template_count() + 1);
+    assert_eq!(report.below_relay, 0);
+    assert_eq!(report.lowest.fee_rate, report.lowest.committed_fee_rate);
+    // the close-all template pays FEE_AMOUNT once for everyone from the biggest tx
+    assert!(report.lowest.spender.is_none());
+    assert!(report.lowest.vsize > report.smallest.vsize);
+    assert!(report.warnings.is_empty(), "{:?}", report.warnings);
+}
+
+#[test]
+fn a_floor_over_the_committed_fees_refuses_the_tree() {
+    let mut config = NetworkConfig::new(NetworkProfile::RegtestLocal);
+    config.anchor_amount = Some(FEE_AMOUNT - Amount::from_sat(1_000));
+    let backend = backend(&config);
+    let addresses = addresses(config.network);
+    let anchor_addr = anchor(&config);
+
+    // with the anchor the templates commit FEE_AMOUNT in a few hundred vB
+    config.min_template_fee_rate = Some(10_000);
+    let tree = build_pools(&addresses, &anchor_addr, &config, &backend).unwrap();
+    let report = template_fee_report(&tree, &config, &backend, &addresses, &anchor_addr).unwrap();
+    assert_eq!(report.lowest.fee, Amount::from_sat(1_000));
+    assert_eq!(report.lowest.committed_fee, FEE_AMOUNT);
+
+    config.min_template_fee_rate = Some(report.lowest.committed_fee_rate + 1);
+    let error = build_pools(&addresses, &anchor_addr, &config, &backend).unwrap_err();
+    assert!(
+        error.to_string().contains("POOL_MIN_TEMPLATE_FEE_RATE"),
+        "{}",
+        error
+    );
+}
@@ -0,0 +1,62 @@
+use op_ctv_payment_pool::{
+    config::NetworkConfig,
+    ids::NodePath,
+    pools::build_pools,
+    profile::NetworkProfile,
+    tree::{estimated_tree_bytes, PoolTree},
+    POOL_USERS,
+};
+
+mod common;
+
+use common::{addresses, anchor, backend};
+
+fn tree(memory_budget: Option<u64>) -> (NetworkConfig, PoolTree) {
+    let mut config = NetworkConfig::new(NetworkProfile::RegtestLocal);
+    config.tree_memory_budget = memory_budget;
+    let backend = backend(&config);
+    let anchor_addr = anchor(&config);
+    let tree = build_pools(&addresses(config.network), &anchor_addr, &config, &backend).unwrap();
+    (config, tree)
+}
+
+#[test]
+fn a_tree_over_its_budget_spills_and_reads_back_the_same_nodes() {
+    let (config, in_memory) = tree(None);
+    let (_, spilled) = tree(Some(1));
+    assert_eq!(in_memory.spilled_levels(), 0);
+    // everything but the root
+    assert_eq!(spilled.spilled_levels(), spilled.level_count() - 1);
+    assert_eq!(spilled.len(), in_memory.len());
+
+    // the root is still in memory, a node below it is read back on its own
+    assert_eq!(
+        spilled.root().unwrap().address(&config),
+        in_memory.root().unwrap().address(&config)
+    );
+    let node = NodePath::new(vec![0, 1, 2]).unwrap();
+    assert_eq!(
+        spilled.node(&node).unwrap().address(&config),
+        in_memory.node(&node).unwrap().address(&config)
+    );
+
+    let nodes = |tree: &PoolTree| {
+        tree.iter_nodes()
+            .map(|(users, node)| (users.clone(), node.address(&config), node.amount))
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(nodes(&spilled), nodes(&in_memory));
+}
+
+#[test]
+fn a_tree_under_its_budget_stays_in_memory() {
+    let (_, tree) = tree(Some(estimated_tree_bytes(POOL_USERS)));
+    assert_eq!(tree.spilled_levels(), 0);
+}
+
+#[test]
+fn the_estimate_grows_with_the_pool_and_saturates() {
+    assert!(estimated_tree_bytes(10) < estimated_tree_bytes(11));
+    assert!(estimated_tree_bytes(20) > 2 * estimated_tree_bytes(19));
+    assert_eq!(estimated_tree_bytes(1000), u64::MAX);
+}
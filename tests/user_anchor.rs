@@ -0,0 +1,219 @@
+use bitcoin::{
+    absolute,
+    hashes::Hash,
+    key::{Keypair, Secp256k1, TapTweak},
+    secp256k1::{Message, SecretKey},
+    sighash::{Prevouts, SighashCache},
+    taproot, transaction, Address, Amount, Network, OutPoint, Transaction, TxIn, TxOut,
+    XOnlyPublicKey,
+};
+
+use op_ctv_payment_pool::{
+    config::NetworkConfig,
+    ids::{NodePath, PoolId, UserIndex},
+    manifest::PoolManifest,
+    pools::{build_pools, node_exit},
+    profile::NetworkProfile,
+    registration::registration_message,
+    state::{decode_state, encode_state},
+    user_anchor::{anchor_child, anchor_key_address, has_pool_anchor, KEYED_ANCHOR_DUST},
+    POOL_USERS,
+};
+
+mod common;
+
+use common::{anchor, backend};
+
+fn keypair(seed: u8) -> Keypair {
+    Keypair::from_secret_key(
+        &Secp256k1::new(),
+        &SecretKey::from_slice(&[seed; 32]).unwrap(),
+    )
+}
+
+fn key(seed: u8) -> XOnlyPublicKey {
+    keypair(seed).x_only_public_key().0
+}
+
+fn addresses(network: Network) -> Vec<Address> {
+    (0..POOL_USERS)
+        .map(|i| anchor_key_address(key(i as u8 + 1), network))
+        .collect()
+}
+
+// the exit of `spender` from the node of `users`, as the outputs it pays
+fn exit_outputs(config: &NetworkConfig, users: &NodePath, spender: usize) -> Vec<TxOut> {
+    let addresses = addresses(config.network);
+    let tree = build_pools(&addresses, &anchor(config), config, &backend(config)).unwrap();
+    node_exit(
+        &tree,
+        config,
+        &backend(config),
+        &addresses,
+        &anchor(config),
+        users,
+        UserIndex::new(spender).unwrap(),
+    )
+    .unwrap()
+    .outputs
+}
+
+// an exit tx paying `anchor` on `key`'s address
+fn parent(key: XOnlyPublicKey, anchor: Amount) -> Transaction {
+    Transaction {
+        version: transaction::Version(3),
+        lock_time: absolute::LockTime::ZERO,
+        input: vec![TxIn::default()],
+        output: vec![
+            TxOut {
+                value: Amount::from_sat(100_000),
+                script_pubkey: anchor_key_address(self::key(100), Network::Regtest).script_pubkey(),
+            },
+            TxOut {
+                value: anchor,
+                script_pubkey: anchor_key_address(key, Network::Regtest).script_pubkey(),
+            },
+        ],
+    }
+}
+
+#[test]
+fn a_registered_key_gets_the_anchor_of_its_exits() {
+    let mut config = NetworkConfig::new(NetworkProfile::RegtestLocal);
+    let addresses = addresses(config.network);
+    config.anchor_keys.insert(addresses[0].to_string(), key(50));
+
+    let keyed = exit_outputs(&config, &NodePath::root(), 0);
+    let anchor_output = keyed.last().unwrap();
+    assert_eq!(
+        anchor_output.script_pubkey,
+        anchor_key_address(key(50), config.network).script_pubkey()
+    );
+    assert_eq!(Some(anchor_output.value), config.anchor_amount);
+    let tx = Transaction {
+        version: transaction::Version(config.tx_version),
+        lock_time: absolute::LockTime::ZERO,
+        input: Vec::new(),
+        output: keyed,
+    };
+    assert!(!has_pool_anchor(&tx, &config));
+
+    // everyone else's exits still pay the pool's anchor
+    let shared = exit_outputs(&config, &NodePath::root(), 1);
+    assert_eq!(
+        shared.last().unwrap().script_pubkey,
+        anchor(&config).script_pubkey()
+    );
+}
+
+#[test]
+fn anchor_keys_are_kept_in_the_manifest() {
+    let mut config = NetworkConfig::new(NetworkProfile::RegtestLocal);
+    let addresses = addresses(config.network);
+    config.anchor_keys.insert(addresses[2].to_string(), key(60));
+    let tree = build_pools(&addresses, &anchor(&config), &config, &backend(&config)).unwrap();
+    let root = tree.root().unwrap().address(&config);
+    let manifest = PoolManifest::new(
+        &config,
+        &backend(&config),
+        &anchor(&config),
+        &addresses,
+        &root,
+    );
+
+    let (decoded, _) = decode_state(&encode_state(&manifest).unwrap()).unwrap();
+    assert_eq!(decoded.anchor_keys, config.anchor_keys);
+    let pool = decoded.load_pool().unwrap();
+    assert_eq!(pool.tree.root().unwrap().address(&pool.config), root);
+
+    // a key for someone outside the pool
+    let mut edited = decoded.clone();
+    edited.anchor_keys.insert(
+        anchor_key_address(key(200), Network::Regtest).to_string(),
+        key(61),
+    );
+    let error = edited.load_pool().err().unwrap();
+    assert!(error.to_string().contains("not in the pool"), "{}", error);
+
+    // a keyed anchor has to be above p2tr dust
+    config.anchor_amount = Some(KEYED_ANCHOR_DUST - Amount::from_sat(1));
+    assert!(build_pools(&addresses, &anchor(&config), &config, &backend(&config)).is_err());
+}
+
+#[test]
+fn registrations_sign_their_anchor_key() {
+    let pool_id = "anchor test".parse::<PoolId>().unwrap();
+    assert_eq!(
+        registration_message(&pool_id, &[], Some(&key(7)), None),
+        format!("anchor test\nanchor:{}", key(7))
+    );
+}
+
+#[test]
+fn the_user_bumps_their_exit_with_their_key() {
+    let user = keypair(70);
+    let parent = parent(user.x_only_public_key().0, Amount::from_sat(5_000));
+    let child = anchor_child(&parent, Amount::ZERO, &user, &[], 2, Network::Regtest).unwrap();
+    assert_eq!(child.version, parent.version);
+    assert_eq!(child.input[0].previous_output.txid, parent.compute_txid());
+
+    // the package pays the rate, what's left goes back to the key
+    let fee = Amount::from_sat(5_000) - child.output[0].value;
+    assert!(fee.to_sat() >= 2 * (parent.vsize() + child.vsize()) as u64);
+    assert_eq!(
+        child.output[0].script_pubkey,
+        anchor_key_address(user.x_only_public_key().0, Network::Regtest).script_pubkey()
+    );
+
+    // signed with the tweaked key
+    let prevouts = [parent.output[1].clone()];
+    let sighash = SighashCache::new(&child)
+        .taproot_key_spend_signature_hash(
+            0,
+            &Prevouts::All(&prevouts),
+            bitcoin::TapSighashType::Default,
+        )
+        .unwrap();
+    let signature = taproot::Signature::from_slice(&child.input[0].witness[0]).unwrap();
+    let secp = Secp256k1::new();
+    let output_key = user
+        .tap_tweak(&secp, None)
+        .to_keypair()
+        .x_only_public_key()
+        .0;
+    secp.verify_schnorr(
+        &signature.signature,
+        &Message::from_digest(sighash.to_byte_array()),
+        &output_key,
+    )
+    .unwrap();
+}
+
+#[test]
+fn a_small_anchor_needs_a_coin_of_the_user() {
+    let user = keypair(80);
+    let address = anchor_key_address(user.x_only_public_key().0, Network::Regtest);
+    let parent = parent(user.x_only_public_key().0, Amount::from_sat(400));
+    let error = anchor_child(&parent, Amount::ZERO, &user, &[], 10, Network::Regtest).unwrap_err();
+    assert!(error.to_string().contains("Send a coin"), "{}", error);
+
+    let coin = (
+        OutPoint::new(Hash::all_zeros(), 7),
+        TxOut {
+            value: Amount::from_sat(20_000),
+            script_pubkey: address.script_pubkey(),
+        },
+    );
+    let child = anchor_child(&parent, Amount::ZERO, &user, &[coin], 10, Network::Regtest).unwrap();
+    assert_eq!(child.input.len(), 2);
+
+    // nobody else's coins
+    let other = (
+        OutPoint::new(Hash::all_zeros(), 8),
+        TxOut {
+            value: Amount::from_sat(20_000),
+            script_pubkey: anchor_key_address(key(81), Network::Regtest).script_pubkey(),
+        },
+    );
+    assert!(anchor_child(&parent, Amount::ZERO, &user, &[other], 10, Network::Regtest).is_err());
+}
@@ -0,0 +1,118 @@
+use bitcoin::{
+    key::{Keypair, Secp256k1},
+    secp256k1::SecretKey,
+    Address, Amount, Network, OutPoint, Sequence, Txid,
+};
+use std::str::FromStr;
+
+use op_ctv_payment_pool::{
+    config::{NetworkConfig, FEE_AMOUNT},
+    interpreter::verify_input,
+    profile::NetworkProfile,
+    vault::{Vault, VaultSpec, DEFAULT_UNVAULT_DELAY},
+};
+
+fn keypair(i: u8) -> Keypair {
+    Keypair::from_secret_key(&Secp256k1::new(), &SecretKey::from_slice(&[i; 32]).unwrap())
+}
+
+fn address(i: u8, network: Network) -> Address {
+    let (xonly, _) = keypair(i).x_only_public_key();
+    Address::p2tr(&Secp256k1::new(), xonly, None, network)
+}
+
+fn vault(anchor_amount: Option<Amount>) -> Vault {
+    let mut config = NetworkConfig::new(NetworkProfile::RegtestLocal);
+    config.anchor_amount = anchor_amount;
+    let spec = VaultSpec::new(
+        &config,
+        Amount::from_sat(100_000),
+        keypair(1).x_only_public_key().0,
+        &address(2, config.network),
+        DEFAULT_UNVAULT_DELAY,
+    )
+    .unwrap();
+    Vault::new(spec).unwrap()
+}
+
+fn outpoint(i: u8) -> OutPoint {
+    OutPoint::new(Txid::from_str(&format!("{:02x}", i).repeat(32)).unwrap(), 0)
+}
+
+#[test]
+fn the_unvault_and_to_cold_txs_execute_against_their_templates() {
+    for anchor_amount in [None, Some(Amount::from_sat(240))] {
+        let vault = vault(anchor_amount);
+        let unvault = vault.unvault_tx(outpoint(1));
+        verify_input(&unvault, 0, &[vault.vault_output()]).unwrap();
+        assert_eq!(unvault.output[0], vault.unvault_output());
+
+        let tocold = vault.tocold_tx(OutPoint::new(unvault.compute_txid(), 0));
+        verify_input(&tocold, 0, &[vault.unvault_output()]).unwrap();
+        assert_eq!(tocold.output[0].value, vault.spec.amount - FEE_AMOUNT * 2);
+        assert_eq!(
+            tocold.output[0].script_pubkey,
+            vault.cold_address.script_pubkey()
+        );
+        assert_eq!(tocold.output.len(), 1 + anchor_amount.iter().count());
+    }
+}
+
+#[test]
+fn a_changed_unvault_or_to_cold_tx_fails_its_template() {
+    let vault = vault(None);
+    let mut unvault = vault.unvault_tx(outpoint(1));
+    unvault.output[0].script_pubkey = address(3, vault.spec.network).script_pubkey();
+    assert!(verify_input(&unvault, 0, &[vault.vault_output()]).is_err());
+
+    let mut tocold = vault.tocold_tx(outpoint(2));
+    tocold.output[0].value -= Amount::from_sat(1);
+    assert!(verify_input(&tocold, 0, &[vault.unvault_output()]).is_err());
+}
+
+#[test]
+fn only_the_hot_key_withdraws_and_only_after_the_delay() {
+    let vault = vault(None);
+    let to = address(4, vault.spec.network);
+    let withdraw = vault
+        .withdraw_tx(outpoint(2), &to, Amount::from_sat(1000), &keypair(1))
+        .unwrap();
+    verify_input(&withdraw, 0, &[vault.unvault_output()]).unwrap();
+    assert_eq!(
+        withdraw.input[0].sequence,
+        Sequence::from_height(DEFAULT_UNVAULT_DELAY)
+    );
+
+    // waiting a block less fails OP_CSV (and the signature, which commits to the sequence)
+    let mut early = withdraw.clone();
+    early.input[0].sequence = Sequence::from_height(DEFAULT_UNVAULT_DELAY - 1);
+    assert!(verify_input(&early, 0, &[vault.unvault_output()]).is_err());
+
+    assert!(vault
+        .withdraw_tx(outpoint(2), &to, Amount::from_sat(1000), &keypair(3))
+        .is_err());
+    // a fee taking everything
+    assert!(vault
+        .withdraw_tx(outpoint(2), &to, vault.unvault_amount(), &keypair(1))
+        .is_err());
+}
+
+#[test]
+fn a_vault_is_rebuilt_from_its_spec_alone() {
+    let vault = vault(Some(Amount::from_sat(240)));
+    let json = serde_json::to_string(&vault.spec).unwrap();
+    let rebuilt = Vault::new(serde_json::from_str(&json).unwrap()).unwrap();
+    assert_eq!(rebuilt.address(), vault.address());
+    assert_eq!(rebuilt.unvault_hash, vault.unvault_hash);
+    assert_eq!(rebuilt.tocold_hash, vault.tocold_hash);
+}
+
+#[test]
+fn a_vault_needs_a_delay_and_enough_to_pay_both_fees() {
+    let mut spec = vault(None).spec;
+    spec.delay = 0;
+    assert!(Vault::new(spec.clone()).is_err());
+    spec.delay = DEFAULT_UNVAULT_DELAY;
+    spec.amount = FEE_AMOUNT * 2;
+    assert!(Vault::new(spec).is_err());
+}
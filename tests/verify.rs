@@ -0,0 +1,130 @@
+use bitcoin::{Address, Network};
+use std::str::FromStr;
+
+use op_ctv_payment_pool::{
+    config::NetworkConfig,
+    manifest::PoolManifest,
+    membership::prove_membership,
+    profile::NetworkProfile,
+    verify::{verify_leaf_proof, verify_pool},
+};
+
+mod common;
+
+use common::{address, keypair, pool_manifest};
+
+fn manifest() -> PoolManifest {
+    pool_manifest(&NetworkConfig::new(NetworkProfile::RegtestLocal))
+}
+
+fn funding_spk(manifest: &PoolManifest) -> bitcoin::ScriptBuf {
+    Address::from_str(&manifest.root_address)
+        .unwrap()
+        .require_network(manifest.network)
+        .unwrap()
+        .script_pubkey()
+}
+
+#[test]
+fn a_pool_verifies_against_its_funding_output() {
+    let manifest = manifest();
+    let report = verify_pool(&manifest, &funding_spk(&manifest));
+    assert!(report.is_valid(), "{:?}", report.errors);
+    assert!(report.matches_onchain && report.matches_manifest);
+    assert!(report.nodes_checked > 0);
+    assert!(report
+        .leaves
+        .iter()
+        .all(|leaf| leaf.committed && leaf.pays_spender));
+}
+
+#[test]
+fn a_template_other_than_the_funded_one_fails() {
+    let manifest = manifest();
+    let funded = funding_spk(&manifest);
+
+    // a delay on every unwind spend is part of every template hash
+    let mut delayed = manifest.clone();
+    delayed.unwind_delay = Some(144);
+    let report = verify_pool(&delayed, &funded);
+    assert!(!report.is_valid());
+    assert!(!report.matches_onchain && !report.matches_manifest);
+    assert_ne!(report.rebuilt_script_pubkey, Some(funded.clone()));
+
+    // and so is every address the templates pay
+    let mut redirected = manifest.clone();
+    redirected.withdraw_addresses[4] = address(40, Network::Regtest).to_string();
+    redirected.committed_scripts = Default::default();
+    let report = verify_pool(&redirected, &funded);
+    assert!(!report.is_valid());
+    assert!(!report.matches_onchain);
+
+    // the right manifest against some other output
+    let report = verify_pool(&manifest, &address(40, Network::Regtest).script_pubkey());
+    assert!(!report.is_valid());
+    assert!(report.matches_manifest && !report.matches_onchain);
+}
+
+#[test]
+fn a_leaf_proof_shows_the_leaf_is_in_the_root() {
+    let manifest = manifest();
+    let user = address(2, Network::Regtest).to_string();
+    let membership = prove_membership(&manifest, &user).unwrap();
+    verify_leaf_proof(
+        &membership.leaf_script,
+        &membership.proof,
+        &funding_spk(&manifest),
+    )
+    .unwrap();
+}
+
+#[test]
+fn a_leaf_proof_is_only_good_for_its_own_leaf() {
+    let manifest = manifest();
+    let funded = funding_spk(&manifest);
+    let membership =
+        prove_membership(&manifest, &address(2, Network::Regtest).to_string()).unwrap();
+    let other = prove_membership(&manifest, &address(7, Network::Regtest).to_string()).unwrap();
+
+    let error = verify_leaf_proof(&other.leaf_script, &membership.proof, &funded).unwrap_err();
+    assert!(error.to_string().contains("hashes to"), "{}", error);
+    // nor for the root of another output
+    let error = verify_leaf_proof(
+        &membership.leaf_script,
+        &membership.proof,
+        &address(40, Network::Regtest).script_pubkey(),
+    )
+    .unwrap_err();
+    assert!(error.to_string().contains("is not the one"), "{}", error);
+}
+
+#[test]
+fn a_tampered_control_block_is_refused() {
+    let manifest = manifest();
+    let funded = funding_spk(&manifest);
+    let membership =
+        prove_membership(&manifest, &address(2, Network::Regtest).to_string()).unwrap();
+    let other = prove_membership(&manifest, &address(7, Network::Regtest).to_string()).unwrap();
+    assert_ne!(membership.proof.siblings, other.proof.siblings);
+
+    // another leaf's path up the tree
+    let mut proof = membership.proof.clone();
+    proof.siblings = other.proof.siblings.clone();
+    let error = verify_leaf_proof(&membership.leaf_script, &proof, &funded).unwrap_err();
+    assert!(error.to_string().contains("siblings lead to"), "{}", error);
+
+    // a sibling left out
+    let mut proof = membership.proof.clone();
+    proof.siblings.pop();
+    assert!(verify_leaf_proof(&membership.leaf_script, &proof, &funded).is_err());
+
+    // the right path under another internal key
+    let mut proof = membership.proof.clone();
+    proof.internal_key = keypair(5).x_only_public_key().0;
+    let error = verify_leaf_proof(&membership.leaf_script, &proof, &funded).unwrap_err();
+    assert!(
+        error.to_string().contains("tweaked with the root"),
+        "{}",
+        error
+    );
+}
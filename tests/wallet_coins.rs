@@ -0,0 +1,123 @@
+use bitcoin::{Amount, OutPoint, Txid};
+use std::collections::HashSet;
+
+use op_ctv_payment_pool::{
+    config::NetworkConfig,
+    fund::required_funding,
+    mock::MockBackend,
+    profile::NetworkProfile,
+    rpc_helper::{send_funding_transaction, FundingDestination},
+    wallet_coins::spendable_coins,
+};
+
+mod common;
+
+use common::anchor;
+
+fn outpoints(coins: &[(OutPoint, bitcoin::TxOut)]) -> HashSet<OutPoint> {
+    coins.iter().map(|(outpoint, _)| *outpoint).collect()
+}
+
+fn inputs(mock: &MockBackend, txid: Txid) -> HashSet<OutPoint> {
+    mock.transaction(txid)
+        .unwrap()
+        .input
+        .iter()
+        .map(|input| input.previous_output)
+        .collect()
+}
+
+fn destination(config: &NetworkConfig) -> FundingDestination {
+    FundingDestination::EntryPool(anchor(config))
+}
+
+#[tokio::test]
+async fn watch_only_and_reused_coins_are_skipped() {
+    let mock = MockBackend::new(bitcoin::Network::Regtest);
+    let rpc = mock.rpc();
+    let usable = mock.add_utxo(Amount::from_sat(100_000));
+    mock.add_watch_only_utxo(Amount::from_sat(200_000));
+    let reused = mock.add_reused_utxo(Amount::from_sat(300_000));
+    mock.mine(1);
+
+    // reused coins only matter to a wallet avoiding reuse
+    let coins = spendable_coins(&rpc, 0, Amount::ZERO).await.unwrap();
+    assert_eq!(outpoints(&coins), HashSet::from([usable, reused]));
+
+    mock.set_avoid_reuse(true);
+    let coins = spendable_coins(&rpc, 0, Amount::ZERO).await.unwrap();
+    assert_eq!(outpoints(&coins), HashSet::from([usable]));
+}
+
+#[tokio::test]
+async fn coins_worth_less_than_their_input_are_not_listed() {
+    let mock = MockBackend::new(bitcoin::Network::Regtest);
+    let rpc = mock.rpc();
+    let large = mock.add_utxo(Amount::from_sat(100_000));
+    mock.add_utxo(Amount::from_sat(50));
+    mock.mine(1);
+    let coins = spendable_coins(&rpc, 0, Amount::from_sat(68))
+        .await
+        .unwrap();
+    assert_eq!(outpoints(&coins), HashSet::from([large]));
+}
+
+#[tokio::test]
+async fn a_wallet_of_only_unusable_coins_says_what_to_do() {
+    let mock = MockBackend::new(bitcoin::Network::Regtest);
+    let rpc = mock.rpc();
+    mock.add_watch_only_utxo(Amount::from_sat(200_000));
+    mock.add_reused_utxo(Amount::from_sat(300_000));
+    mock.set_avoid_reuse(true);
+    mock.mine(1);
+
+    let error = spendable_coins(&rpc, 0, Amount::ZERO)
+        .await
+        .unwrap_err()
+        .to_string();
+    assert!(error.contains("1 watch-only"), "{}", error);
+    assert!(error.contains("fund --psbt"), "{}", error);
+    assert!(error.contains("1 reused"), "{}", error);
+    assert!(error.contains("avoid_reuse"), "{}", error);
+
+    mock.set_private_keys(false);
+    let error = spendable_coins(&rpc, 0, Amount::ZERO)
+        .await
+        .unwrap_err()
+        .to_string();
+    assert!(error.contains("private keys disabled"), "{}", error);
+}
+
+#[tokio::test]
+async fn the_funding_tx_never_spends_a_coin_it_cant_sign() {
+    let config = NetworkConfig::new(NetworkProfile::RegtestLocal);
+    let mock = MockBackend::new(config.network);
+    let rpc = mock.rpc();
+    // the largest coin, coin selection would take it first
+    mock.add_watch_only_utxo(required_funding() * 10);
+    let coin = mock.add_utxo(required_funding() + Amount::from_sat(100_000));
+    mock.mine(1);
+
+    let (txid, _) = send_funding_transaction(&rpc, &config, &destination(&config), None)
+        .await
+        .unwrap();
+    assert_eq!(inputs(&mock, txid), HashSet::from([coin]));
+}
+
+#[tokio::test]
+async fn a_watch_only_wallet_builds_no_funding_tx() {
+    let config = NetworkConfig::new(NetworkProfile::RegtestLocal);
+    let mock = MockBackend::new(config.network);
+    let rpc = mock.rpc();
+    mock.add_utxo(required_funding() * 2);
+    mock.mine(1);
+    mock.set_private_keys(false);
+
+    assert!(
+        send_funding_transaction(&rpc, &config, &destination(&config), None)
+            .await
+            .is_err()
+    );
+    assert!(mock.mempool().is_empty());
+    assert!(mock.locked().is_empty());
+}
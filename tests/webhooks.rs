@@ -0,0 +1,86 @@
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpListener,
+};
+
+use op_ctv_payment_pool::webhooks::{PoolEvent, Webhook, SIGNATURE_HEADER};
+
+fn webhook(secret: &str) -> Webhook {
+    Webhook::new("http://127.0.0.1:9/hook".to_string(), secret.to_string())
+}
+
+#[test]
+fn the_signature_is_hmac_sha256_of_the_body() {
+    // RFC 4231 test case 2
+    assert_eq!(
+        webhook("Jefe").sign(b"what do ya want for nothing?"),
+        "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+    );
+}
+
+// the headers and body of one request to `listener`, answered with a 200
+async fn receive(listener: TcpListener) -> (String, Vec<u8>) {
+    let (mut stream, _) = listener.accept().await.unwrap();
+    let mut request = Vec::new();
+    let mut buf = [0u8; 4096];
+    loop {
+        let read = stream.read(&mut buf).await.unwrap();
+        assert!(read > 0, "the request ended early");
+        request.extend_from_slice(&buf[..read]);
+        let Some(end) = request.windows(4).position(|window| window == b"\r\n\r\n") else {
+            continue;
+        };
+        let headers = String::from_utf8(request[..end].to_vec()).unwrap();
+        let length: usize = headers
+            .lines()
+            .find_map(|line| {
+                let (name, value) = line.split_once(':')?;
+                name.eq_ignore_ascii_case("content-length")
+                    .then(|| value.trim().parse().unwrap())
+            })
+            .unwrap();
+        if request.len() >= end + 4 + length {
+            stream
+                .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\n\r\n")
+                .await
+                .unwrap();
+            return (headers, request[end + 4..end + 4 + length].to_vec());
+        }
+    }
+}
+
+#[tokio::test]
+async fn a_delivered_request_verifies_and_a_tampered_one_does_not() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}/hook", listener.local_addr().unwrap());
+    let received = tokio::spawn(receive(listener));
+    let sender = Webhook::new(url, "shared secret".to_string());
+    sender
+        .notify(None, "bcrt1pexample", &PoolEvent::PoolCompleted, &[])
+        .await;
+
+    let (headers, body) = received.await.unwrap();
+    let signature = headers
+        .lines()
+        .find_map(|line| {
+            let (name, value) = line.split_once(':')?;
+            name.eq_ignore_ascii_case(SIGNATURE_HEADER)
+                .then(|| value.trim().to_string())
+        })
+        .unwrap();
+    assert!(String::from_utf8_lossy(&body).contains("pool_completed"));
+    let receiver = webhook("shared secret");
+    receiver.verify(&body, &signature).unwrap();
+
+    // a body changed on the way
+    let mut tampered = body.clone();
+    tampered[0] = b' ';
+    assert!(receiver.verify(&tampered, &signature).is_err());
+    // a receiver with another secret, or a sender who doesn't know it
+    assert!(webhook("other secret").verify(&body, &signature).is_err());
+    let forged = format!("sha256={}", webhook("guess").sign(&body));
+    assert!(receiver.verify(&body, &forged).is_err());
+    // the bare hex isn't the header's format
+    let bare = signature.trim_start_matches("sha256=");
+    assert!(receiver.verify(&body, bare).is_err());
+}
@@ -0,0 +1,97 @@
+use bitcoin::{
+    address::NetworkUnchecked, Address, Amount, Network, WitnessProgram, WitnessVersion,
+};
+
+use op_ctv_payment_pool::{
+    addresses::{
+        check_address, parse_witness_versions, set_allowed_witness_versions, AddressProblem,
+    },
+    config::NetworkConfig,
+    ids::{NodePath, UserIndex},
+    pools::{build_pools, node_exit},
+    profile::NetworkProfile,
+    POOL_USERS,
+};
+
+mod common;
+
+use common::{anchor, backend};
+
+fn future(version: WitnessVersion, program: &[u8]) -> Address {
+    Address::from_witness_program(
+        WitnessProgram::new(version, program).unwrap(),
+        Network::Regtest,
+    )
+}
+
+fn unchecked(address: &Address) -> Address<NetworkUnchecked> {
+    address.to_string().parse().unwrap()
+}
+
+// one test, the allowlist is process wide
+#[test]
+fn allowed_witness_versions_are_paid() {
+    let v3 = future(WitnessVersion::V3, &[3; 32]);
+    // a v1 program that isn't taproot's 32 bytes
+    let short_v1 = future(WitnessVersion::V1, &[1; 20]);
+    assert!(matches!(
+        check_address(&unchecked(&v3), Network::Regtest),
+        Err(AddressProblem::NonStandard { .. })
+    ));
+    assert!(check_address(&unchecked(&short_v1), Network::Regtest).is_err());
+
+    set_allowed_witness_versions(&parse_witness_versions("v1, 3").unwrap());
+    assert_eq!(
+        check_address(&unchecked(&v3), Network::Regtest).unwrap(),
+        v3
+    );
+    assert_eq!(
+        check_address(&unchecked(&short_v1), Network::Regtest).unwrap(),
+        short_v1
+    );
+    // v2 is still undefined and not allowed
+    assert!(check_address(
+        &unchecked(&future(WitnessVersion::V2, &[2; 40])),
+        Network::Regtest
+    )
+    .is_err());
+
+    // the leaves pay them like any other address
+    let config = NetworkConfig::new(NetworkProfile::RegtestLocal);
+    let mut addresses: Vec<Address> = (0..POOL_USERS)
+        .map(|i| future(WitnessVersion::V3, &[i as u8 + 10; 32]))
+        .collect();
+    addresses[1] = short_v1.clone();
+    let anchor = anchor(&config);
+    let backend = backend(&config);
+    let tree = build_pools(&addresses, &anchor, &config, &backend).unwrap();
+    let exit = node_exit(
+        &tree,
+        &config,
+        &backend,
+        &addresses,
+        &anchor,
+        &NodePath::root(),
+        UserIndex::new(1).unwrap(),
+    )
+    .unwrap();
+    let paid = exit
+        .outputs
+        .iter()
+        .find(|output| output.script_pubkey == short_v1.script_pubkey())
+        .unwrap();
+    assert!(paid.value > Amount::ZERO);
+    assert!(paid.value >= short_v1.script_pubkey().minimal_non_dust());
+}
+
+#[test]
+fn witness_version_lists_are_checked() {
+    assert_eq!(
+        parse_witness_versions("2,16").unwrap(),
+        vec![WitnessVersion::V2, WitnessVersion::V16]
+    );
+    assert!(parse_witness_versions("0").is_err());
+    assert!(parse_witness_versions("17").is_err());
+    assert!(parse_witness_versions("two").is_err());
+    assert!(parse_witness_versions("").unwrap().is_empty());
+}
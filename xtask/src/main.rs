@@ -0,0 +1,37 @@
+use std::{env, fs, path::PathBuf};
+
+use anyhow::{bail, Result};
+use clap::CommandFactory;
+use clap_complete::Shell;
+use op_ctv_payment_pool::cli::Cli;
+
+// Build tasks that need the cli definition, run with `cargo xtask <task>`
+fn main() -> Result<()> {
+    let mut args = env::args().skip(1);
+    match args.next().as_deref() {
+        Some("dist") => dist(
+            args.next()
+                .map_or_else(|| PathBuf::from("target/dist"), PathBuf::from),
+        ),
+        _ => bail!("usage: cargo xtask dist [output dir, default target/dist]"),
+    }
+}
+
+// man pages (one per subcommand) and the bash, zsh and fish completions, for packaging the binary
+fn dist(output: PathBuf) -> Result<()> {
+    let command = Cli::command();
+    let name = command.get_name().to_string();
+
+    let man = output.join("man");
+    fs::create_dir_all(&man)?;
+    clap_mangen::generate_to(command.clone(), &man)?;
+
+    let completions = output.join("completions");
+    fs::create_dir_all(&completions)?;
+    for shell in [Shell::Bash, Shell::Zsh, Shell::Fish] {
+        let path = clap_complete::generate_to(shell, &mut command.clone(), &name, &completions)?;
+        println!("{}", path.display());
+    }
+    println!("man pages in {}", man.display());
+    Ok(())
+}